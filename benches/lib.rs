@@ -2,7 +2,12 @@
 extern crate redeye;
 extern crate test;
 
+use redeye::forward::{Connector, Forwarder};
 use redeye::parser::{CombinedLogLineParser, CommonLogLineParser, LogLineParser};
+use redeye::workers::WorkerPool;
+use std::io::{self, Cursor, Write};
+use std::sync::Mutex;
+use std::thread;
 use test::Bencher;
 
 #[bench]
@@ -32,3 +37,126 @@ fn bench_combined_log_line_parser(b: &mut Bencher) {
             .unwrap()
     });
 }
+
+/// Parse and serialize a corpus of combined log lines using a pool of workers,
+/// the same as the binary does with `--workers`.
+fn bench_worker_pool(b: &mut Bencher, workers: usize) {
+    let line = concat!(
+        "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] ",
+        "\"GET /index.html HTTP/1.0\" 200 2326 ",
+        "\"http://www.example.com/start.html\" ",
+        "\"Mozilla/4.08 [en] (Win98; I ;Nav)\"\n"
+    );
+    let corpus = line.repeat(10_000);
+    let parser = CombinedLogLineParser::new();
+    let pool = WorkerPool::new(workers);
+
+    b.bytes = corpus.len() as u64;
+    b.iter(|| {
+        let mut out = 0;
+        pool.run(
+            Cursor::new(corpus.as_bytes()),
+            |r| {
+                let line = std::str::from_utf8(&r.data[..r.data.len() - 1]).unwrap();
+                serde_json::to_string(&parser.parse(line).unwrap()).unwrap()
+            },
+            |p| -> io::Result<()> {
+                out += p.value.len();
+                Ok(())
+            },
+        )
+        .unwrap();
+        out
+    });
+}
+
+#[bench]
+fn bench_worker_pool_1(b: &mut Bencher) {
+    bench_worker_pool(b, 1);
+}
+
+#[bench]
+fn bench_worker_pool_4(b: &mut Bencher) {
+    bench_worker_pool(b, 4);
+}
+
+#[bench]
+fn bench_worker_pool_8(b: &mut Bencher) {
+    bench_worker_pool(b, 8);
+}
+
+/// Connector that accepts and discards every batch of records.
+struct DiscardConnector;
+
+impl Connector for DiscardConnector {
+    type Stream = ();
+
+    fn connect(&self) -> io::Result<Self::Stream> {
+        Ok(())
+    }
+
+    fn send(&self, _stream: &mut Self::Stream, _records: &[Vec<u8>], _delimiter: u8) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const FORWARD_RECORDS: usize = 10_000;
+
+/// Forward records from several threads sharing a single writer behind a lock.
+fn bench_forward_shared_writer(b: &mut Bencher, producers: usize) {
+    let sender = Mutex::new(Forwarder::new(DiscardConnector).start().unwrap());
+
+    b.iter(|| {
+        thread::scope(|scope| {
+            for _ in 0..producers {
+                scope.spawn(|| {
+                    for _ in 0..FORWARD_RECORDS / producers {
+                        sender
+                            .lock()
+                            .unwrap()
+                            .write_all(b"127.0.0.1 GET /index.html 200\n")
+                            .unwrap();
+                    }
+                });
+            }
+        });
+    });
+}
+
+/// Forward records from several threads each with their own record sender.
+fn bench_forward_record_senders(b: &mut Bencher, producers: usize) {
+    let sender = Forwarder::new(DiscardConnector).start().unwrap();
+
+    b.iter(|| {
+        thread::scope(|scope| {
+            for _ in 0..producers {
+                let records = sender.sender().unwrap();
+                scope.spawn(move || {
+                    for _ in 0..FORWARD_RECORDS / producers {
+                        records.send(b"127.0.0.1 GET /index.html 200".to_vec()).unwrap();
+                    }
+                });
+            }
+        });
+    });
+}
+
+#[bench]
+fn bench_forward_shared_writer_4(b: &mut Bencher) {
+    bench_forward_shared_writer(b, 4);
+}
+
+#[bench]
+fn bench_forward_shared_writer_8(b: &mut Bencher) {
+    bench_forward_shared_writer(b, 8);
+}
+
+#[bench]
+fn bench_forward_record_senders_4(b: &mut Bencher) {
+    bench_forward_record_senders(b, 4);
+}
+
+#[bench]
+fn bench_forward_record_senders_8(b: &mut Bencher) {
+    bench_forward_record_senders(b, 8);
+}
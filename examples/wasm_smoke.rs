@@ -0,0 +1,47 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Parse a combined log line and serialize it as JSON, panicking if the result
+//! isn't what's expected. This is built for `wasm32-unknown-unknown` and run by
+//! `util/wasm-smoke.sh` to check that the parsers work in WebAssembly, where a
+//! panic traps and fails the script.
+
+use redeye::parser::parse_line_to_json;
+use serde_json::Value;
+
+const LINE: &str = concat!(
+    "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 ",
+    "\"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\"",
+);
+
+fn main() {
+    let json = parse_line_to_json("combined", LINE).unwrap();
+    let event: Value = serde_json::from_str(&json).unwrap();
+
+    assert_eq!("2000-10-10T13:55:36-07:00", event["@timestamp"]);
+    assert_eq!("/apache_pb.gif", event["requested_uri"]);
+    assert_eq!(200, event["status_code"]);
+    assert_eq!(2326, event["content_length"]);
+    assert_eq!(
+        "Mozilla/4.08 [en] (Win98; I ;Nav)",
+        event["request_headers"]["user_agent"]
+    );
+
+    assert!(parse_line_to_json("combined", "not a log line").is_err());
+    assert!(parse_line_to_json("yaml", LINE).is_err());
+}
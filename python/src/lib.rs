@@ -0,0 +1,164 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Python bindings for the Redeye parsers
+//!
+//! Built as the `redeye` Python module by maturin, see `pyproject.toml`.
+
+// Triggered by code generated by the pyo3 macros for methods returning PyResult
+#![allow(clippy::useless_conversion)]
+
+use chrono::SecondsFormat;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
+use pyo3::types::{PyDict, PyList};
+use redeye::parser::{self, LogLineParser};
+use redeye::types::{LogFieldValue, RedeyeError};
+use std::collections::HashMap;
+
+/// `datetime.datetime`, looked up once.
+static DATETIME: GILOnceCell<Py<PyAny>> = GILOnceCell::new();
+
+/// Error raised for a line that can't be parsed, with the reason it couldn't be
+/// parsed and the line itself.
+#[pyclass(extends = PyValueError, module = "redeye")]
+struct RedeyeParseError {
+    #[pyo3(get)]
+    reason: String,
+    #[pyo3(get)]
+    line: String,
+}
+
+#[pymethods]
+impl RedeyeParseError {
+    #[new]
+    fn new(reason: String, line: String) -> Self {
+        RedeyeParseError { reason, line }
+    }
+
+    fn __str__(&self) -> String {
+        format!("{}: {}", self.reason, self.line)
+    }
+}
+
+/// Exception for a line that couldn't be parsed.
+fn parse_error(py: Python<'_>, err: &RedeyeError, line: &str) -> PyErr {
+    let reason = match err {
+        // The message of a parse error is the line, which is already included
+        RedeyeError::ParseError(_) => "line doesn't match the format".to_owned(),
+        e => e.to_string(),
+    };
+
+    match Bound::new(py, RedeyeParseError::new(reason, line.to_owned())) {
+        Ok(e) => PyErr::from_value_bound(e.into_any()),
+        Err(e) => e,
+    }
+}
+
+/// Parser for lines of a single access log format, either the name of a built
+/// in format (`common` or `combined`) or an Apache format string.
+///
+/// Events are returned as dicts of native Python values: timestamps are
+/// `datetime` objects with a fixed offset, and mappings such as request headers
+/// are nested dicts.
+#[pyclass(module = "redeye", frozen)]
+struct RedeyeParser {
+    parser: Box<dyn LogLineParser + Send + Sync>,
+}
+
+#[pymethods]
+impl RedeyeParser {
+    #[new]
+    fn new(format: &str) -> PyResult<Self> {
+        parser::parser_for(format)
+            .map(|parser| RedeyeParser { parser })
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Parse a single line, raising `RedeyeParseError` if it can't be parsed.
+    fn parse<'py>(&self, py: Python<'py>, line: &str) -> PyResult<Bound<'py, PyDict>> {
+        match self.parser.parse(line) {
+            Ok(event) => mapping_to_dict(py, event.fields()),
+            Err(e) => Err(parse_error(py, &e, line)),
+        }
+    }
+
+    /// Parse a list of lines without holding the GIL, so other threads can run
+    /// while they're parsed. Raises `RedeyeParseError` for the first line that
+    /// can't be parsed, or if `skip_errors` is true, returns `None` for it.
+    #[pyo3(signature = (lines, skip_errors = false))]
+    fn parse_many<'py>(
+        &self,
+        py: Python<'py>,
+        lines: Vec<String>,
+        skip_errors: bool,
+    ) -> PyResult<Vec<Option<Bound<'py, PyDict>>>> {
+        let events: Vec<_> = py.allow_threads(|| lines.iter().map(|line| self.parser.parse(line)).collect());
+
+        events
+            .into_iter()
+            .zip(lines.iter())
+            .map(|(res, line)| match res {
+                Ok(event) => mapping_to_dict(py, event.fields()).map(Some),
+                Err(_) if skip_errors => Ok(None),
+                Err(e) => Err(parse_error(py, &e, line)),
+            })
+            .collect()
+    }
+}
+
+fn mapping_to_dict<'py>(py: Python<'py>, map: &HashMap<String, LogFieldValue>) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+    for (key, value) in map {
+        dict.set_item(key, value_to_py(py, value)?)?;
+    }
+    Ok(dict)
+}
+
+fn value_to_py(py: Python<'_>, value: &LogFieldValue) -> PyResult<PyObject> {
+    Ok(match value {
+        LogFieldValue::Mapping(map) => mapping_to_dict(py, map)?.into_any().unbind(),
+        LogFieldValue::Timestamp(ts) => {
+            let datetime = DATETIME.get_or_try_init(py, || -> PyResult<_> {
+                Ok(py.import_bound("datetime")?.getattr("datetime")?.unbind())
+            })?;
+            // Before Python 3.11, fromisoformat only accepts 3 or 6 digits of
+            // fractional seconds and not `Z`, so always write microseconds and an
+            // offset. Python datetimes don't have more precision than that anyway.
+            let iso = ts.to_rfc3339_opts(SecondsFormat::Micros, false);
+            datetime.call_method1(py, "fromisoformat", (iso,))?
+        }
+        LogFieldValue::Text(v) => v.into_py(py),
+        LogFieldValue::Int(v) => v.into_py(py),
+        LogFieldValue::Float(v) => v.into_py(py),
+        LogFieldValue::Bool(v) => v.into_py(py),
+        LogFieldValue::List(vals) => {
+            let items = vals.iter().map(|v| value_to_py(py, v)).collect::<PyResult<Vec<_>>>()?;
+            PyList::new_bound(py, items).into_any().unbind()
+        }
+    })
+}
+
+#[pymodule]
+#[pyo3(name = "redeye")]
+fn redeye_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<RedeyeParser>()?;
+    m.add_class::<RedeyeParseError>()?;
+    Ok(())
+}
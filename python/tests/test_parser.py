@@ -0,0 +1,135 @@
+"""Tests for the Python bindings, run with pytest once the module is built.
+
+These use the same fixtures as the Rust tests in the top level tests directory
+so that both agree on what each line parses to.
+
+    $ cd python && maturin develop && pytest
+"""
+
+import os
+import threading
+from datetime import datetime, timedelta, timezone
+
+import pytest
+
+from redeye import RedeyeParseError, RedeyeParser
+
+FIXTURES = os.path.join(os.path.dirname(__file__), "..", "..", "tests", "fixtures")
+
+FIRST = '127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326'
+SECOND = '::1 - - [10/Oct/2000:13:55:37 -0700] "POST /login HTTP/1.1" 302 -'
+COMBINED = FIRST + ' "http://www.example.com/start.html" "Mozilla/4.08 [en] (Win98; I ;Nav)"'
+
+
+def fixture_lines(name):
+    with open(os.path.join(FIXTURES, name), "rb") as f:
+        return f.read().decode("utf-8").splitlines()
+
+
+def test_parse_common():
+    event = RedeyeParser("common").parse(FIRST)
+
+    assert event == {
+        "@timestamp": datetime(2000, 10, 10, 13, 55, 36, tzinfo=timezone(timedelta(hours=-7))),
+        "@version": "1",
+        "content_length": 2326,
+        "message": FIRST,
+        "method": "GET",
+        "protocol": "HTTP/1.0",
+        "remote_host": "127.0.0.1",
+        "remote_user": "frank",
+        "requested_uri": "/apache_pb.gif",
+        "requested_url": "GET /apache_pb.gif HTTP/1.0",
+        "status_code": 200,
+    }
+    assert event["@timestamp"].utcoffset() == timedelta(hours=-7)
+
+
+def test_parse_combined_mapping():
+    event = RedeyeParser("combined").parse(COMBINED)
+
+    assert event["request_headers"] == {
+        "referer": "http://www.example.com/start.html",
+        "user_agent": "Mozilla/4.08 [en] (Win98; I ;Nav)",
+    }
+
+
+def test_parse_fractional_timestamps():
+    # Times with nanoseconds and from floats must convert on every Python the
+    # module supports, not only those where fromisoformat accepts any precision.
+    before = datetime.now(timezone.utc)
+    event = RedeyeParser("morgan_tiny").parse("GET / 200 2326 - 2.134 ms")
+    assert before - timedelta(seconds=1) <= event["@timestamp"] <= datetime.now(timezone.utc)
+
+    event = RedeyeParser("caddy").parse(
+        '{"ts":1646861401.123456789,"request":{"remote_addr":"[::1]:55555","method":"GET","uri":"/"},"status":200}'
+    )
+    assert event["@timestamp"] == datetime(2022, 3, 9, 21, 30, 1, 123456, tzinfo=timezone.utc)
+
+
+def test_parse_custom_format():
+    event = RedeyeParser("%h %>s %D").parse("127.0.0.1 404 1534")
+
+    assert event["status_code"] == 404
+    assert event["duration_us"] == 1534
+
+
+def test_unknown_format():
+    with pytest.raises(ValueError, match="unknown format 'yaml'"):
+        RedeyeParser("yaml")
+
+
+def test_parse_error():
+    with pytest.raises(RedeyeParseError) as info:
+        RedeyeParser("common").parse("not a log line")
+
+    assert isinstance(info.value, ValueError)
+    assert info.value.line == "not a log line"
+    assert info.value.reason == "line doesn't match the format"
+    assert str(info.value) == "line doesn't match the format: not a log line"
+
+
+def test_parse_fixture():
+    # Good lines, a bad line, CRLF line endings, and empty lines
+    parser = RedeyeParser("common")
+    events, errors = [], []
+    for line in fixture_lines("mixed.log"):
+        try:
+            events.append(parser.parse(line))
+        except RedeyeParseError as e:
+            errors.append(e.line)
+
+    assert [e["requested_uri"] for e in events] == ["/apache_pb.gif", "/login", "/apache_pb.gif"]
+    assert errors == ["not a log line", "", ""]
+
+
+def test_parse_many():
+    lines = fixture_lines("mixed.log")
+    parser = RedeyeParser("common")
+
+    events = parser.parse_many(lines, skip_errors=True)
+    assert [e["status_code"] if e else None for e in events] == [200, None, 302, None, None, 200]
+    assert events[0] == parser.parse(lines[0])
+
+    with pytest.raises(RedeyeParseError) as info:
+        parser.parse_many(lines)
+    assert info.value.line == "not a log line"
+
+
+def test_parse_many_threads():
+    lines = [FIRST, SECOND] * 5000
+    parser = RedeyeParser("common")
+    results = [None] * 4
+
+    def parse(i):
+        results[i] = parser.parse_many(lines)
+
+    threads = [threading.Thread(target=parse, args=(i,)) for i in range(len(results))]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+
+    for events in results:
+        assert len(events) == len(lines)
+        assert events[1]["method"] == "POST"
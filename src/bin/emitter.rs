@@ -0,0 +1,336 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Emitter - Write realistic access log lines for testing Redeye
+
+use chrono::{DateTime, FixedOffset, Local};
+use clap::Clap;
+use redeye::generate::AccessLogGenerator;
+use redeye::parser::NamedFormat;
+use redeye::replay::{LineTimestamp, ReplaySchedule};
+use redeye::scenario::{Burst, HotClient, Scenario, StatusSpike};
+use redeye::timestamp::TimestampContext;
+use redeye::types::parse_duration;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Subcommands of emitter. When the first argument isn't one of them, options
+/// are parsed as `generate`.
+#[derive(Clap, Debug)]
+#[clap(name = "emitter")]
+enum EmitterCommand {
+    /// Write generated access log lines
+    Generate(GenerateOptions),
+    /// Write the lines of an existing access log with their original pacing
+    Replay(ReplayOptions),
+}
+
+impl EmitterCommand {
+    /// Parse the subcommand and options from command line arguments, including the
+    /// name of the program.
+    fn try_parse_args(args: Vec<OsString>) -> Result<Self, clap::Error> {
+        match args.get(1).and_then(|a| a.to_str()) {
+            Some("generate") | Some("replay") => EmitterCommand::try_parse_from(args),
+            _ => GenerateOptions::try_parse_from(args).map(EmitterCommand::Generate),
+        }
+    }
+}
+
+/// Emitter writes generated access log lines to stdout at a steady rate, for
+/// load testing Redeye. Lines have realistic status codes, paths, clients, user
+/// agents, and referers, and timestamps that advance in real time (or faster,
+/// with --time-scale). Runs with the same --seed and --start write the same lines.
+///
+/// Scenarios make traffic change the way it does during incidents: --burst,
+/// --hot-client, --scanner-rate, and --status-spike can each be given more than
+/// once and combine with each other. Bursts and status spikes first start
+/// `every` after the emitter does.
+#[derive(Clap, Debug)]
+#[clap(name = "emitter")]
+struct GenerateOptions {
+    /// format of the lines written.
+    #[clap(long, default_value = "combined", possible_values = NamedFormat::NAMES)]
+    format: NamedFormat,
+
+    /// number of lines written per second.
+    #[clap(long, default_value = "10")]
+    rate: f64,
+
+    /// stop after this long, e.g. "30s" or "5m", instead of running until
+    /// interrupted.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    duration: Option<Duration>,
+
+    /// seed for generating lines so that runs can be reproduced. A random seed
+    /// is used if not set.
+    #[clap(long)]
+    seed: Option<u64>,
+
+    /// timestamp of the first line as RFC 3339, e.g. 2020-10-10T13:55:36-07:00.
+    /// The current time is used if not set.
+    #[clap(long, parse(try_from_str = DateTime::parse_from_rfc3339))]
+    start: Option<DateTime<FixedOffset>>,
+
+    /// how much faster timestamps advance than real time, e.g. 60 to write an
+    /// hour of logs every minute.
+    #[clap(long, default_value = "1")]
+    time_scale: f64,
+
+    /// percentage of lines, between 0 and 100, that are deliberately malformed so
+    /// that they can't be parsed.
+    #[clap(long, default_value = "0")]
+    error_rate: f64,
+
+    /// periodically multiply the rate lines are written at, e.g.
+    /// "every=30s,duration=5s,multiplier=20".
+    #[clap(long, number_of_values = 1, value_name = "SPEC")]
+    burst: Vec<Burst>,
+
+    /// make a single client responsible for a fraction of requests, e.g.
+    /// "10.0.0.5:0.4".
+    #[clap(long, number_of_values = 1, value_name = "ADDRESS:FRACTION")]
+    hot_client: Vec<HotClient>,
+
+    /// fraction of lines, between 0 and 1, that are requests for hostile paths
+    /// from scanners, mostly with binary user agents.
+    #[clap(long, default_value = "0")]
+    scanner_rate: f64,
+
+    /// periodically make a fraction of requests fail with a status, e.g.
+    /// "503:every=60s,duration=10s,fraction=0.5".
+    #[clap(long, number_of_values = 1, value_name = "SPEC")]
+    status_spike: Vec<StatusSpike>,
+}
+
+/// Write the lines of a Common or Combined format access log to stdout with the
+/// same gaps between them as their timestamps, for testing Redeye with real
+/// traffic. Lines without a timestamp that can be parsed are written straight
+/// away.
+#[derive(Clap, Debug)]
+struct ReplayOptions {
+    /// access log to replay.
+    path: PathBuf,
+
+    /// how much faster than the original to replay lines, e.g. 10 to replay an
+    /// hour of logs in six minutes. Zero replays them as fast as possible.
+    #[clap(long, default_value = "1")]
+    speed: f64,
+
+    /// start again from the beginning of the log after the last line, until
+    /// interrupted.
+    #[clap(long = "loop")]
+    repeat: bool,
+
+    /// replace the timestamp of each line with the time it's written.
+    #[clap(long)]
+    rewrite_time: bool,
+}
+
+fn main() {
+    let command = EmitterCommand::try_parse_args(env::args_os().collect()).unwrap_or_else(|e| e.exit());
+    let res = match command {
+        EmitterCommand::Generate(opts) => generate(&opts),
+        EmitterCommand::Replay(opts) => replay(&opts),
+    };
+
+    match res {
+        Ok(()) => (),
+        // Stopping when whatever we're writing to goes away is expected
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+        Err(e) => {
+            eprintln!("emitter: error: {}", e);
+            process::exit(3);
+        }
+    }
+}
+
+/// Validate options for generating lines and write them to stdout.
+fn generate(opts: &GenerateOptions) -> io::Result<()> {
+    if !opts.rate.is_finite() || opts.rate <= 0.0 {
+        eprintln!("emitter: error: --rate must be greater than zero");
+        process::exit(1);
+    }
+
+    if !opts.time_scale.is_finite() || opts.time_scale <= 0.0 {
+        eprintln!("emitter: error: --time-scale must be greater than zero");
+        process::exit(1);
+    }
+
+    if !(0.0..=100.0).contains(&opts.error_rate) {
+        eprintln!("emitter: error: --error-rate must be between 0 and 100");
+        process::exit(1);
+    }
+
+    if !(0.0..=1.0).contains(&opts.scanner_rate) {
+        eprintln!("emitter: error: --scanner-rate must be between 0 and 1");
+        process::exit(1);
+    }
+
+    if opts.hot_client.iter().map(|c| c.fraction).sum::<f64>() > 1.0 {
+        eprintln!("emitter: error: --hot-client fractions must not add up to more than 1");
+        process::exit(1);
+    }
+
+    let scenario = opts
+        .burst
+        .iter()
+        .fold(Scenario::new(), |s, b| s.burst(*b))
+        .scanner_rate(opts.scanner_rate);
+    let scenario = opts.hot_client.iter().fold(scenario, |s, c| s.hot_client(*c));
+    let scenario = opts.status_spike.iter().fold(scenario, |s, p| s.status_spike(*p));
+
+    let seed = opts.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+    });
+
+    let generator = AccessLogGenerator::new(opts.format, seed)
+        .error_rate(opts.error_rate / 100.0)
+        .scenario(scenario.clone());
+    let start = opts.start.unwrap_or_else(|| Local::now().into());
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    emit(generator, &scenario, start, opts, &mut out).and_then(|_| out.flush())
+}
+
+/// Validate options for replaying a log and write its lines to stdout.
+fn replay(opts: &ReplayOptions) -> io::Result<()> {
+    if !opts.speed.is_finite() || opts.speed < 0.0 {
+        eprintln!("emitter: error: --speed must be zero or greater");
+        process::exit(1);
+    }
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    resend(opts, &mut out).and_then(|_| out.flush())
+}
+
+/// Write lines at the configured rate, as changed by the scenario, until the
+/// duration has passed, if there is one. Each line is timestamped with the time
+/// it's due to be written, scaled by the time scale, so timestamps only depend on
+/// the start time, rate, and scenario.
+fn emit<W>(
+    mut generator: AccessLogGenerator,
+    scenario: &Scenario,
+    start: DateTime<FixedOffset>,
+    opts: &GenerateOptions,
+    out: &mut W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let began = Instant::now();
+    let mut due = Duration::from_secs(0);
+
+    loop {
+        if opts.duration.map(|d| due >= d).unwrap_or(false) {
+            return Ok(());
+        }
+
+        wait_until(began, due, out)?;
+        let offset = chrono::Duration::microseconds((due.as_secs_f64() * opts.time_scale * 1_000_000.0) as i64);
+        writeln!(out, "{}", generator.line(start + offset, due))?;
+        due = scenario.next_due(opts.rate, due);
+    }
+}
+
+/// Write the lines of the log at the times they're due, starting over after the
+/// last line when looping. Times are measured from when the replay began rather
+/// than between lines so that delays writing one line don't push back the rest.
+fn resend<W>(opts: &ReplayOptions, out: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    let context = TimestampContext::new();
+    let mut schedule = ReplaySchedule::new(opts.speed);
+    let start: DateTime<FixedOffset> = Local::now().into();
+    let began = Instant::now();
+    let mut buf = Vec::new();
+
+    loop {
+        let mut reader = BufReader::new(File::open(&opts.path)?);
+        let mut lines: u64 = 0;
+
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+
+            // Make sure the last line doesn't run into the first when looping
+            if buf.last() != Some(&b'\n') {
+                buf.push(b'\n');
+            }
+
+            lines += 1;
+            let line = match std::str::from_utf8(&buf) {
+                Ok(line) => line,
+                Err(_) => {
+                    out.write_all(&buf)?;
+                    continue;
+                }
+            };
+
+            match LineTimestamp::find(line, &context) {
+                Some(found) => {
+                    wait_until(began, schedule.due(found.timestamp), out)?;
+                    if opts.rewrite_time {
+                        // Based on the monotonic clock so rewritten timestamps never go
+                        // backwards, even across passes of the log.
+                        let now = start
+                            + chrono::Duration::from_std(began.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+                        out.write_all(found.rewrite(line, now).as_bytes())?;
+                    } else {
+                        out.write_all(&buf)?;
+                    }
+                }
+                None => out.write_all(&buf)?,
+            }
+        }
+
+        if !opts.repeat || lines == 0 {
+            return Ok(());
+        }
+
+        schedule.restart();
+    }
+}
+
+/// Sleep until the given time after `began`, flushing anything written so far
+/// first so that it isn't held back while waiting.
+fn wait_until<W>(began: Instant, due: Duration, out: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    let elapsed = began.elapsed();
+    if due > elapsed {
+        out.flush()?;
+        thread::sleep(due - elapsed);
+    }
+
+    Ok(())
+}
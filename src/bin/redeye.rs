@@ -18,73 +18,3304 @@
 
 //! Redeye - Parse Apache-style access logs into Logstash JSON
 
-use clap::Clap;
-use redeye::parser::{CombinedLogLineParser, CommonLogLineParser, LogLineParser};
-use redeye::types::RedeyeError;
-use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Write};
+use chrono::DateTime;
+use clap::{App, Arg, ArgGroup, ArgMatches, ArgSettings, Clap, ErrorKind, IntoApp};
+use redeye::aggregate::{Metric, RequestSummary, TopValues};
+use redeye::bench::{BenchReport, BenchRun};
+use redeye::config::{Config, OptionKind, Setting, SettingValue, Source};
+use redeye::dedup::Dedup;
+use redeye::enrich::{
+    BotDetectionEnricher, BotPattern, CampaignEnricher, Enricher, HttpVersionEnricher, PathNormalizerEnricher,
+    QueryStringEnricher, SeverityEnricher, ThroughputEnricher,
+};
+use redeye::failures::Failures;
+use redeye::filter::Filter;
+use redeye::follow::{FileFollower, FollowFrom};
+use redeye::format::LogFormat;
+#[cfg(unix)]
+use redeye::forward::UnixConnector;
+use redeye::forward::{Connector, Forwarder, LogBuffer, TcpConnector};
+use redeye::generate::AccessLogGenerator;
+use redeye::geoip::{AsnEnricher, GeoIpEnricher, MaxMindAsnDatabase, MaxMindCityDatabase};
+#[cfg(feature = "http")]
+use redeye::http::HttpConnector;
+use redeye::http::{parse_header, HttpTarget};
+use redeye::io::{
+    open_file, read_record, Delimiter, InputSpec, Output, PeriodicFlusher, RotatedName, RotatingFileWriter,
+    RotationPolicy, Utf8Policy,
+};
+#[cfg(feature = "journal")]
+use redeye::journal::{JournalFollower, JournalOptions};
+use redeye::kafka::KafkaConfig;
+#[cfg(feature = "kafka")]
+use redeye::kafka::{KafkaSink, RdKafkaProducer};
+use redeye::net::DatagramReceiver;
+use redeye::output::{ColorChoice, OutputFormat, PrettyRenderer};
+use redeye::parser::{CustomLogLineParser, LogLineParser, NamedFormat, ParserRegistry, SyslogLogLineParser};
+use redeye::pipeline::{self, Collect, Handled, PipelineConfig, PipelineError, PipelineSummary};
+#[cfg(feature = "redis")]
+use redeye::redis::RedisConnector;
+use redeye::redis::{RedisMode, RedisTarget};
+use redeye::sample::Sampler;
+use redeye::select::Selection;
+use redeye::spill::SpillQueue;
+use redeye::stats::{Stats, StatsSnapshot};
+use redeye::timestamp::{AssumedZone, TimestampContext};
+use redeye::types::{parse_duration, DurationUnit, LogEvent, RedeyeError, RedeyeResult};
+use redeye::warnings::{WarningFormat, WarningSink};
+use redeye::watch::{FileWatcher, Pattern};
+use redeye::workers::{Processed, Record, WorkerPool};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, stdin, stdout, BufRead, BufReader, BufWriter, Cursor, IsTerminal};
+use std::iter;
+use std::mem;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Exit code for invalid command line options or configuration.
+const EXIT_USAGE: i32 = 1;
+
+/// Exit code when there were more invalid lines than allowed by --strict, --max-errors, or --check-threshold.
+const EXIT_TOO_MANY_ERRORS: i32 = 2;
+
+/// Exit code when input couldn't be read or output couldn't be written.
+const EXIT_IO_ERROR: i32 = 3;
+
+/// Exit code when stopped early by SIGINT or SIGTERM, following the shell convention for SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Name of the journal used as the source of its entries, e.g. for `source_file`.
+#[cfg(feature = "journal")]
+const JOURNAL_SOURCE: &str = "journal";
+
+/// Number of the most common distinct failures included in the report of the stats subcommand.
+const STATS_TOP_FAILURES: usize = 5;
+
+/// Name of the generated input used as the source of its lines by the bench subcommand.
+const BENCH_SOURCE: &str = "bench";
+
+/// Seed for lines generated by the bench subcommand, fixed so that results on
+/// different machines are for the same lines.
+const BENCH_SEED: u64 = 1;
+
+/// Formats that aren't access logs, so bench can't generate lines for them.
+const NOT_ACCESS_LOG_FORMATS: &[&str] = &["error", "referer", "agent"];
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[cfg(feature = "count-allocations")]
+static ALLOCATIONS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// System allocator that counts the allocations made, for the bench subcommand.
+#[cfg(feature = "count-allocations")]
+struct CountingAllocator;
+
+#[cfg(feature = "count-allocations")]
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        std::alloc::System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+/// Number of allocations (including reallocations) made so far, if Redeye was
+/// built with the count-allocations feature.
+#[cfg(feature = "count-allocations")]
+fn allocations() -> Option<u64> {
+    Some(ALLOCATIONS.load(Ordering::Relaxed))
+}
+
+#[cfg(not(feature = "count-allocations"))]
+fn allocations() -> Option<u64> {
+    None
+}
 
 /// Redeye converts NCSA or Apache HTTPd style access logs to JSON understood by
-/// Logstash. Access log entries are read line by line from stdin, converted to
-/// Logstash JSON, and emitted on stdout. Currently Common and Combined access
-/// log formats are supported. For more information about these formats, see
+/// Logstash. Access log entries are read line by line from the given files (or
+/// stdin), converted to Logstash JSON, and emitted on stdout. Common, Combined, and custom
+/// Apache or nginx access log formats are supported. For more information about these formats, see
 /// https://httpd.apache.org/docs/current/logs.html#accesslog",
 #[derive(Clap, Debug)]
-#[clap(name = "redeye")]
+#[clap(
+    name = "redeye",
+    group = ArgGroup::new("log-format"),
+    after_help = "SUBCOMMANDS:\n    parse         write each log entry as JSON (the default when no subcommand is given)\n    stats         print a report of requests instead of writing log entries, see redeye stats --help\n    top           print the values of a field with the most requests or bytes, see redeye top --help\n    check-config  check a configuration file and print the settings it results in\n\nEXIT STATUS:\n    0    all input was read\n    1    invalid options or configuration\n    2    too many invalid lines, see --strict, --max-errors, and --check\n    3    input couldn't be read or output couldn't be written\n    130  stopped by SIGINT or SIGTERM after writing all input read\n\nENVIRONMENT:\n    REDEYE_<OPTION>  value of any option not given on the command line, e.g. REDEYE_OUTPUT_BUFFER=65536.\n                     Lists are comma separated or numbered, e.g. REDEYE_FILTER_0, and flags are true or false"
+)]
 struct RedeyeOptions {
-    /// parse log entries assuming the Common log format. Entries
-    /// that don't match this format will be discarded and a warning
-    /// will be printed to stderr.
+    /// read settings from this TOML file. Each key is the name of an option,
+    /// e.g. output-buffer = 65536, and keys in a table are prefixed with its
+    /// name, e.g. buffer = 65536 in an [output] table. Options given on the
+    /// command line or in REDEYE_* environment variables override settings from
+    /// the file.
     #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// parse log entries in the format with this name, e.g. common, combined,
+    /// or nginx_timed. Use --list-formats to see every format. Entries that
+    /// don't match this format will be discarded and a warning will be printed
+    /// to stderr. Use --format-apache, --format-nginx, or --format-file for
+    /// other formats.
+    #[clap(long, group = "log-format", value_name = "NAME")]
+    format: Option<String>,
+
+    /// same as --format common.
+    #[clap(long, hidden = true, group = "log-format")]
     common_format: bool,
 
-    /// parse log entries assuming the Combined log format. Entries
-    /// that don't match this format will be discarded and a warning
-    /// will be printed to stderr.
-    #[clap(long)]
+    /// same as --format combined.
+    #[clap(long, hidden = true, group = "log-format")]
     combined_format: bool,
 
     /// how large a buffer to use when writing output, in bytes.
     #[clap(long, default_value = "1024")]
     output_buffer: usize,
 
+    /// flush output after every event instead of when the output buffer is
+    /// full. Enabled automatically when writing to a terminal.
+    #[clap(long)]
+    line_buffered: bool,
+
+    /// flush output at least this often, e.g. "1s" or "500ms", even when the
+    /// output buffer isn't full, so that events aren't delayed when input is
+    /// slow.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    flush_interval: Option<Duration>,
+
     /// how large a buffer to use when reading input, in bytes.
     #[clap(long, default_value = "1024")]
     input_buffer: usize,
+
+    /// flag requests made by crawlers, bots, and scripted HTTP clients
+    /// based on their user agent with the fields `bot` and `bot_name`.
+    #[clap(long)]
+    detect_bots: bool,
+
+    /// file of additional bot user agent patterns, one per line. Lines may
+    /// optionally start with a bot name followed by a tab. Implies
+    /// --detect-bots.
+    #[clap(long, parse(from_os_str))]
+    bot_patterns: Option<PathBuf>,
+
+    /// treat requests without a user agent as made by humans instead of
+    /// bots when detecting bots.
+    #[clap(long)]
+    bot_allow_missing_ua: bool,
+
+    /// treat requests for paths that are only fetched by bots, such as
+    /// `/robots.txt`, as bots regardless of user agent when detecting bots.
+    #[clap(long)]
+    bot_check_paths: bool,
+
+    /// parse the query string of requested URIs into the `query_params`
+    /// field.
+    #[clap(long)]
+    parse_query: bool,
+
+    /// extract `utm_*` marketing campaign parameters from the query string
+    /// into the `campaign` field. Implies --parse-query.
+    #[clap(long)]
+    extract_campaign: bool,
+
+    /// remove campaign parameters from `query_params` after extracting
+    /// them. Implies --extract-campaign.
+    #[clap(long)]
+    strip_campaign_params: bool,
+
+    /// add a numeric `http_version` field derived from the protocol of
+    /// each request, e.g. `1.1` for `HTTP/1.1`.
+    #[clap(long)]
+    http_version: bool,
+
+    /// add a normalized form of the requested path as `uri_normalized` and
+    /// flag directory traversal attempts with `path_traversal_attempt`.
+    #[clap(long)]
+    normalize_path: bool,
+
+    /// name of a request duration field used to add the throughput of each
+    /// request as the `bytes_per_second` field.
+    #[clap(long)]
+    throughput_duration_field: Option<String>,
+
+    /// unit of the request duration field used to compute throughput, one of
+    /// `s`, `ms`, or `us`.
+    #[clap(long, default_value = "s")]
+    throughput_duration_unit: DurationUnit,
+
+    /// add a syslog-style severity derived from the status code of each
+    /// request as the `severity` and `severity_code` fields.
+    #[clap(long)]
+    severity: bool,
+
+    /// override the severity of specific status codes, e.g.
+    /// "404=notice,499=warning". Implies --severity.
+    #[clap(long)]
+    severity_map: Option<String>,
+
+    /// path to a MaxMind City database (e.g. GeoLite2-City.mmdb) used to
+    /// add the location of each client as the `geoip` field.
+    #[clap(long, parse(from_os_str))]
+    geoip_db: Option<PathBuf>,
+
+    /// path to a MaxMind ASN database (e.g. GeoLite2-ASN.mmdb) used to
+    /// add the network of each client as the `asn` and `as_org` fields.
+    #[clap(long, parse(from_os_str))]
+    asn_db: Option<PathBuf>,
+
+    /// add the name of the file each entry was read from as the
+    /// `source_file` field. Entries read from stdin use `-`.
+    #[clap(long)]
+    stamp_source: bool,
+
+    /// files to read log entries from, in order. Use `-` to read from stdin.
+    /// If no files are given, entries are read from stdin.
+    #[clap(name = "FILE", parse(from_os_str))]
+    files: Vec<PathBuf>,
+
+    /// read the files matching a path, which may be a glob pattern, in the
+    /// given format from --list-formats instead of using --format, e.g.
+    /// 'path=/var/log/nginx/*.log,format=combined'. May be given multiple times
+    /// to read files in different formats. Events include the file they were
+    /// read from as `source_file` and its format as `source_format`.
+    #[clap(
+        long,
+        value_name = "SPEC",
+        number_of_values = 1,
+        conflicts_with_all = &["FILE", "watch", "journal", "listen-udp"]
+    )]
+    input: Vec<InputSpec>,
+
+    /// keep reading files after reaching the end of them, waiting for new
+    /// entries to be written like `tail -F`. Rotated or truncated files are
+    /// reopened and read from the start. Stop with SIGTERM or SIGINT.
+    #[clap(long)]
+    follow: bool,
+
+    /// where to start reading files when following them: `start` to emit
+    /// existing entries first, or `end` to only emit new entries.
+    #[clap(long, default_value = "end")]
+    follow_from: FollowFrom,
+
+    /// follow every file matching this glob pattern, e.g.
+    /// '/var/log/nginx/*.access.log', like --follow. Files created later are
+    /// read from the start and files that are deleted are read to the end and
+    /// then closed. Events include the file they were read from as
+    /// `source_file`.
+    #[clap(long, conflicts_with_all = &["follow", "FILE"])]
+    watch: Option<Pattern>,
+
+    /// how often to look for new files matching --watch, e.g. "10s".
+    #[clap(long, default_value = "5s", parse(try_from_str = parse_duration))]
+    watch_interval: Duration,
+
+    /// save how far each file matching --watch has been read, or the last entry
+    /// read with --journal, to this file and continue from there when restarted
+    /// instead of from --follow-from.
+    #[clap(long, parse(from_os_str))]
+    state_file: Option<PathBuf>,
+
+    /// follow entries in the systemd journal instead of reading files, like
+    /// --follow. The MESSAGE of each entry is parsed and the host, unit, and
+    /// time it was written to the journal are added as the `journal_hostname`,
+    /// `journal_unit`, and `received_at` fields. Runs journalctl and requires
+    /// Redeye to be built with the journal feature.
+    #[clap(long, conflicts_with_all = &["follow", "FILE", "watch"])]
+    journal: bool,
+
+    /// with --journal, only follow entries from this systemd unit, e.g.
+    /// nginx.service. May be given multiple times.
+    #[clap(long, number_of_values = 1, requires = "journal")]
+    #[cfg_attr(not(feature = "journal"), allow(dead_code))]
+    journal_unit: Vec<String>,
+
+    /// read all FILEs at once and emit events in order of their timestamps,
+    /// instead of reading one file after another. Each file is parsed on its own
+    /// thread. Events without a timestamp are emitted as they're read.
+    #[clap(long, conflicts_with_all = &["follow", "watch", "journal", "listen-udp"])]
+    merge_by_time: bool,
+
+    /// with --merge-by-time, how far out of order events within a file may be,
+    /// e.g. "30s". Events are held until every file has an event at least this
+    /// much later. Events further out of order are emitted when they're read and
+    /// counted as late in the summary.
+    #[clap(long, default_value = "0s", parse(try_from_str = parse_duration))]
+    merge_window: Duration,
+
+    /// drop events that are duplicates of the event before them, e.g. repeated
+    /// health checks. Events are compared by every field except @timestamp and
+    /// message, or only the given comma separated fields, e.g.
+    /// --dedup-consecutive=method,uri,status_code. The next event written after
+    /// a run of duplicates has a `repeat_count` field with the number dropped.
+    #[clap(long, require_equals = true, min_values = 0, value_name = "FIELDS")]
+    dedup_consecutive: Option<Option<String>>,
+
+    /// with --dedup-consecutive, how long a run of duplicates can last before
+    /// the last one is written with the number dropped, e.g. "5m".
+    #[clap(long, default_value = "60s", parse(try_from_str = parse_duration))]
+    dedup_interval: Duration,
+
+    /// don't decompress gzip, zstd, or bzip2 compressed input files. By
+    /// default, compressed files are detected by their extension or contents.
+    #[clap(long)]
+    no_auto_decompress: bool,
+
+    /// write events to this file instead of stdout.
+    #[clap(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// rotate the output file once it is at least this many bytes.
+    /// Requires --output.
+    #[clap(long)]
+    rotate_size: Option<u64>,
+
+    /// rotate the output file once it has been written to for this long,
+    /// e.g. "30m" or "1d". Requires --output.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    rotate_interval: Option<Duration>,
+
+    /// number of rotated output files to keep, deleting the oldest ones.
+    /// Requires --output.
+    #[clap(long)]
+    rotate_keep: Option<usize>,
+
+    /// name of rotated output files, using {name} for the output file name,
+    /// {seq} for a sequence number, and %Y, %m, %d, %H, %M, and %S for the
+    /// time it was rotated in UTC. Defaults to "{name}.%Y%m%d%H%M%S".
+    /// Requires --output.
+    #[clap(long)]
+    rotate_name: Option<RotatedName>,
+
+    /// parse log entries using the given Apache `LogFormat` string, e.g.
+    /// '%h %l %u %t "%r" %>s %b %D'. varnishncsa format strings, with
+    /// extensions such as %{Varnish:handling}x, are accepted too.
+    #[clap(long, group = "log-format")]
+    format_apache: Option<String>,
+
+    /// parse log entries using the given nginx `log_format` string, e.g.
+    /// '$remote_addr [$time_local] "$request" $status $request_time'.
+    #[clap(long, group = "log-format")]
+    format_nginx: Option<String>,
+
+    /// parse log entries using the Apache (or varnishncsa) or nginx format
+    /// string in the given file. Formats containing `%` directives are treated
+    /// as Apache formats, otherwise they are treated as nginx formats.
+    #[clap(long, group = "log-format", parse(from_os_str))]
+    format_file: Option<PathBuf>,
+
+    /// time zone of timestamps that don't include an offset, such as those
+    /// written with Apache's %{format}t, as an IANA name (e.g. Europe/Berlin) or
+    /// a fixed offset (e.g. -07:00). Local times that happen twice when clocks
+    /// go back are the earlier one, and those skipped when clocks go forward are
+    /// moved forward by the length of the gap. Timestamps without an offset are
+    /// invalid if this isn't set, except in error logs and Python development
+    /// server logs, which are assumed to be UTC.
+    #[clap(long, value_name = "ZONE", allow_hyphen_values = true)]
+    assume_timezone: Option<AssumedZone>,
+
+    /// print the name and type of each field parsed from log entries in the
+    /// selected format and exit.
+    #[clap(long)]
+    print_schema: bool,
+
+    /// print the name of each format that can be used with --format and exit.
+    #[clap(long)]
+    list_formats: bool,
+
+    /// print the value of each option and where it came from (the command line,
+    /// a REDEYE_* environment variable, the --config file, or the default) and
+    /// exit.
+    #[clap(long)]
+    print_config: bool,
+
+    /// stop with a nonzero exit code at the first line that can't be parsed.
+    #[clap(long)]
+    strict: bool,
+
+    /// stop with a nonzero exit code after more than this many lines that
+    /// can't be parsed. Use 0 to allow any number of them.
+    #[clap(long, default_value = "0")]
+    max_errors: u64,
+
+    /// don't print a summary of lines read, events emitted, and errors to
+    /// stderr after all input has been read.
+    #[clap(long)]
+    no_summary: bool,
+
+    /// print the summary as a JSON object instead of human readable text.
+    #[clap(long)]
+    summary_json: bool,
+
+    /// don't print warnings about lines that can't be parsed. The summary
+    /// still includes the number of them.
+    #[clap(long)]
+    quiet: bool,
+
+    /// maximum number of warnings of each kind to print per second. Further
+    /// warnings are counted and reported as suppressed.
+    #[clap(long, default_value = "10")]
+    warnings_per_second: u64,
+
+    /// format of warnings about lines that can't be parsed: text for human
+    /// readable lines or json for a JSON object per warning.
+    #[clap(long, value_name = "FORMAT", default_value = "text")]
+    warnings_format: WarningFormat,
+
+    /// only output events matching an expression, e.g. 'status_code >= 500' or
+    /// 'requested_uri startswith "/api/"'. May be given multiple times, in which
+    /// case events must match all of them. See the README for the syntax.
+    #[clap(long, number_of_values = 1)]
+    filter: Vec<Filter>,
+
+    /// only output the given comma separated fields, in order. Nested fields
+    /// are output at the top level under their last name, or a different name
+    /// given as 'path=name', e.g. 'status_code,request_headers.user_agent=ua'.
+    #[clap(long)]
+    select: Option<Selection>,
+
+    /// keep each event with this probability between 0 and 1, dropping the rest.
+    #[clap(long)]
+    sample_rate: Option<f64>,
+
+    /// seed for --sample-rate so the same events are kept each time the same
+    /// input is processed.
+    #[clap(long)]
+    sample_seed: Option<u64>,
+
+    /// with --sample-rate, keep or drop events based on a hash of this field
+    /// (e.g. remote_host) so that all events with the same value are kept or
+    /// dropped together.
+    #[clap(long)]
+    sample_key: Option<String>,
+
+    /// keep only the first of every N events, dropping the rest.
+    #[clap(long)]
+    sample_every: Option<u64>,
+
+    /// number of threads used to parse lines. Output is written in the same
+    /// order as the input regardless of the number of threads. Formats where
+    /// lines depend on the lines before them, e.g. w3c, use a single thread.
+    #[clap(long, default_value = "1")]
+    workers: usize,
+
+    /// byte that separates input lines, either "newline" or "nul". A carriage
+    /// return before a newline is also removed.
+    #[clap(long, default_value = "newline")]
+    input_delimiter: Delimiter,
+
+    /// largest input line in bytes, not counting the delimiter. Longer lines
+    /// (and datagrams or journal messages) are counted as errors and skipped
+    /// without ever holding more than N bytes of them in memory.
+    #[clap(long, value_name = "N")]
+    max_record_bytes: Option<usize>,
+
+    /// what to do with input lines that aren't valid UTF-8: "lossy" replaces
+    /// invalid bytes with the unicode replacement character, "skip" skips the
+    /// line and counts it, and "error" treats the line as an error.
+    #[clap(long, default_value = "lossy", value_name = "POLICY")]
+    invalid_utf8: Utf8Policy,
+
+    /// byte written after each output event, either "newline" or "nul".
+    #[clap(long, default_value = "newline")]
+    output_delimiter: Delimiter,
+
+    /// parse all input and report on lines that can't be handled instead of
+    /// writing events. A report with the rate of lines parsed successfully and
+    /// the most common failures with example lines is printed to stdout in
+    /// place of the summary.
+    #[clap(long, conflicts_with = "output")]
+    check: bool,
+
+    /// with --check, the fraction of lines (between 0 and 1) that may fail
+    /// before exiting with a nonzero status. By default any failure does.
+    #[clap(long, default_value = "0")]
+    check_threshold: f64,
+
+    /// with --check, the number of the most common distinct failures to report.
+    #[clap(long, default_value = "10")]
+    check_top: usize,
+
+    /// receive entries as UDP datagrams on this address (e.g. 0.0.0.0:514)
+    /// instead of reading files, until stopped. Each datagram is a single entry
+    /// and the address it was sent from is added as the `source_address` field.
+    #[clap(long)]
+    listen_udp: Option<String>,
+
+    /// with --listen-udp, size of the socket receive buffer in bytes. Larger
+    /// buffers avoid dropping datagrams during bursts. The OS default is used
+    /// if not set.
+    #[clap(long)]
+    udp_receive_buffer: Option<usize>,
+
+    /// with --listen-udp, size of the largest datagram in bytes that is parsed.
+    /// Larger datagrams are counted in the summary and skipped.
+    #[clap(long, default_value = "65507")]
+    udp_max_bytes: usize,
+
+    /// remove a syslog header (RFC 3164 or RFC 5424) from the start of each
+    /// entry before parsing it, e.g. when receiving entries from syslog with
+    /// --listen-udp. Entries without a header are parsed as-is.
+    #[clap(long)]
+    strip_syslog: bool,
+
+    /// send events to a TCP server at HOST:PORT (e.g. the tcp input of Logstash)
+    /// instead of writing them to stdout. If the connection is lost, events are
+    /// buffered while reconnecting and the oldest are dropped if the buffer fills.
+    #[clap(long, conflicts_with = "output")]
+    output_tcp: Option<String>,
+
+    /// with a remote output such as --output-tcp, largest number of events
+    /// buffered while reconnecting.
+    #[clap(long, default_value = "10000")]
+    reconnect_buffer_events: usize,
+
+    /// with a remote output such as --output-tcp, largest number of bytes of
+    /// events buffered while reconnecting.
+    #[clap(long, default_value = "16777216")]
+    reconnect_buffer_bytes: usize,
+
+    /// with a remote output such as --output-tcp, keep events in files in this
+    /// directory once the reconnect buffer is full instead of dropping them. They
+    /// are sent in order after reconnecting, by a later run of Redeye using the
+    /// same directory if needed.
+    #[clap(long, value_name = "DIR")]
+    spill_dir: Option<PathBuf>,
+
+    /// with --spill-dir, largest number of bytes of events kept on disk. Further
+    /// events are dropped.
+    #[clap(long, default_value = "1073741824")]
+    spill_max_bytes: u64,
+
+    /// with --spill-dir, number of bytes after which events are written to a new
+    /// file in the directory.
+    #[clap(long, default_value = "67108864")]
+    spill_segment_bytes: u64,
+
+    /// send events to a server listening on a Unix stream socket at this path
+    /// (e.g. a local Vector or Fluent Bit agent) instead of writing them to
+    /// stdout. Events are buffered the same as --output-tcp, including when the
+    /// socket doesn't exist yet.
+    #[clap(long, conflicts_with_all = &["output", "output-tcp"])]
+    output_unix: Option<PathBuf>,
+
+    /// send events as messages to a Kafka topic, configured with comma separated
+    /// name=value settings, e.g. brokers=k1:9092,k2:9092,topic=access-logs. The
+    /// value of the key=FIELD setting (remote_host by default) is used as the key
+    /// of messages. Requires Redeye to be built with the kafka feature.
+    #[clap(long, conflicts_with_all = &["output", "output-tcp", "output-unix"])]
+    output_kafka: Option<KafkaConfig>,
+
+    /// send events to a Redis server at this URL (e.g. redis://localhost:6379/0),
+    /// as read by the redis input of Logstash. Events are buffered the same as
+    /// --output-tcp. Requires Redeye to be built with the redis feature.
+    #[clap(long, conflicts_with_all = &["output", "output-tcp", "output-unix", "output-kafka"])]
+    output_redis: Option<String>,
+
+    /// with --output-redis, key of the list or stream events are added to.
+    #[clap(long, default_value = "logstash")]
+    redis_key: String,
+
+    /// with --output-redis, add events to a list (with RPUSH) or a stream (with
+    /// XADD). Possible values are "list" and "stream".
+    #[clap(long, default_value = "list")]
+    redis_mode: RedisMode,
+
+    /// with --redis-mode stream, add each event as JSON in a single field with
+    /// this name instead of adding each of its fields to the stream entry.
+    #[clap(long)]
+    redis_stream_field: Option<String>,
+
+    /// post batches of events to this URL (e.g. the http input of Logstash or the
+    /// _bulk API of Elasticsearch). Failed requests are retried and batches that
+    /// can't be sent are written to --dead-letter, if set. Requires Redeye to be
+    /// built with the http feature.
+    #[clap(
+        long,
+        conflicts_with_all = &["output", "output-tcp", "output-unix", "output-kafka", "output-redis"]
+    )]
+    output_http: Option<String>,
+
+    /// how events are framed. Possible values are "json" (a JSON object per line),
+    /// "es-bulk" (for the _bulk API of Elasticsearch, requires --output-http), and
+    /// "pretty-log" (an aligned, human readable line per event). Defaults to
+    /// pretty-log when writing to a terminal and json otherwise.
+    #[clap(long)]
+    output_format: Option<OutputFormat>,
+
+    /// with --output-format pretty-log, whether to colorize events. Possible
+    /// values are "auto" (when writing to a terminal and NO_COLOR isn't set),
+    /// "always", and "never".
+    #[clap(long, default_value = "auto")]
+    color: ColorChoice,
+
+    /// with --output-format pretty-log, add every field that doesn't have a
+    /// column to the end of each line as key=value.
+    #[clap(short = 'v', long)]
+    verbose: bool,
+
+    /// with --output-http, header sent with each request, as 'Name: value'. May
+    /// be given multiple times.
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_header))]
+    http_header: Vec<(String, String)>,
+
+    /// with --output-http, authenticate requests with this USER:PASSWORD.
+    #[clap(long)]
+    http_user: Option<String>,
+
+    /// with --output-http, compress requests with gzip.
+    #[clap(long)]
+    http_gzip: bool,
+
+    /// with --output-http, largest number of events sent in each request.
+    #[clap(long, default_value = "1000")]
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    http_batch_events: usize,
+
+    /// with --output-http, number of bytes of events after which a request is
+    /// sent without adding more events.
+    #[clap(long, default_value = "1048576")]
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    http_batch_bytes: usize,
+
+    /// with --output-http, longest amount of time to wait for more events to
+    /// fill a batch before sending it, e.g. "500ms" or "1s".
+    #[clap(long, default_value = "1s", parse(try_from_str = parse_duration))]
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    http_batch_wait: Duration,
+
+    /// with --output-http, number of times a failed request is retried before
+    /// its batch is given up on.
+    #[clap(long, default_value = "5")]
+    #[cfg_attr(not(feature = "http"), allow(dead_code))]
+    http_retries: u32,
+
+    /// append events that can't be sent to a remote output to this file instead
+    /// of dropping them, e.g. because retries are exhausted, they're rejected, or
+    /// they're still buffered when Redeye exits.
+    #[clap(long)]
+    dead_letter: Option<PathBuf>,
 }
 
-fn handle_redeye_error(err: RedeyeError) {
-    let display = match err {
-        RedeyeError::IoError(e) => format!("I/O error: {}", e),
-        RedeyeError::SerializationError(e) => format!("Serialization error: {}", e),
-        RedeyeError::TimestampParseError(e) => format!("Invalid timestamp: {}", e),
-        RedeyeError::ParseError(e) => format!("Invalid log line: {}", e),
-    };
+/// Subcommands of redeye. When the first argument isn't one of them, options are
+/// parsed as `parse` for compatibility with versions without subcommands.
+#[derive(Clap, Debug)]
+#[clap(name = "redeye")]
+enum RedeyeCommand {
+    /// Write each log entry as JSON
+    Parse(RedeyeOptions),
+    /// Print a report of requests instead of writing log entries
+    Stats(StatsOptions),
+    /// Print the values of a field with the most requests or bytes
+    Top(TopOptions),
+    /// Check a configuration file and print the settings it results in
+    CheckConfig(CheckConfigOptions),
+    /// Measure how quickly generated lines are processed
+    Bench(BenchOptions),
+}
+
+impl RedeyeCommand {
+    /// Parse the subcommand and options from command line arguments, including the
+    /// name of the program.
+    fn try_parse_args(args: Vec<OsString>) -> Result<Self, clap::Error> {
+        match args.get(1).and_then(|a| a.to_str()) {
+            Some("parse") | Some("stats") | Some("top") | Some("check-config") | Some("bench") => {
+                RedeyeCommand::try_parse_from(args)
+            }
+            _ => RedeyeOptions::try_parse_from(args).map(RedeyeCommand::Parse),
+        }
+    }
+
+    /// Options shared by all subcommands that process logs.
+    fn options(&self) -> Option<&RedeyeOptions> {
+        match self {
+            RedeyeCommand::Parse(opts) => Some(opts),
+            RedeyeCommand::Stats(stats) => Some(&stats.opts),
+            RedeyeCommand::Top(top) => Some(&top.opts),
+            RedeyeCommand::Bench(bench) => Some(&bench.opts),
+            RedeyeCommand::CheckConfig(_) => None,
+        }
+    }
 
-    eprintln!("redeye: warning: {}", display);
+    /// Name of the subcommand given on the command line, if any. Parse is the
+    /// default so it's only returned when given explicitly.
+    fn subcommand(&self, args: &[OsString]) -> Option<&'static str> {
+        match self {
+            RedeyeCommand::Parse(_) => args.get(1).filter(|a| *a == "parse").map(|_| "parse"),
+            RedeyeCommand::Stats(_) => Some("stats"),
+            RedeyeCommand::Top(_) => Some("top"),
+            RedeyeCommand::CheckConfig(_) => Some("check-config"),
+            RedeyeCommand::Bench(_) => Some("bench"),
+        }
+    }
+
+    /// Parse the subcommand and options again with settings from `REDEYE_*`
+    /// environment variables and the file given by --config added for options
+    /// that weren't given on the command line. Environment variables take
+    /// precedence over the file. Also returns the arguments used and where they
+    /// came from, for --print-config.
+    fn with_settings<I>(self, args: Vec<OsString>, vars: I) -> RedeyeResult<(Self, MergedArgs)>
+    where
+        I: IntoIterator<Item = (OsString, OsString)>,
+    {
+        let subcommand = self.subcommand(&args);
+        let path = match self.options() {
+            Some(opts) => opts.config.clone(),
+            None => return Ok((self, MergedArgs::new(subcommand, args))),
+        };
+
+        let app = options_app(subcommand);
+        let options: Vec<(String, OptionKind)> = app
+            .get_arguments()
+            .filter(|a| is_configurable(a) || a.get_name() == "config")
+            .map(|a| (option_name(a), option_kind(a)))
+            .collect();
+        let options: Vec<(&str, OptionKind)> = options.iter().map(|(n, k)| (n.as_str(), *k)).collect();
+
+        let mut env = Config::from_env(vars, &options)?;
+        let env_path = env.remove("config").and_then(|s| match s.value() {
+            SettingValue::Value(v) => Some(PathBuf::from(v)),
+            _ => None,
+        });
+
+        let file = match path.or(env_path) {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        let merged = merge_settings(subcommand, args, &[&env, &file])?;
+        let command = RedeyeCommand::try_parse_args(merged.args.clone())
+            .map_err(|e| RedeyeError::ConfigError(clap_error_message(&e)))?;
+        Ok((command, merged))
+    }
 }
 
-fn main() {
-    let opts = RedeyeOptions::parse();
+/// Options for the check-config subcommand.
+#[derive(Clap, Debug)]
+struct CheckConfigOptions {
+    /// TOML configuration file to check.
+    path: PathBuf,
+}
+
+/// Options that can't be set from a configuration file or the environment.
+const NOT_CONFIGURABLE: &[&str] = &["config", "help", "version"];
+
+/// Options that select the log format. Only one may be used so a format given on
+/// the command line replaces one from a configuration file.
+const FORMAT_OPTIONS: &[&str] = &[
+    "format",
+    "common-format",
+    "combined-format",
+    "format-apache",
+    "format-nginx",
+    "format-file",
+];
+
+/// Definition of the options of a subcommand (or of the default, parse).
+fn options_app(subcommand: Option<&str>) -> App<'static> {
+    match subcommand {
+        Some(name) => RedeyeCommand::into_app()
+            .get_subcommands()
+            .find(|c| c.get_name() == name)
+            .cloned()
+            .unwrap_or_else(RedeyeOptions::into_app),
+        None => RedeyeOptions::into_app(),
+    }
+}
+
+/// Split arguments into the program name and subcommand, and the options after them.
+fn split_subcommand<'a>(subcommand: Option<&str>, args: &'a [OsString]) -> (&'a [OsString], &'a [OsString]) {
+    let split = if subcommand.is_some() { 2 } else { 1 };
+    args.split_at(split.min(args.len()))
+}
+
+/// True for arguments without a flag. Clap only assigns their index when an
+/// app is built so it can't be used here.
+fn is_positional(arg: &Arg) -> bool {
+    arg.get_long().is_none() && arg.get_short().is_none()
+}
+
+/// True for options that can be set from a configuration file or the environment.
+fn is_configurable(arg: &Arg) -> bool {
+    !NOT_CONFIGURABLE.contains(&arg.get_name()) && (arg.get_long().is_some() || is_positional(arg))
+}
+
+/// Name of an option in settings, its long name or the lower case name of a
+/// positional argument.
+fn option_name(arg: &Arg) -> String {
+    arg.get_long()
+        .map(str::to_owned)
+        .unwrap_or_else(|| arg.get_name().to_lowercase())
+}
 
-    let parser: Box<dyn LogLineParser + Send + Sync> = if opts.common_format {
-        Box::new(CommonLogLineParser::new())
-    } else if opts.combined_format {
-        Box::new(CombinedLogLineParser::new())
+/// Kind of values that a command line option accepts.
+fn option_kind(arg: &Arg) -> OptionKind {
+    if is_positional(arg) {
+        OptionKind::Positional
+    } else if !arg.is_set(ArgSettings::TakesValue) {
+        OptionKind::Flag
+    } else if arg.is_set(ArgSettings::RequireEquals) {
+        OptionKind::OptionalValue
+    } else if arg.is_set(ArgSettings::MultipleOccurrences) || arg.is_set(ArgSettings::MultipleValues) {
+        OptionKind::Values
     } else {
-        eprintln!("redeye: error: Log input format must be specified");
-        process::exit(1);
+        OptionKind::Value
+    }
+}
+
+/// Find the option for a setting and convert it to command line arguments, checking
+/// that its value is valid for the option.
+fn setting_args<'a, 'b>(app: &'a App<'b>, setting: &Setting) -> RedeyeResult<(&'a Arg<'b>, Vec<String>)> {
+    let arg = app
+        .get_arguments()
+        .filter(|a| is_configurable(a))
+        .find(|a| option_name(a) == setting.name())
+        .ok_or_else(|| setting.error("unknown setting"))?;
+
+    let args = setting.args(option_kind(arg))?;
+    let check = iter::once(app.get_name().to_owned()).chain(args.iter().cloned());
+    match app.clone().try_get_matches_from(check) {
+        Err(e) if e.kind != ErrorKind::MissingRequiredArgument => Err(setting.error(&clap_error_message(&e))),
+        _ => Ok((arg, args)),
+    }
+}
+
+/// True if an option is overridden by options that were given elsewhere: the
+/// same option, options that conflict with it, or another log format.
+fn is_overridden(app: &App, arg: &Arg, given: &dyn Fn(&Arg) -> bool) -> bool {
+    given(arg)
+        || app.get_arg_conflicts_with(arg).into_iter().any(given)
+        || app.get_arguments().filter(|a| given(a)).any(|a| {
+            app.get_arg_conflicts_with(a)
+                .iter()
+                .any(|c| c.get_name() == arg.get_name())
+        })
+        || (FORMAT_OPTIONS.contains(&arg.get_name())
+            && app
+                .get_arguments()
+                .any(|a| FORMAT_OPTIONS.contains(&a.get_name()) && given(a)))
+}
+
+/// Add arguments for settings to the command line arguments. Each group of
+/// settings takes precedence over those after it and the command line takes
+/// precedence over all of them, so settings are skipped when they are overridden
+/// by options given on the command line or in an earlier group.
+fn merge_settings(
+    subcommand: Option<&'static str>,
+    args: Vec<OsString>,
+    groups: &[&Config],
+) -> RedeyeResult<MergedArgs> {
+    let app = options_app(subcommand);
+    let (head, rest) = split_subcommand(subcommand, &args);
+
+    let cli = app
+        .clone()
+        .try_get_matches_from(iter::once(OsString::from(app.get_name())).chain(rest.iter().cloned()))
+        .map_err(|e| RedeyeError::ConfigError(clap_error_message(&e)))?;
+
+    let mut used: Vec<(String, Setting, Vec<String>)> = Vec::new();
+    for config in groups {
+        let mut group = Vec::new();
+        for setting in config.settings() {
+            let (arg, args) = setting_args(&app, setting)?;
+            let given =
+                |a: &Arg| cli.occurrences_of(a.get_name()) > 0 || used.iter().any(|(name, _, _)| name == a.get_name());
+
+            if !is_overridden(&app, arg, &given) {
+                group.push((arg.get_name().to_owned(), setting.clone(), args));
+            }
+        }
+
+        used.extend(group);
+    }
+
+    let mut merged: Vec<OsString> = head.to_vec();
+    for (_, _, args) in used.iter() {
+        merged.extend(args.iter().map(OsString::from));
+    }
+
+    merged.extend(rest.iter().cloned());
+    Ok(MergedArgs {
+        subcommand,
+        args: merged,
+        used: used.into_iter().map(|(name, setting, _)| (name, setting)).collect(),
+    })
+}
+
+/// Command line arguments with settings from the environment or a configuration
+/// file added, and the settings that were used for each option.
+#[derive(Debug)]
+struct MergedArgs {
+    subcommand: Option<&'static str>,
+    args: Vec<OsString>,
+    used: Vec<(String, Setting)>,
+}
+
+impl MergedArgs {
+    fn new(subcommand: Option<&'static str>, args: Vec<OsString>) -> Self {
+        MergedArgs {
+            subcommand,
+            args,
+            used: Vec::new(),
+        }
+    }
+
+    /// Every option with its value and where the value came from, as TOML.
+    fn describe(&self) -> RedeyeResult<String> {
+        let app = options_app(self.subcommand);
+        let (_, rest) = split_subcommand(self.subcommand, &self.args);
+        let matches = app
+            .clone()
+            .try_get_matches_from(iter::once(OsString::from(app.get_name())).chain(rest.iter().cloned()))
+            .map_err(|e| RedeyeError::ConfigError(clap_error_message(&e)))?;
+
+        Ok(effective_config(&app, &matches, &self.used))
+    }
+}
+
+/// First line of an error from clap, without its prefix.
+fn clap_error_message(e: &clap::Error) -> String {
+    let msg = e.to_string();
+    let first = msg.lines().next().unwrap_or("");
+    first.strip_prefix("error: ").unwrap_or(first).to_owned()
+}
+
+/// Load a configuration file and check that the settings in it are valid, then
+/// print every option with the value that results from it.
+fn check_config(path: &Path) -> RedeyeResult<()> {
+    let config = Config::load(path)?;
+    let merged = merge_settings(None, vec![OsString::from("redeye")], &[&config])?;
+    let opts = RedeyeOptions::try_parse_from(merged.args.clone())
+        .map_err(|e| RedeyeError::ConfigError(clap_error_message(&e)))?;
+
+    SelectedFormat::from_opts(&opts, &ParserRegistry::new())?;
+    new_enrichers(&opts)?;
+    new_sampler(&opts)?;
+    Destination::from_options(&opts, false)?;
+
+    print!("{}", merged.describe()?);
+    Ok(())
+}
+
+/// Every option that can be set in a configuration file with its value from the
+/// parsed arguments, as TOML, followed by a comment saying where the value came
+/// from. Options without a value are commented out.
+fn effective_config(app: &App, matches: &ArgMatches, used: &[(String, Setting)]) -> String {
+    let quote = |v: &str| match v.parse::<i64>() {
+        Ok(n) => n.to_string(),
+        Err(_) => serde_json::to_string(v).unwrap_or_default(),
     };
+    let mut out = String::new();
+
+    // Hidden options are aliases of others so they're only included when used.
+    let visible = |a: &Arg| !a.is_set(ArgSettings::Hidden) || matches.is_present(a.get_name());
+    for arg in app.get_arguments().filter(|a| is_configurable(a) && visible(a)) {
+        let name = option_name(arg);
+        let values: Vec<&str> = matches
+            .values_of(arg.get_name())
+            .map(|v| v.collect())
+            .unwrap_or_default();
+        let line = match (option_kind(arg), values.first()) {
+            (OptionKind::Flag, _) => format!("{} = {}", name, matches.is_present(arg.get_name())),
+            (OptionKind::Value, Some(v)) => format!("{} = {}", name, quote(v)),
+            (OptionKind::Value, None) => format!("# {} is not set", name),
+            (OptionKind::OptionalValue, Some(v)) => format!("{} = {}", name, quote(v)),
+            (OptionKind::OptionalValue, None) => format!("{} = {}", name, matches.is_present(arg.get_name())),
+            (OptionKind::Values, _) | (OptionKind::Positional, _) => format!(
+                "{} = [{}]",
+                name,
+                values.iter().map(|v| quote(v)).collect::<Vec<String>>().join(", ")
+            ),
+        };
+
+        let source = match used.iter().find(|(n, _)| n == arg.get_name()).map(|(_, s)| s) {
+            Some(setting) => match setting.source() {
+                Source::File(line) => format!("{} on line {}", setting.key(), line),
+                Source::Env => setting.key().to_owned(),
+            },
+            None if matches.occurrences_of(arg.get_name()) > 0 => "command line".to_owned(),
+            None if line.starts_with('#') => {
+                out.push_str(&line);
+                out.push('\n');
+                continue;
+            }
+            None => "default".to_owned(),
+        };
+
+        out.push_str(&format!("{}  # {}\n", line, source));
+    }
+
+    out
+}
+
+/// Options for the stats subcommand.
+#[derive(Clap, Debug)]
+struct StatsOptions {
+    #[clap(flatten)]
+    opts: RedeyeOptions,
+
+    /// also break down content length and duration by the value of this field,
+    /// as a dotted path (e.g. requested_uri or request_headers.user_agent).
+    #[clap(long, value_name = "FIELD")]
+    group_by: Option<String>,
+
+    /// print the report as a JSON object instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+/// Options for the top subcommand.
+#[derive(Clap, Debug)]
+struct TopOptions {
+    #[clap(flatten)]
+    opts: RedeyeOptions,
+
+    /// field to group events by, as a dotted path (e.g. requested_uri or
+    /// request_headers.user_agent).
+    #[clap(long)]
+    by: String,
+
+    /// rank values of the field by number of events ("count") or by the sum
+    /// of their content length ("bytes").
+    #[clap(long, default_value = "count")]
+    metric: Metric,
+
+    /// only include events that match this expression, the same as --filter.
+    #[clap(long = "where", number_of_values = 1)]
+    where_filter: Vec<Filter>,
+
+    /// number of values to print.
+    #[clap(short = 'n', long, default_value = "20")]
+    limit: usize,
+
+    /// print the report as a JSON object instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+/// Options for the bench subcommand.
+#[derive(Clap, Debug)]
+struct BenchOptions {
+    #[clap(flatten)]
+    opts: RedeyeOptions,
+
+    /// number of lines generated and processed by each run.
+    #[clap(long, default_value = "100000", value_name = "N")]
+    lines: usize,
+
+    /// print the results as a JSON object instead of a table.
+    #[clap(long)]
+    json: bool,
+}
+
+impl BenchOptions {
+    /// True if none of the options that select the log format were given, in
+    /// which case generated lines are parsed as the Combined format.
+    fn without_format(&self) -> bool {
+        let opts = &self.opts;
+        opts.format.is_none()
+            && !opts.common_format
+            && !opts.combined_format
+            && opts.format_apache.is_none()
+            && opts.format_nginx.is_none()
+            && opts.format_file.is_none()
+    }
+}
+
+/// Aggregation of events printed as a report instead of writing the events.
+enum Report {
+    Requests(Box<RequestSummary>),
+    Top { values: TopValues, limit: usize },
+}
+
+impl Collect for Report {
+    fn add(&mut self, event: &LogEvent) {
+        match self {
+            Report::Requests(summary) => summary.add(event),
+            Report::Top { values, .. } => values.add(event),
+        }
+    }
+}
+
+/// Log format selected by command line options.
+enum SelectedFormat {
+    /// Name of a format in the parser registry.
+    Named(String),
+    Custom(LogFormat),
+    /// Each `--input` has its own format, these are their names.
+    PerInput(Vec<String>),
+}
+
+impl SelectedFormat {
+    fn from_opts(opts: &RedeyeOptions, registry: &ParserRegistry) -> RedeyeResult<Self> {
+        // The format options are in a group so clap rejects more than one of them.
+        // The legacy flags are the same as --format with their format.
+        let named = if opts.common_format {
+            Some(NamedFormat::Common.name())
+        } else if opts.combined_format {
+            Some(NamedFormat::Combined.name())
+        } else {
+            opts.format.as_deref()
+        };
+
+        // Conflicts with the group of format options aren't checked by clap
+        let global = named.is_some()
+            || opts.format_apache.is_some()
+            || opts.format_nginx.is_some()
+            || opts.format_file.is_some();
+        if global && !opts.input.is_empty() {
+            return Err(RedeyeError::ConfigError(
+                "--input can't be used with --format, --format-apache, --format-nginx, or --format-file".to_owned(),
+            ));
+        }
+
+        let format = if let Some(name) = named {
+            if !registry.contains(name) {
+                return Err(RedeyeError::ConfigError(format!(
+                    "unknown format '{}', expected one of {} (see --list-formats)",
+                    name,
+                    registry.names().join(", ")
+                )));
+            }
+
+            SelectedFormat::Named(name.to_owned())
+        } else if let Some(ref spec) = opts.format_apache {
+            // varnishncsa formats are Apache formats with extensions, so accept both
+            SelectedFormat::Custom(LogFormat::varnish(spec)?)
+        } else if let Some(ref spec) = opts.format_nginx {
+            SelectedFormat::Custom(LogFormat::nginx(spec)?)
+        } else if let Some(ref path) = opts.format_file {
+            let contents = fs::read_to_string(path)?;
+            let spec = contents.trim_end_matches(&['\r', '\n'][..]);
+
+            if spec.contains('%') {
+                SelectedFormat::Custom(LogFormat::varnish(spec)?)
+            } else {
+                SelectedFormat::Custom(LogFormat::nginx(spec)?)
+            }
+        } else if !opts.input.is_empty() {
+            let mut names: Vec<String> = Vec::new();
+
+            for input in opts.input.iter() {
+                if !registry.contains(input.format()) {
+                    return Err(RedeyeError::ConfigError(format!(
+                        "unknown format '{}' for input '{}', expected one of {}",
+                        input.format(),
+                        input,
+                        registry.names().join(", ")
+                    )));
+                }
+
+                if !names.iter().any(|n| n == input.format()) {
+                    names.push(input.format().to_owned());
+                }
+            }
+
+            SelectedFormat::PerInput(names)
+        } else {
+            return Err(RedeyeError::ConfigError(
+                "Log input format must be specified with --format, --format-apache, --format-nginx, or --format-file"
+                    .to_owned(),
+            ));
+        };
+
+        Ok(format)
+    }
+
+    /// Names of the registered formats that were selected, if any.
+    fn names(&self) -> Vec<&str> {
+        match self {
+            SelectedFormat::Named(name) => vec![name.as_str()],
+            SelectedFormat::Custom(_) => Vec::new(),
+            SelectedFormat::PerInput(names) => names.iter().map(String::as_str).collect(),
+        }
+    }
+
+    fn parser(
+        self,
+        registry: &ParserRegistry,
+        context: TimestampContext,
+    ) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+        match self {
+            SelectedFormat::Named(name) => registry.create_with_context(&name, context),
+            SelectedFormat::Custom(format) => Ok(Box::new(
+                CustomLogLineParser::from_format(format).timestamp_context(context),
+            )),
+            // Lines from every input are parsed by the parser for that input. This
+            // one is only used for lines that don't come from one, e.g. benchmarks.
+            SelectedFormat::PerInput(names) => registry.create_with_context(&names[0], context),
+        }
+    }
+
+    /// Fields emitted by the parser of each selected format, or an error if a
+    /// format doesn't know its fields ahead of time.
+    fn schema(&self, registry: &ParserRegistry) -> RedeyeResult<Vec<(String, &'static str)>> {
+        if let SelectedFormat::Custom(format) = self {
+            return Ok(format.schema());
+        }
+
+        let mut schema: Vec<(String, &'static str)> = Vec::new();
+        for name in self.names() {
+            let fields = registry.create(name)?.schema().ok_or_else(|| {
+                RedeyeError::ConfigError(format!(
+                    "the fields of the {} format depend on each line so it has no schema",
+                    name
+                ))
+            })?;
+
+            for (name, kind) in fields {
+                if !schema.iter().any(|(n, _)| *n == name) {
+                    schema.push((name, kind));
+                }
+            }
+        }
+
+        Ok(schema)
+    }
+}
+
+fn new_enrichers(opts: &RedeyeOptions) -> RedeyeResult<Vec<Box<dyn Enricher + Send + Sync>>> {
+    let mut enrichers: Vec<Box<dyn Enricher + Send + Sync>> = Vec::new();
+    let extract_campaign = opts.extract_campaign || opts.strip_campaign_params;
+
+    if opts.parse_query || extract_campaign {
+        enrichers.push(Box::new(QueryStringEnricher::new()));
+    }
+
+    if extract_campaign {
+        enrichers.push(Box::new(
+            CampaignEnricher::new().strip_params(opts.strip_campaign_params),
+        ));
+    }
+
+    if opts.normalize_path {
+        enrichers.push(Box::new(PathNormalizerEnricher::new()));
+    }
+
+    if let Some(ref field) = opts.throughput_duration_field {
+        enrichers.push(Box::new(ThroughputEnricher::new(field, opts.throughput_duration_unit)));
+    }
+
+    if opts.severity || opts.severity_map.is_some() {
+        let overrides = match opts.severity_map {
+            Some(ref spec) => SeverityEnricher::parse_overrides(spec)?,
+            None => HashMap::new(),
+        };
+
+        enrichers.push(Box::new(SeverityEnricher::with_overrides(overrides)));
+    }
+
+    if opts.http_version {
+        enrichers.push(Box::new(HttpVersionEnricher::new()));
+    }
+
+    if let Some(ref path) = opts.geoip_db {
+        enrichers.push(Box::new(GeoIpEnricher::new(Box::new(MaxMindCityDatabase::open(path)?))));
+    }
+
+    if let Some(ref path) = opts.asn_db {
+        enrichers.push(Box::new(AsnEnricher::new(Box::new(MaxMindAsnDatabase::open(path)?))));
+    }
+
+    if opts.detect_bots || opts.bot_patterns.is_some() {
+        let extra = match opts.bot_patterns {
+            Some(ref path) => BotPattern::load_all(path)?,
+            None => Vec::new(),
+        };
+
+        enrichers.push(Box::new(
+            BotDetectionEnricher::with_patterns(&extra)?
+                .missing_user_agent_is_bot(!opts.bot_allow_missing_ua)
+                .check_paths(opts.bot_check_paths),
+        ));
+    }
+
+    Ok(enrichers)
+}
+
+/// Parser for the lines of a single input, along with the name of the file used as
+/// its source and the name of its format.
+type SourceParser = (String, String, Box<dyn LogLineParser + Send + Sync>);
+
+/// Files matching each `--input` and a parser for the lines of each of them in
+/// the format of its input.
+fn new_source_parsers(
+    opts: &RedeyeOptions,
+    registry: &ParserRegistry,
+    context: TimestampContext,
+) -> RedeyeResult<(Vec<PathBuf>, Vec<SourceParser>)> {
+    let mut files = Vec::new();
+    let mut parsers: Vec<SourceParser> = Vec::new();
+
+    for input in opts.input.iter() {
+        for path in input.files()? {
+            let source = path.to_string_lossy().into_owned();
+            if parsers.iter().any(|(s, _, _)| *s == source) {
+                return Err(RedeyeError::ConfigError(format!(
+                    "file '{}' matches more than one input",
+                    source
+                )));
+            }
+
+            let parser = registry.create_with_context(input.format(), context)?;
+            let parser: Box<dyn LogLineParser + Send + Sync> = if opts.strip_syslog {
+                Box::new(SyslogLogLineParser::new(parser))
+            } else {
+                parser
+            };
+
+            let format = input.format().to_owned();
+            parsers.push((source, format, parser));
+            files.push(path);
+        }
+    }
+
+    Ok((files, parsers))
+}
+
+fn new_sampler(opts: &RedeyeOptions) -> RedeyeResult<Option<Sampler>> {
+    match (opts.sample_rate, opts.sample_every) {
+        (Some(_), Some(_)) => Err(RedeyeError::ConfigError(
+            "Only one of --sample-rate or --sample-every may be used".to_owned(),
+        )),
+        (None, _) if opts.sample_key.is_some() || opts.sample_seed.is_some() => Err(RedeyeError::ConfigError(
+            "--sample-key and --sample-seed require --sample-rate".to_owned(),
+        )),
+        (None, Some(n)) => Sampler::every(n).map(Some),
+        (Some(rate), None) => match opts.sample_key {
+            // Key based sampling defaults to a fixed seed so the same values are
+            // kept between runs, random sampling defaults to a different sample.
+            Some(ref key) => Sampler::key(key.as_str(), rate, opts.sample_seed.unwrap_or(0)).map(Some),
+            None => Sampler::rate(rate, opts.sample_seed.unwrap_or_else(random_seed)).map(Some),
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+/// Deduplicate consecutive events by the comma separated fields, or by every field
+/// if there aren't any.
+fn new_dedup(fields: Option<&str>, interval: Duration) -> Dedup<String> {
+    let fields = fields
+        .map(|f| {
+            f.split(',')
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect()
+        })
+        .filter(|f: &Vec<String>| !f.is_empty());
+    Dedup::new(fields).flush_interval(interval)
+}
+
+/// Seed for random sampling based on the current time.
+fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Set the flag when SIGHUP is received.
+#[cfg(unix)]
+fn register_reopen(flag: &Arc<AtomicBool>) -> io::Result<()> {
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, flag.clone()).map(|_| ())
+}
+
+/// There is no SIGHUP on this platform so the flag is never set.
+#[cfg(not(unix))]
+fn register_reopen(_flag: &Arc<AtomicBool>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Create a forwarder for events to a remote destination, configured based on
+/// the options.
+fn new_forwarder<C: Connector>(connector: C, opts: &RedeyeOptions, stats: &Arc<Stats>) -> RedeyeResult<Forwarder<C>> {
+    let buffer = LogBuffer::new()
+        .max_records(opts.reconnect_buffer_events)
+        .max_bytes(opts.reconnect_buffer_bytes);
+
+    let spill = match opts.spill_dir {
+        Some(ref dir) => Some(
+            SpillQueue::open(dir)
+                .map_err(|e| {
+                    RedeyeError::ConfigError(format!("Unable to use spill directory {}: {}", dir.display(), e))
+                })?
+                .max_bytes(opts.spill_max_bytes)
+                .segment_bytes(opts.spill_segment_bytes),
+        ),
+        None => None,
+    };
+
+    Ok(Forwarder::new(connector)
+        .buffer(buffer)
+        .delimiter(opts.output_delimiter.byte())
+        .dead_letter(opts.dead_letter.as_deref())
+        .spill(spill)
+        .stats(stats.clone()))
+}
+
+/// Send events to a Unix socket, which doesn't need to exist yet, e.g. when
+/// started before the agent that listens on it.
+#[cfg(unix)]
+fn new_unix_output(path: &Path, opts: &RedeyeOptions, stats: &Arc<Stats>) -> RedeyeResult<Box<dyn Output + Send>> {
+    let sender = new_forwarder(UnixConnector::new(path), opts, stats)?
+        .wait_for_connection(true)
+        .start()
+        .map_err(|e| connect_error(path.display(), e))?;
+
+    Ok(Box::new(sender))
+}
+
+#[cfg(not(unix))]
+fn new_unix_output(_path: &Path, _opts: &RedeyeOptions, _stats: &Arc<Stats>) -> RedeyeResult<Box<dyn Output + Send>> {
+    Err(RedeyeError::ConfigError(
+        "--output-unix is not supported on this platform".to_owned(),
+    ))
+}
+
+/// Send events to a Kafka topic, flushing them when finished.
+#[cfg(feature = "kafka")]
+fn new_kafka_output(
+    config: &KafkaConfig,
+    opts: &RedeyeOptions,
+    stats: &Arc<Stats>,
+) -> RedeyeResult<Box<dyn Output + Send>> {
+    let producer = RdKafkaProducer::new(config)?;
+    Ok(Box::new(
+        KafkaSink::new(producer)
+            .config(config)
+            .delimiter(opts.output_delimiter.byte())
+            .stats(stats.clone()),
+    ))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn new_kafka_output(
+    _config: &KafkaConfig,
+    _opts: &RedeyeOptions,
+    _stats: &Arc<Stats>,
+) -> RedeyeResult<Box<dyn Output + Send>> {
+    Err(RedeyeError::ConfigError(
+        "--output-kafka requires Redeye to be built with the kafka feature".to_owned(),
+    ))
+}
+
+/// Add events to a Redis list or stream.
+#[cfg(feature = "redis")]
+fn new_redis_output(
+    url: &str,
+    target: RedisTarget,
+    opts: &RedeyeOptions,
+    stats: &Arc<Stats>,
+) -> RedeyeResult<Box<dyn Output + Send>> {
+    let sender = new_forwarder(RedisConnector::new(url, target)?, opts, stats)?
+        .start()
+        .map_err(|e| connect_error(url, e))?;
+
+    Ok(Box::new(sender))
+}
+
+#[cfg(not(feature = "redis"))]
+fn new_redis_output(
+    _url: &str,
+    _target: RedisTarget,
+    _opts: &RedeyeOptions,
+    _stats: &Arc<Stats>,
+) -> RedeyeResult<Box<dyn Output + Send>> {
+    Err(RedeyeError::ConfigError(
+        "--output-redis requires Redeye to be built with the redis feature".to_owned(),
+    ))
+}
+
+/// Post batches of events to an HTTP endpoint.
+#[cfg(feature = "http")]
+fn new_http_output(
+    target: HttpTarget,
+    opts: &RedeyeOptions,
+    stats: &Arc<Stats>,
+) -> RedeyeResult<Box<dyn Output + Send>> {
+    let url = target.url().to_owned();
+    let sender = new_forwarder(HttpConnector::new(target), opts, stats)?
+        .max_batch_records(opts.http_batch_events)
+        .max_batch_bytes(opts.http_batch_bytes)
+        .linger(opts.http_batch_wait)
+        .retries(Some(opts.http_retries))
+        .start()
+        .map_err(|e| connect_error(url, e))?;
+
+    Ok(Box::new(sender))
+}
+
+#[cfg(not(feature = "http"))]
+fn new_http_output(
+    _target: HttpTarget,
+    _opts: &RedeyeOptions,
+    _stats: &Arc<Stats>,
+) -> RedeyeResult<Box<dyn Output + Send>> {
+    Err(RedeyeError::ConfigError(
+        "--output-http requires Redeye to be built with the http feature".to_owned(),
+    ))
+}
+
+/// Build the endpoint for --output-http from the options.
+fn new_http_target(url: &str, opts: &RedeyeOptions) -> RedeyeResult<HttpTarget> {
+    let format = opts.output_format.unwrap_or(OutputFormat::Json);
+    let mut target = HttpTarget::new(url).format(format).gzip(opts.http_gzip);
+    if let Some(ref credentials) = opts.http_user {
+        let (user, password) = credentials.split_at(
+            credentials
+                .find(':')
+                .ok_or_else(|| RedeyeError::ConfigError("--http-user must be given as USER:PASSWORD".to_owned()))?,
+        );
+        target = target.basic_auth(user, &password[1..]);
+    }
+
+    for (name, value) in &opts.http_header {
+        target = target.header(name.as_str(), value.as_str());
+    }
+
+    Ok(target)
+}
+
+fn connect_error<D: fmt::Display>(dest: D, e: io::Error) -> RedeyeError {
+    RedeyeError::IoError(io::Error::new(
+        e.kind(),
+        format!("Unable to connect to {}: {}", dest, e),
+    ))
+}
+
+/// Where events are written, based on the options.
+#[derive(Debug)]
+enum Destination<'a> {
+    Stdout,
+    Discard,
+    File(&'a Path),
+    Tcp(&'a str),
+    Unix(&'a Path),
+    Kafka(&'a KafkaConfig),
+    Redis(&'a str),
+    Http(&'a str),
+}
+
+impl<'a> Destination<'a> {
+    /// Pick the destination for events from the options. When only a report is
+    /// printed, or lines are only being checked, events are discarded.
+    fn from_options(opts: &'a RedeyeOptions, report: bool) -> RedeyeResult<Self> {
+        let rotating = opts.rotate_size.is_some()
+            || opts.rotate_interval.is_some()
+            || opts.rotate_keep.is_some()
+            || opts.rotate_name.is_some();
+        let remote = if let Some(ref addr) = opts.output_tcp {
+            Some(Destination::Tcp(addr))
+        } else if let Some(ref path) = opts.output_unix {
+            Some(Destination::Unix(path))
+        } else if let Some(ref config) = opts.output_kafka {
+            Some(Destination::Kafka(config))
+        } else if let Some(ref url) = opts.output_redis {
+            Some(Destination::Redis(url))
+        } else {
+            opts.output_http.as_deref().map(Destination::Http)
+        };
+
+        if opts.output_format == Some(OutputFormat::EsBulk) && !matches!(remote, Some(Destination::Http(_))) {
+            return Err(RedeyeError::ConfigError(
+                "--output-format es-bulk requires --output-http".to_owned(),
+            ));
+        }
+
+        if opts.output_format == Some(OutputFormat::PrettyLog) {
+            if remote.is_some() {
+                return Err(RedeyeError::ConfigError(
+                    "--output-format pretty-log can't be used with --output-tcp, --output-unix, --output-kafka, \
+                     --output-redis, or --output-http"
+                        .to_owned(),
+                ));
+            }
+
+            if opts.select.is_some() {
+                return Err(RedeyeError::ConfigError(
+                    "--select can't be used with --output-format pretty-log".to_owned(),
+                ));
+            }
+        }
+
+        match (remote, &opts.output) {
+            (Some(_), _) if report || opts.check => Err(RedeyeError::ConfigError(
+                "--output-tcp, --output-unix, --output-kafka, --output-redis, and --output-http can't be used \
+                 with --check or the stats or top subcommands"
+                    .to_owned(),
+            )),
+            (Some(d), _) => Ok(d),
+            (None, Some(_)) if report => Err(RedeyeError::ConfigError(
+                "--output can't be used with the stats or top subcommands".to_owned(),
+            )),
+            (None, Some(p)) => Ok(Destination::File(p)),
+            (None, None) if rotating => Err(RedeyeError::ConfigError(
+                "--rotate-size, --rotate-interval, --rotate-keep, and --rotate-name require --output".to_owned(),
+            )),
+            (None, None) if opts.check || report => Ok(Destination::Discard),
+            (None, None) => Ok(Destination::Stdout),
+        }
+    }
+}
+
+/// True if events are written to stdout and it's a terminal, in which case they
+/// should be visible as soon as they're written.
+fn writes_to_terminal(opts: &RedeyeOptions, report: bool) -> bool {
+    matches!(Destination::from_options(opts, report), Ok(Destination::Stdout)) && io::stdout().is_terminal()
+}
+
+/// Format of events from the options. If it isn't set, events written to a terminal
+/// are pretty and all others are JSON.
+fn output_format(opts: &RedeyeOptions, report: bool) -> OutputFormat {
+    match opts.output_format {
+        Some(format) => format,
+        None if writes_to_terminal(opts, report) && opts.select.is_none() => OutputFormat::PrettyLog,
+        None => OutputFormat::Json,
+    }
+}
+
+/// Create the renderer for events if they're written as pretty lines, colorized
+/// and fit to the width of the terminal if they're written to one.
+fn new_renderer(opts: &RedeyeOptions, report: bool) -> Option<PrettyRenderer> {
+    if output_format(opts, report) != OutputFormat::PrettyLog {
+        return None;
+    }
+
+    let terminal = writes_to_terminal(opts, report);
+    let no_color = env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false);
+    Some(
+        PrettyRenderer::new()
+            .color(opts.color.enabled(terminal, no_color))
+            .verbose(opts.verbose)
+            .width(if terminal { terminal_width() } else { None }),
+    )
+}
+
+/// Width of the terminal that stdout is written to, if it can be determined.
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    // SAFETY: The file descriptor is valid for as long as stdout is borrowed and
+    // TIOCGWINSZ only writes a winsize to the pointer given.
+    let res = unsafe { libc::ioctl(io::stdout().as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+    if res == 0 && size.ws_col > 0 {
+        Some(usize::from(size.ws_col))
+    } else {
+        columns_width()
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_width() -> Option<usize> {
+    columns_width()
+}
+
+/// Width of the terminal from the COLUMNS environment variable, if it's set.
+fn columns_width() -> Option<usize> {
+    env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(|&c| c > 0)
+}
+
+/// Create the destination for events based on the options.
+fn new_output(opts: &RedeyeOptions, report: bool, stats: &Arc<Stats>) -> RedeyeResult<Box<dyn Output + Send>> {
+    match Destination::from_options(opts, report)? {
+        Destination::Stdout => Ok(Box::new(stdout())),
+        Destination::Discard => Ok(Box::new(io::sink())),
+        Destination::File(path) => new_file_output(path, opts),
+        Destination::Tcp(addr) => {
+            let sender = new_forwarder(TcpConnector::new(addr), opts, stats)?
+                .start()
+                .map_err(|e| connect_error(addr, e))?;
+            Ok(Box::new(sender))
+        }
+        Destination::Unix(path) => new_unix_output(path, opts, stats),
+        Destination::Kafka(config) => new_kafka_output(config, opts, stats),
+        Destination::Redis(url) => {
+            let target = RedisTarget::new(opts.redis_key.as_str())
+                .mode(opts.redis_mode)
+                .stream_field(opts.redis_stream_field.as_deref());
+            new_redis_output(url, target, opts, stats)
+        }
+        Destination::Http(url) => new_http_output(new_http_target(url, opts)?, opts, stats),
+    }
+}
+
+/// Write events to a file, rotating it based on the options.
+fn new_file_output(path: &Path, opts: &RedeyeOptions) -> RedeyeResult<Box<dyn Output + Send>> {
+    let mut policy = RotationPolicy::new();
+    if let Some(size) = opts.rotate_size {
+        policy = policy.max_size(size);
+    }
+
+    if let Some(interval) = opts.rotate_interval {
+        policy = policy.interval(interval);
+    }
+
+    if let Some(keep) = opts.rotate_keep {
+        policy = policy.keep(keep);
+    }
+
+    let mut writer = RotatingFileWriter::open(path, policy)?.delimiter(opts.output_delimiter);
+    if let Some(ref name) = opts.rotate_name {
+        writer = writer.rotated_name(name.clone());
+    }
+
+    Ok(Box::new(writer))
+}
+
+/// Print a warning for each selected field that isn't in the schema of the parser.
+fn warn_unknown_columns(selection: &Selection, schema: &[(String, &'static str)]) {
+    for c in selection.columns() {
+        let prefix = format!("{}.", c.path());
+        let known = schema
+            .iter()
+            .any(|(name, _)| name == c.path() || name.starts_with(&prefix));
+
+        if !known {
+            eprintln!(
+                "redeye: warning: Selected field '{}' is never emitted by the chosen log format",
+                c.path()
+            );
+        }
+    }
+}
+
+fn main() {
+    // Parse errors are reported by clap with an exit code of 2 by default, which
+    // we use for too many invalid lines. Error::exit uses 1 (or 0 for --help).
+    let args: Vec<OsString> = env::args_os().collect();
+    let command = RedeyeCommand::try_parse_args(args.clone()).unwrap_or_else(|e| e.exit());
+    let (command, merged) = command.with_settings(args, env::vars_os()).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(if e.is_io_error() { EXIT_IO_ERROR } else { EXIT_USAGE });
+    });
+
+    let (opts, report, json, bench) = match command {
+        RedeyeCommand::CheckConfig(check) => {
+            if let Err(e) = check_config(&check.path) {
+                eprintln!("redeye: error: {}", e);
+                process::exit(if e.is_io_error() { EXIT_IO_ERROR } else { EXIT_USAGE });
+            }
+
+            return;
+        }
+        RedeyeCommand::Parse(opts) => (opts, None, false, None),
+        RedeyeCommand::Stats(stats) => {
+            let summary = match stats.group_by {
+                Some(path) => RequestSummary::new().group_by(path),
+                None => RequestSummary::new(),
+            };
+
+            (stats.opts, Some(Report::Requests(Box::new(summary))), stats.json, None)
+        }
+        RedeyeCommand::Top(top) => {
+            let mut opts = top.opts;
+            opts.filter.extend(top.where_filter);
+
+            let values = TopValues::new(top.by, top.metric);
+            let report = Report::Top {
+                values,
+                limit: top.limit,
+            };
+            (opts, Some(report), top.json, None)
+        }
+        RedeyeCommand::Bench(bench) => {
+            let without_format = bench.without_format();
+            let mut opts = bench.opts;
+            if without_format {
+                opts.format = Some(NamedFormat::Combined.name().to_owned());
+            }
+
+            (opts, None, bench.json, Some(bench.lines))
+        }
+    };
+
+    if opts.print_config {
+        match merged.describe() {
+            Ok(config) => print!("{}", config),
+            Err(e) => {
+                eprintln!("redeye: error: {}", e);
+                process::exit(EXIT_USAGE);
+            }
+        }
+
+        return;
+    }
+
+    let registry = ParserRegistry::new();
+    if opts.list_formats {
+        for name in registry.names() {
+            println!("{}", name);
+        }
+
+        return;
+    }
+
+    let format = SelectedFormat::from_opts(&opts, &registry).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(EXIT_USAGE);
+    });
+
+    if opts.print_schema {
+        let schema = format.schema(&registry).unwrap_or_else(|e| {
+            eprintln!("redeye: error: {}", e);
+            process::exit(EXIT_USAGE);
+        });
+
+        for (name, kind) in schema {
+            println!("{}\t{}", name, kind);
+        }
+
+        return;
+    }
+
+    let enrichers = new_enrichers(&opts).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(EXIT_USAGE);
+    });
+
+    // Fields that aren't emitted by the parser can only be added by enrichers so
+    // when there aren't any, selecting them is most likely a typo.
+    if let Some(ref selection) = opts.select {
+        if enrichers.is_empty() && !opts.stamp_source && opts.watch.is_none() && opts.input.is_empty() {
+            // Formats that don't know their fields ahead of time can emit anything
+            if let Ok(schema) = format.schema(&registry) {
+                warn_unknown_columns(selection, &schema);
+            }
+        }
+    }
+
+    let sampler = new_sampler(&opts).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(EXIT_USAGE);
+    });
+
+    if opts.check && report.is_some() {
+        eprintln!("redeye: error: --check can't be used with the stats or top subcommands");
+        process::exit(EXIT_USAGE);
+    }
+
+    if opts.flush_interval == Some(Duration::from_secs(0)) {
+        eprintln!("redeye: error: --flush-interval must be greater than zero");
+        process::exit(EXIT_USAGE);
+    }
+
+    if !(0.0..=1.0).contains(&opts.check_threshold) {
+        eprintln!(
+            "redeye: error: Invalid check threshold {}, must be between 0 and 1",
+            opts.check_threshold
+        );
+        process::exit(EXIT_USAGE);
+    }
+
+    let timestamps = match opts.assume_timezone {
+        Some(zone) => TimestampContext::new().assume_zone(zone),
+        None => TimestampContext::new(),
+    };
+
+    // Lines are generated in the named format when that's what's being parsed and
+    // the Combined format otherwise, which custom formats are usually based on.
+    let generated = match format {
+        SelectedFormat::Named(ref name) => name.parse().unwrap_or(NamedFormat::Combined),
+        _ => NamedFormat::Combined,
+    };
+
+    if bench.is_some() {
+        if let Some(name) = format.names().into_iter().find(|n| NOT_ACCESS_LOG_FORMATS.contains(n)) {
+            eprintln!(
+                "redeye: error: bench only generates access logs and can't be used with the {} format",
+                name
+            );
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    let parser = format.parser(&registry, timestamps).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(EXIT_USAGE);
+    });
+
+    let (input_files, source_parsers) = new_source_parsers(&opts, &registry, timestamps).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(EXIT_USAGE);
+    });
+
+    let parser = if opts.strip_syslog {
+        Box::new(SyslogLogLineParser::new(parser))
+    } else {
+        parser
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // SIGHUP is only handled when writing to a file since otherwise there's nothing
+    // to reopen and it should keep its default behavior of stopping the process.
+    let reopen = Arc::new(AtomicBool::new(false));
+    if opts.output.is_some() {
+        if let Err(e) = register_reopen(&reopen) {
+            eprintln!("redeye: error: Unable to install signal handler: {}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    // Events aren't written to stdout when it's used for a report or benchmark results
+    let reporting = report.is_some() || bench.is_some();
+    let line_buffered = opts.line_buffered || writes_to_terminal(&opts, reporting);
+    let stats = Arc::new(Stats::new());
+    let failures = if opts.check || report.is_some() {
+        Some(Arc::new(Mutex::new(Failures::new())))
+    } else {
+        None
+    };
+    let report = report.map(|r| Arc::new(Mutex::new(r)));
+
+    let mut config = PipelineConfig::new(Arc::from(parser))
+        .stamp_source(opts.stamp_source || opts.watch.is_some() || !opts.input.is_empty())
+        .max_errors(match (opts.strict, opts.max_errors) {
+            (true, _) => Some(0),
+            (false, 0) => None,
+            (false, n) => Some(n),
+        })
+        .warnings(Arc::new(
+            WarningSink::stderr()
+                .quiet(opts.quiet)
+                .per_second(opts.warnings_per_second)
+                .format(opts.warnings_format)
+                .stats(stats.clone()),
+        ))
+        .workers(opts.workers)
+        .input_delimiter(opts.input_delimiter)
+        .max_record_bytes(opts.max_record_bytes)
+        .utf8(opts.invalid_utf8)
+        .output_delimiter(opts.output_delimiter)
+        .line_buffered(line_buffered)
+        .reopen_when(reopen.clone())
+        .stop_when(stop.clone())
+        .stats(stats.clone());
+
+    for (source, format, parser) in source_parsers {
+        config = config.source_parser(source, format, Arc::from(parser));
+    }
+    for enricher in enrichers {
+        config = config.enricher(Arc::from(enricher));
+    }
+    for filter in opts.filter.iter() {
+        config = config.filter(filter.clone());
+    }
+    if let Some(ref selection) = opts.select {
+        config = config.select(selection.clone());
+    }
+    if let Some(sampler) = sampler {
+        config = config.sampler(sampler);
+    }
+    if let Some(ref fields) = opts.dedup_consecutive {
+        config = config.dedup(new_dedup(fields.as_deref(), opts.dedup_interval));
+    }
+    if let Some(pretty) = new_renderer(&opts, reporting) {
+        config = config.pretty(pretty);
+    }
+    if let Some(ref failures) = failures {
+        config = config.failures(failures.clone());
+    }
+    if let Some(ref report) = report {
+        config = config.collect(report.clone());
+    }
+
+    if let Some(lines) = bench {
+        match run_bench(&config, &opts, generated, lines) {
+            Ok(results) if json => println!("{}", serde_json::to_string(&results).unwrap()),
+            Ok(results) => print!("{}", results),
+            Err(code) => process::exit(code),
+        }
+
+        return;
+    }
+
+    if opts.listen_udp.is_some() && (opts.follow || opts.watch.is_some() || opts.journal || !opts.files.is_empty()) {
+        eprintln!("redeye: error: FILE, --follow, --watch, and --journal can't be used with --listen-udp");
+        process::exit(EXIT_USAGE);
+    }
+
+    if opts.state_file.is_some() && opts.watch.is_none() && !opts.journal {
+        eprintln!("redeye: error: --state-file can only be used with --watch or --journal");
+        process::exit(EXIT_USAGE);
+    }
+
+    #[cfg(not(feature = "journal"))]
+    if opts.journal {
+        eprintln!("redeye: error: --journal requires Redeye to be built with the journal feature");
+        process::exit(EXIT_USAGE);
+    }
+
+    #[cfg(feature = "journal")]
+    let mut journal = if opts.journal {
+        Some(new_journal(&opts).unwrap_or_else(|e| {
+            eprintln!("redeye: error: {}", e);
+            process::exit(if e.is_io_error() { EXIT_IO_ERROR } else { EXIT_USAGE });
+        }))
+    } else {
+        None
+    };
+
+    let mut watcher = opts.watch.as_ref().map(|pattern| {
+        new_watcher(&opts, pattern, &stop).unwrap_or_else(|e| {
+            eprintln!("redeye: error: {}", e);
+            process::exit(if e.is_io_error() { EXIT_IO_ERROR } else { EXIT_USAGE });
+        })
+    });
+
+    let mut files = if opts.listen_udp.is_some() || watcher.is_some() || opts.journal {
+        Vec::new()
+    } else if !opts.input.is_empty() {
+        input_files
+    } else if opts.files.is_empty() {
+        vec![PathBuf::from("-")]
+    } else {
+        opts.files.clone()
+    };
+
+    if opts.follow && files.len() > 1 {
+        eprintln!("redeye: error: Only a single FILE can be read with --follow");
+        process::exit(EXIT_USAGE);
+    }
+
+    // When interrupted, we stop reading input, finish handling and writing lines
+    // that have already been read, and then exit normally. If we're interrupted
+    // again while doing that, exit immediately.
+    for sig in &[signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        let res = signal_hook::flag::register_conditional_shutdown(*sig, EXIT_INTERRUPTED, stop.clone())
+            .and_then(|_| signal_hook::flag::register(*sig, stop.clone()));
+
+        if let Err(e) = res {
+            eprintln!("redeye: error: Unable to install signal handler: {}", e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    let output = new_output(&opts, report.is_some(), &stats).unwrap_or_else(|e| {
+        eprintln!("redeye: error: {}", e);
+        process::exit(if e.is_io_error() { EXIT_IO_ERROR } else { EXIT_USAGE });
+    });
+
+    let writer = BufWriter::with_capacity(opts.output_buffer, output);
+    let mut writer: Box<dyn Output + Send> = match opts.flush_interval {
+        Some(interval) => Box::new(PeriodicFlusher::start(writer, interval).unwrap_or_else(|e| {
+            eprintln!("redeye: error: Unable to start flushing output: {}", e);
+            process::exit(EXIT_IO_ERROR);
+        })),
+        None => Box::new(writer),
+    };
+    let mut exit_code = 0;
+
+    if let Some(ref addr) = opts.listen_udp {
+        let source = format!("udp://{}", addr);
+        let res = new_receiver(&opts, addr)
+            .map_err(|e| PipelineError::Input(e.into()))
+            .and_then(|mut r| pipeline::process_datagrams(&config.clone().source(&source), &mut r, &mut writer));
+
+        if let Err(e) = res {
+            exit_code = report_error(e, &source, &stats).0;
+        }
+    }
+
+    if let (Some(ref mut watcher), Some(ref pattern)) = (&mut watcher, &opts.watch) {
+        if let Err(e) = pipeline::process_watched(&config, watcher, &mut writer) {
+            exit_code = report_error(e, pattern.as_str(), &stats).0;
+        }
+    }
+
+    #[cfg(feature = "journal")]
+    if let Some(ref mut journal) = journal {
+        if let Err(e) = pipeline::process_journal(&config.clone().source(JOURNAL_SOURCE), journal, &mut writer) {
+            exit_code = report_error(e, JOURNAL_SOURCE, &stats).0;
+        }
+    }
+
+    // All files are read at once when merging, leaving none to read one at a time
+    if opts.merge_by_time {
+        let mut inputs: Vec<(String, Box<dyn BufRead + Send>)> = Vec::with_capacity(files.len());
+        for path in mem::take(&mut files) {
+            let source = path.to_string_lossy().into_owned();
+            if source == "-" {
+                inputs.push((source, Box::new(BufReader::with_capacity(opts.input_buffer, stdin()))));
+                continue;
+            }
+
+            match open_file(&path, !opts.no_auto_decompress) {
+                Ok(f) => inputs.push((source, Box::new(BufReader::with_capacity(opts.input_buffer, f)))),
+                Err(e) => exit_code = report_error(PipelineError::Input(e.into()), &source, &stats).0,
+            }
+        }
+
+        let res = pipeline::process_merged(&config, inputs, opts.merge_window, &mut writer, |e, source| {
+            exit_code = report_error(e, source, &stats).0;
+        });
+
+        if let Err(e) = res {
+            exit_code = report_error(e, "input", &stats).0;
+        }
+    }
+
+    for path in files.iter() {
+        if stop.load(Ordering::Acquire) {
+            break;
+        }
+
+        let source = path.to_string_lossy();
+        let config = config.clone().source(source.as_ref());
+
+        let res = if source == "-" {
+            let reader = BufReader::with_capacity(opts.input_buffer, stdin());
+            pipeline::process(&config, reader, &mut writer)
+        } else if opts.follow {
+            FileFollower::open(path, opts.follow_from, stop.clone())
+                .map_err(|e| PipelineError::Input(e.into()))
+                .and_then(|f| {
+                    let reader = BufReader::with_capacity(opts.input_buffer, f);
+                    pipeline::process(&config, reader, &mut writer)
+                })
+        } else {
+            open_file(path, !opts.no_auto_decompress)
+                .map_err(|e| PipelineError::Input(e.into()))
+                .and_then(|f| {
+                    let reader = BufReader::with_capacity(opts.input_buffer, f);
+                    pipeline::process(&config, reader, &mut writer)
+                })
+        };
+
+        if let Err(e) = res {
+            let (code, stop_reading) = report_error(e, &source, &stats);
+            exit_code = code;
+            if stop_reading {
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = pipeline::finish(&config, &mut writer) {
+        let code = report_error(e, "input", &stats).0;
+        if exit_code == 0 {
+            exit_code = code;
+        }
+    }
+
+    if exit_code == 0 && stop.load(Ordering::Acquire) {
+        exit_code = EXIT_INTERRUPTED;
+    }
+
+    let failures = failures.as_ref().map(|f| f.lock().unwrap());
+    if let (Some(report), Some(failures)) = (&report, &failures) {
+        match *report.lock().unwrap() {
+            Report::Requests(ref summary) => print_stats_report(&stats.snapshot(), summary, failures, json),
+            Report::Top { ref values, limit } => print_top_report(&stats.snapshot(), values, limit, failures, json),
+        }
+    } else if let Some(ref failures) = failures {
+        let snapshot = stats.snapshot();
+        let rate = failure_rate(&snapshot, failures);
+
+        print_check_report(&snapshot, failures, &opts, rate);
+        if exit_code == 0 && rate > opts.check_threshold {
+            exit_code = EXIT_TOO_MANY_ERRORS;
+        }
+    } else if !opts.no_summary {
+        print_summary(&stats, opts.summary_json);
+    }
+
+    process::exit(exit_code);
+}
+
+/// Print why processing an input stopped early, returning the exit code to use and
+/// whether the rest of the inputs should be skipped.
+fn report_error(err: PipelineError, source: &str, stats: &Stats) -> (i32, bool) {
+    match err {
+        PipelineError::Input(e) => {
+            eprintln!("redeye: error: Unable to read {}: {}", source, e);
+            (EXIT_IO_ERROR, false)
+        }
+        PipelineError::Output(e) => {
+            eprintln!("redeye: error: Unable to write output: {}", e);
+            (EXIT_IO_ERROR, true)
+        }
+        PipelineError::TooManyErrors => {
+            let snapshot = stats.snapshot();
+            eprintln!(
+                "redeye: error: Stopping after {} invalid lines of {} read",
+                snapshot.errors(),
+                snapshot.lines_read
+            );
+            (EXIT_TOO_MANY_ERRORS, true)
+        }
+    }
+}
+
+/// Process generated lines in the given format with a single worker and then
+/// with `workers` of them (one per CPU if that's just one), discarding the events.
+/// Returns the exit code if processing stops because of an error, after printing it.
+fn run_bench(
+    config: &PipelineConfig,
+    opts: &RedeyeOptions,
+    format: NamedFormat,
+    lines: usize,
+) -> Result<BenchReport, i32> {
+    let input = bench_input(format, lines, opts.input_delimiter.byte());
+    let workers = match opts.workers {
+        0 | 1 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n,
+    };
+
+    let mut runs = Vec::new();
+    for workers in iter::once(1).chain(Some(workers).filter(|n| *n > 1)) {
+        let stats = Arc::new(Stats::new());
+        let config = config.clone().source(BENCH_SOURCE).stats(stats.clone());
+        let run = bench_run(
+            &config,
+            &input,
+            workers,
+            (opts.input_delimiter, opts.max_record_bytes),
+            &stats,
+        )
+        .map_err(|e| report_error(e, BENCH_SOURCE, &stats).0)?;
+        runs.push(run);
+    }
+
+    Ok(BenchReport::new(format.name(), runs))
+}
+
+/// Generated lines in the given format, each followed by the delimiter.
+fn bench_input(format: NamedFormat, lines: usize, delimiter: u8) -> Vec<u8> {
+    let start = DateTime::parse_from_rfc3339("2020-10-10T13:55:36-07:00").unwrap();
+    let mut generator = AccessLogGenerator::new(format, BENCH_SEED);
+    let mut input = Vec::new();
+
+    for i in 0..lines {
+        let elapsed = Duration::from_millis(i as u64);
+        let timestamp = start + chrono::Duration::milliseconds(i as i64);
+        input.extend_from_slice(generator.line(timestamp, elapsed).as_bytes());
+        input.push(delimiter);
+    }
+
+    input
+}
+
+/// Process the input with a number of workers the same way as a file, timing
+/// how long it takes to handle each line. Lines are split on the delimiter and
+/// limited to the maximum length given, which should match the config.
+fn bench_run(
+    config: &PipelineConfig,
+    input: &[u8],
+    workers: usize,
+    (delimiter, max_record_bytes): (Delimiter, Option<usize>),
+    stats: &Stats,
+) -> Result<BenchRun, PipelineError> {
+    let mut run = BenchRun::new(workers);
+    let mut out = io::sink();
+    let mut summary = PipelineSummary::default();
+    let before = allocations();
+    let started = Instant::now();
+
+    let handle = |record: Record| {
+        let started = Instant::now();
+        let value = pipeline::handle(config, record);
+        (value, started.elapsed())
+    };
+
+    let mut emit = |processed: Processed<(Handled, Duration)>| {
+        let (value, latency) = processed.value;
+        run.line(latency);
+        let processed = Processed {
+            line: processed.line,
+            bytes: processed.bytes,
+            value,
+        };
+        pipeline::emit(config, processed, &mut out, &mut summary)
+    };
+
+    if workers > 1 {
+        WorkerPool::new(workers)
+            .delimiter(delimiter)
+            .max_record_bytes(max_record_bytes)
+            .run(Cursor::new(input), handle, &mut emit)?;
+    } else {
+        let mut reader = Cursor::new(input);
+        let mut buf = Vec::new();
+        let mut line = 0;
+
+        while let Some(read) = read_record(&mut reader, delimiter.byte(), max_record_bytes, &mut buf)? {
+            line += 1;
+            let value = handle(Record {
+                data: &buf,
+                too_long: read.too_long,
+            });
+            emit(Processed {
+                line,
+                bytes: read.bytes,
+                value,
+            })?;
+            buf.clear();
+        }
+    }
+
+    pipeline::finish(config, &mut out)?;
+    let elapsed = started.elapsed();
+    let snapshot = stats.snapshot();
+    run.finish(
+        snapshot.lines_read,
+        snapshot.errors(),
+        snapshot.bytes_in,
+        snapshot.bytes_out,
+        elapsed,
+    );
+
+    if let (Some(before), Some(after)) = (before, allocations()) {
+        run.allocations(after - before);
+    }
+
+    Ok(run)
+}
+
+/// Create a watcher for files matching the pattern, continuing from the state file if given.
+fn new_watcher(opts: &RedeyeOptions, pattern: &Pattern, stop: &Arc<AtomicBool>) -> RedeyeResult<FileWatcher> {
+    let watcher = FileWatcher::new(pattern.clone(), opts.follow_from, stop.clone())
+        .rescan_interval(opts.watch_interval)
+        .max_record_bytes(opts.max_record_bytes);
+    match opts.state_file {
+        Some(ref path) => watcher.state_file(path),
+        None => Ok(watcher),
+    }
+}
+
+/// Start following the journal, continuing from the state file if given.
+#[cfg(feature = "journal")]
+fn new_journal(opts: &RedeyeOptions) -> RedeyeResult<JournalFollower> {
+    let journal = JournalOptions::new(opts.follow_from).units(opts.journal_unit.clone());
+    JournalFollower::spawn(journal, opts.state_file.clone())
+}
+
+/// Bind to the address to receive datagrams, using the size options for them.
+fn new_receiver(opts: &RedeyeOptions, addr: &str) -> io::Result<DatagramReceiver> {
+    let receiver = DatagramReceiver::bind(addr)?.max_size(opts.udp_max_bytes);
+    if let Some(bytes) = opts.udp_receive_buffer {
+        set_receive_buffer(receiver.socket(), bytes)?;
+    }
+
+    Ok(receiver)
+}
+
+/// Set the size of the receive buffer (SO_RCVBUF) of a socket.
+#[cfg(unix)]
+fn set_receive_buffer(socket: &UdpSocket, bytes: usize) -> io::Result<()> {
+    use std::convert::TryFrom;
+    use std::os::unix::io::AsRawFd;
+
+    let size = libc::c_int::try_from(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "receive buffer size too large"))?;
+
+    // SAFETY: The file descriptor is valid for as long as the socket is borrowed and
+    // the option value points to a c_int of the given length, as SO_RCVBUF expects.
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn set_receive_buffer(_socket: &UdpSocket, _bytes: usize) -> io::Result<()> {
+    Err(io::Error::other(
+        "--udp-receive-buffer is not supported on this platform",
+    ))
+}
+
+/// Print counts of lines and events processed to stderr.
+fn print_summary(stats: &Stats, json: bool) {
+    let snapshot = stats.snapshot();
+
+    if json {
+        match serde_json::to_string(&snapshot) {
+            Ok(s) => eprintln!("{}", s),
+            Err(e) => eprintln!("redeye: error: Unable to serialize summary: {}", e),
+        }
+    } else {
+        eprintln!("redeye: summary:\n{}", snapshot);
+    }
+}
+
+/// Fraction of lines read that failed, zero if no lines were read.
+fn failure_rate(snapshot: &StatsSnapshot, failures: &Failures) -> f64 {
+    if snapshot.lines_read == 0 {
+        0.0
+    } else {
+        failures.total() as f64 / snapshot.lines_read as f64
+    }
+}
+
+/// Print the result of --check to stdout: the number of lines that could and
+/// couldn't be parsed and the most common reasons they couldn't.
+fn print_check_report(snapshot: &StatsSnapshot, failures: &Failures, opts: &RedeyeOptions, rate: f64) {
+    let passed = rate <= opts.check_threshold;
+    let parsed = snapshot.lines_read - failures.total();
+
+    if opts.summary_json {
+        let by_reason: serde_json::Map<String, serde_json::Value> = failures
+            .by_reason()
+            .into_iter()
+            .map(|(reason, count)| (reason.to_string(), count.into()))
+            .collect();
+
+        let report = serde_json::json!({
+            "lines_read": snapshot.lines_read,
+            "lines_parsed": parsed,
+            "lines_failed": failures.total(),
+            "failure_rate": rate,
+            "threshold": opts.check_threshold,
+            "passed": passed,
+            "failures_by_reason": by_reason,
+            "top_failures": failures.top(opts.check_top),
+            "untracked_failures": failures.untracked(),
+        });
+
+        println!("{}", report);
+        return;
+    }
+
+    let percent = |n: u64| {
+        if snapshot.lines_read == 0 {
+            0.0
+        } else {
+            n as f64 * 100.0 / snapshot.lines_read as f64
+        }
+    };
+
+    println!("lines read:       {}", snapshot.lines_read);
+    println!("lines parsed:     {} ({:.2}%)", parsed, percent(parsed));
+    println!(
+        "lines failed:     {} ({:.2}%)",
+        failures.total(),
+        percent(failures.total())
+    );
+    for (reason, count) in failures.by_reason() {
+        println!("  {}: {}", reason, count);
+    }
+
+    print_top_failures(failures, opts.check_top);
+    println!(
+        "result:           {} ({:.2}% failed, threshold {:.2}%)",
+        if passed { "passed" } else { "failed" },
+        rate * 100.0,
+        opts.check_threshold * 100.0
+    );
+}
+
+/// Print the most common distinct failures with their example lines to stdout.
+fn print_top_failures(failures: &Failures, k: usize) {
+    let top = failures.top(k);
+    if !top.is_empty() {
+        println!("most common failures:");
+        for failure in top {
+            println!("  {} x {}: {}", failure.count, failure.reason, failure.message);
+            for example in failure.examples.iter() {
+                println!("    {}:{}: {}", example.source, example.line, example.text);
+            }
+        }
+    }
+
+    if failures.untracked() > 0 {
+        println!("  ({} failures with other messages not shown)", failures.untracked());
+    }
+}
+
+/// Print the report of the stats subcommand to stdout: the number of lines read
+/// and that couldn't be parsed, the summary of requests, and the most common
+/// failures.
+fn print_stats_report(snapshot: &StatsSnapshot, summary: &RequestSummary, failures: &Failures, json: bool) {
+    if json {
+        let mut report = match serde_json::to_value(summary) {
+            Ok(serde_json::Value::Object(m)) => m,
+            Ok(_) => serde_json::Map::new(),
+            Err(e) => {
+                eprintln!("redeye: error: Unable to serialize report: {}", e);
+                return;
+            }
+        };
+
+        report.insert("lines_read".to_owned(), snapshot.lines_read.into());
+        report.insert("lines_failed".to_owned(), failures.total().into());
+        report.insert(
+            "top_failures".to_owned(),
+            serde_json::json!(failures.top(STATS_TOP_FAILURES)),
+        );
+        println!("{}", serde_json::Value::Object(report));
+        return;
+    }
+
+    println!("lines read:       {}", snapshot.lines_read);
+    println!("lines failed:     {}", failures.total());
+    println!("{}", summary);
+    print_top_failures(failures, STATS_TOP_FAILURES);
+}
+
+/// Print the report of the top subcommand to stdout: the values of the field with
+/// the largest totals and the most common failures.
+fn print_top_report(snapshot: &StatsSnapshot, values: &TopValues, limit: usize, failures: &Failures, json: bool) {
+    let top = values.top(limit);
+    let percent = |total: u64| {
+        if values.sum() == 0 {
+            0.0
+        } else {
+            total as f64 * 100.0 / values.sum() as f64
+        }
+    };
+
+    if json {
+        let ranked: Vec<serde_json::Value> = top
+            .iter()
+            .map(|r| {
+                serde_json::json!({
+                    "value": r.value,
+                    "total": r.total,
+                    "percent": percent(r.total),
+                    "error": r.error,
+                })
+            })
+            .collect();
+
+        let report = serde_json::json!({
+            "by": values.path(),
+            "metric": values.metric().to_string(),
+            "total": values.sum(),
+            "approximate": values.is_approximate(),
+            "top": ranked,
+            "lines_read": snapshot.lines_read,
+            "lines_failed": failures.total(),
+            "top_failures": failures.top(STATS_TOP_FAILURES),
+        });
+
+        println!("{}", report);
+        return;
+    }
+
+    // Values are printed last so that long ones (such as user agents) don't
+    // break the alignment of the other columns.
+    let metric = values.metric().to_string();
+    let width = top
+        .iter()
+        .map(|r| r.total.to_string().len())
+        .chain(std::iter::once(metric.len()))
+        .max()
+        .unwrap_or(0);
+
+    if values.is_approximate() {
+        let error_width = top
+            .iter()
+            .map(|r| r.error.to_string().len())
+            .chain(std::iter::once(5))
+            .max()
+            .unwrap_or(0);
+        println!(
+            "{:>w$}  {:>ew$}  {:>7}  {}",
+            metric,
+            "error",
+            "percent",
+            values.path(),
+            w = width,
+            ew = error_width
+        );
+        for r in top.iter() {
+            println!(
+                "{:>w$}  {:>ew$}  {:>6.2}%  {}",
+                r.total,
+                r.error,
+                percent(r.total),
+                r.value,
+                w = width,
+                ew = error_width
+            );
+        }
+
+        println!(
+            "note: too many distinct values to count exactly, totals may be overestimated by up to the error shown"
+        );
+    } else {
+        println!("{:>w$}  {:>7}  {}", metric, "percent", values.path(), w = width);
+        for r in top.iter() {
+            println!("{:>w$}  {:>6.2}%  {}", r.total, percent(r.total), r.value, w = width);
+        }
+    }
+
+    if failures.total() > 0 {
+        println!("lines read:       {}", snapshot.lines_read);
+        println!("lines failed:     {}", failures.total());
+        print_top_failures(failures, STATS_TOP_FAILURES);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bench_input, bench_run, effective_config, merge_settings, new_renderer, Destination, PipelineConfig,
+        PipelineError, RedeyeCommand, RedeyeOptions, SelectedFormat, WarningSink,
+    };
+    use clap::{ErrorKind, IntoApp};
+    use redeye::aggregate::Metric;
+    use redeye::config::{Config, OptionKind};
+    use redeye::enrich::HttpVersionEnricher;
+    use redeye::format::LogFormat;
+    use redeye::io::Delimiter;
+    use redeye::parser::{CommonLogLineParser, LogLineParser, NamedFormat, ParserRegistry};
+    use redeye::stats::Stats;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_command_parse_args() {
+        let args = |a: &[&str]| a.iter().map(OsString::from).collect::<Vec<OsString>>();
+
+        match RedeyeCommand::try_parse_args(args(&["redeye", "--common-format", "stats"])).unwrap() {
+            RedeyeCommand::Parse(opts) => assert_eq!(vec![PathBuf::from("stats")], opts.files),
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        match RedeyeCommand::try_parse_args(args(&["redeye", "parse", "--common-format", "a.log"])).unwrap() {
+            RedeyeCommand::Parse(opts) => assert_eq!(vec![PathBuf::from("a.log")], opts.files),
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        match RedeyeCommand::try_parse_args(args(&["redeye", "stats", "--json", "--common-format"])).unwrap() {
+            RedeyeCommand::Stats(stats) => {
+                assert!(stats.json);
+                assert!(stats.opts.common_format);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        let top = args(&[
+            "redeye",
+            "top",
+            "--common-format",
+            "--by",
+            "remote_host",
+            "-n",
+            "5",
+            "--metric",
+            "bytes",
+        ]);
+        match RedeyeCommand::try_parse_args(top).unwrap() {
+            RedeyeCommand::Top(top) => {
+                assert_eq!("remote_host", top.by);
+                assert_eq!(Metric::Bytes, top.metric);
+                assert_eq!(5, top.limit);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        match RedeyeCommand::try_parse_args(args(&["redeye", "bench", "--lines", "500", "--workers", "4"])).unwrap() {
+            RedeyeCommand::Bench(bench) => {
+                assert_eq!(500, bench.lines);
+                assert_eq!(4, bench.opts.workers);
+                assert!(bench.without_format());
+                assert!(!bench.json);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        match RedeyeCommand::try_parse_args(args(&["redeye", "bench", "--common-format", "--json"])).unwrap() {
+            RedeyeCommand::Bench(bench) => {
+                assert_eq!(100_000, bench.lines);
+                assert!(!bench.without_format());
+                assert!(bench.json);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        assert!(RedeyeCommand::try_parse_args(args(&["redeye", "top", "--common-format"])).is_err());
+        assert!(RedeyeCommand::try_parse_args(args(&["redeye", "--json"])).is_err());
+    }
+
+    #[test]
+    fn test_bench_run() {
+        let input = bench_input(NamedFormat::Common, 500, b'\n');
+        assert_eq!(500, input.iter().filter(|b| **b == b'\n').count());
+
+        for workers in &[1, 3] {
+            let stats = Arc::new(Stats::new());
+            let config = PipelineConfig::new(Arc::new(CommonLogLineParser::new()))
+                .enricher(Arc::new(HttpVersionEnricher::new()))
+                .stats(stats.clone());
+
+            let run = bench_run(&config, &input, *workers, (Delimiter::Newline, None), &stats).unwrap();
+            assert_eq!(*workers, run.workers());
+            assert_eq!(0, run.errors());
+            assert_eq!(500, run.latency().count());
+            assert!(run.lines_per_second() > 0.0);
+
+            let snapshot = stats.snapshot();
+            assert_eq!(500, snapshot.lines_read);
+            assert_eq!(500, snapshot.events_emitted);
+            assert_eq!(input.len() as u64, snapshot.bytes_in);
+        }
+    }
+
+    #[test]
+    fn test_bench_run_wrong_format() {
+        let input = bench_input(NamedFormat::Combined, 100, b'\n');
+        let stats = Arc::new(Stats::new());
+        let config = PipelineConfig::new(Arc::new(CommonLogLineParser::new()))
+            .max_errors(Some(10))
+            .warnings(Arc::new(WarningSink::stderr().quiet(true)))
+            .stats(stats.clone());
+
+        match bench_run(&config, &input, 1, (Delimiter::Newline, None), &stats) {
+            Err(PipelineError::TooManyErrors) => (),
+            v => panic!("Unexpected result: {:?}", v.map(|r| r.workers())),
+        }
+    }
+
+    #[test]
+    fn test_destination_from_options() {
+        let opts = |a: &[&str]| {
+            let args = a.iter().map(OsString::from).collect::<Vec<OsString>>();
+            match RedeyeCommand::try_parse_args(args).unwrap() {
+                RedeyeCommand::Parse(opts) => opts,
+                c => panic!("unexpected command {:?}", c),
+            }
+        };
+
+        let stdout = opts(&["redeye", "--common-format"]);
+        assert!(matches!(
+            Destination::from_options(&stdout, false),
+            Ok(Destination::Stdout)
+        ));
+        assert!(matches!(
+            Destination::from_options(&stdout, true),
+            Ok(Destination::Discard)
+        ));
+
+        let redis = opts(&["redeye", "--common-format", "--output-redis", "redis://localhost/"]);
+        assert!(matches!(
+            Destination::from_options(&redis, false),
+            Ok(Destination::Redis("redis://localhost/"))
+        ));
+        assert!(Destination::from_options(&redis, true).is_err());
+
+        let http = opts(&["redeye", "--common-format", "--output-http", "http://localhost/"]);
+        assert!(matches!(
+            Destination::from_options(&http, false),
+            Ok(Destination::Http("http://localhost/"))
+        ));
+
+        let bulk = opts(&["redeye", "--common-format", "--output-format", "es-bulk"]);
+        assert!(Destination::from_options(&bulk, false).is_err());
+
+        let pretty = opts(&["redeye", "--common-format", "--output-format", "pretty-log"]);
+        assert!(matches!(
+            Destination::from_options(&pretty, false),
+            Ok(Destination::Stdout)
+        ));
+        let pretty = opts(&[
+            "redeye",
+            "--common-format",
+            "--output-format",
+            "pretty-log",
+            "--output-tcp",
+            "localhost:5000",
+        ]);
+        assert!(Destination::from_options(&pretty, false).is_err());
+        let pretty = opts(&[
+            "redeye",
+            "--common-format",
+            "--output-format",
+            "pretty-log",
+            "--select",
+            "method",
+        ]);
+        assert!(Destination::from_options(&pretty, false).is_err());
+
+        let rotating = opts(&["redeye", "--common-format", "--rotate-keep", "3"]);
+        assert!(Destination::from_options(&rotating, false).is_err());
+
+        let named = opts(&["redeye", "--common-format", "--rotate-name", "{name}.{seq}"]);
+        assert!(Destination::from_options(&named, false).is_err());
+    }
+
+    #[test]
+    fn test_new_renderer() {
+        let opts = |a: &[&str]| {
+            let args = a.iter().map(OsString::from).collect::<Vec<OsString>>();
+            match RedeyeCommand::try_parse_args(args).unwrap() {
+                RedeyeCommand::Parse(opts) => opts,
+                c => panic!("unexpected command {:?}", c),
+            }
+        };
+
+        // Events written to a file are only pretty when asked for.
+        assert!(new_renderer(&opts(&["redeye", "--common-format", "--output", "out.log"]), false).is_none());
+
+        let pretty = opts(&[
+            "redeye",
+            "--common-format",
+            "--output",
+            "out.log",
+            "--output-format",
+            "pretty-log",
+            "--color",
+            "always",
+            "-v",
+        ]);
+        let renderer = new_renderer(&pretty, false).unwrap();
+        let event = CommonLogLineParser::new()
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326")
+            .unwrap();
+        let line = renderer.render(&event);
+        assert!(line.starts_with("\x1b[2m2000-10-10T13:55:36-07:00\x1b[0m "), "{}", line);
+        assert!(line.contains(" protocol=HTTP/1.1 "), "{}", line);
+
+        let never = opts(&[
+            "redeye",
+            "--common-format",
+            "--output-format",
+            "pretty-log",
+            "--color",
+            "never",
+        ]);
+        assert!(!new_renderer(&never, false).unwrap().render(&event).contains('\x1b'));
+    }
+
+    #[test]
+    fn test_selected_format_from_options() {
+        let args = |a: &[&str]| a.iter().map(OsString::from).collect::<Vec<OsString>>();
+        let format = |a: &[&str]| match RedeyeCommand::try_parse_args(args(a)).unwrap() {
+            RedeyeCommand::Parse(opts) => SelectedFormat::from_opts(&opts, &ParserRegistry::new()),
+            c => panic!("unexpected command {:?}", c),
+        };
+        let named = |a: &[&str]| match format(a) {
+            Ok(SelectedFormat::Named(name)) => name,
+            Ok(_) => panic!("expected a named format for {:?}", a),
+            Err(e) => panic!("unexpected error for {:?}: {}", a, e),
+        };
+
+        assert_eq!("combined", named(&["redeye", "--format", "combined"]));
+        assert_eq!("common", named(&["redeye", "--common-format"]));
+        assert_eq!("combined", named(&["redeye", "--combined-format"]));
+        assert_eq!("vhost_combined", named(&["redeye", "--format", "vhost_combined"]));
+        assert!(matches!(
+            format(&["redeye", "--format-nginx", "$remote_addr"]),
+            Ok(SelectedFormat::Custom(_))
+        ));
+        assert_eq!("nginx_timed", named(&["redeye", "--format", "nginx_timed"]));
+        assert_eq!("nginx_upstream", named(&["redeye", "--format", "nginx_upstream"]));
+        assert_eq!("ingress_nginx", named(&["redeye", "--format", "ingress_nginx"]));
+        assert_eq!(
+            "combined_duration_ms",
+            named(&["redeye", "--format", "combined_duration_ms"])
+        );
+        assert!(format(&["redeye", "--format", "combined_duration_minutes"]).is_err());
+        assert_eq!("error", named(&["redeye", "--format", "error"]));
+        assert_eq!("referer", named(&["redeye", "--format", "referer"]));
+        assert_eq!("agent", named(&["redeye", "--format", "agent"]));
+        assert!(format(&["redeye", "--format", "apache"]).is_err());
+        assert!(format(&["redeye"]).is_err());
+
+        // Only one format option may be used, including in subcommands
+        for conflict in &[
+            &["redeye", "--common-format", "--combined-format"][..],
+            &["redeye", "--format", "common", "--common-format"][..],
+            &["redeye", "--format", "common", "--format-apache", "%h"][..],
+            &[
+                "redeye",
+                "--format-nginx",
+                "$remote_addr",
+                "--format-file",
+                "format.txt",
+            ][..],
+            &["redeye", "stats", "--combined-format", "--format", "combined"][..],
+        ] {
+            let err = RedeyeCommand::try_parse_args(args(conflict)).unwrap_err();
+            assert_eq!(ErrorKind::ArgumentConflict, err.kind, "{:?}", conflict);
+        }
+    }
+
+    #[test]
+    fn test_selected_format_per_input() {
+        let args = |a: &[&str]| a.iter().map(OsString::from).collect::<Vec<OsString>>();
+        let format = |a: &[&str]| match RedeyeCommand::try_parse_args(args(a)).unwrap() {
+            RedeyeCommand::Parse(opts) => SelectedFormat::from_opts(&opts, &ParserRegistry::new()),
+            c => panic!("unexpected command {:?}", c),
+        };
+
+        match format(&[
+            "redeye",
+            "--input",
+            "path=a.log,format=combined",
+            "--input",
+            "path=b.log,format=common",
+            "--input",
+            "path=c.log,format=combined",
+        ]) {
+            Ok(SelectedFormat::PerInput(names)) => assert_eq!(vec!["combined", "common"], names),
+            _ => panic!("expected a format per input"),
+        }
+
+        assert!(format(&["redeye", "--input", "path=a.log,format=haproxy-http"]).is_err());
+        assert!(format(&["redeye", "--format", "common", "--input", "path=a.log,format=common"]).is_err());
+
+        let err = RedeyeCommand::try_parse_args(args(&["redeye", "--input", "path=a.log,format=common", "b.log"]))
+            .unwrap_err();
+        assert_eq!(ErrorKind::ArgumentConflict, err.kind);
+    }
+
+    #[test]
+    fn test_selected_format_schema() {
+        let registry = ParserRegistry::new();
+        let fields = |format: SelectedFormat| -> Vec<String> {
+            format
+                .schema(&registry)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+
+        let timed = fields(SelectedFormat::Named("nginx_timed".to_owned()));
+        assert_eq!(
+            vec!["request_headers.user_agent", "request_time", "@version", "message"],
+            timed[timed.len() - 4..].to_vec()
+        );
+
+        let custom = fields(SelectedFormat::Custom(LogFormat::apache("%h %>s").unwrap()));
+        assert_eq!(vec!["remote_host", "status_code", "@version", "message"], custom);
+
+        let inputs = fields(SelectedFormat::PerInput(vec!["common".to_owned(), "agent".to_owned()]));
+        assert!(inputs.contains(&"status_code".to_owned()));
+        assert!(inputs.contains(&"request_headers.user_agent".to_owned()));
+        assert_eq!(1, inputs.iter().filter(|n| *n == "message").count());
+    }
+
+    fn merged(config: &str, subcommand: Option<&'static str>, a: &[&str]) -> Result<Vec<String>, String> {
+        let config: Config = config.parse().unwrap();
+        let args = a.iter().map(OsString::from).collect();
+        merge_settings(subcommand, args, &[&config])
+            .map(|m| m.args.into_iter().map(|a| a.into_string().unwrap()).collect())
+            .map_err(|e| e.to_string())
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_settings() {
+        let config = concat!(
+            "common-format = true\n",
+            "filter = [\"status_code >= 500\"]\n",
+            "\n",
+            "[output]\n",
+            "buffer = 2048\n",
+        );
+
+        assert_eq!(
+            vec![
+                "redeye",
+                "--common-format",
+                "--filter=status_code >= 500",
+                "--output-buffer=2048",
+                "--no-summary",
+                "access.log",
+            ],
+            merged(config, None, &["redeye", "--no-summary", "access.log"]).unwrap()
+        );
+
+        assert_eq!(
+            vec![
+                "redeye",
+                "stats",
+                "--common-format",
+                "--filter=status_code >= 500",
+                "--output-buffer=2048",
+                "--json",
+            ],
+            merged(config, Some("stats"), &["redeye", "stats", "--json"]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_settings_precedence() {
+        let config = concat!(
+            "common-format = true\n",
+            "output-buffer = 2048\n",
+            "output-tcp = \"localhost:9000\"\n",
+            "filter = [\"status_code >= 500\"]\n",
+        );
+
+        // Options given on the command line replace settings for the same option,
+        // settings for options that conflict with them, and the log format.
+        assert_eq!(
+            vec![
+                "redeye",
+                "--filter=status_code >= 500",
+                "--output-buffer",
+                "4096",
+                "--output",
+                "out.json",
+                "--combined-format",
+            ],
+            merged(
+                config,
+                None,
+                &[
+                    "redeye",
+                    "--output-buffer",
+                    "4096",
+                    "--output",
+                    "out.json",
+                    "--combined-format"
+                ]
+            )
+            .unwrap()
+        );
+
+        // Lists are replaced rather than added to.
+        assert_eq!(
+            vec![
+                "redeye",
+                "--common-format",
+                "--output-buffer=2048",
+                "--output-tcp=localhost:9000",
+                "--filter",
+                "method == \"GET\"",
+            ],
+            merged(config, None, &["redeye", "--filter", "method == \"GET\""]).unwrap()
+        );
+
+        let args = merged(config, None, &["redeye", "--output-buffer", "4096"]).unwrap();
+        match RedeyeCommand::try_parse_args(args.into_iter().map(OsString::from).collect()).unwrap() {
+            RedeyeCommand::Parse(opts) => {
+                assert!(opts.common_format);
+                assert_eq!(4096, opts.output_buffer);
+                assert_eq!(Some("localhost:9000"), opts.output_tcp.as_deref());
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+    }
+
+    #[test]
+    fn test_merge_settings_invalid() {
+        let cli = &["redeye"];
+
+        assert_eq!(
+            "invalid 'output-bufer' on line 1: unknown setting",
+            merged("output-bufer = 2048\n", None, cli).unwrap_err()
+        );
+        assert_eq!(
+            "invalid 'config' on line 1: unknown setting",
+            merged("config = \"other.toml\"\n", None, cli).unwrap_err()
+        );
+        assert_eq!(
+            "invalid 'output.buffer' on line 2: expected a value, not true or false",
+            merged("[output]\nbuffer = true\n", None, cli).unwrap_err()
+        );
+        assert!(merged("output-buffer = \"lots\"\n", None, cli)
+            .unwrap_err()
+            .starts_with("invalid 'output-buffer' on line 1: Invalid value"));
+        assert!(merged("follow-from = \"middle\"\n", None, cli)
+            .unwrap_err()
+            .starts_with("invalid 'follow-from' on line 1:"));
+        assert!(merged("by = \"method\"\n", None, cli)
+            .unwrap_err()
+            .starts_with("invalid 'by' on line 1: unknown setting"));
+    }
+
+    #[test]
+    fn test_effective_config() {
+        let app = RedeyeOptions::into_app();
+        let matches = app
+            .clone()
+            .try_get_matches_from([
+                "redeye",
+                "--common-format",
+                "--output-buffer=2048",
+                "--filter=method == \"GET\"",
+                "access.log",
+            ])
+            .unwrap();
+
+        let config = effective_config(&app, &matches, &[]);
+        let lines: Vec<&str> = config.lines().collect();
+
+        assert!(lines.contains(&"common-format = true  # command line"));
+        assert!(lines.contains(&"# format is not set"));
+        assert!(!lines.iter().any(|l| l.starts_with("combined-format ")));
+        assert!(lines.contains(&"output-buffer = 2048  # command line"));
+        assert!(lines.contains(&"input-buffer = 1024  # default"));
+        assert!(lines.contains(&"filter = [\"method == \\\"GET\\\"\"]  # command line"));
+        assert!(lines.contains(&"file = [\"access.log\"]  # command line"));
+        assert!(lines.contains(&"# output is not set"));
+        assert!(!lines.iter().any(|l| l.starts_with("config ")));
+
+        // The output must be valid configuration that results in the same options.
+        let parsed: Config = config.parse().unwrap();
+        assert_eq!(
+            vec!["common-format", "output-buffer", "file", "filter"],
+            parsed
+                .settings()
+                .iter()
+                .filter(|s| ["common-format", "output-buffer", "filter", "file"].contains(&s.name()))
+                .map(|s| s.name())
+                .collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn test_merge_settings_env() {
+        let options = &[
+            ("common-format", OptionKind::Flag),
+            ("output-buffer", OptionKind::Value),
+            ("input-buffer", OptionKind::Value),
+            ("filter", OptionKind::Values),
+        ];
+        let env = Config::from_env(
+            vars(&[
+                ("REDEYE_OUTPUT_BUFFER", "4096"),
+                ("REDEYE_INPUT_BUFFER", "512"),
+                ("REDEYE_FILTER_0", "method == \"GET\""),
+            ]),
+            options,
+        )
+        .unwrap();
+        let file: Config = concat!(
+            "common-format = true\n",
+            "output-buffer = 2048\n",
+            "filter = [\"status_code >= 500\"]\n",
+        )
+        .parse()
+        .unwrap();
+
+        // The command line takes precedence over the environment, which takes
+        // precedence over the file.
+        let args = vec![OsString::from("redeye"), OsString::from("--input-buffer=256")];
+        let merged = merge_settings(None, args, &[&env, &file]).unwrap();
+        assert_eq!(
+            vec![
+                "redeye",
+                "--filter=method == \"GET\"",
+                "--output-buffer=4096",
+                "--common-format",
+                "--input-buffer=256",
+            ],
+            merged.args
+        );
+
+        let used: Vec<(&str, &str)> = merged.used.iter().map(|(n, s)| (n.as_str(), s.key())).collect();
+        assert_eq!(
+            vec![
+                ("filter", "REDEYE_FILTER_<n>"),
+                ("output-buffer", "REDEYE_OUTPUT_BUFFER"),
+                ("common-format", "common-format"),
+            ],
+            used
+        );
+    }
+
+    #[test]
+    fn test_with_settings() {
+        use std::fs;
+        use tempfile::TempDir;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("redeye.toml");
+        fs::write(&path, "common-format = true\n\n[output]\nbuffer = 2048\n").unwrap();
+
+        let args: Vec<OsString> = ["redeye", "stats", "--json"].iter().map(OsString::from).collect();
+        let command = RedeyeCommand::try_parse_args(args.clone()).unwrap();
+        let env = vec![
+            (OsString::from("REDEYE_CONFIG"), path.clone().into_os_string()),
+            (OsString::from("REDEYE_QUIET"), OsString::from("true")),
+            (OsString::from("HOME"), OsString::from("/root")),
+        ];
+
+        let (command, merged) = command.with_settings(args, env).unwrap();
+        match command {
+            RedeyeCommand::Stats(stats) => {
+                assert!(stats.json);
+                assert!(stats.opts.common_format);
+                assert!(stats.opts.quiet);
+                assert_eq!(2048, stats.opts.output_buffer);
+            }
+            c => panic!("unexpected command {:?}", c),
+        }
+
+        let config = merged.describe().unwrap();
+        let lines: Vec<&str> = config.lines().collect();
+        assert!(lines.contains(&"common-format = true  # common-format on line 1"));
+        assert!(lines.contains(&"output-buffer = 2048  # output.buffer on line 4"));
+        assert!(lines.contains(&"quiet = true  # REDEYE_QUIET"));
+        assert!(lines.contains(&"json = true  # command line"));
+        assert!(lines.contains(&"strict = false  # default"));
+    }
 
-    let reader = BufReader::with_capacity(opts.input_buffer, stdin());
-    let mut writer = BufWriter::with_capacity(opts.output_buffer, stdout());
+    #[test]
+    fn test_with_settings_invalid_env() {
+        let args: Vec<OsString> = ["redeye", "--common-format"].iter().map(OsString::from).collect();
+        let err = |name: &str, value: &str| {
+            let command = RedeyeCommand::try_parse_args(args.clone()).unwrap();
+            command
+                .with_settings(args.clone(), vars(&[(name, value)]))
+                .unwrap_err()
+                .to_string()
+        };
 
-    for line in reader.lines() {
-        let _r = line
-            .map_err(RedeyeError::from)
-            .and_then(|log| parser.parse(&log))
-            .and_then(|event| serde_json::to_string(&event).map_err(RedeyeError::from))
-            .and_then(|json| writeln!(writer, "{}", json).map_err(RedeyeError::from))
-            .map_err(handle_redeye_error);
+        assert!(err("REDEYE_OUTPUT_BUFFER", "lots").starts_with("invalid REDEYE_OUTPUT_BUFFER: Invalid value"));
+        assert_eq!(
+            "invalid REDEYE_STRICT: expected true or false",
+            err("REDEYE_STRICT", "maybe")
+        );
+        assert_eq!(
+            "invalid REDEYE_OUTPUT_BUFER: unknown setting",
+            err("REDEYE_OUTPUT_BUFER", "1")
+        );
+        assert_eq!("invalid REDEYE_HELP: unknown setting", err("REDEYE_HELP", "true"));
+        assert!(err("REDEYE_CONFIG", "/does/not/exist.toml").contains("/does/not/exist.toml"));
     }
 }
@@ -0,0 +1,1536 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Aggregation of events into reports of requests
+
+use crate::types::{LogEvent, LogFieldValue, RedeyeError};
+use chrono::{DateTime, FixedOffset};
+use serde::ser::{SerializeMap, SerializeStruct};
+use serde::{Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Default number of distinct values counted exactly before switching to an estimate.
+const DEFAULT_EXACT_LIMIT: usize = 100_000;
+
+/// Number of bits of each hash used to pick a HyperLogLog register.
+const HLL_PRECISION: u32 = 14;
+
+/// Number of HyperLogLog registers, giving a standard error of about 0.8%.
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// Relative accuracy of quantiles estimated by a `Histogram`.
+const HISTOGRAM_ACCURACY: f64 = 0.01;
+
+/// Maximum number of buckets kept by a `Histogram`. At 1% accuracy this covers
+/// values across about 17 orders of magnitude before the lowest are collapsed.
+const HISTOGRAM_MAX_BUCKETS: usize = 2048;
+
+/// Maximum number of distinct values of a `--group-by` field that requests are
+/// summarized by, others are summarized together.
+const MAX_GROUPS: usize = 100;
+
+/// Fields that hold the duration of a request in the built-in formats and the
+/// factor that converts each of them to milliseconds.
+const DURATION_FIELDS: [(&str, f64); 5] = [
+    ("duration_us", 0.001),
+    ("duration_ms", 1.0),
+    ("duration_s", 1000.0),
+    ("request_time", 1000.0),
+    ("response_time_ms", 1.0),
+];
+
+/// Maximum number of distinct HTTP methods counted, others are counted together.
+const MAX_METHODS: usize = 64;
+
+/// Name used for events without a status code or method.
+const UNKNOWN: &str = "unknown";
+
+/// Name used for methods beyond the maximum number counted.
+const OTHER: &str = "other";
+
+/// Quantiles of content length and duration included in a report.
+const QUANTILES: [(&str, f64); 4] = [("p50", 0.5), ("p90", 0.9), ("p95", 0.95), ("p99", 0.99)];
+
+/// Quantiles included in a report for each status class or group.
+const BREAKDOWN_QUANTILES: [(&str, f64); 3] = [("p50", 0.5), ("p95", 0.95), ("p99", 0.99)];
+
+/// Count of distinct values that is exact up to a limit and then switches to a
+/// HyperLogLog estimate so that memory use is bounded.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::aggregate::DistinctCount;
+///
+/// let mut clients = DistinctCount::new();
+/// clients.insert("127.0.0.1");
+/// clients.insert("127.0.0.2");
+/// clients.insert("127.0.0.1");
+///
+/// assert_eq!(2, clients.count());
+/// assert!(!clients.is_approximate());
+/// ```
+#[derive(Debug, Clone)]
+pub struct DistinctCount {
+    limit: usize,
+    exact: HashSet<String>,
+    registers: Option<Vec<u8>>,
+}
+
+impl DistinctCount {
+    pub fn new() -> Self {
+        DistinctCount {
+            limit: DEFAULT_EXACT_LIMIT,
+            exact: HashSet::new(),
+            registers: None,
+        }
+    }
+
+    /// Set the number of distinct values counted exactly.
+    pub fn exact_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Record a value being seen.
+    pub fn insert(&mut self, value: &str) {
+        if let Some(ref mut registers) = self.registers {
+            hll_insert(registers, value);
+            return;
+        }
+
+        if self.exact.contains(value) {
+            return;
+        }
+
+        self.exact.insert(value.to_owned());
+        if self.exact.len() > self.limit {
+            let mut registers = vec![0; HLL_REGISTERS];
+            for v in self.exact.drain() {
+                hll_insert(&mut registers, &v);
+            }
+
+            self.registers = Some(registers);
+        }
+    }
+
+    /// Number of distinct values seen, estimated if `is_approximate` is true.
+    pub fn count(&self) -> u64 {
+        match self.registers {
+            Some(ref registers) => hll_estimate(registers),
+            None => self.exact.len() as u64,
+        }
+    }
+
+    /// True if there were too many distinct values to count them exactly.
+    pub fn is_approximate(&self) -> bool {
+        self.registers.is_some()
+    }
+}
+
+impl Default for DistinctCount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hll_insert(registers: &mut [u8], value: &str) {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let index = (hash >> (64 - HLL_PRECISION)) as usize;
+    let rest = (hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+    let rank = rest.leading_zeros() as u8 + 1;
+
+    if rank > registers[index] {
+        registers[index] = rank;
+    }
+}
+
+fn hll_estimate(registers: &[u8]) -> u64 {
+    let m = registers.len() as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let sum: f64 = registers.iter().map(|r| 2f64.powi(-i32::from(*r))).sum();
+    let zeros = registers.iter().filter(|r| **r == 0).count();
+    let estimate = alpha * m * m / sum;
+
+    // Use linear counting for small cardinalities where the raw estimate is biased
+    if estimate <= 2.5 * m && zeros > 0 {
+        (m * (m / zeros as f64).ln()).round() as u64
+    } else {
+        estimate.round() as u64
+    }
+}
+
+/// Histogram of non-negative values that estimates quantiles within a fixed
+/// relative error using logarithmically sized buckets, along with the exact
+/// count, sum, minimum, and maximum of the values.
+///
+/// This is the sketch described in "DDSketch: A Fast and Fully-Mergeable Quantile
+/// Sketch with Relative-Error Guarantees" (Masson, Rim, Lee 2019). Each bucket
+/// covers values between `γ^(k-1)` and `γ^k` where `γ = (1 + α) / (1 - α)` and
+/// `α` is 1%, so an estimate for any quantile is within 1% of the value at that
+/// rank in the input. Memory is bounded by keeping at most 2048 buckets: if values
+/// span a wider range than that, the lowest buckets are collapsed together and
+/// only quantiles that fall in the collapsed range lose their error guarantee.
+/// Histograms can be merged without losing any accuracy.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::aggregate::Histogram;
+///
+/// let mut sizes = Histogram::new();
+/// for v in 1..=100 {
+///     sizes.record(v as f64);
+/// }
+///
+/// assert_eq!(100, sizes.count());
+/// assert_eq!(5050.0, sizes.sum());
+///
+/// let p50 = sizes.quantile(0.5).unwrap();
+/// assert!((p50 - 50.0).abs() <= 1.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    gamma_ln: f64,
+    zeros: u64,
+    buckets: BTreeMap<i32, u64>,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        let gamma = (1.0 + HISTOGRAM_ACCURACY) / (1.0 - HISTOGRAM_ACCURACY);
+        Histogram {
+            gamma_ln: gamma.ln(),
+            zeros: 0,
+            buckets: BTreeMap::new(),
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: 0.0,
+        }
+    }
+
+    /// Record a value. Negative values are recorded as zero.
+    pub fn record(&mut self, value: f64) {
+        let value = value.max(0.0);
+        if value == 0.0 {
+            self.zeros += 1;
+        } else {
+            let key = (value.ln() / self.gamma_ln).ceil() as i32;
+            *self.buckets.entry(key).or_insert(0) += 1;
+            self.collapse();
+        }
+
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Add all values recorded by another histogram to this one. Quantiles of the
+    /// result are the same as if every value had been recorded by this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        self.zeros += other.zeros;
+        for (key, count) in other.buckets.iter() {
+            *self.buckets.entry(*key).or_insert(0) += count;
+        }
+
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.collapse();
+    }
+
+    /// Combine the lowest buckets until there are no more than the maximum.
+    fn collapse(&mut self) {
+        while self.buckets.len() > HISTOGRAM_MAX_BUCKETS {
+            let lowest = match self.buckets.keys().next() {
+                Some(k) => *k,
+                None => return,
+            };
+
+            let count = self.buckets.remove(&lowest).unwrap_or(0);
+            if let Some(next) = self.buckets.values_mut().next() {
+                *next += count;
+            }
+        }
+    }
+
+    /// Number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of all values recorded.
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Smallest value recorded, zero if there are none.
+    pub fn min(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.min
+        }
+    }
+
+    /// Largest value recorded, zero if there are none.
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Average of all values recorded, zero if there are none.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Estimate of the value at quantile `q` (between 0 and 1), `None` if no values
+    /// have been recorded.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+
+        // Nearest rank: the smallest value that at least `q` of all values are less than or equal to
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64)
+            .saturating_sub(1)
+            .min(self.count - 1);
+        if rank < self.zeros {
+            return Some(0.0);
+        } else if rank == 0 {
+            return Some(self.min);
+        }
+
+        let mut seen = self.zeros;
+        for (key, count) in self.buckets.iter() {
+            seen += count;
+            if seen > rank {
+                let gamma = self.gamma_ln.exp();
+                let value = 2.0 * gamma.powi(*key) / (gamma + 1.0);
+                return Some(value.max(self.min).min(self.max));
+            }
+        }
+
+        Some(self.max)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Histograms of the content length and duration of a set of requests.
+///
+/// Durations are read from the `duration_us` field written by Apache formats or
+/// the `request_time` field written by Nginx formats and recorded in milliseconds.
+/// Requests without either are counted but don't contribute to the histograms.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::aggregate::RequestDistribution;
+/// use redeye::types::{LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let mut dist = RequestDistribution::new();
+/// let mut event = LogEvent::from(HashMap::new());
+/// event.insert("content_length", LogFieldValue::Int(512));
+/// event.insert("duration_us", LogFieldValue::Int(2500));
+/// dist.add(&event);
+///
+/// assert_eq!(1, dist.requests());
+/// assert_eq!(512.0, dist.content_length().max());
+/// assert_eq!(2.5, dist.duration().max());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RequestDistribution {
+    requests: u64,
+    content_length: Histogram,
+    duration: Histogram,
+}
+
+impl RequestDistribution {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the content length and duration of a single request.
+    pub fn add(&mut self, event: &LogEvent) {
+        self.requests += 1;
+
+        match event.lookup("content_length") {
+            Some(LogFieldValue::Int(v)) => self.content_length.record(*v as f64),
+            Some(LogFieldValue::Float(v)) => self.content_length.record(*v),
+            _ => (),
+        }
+
+        for (field, to_millis) in DURATION_FIELDS.iter() {
+            match event.lookup(field) {
+                Some(LogFieldValue::Int(v)) => self.duration.record(*v as f64 * to_millis),
+                Some(LogFieldValue::Float(v)) => self.duration.record(*v * to_millis),
+                _ => continue,
+            }
+
+            break;
+        }
+    }
+
+    /// Add all requests from another distribution to this one.
+    pub fn merge(&mut self, other: &RequestDistribution) {
+        self.requests += other.requests;
+        self.content_length.merge(&other.content_length);
+        self.duration.merge(&other.duration);
+    }
+
+    /// Number of requests added.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Histogram of response content lengths in bytes.
+    pub fn content_length(&self) -> &Histogram {
+        &self.content_length
+    }
+
+    /// Histogram of request durations in milliseconds.
+    pub fn duration(&self) -> &Histogram {
+        &self.duration
+    }
+
+    /// Short description of the quantiles of content length and duration, `-` if
+    /// neither were recorded.
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.content_length.count() > 0 {
+            let quantiles: Vec<String> = BREAKDOWN_QUANTILES
+                .iter()
+                .filter_map(|(name, q)| self.content_length.quantile(*q).map(|v| format!("{} {:.0}", name, v)))
+                .collect();
+            parts.push(format!("bytes {}", quantiles.join(" ")));
+        }
+
+        if self.duration.count() > 0 {
+            let quantiles: Vec<String> = BREAKDOWN_QUANTILES
+                .iter()
+                .filter_map(|(name, q)| self.duration.quantile(*q).map(|v| format!("{} {:.1}", name, v)))
+                .collect();
+            parts.push(format!("ms {}", quantiles.join(" ")));
+        }
+
+        if parts.is_empty() {
+            "-".to_owned()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+impl Serialize for RequestDistribution {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("RequestDistribution", 3)?;
+        s.serialize_field("requests", &self.requests)?;
+        s.serialize_field(
+            "content_length",
+            &histogram_json(&self.content_length, &BREAKDOWN_QUANTILES, true),
+        )?;
+        s.serialize_field(
+            "duration_ms",
+            &histogram_json(&self.duration, &BREAKDOWN_QUANTILES, false),
+        )?;
+        s.end()
+    }
+}
+
+/// Quantiles, minimum, mean, and maximum of a histogram as a JSON object, all `null`
+/// if no values were recorded. Values are rounded to integers if `integer` is set.
+fn histogram_json(hist: &Histogram, quantiles: &[(&str, f64)], integer: bool) -> serde_json::Value {
+    let value = |v: f64| {
+        if hist.count() == 0 {
+            serde_json::Value::Null
+        } else if integer {
+            serde_json::json!(v.round() as u64)
+        } else {
+            serde_json::json!((v * 1000.0).round() / 1000.0)
+        }
+    };
+
+    let mut out = serde_json::Map::new();
+    for (name, q) in quantiles.iter() {
+        out.insert((*name).to_owned(), hist.quantile(*q).map(value).unwrap_or_default());
+    }
+
+    out.insert("min".to_owned(), value(hist.min()));
+    out.insert("mean".to_owned(), value(hist.mean()));
+    out.insert("max".to_owned(), value(hist.max()));
+    serde_json::Value::Object(out)
+}
+
+/// Value of the field at a dotted path as a string, `-` if the field is missing.
+fn field_string(event: &LogEvent, path: &str) -> String {
+    match event.lookup(path) {
+        Some(LogFieldValue::Text(s)) => s.clone(),
+        Some(LogFieldValue::Int(v)) => v.to_string(),
+        Some(LogFieldValue::Float(v)) => v.to_string(),
+        Some(LogFieldValue::Bool(v)) => v.to_string(),
+        Some(LogFieldValue::Timestamp(v)) => v.to_rfc3339(),
+        Some(v @ LogFieldValue::Mapping(_)) | Some(v @ LogFieldValue::List(_)) => {
+            serde_json::to_string(v).unwrap_or_default()
+        }
+        None => "-".to_owned(),
+    }
+}
+
+/// Summary of requests: counts by status class and method, content length
+/// and duration quantiles overall and by status class, unique clients, and
+/// requests per minute. Optionally, content length and duration quantiles are
+/// also broken down by the value of another field.
+///
+/// Fields are read from events using the names emitted by the built-in formats:
+/// `status_code`, `method`, `content_length`, `duration_us` or `request_time`,
+/// `remote_host`, and `@timestamp`. Events that are missing any of them are still
+/// counted as requests.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::aggregate::RequestSummary;
+/// use redeye::types::{LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let mut summary = RequestSummary::new();
+/// for status in &[200, 200, 404] {
+///     let mut event = LogEvent::from(HashMap::new());
+///     event.insert("status_code", LogFieldValue::Int(*status));
+///     event.insert("method", LogFieldValue::Text("GET".to_owned()));
+///     summary.add(&event);
+/// }
+///
+/// assert_eq!(3, summary.requests());
+/// assert_eq!(vec![("2xx".to_owned(), 2), ("4xx".to_owned(), 1)], summary.status_classes());
+/// assert_eq!(vec![("GET".to_owned(), 3)], summary.methods());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RequestSummary {
+    requests: u64,
+    status_classes: BTreeMap<String, RequestDistribution>,
+    methods: BTreeMap<String, u64>,
+    overall: RequestDistribution,
+    group_by: Option<String>,
+    groups: HashMap<String, RequestDistribution>,
+    clients: DistinctCount,
+    minutes: BTreeMap<i64, u64>,
+    first: Option<DateTime<FixedOffset>>,
+    last: Option<DateTime<FixedOffset>>,
+}
+
+impl RequestSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also break down content length and duration by the value of the field
+    /// at a dotted path, for up to 100 distinct values.
+    pub fn group_by<S>(mut self, path: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.group_by = Some(path.into());
+        self
+    }
+
+    /// Add a single request to the summary.
+    pub fn add(&mut self, event: &LogEvent) {
+        self.requests += 1;
+        self.overall.add(event);
+
+        let class = match event.lookup("status_code") {
+            Some(LogFieldValue::Int(code)) => format!("{}xx", code / 100),
+            _ => UNKNOWN.to_owned(),
+        };
+        self.status_classes.entry(class).or_default().add(event);
+
+        if let Some(ref path) = self.group_by {
+            let value = field_string(event, path);
+            let group = if self.groups.contains_key(&value) || self.groups.len() < MAX_GROUPS {
+                value
+            } else {
+                OTHER.to_owned()
+            };
+            self.groups.entry(group).or_default().add(event);
+        }
+
+        let method = match event.lookup("method") {
+            Some(LogFieldValue::Text(m)) if self.methods.contains_key(m.as_str()) => m.clone(),
+            Some(LogFieldValue::Text(m)) if self.methods.len() < MAX_METHODS => m.clone(),
+            Some(LogFieldValue::Text(_)) => OTHER.to_owned(),
+            _ => UNKNOWN.to_owned(),
+        };
+        *self.methods.entry(method).or_insert(0) += 1;
+
+        if let Some(LogFieldValue::Text(host)) = event.lookup("remote_host") {
+            self.clients.insert(host);
+        }
+
+        if let Some(LogFieldValue::Timestamp(ts)) = event.lookup("@timestamp") {
+            *self.minutes.entry(ts.timestamp().div_euclid(60)).or_insert(0) += 1;
+            if self.first.map(|f| *ts < f).unwrap_or(true) {
+                self.first = Some(*ts);
+            }
+
+            if self.last.map(|l| *ts > l).unwrap_or(true) {
+                self.last = Some(*ts);
+            }
+        }
+    }
+
+    /// Total number of requests.
+    pub fn requests(&self) -> u64 {
+        self.requests
+    }
+
+    /// Number of requests for each status class (e.g. `2xx`), ordered by class.
+    pub fn status_classes(&self) -> Vec<(String, u64)> {
+        self.status_classes
+            .iter()
+            .map(|(k, v)| (k.clone(), v.requests()))
+            .collect()
+    }
+
+    /// Content length and duration for each status class, ordered by class.
+    pub fn status_class_distributions(&self) -> Vec<(&str, &RequestDistribution)> {
+        self.status_classes.iter().map(|(k, v)| (k.as_str(), v)).collect()
+    }
+
+    /// Dotted path of the field requests are grouped by, if any.
+    pub fn group_path(&self) -> Option<&str> {
+        self.group_by.as_deref()
+    }
+
+    /// Content length and duration for each value of the field requests are
+    /// grouped by, most common first.
+    pub fn group_distributions(&self) -> Vec<(&str, &RequestDistribution)> {
+        let mut groups: Vec<(&str, &RequestDistribution)> = self.groups.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        groups.sort_by(|a, b| b.1.requests().cmp(&a.1.requests()).then(a.0.cmp(b.0)));
+        groups
+    }
+
+    /// Number of requests for each method, most common first.
+    pub fn methods(&self) -> Vec<(String, u64)> {
+        let mut methods: Vec<(String, u64)> = self.methods.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        methods.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        methods
+    }
+
+    /// Histogram of response content lengths in bytes.
+    pub fn content_length(&self) -> &Histogram {
+        self.overall.content_length()
+    }
+
+    /// Histogram of request durations in milliseconds.
+    pub fn duration(&self) -> &Histogram {
+        self.overall.duration()
+    }
+
+    /// Count of distinct clients, based on the remote host.
+    pub fn clients(&self) -> &DistinctCount {
+        &self.clients
+    }
+
+    /// Earliest and latest request timestamps, if any requests had one.
+    pub fn time_range(&self) -> Option<(DateTime<FixedOffset>, DateTime<FixedOffset>)> {
+        self.first.zip(self.last)
+    }
+
+    /// Average number of requests per minute over the minutes between the first
+    /// and last request, inclusive. Zero if no requests had a timestamp.
+    pub fn requests_per_minute(&self) -> f64 {
+        let (first, last) = match (self.minutes.keys().next(), self.minutes.keys().next_back()) {
+            (Some(f), Some(l)) => (*f, *l),
+            _ => return 0.0,
+        };
+
+        let total: u64 = self.minutes.values().sum();
+        total as f64 / (last - first + 1) as f64
+    }
+
+    /// Largest number of requests in any single minute.
+    pub fn peak_requests_per_minute(&self) -> u64 {
+        self.minutes.values().copied().max().unwrap_or(0)
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.requests as f64
+        }
+    }
+}
+
+impl fmt::Display for RequestSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "requests:         {}", self.requests)?;
+        if let Some((first, last)) = self.time_range() {
+            writeln!(f, "first request:    {}", first.to_rfc3339())?;
+            writeln!(f, "last request:     {}", last.to_rfc3339())?;
+            writeln!(
+                f,
+                "requests/minute:  {:.1} average, {} peak",
+                self.requests_per_minute(),
+                self.peak_requests_per_minute()
+            )?;
+        }
+
+        writeln!(
+            f,
+            "unique clients:   {}{}",
+            if self.clients.is_approximate() { "~" } else { "" },
+            self.clients.count()
+        )?;
+
+        let content_length = self.content_length();
+        writeln!(f, "content length:   {:.0} total", content_length.sum())?;
+        for (name, q) in QUANTILES.iter() {
+            if let Some(v) = content_length.quantile(*q) {
+                writeln!(f, "  {}:            {:.0}", name, v)?;
+            }
+        }
+
+        if content_length.count() > 0 {
+            writeln!(f, "  min:            {:.0}", content_length.min())?;
+            writeln!(f, "  mean:           {:.0}", content_length.mean())?;
+            writeln!(f, "  max:            {:.0}", content_length.max())?;
+        }
+
+        let duration = self.duration();
+        if duration.count() > 0 {
+            writeln!(f, "duration (ms):    {} requests", duration.count())?;
+            for (name, q) in QUANTILES.iter() {
+                if let Some(v) = duration.quantile(*q) {
+                    writeln!(f, "  {}:            {:.1}", name, v)?;
+                }
+            }
+
+            writeln!(f, "  min:            {:.1}", duration.min())?;
+            writeln!(f, "  mean:           {:.1}", duration.mean())?;
+            writeln!(f, "  max:            {:.1}", duration.max())?;
+        }
+
+        writeln!(f, "status:")?;
+        for (class, count) in self.status_classes() {
+            writeln!(f, "  {:<15} {:>10} {:>6.1}%", class, count, self.percent(count))?;
+        }
+
+        write!(f, "method:")?;
+        for (method, count) in self.methods() {
+            write!(f, "\n  {:<15} {:>10} {:>6.1}%", method, count, self.percent(count))?;
+        }
+
+        write!(f, "\nby status:")?;
+        for (class, dist) in self.status_class_distributions() {
+            write!(f, "\n  {:<15} {}", class, dist.describe())?;
+        }
+
+        if let Some(path) = self.group_path() {
+            write!(f, "\nby {}:", path)?;
+            for (value, dist) in self.group_distributions() {
+                write!(f, "\n  {:<15} {:>10}  {}", value, dist.requests(), dist.describe())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts by name along with the percentage of all requests, serialized as a map.
+struct Breakdown<'a> {
+    summary: &'a RequestSummary,
+    counts: Vec<(String, u64)>,
+}
+
+impl<'a> Serialize for Breakdown<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.counts.len()))?;
+        for (name, count) in self.counts.iter() {
+            let value = serde_json::json!({"count": count, "percent": self.summary.percent(*count)});
+            map.serialize_entry(name, &value)?;
+        }
+
+        map.end()
+    }
+}
+
+impl Serialize for RequestSummary {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        // Content lengths are a number of bytes so they're output as integers
+        let mut content_length = histogram_json(self.content_length(), &QUANTILES, true);
+        if let serde_json::Value::Object(ref mut m) = content_length {
+            m.insert("total".to_owned(), (self.content_length().sum().round() as u64).into());
+            m.insert("max".to_owned(), (self.content_length().max().round() as u64).into());
+        }
+
+        let mut duration = histogram_json(self.duration(), &QUANTILES, false);
+        if let serde_json::Value::Object(ref mut m) = duration {
+            m.insert("count".to_owned(), self.duration().count().into());
+        }
+
+        let by_status: BTreeMap<&str, &RequestDistribution> = self.status_class_distributions().into_iter().collect();
+        let range = self.time_range();
+        let fields = if self.group_by.is_some() { 13 } else { 12 };
+        let mut s = serializer.serialize_struct("RequestSummary", fields)?;
+        s.serialize_field("requests", &self.requests)?;
+        s.serialize_field("first_request", &range.map(|(f, _)| f.to_rfc3339()))?;
+        s.serialize_field("last_request", &range.map(|(_, l)| l.to_rfc3339()))?;
+        s.serialize_field("requests_per_minute", &self.requests_per_minute())?;
+        s.serialize_field("peak_requests_per_minute", &self.peak_requests_per_minute())?;
+        s.serialize_field("unique_clients", &self.clients.count())?;
+        s.serialize_field("unique_clients_approximate", &self.clients.is_approximate())?;
+        s.serialize_field("content_length", &content_length)?;
+        s.serialize_field("duration_ms", &duration)?;
+        s.serialize_field(
+            "status_classes",
+            &Breakdown {
+                summary: self,
+                counts: self.status_classes(),
+            },
+        )?;
+        s.serialize_field(
+            "methods",
+            &Breakdown {
+                summary: self,
+                counts: self.methods(),
+            },
+        )?;
+        s.serialize_field("by_status_class", &by_status)?;
+        if let Some(path) = self.group_path() {
+            let groups: serde_json::Map<String, serde_json::Value> = self
+                .group_distributions()
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), serde_json::to_value(v).unwrap_or_default()))
+                .collect();
+            s.serialize_field("group_by", &serde_json::json!({"field": path, "groups": groups}))?;
+        }
+        s.end()
+    }
+}
+
+/// Value that events are ranked by in `TopValues`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Number of events.
+    Count,
+    /// Sum of the `content_length` of events.
+    Bytes,
+}
+
+impl FromStr for Metric {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(Metric::Count),
+            "bytes" => Ok(Metric::Bytes),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown metric '{}', expected one of count, bytes",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Count => f.write_str("count"),
+            Metric::Bytes => f.write_str("bytes"),
+        }
+    }
+}
+
+/// One of the values with the largest totals in `HeavyHitters`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ranked {
+    pub value: String,
+    /// Total for the value, which may be larger than the actual total by up to `error`.
+    pub total: u64,
+    /// Largest amount that `total` may have been overestimated by, zero when exact.
+    pub error: u64,
+}
+
+/// Totals of weights for the values with the largest totals.
+///
+/// Totals are exact until more than a limit of distinct values have been seen.
+/// After that, the Space-Saving algorithm is used: only `capacity` values are
+/// tracked and a new value replaces the one with the smallest total, inheriting
+/// that total as its possible error. Any value with a true total larger than the
+/// total of all weights divided by the capacity is guaranteed to be tracked.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::aggregate::HeavyHitters;
+///
+/// let mut uris = HeavyHitters::new();
+/// uris.add("/", 1);
+/// uris.add("/about", 1);
+/// uris.add("/", 1);
+///
+/// let top = uris.top(1);
+/// assert_eq!("/", top[0].value);
+/// assert_eq!(2, top[0].total);
+/// assert_eq!(0, top[0].error);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeavyHitters {
+    capacity: usize,
+    totals: HashMap<String, (u64, u64)>,
+    // Values ordered by total, only maintained once the capacity has been reached
+    order: Option<BTreeSet<(u64, String)>>,
+    sum: u64,
+}
+
+impl HeavyHitters {
+    pub fn new() -> Self {
+        HeavyHitters {
+            capacity: DEFAULT_EXACT_LIMIT,
+            totals: HashMap::new(),
+            order: None,
+            sum: 0,
+        }
+    }
+
+    /// Set the number of distinct values tracked (at least one).
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Add a weight to the total for a value.
+    pub fn add(&mut self, value: &str, weight: u64) {
+        self.sum += weight;
+
+        if let Some((total, _)) = self.totals.get_mut(value) {
+            if let Some(ref mut order) = self.order {
+                order.remove(&(*total, value.to_owned()));
+                order.insert((*total + weight, value.to_owned()));
+            }
+
+            *total += weight;
+            return;
+        }
+
+        if self.totals.len() < self.capacity {
+            self.totals.insert(value.to_owned(), (weight, 0));
+            return;
+        }
+
+        let totals = &self.totals;
+        let order = self
+            .order
+            .get_or_insert_with(|| totals.iter().map(|(v, (t, _))| (*t, v.clone())).collect());
+
+        let (min, evicted) = match order.iter().next() {
+            Some(first) => first.clone(),
+            None => return,
+        };
+
+        order.remove(&(min, evicted.clone()));
+        order.insert((min + weight, value.to_owned()));
+        self.totals.remove(&evicted);
+        self.totals.insert(value.to_owned(), (min + weight, min));
+    }
+
+    /// Sum of all weights added.
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    /// True if more distinct values than the capacity have been seen and totals
+    /// may be overestimated.
+    pub fn is_approximate(&self) -> bool {
+        self.order.is_some()
+    }
+
+    /// Up to `n` values with the largest totals, largest first.
+    pub fn top(&self, n: usize) -> Vec<Ranked> {
+        let mut ranked: Vec<Ranked> = self
+            .totals
+            .iter()
+            .map(|(value, (total, error))| Ranked {
+                value: value.clone(),
+                total: *total,
+                error: *error,
+            })
+            .collect();
+
+        ranked.sort_by(|a, b| b.total.cmp(&a.total).then(a.value.cmp(&b.value)));
+        ranked.truncate(n);
+        ranked
+    }
+}
+
+impl Default for HeavyHitters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Values of a field of events with the largest number of events or bytes.
+///
+/// The field is given as a dotted path, e.g. `request_headers.user_agent`.
+/// Events without the field are counted under `-`, the same way that missing
+/// values are written in access logs.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::aggregate::{Metric, TopValues};
+/// use redeye::types::{LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let mut top = TopValues::new("requested_uri", Metric::Bytes);
+/// for (uri, bytes) in &[("/", 100), ("/big", 5000), ("/", 200)] {
+///     let mut event = LogEvent::from(HashMap::new());
+///     event.insert("requested_uri", LogFieldValue::Text(uri.to_string()));
+///     event.insert("content_length", LogFieldValue::Int(*bytes));
+///     top.add(&event);
+/// }
+///
+/// let ranked = top.top(2);
+/// assert_eq!("/big", ranked[0].value);
+/// assert_eq!(5000, ranked[0].total);
+/// assert_eq!("/", ranked[1].value);
+/// assert_eq!(300, ranked[1].total);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TopValues {
+    path: String,
+    metric: Metric,
+    hitters: HeavyHitters,
+}
+
+impl TopValues {
+    pub fn new<S>(path: S, metric: Metric) -> Self
+    where
+        S: Into<String>,
+    {
+        TopValues {
+            path: path.into(),
+            metric,
+            hitters: HeavyHitters::new(),
+        }
+    }
+
+    /// Set the number of distinct values of the field tracked.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.hitters = self.hitters.capacity(capacity);
+        self
+    }
+
+    /// Dotted path of the field events are grouped by.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Value that events are ranked by.
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    /// Add the count or bytes of an event to the total for its value of the field.
+    pub fn add(&mut self, event: &LogEvent) {
+        let weight = match self.metric {
+            Metric::Count => 1,
+            Metric::Bytes => match event.lookup("content_length") {
+                Some(LogFieldValue::Int(v)) => *v,
+                Some(LogFieldValue::Float(v)) => *v as u64,
+                _ => 0,
+            },
+        };
+
+        let value = field_string(event, &self.path);
+        self.hitters.add(&value, weight);
+    }
+
+    /// Total count or bytes of all events.
+    pub fn sum(&self) -> u64 {
+        self.hitters.sum()
+    }
+
+    /// True if there were too many distinct values to count all of them exactly.
+    pub fn is_approximate(&self) -> bool {
+        self.hitters.is_approximate()
+    }
+
+    /// Up to `n` values with the largest totals, largest first.
+    pub fn top(&self, n: usize) -> Vec<Ranked> {
+        self.hitters.top(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DistinctCount, HeavyHitters, Histogram, Metric, RequestDistribution, RequestSummary, TopValues};
+    use crate::types::{LogEvent, LogFieldValue};
+    use chrono::DateTime;
+    use std::collections::HashMap;
+
+    fn event(host: &str, ts: &str, method: &str, status: u64, length: Option<u64>) -> LogEvent {
+        let mut event = LogEvent::from(HashMap::new());
+        event.insert("remote_host", LogFieldValue::Text(host.to_owned()));
+        event.insert(
+            "@timestamp",
+            LogFieldValue::Timestamp(DateTime::parse_from_rfc3339(ts).unwrap()),
+        );
+        event.insert("method", LogFieldValue::Text(method.to_owned()));
+        event.insert("status_code", LogFieldValue::Int(status));
+        if let Some(v) = length {
+            event.insert("content_length", LogFieldValue::Int(v));
+        }
+
+        event
+    }
+
+    #[test]
+    fn test_distinct_count_exact() {
+        let mut count = DistinctCount::new().exact_limit(10);
+        for i in 0..20 {
+            count.insert(&format!("10.0.0.{}", i % 10));
+        }
+
+        assert_eq!(10, count.count());
+        assert!(!count.is_approximate());
+    }
+
+    #[test]
+    fn test_distinct_count_approximate() {
+        let mut count = DistinctCount::new().exact_limit(1000);
+        for i in 0..50_000 {
+            count.insert(&format!("client-{}", i));
+            count.insert(&format!("client-{}", i / 2));
+        }
+
+        let estimate = count.count();
+        assert!(count.is_approximate());
+        assert!(estimate > 48_500 && estimate < 51_500, "estimate {}", estimate);
+    }
+
+    #[test]
+    fn test_histogram_quantiles() {
+        let mut hist = Histogram::new();
+        for v in 0..=1000 {
+            hist.record(v as f64);
+        }
+
+        assert_eq!(1001, hist.count());
+        assert_eq!(500_500.0, hist.sum());
+        assert_eq!(1000.0, hist.max());
+        assert_eq!(Some(0.0), hist.quantile(0.0));
+        assert_eq!(Some(1000.0), hist.quantile(1.0));
+
+        for (q, expected) in &[(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+            let v = hist.quantile(*q).unwrap();
+            assert!((v - expected).abs() <= expected * 0.01, "q {} = {}", q, v);
+        }
+    }
+
+    #[test]
+    fn test_histogram_empty() {
+        let hist = Histogram::new();
+        assert_eq!(None, hist.quantile(0.5));
+        assert_eq!(0.0, hist.min());
+        assert_eq!(0.0, hist.mean());
+        assert_eq!(0.0, hist.max());
+    }
+
+    #[test]
+    fn test_histogram_min_mean() {
+        let mut hist = Histogram::new();
+        for v in &[40.0, 10.0, 25.0] {
+            hist.record(*v);
+        }
+
+        assert_eq!(10.0, hist.min());
+        assert_eq!(25.0, hist.mean());
+        assert_eq!(40.0, hist.max());
+        assert_eq!(Some(10.0), hist.quantile(0.0));
+    }
+
+    #[test]
+    fn test_histogram_merge() {
+        let mut all = Histogram::new();
+        let mut low = Histogram::new();
+        let mut high = Histogram::new();
+        for v in 0..=1000 {
+            all.record(v as f64);
+            if v < 300 {
+                low.record(v as f64);
+            } else {
+                high.record(v as f64);
+            }
+        }
+
+        low.merge(&high);
+        assert_eq!(all.count(), low.count());
+        assert_eq!(all.sum(), low.sum());
+        assert_eq!(all.min(), low.min());
+        assert_eq!(all.max(), low.max());
+        for q in &[0.0, 0.25, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(all.quantile(*q), low.quantile(*q), "q {}", q);
+        }
+    }
+
+    #[test]
+    fn test_histogram_merge_empty() {
+        let mut hist = Histogram::new();
+        hist.record(5.0);
+        hist.merge(&Histogram::new());
+
+        assert_eq!(1, hist.count());
+        assert_eq!(5.0, hist.min());
+        assert_eq!(Some(5.0), hist.quantile(0.5));
+    }
+
+    #[test]
+    fn test_histogram_bounded() {
+        let mut hist = Histogram::new();
+        for e in -300..300 {
+            for m in 1..10 {
+                hist.record(m as f64 * 10f64.powi(e));
+            }
+        }
+
+        assert!(hist.buckets.len() <= super::HISTOGRAM_MAX_BUCKETS);
+        assert_eq!(5400, hist.count());
+
+        // Quantiles above the collapsed range keep their accuracy
+        let p99 = hist.quantile(0.99).unwrap();
+        let expected = 9.0 * 10f64.powi(293);
+        assert!((p99 - expected).abs() <= expected * 0.01, "p99 = {}", p99);
+    }
+
+    #[test]
+    fn test_request_distribution_durations() {
+        let mut dist = RequestDistribution::new();
+        let mut apache = LogEvent::from(HashMap::new());
+        apache.insert("duration_us", LogFieldValue::Int(1500));
+        let mut nginx = LogEvent::from(HashMap::new());
+        nginx.insert("request_time", LogFieldValue::Float(0.25));
+        let mut tomcat = LogEvent::from(HashMap::new());
+        tomcat.insert("duration_ms", LogFieldValue::Int(42));
+        let mut lighttpd = LogEvent::from(HashMap::new());
+        lighttpd.insert("duration_s", LogFieldValue::Int(3));
+        let mut morgan = LogEvent::from(HashMap::new());
+        morgan.insert("response_time_ms", LogFieldValue::Float(2.5));
+        dist.add(&apache);
+        dist.add(&nginx);
+        dist.add(&tomcat);
+        dist.add(&lighttpd);
+        dist.add(&morgan);
+        dist.add(&LogEvent::from(HashMap::new()));
+
+        assert_eq!(6, dist.requests());
+        assert_eq!(0, dist.content_length().count());
+        assert_eq!(5, dist.duration().count());
+        assert_eq!(1.5, dist.duration().min());
+        assert_eq!(3000.0, dist.duration().max());
+    }
+
+    #[test]
+    fn test_request_distribution_merge() {
+        let mut a = RequestDistribution::new();
+        let mut b = RequestDistribution::new();
+        a.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, Some(100)));
+        b.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, Some(300)));
+        b.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, None));
+        a.merge(&b);
+
+        assert_eq!(3, a.requests());
+        assert_eq!(2, a.content_length().count());
+        assert_eq!(200.0, a.content_length().mean());
+    }
+
+    #[test]
+    fn test_request_summary() {
+        let mut summary = RequestSummary::new();
+        summary.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, Some(100)));
+        summary.add(&event("10.0.0.2", "2020-01-01T00:00:50Z", "GET", 404, Some(0)));
+        summary.add(&event("10.0.0.1", "2020-01-01T00:01:10Z", "POST", 201, None));
+        summary.add(&event("10.0.0.3", "2020-01-01T00:03:59Z", "GET", 503, Some(300)));
+        summary.add(&LogEvent::from(HashMap::new()));
+
+        assert_eq!(5, summary.requests());
+        assert_eq!(
+            vec![
+                ("2xx".to_owned(), 2),
+                ("4xx".to_owned(), 1),
+                ("5xx".to_owned(), 1),
+                ("unknown".to_owned(), 1),
+            ],
+            summary.status_classes()
+        );
+        assert_eq!(
+            vec![("GET".to_owned(), 3), ("POST".to_owned(), 1), ("unknown".to_owned(), 1)],
+            summary.methods()
+        );
+        assert_eq!(3, summary.clients().count());
+        assert_eq!(3, summary.content_length().count());
+        assert_eq!(400.0, summary.content_length().sum());
+        assert_eq!(2, summary.peak_requests_per_minute());
+        assert_eq!(1.0, summary.requests_per_minute());
+
+        let (first, last) = summary.time_range().unwrap();
+        assert_eq!("2020-01-01T00:00:10+00:00", first.to_rfc3339());
+        assert_eq!("2020-01-01T00:03:59+00:00", last.to_rfc3339());
+    }
+
+    #[test]
+    fn test_request_summary_many_methods() {
+        let mut summary = RequestSummary::new();
+        for i in 0..100 {
+            summary.add(&event(
+                "10.0.0.1",
+                "2020-01-01T00:00:00Z",
+                &format!("M{}", i),
+                200,
+                None,
+            ));
+        }
+
+        let methods = summary.methods();
+        assert_eq!(65, methods.len());
+        assert_eq!(("other".to_owned(), 36), methods[0]);
+    }
+
+    #[test]
+    fn test_request_summary_by_status_class() {
+        let mut summary = RequestSummary::new();
+        for (status, length, duration) in &[(200, 100, 10), (200, 300, 30), (500, 50, 900)] {
+            let mut e = event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", *status, Some(*length));
+            e.insert("duration_us", LogFieldValue::Int(duration * 1000));
+            summary.add(&e);
+        }
+
+        assert_eq!(3, summary.duration().count());
+        assert_eq!(900.0, summary.duration().max());
+
+        let classes = summary.status_class_distributions();
+        assert_eq!(2, classes.len());
+        assert_eq!("2xx", classes[0].0);
+        assert_eq!(2, classes[0].1.requests());
+        assert_eq!(300.0, classes[0].1.content_length().max());
+        assert_eq!(30.0, classes[0].1.duration().max());
+        assert_eq!("5xx", classes[1].0);
+        assert_eq!(Some(900.0), classes[1].1.duration().quantile(0.5));
+    }
+
+    #[test]
+    fn test_request_summary_group_by() {
+        let mut summary = RequestSummary::new().group_by("method");
+        summary.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, Some(100)));
+        summary.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "POST", 200, Some(5000)));
+        summary.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "POST", 200, Some(7000)));
+
+        let groups = summary.group_distributions();
+        assert_eq!(Some("method"), summary.group_path());
+        assert_eq!(2, groups.len());
+        assert_eq!(("POST", 2), (groups[0].0, groups[0].1.requests()));
+        assert_eq!(7000.0, groups[0].1.content_length().max());
+        assert_eq!(("GET", 1), (groups[1].0, groups[1].1.requests()));
+    }
+
+    #[test]
+    fn test_request_summary_group_by_bounded() {
+        let mut summary = RequestSummary::new().group_by("remote_host");
+        for i in 0..150 {
+            summary.add(&event(
+                &format!("10.0.0.{}", i),
+                "2020-01-01T00:00:10Z",
+                "GET",
+                200,
+                None,
+            ));
+        }
+
+        let groups = summary.group_distributions();
+        assert_eq!(101, groups.len());
+        assert_eq!(("other", 50), (groups[0].0, groups[0].1.requests()));
+    }
+
+    #[test]
+    fn test_request_summary_serialize() {
+        let mut summary = RequestSummary::new();
+        summary.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, Some(100)));
+        summary.add(&event("10.0.0.2", "2020-01-01T00:00:50Z", "GET", 500, Some(300)));
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(2, json["requests"]);
+        assert_eq!("2020-01-01T00:00:10+00:00", json["first_request"]);
+        assert_eq!(2.0, json["requests_per_minute"]);
+        assert_eq!(2, json["unique_clients"]);
+        assert_eq!(false, json["unique_clients_approximate"]);
+        assert_eq!(400, json["content_length"]["total"]);
+        assert_eq!(300, json["content_length"]["max"]);
+        assert_eq!(100, json["content_length"]["p50"]);
+        assert_eq!(100, json["content_length"]["min"]);
+        assert_eq!(200, json["content_length"]["mean"]);
+        assert_eq!(0, json["duration_ms"]["count"]);
+        assert!(json["duration_ms"]["p99"].is_null());
+        assert_eq!(1, json["by_status_class"]["2xx"]["requests"]);
+        assert_eq!(300, json["by_status_class"]["5xx"]["content_length"]["p95"]);
+        assert!(json["by_status_class"]["5xx"]["duration_ms"]["p95"].is_null());
+        assert!(json.get("group_by").is_none());
+        assert_eq!(1, json["status_classes"]["5xx"]["count"]);
+        assert_eq!(50.0, json["status_classes"]["5xx"]["percent"]);
+        assert_eq!(100.0, json["methods"]["GET"]["percent"]);
+    }
+
+    #[test]
+    fn test_request_summary_display() {
+        let mut summary = RequestSummary::new();
+        summary.add(&event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, Some(100)));
+
+        let text = summary.to_string();
+        assert!(text.starts_with("requests:         1\n"), "{}", text);
+        assert!(text.contains("unique clients:   1\n"), "{}", text);
+        assert!(text.contains("  2xx                      1  100.0%"), "{}", text);
+        assert!(
+            text.contains("\nby status:\n  2xx             bytes p50 100 p95 100 p99 100"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("duration"), "{}", text);
+    }
+
+    #[test]
+    fn test_request_summary_display_group_by() {
+        let mut summary = RequestSummary::new().group_by("method");
+        let mut e = event("10.0.0.1", "2020-01-01T00:00:10Z", "GET", 200, None);
+        e.insert("request_time", LogFieldValue::Float(0.012));
+        summary.add(&e);
+
+        let text = summary.to_string();
+        assert!(
+            text.contains("duration (ms):    1 requests\n  p50:            12.0\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.ends_with("\nby method:\n  GET                      1  ms p50 12.0 p95 12.0 p99 12.0"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_heavy_hitters_exact() {
+        let mut hitters = HeavyHitters::new().capacity(10);
+        for (value, weight) in &[("a", 1), ("b", 5), ("a", 3), ("c", 2)] {
+            hitters.add(value, *weight);
+        }
+
+        let top = hitters.top(2);
+        assert!(!hitters.is_approximate());
+        assert_eq!(11, hitters.sum());
+        assert_eq!(("b", 5, 0), (top[0].value.as_str(), top[0].total, top[0].error));
+        assert_eq!(("a", 4, 0), (top[1].value.as_str(), top[1].total, top[1].error));
+    }
+
+    #[test]
+    fn test_heavy_hitters_approximate() {
+        let mut hitters = HeavyHitters::new().capacity(50);
+
+        // A few frequent values mixed with many values that are only seen once
+        for i in 0..10_000 {
+            hitters.add(&format!("rare-{}", i), 1);
+            if i % 10 == 0 {
+                hitters.add("frequent-a", 1);
+            }
+            if i % 20 == 0 {
+                hitters.add("frequent-b", 1);
+            }
+        }
+
+        assert!(hitters.is_approximate());
+        assert_eq!(11_500, hitters.sum());
+
+        let top = hitters.top(2);
+        assert_eq!("frequent-a", top[0].value);
+        assert_eq!("frequent-b", top[1].value);
+        for r in top.iter() {
+            let actual = if r.value == "frequent-a" { 1000 } else { 500 };
+            assert!(r.total >= actual && r.total - r.error <= actual, "{:?}", r);
+        }
+    }
+
+    #[test]
+    fn test_top_values_nested_and_missing() {
+        let mut top = TopValues::new("request_headers.user_agent", Metric::Count);
+        for ua in &[Some("curl"), Some("firefox"), Some("curl"), None] {
+            let mut event = LogEvent::from(HashMap::new());
+            if let Some(ua) = ua {
+                let mut headers = HashMap::new();
+                headers.insert("user_agent".to_owned(), LogFieldValue::Text(ua.to_string()));
+                event.insert("request_headers", LogFieldValue::Mapping(headers));
+            }
+            top.add(&event);
+        }
+
+        let ranked = top.top(10);
+        assert_eq!(4, top.sum());
+        assert_eq!(3, ranked.len());
+        assert_eq!(("curl", 2), (ranked[0].value.as_str(), ranked[0].total));
+        assert_eq!(("-", 1), (ranked[1].value.as_str(), ranked[1].total));
+        assert_eq!(("firefox", 1), (ranked[2].value.as_str(), ranked[2].total));
+    }
+
+    #[test]
+    fn test_metric_from_str() {
+        assert_eq!(Metric::Count, "count".parse().unwrap());
+        assert_eq!(Metric::Bytes, "bytes".parse().unwrap());
+        assert!("requests".parse::<Metric>().unwrap_err().is_config_error());
+    }
+}
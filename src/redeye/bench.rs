@@ -0,0 +1,348 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Measuring how quickly lines are processed, for `redeye bench`
+
+use crate::aggregate::Histogram;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::time::Duration;
+
+/// Quantiles of per-line latency that are reported.
+const LATENCY_QUANTILES: [(&str, f64); 3] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99)];
+
+/// Results of processing the same lines with a number of workers.
+///
+/// Latency is the time taken to parse, enrich, filter, and serialize each line,
+/// not including time spent waiting to be read or written, so with more than
+/// one worker it's usually higher than a single worker even when throughput is
+/// much better.
+#[derive(Debug, Clone)]
+pub struct BenchRun {
+    workers: usize,
+    lines: u64,
+    errors: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+    elapsed: Duration,
+    latency: Histogram,
+    allocations: Option<u64>,
+}
+
+impl BenchRun {
+    pub fn new(workers: usize) -> Self {
+        BenchRun {
+            workers,
+            lines: 0,
+            errors: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            elapsed: Duration::from_secs(0),
+            latency: Histogram::new(),
+            allocations: None,
+        }
+    }
+
+    /// Record the time taken to process a single line.
+    pub fn line(&mut self, latency: Duration) {
+        self.latency.record(latency.as_secs_f64() * 1_000_000.0);
+    }
+
+    /// Set the totals once every line has been processed.
+    pub fn finish(&mut self, lines: u64, errors: u64, bytes_in: u64, bytes_out: u64, elapsed: Duration) {
+        self.lines = lines;
+        self.errors = errors;
+        self.bytes_in = bytes_in;
+        self.bytes_out = bytes_out;
+        self.elapsed = elapsed;
+    }
+
+    /// Set the number of allocations made while processing lines, if they were counted.
+    pub fn allocations(&mut self, allocations: u64) {
+        self.allocations = Some(allocations);
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Number of lines that couldn't be processed, usually because they weren't
+    /// in the expected format.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+
+    /// Lines processed per second.
+    pub fn lines_per_second(&self) -> f64 {
+        per_second(self.lines as f64, self.elapsed)
+    }
+
+    /// Megabytes (10^6 bytes) of input read per second.
+    pub fn mb_in_per_second(&self) -> f64 {
+        per_second(self.bytes_in as f64 / 1_000_000.0, self.elapsed)
+    }
+
+    /// Megabytes (10^6 bytes) of events written per second.
+    pub fn mb_out_per_second(&self) -> f64 {
+        per_second(self.bytes_out as f64 / 1_000_000.0, self.elapsed)
+    }
+
+    /// Time taken to process each line, in microseconds.
+    pub fn latency(&self) -> &Histogram {
+        &self.latency
+    }
+
+    /// Average number of allocations made per line, if allocations were counted.
+    pub fn allocations_per_line(&self) -> Option<f64> {
+        match self.allocations {
+            Some(n) if self.lines > 0 => Some(n as f64 / self.lines as f64),
+            _ => None,
+        }
+    }
+}
+
+fn per_second(value: f64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        value / secs
+    } else {
+        0.0
+    }
+}
+
+impl Serialize for BenchRun {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut latency = serde_json::Map::new();
+        for (name, q) in LATENCY_QUANTILES.iter() {
+            latency.insert(
+                (*name).to_owned(),
+                serde_json::json!(rounded(self.latency.quantile(*q))),
+            );
+        }
+
+        let count = self.latency.count() > 0;
+        latency.insert(
+            "mean".to_owned(),
+            serde_json::json!(rounded(Some(self.latency.mean()).filter(|_| count))),
+        );
+        latency.insert(
+            "max".to_owned(),
+            serde_json::json!(rounded(Some(self.latency.max()).filter(|_| count))),
+        );
+
+        let mut s = serializer.serialize_struct("BenchRun", 11)?;
+        s.serialize_field("workers", &self.workers)?;
+        s.serialize_field("lines", &self.lines)?;
+        s.serialize_field("errors", &self.errors)?;
+        s.serialize_field("elapsed_seconds", &rounded(Some(self.elapsed.as_secs_f64())))?;
+        s.serialize_field("lines_per_second", &self.lines_per_second().round())?;
+        s.serialize_field("mb_in_per_second", &rounded(Some(self.mb_in_per_second())))?;
+        s.serialize_field("mb_out_per_second", &rounded(Some(self.mb_out_per_second())))?;
+        s.serialize_field("bytes_in", &self.bytes_in)?;
+        s.serialize_field("bytes_out", &self.bytes_out)?;
+        s.serialize_field("latency_us", &latency)?;
+        s.serialize_field("allocations_per_line", &rounded(self.allocations_per_line()))?;
+        s.end()
+    }
+}
+
+/// Value rounded to three decimal places.
+fn rounded(v: Option<f64>) -> Option<f64> {
+    v.map(|v| (v * 1000.0).round() / 1000.0)
+}
+
+/// Results of processing the same lines with different numbers of workers,
+/// printed as a table with a row for each run.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::bench::{BenchReport, BenchRun};
+/// use std::time::Duration;
+///
+/// let mut run = BenchRun::new(1);
+/// run.line(Duration::from_micros(4));
+/// run.finish(1, 0, 100, 250, Duration::from_micros(5));
+///
+/// let report = BenchReport::new("combined", vec![run]);
+/// assert!(report.to_string().contains("lines/sec"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    format: String,
+    runs: Vec<BenchRun>,
+}
+
+impl BenchReport {
+    pub fn new<S: Into<String>>(format: S, runs: Vec<BenchRun>) -> Self {
+        BenchReport {
+            format: format.into(),
+            runs,
+        }
+    }
+
+    pub fn runs(&self) -> &[BenchRun] {
+        &self.runs
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self.runs.first().map(|r| r.lines).unwrap_or(0);
+        writeln!(f, "format:  {}", self.format)?;
+        writeln!(f, "lines:   {}", lines)?;
+        writeln!(f)?;
+
+        let allocations = self.runs.iter().any(|r| r.allocations.is_some());
+        write!(
+            f,
+            "{:>7}  {:>10}  {:>8}  {:>8}  {:>9}  {:>8}  {:>8}  {:>8}  {:>8}",
+            "workers", "lines/sec", "MB/s in", "MB/s out", "mean (us)", "p50", "p90", "p99", "max"
+        )?;
+        if allocations {
+            write!(f, "  {:>11}", "allocs/line")?;
+        }
+        writeln!(f)?;
+
+        for run in self.runs.iter() {
+            let latency = |q: f64| run.latency.quantile(q).unwrap_or(0.0);
+            write!(
+                f,
+                "{:>7}  {:>10.0}  {:>8.1}  {:>8.1}  {:>9.1}  {:>8.1}  {:>8.1}  {:>8.1}  {:>8.1}",
+                run.workers,
+                run.lines_per_second(),
+                run.mb_in_per_second(),
+                run.mb_out_per_second(),
+                run.latency.mean(),
+                latency(0.5),
+                latency(0.9),
+                latency(0.99),
+                run.latency.max(),
+            )?;
+            if let Some(n) = run.allocations_per_line() {
+                write!(f, "  {:>11.1}", n)?;
+            }
+            writeln!(f)?;
+        }
+
+        let errors: u64 = self.runs.iter().map(|r| r.errors).max().unwrap_or(0);
+        if errors > 0 {
+            writeln!(f)?;
+            writeln!(f, "{} lines couldn't be processed, check that --format matches", errors)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for BenchReport {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("BenchReport", 3)?;
+        s.serialize_field("format", &self.format)?;
+        s.serialize_field("lines", &self.runs.first().map(|r| r.lines).unwrap_or(0))?;
+        s.serialize_field("runs", &self.runs)?;
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BenchReport, BenchRun};
+    use std::time::Duration;
+
+    fn run(workers: usize) -> BenchRun {
+        let mut run = BenchRun::new(workers);
+        for us in 1..=100 {
+            run.line(Duration::from_micros(us));
+        }
+        run.finish(100, 2, 2_000_000, 5_000_000, Duration::from_millis(500));
+        run
+    }
+
+    #[test]
+    fn test_bench_run_rates() {
+        let run = run(1);
+        assert_eq!(200.0, run.lines_per_second());
+        assert_eq!(4.0, run.mb_in_per_second());
+        assert_eq!(10.0, run.mb_out_per_second());
+        assert_eq!(None, run.allocations_per_line());
+
+        let p50 = run.latency().quantile(0.5).unwrap();
+        assert!((p50 - 50.0).abs() <= 1.0, "{}", p50);
+    }
+
+    #[test]
+    fn test_bench_run_no_time() {
+        let mut run = BenchRun::new(1);
+        run.finish(0, 0, 0, 0, Duration::from_secs(0));
+        run.allocations(10);
+
+        assert_eq!(0.0, run.lines_per_second());
+        assert_eq!(0.0, run.mb_in_per_second());
+        assert_eq!(None, run.allocations_per_line());
+    }
+
+    #[test]
+    fn test_bench_run_allocations() {
+        let mut run = run(1);
+        run.allocations(2500);
+        assert_eq!(Some(25.0), run.allocations_per_line());
+    }
+
+    #[test]
+    fn test_bench_report_display() {
+        let mut multi = run(4);
+        multi.allocations(2500);
+        let report = BenchReport::new("combined", vec![run(1), multi]).to_string();
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!("format:  combined", lines[0]);
+        assert_eq!("lines:   100", lines[1]);
+        assert!(lines[3].trim_start().starts_with("workers"), "{}", lines[3]);
+        assert!(lines[3].ends_with("allocs/line"), "{}", lines[3]);
+        assert!(lines[4].trim_start().starts_with("1         200"), "{}", lines[4]);
+        assert!(lines[5].trim_start().starts_with("4         200"), "{}", lines[5]);
+        assert!(lines[5].ends_with("25.0"), "{}", lines[5]);
+        assert!(report.contains("2 lines couldn't be processed"), "{}", report);
+    }
+
+    #[test]
+    fn test_bench_report_json() {
+        let report = BenchReport::new("common", vec![run(1)]);
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!("common", json["format"]);
+        assert_eq!(100, json["lines"]);
+        let run = &json["runs"][0];
+        assert_eq!(1, run["workers"]);
+        assert_eq!(200.0, run["lines_per_second"]);
+        assert_eq!(4.0, run["mb_in_per_second"]);
+        assert_eq!(10.0, run["mb_out_per_second"]);
+        assert_eq!(2, run["errors"]);
+        assert_eq!(100.0, run["latency_us"]["max"]);
+        assert!(run["latency_us"]["p99"].as_f64().unwrap() > 95.0);
+        assert!(run["allocations_per_line"].is_null());
+    }
+}
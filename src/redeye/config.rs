@@ -0,0 +1,720 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Settings loaded from a TOML configuration file or environment variables
+//!
+//! Each setting corresponds to a command line option. Keys at the top level of
+//! the file are option names and keys in a table are prefixed with the name of
+//! the table, so `buffer` in an `[output]` table is the same as `output-buffer`
+//! at the top level and `--output-buffer` on the command line. Underscores in
+//! keys are treated as dashes.
+//!
+//! Environment variables are option names in upper case with dashes replaced by
+//! underscores and a `REDEYE_` prefix, e.g. `REDEYE_OUTPUT_BUFFER`. Options that
+//! may be given multiple times are set with a comma separated list, or with one
+//! variable per value by adding a numeric suffix, e.g. `REDEYE_FILTER_0` and
+//! `REDEYE_FILTER_1`, which are used in numeric order. The suffixed form is for
+//! values that contain commas, including `name=value` pairs for options that
+//! take them. Flags are set with `true` or `false` (or `1`, `yes`, `on` and `0`,
+//! `no`, `off`). Empty variables are ignored.
+//!
+//! Lists in the file may contain inline tables, which are the same as the
+//! comma separated `key=value` pairs taken by options such as `--input`, e.g.
+//! `input = [{ path = "/var/log/haproxy.log", format = "combined" }]`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use redeye::config::{Config, OptionKind};
+//!
+//! let config: Config = r#"
+//! combined-format = true
+//!
+//! [output]
+//! buffer = 65536
+//! "#
+//! .parse()
+//! .unwrap();
+//!
+//! let setting = config.get("output-buffer").unwrap();
+//! assert_eq!("output.buffer", setting.key());
+//! assert_eq!(vec!["--output-buffer=65536"], setting.args(OptionKind::Value).unwrap());
+//! ```
+
+use crate::types::{RedeyeError, RedeyeResult};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use toml_edit::{Document, InlineTable, Item, Table, Value};
+
+/// How a command line option accepts values, which determines the values that
+/// a setting for it can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionKind {
+    /// An option without a value, set with `true` or `false`.
+    Flag,
+    /// An option with a single value.
+    Value,
+    /// An option that can be given with or without a single value, set with
+    /// `true` or `false` or a value.
+    OptionalValue,
+    /// An option that can be given multiple times, set with a value or a list.
+    Values,
+    /// Positional arguments, set with a value or a list.
+    Positional,
+}
+
+/// Value of a setting in a configuration file. Numbers are kept as they would be
+/// written on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingValue {
+    Bool(bool),
+    Value(String),
+    List(Vec<String>),
+}
+
+/// Prefix of environment variables for settings.
+pub const ENV_PREFIX: &str = "REDEYE_";
+
+/// Name of the environment variable for a command line option, e.g.
+/// `REDEYE_OUTPUT_BUFFER` for `output-buffer`.
+pub fn env_var(name: &str) -> String {
+    format!("{}{}", ENV_PREFIX, name.to_uppercase().replace('-', "_"))
+}
+
+/// Where a setting came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// A line (starting from one) of a configuration file.
+    File(usize),
+    /// An environment variable, the key of the setting.
+    Env,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(line) => write!(f, "line {}", line),
+            Source::Env => write!(f, "the environment"),
+        }
+    }
+}
+
+/// Single setting from a configuration file or the environment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setting {
+    key: String,
+    name: String,
+    source: Source,
+    value: SettingValue,
+}
+
+impl Setting {
+    /// Key of the setting as written in the file, including any tables it's in,
+    /// e.g. `output.buffer`, or the name of the environment variable it's from.
+    /// Settings from numbered variables use `_<n>` in place of the number, e.g.
+    /// `REDEYE_FILTER_<n>`.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Name of the command line option for the setting, e.g. `output-buffer`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn value(&self) -> &SettingValue {
+        &self.value
+    }
+
+    /// Command line arguments equivalent to the setting for an option of the given
+    /// kind, or an error if the value can't be used for that kind of option.
+    pub fn args(&self, kind: OptionKind) -> RedeyeResult<Vec<String>> {
+        // Values are joined to the option so that those starting with a dash
+        // aren't mistaken for another option.
+        let option = |v: &str| format!("--{}={}", self.name, v);
+        match (kind, &self.value) {
+            (OptionKind::Flag, SettingValue::Bool(true)) => Ok(vec![format!("--{}", self.name)]),
+            (OptionKind::Flag, SettingValue::Bool(false)) => Ok(Vec::new()),
+            (OptionKind::Flag, _) => Err(self.error("expected true or false")),
+            (OptionKind::OptionalValue, SettingValue::Bool(true)) => Ok(vec![format!("--{}", self.name)]),
+            (OptionKind::OptionalValue, SettingValue::Bool(false)) => Ok(Vec::new()),
+            (OptionKind::OptionalValue, SettingValue::Value(v)) => Ok(vec![option(v)]),
+            (_, SettingValue::Bool(_)) => Err(self.error("expected a value, not true or false")),
+            (OptionKind::Value, SettingValue::Value(v)) => Ok(vec![option(v)]),
+            (OptionKind::Value | OptionKind::OptionalValue, SettingValue::List(_)) => {
+                Err(self.error("expected a single value, not a list"))
+            }
+            (OptionKind::Values, SettingValue::Value(v)) => Ok(vec![option(v)]),
+            (OptionKind::Values, SettingValue::List(vals)) => Ok(vals.iter().map(|v| option(v)).collect()),
+            (OptionKind::Positional, SettingValue::Value(v)) => Ok(vec![v.clone()]),
+            (OptionKind::Positional, SettingValue::List(vals)) => Ok(vals.clone()),
+        }
+    }
+
+    /// Error for an invalid value of the setting, pointing at its key and line or
+    /// at the environment variable it's from.
+    pub fn error(&self, msg: &str) -> RedeyeError {
+        RedeyeError::ConfigError(match self.source {
+            Source::File(line) => format!("invalid '{}' on line {}: {}", self.key, line, msg),
+            Source::Env => format!("invalid {}: {}", self.key, msg),
+        })
+    }
+}
+
+/// Settings from a configuration file, in the order they're written.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    settings: Vec<Setting>,
+}
+
+impl Config {
+    /// Read and parse a configuration file. Errors parsing it include the path.
+    pub fn load<P>(path: P) -> RedeyeResult<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(|e| {
+            RedeyeError::IoError(std::io::Error::new(
+                e.kind(),
+                format!("Unable to read config {}: {}", path.display(), e),
+            ))
+        })?;
+
+        contents
+            .parse()
+            .map_err(|e| RedeyeError::ConfigError(format!("Invalid config {}: {}", path.display(), e)))
+    }
+
+    /// Read settings from `REDEYE_*` environment variables for options with the
+    /// given names and kinds. Variables that don't match any of the options, or
+    /// that have a value that can't be used for their kind of option, are errors.
+    /// Settings are sorted by name since the order of variables is arbitrary.
+    pub fn from_env<I>(vars: I, options: &[(&str, OptionKind)]) -> RedeyeResult<Self>
+    where
+        I: IntoIterator<Item = (OsString, OsString)>,
+    {
+        let kind_of = |name: &str| options.iter().find(|(o, _)| *o == name).map(|(_, k)| *k);
+        let mut settings: Vec<Setting> = Vec::new();
+        let mut numbered: Vec<(String, u64, String)> = Vec::new();
+
+        for (var, value) in vars {
+            let var = match var.into_string() {
+                Ok(v) if v.starts_with(ENV_PREFIX) => v,
+                _ => continue,
+            };
+
+            let value = value
+                .into_string()
+                .map_err(|_| RedeyeError::ConfigError(format!("invalid {}: not valid UTF-8", var)))?;
+            if value.is_empty() {
+                continue;
+            }
+
+            let name = var[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+            if let Some(kind) = kind_of(&name) {
+                let value = env_value(kind, &value)
+                    .ok_or_else(|| RedeyeError::ConfigError(format!("invalid {}: expected true or false", var)))?;
+                settings.push(Setting {
+                    key: var,
+                    name,
+                    source: Source::Env,
+                    value,
+                });
+                continue;
+            }
+
+            let indexed = name
+                .rsplit_once('-')
+                .and_then(|(base, n)| n.parse::<u64>().ok().map(|n| (base, n)))
+                .filter(|(base, _)| matches!(kind_of(base), Some(OptionKind::Values | OptionKind::Positional)));
+
+            match indexed {
+                Some((base, n)) => numbered.push((base.to_owned(), n, value)),
+                None => return Err(RedeyeError::ConfigError(format!("invalid {}: unknown setting", var))),
+            }
+        }
+
+        numbered.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        for (name, _, value) in numbered {
+            let key = format!("{}_<n>", env_var(&name));
+            if let Some(setting) = settings.iter_mut().find(|s| s.name == name) {
+                match setting.value {
+                    SettingValue::List(ref mut values) if setting.key == key => values.push(value),
+                    _ => {
+                        return Err(RedeyeError::ConfigError(format!(
+                            "invalid {}: can't be used with {}",
+                            key, setting.key
+                        )))
+                    }
+                }
+            } else {
+                settings.push(Setting {
+                    key,
+                    name,
+                    source: Source::Env,
+                    value: SettingValue::List(vec![value]),
+                });
+            }
+        }
+
+        settings.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Config { settings })
+    }
+
+    pub fn settings(&self) -> &[Setting] {
+        &self.settings
+    }
+
+    /// Setting for the command line option with this name, if there is one.
+    pub fn get(&self, name: &str) -> Option<&Setting> {
+        self.settings.iter().find(|s| s.name == name)
+    }
+
+    /// Remove the setting for the command line option with this name, if there is one.
+    pub fn remove(&mut self, name: &str) -> Option<Setting> {
+        let index = self.settings.iter().position(|s| s.name == name)?;
+        Some(self.settings.remove(index))
+    }
+}
+
+/// Value of a setting from an environment variable for an option of the given
+/// kind, or `None` if it's a flag and the value isn't true or false.
+fn env_value(kind: OptionKind, value: &str) -> Option<SettingValue> {
+    let flag = match value.to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(SettingValue::Bool(true)),
+        "false" | "0" | "no" | "off" => Some(SettingValue::Bool(false)),
+        _ => None,
+    };
+
+    match kind {
+        OptionKind::Flag => flag,
+        OptionKind::OptionalValue => flag.or_else(|| Some(SettingValue::Value(value.to_owned()))),
+        OptionKind::Value => Some(SettingValue::Value(value.to_owned())),
+        OptionKind::Values | OptionKind::Positional => Some(SettingValue::List(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        )),
+    }
+}
+
+impl FromStr for Config {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let doc: Document<String> = s.parse().map_err(|e: toml_edit::TomlError| {
+            let line = e.span().map(|span| line_of(s, span.start)).unwrap_or(1);
+            RedeyeError::ConfigError(format!("line {}: {}", line, e.message().trim_end()))
+        })?;
+
+        let mut settings = Vec::new();
+        read_table(doc.raw(), "", "", doc.as_table(), &mut settings)?;
+        Ok(Config { settings })
+    }
+}
+
+/// Add the settings in a table, and any tables nested in it, prefixing their keys
+/// and names with those of the table.
+fn read_table(
+    raw: &str,
+    key_prefix: &str,
+    name_prefix: &str,
+    table: &Table,
+    out: &mut Vec<Setting>,
+) -> RedeyeResult<()> {
+    for (k, item) in table.iter() {
+        let key = format!("{}{}", key_prefix, k);
+        let name = format!("{}{}", name_prefix, k.replace('_', "-"));
+        let line = table
+            .get_key_value(k)
+            .and_then(|(k, _)| k.span())
+            .map(|span| line_of(raw, span.start))
+            .unwrap_or(0);
+
+        let value = match item {
+            Item::Table(nested) => {
+                read_table(raw, &format!("{}.", key), &format!("{}-", name), nested, out)?;
+                continue;
+            }
+            Item::Value(v) => setting_value(v),
+            _ => None,
+        };
+
+        let setting = Setting {
+            key,
+            name,
+            source: Source::File(line),
+            value: SettingValue::Bool(false),
+        };
+
+        let value = value.ok_or_else(|| setting.error("expected a string, number, boolean, or list of them"))?;
+        if let Some(other) = out.iter().find(|s| s.name == setting.name) {
+            return Err(setting.error(&format!("already set by '{}' on {}", other.key, other.source)));
+        }
+
+        out.push(Setting { value, ..setting });
+    }
+
+    Ok(())
+}
+
+/// Value of a setting, or `None` if it isn't a kind of value that can be used
+/// for a command line option.
+fn setting_value(value: &Value) -> Option<SettingValue> {
+    match value {
+        Value::Boolean(b) => Some(SettingValue::Bool(*b.value())),
+        Value::Array(array) => array
+            .iter()
+            .map(|v| match v {
+                Value::InlineTable(t) => pairs(t),
+                v => scalar(v),
+            })
+            .collect::<Option<Vec<String>>>()
+            .map(SettingValue::List),
+        v => scalar(v).map(SettingValue::Value),
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        _ => None,
+    }
+}
+
+/// Keys and values of a table as comma separated `key=value` pairs, the form
+/// taken by options such as `--input`, or `None` if a value isn't a scalar.
+fn pairs(table: &InlineTable) -> Option<String> {
+    table
+        .iter()
+        .map(|(k, v)| scalar(v).map(|v| format!("{}={}", k, v)))
+        .collect::<Option<Vec<String>>>()
+        .map(|p| p.join(","))
+}
+
+/// Line number (starting from one) of a byte offset in the file.
+fn line_of(raw: &str, offset: usize) -> usize {
+    raw[..offset].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{env_var, Config, OptionKind, SettingValue, Source};
+    use std::ffi::OsString;
+    use std::fs;
+
+    #[test]
+    fn test_config_tables() {
+        let config: Config = concat!(
+            "common-format = true\n",
+            "files = [\"a.log\", \"b.log\"]\n",
+            "\n",
+            "[output]\n",
+            "buffer = 65536\n",
+            "\n",
+            "[http.batch]\n",
+            "wait = \"500ms\"\n",
+        )
+        .parse()
+        .unwrap();
+
+        let names: Vec<&str> = config.settings().iter().map(|s| s.name()).collect();
+        assert_eq!(
+            vec!["common-format", "files", "output-buffer", "http-batch-wait"],
+            names
+        );
+
+        let wait = config.get("http-batch-wait").unwrap();
+        assert_eq!("http.batch.wait", wait.key());
+        assert_eq!(Source::File(8), wait.source());
+        assert_eq!(&SettingValue::Value("500ms".to_owned()), wait.value());
+    }
+
+    #[test]
+    fn test_config_inline_tables() {
+        let config: Config = concat!(
+            "input = [\n",
+            "  { path = \"/var/log/nginx/*.log\", format = \"combined\" },\n",
+            "  \"path=/var/log/haproxy.log,format=common\",\n",
+            "]\n",
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(
+            &SettingValue::List(vec![
+                "path=/var/log/nginx/*.log,format=combined".to_owned(),
+                "path=/var/log/haproxy.log,format=common".to_owned(),
+            ]),
+            config.get("input").unwrap().value()
+        );
+
+        assert!("input = [{ path = [\"a.log\"] }]\n".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_config_underscores() {
+        let config: Config = "[output]\nline_buffered = true\n".parse().unwrap();
+        assert_eq!(
+            "output.line_buffered",
+            config.get("output-line-buffered").unwrap().key()
+        );
+    }
+
+    #[test]
+    fn test_config_multiline_string() {
+        let config: Config = "format-apache = '''\n%h %l %u %t \"%r\" %>s %b'''\n".parse().unwrap();
+        assert_eq!(
+            &SettingValue::Value("%h %l %u %t \"%r\" %>s %b".to_owned()),
+            config.get("format-apache").unwrap().value()
+        );
+    }
+
+    #[test]
+    fn test_config_invalid_toml() {
+        let err = "quiet = true\noutput-buffer = \n".parse::<Config>().unwrap_err();
+        assert!(err.to_string().starts_with("line 2: "), "{}", err);
+    }
+
+    #[test]
+    fn test_config_invalid_value() {
+        let err = "quiet = true\nselect = { a = 1 }\n".parse::<Config>().unwrap_err();
+        assert_eq!(
+            "invalid 'select' on line 2: expected a string, number, boolean, or list of them",
+            err.to_string()
+        );
+
+        assert!("filter = [[\"a\"]]\n".parse::<Config>().is_err());
+        assert!("filter = [true]\n".parse::<Config>().is_err());
+        assert!("since = 2020-01-01T00:00:00Z\n".parse::<Config>().is_err());
+        assert!("[[output]]\nbuffer = 1\n".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn test_config_duplicate() {
+        let err = "output-buffer = 1\n[output]\nbuffer = 2\n"
+            .parse::<Config>()
+            .unwrap_err();
+        assert_eq!(
+            "invalid 'output.buffer' on line 3: already set by 'output-buffer' on line 1",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_setting_args() {
+        let config: Config = concat!(
+            "quiet = true\n",
+            "strict = false\n",
+            "workers = 4\n",
+            "filter = [\"status_code >= 500\", \"method == GET\"]\n",
+            "files = \"access.log\"\n",
+        )
+        .parse()
+        .unwrap();
+
+        let args = |name: &str, kind| config.get(name).unwrap().args(kind);
+        assert_eq!(vec!["--quiet"], args("quiet", OptionKind::Flag).unwrap());
+        assert!(args("strict", OptionKind::Flag).unwrap().is_empty());
+        assert_eq!(vec!["--workers=4"], args("workers", OptionKind::Value).unwrap());
+        assert_eq!(
+            vec!["--filter=status_code >= 500", "--filter=method == GET"],
+            args("filter", OptionKind::Values).unwrap()
+        );
+        assert_eq!(vec!["access.log"], args("files", OptionKind::Positional).unwrap());
+        assert_eq!(vec!["--quiet"], args("quiet", OptionKind::OptionalValue).unwrap());
+        assert!(args("strict", OptionKind::OptionalValue).unwrap().is_empty());
+        assert_eq!(vec!["--workers=4"], args("workers", OptionKind::OptionalValue).unwrap());
+    }
+
+    #[test]
+    fn test_setting_args_wrong_kind() {
+        let config: Config = "quiet = 1\nworkers = true\nfilter = [\"a\"]\n".parse().unwrap();
+        let err = |name: &str, kind| config.get(name).unwrap().args(kind).unwrap_err().to_string();
+
+        assert_eq!(
+            "invalid 'quiet' on line 1: expected true or false",
+            err("quiet", OptionKind::Flag)
+        );
+        assert_eq!(
+            "invalid 'workers' on line 2: expected a value, not true or false",
+            err("workers", OptionKind::Value)
+        );
+        assert_eq!(
+            "invalid 'filter' on line 3: expected a single value, not a list",
+            err("filter", OptionKind::Value)
+        );
+    }
+
+    #[test]
+    fn test_config_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redeye.toml");
+        assert!(Config::load(&path).unwrap_err().is_io_error());
+
+        fs::write(&path, "workers = \n").unwrap();
+        let err = Config::load(&path).unwrap_err();
+        assert!(err.is_config_error());
+        assert!(err.to_string().contains("redeye.toml"), "{}", err);
+
+        fs::write(&path, "workers = 2\n").unwrap();
+        assert_eq!(1, Config::load(&path).unwrap().settings().len());
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .collect()
+    }
+
+    const OPTIONS: &[(&str, OptionKind)] = &[
+        ("quiet", OptionKind::Flag),
+        ("output-buffer", OptionKind::Value),
+        ("filter", OptionKind::Values),
+        ("http-header", OptionKind::Values),
+        ("dedup-consecutive", OptionKind::OptionalValue),
+        ("file", OptionKind::Positional),
+    ];
+
+    #[test]
+    fn test_env_var() {
+        assert_eq!("REDEYE_OUTPUT_BUFFER", env_var("output-buffer"));
+        assert_eq!("REDEYE_FILE", env_var("file"));
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        let config = Config::from_env(
+            env(&[
+                ("PATH", "/usr/bin"),
+                ("REDEYE_QUIET", "Yes"),
+                ("REDEYE_OUTPUT_BUFFER", "65536"),
+                ("REDEYE_FILE", "a.log, b.log,"),
+                ("REDEYE_HTTP_HEADER_10", "X-Third: c"),
+                ("REDEYE_HTTP_HEADER_2", "X-Second: b, c"),
+                ("REDEYE_HTTP_HEADER_0", "X-First: a"),
+                ("REDEYE_FILTER", ""),
+            ]),
+            OPTIONS,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = config.settings().iter().map(|s| s.name()).collect();
+        assert_eq!(vec!["file", "http-header", "output-buffer", "quiet"], names);
+
+        let quiet = config.get("quiet").unwrap();
+        assert_eq!("REDEYE_QUIET", quiet.key());
+        assert_eq!(Source::Env, quiet.source());
+        assert_eq!(&SettingValue::Bool(true), quiet.value());
+
+        assert_eq!(
+            &SettingValue::Value("65536".to_owned()),
+            config.get("output-buffer").unwrap().value()
+        );
+        assert_eq!(
+            &SettingValue::List(vec!["a.log".to_owned(), "b.log".to_owned()]),
+            config.get("file").unwrap().value()
+        );
+
+        // Numbered variables are used in numeric order and aren't split on commas.
+        let headers = config.get("http-header").unwrap();
+        assert_eq!("REDEYE_HTTP_HEADER_<n>", headers.key());
+        assert_eq!(
+            &SettingValue::List(vec![
+                "X-First: a".to_owned(),
+                "X-Second: b, c".to_owned(),
+                "X-Third: c".to_owned()
+            ]),
+            headers.value()
+        );
+    }
+
+    #[test]
+    fn test_config_from_env_flags() {
+        for (value, expected) in &[
+            ("true", true),
+            ("1", true),
+            ("ON", true),
+            ("false", false),
+            ("no", false),
+        ] {
+            let config = Config::from_env(env(&[("REDEYE_QUIET", value)]), OPTIONS).unwrap();
+            assert_eq!(
+                &SettingValue::Bool(*expected),
+                config.get("quiet").unwrap().value(),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_from_env_optional_value() {
+        let value = |v: &str| {
+            let config = Config::from_env(env(&[("REDEYE_DEDUP_CONSECUTIVE", v)]), OPTIONS).unwrap();
+            config.get("dedup-consecutive").unwrap().value().clone()
+        };
+
+        assert_eq!(SettingValue::Bool(true), value("yes"));
+        assert_eq!(SettingValue::Bool(false), value("off"));
+        assert_eq!(SettingValue::Value("method,uri".to_owned()), value("method,uri"));
+    }
+
+    #[test]
+    fn test_config_from_env_invalid() {
+        let err = |vars: &[(&str, &str)]| Config::from_env(env(vars), OPTIONS).unwrap_err().to_string();
+
+        assert_eq!(
+            "invalid REDEYE_OUTPUT_BUFER: unknown setting",
+            err(&[("REDEYE_OUTPUT_BUFER", "1")])
+        );
+        assert_eq!(
+            "invalid REDEYE_QUIET: expected true or false",
+            err(&[("REDEYE_QUIET", "sometimes")])
+        );
+        assert_eq!(
+            "invalid REDEYE_OUTPUT_BUFFER_0: unknown setting",
+            err(&[("REDEYE_OUTPUT_BUFFER_0", "1")])
+        );
+        assert_eq!(
+            "invalid REDEYE_FILTER_<n>: can't be used with REDEYE_FILTER",
+            err(&[("REDEYE_FILTER", "a"), ("REDEYE_FILTER_0", "b")])
+        );
+    }
+
+    #[test]
+    fn test_config_remove() {
+        let mut config = Config::from_env(env(&[("REDEYE_QUIET", "1")]), OPTIONS).unwrap();
+        assert!(config.remove("output-buffer").is_none());
+        assert_eq!("REDEYE_QUIET", config.remove("quiet").unwrap().key());
+        assert!(config.settings().is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Dropping consecutive duplicate events
+//!
+//! An event is a duplicate of the event before it if their dedup keys (see
+//! `LogEvent::dedup_key`) are equal. Duplicates are dropped and the next event
+//! that's written says how many were dropped before it. So that a long run of
+//! duplicates doesn't go unreported, the last duplicate is written once the run
+//! has lasted longer than the flush interval, along with how many were dropped
+//! before it.
+
+use crate::types::LogEvent;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default time a run of duplicates can last before the last one is written.
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Event to write along with the number of duplicates dropped just before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deduped<T> {
+    pub item: T,
+    pub repeats: u64,
+}
+
+/// State of the current run of duplicates.
+#[derive(Debug)]
+struct Run<T> {
+    last: Option<u64>,
+    dropped: u64,
+    held: Option<T>,
+    started: Option<Instant>,
+}
+
+/// Filter of consecutive duplicate events, which may be shared between threads.
+///
+/// Keys are computed from events with `key`, which may be done in any order.
+/// Events must be passed to `push` in the order they're written.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::dedup::{Dedup, Deduped};
+///
+/// let dedup = Dedup::new(None);
+/// assert_eq!(Some(Deduped { item: "a", repeats: 0 }), dedup.push(1, "a"));
+/// assert_eq!(None, dedup.push(1, "a"));
+/// assert_eq!(None, dedup.push(1, "a"));
+/// assert_eq!(Some(Deduped { item: "b", repeats: 2 }), dedup.push(2, "b"));
+/// ```
+#[derive(Debug)]
+pub struct Dedup<T> {
+    fields: Option<Vec<String>>,
+    interval: Duration,
+    run: Mutex<Run<T>>,
+}
+
+impl<T> Dedup<T> {
+    /// Create a filter comparing the given fields of events, or every field except
+    /// those that always differ if no fields are given.
+    pub fn new(fields: Option<Vec<String>>) -> Self {
+        Dedup {
+            fields,
+            interval: DEFAULT_FLUSH_INTERVAL,
+            run: Mutex::new(Run {
+                last: None,
+                dropped: 0,
+                held: None,
+                started: None,
+            }),
+        }
+    }
+
+    /// Set how long a run of duplicates can last before the last one is written.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Key of an event, equal for events that are duplicates of each other.
+    pub fn key(&self, event: &LogEvent) -> u64 {
+        event.dedup_key(self.fields.as_deref())
+    }
+
+    /// Handle the next event, returning it if it should be written or `None` if
+    /// it's a duplicate of the event before it.
+    pub fn push(&self, key: u64, item: T) -> Option<Deduped<T>> {
+        let mut run = self.run.lock().unwrap();
+        if run.last == Some(key) {
+            run.dropped += 1;
+            run.held = Some(item);
+            run.started.get_or_insert_with(Instant::now);
+            return None;
+        }
+
+        let repeats = run.dropped;
+        run.last = Some(key);
+        run.dropped = 0;
+        run.held = None;
+        run.started = None;
+        Some(Deduped { item, repeats })
+    }
+
+    /// Last duplicate of a run that has lasted longer than the flush interval, if
+    /// there is one, with the number of duplicates dropped before it.
+    pub fn flush(&self) -> Option<Deduped<T>> {
+        let mut run = self.run.lock().unwrap();
+        match run.started {
+            Some(started) if started.elapsed() >= self.interval => Self::take(&mut run),
+            _ => None,
+        }
+    }
+
+    /// Last duplicate of the current run, if there is one, with the number of
+    /// duplicates dropped before it. Used when there are no more events.
+    pub fn finish(&self) -> Option<Deduped<T>> {
+        Self::take(&mut self.run.lock().unwrap())
+    }
+
+    fn take(run: &mut Run<T>) -> Option<Deduped<T>> {
+        let item = run.held.take()?;
+        let repeats = run.dropped - 1;
+        run.dropped = 0;
+        run.started = None;
+        Some(Deduped { item, repeats })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dedup, Deduped};
+    use crate::types::{LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// Push items with the given keys, returning what's written as "item:repeats".
+    fn run(dedup: &Dedup<usize>, keys: &[u64]) -> Vec<String> {
+        let mut out: Vec<String> = keys
+            .iter()
+            .enumerate()
+            .filter_map(|(i, k)| dedup.push(*k, i))
+            .map(|d| format!("{}:{}", d.item, d.repeats))
+            .collect();
+
+        out.extend(dedup.finish().map(|d| format!("{}:{}", d.item, d.repeats)));
+        out
+    }
+
+    #[test]
+    fn test_push_collapses_runs() {
+        let dedup = Dedup::new(None);
+        assert_eq!(vec!["0:0", "3:2", "4:0", "5:0"], run(&dedup, &[1, 1, 1, 2, 3, 1]));
+    }
+
+    #[test]
+    fn test_push_interleaved_not_collapsed() {
+        let dedup = Dedup::new(None);
+        assert_eq!(
+            vec!["0:0", "1:0", "2:0", "3:0", "4:0", "5:0"],
+            run(&dedup, &[1, 2, 1, 2, 1, 2])
+        );
+
+        let dedup = Dedup::new(None);
+        assert_eq!(vec!["0:0", "1:0", "3:1", "4:0"], run(&dedup, &[1, 2, 2, 1, 2]));
+    }
+
+    #[test]
+    fn test_finish_writes_last_duplicate() {
+        let dedup = Dedup::new(None);
+        assert_eq!(vec!["0:0", "1:0", "4:2"], run(&dedup, &[1, 2, 2, 2, 2]));
+
+        // Counts start again after the last duplicate is written
+        assert_eq!(None, dedup.push(2, 5));
+        assert_eq!(Some(Deduped { item: 6, repeats: 1 }), dedup.push(3, 6));
+        assert_eq!(None, dedup.finish());
+    }
+
+    #[test]
+    fn test_flush_after_interval() {
+        let dedup = Dedup::new(None).flush_interval(Duration::from_millis(50));
+        assert!(dedup.push(1, 0).is_some());
+        assert_eq!(None, dedup.flush());
+
+        assert_eq!(None, dedup.push(1, 1));
+        assert_eq!(None, dedup.push(1, 2));
+        assert_eq!(None, dedup.flush());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(Some(Deduped { item: 2, repeats: 1 }), dedup.flush());
+        assert_eq!(None, dedup.flush());
+
+        // The run continues after being flushed
+        assert_eq!(None, dedup.push(1, 3));
+        assert_eq!(Some(Deduped { item: 4, repeats: 1 }), dedup.push(2, 4));
+    }
+
+    #[test]
+    fn test_key_fields() {
+        let event = |uri: &str, status: u64| {
+            let mut fields = HashMap::new();
+            fields.insert("requested_uri".to_owned(), LogFieldValue::Text(uri.to_owned()));
+            fields.insert("status_code".to_owned(), LogFieldValue::Int(status));
+            LogEvent::from(fields)
+        };
+
+        let all: Dedup<()> = Dedup::new(None);
+        assert_ne!(all.key(&event("/health", 200)), all.key(&event("/health", 503)));
+
+        let uri: Dedup<()> = Dedup::new(Some(vec!["requested_uri".to_owned()]));
+        assert_eq!(uri.key(&event("/health", 200)), uri.key(&event("/health", 503)));
+        assert_ne!(uri.key(&event("/health", 200)), uri.key(&event("/", 200)));
+    }
+}
@@ -0,0 +1,294 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Functions for parsing lines or text in a single call
+
+use crate::format::LogFormat;
+use crate::parser::{parser_for, CustomLogLineParser, LogLineParser, NamedFormat};
+use crate::types::{LogEvent, RedeyeError, RedeyeResult};
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// Formats that the functions in this module can create a parser for.
+///
+/// Implemented for a `LogFormat`, a `NamedFormat`, and a `&str` that's either
+/// the name of a format in the default `ParserRegistry` or an Apache format
+/// string, the same as `parser_for`.
+pub trait IntoParser {
+    fn into_parser(self) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>>;
+}
+
+impl IntoParser for LogFormat {
+    fn into_parser(self) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+        Ok(Box::new(CustomLogLineParser::from_format(self)))
+    }
+}
+
+impl IntoParser for NamedFormat {
+    fn into_parser(self) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+        parser_for(self.name())
+    }
+}
+
+impl IntoParser for &str {
+    fn into_parser(self) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+        parser_for(self)
+    }
+}
+
+/// Iterator over the events parsed from each line of a reader.
+///
+/// Every line, including empty ones, results in either an event or the error
+/// from parsing it, except directives handled by the parser which are skipped.
+/// A trailing `\r` is removed from lines and bytes that aren't valid UTF-8 are
+/// replaced. The iterator ends after the first error reading from the reader.
+pub struct EventIter<R> {
+    reader: R,
+    parser: Box<dyn LogLineParser + Send + Sync>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R> EventIter<R>
+where
+    R: BufRead,
+{
+    pub fn new(reader: R, parser: Box<dyn LogLineParser + Send + Sync>) -> Self {
+        EventIter {
+            reader,
+            parser,
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+}
+
+impl<R> Iterator for EventIter<R>
+where
+    R: BufRead,
+{
+    type Item = RedeyeResult<LogEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            self.buf.clear();
+            loop {
+                match self.reader.read_until(b'\n', &mut self.buf) {
+                    Ok(0) => {
+                        self.done = true;
+                        return None;
+                    }
+                    Ok(_) => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        self.done = true;
+                        return Some(Err(RedeyeError::from(e)));
+                    }
+                }
+            }
+
+            let line = String::from_utf8_lossy(&self.buf);
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if !self.parser.directive(line) {
+                return Some(self.parser.parse(line));
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for EventIter<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventIter").field("done", &self.done).finish()
+    }
+}
+
+/// Parse a single line.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::easy::parse_line;
+/// use redeye::types::LogFieldValue;
+///
+/// let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+/// let event = parse_line("common", line).unwrap();
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.fields().get("status_code"));
+///
+/// assert!(parse_line("%h %>s", "127.0.0.1 404").is_ok());
+/// assert!(parse_line("common", "not a log line").is_err());
+/// ```
+pub fn parse_line<F>(format: F, line: &str) -> RedeyeResult<LogEvent>
+where
+    F: IntoParser,
+{
+    format.into_parser()?.parse(line)
+}
+
+/// Parse each line of some text, returning an error if a parser couldn't be
+/// created for the format.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::easy::parse_str;
+///
+/// let text = "127.0.0.1 200\nnot a log line\n127.0.0.1 404\n";
+/// let results: Vec<_> = parse_str("%h %>s", text).unwrap().collect();
+///
+/// assert_eq!(3, results.len());
+/// assert!(results[0].is_ok());
+/// assert!(results[1].is_err());
+/// assert!(results[2].is_ok());
+/// ```
+pub fn parse_str<F>(format: F, text: &str) -> RedeyeResult<EventIter<&[u8]>>
+where
+    F: IntoParser,
+{
+    parse_reader(format, text.as_bytes())
+}
+
+/// Parse each line read from a reader, returning an error if a parser couldn't
+/// be created for the format.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use redeye::easy::{parse_reader, to_json};
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// let file = BufReader::new(File::open("access.log").unwrap());
+/// for res in parse_reader("combined", file).unwrap() {
+///     match res {
+///         Ok(event) => println!("{}", to_json(&event).unwrap()),
+///         Err(e) => eprintln!("{}", e),
+///     }
+/// }
+/// ```
+pub fn parse_reader<F, R>(format: F, reader: R) -> RedeyeResult<EventIter<R>>
+where
+    F: IntoParser,
+    R: BufRead,
+{
+    Ok(EventIter::new(reader, format.into_parser()?))
+}
+
+/// Serialize an event as a JSON object.
+pub fn to_json(event: &LogEvent) -> RedeyeResult<String> {
+    Ok(serde_json::to_string(event)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_line, parse_reader, parse_str, to_json, EventIter};
+    use crate::format::LogFormat;
+    use crate::parser::{NamedFormat, W3cLogLineParser};
+    use crate::types::{LogFieldValue, RedeyeError};
+    use std::io::{self, BufRead, Read};
+
+    const COMMON: &str = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+
+    #[test]
+    fn test_parse_line_formats() {
+        assert!(parse_line("common", COMMON).is_ok());
+        assert!(parse_line(NamedFormat::Common, COMMON).is_ok());
+        assert!(parse_line(LogFormat::apache("%h %>s").unwrap(), "127.0.0.1 404").is_ok());
+        assert!(matches!(parse_line("yaml", COMMON), Err(RedeyeError::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_parse_str_interleaved_errors() {
+        let text = format!("{}\nnot a log line\r\n{}\r\n\n{}", COMMON, COMMON, COMMON);
+        let results: Vec<_> = parse_str("common", &text).unwrap().collect();
+
+        let ok: Vec<bool> = results.iter().map(|r| r.is_ok()).collect();
+        assert_eq!(vec![true, false, true, false, true], ok);
+        assert!(matches!(results[1], Err(RedeyeError::ParseError(ref l)) if l == "not a log line"));
+        assert!(matches!(results[3], Err(RedeyeError::ParseError(ref l)) if l.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_str_unknown_format() {
+        assert!(parse_str("yaml", COMMON).is_err());
+    }
+
+    #[test]
+    fn test_parse_reader_invalid_utf8() {
+        let input: &[u8] = b"127.0.0.1 \xff\n127.0.0.1 404\n";
+        let results: Vec<_> = parse_reader("%h %>s", input).unwrap().collect();
+
+        assert_eq!(2, results.len());
+        assert!(results[0].is_err());
+        let event = results[1].as_ref().unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(404)), event.fields().get("status_code"));
+    }
+
+    struct Failing;
+
+    impl Read for Failing {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    impl BufRead for Failing {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            Err(io::Error::other("broken"))
+        }
+
+        fn consume(&mut self, _amt: usize) {}
+    }
+
+    #[test]
+    fn test_parse_reader_io_error() {
+        let mut events = parse_reader("common", Failing).unwrap();
+
+        assert!(matches!(events.next(), Some(Err(RedeyeError::IoError(_)))));
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn test_event_iter_directives() {
+        let input = "#Version: 1.0\n#Fields: c-ip sc-status\n127.0.0.1 200\n#Fields: sc-status c-ip\n404 127.0.0.2\n";
+        let events: Vec<_> = EventIter::new(input.as_bytes(), Box::new(W3cLogLineParser::new()))
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(2, events.len());
+        assert_eq!(Some(&LogFieldValue::Int(200)), events[0].lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(404)), events[1].lookup("status_code"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("127.0.0.2".to_owned())),
+            events[1].lookup("remote_host")
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let event = parse_line("%h %>s", "127.0.0.1 404").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&to_json(&event).unwrap()).unwrap();
+
+        assert_eq!(404, json["status_code"]);
+        assert_eq!("127.0.0.1", json["remote_host"]);
+    }
+}
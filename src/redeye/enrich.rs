@@ -0,0 +1,1408 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Enrichment of parsed log events with derived fields
+
+use crate::types::{DurationUnit, LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
+use regex::{RegexSet, RegexSetBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Maximum number of distinct user agents to cache bot detection results for.
+const BOT_CACHE_SIZE: usize = 10_000;
+
+/// Crawlers and HTTP clients detected by default, as pairs of bot name and
+/// (case-insensitive) pattern. Earlier entries take precedence over later ones.
+const DEFAULT_BOT_PATTERNS: &[(&str, &str)] = &[
+    ("Googlebot", r"googlebot"),
+    ("bingbot", r"bingbot"),
+    ("Baiduspider", r"baiduspider"),
+    ("YandexBot", r"yandex(bot|images)"),
+    ("DuckDuckBot", r"duckduckbot"),
+    ("Yahoo! Slurp", r"slurp"),
+    ("AhrefsBot", r"ahrefsbot"),
+    ("SemrushBot", r"semrushbot"),
+    ("facebookexternalhit", r"facebookexternalhit"),
+    ("Twitterbot", r"twitterbot"),
+    ("HeadlessChrome", r"headlesschrome"),
+    ("PhantomJS", r"phantomjs"),
+    ("curl", r"^curl/"),
+    ("Wget", r"^wget/"),
+    ("python-requests", r"python-requests"),
+    ("Python-urllib", r"python-urllib"),
+    ("Go-http-client", r"go-http-client"),
+    ("Apache-HttpClient", r"apache-httpclient"),
+    ("libwww-perl", r"libwww-perl"),
+    ("generic", r"bot\b|crawler|spider"),
+];
+
+/// Requested paths that are only ever fetched by bots.
+const BOT_PATHS: &[&str] = &["/robots.txt"];
+
+/// Add derived fields to a parsed `LogEvent`.
+///
+/// Enrichers are applied to each event after it has been successfully parsed
+/// and before it is serialized. Implementations must be cheap enough to run for
+/// every log line and should never fail: if the fields they depend on are not
+/// present, they should leave the event unchanged.
+pub trait Enricher {
+    /// Add any derived fields to the given event.
+    fn enrich(&self, event: &mut LogEvent);
+}
+
+/// A named pattern used to identify a bot by its user agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BotPattern {
+    pub name: String,
+    pub pattern: String,
+}
+
+impl BotPattern {
+    pub fn new<N, P>(name: N, pattern: P) -> Self
+    where
+        N: Into<String>,
+        P: Into<String>,
+    {
+        BotPattern {
+            name: name.into(),
+            pattern: pattern.into(),
+        }
+    }
+
+    /// Parse bot patterns from the contents of a pattern file.
+    ///
+    /// Each non-blank line that doesn't start with `#` is a pattern. A line may
+    /// optionally start with a bot name followed by a tab, e.g. `Monitor\tpingdom`.
+    /// Otherwise, the pattern itself is used as the bot name.
+    pub fn parse_all(contents: &str) -> Vec<BotPattern> {
+        contents
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| match l.find('\t') {
+                Some(i) => BotPattern::new(l[..i].trim(), l[i + 1..].trim()),
+                None => BotPattern::new(l, l),
+            })
+            .collect()
+    }
+
+    /// Read and parse bot patterns from the given file.
+    pub fn load_all<P>(path: P) -> RedeyeResult<Vec<BotPattern>>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::parse_all(&fs::read_to_string(path)?))
+    }
+}
+
+/// Implementation of an `Enricher` that flags requests made by crawlers, bots,
+/// and scripted HTTP clients.
+///
+/// The `request_headers.user_agent` field of each event is matched against a
+/// built-in list of patterns (case-insensitively) which may be extended with
+/// additional patterns. Matching events get the fields `bot: true` and `bot_name`,
+/// all others get `bot: false`. Results are cached per user agent.
+///
+/// Events without a user agent are considered bots by default since nearly all
+/// browsers send one.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{BotDetectionEnricher, Enricher};
+/// use redeye::parser::{LogLineParser, CombinedLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CombinedLogLineParser::new();
+/// let enricher = BotDetectionEnricher::new();
+/// let mut event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 \"-\" \"curl/7.64.1\"").unwrap();
+/// enricher.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Bool(true)), event.lookup("bot"));
+/// assert_eq!(Some(&LogFieldValue::Text("curl".to_string())), event.lookup("bot_name"));
+/// ```
+#[derive(Debug)]
+pub struct BotDetectionEnricher {
+    names: Vec<String>,
+    patterns: RegexSet,
+    missing_is_bot: bool,
+    check_paths: bool,
+    cache: Mutex<HashMap<String, Option<usize>>>,
+}
+
+impl BotDetectionEnricher {
+    pub fn new() -> Self {
+        // Unwrap is OK here since the default patterns are known to be valid.
+        Self::with_patterns(&[]).unwrap()
+    }
+
+    /// Create a new enricher using the default patterns followed by the given
+    /// additional patterns. Return an error if any pattern is not a valid regex.
+    pub fn with_patterns(extra: &[BotPattern]) -> RedeyeResult<Self> {
+        let mut names = Vec::with_capacity(DEFAULT_BOT_PATTERNS.len() + extra.len());
+        let mut patterns = Vec::with_capacity(DEFAULT_BOT_PATTERNS.len() + extra.len());
+
+        for (name, pattern) in DEFAULT_BOT_PATTERNS {
+            names.push((*name).to_owned());
+            patterns.push((*pattern).to_owned());
+        }
+
+        for p in extra {
+            names.push(p.name.clone());
+            patterns.push(p.pattern.clone());
+        }
+
+        let patterns = RegexSetBuilder::new(&patterns)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| RedeyeError::ConfigError(format!("invalid bot pattern: {}", e)))?;
+
+        Ok(BotDetectionEnricher {
+            names,
+            patterns,
+            missing_is_bot: true,
+            check_paths: false,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Set whether events without a user agent should be flagged as bots.
+    pub fn missing_user_agent_is_bot(mut self, val: bool) -> Self {
+        self.missing_is_bot = val;
+        self
+    }
+
+    /// Set whether requests for paths only fetched by bots (e.g. `/robots.txt`)
+    /// should be flagged as bots regardless of their user agent.
+    pub fn check_paths(mut self, val: bool) -> Self {
+        self.check_paths = val;
+        self
+    }
+
+    /// Return the index of the first pattern matching the user agent, if any.
+    fn match_user_agent(&self, user_agent: &str) -> Option<usize> {
+        // Lock poisoning only happens if another thread panicked while holding
+        // the lock, in which case we just bypass the cache.
+        if let Ok(cache) = self.cache.lock() {
+            if let Some(res) = cache.get(user_agent) {
+                return *res;
+            }
+        }
+
+        let res = self.patterns.matches(user_agent).into_iter().next();
+
+        if let Ok(mut cache) = self.cache.lock() {
+            if cache.len() >= BOT_CACHE_SIZE {
+                cache.clear();
+            }
+            cache.insert(user_agent.to_owned(), res);
+        }
+
+        res
+    }
+
+    fn is_bot_path(event: &LogEvent) -> bool {
+        match event.lookup("requested_uri") {
+            Some(LogFieldValue::Text(uri)) => {
+                let path = uri.split('?').next().unwrap_or("");
+                BOT_PATHS.contains(&path)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for BotDetectionEnricher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Enricher for BotDetectionEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let (bot, name) = match event.lookup("request_headers.user_agent") {
+            Some(LogFieldValue::Text(ua)) => match self.match_user_agent(ua) {
+                Some(i) => (true, Some(self.names[i].clone())),
+                None => (self.check_paths && Self::is_bot_path(event), None),
+            },
+            _ => (
+                self.missing_is_bot || (self.check_paths && Self::is_bot_path(event)),
+                None,
+            ),
+        };
+
+        event.insert("bot", LogFieldValue::Bool(bot));
+        if let Some(n) = name {
+            event.insert("bot_name", LogFieldValue::Text(n));
+        }
+    }
+}
+
+/// Implementation of an `Enricher` that parses the query string of the
+/// `requested_uri` field into a `query_params` mapping.
+///
+/// Parameter names and values are percent-decoded (with `+` treated as a
+/// space). If a parameter appears multiple times, only the first value is
+/// kept. Parameters without a value (e.g. `?debug`) map to an empty string.
+/// Events without a query string are left unchanged.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{Enricher, QueryStringEnricher};
+/// use redeye::parser::{LogLineParser, CommonLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CommonLogLineParser::new();
+/// let enricher = QueryStringEnricher::new();
+/// let mut event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /search?q=red%20eye HTTP/1.0\" 200 2326").unwrap();
+/// enricher.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Text("red eye".to_string())), event.lookup("query_params.q"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct QueryStringEnricher;
+
+impl QueryStringEnricher {
+    pub fn new() -> Self {
+        QueryStringEnricher
+    }
+}
+
+impl Enricher for QueryStringEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let params = match event.lookup("requested_uri") {
+            Some(LogFieldValue::Text(uri)) => match uri.find('?') {
+                Some(i) => parse_query_string(&uri[i + 1..]),
+                None => return,
+            },
+            _ => return,
+        };
+
+        if !params.is_empty() {
+            event.insert("query_params", LogFieldValue::Mapping(params));
+        }
+    }
+}
+
+/// Parse a query string (without the leading `?`) into a map of decoded values.
+fn parse_query_string(query: &str) -> HashMap<String, LogFieldValue> {
+    let mut out = HashMap::new();
+
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, val) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, ""),
+        };
+
+        let key = percent_decode(key, true);
+        if key.is_empty() {
+            continue;
+        }
+
+        out.entry(key)
+            .or_insert_with(|| LogFieldValue::Text(percent_decode(val, true)));
+    }
+
+    out
+}
+
+/// Decode `%XX` escape sequences in a string, optionally treating `+` as a space.
+///
+/// Invalid escape sequences are left as-is and decoded bytes that are not valid
+/// UTF-8 are replaced with the unicode replacement character.
+pub(crate) fn percent_decode(val: &str, plus_as_space: bool) -> String {
+    let bytes = val.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    out.push(hi << 4 | lo);
+                    i += 3;
+                }
+                _ => {
+                    out.push(b'%');
+                    i += 1;
+                }
+            },
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Convert a single ASCII hex digit into its value.
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Maximum length, in characters, of campaign values copied from query parameters.
+const CAMPAIGN_MAX_LENGTH: usize = 256;
+
+/// Query parameters copied into the `campaign` mapping, along with the key used.
+const CAMPAIGN_PARAMS: &[(&str, &str)] = &[
+    ("utm_source", "source"),
+    ("utm_medium", "medium"),
+    ("utm_campaign", "name"),
+    ("utm_term", "term"),
+    ("utm_content", "content"),
+];
+
+/// Click identifier query parameters whose presence is recorded in the
+/// `campaign` mapping, along with the key used.
+const CAMPAIGN_CLICK_IDS: &[(&str, &str)] = &[("gclid", "has_gclid"), ("fbclid", "has_fbclid")];
+
+/// Implementation of an `Enricher` that extracts marketing campaign attribution
+/// from the `query_params` mapping produced by the `QueryStringEnricher`.
+///
+/// The `utm_source`, `utm_medium`, `utm_campaign`, `utm_term`, and `utm_content`
+/// parameters are copied into a nested `campaign` mapping as `source`, `medium`,
+/// `name`, `term`, and `content`, along with `has_gclid` and `has_fbclid` flags
+/// indicating whether click identifiers were present. Since the values are
+/// controlled by whoever made the request, they are truncated to 256 characters.
+///
+/// Events without any `utm_*` parameters don't get a `campaign` mapping. When
+/// configured to strip parameters, campaign parameters are removed from
+/// `query_params` (and the mapping is removed entirely if nothing is left) to
+/// reduce the number of distinct values downstream.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{CampaignEnricher, Enricher, QueryStringEnricher};
+/// use redeye::parser::{LogLineParser, CommonLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CommonLogLineParser::new();
+/// let query = QueryStringEnricher::new();
+/// let campaign = CampaignEnricher::new();
+/// let mut event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /?utm_source=news&utm_medium=email HTTP/1.0\" 200 2326").unwrap();
+/// query.enrich(&mut event);
+/// campaign.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Text("news".to_string())), event.lookup("campaign.source"));
+/// assert_eq!(Some(&LogFieldValue::Bool(false)), event.lookup("campaign.has_gclid"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CampaignEnricher {
+    strip_params: bool,
+}
+
+impl CampaignEnricher {
+    pub fn new() -> Self {
+        CampaignEnricher { strip_params: false }
+    }
+
+    /// Set whether campaign parameters should be removed from `query_params`
+    /// after being copied into the `campaign` mapping.
+    pub fn strip_params(mut self, val: bool) -> Self {
+        self.strip_params = val;
+        self
+    }
+}
+
+impl Enricher for CampaignEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let params = match event.fields_mut().get_mut("query_params") {
+            Some(LogFieldValue::Mapping(map)) => map,
+            _ => return,
+        };
+
+        let mut campaign = HashMap::new();
+        for (param, key) in CAMPAIGN_PARAMS {
+            if let Some(LogFieldValue::Text(v)) = params.get(*param) {
+                let val: String = v.chars().take(CAMPAIGN_MAX_LENGTH).collect();
+                campaign.insert((*key).to_owned(), LogFieldValue::Text(val));
+            }
+        }
+
+        if campaign.is_empty() {
+            return;
+        }
+
+        for (param, key) in CAMPAIGN_CLICK_IDS {
+            campaign.insert((*key).to_owned(), LogFieldValue::Bool(params.contains_key(*param)));
+        }
+
+        if self.strip_params {
+            for (param, _) in CAMPAIGN_PARAMS.iter().chain(CAMPAIGN_CLICK_IDS.iter()) {
+                params.remove(*param);
+            }
+
+            if params.is_empty() {
+                event.fields_mut().remove("query_params");
+            }
+        }
+
+        event.insert("campaign", LogFieldValue::Mapping(campaign));
+    }
+}
+
+/// Implementation of an `Enricher` that derives a numeric `http_version` field
+/// from the `protocol` field, e.g. `1.1` for `HTTP/1.1`.
+///
+/// The `protocol` field is left untouched. Events with a missing or non-HTTP
+/// protocol (some proxies log `-` or garbage there) don't get the field.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{Enricher, HttpVersionEnricher};
+/// use redeye::parser::{LogLineParser, CommonLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CommonLogLineParser::new();
+/// let enricher = HttpVersionEnricher::new();
+/// let mut event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326").unwrap();
+/// enricher.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Float(1.1)), event.lookup("http_version"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HttpVersionEnricher;
+
+impl HttpVersionEnricher {
+    pub fn new() -> Self {
+        HttpVersionEnricher
+    }
+}
+
+impl Enricher for HttpVersionEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let version = match event.lookup("protocol") {
+            Some(LogFieldValue::Text(p)) => parse_http_version(p),
+            _ => None,
+        };
+
+        if let Some(v) = version {
+            event.insert("http_version", LogFieldValue::Float(v));
+        }
+    }
+}
+
+/// Parse the version out of a protocol token like `HTTP/1.1` or `HTTP/2`.
+fn parse_http_version(protocol: &str) -> Option<f64> {
+    let prefix = protocol.get(..5)?;
+    if !prefix.eq_ignore_ascii_case("HTTP/") {
+        return None;
+    }
+
+    let version = &protocol[5..];
+    let mut parts = version.splitn(2, '.');
+    let major = parts.next().filter(|p| is_digits(p))?;
+    let minor = match parts.next() {
+        Some(p) if is_digits(p) => p,
+        Some(_) => return None,
+        None => "0",
+    };
+
+    format!("{}.{}", major, minor).parse().ok()
+}
+
+fn is_digits(val: &str) -> bool {
+    !val.is_empty() && val.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Implementation of an `Enricher` that computes a normalized form of the path
+/// of the `requested_uri` field as `uri_normalized`.
+///
+/// Normalization collapses duplicate slashes, resolves `.` and `..` segments
+/// (never going above the root), and lowercases the hex digits of percent-encoded
+/// characters. The query string is not included. Events where normalization
+/// removed `..` segments, or where decoding a segment revealed a hidden dot
+/// segment (e.g. `%2e%2e` or `..%2f`), are flagged with `path_traversal_attempt: true`.
+///
+/// The original `requested_uri` field is left untouched.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{Enricher, PathNormalizerEnricher};
+/// use redeye::parser::{LogLineParser, CommonLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CommonLogLineParser::new();
+/// let enricher = PathNormalizerEnricher::new();
+/// let mut event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /a//b/../c/./d HTTP/1.0\" 200 2326").unwrap();
+/// enricher.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Text("/a/c/d".to_string())), event.lookup("uri_normalized"));
+/// assert_eq!(Some(&LogFieldValue::Bool(true)), event.lookup("path_traversal_attempt"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PathNormalizerEnricher;
+
+impl PathNormalizerEnricher {
+    pub fn new() -> Self {
+        PathNormalizerEnricher
+    }
+}
+
+impl Enricher for PathNormalizerEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let (normalized, traversal) = match event.lookup("requested_uri") {
+            Some(LogFieldValue::Text(uri)) => normalize_path(uri.split('?').next().unwrap_or("")),
+            _ => return,
+        };
+
+        event.insert("uri_normalized", LogFieldValue::Text(normalized));
+        if traversal {
+            event.insert("path_traversal_attempt", LogFieldValue::Bool(true));
+        }
+    }
+}
+
+/// Normalize a URI path, returning the normalized path and whether or not the
+/// path appeared to be a directory traversal attempt.
+fn normalize_path(path: &str) -> (String, bool) {
+    let mut segments: Vec<String> = Vec::new();
+    let mut traversal = false;
+    let trailing_slash = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+
+    for raw in path.split('/').filter(|s| !s.is_empty()) {
+        let segment = lowercase_percent_encoding(raw);
+        let decoded = percent_decode(&segment, false);
+
+        if decoded != segment && is_hidden_dot_segment(&decoded) {
+            traversal = true;
+        }
+
+        match decoded.as_str() {
+            "." => {}
+            ".." => {
+                traversal = true;
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut out = String::with_capacity(path.len());
+    for s in segments.iter() {
+        out.push('/');
+        out.push_str(s);
+    }
+
+    if out.is_empty() || trailing_slash {
+        out.push('/');
+    }
+
+    (out, traversal)
+}
+
+/// Return true if the decoded segment is or contains a dot segment that was
+/// hidden by percent-encoding the dots or the separators around them.
+fn is_hidden_dot_segment(decoded: &str) -> bool {
+    decoded.split(['/', '\\']).any(|p| p == "." || p == "..")
+}
+
+/// Lowercase the hex digits of all percent-encoded characters in a string.
+fn lowercase_percent_encoding(val: &str) -> String {
+    let mut out = String::with_capacity(val.len());
+    let mut chars = val.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        out.push(c);
+        if c == '%' {
+            for _ in 0..2 {
+                match chars.peek() {
+                    Some(h) if h.is_ascii_hexdigit() => {
+                        out.push(h.to_ascii_lowercase());
+                        chars.next();
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Implementation of an `Enricher` that derives a `bytes_per_second` field from
+/// the `content_length` field and a request duration field.
+///
+/// Since different log formats record the duration of a request under different
+/// names and in different units, both are configurable. The value is rounded to
+/// three decimal places. Events missing either field, or with a duration of zero,
+/// don't get the field.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{Enricher, ThroughputEnricher};
+/// use redeye::types::{DurationUnit, LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let mut fields = HashMap::new();
+/// fields.insert("content_length".to_string(), LogFieldValue::Int(2000));
+/// fields.insert("duration_ms".to_string(), LogFieldValue::Int(500));
+///
+/// let mut event = LogEvent::from(fields);
+/// let enricher = ThroughputEnricher::new("duration_ms", DurationUnit::Milliseconds);
+/// enricher.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Float(4000.0)), event.lookup("bytes_per_second"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ThroughputEnricher {
+    duration_field: String,
+    unit: DurationUnit,
+}
+
+impl ThroughputEnricher {
+    pub fn new<S>(duration_field: S, unit: DurationUnit) -> Self
+    where
+        S: Into<String>,
+    {
+        ThroughputEnricher {
+            duration_field: duration_field.into(),
+            unit,
+        }
+    }
+}
+
+impl Enricher for ThroughputEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let bytes = match event.lookup("content_length") {
+            Some(LogFieldValue::Int(v)) => *v as f64,
+            _ => return,
+        };
+
+        let duration = match event.lookup(&self.duration_field) {
+            Some(LogFieldValue::Int(v)) => self.unit.to_seconds(*v as f64),
+            Some(LogFieldValue::Float(v)) => self.unit.to_seconds(*v),
+            _ => return,
+        };
+
+        // Negative, zero, infinite, or NaN durations can't produce a meaningful
+        // rate and would otherwise end up as nonsense (or null) in the output.
+        if !duration.is_finite() || duration <= 0.0 {
+            return;
+        }
+
+        let rate = (bytes / duration * 1000.0).round() / 1000.0;
+        if rate.is_finite() {
+            event.insert("bytes_per_second", LogFieldValue::Float(rate));
+        }
+    }
+}
+
+/// Syslog-style severity levels, from RFC 5424.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Emergency,
+    Alert,
+    Critical,
+    Error,
+    Warning,
+    Notice,
+    Info,
+    Debug,
+}
+
+impl Severity {
+    /// Numeric syslog code of the severity.
+    pub fn code(self) -> u64 {
+        match self {
+            Severity::Emergency => 0,
+            Severity::Alert => 1,
+            Severity::Critical => 2,
+            Severity::Error => 3,
+            Severity::Warning => 4,
+            Severity::Notice => 5,
+            Severity::Info => 6,
+            Severity::Debug => 7,
+        }
+    }
+
+    /// Lowercase name of the severity.
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Emergency => "emergency",
+            Severity::Alert => "alert",
+            Severity::Critical => "critical",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Notice => "notice",
+            Severity::Info => "info",
+            Severity::Debug => "debug",
+        }
+    }
+
+    /// Default severity for a response with the given status code, or no status.
+    pub fn for_status(status: Option<u64>) -> Self {
+        match status {
+            Some(s) if s >= 500 => Severity::Error,
+            Some(s) if s >= 400 => Severity::Warning,
+            Some(_) => Severity::Info,
+            None => Severity::Notice,
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "emergency" | "emerg" => Ok(Severity::Emergency),
+            "alert" => Ok(Severity::Alert),
+            "critical" | "crit" => Ok(Severity::Critical),
+            "error" | "err" => Ok(Severity::Error),
+            "warning" | "warn" => Ok(Severity::Warning),
+            "notice" => Ok(Severity::Notice),
+            "info" | "informational" => Ok(Severity::Info),
+            "debug" => Ok(Severity::Debug),
+            _ => Err(RedeyeError::ConfigError(format!("unknown severity '{}'", s))),
+        }
+    }
+}
+
+/// Implementation of an `Enricher` that maps the `status_code` field to a
+/// syslog-style severity as the `severity` (name) and `severity_code` (number)
+/// fields.
+///
+/// By default, 1xx, 2xx, and 3xx responses are `info` (6), 4xx responses are
+/// `warning` (4), 5xx responses are `error` (3), and events without a status
+/// are `notice` (5). The severity of individual status codes can be overridden.
+/// Events that already have a `severity` field are left unchanged.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::enrich::{Enricher, SeverityEnricher};
+/// use redeye::parser::{LogLineParser, CommonLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CommonLogLineParser::new();
+/// let overrides = SeverityEnricher::parse_overrides("404=notice").unwrap();
+/// let enricher = SeverityEnricher::with_overrides(overrides);
+/// let mut event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 404 2326").unwrap();
+/// enricher.enrich(&mut event);
+///
+/// assert_eq!(Some(&LogFieldValue::Text("notice".to_string())), event.lookup("severity"));
+/// assert_eq!(Some(&LogFieldValue::Int(5)), event.lookup("severity_code"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SeverityEnricher {
+    overrides: HashMap<u64, Severity>,
+}
+
+impl SeverityEnricher {
+    pub fn new() -> Self {
+        Self::with_overrides(HashMap::new())
+    }
+
+    /// Create a new enricher using the given severity for specific status codes
+    /// and the default severities for all others.
+    pub fn with_overrides(overrides: HashMap<u64, Severity>) -> Self {
+        SeverityEnricher { overrides }
+    }
+
+    /// Parse severity overrides in the form `404=notice,499=warning`.
+    ///
+    /// Return an error if any status code is not a number between 100 and 599
+    /// or any severity is not a known syslog severity name.
+    pub fn parse_overrides(spec: &str) -> RedeyeResult<HashMap<u64, Severity>> {
+        let mut out = HashMap::new();
+
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let (status, severity) = match entry.find('=') {
+                Some(i) => (entry[..i].trim(), entry[i + 1..].trim()),
+                None => {
+                    return Err(RedeyeError::ConfigError(format!(
+                        "invalid severity mapping '{}', expected STATUS=SEVERITY",
+                        entry
+                    )));
+                }
+            };
+
+            let code = status
+                .parse::<u64>()
+                .map_err(|_| RedeyeError::ConfigError(format!("invalid status code '{}'", status)))?;
+
+            if !(100..=599).contains(&code) {
+                return Err(RedeyeError::ConfigError(format!("unknown status code {}", code)));
+            }
+
+            out.insert(code, severity.parse()?);
+        }
+
+        Ok(out)
+    }
+}
+
+impl Enricher for SeverityEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        if event.lookup("severity").is_some() {
+            return;
+        }
+
+        let status = match event.lookup("status_code") {
+            Some(LogFieldValue::Int(v)) => Some(*v),
+            _ => None,
+        };
+
+        let severity = status
+            .and_then(|s| self.overrides.get(&s).copied())
+            .unwrap_or_else(|| Severity::for_status(status));
+
+        event.insert("severity", LogFieldValue::Text(severity.name().to_owned()));
+        event.insert("severity_code", LogFieldValue::Int(severity.code()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        normalize_path, parse_http_version, percent_decode, BotDetectionEnricher, BotPattern, CampaignEnricher,
+        Enricher, HttpVersionEnricher, PathNormalizerEnricher, QueryStringEnricher, Severity, SeverityEnricher,
+        ThroughputEnricher,
+    };
+    use crate::types::{DurationUnit, LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+
+    fn event(user_agent: Option<&str>, uri: &str) -> LogEvent {
+        let mut fields = HashMap::new();
+        fields.insert("requested_uri".to_owned(), LogFieldValue::Text(uri.to_owned()));
+
+        if let Some(ua) = user_agent {
+            let mut headers = HashMap::new();
+            headers.insert("user_agent".to_owned(), LogFieldValue::Text(ua.to_owned()));
+            fields.insert("request_headers".to_owned(), LogFieldValue::Mapping(headers));
+        }
+
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_bot_pattern_parse_all() {
+        let patterns = BotPattern::parse_all("# comment\n\nMonitor\tpingdom\n  uptimerobot  \n");
+        assert_eq!(
+            vec![
+                BotPattern::new("Monitor", "pingdom"),
+                BotPattern::new("uptimerobot", "uptimerobot"),
+            ],
+            patterns
+        );
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_known_bot() {
+        let enricher = BotDetectionEnricher::new();
+        let mut e = event(
+            Some("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"),
+            "/",
+        );
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Bool(true)), e.lookup("bot"));
+        assert_eq!(Some(&LogFieldValue::Text("Googlebot".to_owned())), e.lookup("bot_name"));
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_case_insensitive() {
+        let enricher = BotDetectionEnricher::new();
+        let mut e = event(Some("PYTHON-REQUESTS/2.25.1"), "/");
+        enricher.enrich(&mut e);
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("python-requests".to_owned())),
+            e.lookup("bot_name")
+        );
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_browser() {
+        let enricher = BotDetectionEnricher::new();
+        let mut e = event(
+            Some("Mozilla/5.0 (X11; Linux x86_64; rv:89.0) Gecko/20100101 Firefox/89.0"),
+            "/",
+        );
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Bool(false)), e.lookup("bot"));
+        assert_eq!(None, e.lookup("bot_name"));
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_cached_result() {
+        let enricher = BotDetectionEnricher::new();
+        let mut first = event(Some("Wget/1.20.3 (linux-gnu)"), "/");
+        let mut second = event(Some("Wget/1.20.3 (linux-gnu)"), "/");
+        enricher.enrich(&mut first);
+        enricher.enrich(&mut second);
+
+        assert_eq!(1, enricher.cache.lock().unwrap().len());
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_missing_user_agent() {
+        let enricher = BotDetectionEnricher::new();
+        let mut e = event(None, "/");
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Bool(true)), e.lookup("bot"));
+        assert_eq!(None, e.lookup("bot_name"));
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_missing_user_agent_not_bot() {
+        let enricher = BotDetectionEnricher::new().missing_user_agent_is_bot(false);
+        let mut e = event(None, "/");
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Bool(false)), e.lookup("bot"));
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_robots_path() {
+        let enricher = BotDetectionEnricher::new().check_paths(true);
+        let mut e = event(Some("Mozilla/5.0 (Windows NT 10.0; Win64; x64)"), "/robots.txt");
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Bool(true)), e.lookup("bot"));
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_extra_patterns() {
+        let extra = vec![BotPattern::new("Monitor", "^internal-monitor/")];
+        let enricher = BotDetectionEnricher::with_patterns(&extra).unwrap();
+        let mut e = event(Some("Internal-Monitor/1.0"), "/health");
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Text("Monitor".to_owned())), e.lookup("bot_name"));
+    }
+
+    #[test]
+    fn test_bot_detection_enricher_invalid_pattern() {
+        let extra = vec![BotPattern::new("Broken", "(unclosed")];
+        let res = BotDetectionEnricher::with_patterns(&extra);
+
+        match res {
+            Err(e) if e.is_config_error() => (),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    fn uri_event(uri: &str) -> LogEvent {
+        let mut fields = HashMap::new();
+        fields.insert("requested_uri".to_owned(), LogFieldValue::Text(uri.to_owned()));
+        LogEvent::from(fields)
+    }
+
+    fn enrich_campaign(uri: &str, strip: bool) -> LogEvent {
+        let mut e = uri_event(uri);
+        QueryStringEnricher::new().enrich(&mut e);
+        CampaignEnricher::new().strip_params(strip).enrich(&mut e);
+        e
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!("a b+c", percent_decode("a%20b+c", false));
+        assert_eq!("a b c", percent_decode("a%20b+c", true));
+        assert_eq!("café", percent_decode("caf%C3%A9", false));
+        assert_eq!("100%", percent_decode("100%", false));
+        assert_eq!("%zz%4", percent_decode("%zz%4", false));
+        assert_eq!("\u{FFFD}", percent_decode("%ff", false));
+    }
+
+    #[test]
+    fn test_query_string_enricher() {
+        let mut e = uri_event("/search?q=red+eye&page=2&debug&page=3&=x");
+        QueryStringEnricher::new().enrich(&mut e);
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("red eye".to_owned())),
+            e.lookup("query_params.q")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("2".to_owned())),
+            e.lookup("query_params.page")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("".to_owned())),
+            e.lookup("query_params.debug")
+        );
+        assert_eq!(None, e.lookup("query_params."));
+    }
+
+    #[test]
+    fn test_query_string_enricher_no_query() {
+        let mut no_query = uri_event("/index.html");
+        let mut empty_query = uri_event("/index.html?");
+        QueryStringEnricher::new().enrich(&mut no_query);
+        QueryStringEnricher::new().enrich(&mut empty_query);
+
+        assert_eq!(None, no_query.lookup("query_params"));
+        assert_eq!(None, empty_query.lookup("query_params"));
+    }
+
+    #[test]
+    fn test_campaign_enricher() {
+        let e = enrich_campaign(
+            "/?utm_source=news%20letter&utm_medium=email&utm_campaign=spring&utm_term=shoes&utm_content=a&gclid=abc",
+            false,
+        );
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("news letter".to_owned())),
+            e.lookup("campaign.source")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("email".to_owned())),
+            e.lookup("campaign.medium")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("spring".to_owned())),
+            e.lookup("campaign.name")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("shoes".to_owned())),
+            e.lookup("campaign.term")
+        );
+        assert_eq!(Some(&LogFieldValue::Text("a".to_owned())), e.lookup("campaign.content"));
+        assert_eq!(Some(&LogFieldValue::Bool(true)), e.lookup("campaign.has_gclid"));
+        assert_eq!(Some(&LogFieldValue::Bool(false)), e.lookup("campaign.has_fbclid"));
+        assert!(e.lookup("query_params.utm_source").is_some());
+    }
+
+    #[test]
+    fn test_campaign_enricher_length_capped() {
+        let long = "x".repeat(1000);
+        let e = enrich_campaign(&format!("/?utm_source={}", long), false);
+
+        match e.lookup("campaign.source") {
+            Some(LogFieldValue::Text(v)) => assert_eq!(256, v.len()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_campaign_enricher_no_utm_params() {
+        let no_query = enrich_campaign("/index.html", false);
+        let click_only = enrich_campaign("/index.html?gclid=abc&page=1", false);
+
+        assert_eq!(None, no_query.lookup("campaign"));
+        assert_eq!(None, click_only.lookup("campaign"));
+    }
+
+    #[test]
+    fn test_campaign_enricher_strip_params() {
+        let partial = enrich_campaign("/?utm_source=news&fbclid=xyz&page=1", true);
+        let all = enrich_campaign("/?utm_source=news&fbclid=xyz", true);
+
+        assert_eq!(None, partial.lookup("query_params.utm_source"));
+        assert_eq!(None, partial.lookup("query_params.fbclid"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("1".to_owned())),
+            partial.lookup("query_params.page")
+        );
+        assert_eq!(Some(&LogFieldValue::Bool(true)), partial.lookup("campaign.has_fbclid"));
+        assert_eq!(None, all.lookup("query_params"));
+        assert!(all.lookup("campaign").is_some());
+    }
+
+    fn protocol_event(protocol: Option<&str>) -> LogEvent {
+        let mut fields = HashMap::new();
+        if let Some(p) = protocol {
+            fields.insert("protocol".to_owned(), LogFieldValue::Text(p.to_owned()));
+        }
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_parse_http_version() {
+        assert_eq!(Some(1.0), parse_http_version("HTTP/1.0"));
+        assert_eq!(Some(1.1), parse_http_version("HTTP/1.1"));
+        assert_eq!(Some(2.0), parse_http_version("HTTP/2.0"));
+        assert_eq!(Some(2.0), parse_http_version("HTTP/2"));
+        assert_eq!(Some(3.0), parse_http_version("http/3"));
+        assert_eq!(None, parse_http_version("-"));
+        assert_eq!(None, parse_http_version("HTTP/"));
+        assert_eq!(None, parse_http_version("HTTP/1.x"));
+        assert_eq!(None, parse_http_version("HTTP/1.1.1"));
+        assert_eq!(None, parse_http_version("SPDY/3"));
+        assert_eq!(None, parse_http_version("HTTP/\u{e9}"));
+    }
+
+    #[test]
+    fn test_http_version_enricher() {
+        let mut e = protocol_event(Some("HTTP/2.0"));
+        HttpVersionEnricher::new().enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Float(2.0)), e.lookup("http_version"));
+        assert_eq!(Some(&LogFieldValue::Text("HTTP/2.0".to_owned())), e.lookup("protocol"));
+    }
+
+    #[test]
+    fn test_http_version_enricher_not_http() {
+        let mut garbage = protocol_event(Some("\\x16\\x03\\x01"));
+        let mut missing = protocol_event(None);
+        HttpVersionEnricher::new().enrich(&mut garbage);
+        HttpVersionEnricher::new().enrich(&mut missing);
+
+        assert_eq!(None, garbage.lookup("http_version"));
+        assert_eq!(None, missing.lookup("http_version"));
+    }
+
+    #[test]
+    fn test_normalize_path_benign() {
+        let cases = &[
+            ("/", "/"),
+            ("", "/"),
+            ("/index.html", "/index.html"),
+            ("/static/js/vendor/app.min.js", "/static/js/vendor/app.min.js"),
+            ("/a//b///c/", "/a/b/c/"),
+            ("/a/./b/.", "/a/b/"),
+            ("/files/report..final.pdf", "/files/report..final.pdf"),
+            ("/.well-known/acme-challenge/abc", "/.well-known/acme-challenge/abc"),
+            ("/search/%7Euser/%2Fetc", "/search/%7euser/%2fetc"),
+            ("/a/...", "/a/..."),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!((expected.to_string(), false), normalize_path(path), "path {}", path);
+        }
+    }
+
+    #[test]
+    fn test_normalize_path_traversal() {
+        let cases = &[
+            ("/a//b/../c/./d", "/a/c/d"),
+            ("/../../etc/passwd", "/etc/passwd"),
+            ("/a/%2e%2e/c", "/c"),
+            ("/a/%2E%2E/%2E%2E/%2E%2E/etc/passwd", "/etc/passwd"),
+            ("/a/.%2e/c", "/c"),
+            ("/cgi-bin/%2e/x", "/cgi-bin/x"),
+            ("/%2e%2e%2f%2e%2e%2fetc/passwd", "/%2e%2e%2f%2e%2e%2fetc/passwd"),
+            ("/..%5c..%5cwindows/win.ini", "/..%5c..%5cwindows/win.ini"),
+            ("/a/b/..", "/a/"),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!((expected.to_string(), true), normalize_path(path), "path {}", path);
+        }
+    }
+
+    #[test]
+    fn test_path_normalizer_enricher() {
+        let mut e = uri_event("/a/%2e%2e/admin?x=1");
+        PathNormalizerEnricher::new().enrich(&mut e);
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("/admin".to_owned())),
+            e.lookup("uri_normalized")
+        );
+        assert_eq!(Some(&LogFieldValue::Bool(true)), e.lookup("path_traversal_attempt"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("/a/%2e%2e/admin?x=1".to_owned())),
+            e.lookup("requested_uri")
+        );
+    }
+
+    #[test]
+    fn test_path_normalizer_enricher_benign() {
+        let mut e = uri_event("/blog//2021/post.html?page=2");
+        PathNormalizerEnricher::new().enrich(&mut e);
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("/blog/2021/post.html".to_owned())),
+            e.lookup("uri_normalized")
+        );
+        assert_eq!(None, e.lookup("path_traversal_attempt"));
+    }
+
+    fn throughput_event(bytes: Option<u64>, duration: Option<LogFieldValue>) -> LogEvent {
+        let mut fields = HashMap::new();
+        if let Some(b) = bytes {
+            fields.insert("content_length".to_owned(), LogFieldValue::Int(b));
+        }
+        if let Some(d) = duration {
+            fields.insert("request_time".to_owned(), d);
+        }
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_throughput_enricher_float_seconds() {
+        let enricher = ThroughputEnricher::new("request_time", DurationUnit::Seconds);
+        let mut e = throughput_event(Some(1000), Some(LogFieldValue::Float(0.3)));
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Float(3333.333)), e.lookup("bytes_per_second"));
+    }
+
+    #[test]
+    fn test_throughput_enricher_int_microseconds() {
+        let enricher = ThroughputEnricher::new("request_time", DurationUnit::Microseconds);
+        let mut e = throughput_event(Some(512), Some(LogFieldValue::Int(250_000)));
+        enricher.enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Float(2048.0)), e.lookup("bytes_per_second"));
+    }
+
+    #[test]
+    fn test_throughput_enricher_zero_duration() {
+        let enricher = ThroughputEnricher::new("request_time", DurationUnit::Seconds);
+        let mut int_zero = throughput_event(Some(1000), Some(LogFieldValue::Int(0)));
+        let mut float_zero = throughput_event(Some(1000), Some(LogFieldValue::Float(0.0)));
+        let mut nan = throughput_event(Some(1000), Some(LogFieldValue::Float(f64::NAN)));
+        enricher.enrich(&mut int_zero);
+        enricher.enrich(&mut float_zero);
+        enricher.enrich(&mut nan);
+
+        assert_eq!(None, int_zero.lookup("bytes_per_second"));
+        assert_eq!(None, float_zero.lookup("bytes_per_second"));
+        assert_eq!(None, nan.lookup("bytes_per_second"));
+    }
+
+    #[test]
+    fn test_throughput_enricher_missing_fields() {
+        let enricher = ThroughputEnricher::new("request_time", DurationUnit::Seconds);
+        let mut no_bytes = throughput_event(None, Some(LogFieldValue::Float(0.5)));
+        let mut no_duration = throughput_event(Some(1000), None);
+        let mut wrong_type = throughput_event(Some(1000), Some(LogFieldValue::Text("0.5".to_owned())));
+        enricher.enrich(&mut no_bytes);
+        enricher.enrich(&mut no_duration);
+        enricher.enrich(&mut wrong_type);
+
+        assert_eq!(None, no_bytes.lookup("bytes_per_second"));
+        assert_eq!(None, no_duration.lookup("bytes_per_second"));
+        assert_eq!(None, wrong_type.lookup("bytes_per_second"));
+    }
+
+    fn status_event(status: Option<u64>) -> LogEvent {
+        let mut fields = HashMap::new();
+        if let Some(s) = status {
+            fields.insert("status_code".to_owned(), LogFieldValue::Int(s));
+        }
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_severity_for_status() {
+        assert_eq!(Severity::Info, Severity::for_status(Some(101)));
+        assert_eq!(Severity::Info, Severity::for_status(Some(200)));
+        assert_eq!(Severity::Info, Severity::for_status(Some(304)));
+        assert_eq!(Severity::Warning, Severity::for_status(Some(404)));
+        assert_eq!(Severity::Error, Severity::for_status(Some(503)));
+        assert_eq!(Severity::Notice, Severity::for_status(None));
+    }
+
+    #[test]
+    fn test_severity_from_str() {
+        assert_eq!(Severity::Warning, "warning".parse().unwrap());
+        assert_eq!(Severity::Warning, "WARN".parse().unwrap());
+        assert_eq!(Severity::Critical, "crit".parse().unwrap());
+        assert!("loud".parse::<Severity>().unwrap_err().is_config_error());
+    }
+
+    #[test]
+    fn test_severity_enricher_parse_overrides() {
+        let overrides = SeverityEnricher::parse_overrides("404=notice, 499=warning,").unwrap();
+
+        assert_eq!(2, overrides.len());
+        assert_eq!(Some(&Severity::Notice), overrides.get(&404));
+        assert_eq!(Some(&Severity::Warning), overrides.get(&499));
+        assert!(SeverityEnricher::parse_overrides("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_severity_enricher_parse_overrides_errors() {
+        for spec in &[
+            "404",
+            "abc=notice",
+            "-1=notice",
+            "99=info",
+            "600=error",
+            "404=loud",
+            "=info",
+        ] {
+            match SeverityEnricher::parse_overrides(spec) {
+                Err(e) if e.is_config_error() => (),
+                v => panic!("Unexpected result for {}: {:?}", spec, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_severity_enricher_defaults() {
+        let enricher = SeverityEnricher::new();
+        let cases = &[
+            (Some(200), "info", 6),
+            (Some(301), "info", 6),
+            (Some(404), "warning", 4),
+            (Some(500), "error", 3),
+            (None, "notice", 5),
+        ];
+
+        for (status, name, code) in cases {
+            let mut e = status_event(*status);
+            enricher.enrich(&mut e);
+
+            assert_eq!(Some(&LogFieldValue::Text(name.to_string())), e.lookup("severity"));
+            assert_eq!(Some(&LogFieldValue::Int(*code)), e.lookup("severity_code"));
+        }
+    }
+
+    #[test]
+    fn test_severity_enricher_overrides() {
+        let overrides = SeverityEnricher::parse_overrides("404=notice").unwrap();
+        let enricher = SeverityEnricher::with_overrides(overrides);
+        let mut overridden = status_event(Some(404));
+        let mut default = status_event(Some(403));
+        enricher.enrich(&mut overridden);
+        enricher.enrich(&mut default);
+
+        assert_eq!(Some(&LogFieldValue::Int(5)), overridden.lookup("severity_code"));
+        assert_eq!(Some(&LogFieldValue::Int(4)), default.lookup("severity_code"));
+    }
+
+    #[test]
+    fn test_severity_enricher_existing_severity() {
+        let mut e = status_event(Some(500));
+        e.insert("severity", LogFieldValue::Text("debug".to_owned()));
+        SeverityEnricher::new().enrich(&mut e);
+
+        assert_eq!(Some(&LogFieldValue::Text("debug".to_owned())), e.lookup("severity"));
+        assert_eq!(None, e.lookup("severity_code"));
+    }
+}
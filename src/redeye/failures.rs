@@ -0,0 +1,373 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Aggregation of lines that couldn't be handled
+
+use crate::types::RedeyeError;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Default number of example lines kept for each distinct failure.
+const DEFAULT_EXAMPLES: usize = 3;
+
+/// Default number of distinct failures tracked before further ones are only counted.
+const DEFAULT_MAX_DISTINCT: usize = 1000;
+
+/// Maximum length in bytes of an example line, longer lines are truncated.
+const MAX_EXAMPLE_LEN: usize = 1024;
+
+/// Message used for lines that don't match the log format. The error for these
+/// is the line itself so it can't be used to group failures.
+const INVALID_LINE_MESSAGE: &str = "line does not match the log format";
+
+/// Kind of error that caused a line to fail, based on the kind of `RedeyeError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FailureReason {
+    InvalidLine,
+    InvalidTimestamp,
+    Io,
+    Serialization,
+    Config,
+    RecordTooLong,
+}
+
+impl FailureReason {
+    /// Reason for a failure caused by the given error.
+    pub fn of(err: &RedeyeError) -> Self {
+        match err {
+            RedeyeError::IoError(_) => FailureReason::Io,
+            RedeyeError::SerializationError(_) => FailureReason::Serialization,
+            RedeyeError::TimestampParseError(_) => FailureReason::InvalidTimestamp,
+            RedeyeError::ParseError(_) => FailureReason::InvalidLine,
+            RedeyeError::ConfigError(_) => FailureReason::Config,
+            RedeyeError::RecordTooLong(_) => FailureReason::RecordTooLong,
+        }
+    }
+
+    /// Short human readable name of the reason.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureReason::InvalidLine => "invalid log line",
+            FailureReason::InvalidTimestamp => "invalid timestamp",
+            FailureReason::Io => "i/o",
+            FailureReason::Serialization => "serialization",
+            FailureReason::Config => "config",
+            FailureReason::RecordTooLong => "record too long",
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for FailureReason {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Line that failed, along with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Example {
+    pub source: String,
+    pub line: u64,
+    pub text: String,
+}
+
+impl Serialize for Example {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("Example", 3)?;
+        s.serialize_field("source", &self.source)?;
+        s.serialize_field("line", &self.line)?;
+        s.serialize_field("text", &self.text)?;
+        s.end()
+    }
+}
+
+/// Distinct failure: the reason and message shared by one or more lines, the
+/// number of them, and examples of the first few.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub reason: FailureReason,
+    pub message: String,
+    pub count: u64,
+    pub examples: Vec<Example>,
+}
+
+impl Serialize for Failure {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("Failure", 4)?;
+        s.serialize_field("reason", &self.reason)?;
+        s.serialize_field("message", &self.message)?;
+        s.serialize_field("count", &self.count)?;
+        s.serialize_field("examples", &self.examples)?;
+        s.end()
+    }
+}
+
+/// Counts of lines that couldn't be handled, grouped by reason and by distinct
+/// error message, with example lines for each message.
+///
+/// Memory use is bounded: only a limited number of distinct messages are tracked
+/// (failures with other messages are still counted by reason) and example lines
+/// are truncated.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::failures::{FailureReason, Failures};
+/// use redeye::types::RedeyeError;
+///
+/// let mut failures = Failures::new().examples(1);
+/// failures.record(&RedeyeError::ParseError("foo".to_owned()), "access.log", 3, "foo");
+/// failures.record(&RedeyeError::ParseError("bar".to_owned()), "access.log", 7, "bar");
+///
+/// assert_eq!(2, failures.total());
+/// assert_eq!(vec![(FailureReason::InvalidLine, 2)], failures.by_reason());
+///
+/// let top = failures.top(10);
+/// assert_eq!(1, top.len());
+/// assert_eq!(2, top[0].count);
+/// assert_eq!("foo", top[0].examples[0].text);
+/// ```
+#[derive(Debug)]
+pub struct Failures {
+    max_examples: usize,
+    max_distinct: usize,
+    total: u64,
+    untracked: u64,
+    by_reason: HashMap<FailureReason, u64>,
+    distinct: HashMap<(FailureReason, String), Failure>,
+}
+
+impl Failures {
+    pub fn new() -> Self {
+        Failures {
+            max_examples: DEFAULT_EXAMPLES,
+            max_distinct: DEFAULT_MAX_DISTINCT,
+            total: 0,
+            untracked: 0,
+            by_reason: HashMap::new(),
+            distinct: HashMap::new(),
+        }
+    }
+
+    /// Set the number of example lines kept for each distinct failure.
+    pub fn examples(mut self, examples: usize) -> Self {
+        self.max_examples = examples;
+        self
+    }
+
+    /// Set the number of distinct failures tracked.
+    pub fn max_distinct(mut self, max: usize) -> Self {
+        self.max_distinct = max;
+        self
+    }
+
+    /// Record the error for a line of a source, along with the text of the line.
+    pub fn record(&mut self, err: &RedeyeError, source: &str, line: u64, text: &str) {
+        let reason = FailureReason::of(err);
+        let message = match err {
+            RedeyeError::ParseError(_) => INVALID_LINE_MESSAGE.to_owned(),
+            _ => err.to_string(),
+        };
+
+        self.total += 1;
+        *self.by_reason.entry(reason).or_insert(0) += 1;
+
+        let key = (reason, message);
+        if !self.distinct.contains_key(&key) && self.distinct.len() >= self.max_distinct {
+            self.untracked += 1;
+            return;
+        }
+
+        let max_examples = self.max_examples;
+        let failure = self
+            .distinct
+            .entry(key)
+            .or_insert_with_key(|(reason, message)| Failure {
+                reason: *reason,
+                message: message.clone(),
+                count: 0,
+                examples: Vec::new(),
+            });
+
+        failure.count += 1;
+        if failure.examples.len() < max_examples {
+            failure.examples.push(Example {
+                source: source.to_owned(),
+                line,
+                text: truncate(text, MAX_EXAMPLE_LEN).to_owned(),
+            });
+        }
+    }
+
+    /// Total number of failures recorded.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of failures that weren't tracked as distinct failures because too
+    /// many distinct ones had already been recorded.
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+
+    /// Number of failures for each reason, most common first.
+    pub fn by_reason(&self) -> Vec<(FailureReason, u64)> {
+        let mut reasons: Vec<(FailureReason, u64)> = self.by_reason.iter().map(|(r, c)| (*r, *c)).collect();
+        reasons.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        reasons
+    }
+
+    /// Up to `k` distinct failures, most common first.
+    pub fn top(&self, k: usize) -> Vec<&Failure> {
+        let mut failures: Vec<&Failure> = self.distinct.values().collect();
+        failures.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(a.reason.cmp(&b.reason))
+                .then(a.message.cmp(&b.message))
+        });
+        failures.truncate(k);
+        failures
+    }
+}
+
+impl Default for Failures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shorten a string to at most `max` bytes without splitting a character.
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{truncate, FailureReason, Failures};
+    use crate::types::RedeyeError;
+    use chrono::DateTime;
+    use std::io;
+
+    fn timestamp_error() -> RedeyeError {
+        DateTime::parse_from_str("yesterday", "%d/%b/%Y:%T %z")
+            .unwrap_err()
+            .into()
+    }
+
+    #[test]
+    fn test_failures_grouped_by_message() {
+        let mut failures = Failures::new();
+        for i in 0..5 {
+            failures.record(&RedeyeError::ParseError("x".to_owned()), "a.log", i, "x");
+        }
+
+        failures.record(&timestamp_error(), "a.log", 10, "bad ts");
+        failures.record(&timestamp_error(), "b.log", 1, "bad ts again");
+        failures.record(
+            &RedeyeError::IoError(io::Error::new(io::ErrorKind::InvalidData, "not utf-8")),
+            "b.log",
+            2,
+            "\u{fffd}",
+        );
+
+        assert_eq!(8, failures.total());
+        assert_eq!(
+            vec![
+                (FailureReason::InvalidLine, 5),
+                (FailureReason::InvalidTimestamp, 2),
+                (FailureReason::Io, 1),
+            ],
+            failures.by_reason()
+        );
+
+        let top = failures.top(2);
+        assert_eq!(2, top.len());
+        assert_eq!(FailureReason::InvalidLine, top[0].reason);
+        assert_eq!(5, top[0].count);
+        assert_eq!(3, top[0].examples.len());
+        assert_eq!(FailureReason::InvalidTimestamp, top[1].reason);
+        assert_eq!(2, top[1].count);
+        assert_eq!("b.log", top[1].examples[1].source);
+        assert_eq!(1, top[1].examples[1].line);
+    }
+
+    #[test]
+    fn test_failures_max_distinct() {
+        let mut failures = Failures::new().max_distinct(1);
+        failures.record(&RedeyeError::ConfigError("one".to_owned()), "-", 1, "a");
+        failures.record(&RedeyeError::ConfigError("two".to_owned()), "-", 2, "b");
+        failures.record(&RedeyeError::ConfigError("one".to_owned()), "-", 3, "c");
+
+        assert_eq!(3, failures.total());
+        assert_eq!(1, failures.untracked());
+        assert_eq!(vec![(FailureReason::Config, 3)], failures.by_reason());
+
+        let top = failures.top(10);
+        assert_eq!(1, top.len());
+        assert_eq!("one", top[0].message);
+        assert_eq!(2, top[0].count);
+    }
+
+    #[test]
+    fn test_failures_serialize() {
+        let mut failures = Failures::new().examples(1);
+        failures.record(&RedeyeError::ParseError("x".to_owned()), "a.log", 4, "x");
+        failures.record(&RedeyeError::ParseError("y".to_owned()), "a.log", 5, "y");
+
+        let json = serde_json::to_value(failures.top(1)).unwrap();
+        assert_eq!("invalid log line", json[0]["reason"]);
+        assert_eq!(2, json[0]["count"]);
+        assert_eq!(1, json[0]["examples"].as_array().unwrap().len());
+        assert_eq!(4, json[0]["examples"][0]["line"]);
+        assert_eq!("x", json[0]["examples"][0]["text"]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!("abc", truncate("abc", 10));
+        assert_eq!("ab", truncate("abc", 2));
+        assert_eq!("a", truncate("aé", 2));
+    }
+}
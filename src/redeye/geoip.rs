@@ -0,0 +1,453 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Enrichment of log events based on the IP address of the client using
+//! MaxMind databases
+
+use crate::enrich::Enricher;
+use crate::types::{LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
+use maxminddb::{geoip2, Reader};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Default maximum number of distinct addresses to cache lookup results for.
+const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// Autonomous system information for an IP address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsnInfo {
+    pub number: Option<u32>,
+    pub organization: Option<String>,
+}
+
+/// Geographic location information for an IP address.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoInfo {
+    pub country_code: Option<String>,
+    pub country_name: Option<String>,
+    pub city_name: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// Source of information about IP addresses, typically a MaxMind database.
+pub trait IpDatabase<T> {
+    /// Look up information for the address, returning `None` if the address
+    /// isn't in the database.
+    fn lookup(&self, ip: IpAddr) -> Option<T>;
+}
+
+/// Reader for a MaxMind ASN database (e.g. `GeoLite2-ASN.mmdb`).
+pub struct MaxMindAsnDatabase {
+    reader: Reader<Vec<u8>>,
+}
+
+impl MaxMindAsnDatabase {
+    pub fn open<P>(path: P) -> RedeyeResult<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(MaxMindAsnDatabase {
+            reader: open_database(path.as_ref())?,
+        })
+    }
+}
+
+impl IpDatabase<AsnInfo> for MaxMindAsnDatabase {
+    fn lookup(&self, ip: IpAddr) -> Option<AsnInfo> {
+        self.reader.lookup::<geoip2::Asn>(ip).ok().map(|r| AsnInfo {
+            number: r.autonomous_system_number,
+            organization: r.autonomous_system_organization.map(|s| s.to_owned()),
+        })
+    }
+}
+
+/// Reader for a MaxMind City database (e.g. `GeoLite2-City.mmdb`).
+pub struct MaxMindCityDatabase {
+    reader: Reader<Vec<u8>>,
+}
+
+impl MaxMindCityDatabase {
+    pub fn open<P>(path: P) -> RedeyeResult<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(MaxMindCityDatabase {
+            reader: open_database(path.as_ref())?,
+        })
+    }
+}
+
+impl IpDatabase<GeoInfo> for MaxMindCityDatabase {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        self.reader.lookup::<geoip2::City>(ip).ok().map(|r| {
+            let country = r.country.as_ref();
+            let location = r.location.as_ref();
+
+            GeoInfo {
+                country_code: country.and_then(|c| c.iso_code).map(|s| s.to_owned()),
+                country_name: country
+                    .and_then(|c| c.names.as_ref())
+                    .and_then(|n| n.get("en"))
+                    .map(|s| (*s).to_owned()),
+                city_name: r
+                    .city
+                    .as_ref()
+                    .and_then(|c| c.names.as_ref())
+                    .and_then(|n| n.get("en"))
+                    .map(|s| (*s).to_owned()),
+                latitude: location.and_then(|l| l.latitude),
+                longitude: location.and_then(|l| l.longitude),
+            }
+        })
+    }
+}
+
+fn open_database(path: &Path) -> RedeyeResult<Reader<Vec<u8>>> {
+    Reader::open_readfile(path)
+        .map_err(|e| RedeyeError::ConfigError(format!("unable to open MaxMind database {}: {}", path.display(), e)))
+}
+
+/// Cache of per-address lookup results for enrichers that look up information
+/// about the `remote_host` of an event.
+///
+/// Only public IP addresses are looked up: hostnames, and loopback, private,
+/// and link-local addresses are skipped since no database will have useful
+/// information about them. Both successful and failed lookups are cached. Once
+/// the cache is full, it is cleared and starts filling again.
+#[derive(Debug)]
+pub struct IpLookupCache<T> {
+    capacity: usize,
+    entries: Mutex<HashMap<IpAddr, Option<T>>>,
+}
+
+impl<T> IpLookupCache<T>
+where
+    T: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        IpLookupCache {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Look up information about the `remote_host` of the event, using the
+    /// cached result if there is one or the given database otherwise.
+    pub fn lookup<D>(&self, event: &LogEvent, db: &D) -> Option<T>
+    where
+        D: IpDatabase<T> + ?Sized,
+    {
+        let ip = remote_ip(event)?;
+
+        if let Ok(entries) = self.entries.lock() {
+            if let Some(res) = entries.get(&ip) {
+                return res.clone();
+            }
+        }
+
+        let res = db.lookup(ip);
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= self.capacity {
+                entries.clear();
+            }
+            entries.insert(ip, res.clone());
+        }
+
+        res
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map(|e| e.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Get the `remote_host` of the event as an IP address if it is one that can
+/// be usefully looked up in a database.
+fn remote_ip(event: &LogEvent) -> Option<IpAddr> {
+    match event.lookup("remote_host") {
+        Some(LogFieldValue::Text(host)) => host.parse().ok().filter(is_public),
+        _ => None,
+    }
+}
+
+/// Return true if the address is not loopback, private, link-local, or otherwise
+/// reserved for local use.
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                // Unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link-local, fe80::/10
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Implementation of an `Enricher` that adds the autonomous system number and
+/// organization of the `remote_host` of each event as the `asn` and `as_org`
+/// fields.
+pub struct AsnEnricher {
+    db: Box<dyn IpDatabase<AsnInfo> + Send + Sync>,
+    cache: IpLookupCache<AsnInfo>,
+}
+
+impl AsnEnricher {
+    pub fn new(db: Box<dyn IpDatabase<AsnInfo> + Send + Sync>) -> Self {
+        AsnEnricher {
+            db,
+            cache: IpLookupCache::new(DEFAULT_CACHE_SIZE),
+        }
+    }
+}
+
+impl Enricher for AsnEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        if let Some(info) = self.cache.lookup(event, self.db.as_ref()) {
+            if let Some(n) = info.number {
+                event.insert("asn", LogFieldValue::Int(u64::from(n)));
+            }
+
+            if let Some(o) = info.organization {
+                event.insert("as_org", LogFieldValue::Text(o));
+            }
+        }
+    }
+}
+
+/// Implementation of an `Enricher` that adds the location of the `remote_host`
+/// of each event as a `geoip` mapping with the keys `country_code`, `country_name`,
+/// `city_name`, `latitude`, and `longitude` (when known).
+pub struct GeoIpEnricher {
+    db: Box<dyn IpDatabase<GeoInfo> + Send + Sync>,
+    cache: IpLookupCache<GeoInfo>,
+}
+
+impl GeoIpEnricher {
+    pub fn new(db: Box<dyn IpDatabase<GeoInfo> + Send + Sync>) -> Self {
+        GeoIpEnricher {
+            db,
+            cache: IpLookupCache::new(DEFAULT_CACHE_SIZE),
+        }
+    }
+}
+
+impl Enricher for GeoIpEnricher {
+    fn enrich(&self, event: &mut LogEvent) {
+        let info = match self.cache.lookup(event, self.db.as_ref()) {
+            Some(i) => i,
+            None => return,
+        };
+
+        let mut geo = HashMap::new();
+        let text_fields = [
+            ("country_code", info.country_code),
+            ("country_name", info.country_name),
+            ("city_name", info.city_name),
+        ];
+
+        for (k, v) in text_fields {
+            if let Some(v) = v {
+                geo.insert(k.to_owned(), LogFieldValue::Text(v));
+            }
+        }
+
+        for (k, v) in [("latitude", info.latitude), ("longitude", info.longitude)] {
+            if let Some(v) = v {
+                geo.insert(k.to_owned(), LogFieldValue::Float(v));
+            }
+        }
+
+        if !geo.is_empty() {
+            event.insert("geoip", LogFieldValue::Mapping(geo));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_public, AsnEnricher, AsnInfo, GeoInfo, GeoIpEnricher, IpDatabase, IpLookupCache};
+    use crate::enrich::Enricher;
+    use crate::types::{LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FakeAsnDatabase {
+        calls: AtomicUsize,
+    }
+
+    impl IpDatabase<AsnInfo> for FakeAsnDatabase {
+        fn lookup(&self, ip: IpAddr) -> Option<AsnInfo> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if ip.to_string() == "8.8.8.8" {
+                Some(AsnInfo {
+                    number: Some(15169),
+                    organization: Some("GOOGLE".to_owned()),
+                })
+            } else {
+                None
+            }
+        }
+    }
+
+    struct FakeGeoDatabase;
+
+    impl IpDatabase<GeoInfo> for FakeGeoDatabase {
+        fn lookup(&self, _ip: IpAddr) -> Option<GeoInfo> {
+            Some(GeoInfo {
+                country_code: Some("US".to_owned()),
+                country_name: Some("United States".to_owned()),
+                city_name: None,
+                latitude: Some(37.751),
+                longitude: Some(-97.822),
+            })
+        }
+    }
+
+    fn host_event(host: &str) -> LogEvent {
+        let mut fields = HashMap::new();
+        fields.insert("remote_host".to_owned(), LogFieldValue::Text(host.to_owned()));
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in &["8.8.8.8", "2001:4860:4860::8888", "100.128.0.1"] {
+            assert!(is_public(&ip.parse().unwrap()), "{} should be public", ip);
+        }
+
+        for ip in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(!is_public(&ip.parse().unwrap()), "{} should not be public", ip);
+        }
+    }
+
+    #[test]
+    fn test_ip_lookup_cache_caches_results() {
+        let db = FakeAsnDatabase {
+            calls: AtomicUsize::new(0),
+        };
+        let cache = IpLookupCache::new(10);
+
+        assert!(cache.lookup(&host_event("8.8.8.8"), &db).is_some());
+        assert!(cache.lookup(&host_event("8.8.8.8"), &db).is_some());
+        assert!(cache.lookup(&host_event("1.1.1.1"), &db).is_none());
+        assert!(cache.lookup(&host_event("1.1.1.1"), &db).is_none());
+
+        assert_eq!(2, db.calls.load(Ordering::SeqCst));
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn test_ip_lookup_cache_skips_non_public() {
+        let db = FakeAsnDatabase {
+            calls: AtomicUsize::new(0),
+        };
+        let cache = IpLookupCache::new(10);
+
+        assert!(cache.lookup(&host_event("192.168.1.1"), &db).is_none());
+        assert!(cache.lookup(&host_event("example.com"), &db).is_none());
+        assert!(cache.lookup(&LogEvent::from(HashMap::new()), &db).is_none());
+
+        assert_eq!(0, db.calls.load(Ordering::SeqCst));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_ip_lookup_cache_bounded() {
+        let db = FakeAsnDatabase {
+            calls: AtomicUsize::new(0),
+        };
+        let cache = IpLookupCache::new(2);
+
+        cache.lookup(&host_event("1.1.1.1"), &db);
+        cache.lookup(&host_event("1.1.1.2"), &db);
+        cache.lookup(&host_event("1.1.1.3"), &db);
+
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn test_asn_enricher() {
+        let enricher = AsnEnricher::new(Box::new(FakeAsnDatabase {
+            calls: AtomicUsize::new(0),
+        }));
+        let mut found = host_event("8.8.8.8");
+        let mut missing = host_event("1.1.1.1");
+        enricher.enrich(&mut found);
+        enricher.enrich(&mut missing);
+
+        assert_eq!(Some(&LogFieldValue::Int(15169)), found.lookup("asn"));
+        assert_eq!(Some(&LogFieldValue::Text("GOOGLE".to_owned())), found.lookup("as_org"));
+        assert_eq!(None, missing.lookup("asn"));
+        assert_eq!(None, missing.lookup("as_org"));
+    }
+
+    #[test]
+    fn test_geoip_enricher() {
+        let enricher = GeoIpEnricher::new(Box::new(FakeGeoDatabase));
+        let mut e = host_event("8.8.8.8");
+        enricher.enrich(&mut e);
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("US".to_owned())),
+            e.lookup("geoip.country_code")
+        );
+        assert_eq!(Some(&LogFieldValue::Float(37.751)), e.lookup("geoip.latitude"));
+        assert_eq!(None, e.lookup("geoip.city_name"));
+    }
+
+    #[test]
+    fn test_geoip_enricher_private_address() {
+        let enricher = GeoIpEnricher::new(Box::new(FakeGeoDatabase));
+        let mut e = host_event("10.0.0.1");
+        enricher.enrich(&mut e);
+
+        assert_eq!(None, e.lookup("geoip"));
+    }
+}
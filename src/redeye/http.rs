@@ -0,0 +1,438 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Posting batches of events to an HTTP endpoint
+//!
+//! The body and headers of the request for each batch of events are built by an
+//! `HttpTarget`. A `Connector` that sends them is available when the `http`
+//! feature is enabled, for use with a `Forwarder`.
+
+use crate::output::OutputFormat;
+use crate::types::{RedeyeError, RedeyeResult};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Write};
+
+/// Action line before each event when using the Elasticsearch bulk API.
+const ES_BULK_ACTION: &[u8] = b"{\"create\":{}}";
+
+/// Parse a header given as `Name: value`.
+pub fn parse_header(s: &str) -> RedeyeResult<(String, String)> {
+    match s.find(':') {
+        Some(i) if !s[..i].trim().is_empty() => Ok((s[..i].trim().to_owned(), s[i + 1..].trim().to_owned())),
+        _ => Err(RedeyeError::ConfigError(format!(
+            "invalid header '{}', expected 'Name: value'",
+            s
+        ))),
+    }
+}
+
+/// Endpoint that batches of events are posted to and how the requests are built.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::http::HttpTarget;
+/// use redeye::output::OutputFormat;
+///
+/// let target = HttpTarget::new("http://localhost:9200/_bulk").format(OutputFormat::EsBulk);
+/// let body = target.body(&[br#"{"status_code":200}"#.to_vec()]).unwrap();
+///
+/// assert_eq!(&b"{\"create\":{}}\n{\"status_code\":200}\n"[..], &body[..]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpTarget {
+    url: String,
+    format: OutputFormat,
+    gzip: bool,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpTarget {
+    pub fn new<S>(url: S) -> Self
+    where
+        S: Into<String>,
+    {
+        HttpTarget {
+            url: url.into(),
+            format: OutputFormat::Json,
+            gzip: false,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Set how events are framed in the body of each request.
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// If true, compress the body of each request with gzip.
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Add a header sent with each request.
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Authenticate each request with a username and password.
+    pub fn basic_auth(self, user: &str, password: &str) -> Self {
+        let credentials = base64(format!("{}:{}", user, password).as_bytes());
+        self.header("Authorization", format!("Basic {}", credentials))
+    }
+
+    /// URL that requests are sent to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Headers sent with each request, including those describing the body.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![("Content-Type".to_owned(), "application/x-ndjson".to_owned())];
+        if self.gzip {
+            headers.push(("Content-Encoding".to_owned(), "gzip".to_owned()));
+        }
+
+        headers.extend(self.headers.iter().cloned());
+        headers
+    }
+
+    /// Body of the request for a batch of serialized events.
+    pub fn body(&self, records: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        for record in records {
+            if self.format == OutputFormat::EsBulk {
+                body.extend_from_slice(ES_BULK_ACTION);
+                body.push(b'\n');
+            }
+
+            body.extend_from_slice(record);
+            body.push(b'\n');
+        }
+
+        if !self.gzip {
+            return Ok(body);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body)?;
+        encoder.finish()
+    }
+}
+
+/// Standard base64 encoding, with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(feature = "http")]
+pub use self::connector::HttpConnector;
+
+#[cfg(feature = "http")]
+mod connector {
+    use super::HttpTarget;
+    use crate::forward::Connector;
+    use std::io;
+    use std::time::Duration;
+
+    /// Default amount of time to wait for a response to each request.
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Connector that posts each batch of records in a single request.
+    ///
+    /// Requests that fail because of a connection error, a timeout, or a `408`,
+    /// `429`, or `5xx` response are retried. Any other response that isn't `2xx`
+    /// means the records were rejected. Errors for individual events in the
+    /// response from an Elasticsearch `_bulk` request aren't checked.
+    #[derive(Debug, Clone)]
+    pub struct HttpConnector {
+        target: HttpTarget,
+        timeout: Duration,
+    }
+
+    impl HttpConnector {
+        pub fn new(target: HttpTarget) -> Self {
+            HttpConnector {
+                target,
+                timeout: DEFAULT_TIMEOUT,
+            }
+        }
+
+        /// Set the longest amount of time to wait for a response to each request.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+    }
+
+    impl Connector for HttpConnector {
+        type Stream = ::ureq::Agent;
+
+        /// Connections are established for each request (and reused when possible)
+        /// by the agent, so there's nothing that can fail here.
+        fn connect(&self) -> io::Result<::ureq::Agent> {
+            Ok(::ureq::AgentBuilder::new().timeout(self.timeout).build())
+        }
+
+        fn send(&self, agent: &mut ::ureq::Agent, records: &[Vec<u8>], _delimiter: u8) -> io::Result<()> {
+            let body = self.target.body(records)?;
+            let mut request = agent.post(self.target.url());
+            for (name, value) in self.target.headers() {
+                request = request.set(&name, &value);
+            }
+
+            match request.send_bytes(&body) {
+                Ok(_) => Ok(()),
+                Err(::ureq::Error::Status(code, _)) if code == 408 || code == 429 || code >= 500 => {
+                    Err(io::Error::other(format!("HTTP status {}", code)))
+                }
+                Err(::ureq::Error::Status(code, _)) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("HTTP status {}", code),
+                )),
+                Err(e) => Err(io::Error::other(e.to_string())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "http")]
+    use super::HttpConnector;
+    use super::{base64, parse_header, HttpTarget};
+    #[cfg(feature = "http")]
+    use crate::forward::{Backoff, Forwarder};
+    use crate::output::OutputFormat;
+    #[cfg(feature = "http")]
+    use crate::stats::Stats;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    #[cfg(feature = "http")]
+    use std::io::{BufRead, BufReader, Write};
+    #[cfg(feature = "http")]
+    use std::net::TcpListener;
+    #[cfg(feature = "http")]
+    use std::sync::Arc;
+    #[cfg(feature = "http")]
+    use std::thread;
+    #[cfg(feature = "http")]
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            ("X-Api-Key".to_owned(), "abc: 123".to_owned()),
+            parse_header("X-Api-Key: abc: 123").unwrap()
+        );
+        assert_eq!(("X-Empty".to_owned(), "".to_owned()), parse_header("X-Empty:").unwrap());
+        assert!(parse_header("X-Api-Key").is_err());
+        assert!(parse_header(": value").is_err());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("dXNlcjpwYXNzd29yZA==", base64(b"user:password"));
+    }
+
+    #[test]
+    fn test_http_target_headers() {
+        let target = HttpTarget::new("http://localhost/")
+            .gzip(true)
+            .basic_auth("user", "password")
+            .header("X-Source", "redeye");
+
+        assert_eq!(
+            vec![
+                ("Content-Type".to_owned(), "application/x-ndjson".to_owned()),
+                ("Content-Encoding".to_owned(), "gzip".to_owned()),
+                ("Authorization".to_owned(), "Basic dXNlcjpwYXNzd29yZA==".to_owned()),
+                ("X-Source".to_owned(), "redeye".to_owned()),
+            ],
+            target.headers()
+        );
+    }
+
+    #[test]
+    fn test_http_target_body_json() {
+        let target = HttpTarget::new("http://localhost/");
+        let body = target.body(&[b"{\"a\":1}".to_vec(), b"{\"a\":2}".to_vec()]).unwrap();
+        assert_eq!("{\"a\":1}\n{\"a\":2}\n", String::from_utf8(body).unwrap());
+    }
+
+    #[test]
+    fn test_http_target_body_gzip() {
+        let target = HttpTarget::new("http://localhost/")
+            .format(OutputFormat::EsBulk)
+            .gzip(true);
+        let body = target.body(&[b"{\"a\":1}".to_vec()]).unwrap();
+
+        let mut decoded = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!("{\"create\":{}}\n{\"a\":1}\n", decoded);
+    }
+
+    /// Respond to requests with each of the status codes in turn, returning the
+    /// headers and body of each request.
+    #[cfg(feature = "http")]
+    fn mock_server(listener: TcpListener, statuses: Vec<u16>) -> Vec<(Vec<String>, String)> {
+        let mut requests = Vec::new();
+        for status in statuses {
+            let (conn, _) = listener.accept().unwrap();
+            let mut writer = conn.try_clone().unwrap();
+            let mut reader = BufReader::new(conn);
+
+            let mut headers = Vec::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_owned();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = v.trim().parse().unwrap();
+                }
+                headers.push(line);
+            }
+
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(
+                writer,
+                "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+
+            requests.push((headers, String::from_utf8(body).unwrap()));
+        }
+
+        requests
+    }
+
+    #[cfg(feature = "http")]
+    fn fast_backoff() -> Backoff {
+        Backoff::new()
+            .initial(Duration::from_millis(10))
+            .max(Duration::from_millis(20))
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_http_connector_batches() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/_bulk", listener.local_addr().unwrap());
+        let server = thread::spawn(move || mock_server(listener, vec![200, 200]));
+
+        let target = HttpTarget::new(url)
+            .format(OutputFormat::EsBulk)
+            .header("X-Source", "redeye");
+        let mut sender = Forwarder::new(HttpConnector::new(target))
+            .max_batch_records(2)
+            .linger(Duration::from_millis(200))
+            .start()
+            .unwrap();
+
+        sender.write_all(b"{\"a\":1}\n{\"a\":2}\n{\"a\":3}\n").unwrap();
+        sender.finish().unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].0[0].starts_with("POST /_bulk "));
+        assert!(requests[0].0.contains(&"X-Source: redeye".to_owned()));
+        assert_eq!(
+            "{\"create\":{}}\n{\"a\":1}\n{\"create\":{}}\n{\"a\":2}\n",
+            requests[0].1
+        );
+        assert_eq!("{\"create\":{}}\n{\"a\":3}\n", requests[1].1);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_http_connector_retries_then_dead_letters() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead.log");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let stats = Arc::new(Stats::new());
+
+        // The first batch succeeds after a retry, the second is retried until it
+        // runs out of retries, and the third is rejected outright.
+        let statuses = vec![503, 200, 429, 500, 502, 400];
+        let server = thread::spawn(move || mock_server(listener, statuses));
+
+        let mut sender = Forwarder::new(HttpConnector::new(HttpTarget::new(url)))
+            .backoff(fast_backoff())
+            .retries(Some(2))
+            .dead_letter(Some(&path))
+            .stats(stats.clone())
+            .start()
+            .unwrap();
+
+        for i in 0..3 {
+            writeln!(sender, "{{\"a\":{}}}", i).unwrap();
+            thread::sleep(Duration::from_millis(100));
+        }
+        sender.finish().unwrap();
+
+        let bodies: Vec<String> = server.join().unwrap().into_iter().map(|(_, b)| b).collect();
+        assert_eq!(
+            vec![
+                "{\"a\":0}\n",
+                "{\"a\":0}\n",
+                "{\"a\":1}\n",
+                "{\"a\":1}\n",
+                "{\"a\":1}\n",
+                "{\"a\":2}\n"
+            ],
+            bodies
+        );
+        assert_eq!("{\"a\":1}\n{\"a\":2}\n", std::fs::read_to_string(&path).unwrap());
+        assert_eq!(2, stats.snapshot().output_dead_lettered);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+}
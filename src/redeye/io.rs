@@ -0,0 +1,2693 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Reading of log files and writing of events
+
+use crate::types::{RedeyeError, RedeyeResult};
+use crate::watch::Pattern;
+use chrono::{DateTime, Utc};
+use flate2::read::MultiGzDecoder;
+use regex::Regex;
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::{self, FromStr};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// Default name of rotated files: the file name followed by the time it was
+/// rotated, in UTC.
+const DEFAULT_ROTATED_NAME: &str = "{name}.%Y%m%d%H%M%S";
+
+/// Fields of the time a file was rotated that can be used in its name, in the
+/// order that they're compared to sort rotated files.
+const ROTATED_TIME_FIELDS: [char; 6] = ['Y', 'm', 'd', 'H', 'M', 'S'];
+
+/// Byte that separates records in input or output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delimiter {
+    #[default]
+    Newline,
+    Nul,
+}
+
+impl Delimiter {
+    /// Value of the byte used as the delimiter.
+    pub fn byte(self) -> u8 {
+        match self {
+            Delimiter::Newline => b'\n',
+            Delimiter::Nul => b'\0',
+        }
+    }
+
+    /// Remove the delimiter from the end of a record if present. For newlines,
+    /// a carriage return before the newline is also removed.
+    pub fn trim(self, record: &[u8]) -> &[u8] {
+        let record = record.strip_suffix(&[self.byte()]).unwrap_or(record);
+        match self {
+            Delimiter::Newline => record.strip_suffix(b"\r").unwrap_or(record),
+            Delimiter::Nul => record,
+        }
+    }
+}
+
+impl FromStr for Delimiter {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(Delimiter::Newline),
+            "nul" => Ok(Delimiter::Nul),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown delimiter '{}', expected one of newline, nul",
+                s
+            ))),
+        }
+    }
+}
+
+/// What to do with input lines that aren't valid UTF-8, e.g. raw bytes sent by
+/// a scanner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Utf8Policy {
+    /// Replace invalid bytes with the unicode replacement character.
+    #[default]
+    Lossy,
+    /// Skip the line.
+    Skip,
+    /// Treat the line as an error.
+    Error,
+}
+
+impl Utf8Policy {
+    /// Convert a line to text based on the policy, returning `None` if the line
+    /// should be skipped.
+    pub fn decode(self, line: &[u8]) -> Result<Option<Cow<'_, str>>, RedeyeError> {
+        match str::from_utf8(line) {
+            Ok(s) => Ok(Some(Cow::Borrowed(s))),
+            Err(e) => match self {
+                Utf8Policy::Lossy => Ok(Some(String::from_utf8_lossy(line))),
+                Utf8Policy::Skip => Ok(None),
+                Utf8Policy::Error => Err(RedeyeError::IoError(io::Error::new(io::ErrorKind::InvalidData, e))),
+            },
+        }
+    }
+}
+
+impl FromStr for Utf8Policy {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lossy" => Ok(Utf8Policy::Lossy),
+            "skip" => Ok(Utf8Policy::Skip),
+            "error" => Ok(Utf8Policy::Error),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown UTF-8 policy '{}', expected one of lossy, skip, error",
+                s
+            ))),
+        }
+    }
+}
+
+/// Size of a record read by `read_record` and whether it was too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordRead {
+    /// Number of bytes consumed from the reader, including the delimiter and any
+    /// part of the record that was discarded.
+    pub bytes: usize,
+    /// True if the record was longer than the maximum, in which case only the
+    /// first maximum number of bytes were kept.
+    pub too_long: bool,
+}
+
+/// Append the next record from the reader to `buf`, up to and including the
+/// delimiter or the end of the input, the same as `BufRead::read_until`. Returns
+/// `None` at the end of the input.
+///
+/// If `max` is set and the record (not counting the delimiter) is longer than
+/// `max` bytes, only the first `max` bytes are kept and the rest of the record
+/// is read and discarded, so no more than `max + 1` bytes are ever appended to
+/// `buf` for a single record no matter how far away the next delimiter is.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::io::read_record;
+/// use std::io::Cursor;
+///
+/// let mut reader = Cursor::new("abc\nabcdefgh\n");
+/// let mut buf = Vec::new();
+///
+/// let read = read_record(&mut reader, b'\n', Some(4), &mut buf).unwrap().unwrap();
+/// assert_eq!((4, false), (read.bytes, read.too_long));
+/// assert_eq!(b"abc\n", buf.as_slice());
+///
+/// buf.clear();
+/// let read = read_record(&mut reader, b'\n', Some(4), &mut buf).unwrap().unwrap();
+/// assert_eq!((9, true), (read.bytes, read.too_long));
+/// assert_eq!(b"abcd", buf.as_slice());
+/// ```
+pub fn read_record<R>(
+    reader: &mut R,
+    delimiter: u8,
+    max: Option<usize>,
+    buf: &mut Vec<u8>,
+) -> io::Result<Option<RecordRead>>
+where
+    R: BufRead + ?Sized,
+{
+    let start = buf.len();
+    let limit = max.map(|m| m.saturating_add(1)).unwrap_or(usize::MAX);
+    let mut bytes = 0;
+
+    let found = loop {
+        let (found, used) = {
+            let available = match reader.fill_buf() {
+                Ok(b) => b,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let (found, used) = match available.iter().position(|b| *b == delimiter) {
+                Some(i) => (true, i + 1),
+                None => (false, available.len()),
+            };
+
+            let room = limit.saturating_sub(buf.len() - start);
+            buf.extend_from_slice(&available[..used.min(room)]);
+            (found, used)
+        };
+
+        reader.consume(used);
+        bytes += used;
+        if found || used == 0 {
+            break found;
+        }
+    };
+
+    if bytes == 0 {
+        return Ok(None);
+    }
+
+    let len = if found { bytes - 1 } else { bytes };
+    let too_long = max.map(|m| len > m).unwrap_or(false);
+    if too_long {
+        buf.truncate(start + max.unwrap_or(0));
+    }
+
+    Ok(Some(RecordRead { bytes, too_long }))
+}
+
+/// Decoder that splits input arriving in arbitrary chunks into lines, for input
+/// that isn't read a line at a time such as data received from a socket.
+///
+/// Chunks are appended to a buffer and complete lines are taken from the front
+/// of it. The delimiter (and for newlines, a carriage return before it) isn't
+/// included in lines. Lines longer than the maximum are returned as errors
+/// without stopping the lines after them, and lines that aren't valid UTF-8 are
+/// handled based on a `Utf8Policy`. Once a line is known to be too long, the
+/// rest of it is discarded as it arrives so the buffer never holds much more
+/// than the maximum.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::io::LineDecoder;
+///
+/// let mut decoder = LineDecoder::new().max_record_bytes(Some(5));
+/// let mut buf = b"abc\r\nabcdefgh".to_vec();
+///
+/// assert_eq!("abc", decoder.decode(&mut buf).unwrap().unwrap());
+/// assert!(decoder.decode(&mut buf).unwrap().unwrap_err().is_record_too_long_error());
+/// assert!(decoder.decode(&mut buf).is_none());
+///
+/// buf.extend_from_slice(b"ijk\nxyz");
+/// assert!(decoder.decode(&mut buf).is_none());
+/// assert_eq!("xyz", decoder.decode_eof(&mut buf).unwrap().unwrap());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LineDecoder {
+    delimiter: Delimiter,
+    max: Option<usize>,
+    utf8: Utf8Policy,
+    next_index: usize,
+    discarding: bool,
+    skipped: u64,
+}
+
+impl LineDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the delimiter that lines are split on, a newline by default.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Set the maximum length of a line, not counting the delimiter.
+    pub fn max_record_bytes(mut self, max: Option<usize>) -> Self {
+        self.max = max;
+        self
+    }
+
+    /// Set what to do with lines that aren't valid UTF-8. By default, invalid
+    /// bytes are replaced.
+    pub fn utf8(mut self, policy: Utf8Policy) -> Self {
+        self.utf8 = policy;
+        self
+    }
+
+    /// Number of lines skipped because they weren't valid UTF-8.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Read lines from a reader using this decoder.
+    pub fn lines<R: BufRead>(self, reader: R) -> Lines<R> {
+        Lines {
+            reader,
+            decoder: self,
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Take the next complete line from the front of the buffer, or `None` if
+    /// more input is needed.
+    pub fn decode(&mut self, buf: &mut Vec<u8>) -> Option<Result<String, RedeyeError>> {
+        loop {
+            match self.decode_line(buf)? {
+                Some(line) => return Some(line),
+                None => continue,
+            }
+        }
+    }
+
+    /// Take the next complete line, or `Some(None)` if it was skipped.
+    fn decode_line(&mut self, buf: &mut Vec<u8>) -> Option<Option<Result<String, RedeyeError>>> {
+        let delimiter = self.delimiter.byte();
+
+        if self.discarding {
+            match buf.iter().position(|b| *b == delimiter) {
+                Some(i) => {
+                    buf.drain(..=i);
+                    self.discarding = false;
+                }
+                None => {
+                    buf.clear();
+                    return None;
+                }
+            }
+        }
+
+        // Room for a carriage return before a newline that isn't part of the line
+        let window = match self.max {
+            Some(max) => {
+                let cr = if self.delimiter == Delimiter::Newline { 1 } else { 0 };
+                buf.len().min(max.saturating_add(cr + 1))
+            }
+            None => buf.len(),
+        };
+
+        let start = self.next_index.min(window);
+        match buf[start..window].iter().position(|b| *b == delimiter) {
+            Some(i) => {
+                let line: Vec<u8> = buf.drain(..=start + i).collect();
+                self.next_index = 0;
+                Some(self.line(&line))
+            }
+            None if self.max.is_some() && window < buf.len() => {
+                self.next_index = 0;
+                self.discarding = true;
+                Some(Some(Err(RedeyeError::RecordTooLong(self.max.unwrap_or(0)))))
+            }
+            None => {
+                self.next_index = window;
+                None
+            }
+        }
+    }
+
+    /// Take the next line from the buffer once there's no more input, including
+    /// a final line without a delimiter, or `None` if the buffer is empty.
+    pub fn decode_eof(&mut self, buf: &mut Vec<u8>) -> Option<Result<String, RedeyeError>> {
+        if let Some(line) = self.decode(buf) {
+            return Some(line);
+        }
+
+        self.next_index = 0;
+        if self.discarding {
+            self.discarding = false;
+            buf.clear();
+            return None;
+        }
+
+        if buf.is_empty() {
+            return None;
+        }
+
+        let line = mem::take(buf);
+        self.line(&line)
+    }
+
+    /// Text of a line without its delimiter, or `None` if it was skipped.
+    fn line(&mut self, record: &[u8]) -> Option<Result<String, RedeyeError>> {
+        let line = self.delimiter.trim(record);
+        if let Some(max) = self.max {
+            if line.len() > max {
+                return Some(Err(RedeyeError::RecordTooLong(max)));
+            }
+        }
+
+        match self.utf8.decode(line) {
+            Ok(Some(text)) => Some(Ok(text.into_owned())),
+            Ok(None) => {
+                self.skipped += 1;
+                None
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Iterator over the lines of a reader split by a `LineDecoder`. Lines that are
+/// too long (or aren't valid UTF-8, depending on the policy) are errors but the
+/// lines after them are still read. An error reading from the reader is the last
+/// item.
+#[derive(Debug)]
+pub struct Lines<R> {
+    reader: R,
+    decoder: LineDecoder,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = Result<String, RedeyeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.decoder.decode(&mut self.buf) {
+                return Some(line);
+            }
+
+            if self.done {
+                return self.decoder.decode_eof(&mut self.buf);
+            }
+
+            let read = match self.reader.fill_buf() {
+                Ok(available) => {
+                    self.buf.extend_from_slice(available);
+                    available.len()
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.done = true;
+                    self.buf.clear();
+                    return Some(Err(e.into()));
+                }
+            };
+
+            self.reader.consume(read);
+            self.done = read == 0;
+        }
+    }
+}
+
+/// Source of lines from standard input, a file, or any other reader, split by a
+/// `LineDecoder`.
+///
+/// Files are opened when the first line is read rather than when the source is
+/// created, so creating a source for a FIFO doesn't block until something opens
+/// it for writing. An error opening the file is returned as the first line.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::io::{LineDecoder, LineSource};
+/// use std::io::Cursor;
+///
+/// let source = LineSource::reader(Cursor::new(b"one\ntwo\n".to_vec()), LineDecoder::new());
+/// let lines: Vec<String> = source.map(|l| l.unwrap()).collect();
+///
+/// assert_eq!(vec!["one", "two"], lines);
+/// ```
+pub struct LineSource {
+    path: Option<(PathBuf, bool)>,
+    decoder: Option<LineDecoder>,
+    lines: Option<Lines<Box<dyn BufRead + Send>>>,
+}
+
+impl LineSource {
+    /// Read lines from standard input.
+    pub fn stdin(decoder: LineDecoder) -> Self {
+        Self::reader(io::stdin(), decoder)
+    }
+
+    /// Read lines from the file at the given path, decompressing it based on its
+    /// extension or contents if `auto_decompress` is true. See `open_file`.
+    pub fn open<P>(path: P, auto_decompress: bool, decoder: LineDecoder) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        LineSource {
+            path: Some((path.into(), auto_decompress)),
+            decoder: Some(decoder),
+            lines: None,
+        }
+    }
+
+    /// Read lines from any reader.
+    pub fn reader<R>(reader: R, decoder: LineDecoder) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        let reader: Box<dyn BufRead + Send> = Box::new(io::BufReader::new(reader));
+        LineSource {
+            path: None,
+            decoder: None,
+            lines: Some(decoder.lines(reader)),
+        }
+    }
+}
+
+impl Iterator for LineSource {
+    type Item = Result<String, RedeyeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((path, auto_decompress)) = self.path.take() {
+            // The decoder is only kept until the file is opened.
+            let decoder = self.decoder.take().unwrap_or_default();
+            match open_file(&path, auto_decompress) {
+                Ok(reader) => {
+                    let reader: Box<dyn BufRead + Send> = Box::new(io::BufReader::new(reader));
+                    self.lines = Some(decoder.lines(reader));
+                }
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+
+        self.lines.as_mut()?.next()
+    }
+}
+
+impl fmt::Debug for LineSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LineSource")
+            .field("path", &self.path)
+            .field("opened", &self.lines.is_some())
+            .finish()
+    }
+}
+
+/// Files to read and the name of the format their lines are in, given as comma
+/// separated `key=value` pairs, e.g. `path=/var/log/nginx/*.log,format=combined`.
+///
+/// The path may be a glob pattern (see `watch::Pattern`) matching any number of
+/// files. Both `path` and `format` are required. The format name isn't checked
+/// here since that depends on the formats in a `ParserRegistry`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::io::InputSpec;
+///
+/// let spec: InputSpec = "path=/var/log/nginx/*.log,format=combined".parse().unwrap();
+/// assert_eq!("/var/log/nginx/*.log", spec.path());
+/// assert_eq!("combined", spec.format());
+///
+/// assert!("path=/var/log/haproxy.log".parse::<InputSpec>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputSpec {
+    path: String,
+    format: String,
+}
+
+impl InputSpec {
+    pub fn new<P, F>(path: P, format: F) -> Self
+    where
+        P: Into<String>,
+        F: Into<String>,
+    {
+        InputSpec {
+            path: path.into(),
+            format: format.into(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    /// Paths of the files to read, sorted. A path without any wildcards is
+    /// returned as is even if it doesn't exist so that opening it reports why.
+    /// A pattern that doesn't match any files is an error.
+    pub fn files(&self) -> RedeyeResult<Vec<PathBuf>> {
+        if !self.path.contains(&['*', '?', '['][..]) {
+            return Ok(vec![PathBuf::from(&self.path)]);
+        }
+
+        let files = Pattern::new(&self.path)?.scan();
+        if files.is_empty() {
+            return Err(RedeyeError::ConfigError(format!("no files match '{}'", self.path)));
+        }
+
+        Ok(files)
+    }
+}
+
+impl FromStr for InputSpec {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |msg: &str| RedeyeError::ConfigError(format!("invalid input '{}': {}", s, msg));
+        let mut path = None;
+        let mut format = None;
+
+        for pair in s.split(',') {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| invalid("expected key=value pairs"))?;
+            let slot = match key.trim() {
+                "path" => &mut path,
+                "format" => &mut format,
+                k => return Err(invalid(&format!("unknown key '{}', expected one of path, format", k))),
+            };
+
+            if slot.is_some() {
+                return Err(invalid(&format!("'{}' given more than once", key.trim())));
+            }
+
+            let value = value.trim();
+            if value.is_empty() {
+                return Err(invalid(&format!("'{}' is empty", key.trim())));
+            }
+
+            *slot = Some(value.to_owned());
+        }
+
+        match (path, format) {
+            (Some(path), Some(format)) => Ok(InputSpec { path, format }),
+            (None, _) => Err(invalid("missing path")),
+            (_, None) => Err(invalid("missing format")),
+        }
+    }
+}
+
+impl fmt::Display for InputSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "path={},format={}", self.path, self.format)
+    }
+}
+
+/// Compression format of an input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Determine the compression format of a file based on its extension, if
+    /// it has one that we recognize.
+    pub fn from_extension<P>(path: P) -> Option<Compression>
+    where
+        P: AsRef<Path>,
+    {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") => Some(Compression::Zstd),
+            Some("bz2") => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// Determine the compression format of a file based on the first few bytes
+    /// of it, defaulting to no compression.
+    pub fn from_magic(bytes: &[u8]) -> Compression {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else if bytes.starts_with(BZIP2_MAGIC) {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Short name of the compression format for use in messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+        }
+    }
+}
+
+/// Open a file for reading, transparently decompressing it if `auto_decompress`
+/// is set and it appears to be compressed, based on its extension or contents.
+///
+/// An error is returned if the file is compressed using a format that support
+/// was not compiled in for (zstd and bzip2 require the `zstd` and `bzip2` cargo
+/// features, respectively).
+pub fn open_file<P>(path: P, auto_decompress: bool) -> io::Result<Box<dyn Read + Send>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+
+    if !auto_decompress {
+        return Ok(Box::new(file));
+    }
+
+    let compression = match Compression::from_extension(path) {
+        Some(c) => c,
+        // FIFOs and other special files can't be read again from the start after
+        // checking the first few bytes.
+        None if !file.metadata()?.is_file() => Compression::None,
+        None => {
+            let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+            (&mut file).take(ZSTD_MAGIC.len() as u64).read_to_end(&mut magic)?;
+            file.seek(SeekFrom::Start(0))?;
+            Compression::from_magic(&magic)
+        }
+    };
+
+    decompress(file, compression)
+}
+
+/// Wrap a reader to decompress its contents using the given format.
+///
+/// Errors reading compressed data, such as from corrupt or truncated files, are
+/// returned with `InvalidData` as their kind (instead of signaling the end of
+/// the input early) and include the name of the format.
+pub fn decompress<R>(reader: R, compression: Compression) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    let inner: Box<dyn Read + Send> = match compression {
+        Compression::None => return Ok(Box::new(reader)),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => new_zstd_decoder(reader)?,
+        Compression::Bzip2 => new_bzip2_decoder(reader)?,
+    };
+
+    Ok(Box::new(Decompressor { inner, compression }))
+}
+
+#[cfg(feature = "zstd")]
+fn new_zstd_decoder<R>(reader: R) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    Ok(Box::new(zstd::stream::read::Decoder::new(reader)?))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn new_zstd_decoder<R>(_reader: R) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    Err(unsupported(Compression::Zstd))
+}
+
+#[cfg(feature = "bzip2")]
+fn new_bzip2_decoder<R>(reader: R) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn new_bzip2_decoder<R>(_reader: R) -> io::Result<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    Err(unsupported(Compression::Bzip2))
+}
+
+#[cfg(not(all(feature = "zstd", feature = "bzip2")))]
+fn unsupported(compression: Compression) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{} input is not supported, rebuild with the '{}' feature",
+            compression.name(),
+            compression.name()
+        ),
+    )
+}
+
+/// Reader that adds context to errors from a decompressing reader.
+struct Decompressor {
+    inner: Box<dyn Read + Send>,
+    compression: Compression,
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {} data: {}", self.compression.name(), e),
+            )
+        })
+    }
+}
+
+/// When a `RotatingFileWriter` should rotate the file it is writing to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RotationPolicy {
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    keep: Option<usize>,
+}
+
+impl RotationPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rotate the file once it is at least this many bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate the file once it has been open for this long.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Delete the oldest rotated files, keeping only this many of them.
+    pub fn keep(mut self, count: usize) -> Self {
+        self.keep = Some(count);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum NamePart {
+    Literal(String),
+    Name,
+    Seq,
+    Time(char),
+}
+
+/// Pattern for the names of files rotated by a `RotatingFileWriter`.
+///
+/// Patterns can contain `{name}` for the name of the file being rotated, `{seq}`
+/// for a sequence number, and the `strftime` fields `%Y`, `%m`, `%d`, `%H`, `%M`,
+/// and `%S` for the time it was rotated in UTC (or `%%` for a literal `%`). The
+/// default is `{name}.%Y%m%d%H%M%S`. Rotated files are kept in the same directory
+/// as the file being rotated.
+///
+/// When a name is already taken, e.g. when rotating twice in the same second, the
+/// next sequence number is used. Patterns without `{seq}` get `.1`, `.2`, and so
+/// on appended in that case.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::io::RotatedName;
+///
+/// let name: RotatedName = "{name}-%Y-%m-%d.{seq}".parse().unwrap();
+/// assert_eq!("{name}-%Y-%m-%d.{seq}", name.to_string());
+/// assert!("{name}.%s".parse::<RotatedName>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotatedName {
+    parts: Vec<NamePart>,
+}
+
+impl RotatedName {
+    /// Name of a file rotated at the given time with an optional sequence number.
+    fn render(&self, name: &str, time: &DateTime<Utc>, seq: Option<u64>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                NamePart::Literal(s) => out.push_str(s),
+                NamePart::Name => out.push_str(name),
+                NamePart::Seq => out.push_str(&seq.unwrap_or(0).to_string()),
+                NamePart::Time(c) => out.push_str(&time.format(&format!("%{}", c)).to_string()),
+            }
+        }
+
+        match seq {
+            Some(n) if !self.has_seq() => format!("{}.{}", out, n),
+            _ => out,
+        }
+    }
+
+    /// Expression matching files rotated from the given file name, capturing the
+    /// time fields and sequence number.
+    fn matcher(&self, name: &str) -> Regex {
+        let mut expr = String::from("^");
+        for part in &self.parts {
+            match part {
+                NamePart::Literal(s) => expr.push_str(&regex::escape(s)),
+                NamePart::Name => expr.push_str(&regex::escape(name)),
+                NamePart::Seq => expr.push_str(r"(?P<seq>\d+)"),
+                NamePart::Time('Y') => expr.push_str(r"(?P<Y>\d{4})"),
+                NamePart::Time(c) => expr.push_str(&format!(r"(?P<{}>\d{{2}})", c)),
+            }
+        }
+
+        if !self.has_seq() {
+            expr.push_str(r"(?:\.(?P<seq>\d+))?");
+        }
+
+        expr.push('$');
+        // Every part is escaped or a valid group so the expression always compiles.
+        Regex::new(&expr).unwrap()
+    }
+
+    /// Key that sorts files rotated from the given file name oldest first, or
+    /// `None` if the file wasn't rotated from it.
+    fn key(&self, matcher: &Regex, file_name: &str) -> Option<RotatedKey> {
+        let caps = matcher.captures(file_name)?;
+        let field = |name: &str| caps.name(name).and_then(|m| m.as_str().parse().ok());
+
+        let mut time = [0; ROTATED_TIME_FIELDS.len()];
+        for (i, c) in ROTATED_TIME_FIELDS.iter().enumerate() {
+            if let Some(v) = field(&c.to_string()) {
+                time[i] = v;
+            }
+        }
+
+        Some((time, field("seq").unwrap_or(0)))
+    }
+
+    /// Time fields used in the name of a file rotated at the given time.
+    fn time_key(&self, time: &DateTime<Utc>) -> [u64; ROTATED_TIME_FIELDS.len()] {
+        let mut key = [0; ROTATED_TIME_FIELDS.len()];
+        for part in &self.parts {
+            if let NamePart::Time(c) = part {
+                let i = ROTATED_TIME_FIELDS.iter().position(|f| f == c).unwrap();
+                key[i] = time.format(&format!("%{}", c)).to_string().parse().unwrap_or(0);
+            }
+        }
+
+        key
+    }
+
+    fn has_seq(&self) -> bool {
+        self.parts.contains(&NamePart::Seq)
+    }
+}
+
+type RotatedKey = ([u64; ROTATED_TIME_FIELDS.len()], u64);
+
+impl Default for RotatedName {
+    fn default() -> Self {
+        // The default pattern is known to be valid.
+        DEFAULT_ROTATED_NAME.parse().unwrap()
+    }
+}
+
+impl FromStr for RotatedName {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| RedeyeError::ConfigError(format!("invalid rotated file name '{}': {}", s, reason));
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            let part = match c {
+                '/' | '\\' => return Err(invalid("rotated files must be in the same directory")),
+                '%' => match chars.next() {
+                    Some('%') => {
+                        literal.push('%');
+                        continue;
+                    }
+                    Some(f) if ROTATED_TIME_FIELDS.contains(&f) => NamePart::Time(f),
+                    _ => return Err(invalid("expected one of %Y, %m, %d, %H, %M, %S, or %%")),
+                },
+                '{' => {
+                    let rest = chars.as_str();
+                    if let Some(r) = rest.strip_prefix("name}") {
+                        chars = r.chars();
+                        NamePart::Name
+                    } else if let Some(r) = rest.strip_prefix("seq}") {
+                        chars = r.chars();
+                        NamePart::Seq
+                    } else {
+                        return Err(invalid("expected {name} or {seq}"));
+                    }
+                }
+                _ => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+
+            if !literal.is_empty() {
+                parts.push(NamePart::Literal(mem::take(&mut literal)));
+            }
+
+            parts.push(part);
+        }
+
+        if !literal.is_empty() {
+            parts.push(NamePart::Literal(literal));
+        }
+
+        if parts.is_empty() {
+            return Err(invalid("name is empty"));
+        }
+
+        Ok(RotatedName { parts })
+    }
+}
+
+impl fmt::Display for RotatedName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                NamePart::Literal(s) => write!(f, "{}", s.replace('%', "%%"))?,
+                NamePart::Name => write!(f, "{{name}}")?,
+                NamePart::Seq => write!(f, "{{seq}}")?,
+                NamePart::Time(c) => write!(f, "%{}", c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writer for delimited records (newline delimited by default) that rotates the file being written
+/// to based on its size and age.
+///
+/// When the file is rotated, it is flushed and synced to disk (unless disabled),
+/// renamed based on a `RotatedName` pattern (by default the time it was rotated,
+/// e.g. `access.json` becomes `access.json.20181002135536` with a sequence number
+/// appended if that name is already taken), and a new file is opened at the
+/// original path. Rotation is only done between records (at the start of a line,
+/// or after a record written with `write_record`) so a record is never split
+/// between files. Since rotation is only checked when writing, an idle file won't
+/// be rotated until the next record is written to it.
+///
+/// Use `close` when finished to flush and sync the file and find out if that
+/// failed. A writer that is dropped instead is flushed, with errors printed to
+/// stderr since there's nothing to return them to.
+#[derive(Debug)]
+pub struct RotatingFileWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    policy: RotationPolicy,
+    rotated_name: RotatedName,
+    sync_on_rotate: bool,
+    written: u64,
+    opened: Instant,
+    at_boundary: bool,
+    delimiter: u8,
+    closed: bool,
+}
+
+impl RotatingFileWriter {
+    /// Open the file at the given path for appending, creating it if needed.
+    pub fn open<P>(path: P, policy: RotationPolicy) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref().to_path_buf();
+        if path.file_name().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid output file {}", path.display()),
+            ));
+        }
+
+        let file = Self::open_append(&path)?;
+        let written = file.metadata()?.len();
+
+        Ok(RotatingFileWriter {
+            path,
+            file: BufWriter::new(file),
+            policy,
+            rotated_name: RotatedName::default(),
+            sync_on_rotate: true,
+            written,
+            opened: Instant::now(),
+            at_boundary: true,
+            delimiter: Delimiter::Newline.byte(),
+            closed: false,
+        })
+    }
+
+    /// Set the pattern used to name rotated files.
+    pub fn rotated_name(mut self, name: RotatedName) -> Self {
+        self.rotated_name = name;
+        self
+    }
+
+    /// If false, don't sync the file to disk before it is rotated. It is still flushed.
+    pub fn sync_on_rotate(mut self, sync: bool) -> Self {
+        self.sync_on_rotate = sync;
+        self
+    }
+
+    /// Set the delimiter between records, used to avoid rotating in the middle of one.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter.byte();
+        self
+    }
+
+    /// Path of the file currently being written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rotated files for this writer, oldest first.
+    pub fn rotated_files(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self.rotated_entries()?.into_iter().map(|(_, p)| p).collect())
+    }
+
+    /// Write a complete record, including its delimiter if it has one. The end of
+    /// the record is treated as a boundary between records even without a
+    /// delimiter, so the file may be rotated before the next one.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if record.is_empty() {
+            return Ok(());
+        }
+
+        if self.at_boundary && self.rotation_due() {
+            self.rotate()?;
+        }
+
+        self.file.write_all(record)?;
+        self.written += record.len() as u64;
+        self.at_boundary = true;
+        Ok(())
+    }
+
+    /// Close the current file, rename it, open a new one, and delete old files.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        if self.sync_on_rotate {
+            self.sync_all()?;
+        } else {
+            self.file.flush()?;
+        }
+
+        // Files rotated with the same time in their name are given increasing
+        // sequence numbers based on existing files (not just free names) so that
+        // they sort correctly even after older ones have been pruned.
+        let now = Utc::now();
+        let time = self.rotated_name.time_key(&now);
+        let mut seq = self
+            .rotated_entries()?
+            .into_iter()
+            .filter(|((t, _), _)| *t == time)
+            .map(|((_, n), _)| n + 1)
+            .max();
+
+        if seq.is_none() && self.rotated_name.has_seq() {
+            seq = Some(0);
+        }
+
+        // The name can still be taken by a file that sorts differently, e.g. one
+        // created by another program, which is never overwritten.
+        let mut rotated = self.dir().join(self.rotated_name.render(&self.file_name(), &now, seq));
+        while rotated.exists() {
+            seq = Some(seq.map(|n| n + 1).unwrap_or(1));
+            rotated = self.dir().join(self.rotated_name.render(&self.file_name(), &now, seq));
+        }
+
+        fs::rename(&self.path, &rotated)?;
+        self.file = BufWriter::new(Self::open_append(&self.path)?);
+        self.written = 0;
+        self.opened = Instant::now();
+        self.prune()
+    }
+
+    /// Flush any buffered data and sync the current file to disk.
+    pub fn sync_all(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()
+    }
+
+    /// Flush buffered data, sync the file to disk, and close it.
+    pub fn close(mut self) -> RedeyeResult<()> {
+        self.closed = true;
+        self.sync_all()?;
+        Ok(())
+    }
+
+    /// Close the current file and open the file at the original path again, for
+    /// use when the file has been moved by another program (e.g. `logrotate`).
+    /// Unlike `rotate`, the current file is not renamed and old files are not deleted.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let file = Self::open_append(&self.path)?;
+        self.written = file.metadata()?.len();
+        self.file = BufWriter::new(file);
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    fn rotated_entries(&self) -> io::Result<Vec<(RotatedKey, PathBuf)>> {
+        let file_name = self.file_name();
+        let matcher = self.rotated_name.matcher(&file_name);
+        let mut found = Vec::new();
+
+        for entry in fs::read_dir(self.dir())? {
+            let entry = entry?;
+            let entry_name = entry.file_name();
+            let name = match entry_name.to_str() {
+                Some(n) if n != file_name => n,
+                _ => continue,
+            };
+
+            if let Some(key) = self.rotated_name.key(&matcher, name) {
+                found.push((key, entry.path()));
+            }
+        }
+
+        found.sort();
+        Ok(found)
+    }
+
+    fn open_append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn file_name(&self) -> String {
+        // File name is checked to exist when the writer is opened
+        self.path.file_name().unwrap().to_string_lossy().into_owned()
+    }
+
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        }
+    }
+
+    fn rotation_due(&self) -> bool {
+        if self.written == 0 {
+            return false;
+        }
+
+        self.policy.max_size.map(|max| self.written >= max).unwrap_or(false)
+            || self
+                .policy
+                .interval
+                .map(|i| self.opened.elapsed() >= i)
+                .unwrap_or(false)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let keep = match self.policy.keep {
+            Some(k) => k,
+            None => return Ok(()),
+        };
+
+        let rotated = self.rotated_files()?;
+        let excess = rotated.len().saturating_sub(keep);
+        for path in rotated.iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.at_boundary && self.rotation_due() {
+            self.rotate()?;
+        }
+
+        // Write at most a single record at a time so that we can check if rotation
+        // is needed at the start of each one.
+        let end = buf
+            .iter()
+            .position(|b| *b == self.delimiter)
+            .map(|i| i + 1)
+            .unwrap_or(buf.len());
+        let n = self.file.write(&buf[..end])?;
+        if n > 0 {
+            self.written += n as u64;
+            self.at_boundary = buf[n - 1] == self.delimiter;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for RotatingFileWriter {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        if let Err(e) = self.file.flush() {
+            report_drop_error(&self.path.display().to_string(), &e);
+        }
+    }
+}
+
+/// Writer shared with a background thread that flushes it periodically, so that
+/// events written while input is idle don't wait in a buffer until more arrive.
+///
+/// Errors flushing from the background thread are ignored since buffered data is
+/// kept and the error is returned by the next write or flush instead.
+///
+/// Use `close` when finished to stop the thread and flush the writer one last
+/// time. A flusher that is dropped instead does the same, printing any error to
+/// stderr.
+#[derive(Debug)]
+pub struct PeriodicFlusher<W: Write> {
+    inner: Arc<Mutex<W>>,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    closed: bool,
+}
+
+impl<W> PeriodicFlusher<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Start flushing the writer every `interval` in a background thread.
+    pub fn start(inner: W, interval: Duration) -> io::Result<Self> {
+        let inner = Arc::new(Mutex::new(inner));
+        let (stop, rx) = mpsc::channel();
+        let shared = inner.clone();
+
+        let handle = thread::Builder::new().name("redeye-flush".to_owned()).spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                let _ = lock(&shared).flush();
+            }
+        })?;
+
+        Ok(PeriodicFlusher {
+            inner,
+            stop: Some(stop),
+            handle: Some(handle),
+            closed: false,
+        })
+    }
+}
+
+impl<W: Write> PeriodicFlusher<W> {
+    /// Access the writer, blocking the background thread from flushing it.
+    pub fn lock(&self) -> MutexGuard<'_, W> {
+        lock(&self.inner)
+    }
+
+    /// Stop the background thread and wait for it to exit. The writer isn't
+    /// flushed when stopped.
+    pub fn stop(&mut self) {
+        self.stop = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    /// Stop the background thread and flush the writer.
+    pub fn close(mut self) -> RedeyeResult<()> {
+        self.closed = true;
+        self.stop();
+        self.lock().flush()?;
+        Ok(())
+    }
+}
+
+impl<W> Write for PeriodicFlusher<W>
+where
+    W: Write + Send + 'static,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.lock().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl<W: Write> Drop for PeriodicFlusher<W> {
+    fn drop(&mut self) {
+        self.stop();
+        if self.closed {
+            return;
+        }
+
+        if let Err(e) = self.lock().flush() {
+            report_drop_error("output", &e);
+        }
+    }
+}
+
+/// Destination for events that can be reopened, e.g. after being moved by `logrotate`.
+pub trait Output: Write {
+    /// Flush any buffered data and reopen the destination if it is a file.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// Flush any buffered data and, if the destination is a file, sync it to disk.
+    /// Used before exiting, whether because all input has been read or because
+    /// we've been interrupted.
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Output for io::Stdout {}
+
+impl Output for io::Sink {}
+
+impl Output for Vec<u8> {}
+
+impl Output for RotatingFileWriter {
+    fn reopen(&mut self) -> io::Result<()> {
+        RotatingFileWriter::reopen(self)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl<O: Output + ?Sized> Output for Box<O> {
+    fn reopen(&mut self) -> io::Result<()> {
+        (**self).reopen()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+impl<O: Output> Output for BufWriter<O> {
+    fn reopen(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().reopen()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.get_mut().finish()
+    }
+}
+
+impl<O: Output + Send + 'static> Output for PeriodicFlusher<O> {
+    fn reopen(&mut self) -> io::Result<()> {
+        self.lock().reopen()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.stop();
+        self.lock().finish()
+    }
+}
+
+/// When a `FlushingWriter` flushes the writer it wraps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every write.
+    EveryWrite,
+    /// Flush once data has been waiting at least this long since the last flush.
+    Interval(Duration),
+    /// Flush once at least this many bytes have been written since the last flush.
+    Bytes(usize),
+}
+
+/// Writer that flushes the writer it wraps based on a `FlushPolicy`.
+///
+/// The policy is checked after each write. Since an interval can pass without
+/// any writes, e.g. when input is slow, `flush_if_due` should also be called
+/// regularly by whatever is driving the writer so that a trickle of events
+/// isn't left waiting in a buffer.
+///
+/// Use `close` to flush the wrapped writer when finished, or `into_inner` to
+/// flush it and get it back. A writer that is dropped with data that hasn't been
+/// flushed yet flushes it, printing any error to stderr.
+#[derive(Debug)]
+pub struct FlushingWriter<W: Write> {
+    // Only taken by `into_inner`, so always present otherwise
+    inner: Option<W>,
+    policy: FlushPolicy,
+    pending: usize,
+    last_flush: Instant,
+    closed: bool,
+}
+
+impl<W: Write> FlushingWriter<W> {
+    pub fn new(inner: W, policy: FlushPolicy) -> Self {
+        FlushingWriter {
+            inner: Some(inner),
+            policy,
+            pending: 0,
+            last_flush: Instant::now(),
+            closed: false,
+        }
+    }
+
+    /// Number of bytes written since the last flush.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// Flush the wrapped writer if anything has been written since the last
+    /// flush and the policy says it's time. Returns true if it was flushed.
+    pub fn flush_if_due(&mut self) -> io::Result<bool> {
+        let due = self.pending > 0
+            && match self.policy {
+                FlushPolicy::EveryWrite => true,
+                FlushPolicy::Interval(interval) => self.last_flush.elapsed() >= interval,
+                FlushPolicy::Bytes(bytes) => self.pending >= bytes,
+            };
+
+        if due {
+            self.flush()?;
+        }
+
+        Ok(due)
+    }
+
+    /// Access the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Access the wrapped writer. Data written directly to it isn't counted
+    /// towards the policy.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Flush the wrapped writer and return it.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Flush the wrapped writer and drop it.
+    pub fn close(mut self) -> RedeyeResult<()> {
+        self.closed = true;
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for FlushingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.get_mut().write(buf)?;
+        self.pending += n;
+        self.flush_if_due()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()?;
+        self.pending = 0;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for FlushingWriter<W> {
+    fn drop(&mut self) {
+        if self.pending > 0 && self.inner.is_some() && !self.closed {
+            if let Err(e) = self.flush() {
+                report_drop_error("output", &e);
+            }
+        }
+    }
+}
+
+/// Print an error from flushing a writer that was dropped without being closed,
+/// since there's no caller to return it to. Unlike `eprintln!`, this never panics,
+/// even if stderr can't be written to.
+pub(crate) fn report_drop_error(what: &str, err: &io::Error) {
+    let _ = writeln!(
+        io::stderr(),
+        "redeye: warning: Unable to flush {} when dropped: {}",
+        what,
+        err
+    );
+}
+
+/// Lock the writer even if the other thread panicked while holding the lock, since
+/// the worst that can happen is partially written data.
+fn lock<W>(inner: &Mutex<W>) -> MutexGuard<'_, W> {
+    inner.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decompress, open_file, read_record, Compression, Delimiter, FlushPolicy, FlushingWriter, InputSpec,
+        LineDecoder, LineSource, PeriodicFlusher, RecordRead, RotatedName, RotatingFileWriter, RotationPolicy,
+        Utf8Policy,
+    };
+    use crate::types::RedeyeError;
+    use chrono::{TimeZone, Utc};
+    use flate2::write::GzEncoder;
+    use std::cmp;
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const CONTENTS: &str = concat!(
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326\n",
+        "127.0.0.1 - - [10/Oct/2000:13:55:37 -0700] \"GET /a HTTP/1.1\" 404 12\n",
+    );
+
+    fn gzip(contents: &[u8]) -> Vec<u8> {
+        let mut enc = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(contents).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn read_all(mut reader: Box<dyn Read + Send>) -> io::Result<String> {
+        let mut out = String::new();
+        reader.read_to_string(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_compression_from_extension() {
+        assert_eq!(Some(Compression::Gzip), Compression::from_extension("access.log.1.gz"));
+        assert_eq!(Some(Compression::Zstd), Compression::from_extension("access.log.zst"));
+        assert_eq!(Some(Compression::Bzip2), Compression::from_extension("access.log.bz2"));
+        assert_eq!(None, Compression::from_extension("access.log.1"));
+    }
+
+    #[test]
+    fn test_compression_from_magic() {
+        assert_eq!(Compression::Gzip, Compression::from_magic(&gzip(b"test")));
+        assert_eq!(
+            Compression::Zstd,
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0x00])
+        );
+        assert_eq!(Compression::Bzip2, Compression::from_magic(b"BZh91AY"));
+        assert_eq!(Compression::None, Compression::from_magic(b"127.0.0.1 - -"));
+        assert_eq!(Compression::None, Compression::from_magic(b""));
+    }
+
+    #[test]
+    fn test_open_file_gzip_by_magic() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log.1");
+        fs::write(&path, gzip(CONTENTS.as_bytes())).unwrap();
+
+        assert_eq!(CONTENTS, read_all(open_file(&path, true).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_open_file_gzip_concatenated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log.gz");
+        let (first, second) = CONTENTS.split_at(CONTENTS.len() / 2);
+        let mut data = gzip(first.as_bytes());
+        data.extend(gzip(second.as_bytes()));
+        fs::write(&path, data).unwrap();
+
+        assert_eq!(CONTENTS, read_all(open_file(&path, true).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_open_file_gzip_truncated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log.gz");
+        let data = gzip(CONTENTS.as_bytes());
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        let err = read_all(open_file(&path, true).unwrap()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().starts_with("invalid gzip data"));
+    }
+
+    #[test]
+    fn test_open_file_plain() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        fs::write(&path, CONTENTS).unwrap();
+
+        assert_eq!(CONTENTS, read_all(open_file(&path, true).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_open_file_auto_decompress_disabled() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log.gz");
+        let data = gzip(CONTENTS.as_bytes());
+        fs::write(&path, &data).unwrap();
+
+        let mut out = Vec::new();
+        open_file(&path, false).unwrap().read_to_end(&mut out).unwrap();
+        assert_eq!(data, out);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_decompress_zstd() {
+        let data = zstd::stream::encode_all(CONTENTS.as_bytes(), 0).unwrap();
+        assert_eq!(Compression::Zstd, Compression::from_magic(&data));
+
+        let reader = decompress(Cursor::new(data), Compression::Zstd).unwrap();
+        assert_eq!(CONTENTS, read_all(reader).unwrap());
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_decompress_zstd_unsupported() {
+        let err = decompress(Cursor::new(Vec::new()), Compression::Zstd).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn test_decompress_bzip2() {
+        let mut enc = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        enc.write_all(CONTENTS.as_bytes()).unwrap();
+        let data = enc.finish().unwrap();
+        assert_eq!(Compression::Bzip2, Compression::from_magic(&data));
+
+        let reader = decompress(Cursor::new(data), Compression::Bzip2).unwrap();
+        assert_eq!(CONTENTS, read_all(reader).unwrap());
+    }
+
+    #[cfg(not(feature = "bzip2"))]
+    #[test]
+    fn test_decompress_bzip2_unsupported() {
+        let err = decompress(Cursor::new(Vec::new()), Compression::Bzip2).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    fn read_lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| l.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_rotating_file_writer_no_policy() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new()).unwrap();
+
+        writer.write_all(b"one\ntwo\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(vec!["one", "two"], read_lines(&path));
+        assert!(writer.rotated_files().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rotating_file_writer_max_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new().max_size(8)).unwrap();
+
+        writer.write_all(b"one\ntwo\nthree\nfour\nfive\n").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(2, rotated.len());
+        assert_eq!(vec!["one", "two"], read_lines(&rotated[0]));
+        assert_eq!(vec!["three", "four"], read_lines(&rotated[1]));
+        assert_eq!(vec!["five"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_never_splits_records() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new().max_size(1)).unwrap();
+
+        writer.write_all(b"{\"a\":").unwrap();
+        writer.write_all(b"1}\n{\"b\"").unwrap();
+        writer.write_all(b":2}\n").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(1, rotated.len());
+        assert_eq!(vec!["{\"a\":1}"], read_lines(&rotated[0]));
+        assert_eq!(vec!["{\"b\":2}"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_interval() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let policy = RotationPolicy::new().interval(Duration::from_millis(10));
+        let mut writer = RotatingFileWriter::open(&path, policy).unwrap();
+
+        writer.write_all(b"one\n").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        writer.write_all(b"two\n").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(1, rotated.len());
+        assert_eq!(vec!["one"], read_lines(&rotated[0]));
+        assert_eq!(vec!["two"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_keep() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let policy = RotationPolicy::new().max_size(1).keep(2);
+        let mut writer = RotatingFileWriter::open(&path, policy).unwrap();
+
+        writer.write_all(b"one\ntwo\nthree\nfour\nfive\n").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(2, rotated.len());
+        assert_eq!(vec!["three"], read_lines(&rotated[0]));
+        assert_eq!(vec!["four"], read_lines(&rotated[1]));
+        assert_eq!(vec!["five"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_appends_existing() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        fs::write(&path, "existing\n").unwrap();
+        fs::write(dir.path().join("out.json.old"), "unrelated\n").unwrap();
+
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new().max_size(4)).unwrap();
+        writer.write_all(b"new\n").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(1, rotated.len());
+        assert_eq!(vec!["existing"], read_lines(&rotated[0]));
+        assert_eq!(vec!["new"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_reopen() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let moved = dir.path().join("out.json.1");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new()).unwrap();
+
+        writer.write_all(b"one\n").unwrap();
+        fs::rename(&path, &moved).unwrap();
+        writer.write_all(b"two\n").unwrap();
+        writer.reopen().unwrap();
+        writer.write_all(b"three\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(vec!["one", "two"], read_lines(&moved));
+        assert_eq!(vec!["three"], read_lines(&path));
+        assert!(writer.rotated_files().unwrap().is_empty());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let ino = |p: &Path| fs::metadata(p).unwrap().ino();
+            assert_ne!(ino(&moved), ino(&path));
+        }
+    }
+
+    #[test]
+    fn test_rotating_file_writer_nul_delimiter() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new().max_size(4))
+            .unwrap()
+            .delimiter(Delimiter::Nul);
+
+        writer.write_all(b"one\ntwo\0three\0").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(1, rotated.len());
+        assert_eq!("one\ntwo\0", fs::read_to_string(&rotated[0]).unwrap());
+        assert_eq!("three\0", fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn test_delimiter_trim() {
+        assert_eq!(b"abc", Delimiter::Newline.trim(b"abc\n"));
+        assert_eq!(b"abc", Delimiter::Newline.trim(b"abc\r\n"));
+        assert_eq!(b"abc", Delimiter::Newline.trim(b"abc"));
+        assert_eq!(b"abc\n", Delimiter::Nul.trim(b"abc\n\0"));
+        assert_eq!(b"abc\r", Delimiter::Nul.trim(b"abc\r"));
+    }
+
+    #[test]
+    fn test_delimiter_from_str() {
+        assert_eq!(Delimiter::Newline, "newline".parse().unwrap());
+        assert_eq!(Delimiter::Nul, "nul".parse().unwrap());
+        assert!("tab".parse::<Delimiter>().unwrap_err().is_config_error());
+    }
+
+    fn read_records(input: &str, max: Option<usize>) -> Vec<(String, RecordRead)> {
+        // A tiny buffer so that records span many calls to fill the buffer
+        let mut reader = BufReader::with_capacity(3, Cursor::new(input.to_owned()));
+        let mut out = Vec::new();
+        let mut buf = Vec::new();
+
+        while let Some(read) = read_record(&mut reader, b'\n', max, &mut buf).unwrap() {
+            out.push((String::from_utf8(buf.clone()).unwrap(), read));
+            buf.clear();
+        }
+
+        out
+    }
+
+    fn record(bytes: usize, too_long: bool) -> RecordRead {
+        RecordRead { bytes, too_long }
+    }
+
+    #[test]
+    fn test_read_record_unlimited() {
+        let records = read_records("a\nbbbbbbbbbb\nc", None);
+        assert_eq!(
+            vec![
+                ("a\n".to_owned(), record(2, false)),
+                ("bbbbbbbbbb\n".to_owned(), record(11, false)),
+                ("c".to_owned(), record(1, false)),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn test_read_record_exactly_at_limit() {
+        let records = read_records("12345\n123\n", Some(5));
+        assert_eq!(
+            vec![
+                ("12345\n".to_owned(), record(6, false)),
+                ("123\n".to_owned(), record(4, false)),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn test_read_record_one_byte_over() {
+        let records = read_records("123456\nok\n", Some(5));
+        assert_eq!(
+            vec![
+                ("12345".to_owned(), record(7, true)),
+                ("ok\n".to_owned(), record(3, false)),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn test_read_record_no_delimiter_until_eof() {
+        let records = read_records("ok\n0123456789abcdefghij", Some(5));
+        assert_eq!(
+            vec![
+                ("ok\n".to_owned(), record(3, false)),
+                ("01234".to_owned(), record(20, true)),
+            ],
+            records
+        );
+
+        // Exactly at the limit without a delimiter is still a complete record
+        assert_eq!(
+            vec![("12345".to_owned(), record(5, false))],
+            read_records("12345", Some(5))
+        );
+    }
+
+    #[test]
+    fn test_read_record_bounded_buffer() {
+        let input = format!("{}\nend\n", "x".repeat(1_000_000));
+        let mut reader = Cursor::new(input);
+        let mut buf = Vec::new();
+
+        let read = read_record(&mut reader, b'\n', Some(16), &mut buf).unwrap().unwrap();
+        assert_eq!(record(1_000_001, true), read);
+        assert_eq!(16, buf.len());
+        assert!(buf.capacity() < 1024, "capacity {}", buf.capacity());
+
+        buf.clear();
+        read_record(&mut reader, b'\n', Some(16), &mut buf).unwrap().unwrap();
+        assert_eq!(b"end\n", buf.as_slice());
+        assert_eq!(None, read_record(&mut reader, b'\n', Some(16), &mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_rotated_name_default_key() {
+        let name = RotatedName::default();
+        let matcher = name.matcher("out.json");
+        let key = |f: &str| name.key(&matcher, f);
+
+        assert_eq!(Some(([2018, 10, 2, 13, 55, 36], 0)), key("out.json.20181002135536"));
+        assert_eq!(Some(([2018, 10, 2, 13, 55, 36], 12)), key("out.json.20181002135536.12"));
+        assert_eq!(None, key("out.json.2018100213553"));
+        assert_eq!(None, key("out.json.20181002135536.gz"));
+        assert_eq!(None, key("out.json.old"));
+        assert_eq!(None, key("other.json.20181002135536"));
+    }
+
+    #[test]
+    fn test_rotated_name_pattern() {
+        let name: RotatedName = "%d-%m-%Y_{name}.{seq}.log".parse().unwrap();
+        let time = Utc.ymd(2018, 10, 2).and_hms(13, 55, 36);
+
+        assert_eq!("02-10-2018_out.0.log", name.render("out", &time, None));
+        assert_eq!("02-10-2018_out.3.log", name.render("out", &time, Some(3)));
+        assert_eq!([2018, 10, 2, 0, 0, 0], name.time_key(&time));
+
+        // Days are before years in the name but files still sort by time.
+        let matcher = name.matcher("out");
+        let older = name.key(&matcher, "31-12-2017_out.9.log").unwrap();
+        let newer = name.key(&matcher, "01-01-2018_out.0.log").unwrap();
+        assert!(older < newer);
+    }
+
+    #[test]
+    fn test_rotated_name_parse() {
+        assert_eq!(
+            "100%% {name}",
+            "100%% {name}".parse::<RotatedName>().unwrap().to_string()
+        );
+        assert!("{name}.%j".parse::<RotatedName>().is_err());
+        assert!("{name}.{date}".parse::<RotatedName>().is_err());
+        assert!("old/{name}".parse::<RotatedName>().is_err());
+        assert!("".parse::<RotatedName>().is_err());
+    }
+
+    #[test]
+    fn test_rotating_file_writer_rotated_name() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let policy = RotationPolicy::new().max_size(1).keep(2);
+        let mut writer = RotatingFileWriter::open(&path, policy)
+            .unwrap()
+            .rotated_name("archive-{name}-{seq}".parse().unwrap())
+            .sync_on_rotate(false);
+
+        writer.write_all(b"one\ntwo\nthree\nfour\n").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(
+            vec![
+                dir.path().join("archive-out.json-1"),
+                dir.path().join("archive-out.json-2")
+            ],
+            rotated
+        );
+        assert_eq!(vec!["two"], read_lines(&rotated[0]));
+        assert_eq!(vec!["three"], read_lines(&rotated[1]));
+        assert_eq!(vec!["four"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_name_taken() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new())
+            .unwrap()
+            .rotated_name("{name}.old".parse().unwrap());
+
+        // Another program already created a file with the rotated name.
+        fs::write(dir.path().join("out.json.old"), "other\n").unwrap();
+        writer.write_all(b"one\n").unwrap();
+        writer.rotate().unwrap();
+        writer.write_all(b"two\n").unwrap();
+        writer.rotate().unwrap();
+
+        assert_eq!(vec!["other"], read_lines(&dir.path().join("out.json.old")));
+        assert_eq!(vec!["one"], read_lines(&dir.path().join("out.json.old.1")));
+        assert_eq!(vec!["two"], read_lines(&dir.path().join("out.json.old.2")));
+    }
+
+    #[test]
+    fn test_rotating_file_writer_drop() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new()).unwrap();
+        writer.write_all(b"one\ntwo\n").unwrap();
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+
+        drop(writer);
+        assert_eq!("one\ntwo\n", fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn test_rotating_file_writer_close() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new()).unwrap();
+        writer.write_all(b"one\n").unwrap();
+        writer.close().unwrap();
+        assert_eq!("one\n", fs::read_to_string(&path).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rotating_file_writer_close_error() {
+        // Writes to /dev/full fail with ENOSPC once they reach the device
+        let mut writer = RotatingFileWriter::open("/dev/full", RotationPolicy::new()).unwrap();
+        writer.write_all(b"one\n").unwrap();
+        assert!(writer.close().is_err());
+    }
+
+    #[test]
+    fn test_rotating_file_writer_write_record() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let mut writer = RotatingFileWriter::open(&path, RotationPolicy::new().max_size(1))
+            .unwrap()
+            .rotated_name("{name}.{seq}".parse().unwrap());
+
+        // Records without a delimiter are still never split or rotated mid-way.
+        writer.write_record(b"{\"a\":1}").unwrap();
+        writer.write_record(b"{\"b\":2}").unwrap();
+        writer.write_record(b"").unwrap();
+        writer.flush().unwrap();
+
+        let rotated = writer.rotated_files().unwrap();
+        assert_eq!(1, rotated.len());
+        assert_eq!("{\"a\":1}", fs::read_to_string(&rotated[0]).unwrap());
+        assert_eq!("{\"b\":2}", fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn test_rotating_file_writer_rotate_after_external_rename() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.json");
+        let policy = RotationPolicy::new().max_size(4).keep(1);
+        let mut writer = RotatingFileWriter::open(&path, policy)
+            .unwrap()
+            .rotated_name("{name}.{seq}".parse().unwrap());
+
+        writer.write_all(b"one\n").unwrap();
+        writer.flush().unwrap();
+        fs::rename(&path, dir.path().join("moved.json")).unwrap();
+        writer.reopen().unwrap();
+
+        // The reopened file is empty so it isn't rotated until it's written to.
+        writer.write_all(b"two\n").unwrap();
+        writer.write_all(b"three\n").unwrap();
+        writer.write_all(b"four\n").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(vec!["one"], read_lines(&dir.path().join("moved.json")));
+        assert_eq!(vec![dir.path().join("out.json.1")], writer.rotated_files().unwrap());
+        assert_eq!(vec!["three"], read_lines(&dir.path().join("out.json.1")));
+        assert_eq!(vec!["four"], read_lines(&path));
+    }
+
+    #[test]
+    fn test_periodic_flusher() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("events.log");
+        let file = fs::File::create(&path).unwrap();
+
+        let mut writer = PeriodicFlusher::start(BufWriter::new(file), Duration::from_millis(10)).unwrap();
+        writer.write_all(b"{\"a\":1}\n").unwrap();
+
+        // The buffer is far from full but is flushed by the background thread.
+        let mut contents = String::new();
+        for _ in 0..100 {
+            contents = fs::read_to_string(&path).unwrap();
+            if !contents.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!("{\"a\":1}\n", contents);
+        writer.stop();
+        writer.write_all(b"{\"a\":2}\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!("{\"a\":1}\n{\"a\":2}\n", fs::read_to_string(&path).unwrap());
+    }
+
+    /// Writer that keeps everything written to it and, separately, what had been
+    /// written as of the last flush.
+    #[derive(Debug, Default)]
+    struct Recorder {
+        written: Vec<u8>,
+        flushed: Vec<u8>,
+        flushes: usize,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed = self.written.clone();
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flushing_writer_every_write() {
+        let mut writer = FlushingWriter::new(Recorder::default(), FlushPolicy::EveryWrite);
+        writer.write_all(b"one\n").unwrap();
+        assert_eq!(b"one\n", &writer.get_ref().flushed[..]);
+        writer.write_all(b"two\n").unwrap();
+        assert_eq!(b"one\ntwo\n", &writer.get_ref().flushed[..]);
+
+        // Nothing has been written since the last flush.
+        assert!(!writer.flush_if_due().unwrap());
+        assert_eq!(2, writer.get_ref().flushes);
+    }
+
+    #[test]
+    fn test_flushing_writer_bytes() {
+        let mut writer = FlushingWriter::new(Recorder::default(), FlushPolicy::Bytes(10));
+        writer.write_all(b"1234\n").unwrap();
+        writer.write_all(b"567\n").unwrap();
+        assert_eq!(9, writer.pending());
+        assert!(!writer.flush_if_due().unwrap());
+        assert!(writer.get_ref().flushed.is_empty());
+
+        writer.write_all(b"8\n").unwrap();
+        assert_eq!(0, writer.pending());
+        assert_eq!(b"1234\n567\n8\n", &writer.get_ref().flushed[..]);
+    }
+
+    #[test]
+    fn test_flushing_writer_interval() {
+        let mut writer = FlushingWriter::new(Recorder::default(), FlushPolicy::Interval(Duration::from_millis(50)));
+        writer.write_all(b"one\n").unwrap();
+        assert!(!writer.flush_if_due().unwrap());
+        assert!(writer.get_ref().flushed.is_empty());
+
+        thread::sleep(Duration::from_millis(60));
+        assert!(writer.flush_if_due().unwrap());
+        assert_eq!(b"one\n", &writer.get_ref().flushed[..]);
+
+        // An idle writer isn't flushed again even though the interval has passed.
+        thread::sleep(Duration::from_millis(60));
+        assert!(!writer.flush_if_due().unwrap());
+
+        // A write after the interval has passed is flushed right away.
+        writer.write_all(b"two\n").unwrap();
+        assert_eq!(b"one\ntwo\n", &writer.get_ref().flushed[..]);
+        assert_eq!(2, writer.get_ref().flushes);
+    }
+
+    #[test]
+    fn test_flushing_writer_into_inner() {
+        let mut writer = FlushingWriter::new(Recorder::default(), FlushPolicy::Bytes(1024));
+        writer.write_all(b"one\n").unwrap();
+        writer.write_all(b"two\n").unwrap();
+
+        let recorder = writer.into_inner().unwrap();
+        assert_eq!(b"one\ntwo\n", &recorder.flushed[..]);
+        assert_eq!(recorder.written, recorder.flushed);
+    }
+
+    /// Writer that only makes data visible to the test when flushed, which it
+    /// doesn't do by itself when dropped, and that fails to flush if `fail` is set.
+    #[derive(Default)]
+    struct Buffered {
+        pending: Vec<u8>,
+        flushed: Arc<Mutex<Vec<u8>>>,
+        fail: bool,
+    }
+
+    impl Buffered {
+        fn failing() -> Self {
+            Buffered {
+                fail: true,
+                ..Buffered::default()
+            }
+        }
+    }
+
+    impl Write for Buffered {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if self.fail {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "injected failure"));
+            }
+
+            self.flushed.lock().unwrap().append(&mut self.pending);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flushing_writer_drop() {
+        let inner = Buffered::default();
+        let flushed = inner.flushed.clone();
+
+        let mut writer = FlushingWriter::new(inner, FlushPolicy::Bytes(1024));
+        writer.write_all(b"one\n").unwrap();
+        writer.write_all(b"two\n").unwrap();
+        assert!(flushed.lock().unwrap().is_empty());
+
+        drop(writer);
+        assert_eq!(b"one\ntwo\n", &flushed.lock().unwrap()[..]);
+    }
+
+    #[test]
+    fn test_flushing_writer_close() {
+        let inner = Buffered::default();
+        let flushed = inner.flushed.clone();
+
+        let mut writer = FlushingWriter::new(inner, FlushPolicy::Bytes(1024));
+        writer.write_all(b"one\n").unwrap();
+        writer.close().unwrap();
+        assert_eq!(b"one\n", &flushed.lock().unwrap()[..]);
+
+        let mut writer = FlushingWriter::new(Buffered::failing(), FlushPolicy::Bytes(1024));
+        writer.write_all(b"one\n").unwrap();
+        assert!(writer.close().is_err());
+    }
+
+    #[test]
+    fn test_periodic_flusher_drop() {
+        let inner = Buffered::default();
+        let flushed = inner.flushed.clone();
+
+        let mut writer = PeriodicFlusher::start(inner, Duration::from_secs(3600)).unwrap();
+        writer.write_all(b"one\n").unwrap();
+        assert!(flushed.lock().unwrap().is_empty());
+
+        drop(writer);
+        assert_eq!(b"one\n", &flushed.lock().unwrap()[..]);
+    }
+
+    #[test]
+    fn test_periodic_flusher_close() {
+        let inner = Buffered::default();
+        let flushed = inner.flushed.clone();
+
+        let mut writer = PeriodicFlusher::start(inner, Duration::from_secs(3600)).unwrap();
+        writer.write_all(b"one\n").unwrap();
+        writer.close().unwrap();
+        assert_eq!(b"one\n", &flushed.lock().unwrap()[..]);
+
+        let mut writer = PeriodicFlusher::start(Buffered::failing(), Duration::from_secs(3600)).unwrap();
+        writer.write_all(b"one\n").unwrap();
+        assert!(writer.close().is_err());
+    }
+
+    /// Owned reader that returns at most a few bytes from each read.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        size: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = cmp::min(self.pos + cmp::min(self.size, buf.len()), self.data.len());
+            let n = end - self.pos;
+            buf[..n].copy_from_slice(&self.data[self.pos..end]);
+            self.pos = end;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_line_source_reader_small_chunks() {
+        let data: String = (0..10_000).map(|i| format!("line {}\n", i)).collect();
+        let reader = Trickle {
+            data: data.into_bytes(),
+            pos: 0,
+            size: 5,
+        };
+
+        let lines: Vec<String> = LineSource::reader(reader, LineDecoder::new())
+            .map(|l| l.unwrap())
+            .collect();
+
+        assert_eq!(10_000, lines.len());
+        assert_eq!("line 0", lines[0]);
+        assert_eq!("line 9999", lines[9999]);
+    }
+
+    #[test]
+    fn test_line_source_open_large_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let long = "x".repeat(100);
+        let data: String = (0..50_000)
+            .map(|i| {
+                if i % 1000 == 0 {
+                    format!("{}\n", long)
+                } else {
+                    format!("line {}\n", i)
+                }
+            })
+            .collect();
+        fs::write(&path, data).unwrap();
+
+        let decoder = LineDecoder::new().max_record_bytes(Some(64));
+        let results: Vec<_> = LineSource::open(&path, true, decoder).collect();
+
+        assert_eq!(50_000, results.len());
+        assert!(results[0].as_ref().unwrap_err().is_record_too_long_error());
+        assert_eq!("line 49999", results[49_999].as_ref().unwrap());
+        assert_eq!(50, results.iter().filter(|r| r.is_err()).count());
+    }
+
+    #[test]
+    fn test_line_source_open_missing() {
+        let dir = TempDir::new().unwrap();
+        let mut source = LineSource::open(dir.path().join("missing.log"), true, LineDecoder::new());
+
+        assert!(source.next().unwrap().unwrap_err().is_io_error());
+        assert!(source.next().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_line_source_open_fifo() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("access.fifo");
+        let status = std::process::Command::new("mkfifo").arg(&path).status().unwrap();
+        assert!(status.success());
+
+        // Nothing has opened the FIFO for writing yet, which would block opening it.
+        let source = LineSource::open(&path, true, LineDecoder::new());
+
+        let writer_path = path.clone();
+        let writer = thread::spawn(move || {
+            let mut fifo = fs::OpenOptions::new().write(true).open(writer_path).unwrap();
+            for i in 0..3 {
+                writeln!(fifo, "line {}", i).unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let lines: Vec<String> = source.map(|l| l.unwrap()).collect();
+        writer.join().unwrap();
+        assert_eq!(vec!["line 0", "line 1", "line 2"], lines);
+    }
+
+    /// Reader that returns at most a few bytes at a time, so lines are split
+    /// across reads.
+    struct Chunked<'a> {
+        data: &'a [u8],
+        size: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.fill_buf()?.read(buf)?;
+            self.consume(n);
+            Ok(n)
+        }
+    }
+
+    impl BufRead for Chunked<'_> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            Ok(&self.data[..self.size.min(self.data.len())])
+        }
+
+        fn consume(&mut self, amt: usize) {
+            self.data = &self.data[amt..];
+        }
+    }
+
+    /// Lines as strings, or a description of the error for each line that is one.
+    fn decoded(decoder: LineDecoder, data: &[u8], size: usize) -> Vec<String> {
+        decoder
+            .lines(Chunked { data, size })
+            .map(|r| match r {
+                Ok(line) => line,
+                Err(RedeyeError::RecordTooLong(max)) => format!("<too long: {}>", max),
+                Err(e) if e.is_io_error() => "<io error>".to_owned(),
+                Err(e) => panic!("Unexpected result: {:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_line_decoder_lines() {
+        let data = b"first\nsecond\r\n\nlast";
+        for size in 1..=data.len() {
+            assert_eq!(
+                vec!["first", "second", "", "last"],
+                decoded(LineDecoder::new(), data, size),
+                "chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_line_decoder_max_record_bytes() {
+        let data = b"abcde\nabcde\r\nabcdef\nabcdefghijklmnop\nxyz\r\nabcdefghi";
+        let decoder = LineDecoder::new().max_record_bytes(Some(5));
+        for size in 1..=data.len() {
+            assert_eq!(
+                vec![
+                    "abcde",
+                    "abcde",
+                    "<too long: 5>",
+                    "<too long: 5>",
+                    "xyz",
+                    "<too long: 5>"
+                ],
+                decoded(decoder.clone(), data, size),
+                "chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_line_decoder_invalid_utf8() {
+        let data = b"ok\n\xff\xfe\nalso ok\n";
+        for size in 1..=data.len() {
+            assert_eq!(
+                vec!["ok", "\u{fffd}\u{fffd}", "also ok"],
+                decoded(LineDecoder::new(), data, size),
+                "chunks of {}",
+                size
+            );
+            assert_eq!(
+                vec!["ok", "also ok"],
+                decoded(LineDecoder::new().utf8(Utf8Policy::Skip), data, size),
+                "chunks of {}",
+                size
+            );
+            assert_eq!(
+                vec!["ok", "<io error>", "also ok"],
+                decoded(LineDecoder::new().utf8(Utf8Policy::Error), data, size),
+                "chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_line_decoder_skipped() {
+        let mut decoder = LineDecoder::new().utf8(Utf8Policy::Skip);
+        let mut buf = b"\xff\n\xfe\nok\n\xfd".to_vec();
+
+        assert_eq!("ok", decoder.decode(&mut buf).unwrap().unwrap());
+        assert!(decoder.decode_eof(&mut buf).is_none());
+        assert_eq!(3, decoder.skipped());
+    }
+
+    #[test]
+    fn test_utf8_policy() {
+        let invalid = b"GET /\xc0\xaf";
+        assert_eq!(
+            "GET /\u{fffd}\u{fffd}",
+            Utf8Policy::Lossy.decode(invalid).unwrap().unwrap()
+        );
+        assert_eq!(None, Utf8Policy::Skip.decode(invalid).unwrap());
+        assert!(Utf8Policy::Error.decode(invalid).unwrap_err().is_io_error());
+        assert_eq!("GET /", Utf8Policy::Skip.decode(b"GET /").unwrap().unwrap());
+
+        assert_eq!(Utf8Policy::Skip, "skip".parse().unwrap());
+        assert!("replace".parse::<Utf8Policy>().is_err());
+    }
+
+    #[test]
+    fn test_line_decoder_nul_delimiter() {
+        let data = b"one\r\0two\nthree\0";
+        let decoder = LineDecoder::new().delimiter(Delimiter::Nul).max_record_bytes(Some(9));
+        for size in 1..=data.len() {
+            assert_eq!(
+                vec!["one\r", "two\nthree"],
+                decoded(decoder.clone(), data, size),
+                "chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn test_line_decoder_bounded_buffer() {
+        let mut decoder = LineDecoder::new().max_record_bytes(Some(10));
+        let mut buf = Vec::new();
+        let mut errors = 0;
+
+        for _ in 0..1000 {
+            buf.extend_from_slice(&[b'x'; 100]);
+            while let Some(line) = decoder.decode(&mut buf) {
+                assert!(line.unwrap_err().is_record_too_long_error());
+                errors += 1;
+            }
+            assert!(buf.len() <= 100, "{} bytes buffered", buf.len());
+        }
+
+        buf.extend_from_slice(b"x\nnext\n");
+        assert_eq!("next", decoder.decode(&mut buf).unwrap().unwrap());
+        assert!(decoder.decode_eof(&mut buf).is_none());
+        assert_eq!(1, errors);
+    }
+
+    #[test]
+    fn test_line_decoder_partial_decode() {
+        let mut decoder = LineDecoder::new();
+        let mut buf = b"par".to_vec();
+        assert!(decoder.decode(&mut buf).is_none());
+
+        buf.extend_from_slice(b"tial\r");
+        assert!(decoder.decode(&mut buf).is_none());
+
+        buf.extend_from_slice(b"\nnext");
+        assert_eq!("partial", decoder.decode(&mut buf).unwrap().unwrap());
+        assert!(decoder.decode(&mut buf).is_none());
+        assert_eq!("next", decoder.decode_eof(&mut buf).unwrap().unwrap());
+        assert!(decoder.decode_eof(&mut buf).is_none());
+    }
+
+    /// Reader that fails after returning some data.
+    struct FailingReader(bool);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 {
+                return Err(io::Error::other("broken"));
+            }
+            self.0 = true;
+            (&b"one\ntw"[..]).read(buf)
+        }
+    }
+
+    #[test]
+    fn test_line_decoder_read_error() {
+        let mut lines = LineDecoder::new().lines(BufReader::new(FailingReader(false)));
+        assert_eq!("one", lines.next().unwrap().unwrap());
+        assert!(lines.next().unwrap().unwrap_err().is_io_error());
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_input_spec_parse() {
+        let spec: InputSpec = " format = haproxy-http , path=/var/log/haproxy.log".parse().unwrap();
+        assert_eq!(InputSpec::new("/var/log/haproxy.log", "haproxy-http"), spec);
+        assert_eq!("path=/var/log/haproxy.log,format=haproxy-http", spec.to_string());
+
+        for (input, err) in &[
+            ("path=a.log", "missing format"),
+            ("format=common", "missing path"),
+            (
+                "path=a.log,format=common,workers=2",
+                "unknown key 'workers', expected one of path, format",
+            ),
+            ("path=a.log,path=b.log,format=common", "'path' given more than once"),
+            ("path=a.log,format=", "'format' is empty"),
+            ("a.log", "expected key=value pairs"),
+        ] {
+            let res = input.parse::<InputSpec>();
+            assert_eq!(
+                format!("invalid input '{}': {}", input, err),
+                res.unwrap_err().to_string(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_input_spec_files() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("b.log"), "").unwrap();
+        fs::write(dir.path().join("a.log"), "").unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+
+        let pattern = format!("{}/*.log", dir.path().display());
+        let files = InputSpec::new(pattern, "common").files().unwrap();
+        assert_eq!(vec![dir.path().join("a.log"), dir.path().join("b.log")], files);
+
+        let missing = dir.path().join("missing.log");
+        let files = InputSpec::new(missing.to_str().unwrap(), "common").files().unwrap();
+        assert_eq!(vec![missing], files);
+
+        let pattern = format!("{}/*.gz", dir.path().display());
+        assert!(InputSpec::new(pattern, "common").files().is_err());
+    }
+}
@@ -0,0 +1,571 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Reading log entries from the systemd journal
+//!
+//! Entries are read in the journal export format written by `journalctl -o export`.
+//! Each entry is a series of fields followed by an empty line. Fields are written
+//! as `NAME=value` lines unless the value contains a newline or other control
+//! characters, in which case the name is followed by a newline, the length of the
+//! value as a little-endian 64 bit integer, the value itself, and another newline.
+//!
+//! Following the journal requires the `journal` feature, which runs `journalctl`
+//! as a child process.
+
+use crate::follow::FollowFrom;
+use crate::types::LogFieldValue;
+use chrono::{FixedOffset, TimeZone, Utc};
+use std::io::{self, BufRead, Read};
+use std::str;
+
+/// Largest value of a single binary field that will be read.
+const MAX_FIELD_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Single entry from the journal with each of its fields, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JournalEntry {
+    fields: Vec<(String, Vec<u8>)>,
+}
+
+impl JournalEntry {
+    /// Value of the first field with the given name, if there is one.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_slice())
+    }
+
+    /// Text of the entry, the `MESSAGE` field.
+    pub fn message(&self) -> Option<&[u8]> {
+        self.get("MESSAGE")
+    }
+
+    /// Position of the entry in the journal, the `__CURSOR` field.
+    pub fn cursor(&self) -> Option<&str> {
+        self.get("__CURSOR").and_then(|v| str::from_utf8(v).ok())
+    }
+
+    /// Fields added to the event parsed from the entry: the host as `journal_hostname`,
+    /// the unit as `journal_unit`, and the time the entry was written to the journal
+    /// as `received_at`. Fields that the entry doesn't have are left out.
+    pub fn metadata(&self) -> Vec<(&'static str, LogFieldValue)> {
+        let mut out = Vec::new();
+        let text = |name| self.get(name).map(|v| String::from_utf8_lossy(v).into_owned());
+
+        if let Some(host) = text("_HOSTNAME") {
+            out.push(("journal_hostname", LogFieldValue::Text(host)));
+        }
+
+        if let Some(unit) = text("_SYSTEMD_UNIT") {
+            out.push(("journal_unit", LogFieldValue::Text(unit)));
+        }
+
+        let received = text("__REALTIME_TIMESTAMP")
+            .and_then(|v| v.parse::<i64>().ok())
+            .and_then(|us| {
+                Utc.timestamp_opt(us.div_euclid(1_000_000), us.rem_euclid(1_000_000) as u32 * 1000)
+                    .single()
+            });
+
+        if let Some(ts) = received {
+            out.push((
+                "received_at",
+                LogFieldValue::Timestamp(ts.with_timezone(&FixedOffset::east(0))),
+            ));
+        }
+
+        out
+    }
+}
+
+/// Reader of journal entries in the export format.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::journal::ExportReader;
+///
+/// let export = b"__CURSOR=s=1;i=2\nMESSAGE=hello\n\n";
+/// let mut reader = ExportReader::new(&export[..]);
+///
+/// let entry = reader.read_entry().unwrap().unwrap();
+/// assert_eq!(Some(&b"hello"[..]), entry.message());
+/// assert_eq!(Some("s=1;i=2"), entry.cursor());
+/// assert!(reader.read_entry().unwrap().is_none());
+/// ```
+#[derive(Debug)]
+pub struct ExportReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R> ExportReader<R>
+where
+    R: BufRead,
+{
+    /// Create a reader of entries from the export format.
+    pub fn new(reader: R) -> Self {
+        ExportReader {
+            reader,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Read the next entry, or `None` at the end of the input. An error is returned
+    /// if the input ends partway through an entry or a field is malformed.
+    pub fn read_entry(&mut self) -> io::Result<Option<JournalEntry>> {
+        let mut entry = JournalEntry::default();
+
+        loop {
+            self.buf.clear();
+            if self.reader.read_until(b'\n', &mut self.buf)? == 0 {
+                return if entry.fields.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete journal entry"))
+                };
+            }
+
+            if self.buf.last() != Some(&b'\n') {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete journal entry"));
+            }
+
+            let line = &self.buf[..self.buf.len() - 1];
+            if line.is_empty() {
+                if entry.fields.is_empty() {
+                    continue;
+                }
+
+                return Ok(Some(entry));
+            }
+
+            let field = match line.iter().position(|&b| b == b'=') {
+                Some(i) => (field_name(&line[..i])?, line[i + 1..].to_vec()),
+                None => {
+                    let name = field_name(line)?;
+                    (name, self.read_binary()?)
+                }
+            };
+
+            entry.fields.push(field);
+        }
+    }
+
+    /// Read the length and value of a binary field, and the newline after it.
+    fn read_binary(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0; 8];
+        self.reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        if len > MAX_FIELD_BYTES {
+            return Err(invalid(format!("journal field of {} bytes is too large", len)));
+        }
+
+        let mut value = Vec::with_capacity(len as usize);
+        (&mut self.reader).take(len).read_to_end(&mut value)?;
+        if value.len() as u64 != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete journal entry"));
+        }
+
+        let mut newline = [0; 1];
+        self.reader.read_exact(&mut newline)?;
+        if newline[0] != b'\n' {
+            return Err(invalid("missing newline after journal field".to_owned()));
+        }
+
+        Ok(value)
+    }
+}
+
+/// Validate the name of a field, which may only contain uppercase letters, digits,
+/// and underscores.
+fn field_name(name: &[u8]) -> io::Result<String> {
+    let valid = !name.is_empty()
+        && name
+            .iter()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || *b == b'_');
+
+    if !valid {
+        return Err(invalid(format!(
+            "invalid journal field name '{}'",
+            String::from_utf8_lossy(name)
+        )));
+    }
+
+    // Only ASCII characters are allowed so this is always valid UTF-8
+    Ok(String::from_utf8_lossy(name).into_owned())
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Options for following the journal with `journalctl`.
+#[derive(Debug, Clone)]
+pub struct JournalOptions {
+    program: String,
+    units: Vec<String>,
+    from: FollowFrom,
+    after_cursor: Option<String>,
+}
+
+impl JournalOptions {
+    /// Follow entries from every unit, starting from the beginning or end of the journal.
+    pub fn new(from: FollowFrom) -> Self {
+        JournalOptions {
+            program: "journalctl".to_owned(),
+            units: Vec::new(),
+            from,
+            after_cursor: None,
+        }
+    }
+
+    /// Only follow entries from the given units.
+    pub fn units(mut self, units: Vec<String>) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Start after the entry with the given cursor instead of the beginning or end.
+    pub fn after_cursor(mut self, cursor: Option<String>) -> Self {
+        self.after_cursor = cursor;
+        self
+    }
+
+    /// Run this program instead of `journalctl`.
+    pub fn program<S>(mut self, program: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.program = program.into();
+        self
+    }
+
+    /// Arguments to pass to `journalctl`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["--output=export".to_owned(), "--follow".to_owned()];
+        args.extend(self.units.iter().map(|u| format!("--unit={}", u)));
+
+        match (&self.after_cursor, self.from) {
+            (Some(cursor), _) => {
+                args.push("--lines=all".to_owned());
+                args.push(format!("--after-cursor={}", cursor));
+            }
+            (None, FollowFrom::Start) => args.push("--lines=all".to_owned()),
+            (None, FollowFrom::End) => args.push("--lines=0".to_owned()),
+        }
+
+        args
+    }
+}
+
+#[cfg(feature = "journal")]
+pub use self::follower::JournalFollower;
+
+#[cfg(feature = "journal")]
+mod follower {
+    use super::{ExportReader, JournalEntry, JournalOptions};
+    use crate::types::RedeyeResult;
+    use crate::watch::StateFile;
+    use std::io::{self, BufRead};
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Default amount of time to wait for an entry before returning.
+    const DEFAULT_TIMEOUT: Duration = Duration::from_millis(250);
+
+    /// Number of entries read ahead of those being handled.
+    const READ_AHEAD: usize = 1024;
+
+    /// Follower of journal entries, read from `journalctl` running as a child process.
+    ///
+    /// Entries are read on a separate thread so that waiting for one can time out,
+    /// letting callers check if they should stop. `journalctl` is stopped when the
+    /// follower is dropped. When a state file is used, the cursor of the last entry
+    /// handled is saved to it and following starts after that entry when restarted.
+    #[derive(Debug)]
+    pub struct JournalFollower {
+        child: Option<Child>,
+        program: String,
+        entries: Receiver<io::Result<JournalEntry>>,
+        timeout: Duration,
+        state: Option<StateFile>,
+        cursor: Option<String>,
+    }
+
+    impl JournalFollower {
+        /// Start `journalctl` to follow entries, continuing after the cursor saved in
+        /// the state file if there is one. The state file doesn't need to exist.
+        pub fn spawn(opts: JournalOptions, state_file: Option<PathBuf>) -> RedeyeResult<Self> {
+            let state = state_file.map(StateFile::load).transpose()?;
+            let opts = match state.as_ref().and_then(|s| s.cursor()) {
+                Some(cursor) => opts.after_cursor(Some(cursor.to_owned())),
+                None => opts,
+            };
+
+            let mut child = Command::new(&opts.program)
+                .args(opts.args())
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|e| io::Error::new(e.kind(), format!("Unable to run {}: {}", opts.program, e)))?;
+
+            let stdout = child.stdout.take().expect("stdout of journalctl is piped");
+            let mut follower = Self::start(io::BufReader::new(stdout), state);
+            follower.child = Some(child);
+            follower.program = opts.program;
+            Ok(follower)
+        }
+
+        /// Read entries in the export format from a reader instead of `journalctl`,
+        /// saving cursors to the state file if there is one.
+        pub fn from_reader<R>(reader: R, state_file: Option<PathBuf>) -> RedeyeResult<Self>
+        where
+            R: BufRead + Send + 'static,
+        {
+            let state = state_file.map(StateFile::load).transpose()?;
+            Ok(Self::start(reader, state))
+        }
+
+        /// Read entries from a reader on a new thread.
+        fn start<R>(reader: R, state: Option<StateFile>) -> Self
+        where
+            R: BufRead + Send + 'static,
+        {
+            let (tx, rx) = mpsc::sync_channel(READ_AHEAD);
+            // The thread ends at the end of the input or once the follower, and so the
+            // receiver, is dropped.
+            thread::spawn(move || {
+                let mut reader = ExportReader::new(reader);
+                loop {
+                    let res = match reader.read_entry() {
+                        Ok(Some(entry)) => Ok(entry),
+                        Ok(None) => break,
+                        Err(e) => Err(e),
+                    };
+
+                    let failed = res.is_err();
+                    if tx.send(res).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+
+            JournalFollower {
+                child: None,
+                program: "journal".to_owned(),
+                entries: rx,
+                timeout: DEFAULT_TIMEOUT,
+                state,
+                cursor: None,
+            }
+        }
+
+        /// Set how long to wait for an entry before returning `None`.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        /// Next entry from the journal, or `None` if there isn't one within the timeout.
+        /// An error is returned if `journalctl` exits or its output can't be parsed.
+        pub fn receive(&mut self) -> io::Result<Option<JournalEntry>> {
+            match self.entries.recv_timeout(self.timeout) {
+                Ok(Ok(entry)) => {
+                    if let Some(cursor) = entry.cursor() {
+                        self.cursor = Some(cursor.to_owned());
+                    }
+
+                    Ok(Some(entry))
+                }
+                Ok(Err(e)) => Err(e),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let msg = match self.child.as_mut().map(|c| c.wait()) {
+                        Some(Ok(status)) => format!("{} exited ({})", self.program, status),
+                        Some(Err(e)) => format!("{} exited: {}", self.program, e),
+                        None => format!("{} ended", self.program),
+                    };
+
+                    Err(io::Error::new(io::ErrorKind::UnexpectedEof, msg))
+                }
+            }
+        }
+
+        /// Save the cursor of the last entry received to the state file, if there is
+        /// one. Only call this once entries that have been received have been handled.
+        pub fn save_state(&mut self) -> io::Result<()> {
+            match (&mut self.state, &self.cursor) {
+                (Some(state), Some(cursor)) => state.save_cursor(cursor),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl Drop for JournalFollower {
+        fn drop(&mut self) {
+            if let Some(ref mut child) = self.child {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportReader, JournalEntry, JournalOptions};
+    use crate::follow::FollowFrom;
+    use crate::types::LogFieldValue;
+    use std::io;
+
+    fn binary_field(name: &str, value: &[u8]) -> Vec<u8> {
+        let mut out = format!("{}\n", name).into_bytes();
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        out.extend_from_slice(value);
+        out.push(b'\n');
+        out
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let mut export = b"__CURSOR=s=1;i=1\n__REALTIME_TIMESTAMP=1600000000123456\nMESSAGE=first\n\n".to_vec();
+        export.extend_from_slice(b"__CURSOR=s=1;i=2\n");
+        export.extend(binary_field("MESSAGE", b"second\nline"));
+        export.extend_from_slice(b"_HOSTNAME=web1\n\n");
+
+        let mut reader = ExportReader::new(&export[..]);
+        let first = reader.read_entry().unwrap().unwrap();
+        assert_eq!(Some(&b"first"[..]), first.message());
+        assert_eq!(Some("s=1;i=1"), first.cursor());
+
+        let second = reader.read_entry().unwrap().unwrap();
+        assert_eq!(Some(&b"second\nline"[..]), second.message());
+        assert_eq!(Some(&b"web1"[..]), second.get("_HOSTNAME"));
+        assert_eq!(Some("s=1;i=2"), second.cursor());
+
+        assert_eq!(None, reader.read_entry().unwrap());
+    }
+
+    #[test]
+    fn test_read_value_with_equals() {
+        let mut reader = ExportReader::new(&b"MESSAGE=GET /?a=b\n\n"[..]);
+        let entry = reader.read_entry().unwrap().unwrap();
+        assert_eq!(Some(&b"GET /?a=b"[..]), entry.message());
+    }
+
+    #[test]
+    fn test_read_incomplete_entry() {
+        let mut reader = ExportReader::new(&b"MESSAGE=first\n"[..]);
+        let err = reader.read_entry().unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        let mut export = b"MESSAGE\n".to_vec();
+        export.extend_from_slice(&10u64.to_le_bytes());
+        export.extend_from_slice(b"short");
+        let mut reader = ExportReader::new(&export[..]);
+        let err = reader.read_entry().unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn test_read_invalid_field() {
+        let mut reader = ExportReader::new(&b"not a field\n\n"[..]);
+        let err = reader.read_entry().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("not a field"), "{}", err);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let export = b"_HOSTNAME=web1\n_SYSTEMD_UNIT=nginx.service\n__REALTIME_TIMESTAMP=1600000000123456\n\n";
+        let entry = ExportReader::new(&export[..]).read_entry().unwrap().unwrap();
+        let metadata = entry.metadata();
+
+        assert_eq!(3, metadata.len());
+        assert_eq!("journal_hostname", metadata[0].0);
+        assert_eq!(LogFieldValue::Text("web1".to_owned()), metadata[0].1);
+        assert_eq!("journal_unit", metadata[1].0);
+        assert_eq!(LogFieldValue::Text("nginx.service".to_owned()), metadata[1].1);
+        assert_eq!("received_at", metadata[2].0);
+        match metadata[2].1 {
+            LogFieldValue::Timestamp(ts) => assert_eq!("2020-09-13T12:26:40.123456+00:00", ts.to_rfc3339()),
+            ref other => panic!("unexpected value {:?}", other),
+        }
+
+        assert!(JournalEntry::default().metadata().is_empty());
+    }
+
+    #[test]
+    fn test_options_args() {
+        let args = JournalOptions::new(FollowFrom::End)
+            .units(vec!["nginx.service".to_owned(), "apache2.service".to_owned()])
+            .args();
+        assert_eq!(
+            vec![
+                "--output=export",
+                "--follow",
+                "--unit=nginx.service",
+                "--unit=apache2.service",
+                "--lines=0"
+            ],
+            args
+        );
+
+        let args = JournalOptions::new(FollowFrom::Start).args();
+        assert_eq!(vec!["--output=export", "--follow", "--lines=all"], args);
+
+        let args = JournalOptions::new(FollowFrom::End)
+            .after_cursor(Some("s=1;i=2".to_owned()))
+            .args();
+        assert_eq!(
+            vec!["--output=export", "--follow", "--lines=all", "--after-cursor=s=1;i=2"],
+            args
+        );
+    }
+
+    #[cfg(feature = "journal")]
+    #[test]
+    fn test_follower_state_file() {
+        use super::JournalFollower;
+        use std::fs;
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state.json");
+        let export = b"__CURSOR=s=1;i=1\nMESSAGE=first\n\n__CURSOR=s=1;i=2\nMESSAGE=second\n\n";
+
+        // Exiting without any more entries is an error since the journal is followed
+        let mut follower = JournalFollower::spawn(JournalOptions::new(FollowFrom::End).program("true"), None)
+            .unwrap()
+            .timeout(Duration::from_secs(5));
+        let err = follower.receive().unwrap_err();
+        assert!(err.to_string().contains("true exited"), "{}", err);
+
+        let mut follower = JournalFollower::from_reader(&export[..], Some(state.clone()))
+            .unwrap()
+            .timeout(Duration::from_secs(5));
+        assert_eq!(Some(&b"first"[..]), follower.receive().unwrap().unwrap().message());
+        assert_eq!(Some(&b"second"[..]), follower.receive().unwrap().unwrap().message());
+        follower.save_state().unwrap();
+
+        let saved = fs::read_to_string(&state).unwrap();
+        assert!(saved.contains("\"cursor\":\"s=1;i=2\""), "{}", saved);
+        assert!(follower.receive().is_err());
+    }
+}
@@ -0,0 +1,781 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Sending events to a Kafka topic
+//!
+//! Events are sent by a `KafkaSink` using a `Producer`. A producer backed by
+//! librdkafka is available when the `kafka` feature is enabled.
+
+use crate::io::{report_drop_error, Output};
+use crate::stats::Stats;
+use crate::types::{RedeyeError, RedeyeResult};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::mem;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default field of events used as the key of messages.
+const DEFAULT_KEY: &str = "remote_host";
+
+/// Default number of attempts to send a message after the first one fails.
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Default number of messages that can be waiting to be delivered.
+const DEFAULT_MAX_IN_FLIGHT: usize = 10_000;
+
+/// Default amount of time spent waiting for messages to be delivered when finishing.
+const DEFAULT_FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Amount of time to wait for deliveries when the producer can't accept more messages.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where and how to send events to Kafka, parsed from comma separated `name=value`
+/// pairs.
+///
+/// `brokers` and `topic` are required. `key` is the field of events used as the key
+/// of messages (`remote_host` by default, empty for no key), `retries` is the number
+/// of times sending a message is retried, and `max_in_flight` is the number of
+/// messages that can be waiting to be delivered. Any other settings are passed to
+/// the producer.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::kafka::KafkaConfig;
+///
+/// let config: KafkaConfig = "brokers=k1:9092,k2:9092,topic=access-logs,acks=all".parse().unwrap();
+/// assert_eq!("k1:9092,k2:9092", config.brokers());
+/// assert_eq!("access-logs", config.topic());
+/// assert_eq!(Some("remote_host"), config.key());
+/// assert_eq!(&[("acks".to_owned(), "all".to_owned())], config.properties());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaConfig {
+    brokers: String,
+    topic: String,
+    key: Option<String>,
+    retries: u32,
+    max_in_flight: usize,
+    properties: Vec<(String, String)>,
+}
+
+impl KafkaConfig {
+    /// Comma separated `HOST:PORT` addresses of brokers.
+    pub fn brokers(&self) -> &str {
+        &self.brokers
+    }
+
+    /// Topic messages are sent to.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Field of events used as the key of messages, if any.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// Number of times sending a message is retried before it is dropped.
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    /// Number of messages that can be waiting to be delivered.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    /// Other settings for the producer.
+    pub fn properties(&self) -> &[(String, String)] {
+        &self.properties
+    }
+}
+
+impl FromStr for KafkaConfig {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Values can contain commas (e.g. a list of brokers) so anything that
+        // isn't a `name=value` pair is part of the value before it.
+        let mut pairs: Vec<(String, String)> = Vec::new();
+        for part in s.split(',') {
+            match (part.find('='), pairs.last_mut()) {
+                (Some(i), _) => pairs.push((part[..i].trim().to_owned(), part[i + 1..].to_owned())),
+                (None, Some((_, value))) => {
+                    value.push(',');
+                    value.push_str(part);
+                }
+                (None, None) => {
+                    return Err(RedeyeError::ConfigError(format!(
+                        "invalid Kafka setting '{}', expected name=value",
+                        part
+                    )))
+                }
+            }
+        }
+
+        let mut brokers = None;
+        let mut topic = None;
+        let mut key = Some(DEFAULT_KEY.to_owned());
+        let mut retries = DEFAULT_RETRIES;
+        let mut max_in_flight = DEFAULT_MAX_IN_FLIGHT;
+        let mut properties = Vec::new();
+
+        for (name, value) in pairs {
+            match name.as_str() {
+                "brokers" => brokers = Some(value),
+                "topic" => topic = Some(value),
+                "key" if value.is_empty() => key = None,
+                "key" => key = Some(value),
+                "retries" => retries = parse_number(&name, &value)?,
+                "max_in_flight" => max_in_flight = parse_number(&name, &value)?,
+                _ => properties.push((name, value)),
+            }
+        }
+
+        let missing = |name: &str| RedeyeError::ConfigError(format!("Kafka setting '{}' is required", name));
+        let brokers = brokers.filter(|b| !b.is_empty()).ok_or_else(|| missing("brokers"))?;
+        let topic = topic.filter(|t| !t.is_empty()).ok_or_else(|| missing("topic"))?;
+
+        if max_in_flight == 0 {
+            return Err(RedeyeError::ConfigError(
+                "Kafka setting 'max_in_flight' must be at least 1".to_owned(),
+            ));
+        }
+
+        Ok(KafkaConfig {
+            brokers,
+            topic,
+            key,
+            retries,
+            max_in_flight,
+            properties,
+        })
+    }
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> Result<T, RedeyeError> {
+    value.parse().map_err(|_| {
+        RedeyeError::ConfigError(format!(
+            "invalid value '{}' for Kafka setting '{}', expected a number",
+            value, name
+        ))
+    })
+}
+
+/// Message to send, along with the number of attempts already made to send it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub key: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+    pub attempts: u32,
+}
+
+/// Outcome of sending a message: the message and an error if it couldn't be delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub message: Message,
+    pub error: Option<String>,
+}
+
+/// Reason a producer didn't accept a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// Too many messages are waiting to be delivered, the message can be sent again
+    /// once some have been.
+    QueueFull(Message),
+    /// The message can't be sent.
+    Failed(Message, String),
+}
+
+/// Client that sends messages to a Kafka topic in the background.
+pub trait Producer: Send {
+    /// Queue a message to be sent without blocking.
+    fn send(&mut self, message: Message) -> Result<(), SendError>;
+
+    /// Wait up to `timeout` for queued messages to be delivered or to fail,
+    /// returning the outcome of each one that has.
+    fn poll(&mut self, timeout: Duration) -> Vec<Delivery>;
+}
+
+/// Writer that sends each record written to it as a message using a `Producer`.
+///
+/// The number of messages waiting to be delivered is bounded and writes block
+/// until some have been delivered when there are too many. Messages that can't
+/// be delivered are retried a limited number of times, then dropped and counted.
+/// Retried messages may be delivered after messages written later.
+///
+/// Use `close` or `finish` to wait for messages to be delivered when finished. A
+/// sink that is dropped instead does the same.
+#[derive(Debug)]
+pub struct KafkaSink<P: Producer> {
+    producer: P,
+    key: Option<String>,
+    retries: u32,
+    max_in_flight: usize,
+    flush_timeout: Duration,
+    delimiter: u8,
+    stats: Arc<Stats>,
+    in_flight: usize,
+    pending: VecDeque<Message>,
+    record: Vec<u8>,
+}
+
+impl<P> KafkaSink<P>
+where
+    P: Producer,
+{
+    pub fn new(producer: P) -> Self {
+        KafkaSink {
+            producer,
+            key: Some(DEFAULT_KEY.to_owned()),
+            retries: DEFAULT_RETRIES,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            flush_timeout: DEFAULT_FLUSH_TIMEOUT,
+            delimiter: b'\n',
+            stats: Arc::new(Stats::new()),
+            in_flight: 0,
+            pending: VecDeque::new(),
+            record: Vec::new(),
+        }
+    }
+
+    /// Use the key, number of retries, and maximum messages in flight from the config.
+    pub fn config(mut self, config: &KafkaConfig) -> Self {
+        self.key = config.key.clone();
+        self.retries = config.retries;
+        self.max_in_flight = config.max_in_flight;
+        self
+    }
+
+    /// Set the longest amount of time to wait for messages to be delivered when
+    /// the sink is finished before they are dropped.
+    pub fn flush_timeout(mut self, timeout: Duration) -> Self {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    /// Set the byte at the end of each record, which isn't included in messages.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Set the stats used to count messages dropped.
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Send any partial record written and wait for all messages to be delivered,
+    /// dropping those that aren't before the flush timeout.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.record.is_empty() {
+            let record = mem::take(&mut self.record);
+            self.produce(record);
+        }
+
+        let deadline = Instant::now() + self.flush_timeout;
+        while (self.in_flight > 0 || !self.pending.is_empty()) && Instant::now() < deadline {
+            self.send_pending(Some(deadline));
+            self.poll(POLL_INTERVAL);
+        }
+
+        let remaining = self.in_flight + self.pending.len();
+        if remaining > 0 {
+            self.stats.output_dropped(remaining as u64);
+            self.in_flight = 0;
+            self.pending.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Send any partial record and wait for messages to be delivered like `finish`.
+    /// Messages that aren't delivered in time are counted as dropped rather than
+    /// returned as an error.
+    pub fn close(mut self) -> RedeyeResult<()> {
+        self.finish()?;
+        Ok(())
+    }
+
+    fn produce(&mut self, payload: Vec<u8>) {
+        let key = self.key.as_deref().and_then(|path| message_key(&payload, path));
+        self.pending.push_back(Message {
+            key,
+            payload,
+            attempts: 0,
+        });
+
+        self.send_pending(None);
+    }
+
+    /// Hand pending messages to the producer, waiting for deliveries when there are
+    /// too many messages in flight, until there are none or the deadline passes.
+    fn send_pending(&mut self, deadline: Option<Instant>) {
+        while let Some(message) = self.pending.pop_front() {
+            if deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+                self.pending.push_front(message);
+                return;
+            }
+
+            if self.in_flight >= self.max_in_flight {
+                self.pending.push_front(message);
+                self.poll(POLL_INTERVAL);
+                continue;
+            }
+
+            match self.producer.send(message) {
+                Ok(()) => self.in_flight += 1,
+                Err(SendError::QueueFull(message)) => {
+                    self.pending.push_front(message);
+                    self.poll(POLL_INTERVAL);
+                }
+                Err(SendError::Failed(message, _)) => self.retry(message),
+            }
+        }
+    }
+
+    fn poll(&mut self, timeout: Duration) {
+        for delivery in self.producer.poll(timeout) {
+            self.in_flight = self.in_flight.saturating_sub(1);
+            if delivery.error.is_some() {
+                self.retry(delivery.message);
+            }
+        }
+    }
+
+    fn retry(&mut self, mut message: Message) {
+        message.attempts += 1;
+        if message.attempts > self.retries {
+            self.stats.output_dropped(1);
+        } else {
+            self.pending.push_back(message);
+        }
+    }
+}
+
+impl<P> Write for KafkaSink<P>
+where
+    P: Producer,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|b| *b == self.delimiter) {
+            self.record.extend_from_slice(&rest[..i]);
+            let record = mem::take(&mut self.record);
+            self.produce(record);
+            rest = &rest[i + 1..];
+        }
+
+        self.record.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    /// Handle any deliveries that have completed without waiting for more.
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll(Duration::from_secs(0));
+        self.send_pending(None);
+        Ok(())
+    }
+}
+
+impl<P: Producer> Output for KafkaSink<P> {
+    fn finish(&mut self) -> io::Result<()> {
+        KafkaSink::finish(self)
+    }
+}
+
+impl<P: Producer> Drop for KafkaSink<P> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            report_drop_error("Kafka messages", &e);
+        }
+    }
+}
+
+/// Value of a field of a serialized event to use as the key of its message, `None`
+/// if the event doesn't have the field.
+fn message_key(payload: &[u8], path: &str) -> Option<Vec<u8>> {
+    let event: Value = serde_json::from_slice(payload).ok()?;
+    let value = path.split('.').try_fold(&event, |v, name| v.get(name))?;
+
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone().into_bytes()),
+        v => Some(v.to_string().into_bytes()),
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub use self::rdkafka_producer::RdKafkaProducer;
+
+#[cfg(feature = "kafka")]
+mod rdkafka_producer {
+    use super::{Delivery, KafkaConfig, Message, Producer, SendError};
+    use crate::types::{RedeyeError, RedeyeResult};
+    use rdkafka::config::ClientConfig;
+    use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+    use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer as _, ProducerContext};
+    use rdkafka::ClientContext;
+    use std::mem;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct DeliveryContext {
+        deliveries: Mutex<Vec<Delivery>>,
+    }
+
+    impl ClientContext for DeliveryContext {}
+
+    impl ProducerContext for DeliveryContext {
+        type DeliveryOpaque = Box<Message>;
+
+        fn delivery(&self, result: &DeliveryResult<'_>, message: Box<Message>) {
+            let error = result.as_ref().err().map(|(e, _)| e.to_string());
+            self.deliveries.lock().unwrap().push(Delivery {
+                message: *message,
+                error,
+            });
+        }
+    }
+
+    /// Producer using librdkafka.
+    pub struct RdKafkaProducer {
+        producer: BaseProducer<DeliveryContext>,
+        topic: String,
+    }
+
+    impl RdKafkaProducer {
+        /// Create a producer for the brokers and topic of the config, using any
+        /// other settings of the config for librdkafka.
+        pub fn new(config: &KafkaConfig) -> RedeyeResult<Self> {
+            let mut client = ClientConfig::new();
+            client.set("bootstrap.servers", config.brokers());
+            for (name, value) in config.properties() {
+                client.set(name, value);
+            }
+
+            let producer = client
+                .create_with_context(DeliveryContext::default())
+                .map_err(|e| RedeyeError::ConfigError(format!("unable to create Kafka producer: {}", e)))?;
+
+            Ok(RdKafkaProducer {
+                producer,
+                topic: config.topic().to_owned(),
+            })
+        }
+    }
+
+    impl Producer for RdKafkaProducer {
+        fn send(&mut self, message: Message) -> Result<(), SendError> {
+            // The message is returned once it's been delivered so the record needs
+            // its own copy of the key and payload.
+            let key = message.key.clone();
+            let payload = message.payload.clone();
+
+            let mut record: BaseRecord<'_, [u8], [u8], Box<Message>> =
+                BaseRecord::with_opaque_to(&self.topic, Box::new(message)).payload(&payload[..]);
+            if let Some(ref k) = key {
+                record = record.key(&k[..]);
+            }
+
+            self.producer.send(record).map_err(|(e, record)| {
+                let message = *record.delivery_opaque;
+                match e {
+                    KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull) => SendError::QueueFull(message),
+                    e => SendError::Failed(message, e.to_string()),
+                }
+            })
+        }
+
+        fn poll(&mut self, timeout: Duration) -> Vec<Delivery> {
+            self.producer.poll(timeout);
+            mem::take(&mut *self.producer.context().deliveries.lock().unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message_key, Delivery, KafkaConfig, KafkaSink, Message, Producer, SendError};
+    use crate::stats::Stats;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Producer that delivers queued messages when polled, failing the first
+    /// `failures` deliveries.
+    #[derive(Debug, Default)]
+    struct MockProducer {
+        queue_limit: Option<usize>,
+        failures: usize,
+        queued: Vec<Message>,
+        delivered: Vec<Message>,
+        max_queued: usize,
+        stalled: bool,
+    }
+
+    impl Producer for MockProducer {
+        fn send(&mut self, message: Message) -> Result<(), SendError> {
+            if self.queue_limit.map(|l| self.queued.len() >= l).unwrap_or(false) {
+                return Err(SendError::QueueFull(message));
+            }
+
+            self.queued.push(message);
+            self.max_queued = self.max_queued.max(self.queued.len());
+            Ok(())
+        }
+
+        fn poll(&mut self, _timeout: Duration) -> Vec<Delivery> {
+            if self.stalled {
+                return Vec::new();
+            }
+
+            let mut out = Vec::new();
+            for message in self.queued.drain(..) {
+                if self.failures > 0 {
+                    self.failures -= 1;
+                    out.push(Delivery {
+                        message,
+                        error: Some("broker unavailable".to_owned()),
+                    });
+                } else {
+                    self.delivered.push(message.clone());
+                    out.push(Delivery { message, error: None });
+                }
+            }
+
+            out
+        }
+    }
+
+    /// Producer that delivers every queued message when polled to a list shared
+    /// with the test, so they can be checked after the sink is dropped.
+    #[derive(Debug, Default)]
+    struct SharedProducer {
+        queued: Vec<Message>,
+        delivered: Arc<Mutex<Vec<Message>>>,
+    }
+
+    impl Producer for SharedProducer {
+        fn send(&mut self, message: Message) -> Result<(), SendError> {
+            self.queued.push(message);
+            Ok(())
+        }
+
+        fn poll(&mut self, _timeout: Duration) -> Vec<Delivery> {
+            let mut delivered = self.delivered.lock().unwrap();
+            self.queued
+                .drain(..)
+                .map(|message| {
+                    delivered.push(message.clone());
+                    Delivery { message, error: None }
+                })
+                .collect()
+        }
+    }
+
+    fn payloads(messages: &[Message]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|m| String::from_utf8(m.payload.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_kafka_config_from_str() {
+        let config: KafkaConfig =
+            "brokers=a:9092,b:9092,topic=logs,key=request_headers.host,retries=5,max_in_flight=10"
+                .parse()
+                .unwrap();
+
+        assert_eq!("a:9092,b:9092", config.brokers());
+        assert_eq!("logs", config.topic());
+        assert_eq!(Some("request_headers.host"), config.key());
+        assert_eq!(5, config.retries());
+        assert_eq!(10, config.max_in_flight());
+        assert!(config.properties().is_empty());
+
+        let config: KafkaConfig = "topic=logs,brokers=a:9092,key=".parse().unwrap();
+        assert_eq!(None, config.key());
+    }
+
+    #[test]
+    fn test_kafka_config_from_str_invalid() {
+        assert!("brokers=a:9092".parse::<KafkaConfig>().is_err());
+        assert!("topic=logs".parse::<KafkaConfig>().is_err());
+        assert!("a:9092,topic=logs".parse::<KafkaConfig>().is_err());
+        assert!("brokers=a:9092,topic=logs,retries=lots".parse::<KafkaConfig>().is_err());
+        assert!("brokers=a:9092,topic=logs,max_in_flight=0"
+            .parse::<KafkaConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_message_key() {
+        let event = br#"{"remote_host":"10.1.1.1","status_code":200,"request_headers":{"host":"example.com"}}"#;
+        assert_eq!(Some(b"10.1.1.1".to_vec()), message_key(event, "remote_host"));
+        assert_eq!(Some(b"200".to_vec()), message_key(event, "status_code"));
+        assert_eq!(
+            Some(b"example.com".to_vec()),
+            message_key(event, "request_headers.host")
+        );
+        assert_eq!(None, message_key(event, "user"));
+        assert_eq!(None, message_key(b"not json", "remote_host"));
+    }
+
+    #[test]
+    fn test_kafka_sink_sends_messages() {
+        let mut sink = KafkaSink::new(MockProducer::default());
+        sink.write_all(b"{\"remote_host\":\"a\"}\n{\"remote_h").unwrap();
+        sink.write_all(b"ost\":\"b\"}\n{}").unwrap();
+        sink.finish().unwrap();
+
+        let delivered = &sink.producer.delivered;
+        assert_eq!(
+            vec![r#"{"remote_host":"a"}"#, r#"{"remote_host":"b"}"#, "{}"],
+            payloads(delivered)
+        );
+        assert_eq!(Some(b"a".to_vec()), delivered[0].key);
+        assert_eq!(Some(b"b".to_vec()), delivered[1].key);
+        assert_eq!(None, delivered[2].key);
+    }
+
+    #[test]
+    fn test_kafka_sink_drop() {
+        let producer = SharedProducer::default();
+        let delivered = producer.delivered.clone();
+
+        let mut sink = KafkaSink::new(producer);
+        sink.write_all(b"{\"n\":1}\n{\"n\":2}\n{\"n\":").unwrap();
+        drop(sink);
+
+        // Including the partial record at the end
+        let delivered = delivered.lock().unwrap();
+        assert_eq!(vec![r#"{"n":1}"#, r#"{"n":2}"#, r#"{"n":"#], payloads(&delivered));
+    }
+
+    #[test]
+    fn test_kafka_sink_close() {
+        let producer = SharedProducer::default();
+        let delivered = producer.delivered.clone();
+
+        let mut sink = KafkaSink::new(producer);
+        writeln!(sink, "{{\"n\":1}}").unwrap();
+        sink.close().unwrap();
+        assert_eq!(vec![r#"{"n":1}"#], payloads(&delivered.lock().unwrap()));
+    }
+
+    #[test]
+    fn test_kafka_sink_max_in_flight() {
+        let config: KafkaConfig = "brokers=a:9092,topic=logs,max_in_flight=3".parse().unwrap();
+        let mut sink = KafkaSink::new(MockProducer::default()).config(&config);
+        for i in 0..10 {
+            writeln!(sink, "{{\"n\":{}}}", i).unwrap();
+        }
+        sink.finish().unwrap();
+
+        assert_eq!(3, sink.producer.max_queued);
+        assert_eq!(10, sink.producer.delivered.len());
+    }
+
+    #[test]
+    fn test_kafka_sink_queue_full() {
+        let producer = MockProducer {
+            queue_limit: Some(2),
+            ..MockProducer::default()
+        };
+
+        let mut sink = KafkaSink::new(producer);
+        for i in 0..5 {
+            writeln!(sink, "{}", i).unwrap();
+        }
+        sink.finish().unwrap();
+
+        assert_eq!(vec!["0", "1", "2", "3", "4"], payloads(&sink.producer.delivered));
+    }
+
+    #[test]
+    fn test_kafka_sink_retries() {
+        let stats = Arc::new(Stats::new());
+        let producer = MockProducer {
+            failures: 2,
+            ..MockProducer::default()
+        };
+
+        let mut sink = KafkaSink::new(producer).stats(stats.clone());
+        sink.write_all(b"one\n").unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(vec!["one"], payloads(&sink.producer.delivered));
+        assert_eq!(2, sink.producer.delivered[0].attempts);
+        assert_eq!(0, stats.snapshot().output_dropped);
+    }
+
+    #[test]
+    fn test_kafka_sink_retries_exhausted() {
+        let stats = Arc::new(Stats::new());
+        let config: KafkaConfig = "brokers=a:9092,topic=logs,retries=1".parse().unwrap();
+        let producer = MockProducer {
+            failures: 2,
+            ..MockProducer::default()
+        };
+
+        let mut sink = KafkaSink::new(producer).config(&config).stats(stats.clone());
+        sink.write_all(b"one\ntwo\n").unwrap();
+        sink.finish().unwrap();
+
+        // Both messages fail once, then each is retried once and succeeds.
+        assert_eq!(2, sink.producer.delivered.len());
+        assert_eq!(0, stats.snapshot().output_dropped);
+
+        let producer = MockProducer {
+            failures: 3,
+            ..MockProducer::default()
+        };
+
+        let mut sink = KafkaSink::new(producer).config(&config).stats(stats.clone());
+        sink.write_all(b"one\n").unwrap();
+        sink.finish().unwrap();
+
+        assert!(sink.producer.delivered.is_empty());
+        assert_eq!(1, stats.snapshot().output_dropped);
+    }
+
+    #[test]
+    fn test_kafka_sink_flush_timeout() {
+        let stats = Arc::new(Stats::new());
+        let producer = MockProducer {
+            stalled: true,
+            ..MockProducer::default()
+        };
+
+        let mut sink = KafkaSink::new(producer)
+            .flush_timeout(Duration::from_millis(10))
+            .stats(stats.clone());
+        sink.write_all(b"one\ntwo\n").unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(2, stats.snapshot().output_dropped);
+    }
+}
@@ -17,8 +17,58 @@
 //
 
 //! Supporting library for the Redeye log parser.
+//!
+//! The `easy` module parses a line, some text, or a reader in a single call.
+//!
+//! Everything is synchronous by default, with no async runtime as a dependency.
+//! Optional cargo features add more:
+//!
+//! * `stream`: `stream::LogEventStream`, reading events from a tokio `AsyncRead`.
+//! * `kafka`, `redis`, `http`: sending events to those destinations.
+//! * `journal`: following the systemd journal.
+//! * `zstd`, `bzip2`: decompressing input files compressed with them.
+//! * `count-allocations`: counting allocations in `redeye bench`, which wraps the
+//!   system allocator to do so.
+//! * `nightly`: benchmarks, which require a nightly compiler.
 
 #![forbid(unsafe_code)]
 
+pub mod aggregate;
+pub mod bench;
+pub mod clock;
+pub mod config;
+pub mod dedup;
+pub mod easy;
+pub mod enrich;
+pub mod failures;
+pub mod filter;
+pub mod follow;
+pub mod format;
+pub mod forward;
+pub mod generate;
+pub mod geoip;
+pub mod http;
+pub mod io;
+pub mod journal;
+pub mod kafka;
+pub mod merge;
+pub mod metrics;
+pub mod net;
+pub mod output;
 pub mod parser;
+pub mod pipeline;
+pub mod ratelimit;
+pub mod redis;
+pub mod replay;
+pub mod sample;
+pub mod scenario;
+pub mod select;
+pub mod spill;
+pub mod stats;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod timestamp;
 pub mod types;
+pub mod warnings;
+pub mod watch;
+pub mod workers;
@@ -0,0 +1,353 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Merging events from multiple inputs in order of their timestamps
+//!
+//! Each input is expected to be mostly in order, with events out of order by
+//! no more than the merge window. An event is emitted once every input that
+//! hasn't ended has read an event at least the window later than it, at which
+//! point no input is expected to produce an earlier event. Events that arrive
+//! after a later event has already been emitted are emitted immediately and
+//! counted as late. Events without a timestamp are emitted in the order they
+//! arrive.
+
+use chrono::{DateTime, FixedOffset};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::time::Duration;
+
+/// Default maximum number of events buffered for each input.
+const DEFAULT_MAX_BUFFERED: usize = 10_000;
+
+/// Event waiting to be emitted, ordered by timestamp and then by arrival.
+#[derive(Debug)]
+struct Pending<T> {
+    timestamp: DateTime<FixedOffset>,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Pending<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Pending<T> {}
+
+impl<T> PartialOrd for Pending<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Pending<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.timestamp.cmp(&other.timestamp).then(self.seq.cmp(&other.seq))
+    }
+}
+
+/// Events buffered from a single input and how far it has been read.
+#[derive(Debug)]
+struct Input<T> {
+    pending: BinaryHeap<Reverse<Pending<T>>>,
+    newest: Option<DateTime<FixedOffset>>,
+    finished: bool,
+}
+
+impl<T> Input<T> {
+    /// True if no event earlier than the timestamp is expected from this input.
+    fn is_past(&self, timestamp: DateTime<FixedOffset>, window: chrono::Duration) -> bool {
+        self.finished || self.newest.map(|n| n - window >= timestamp).unwrap_or(false)
+    }
+}
+
+/// K-way merge of events from multiple inputs by timestamp.
+///
+/// Callers read an event from the input returned by `next_input`, add it with
+/// `push` (or call `finish` if the input has ended), and then take every event
+/// that is ready with `pop`, until `next_input` returns `None`.
+///
+/// # Example
+///
+/// ```rust
+/// use chrono::DateTime;
+/// use redeye::merge::TimeMerge;
+/// use std::time::Duration;
+///
+/// let ts = |s| Some(DateTime::parse_from_rfc3339(s).unwrap());
+/// let mut merge = TimeMerge::new(2, Duration::from_secs(0));
+///
+/// merge.push(0, ts("2020-01-01T00:00:02Z"), "b");
+/// merge.push(1, ts("2020-01-01T00:00:01Z"), "a");
+/// assert_eq!(Some("a"), merge.pop());
+/// assert_eq!(None, merge.pop());
+///
+/// merge.finish(1);
+/// assert_eq!(Some("b"), merge.pop());
+/// ```
+#[derive(Debug)]
+pub struct TimeMerge<T> {
+    inputs: Vec<Input<T>>,
+    window: chrono::Duration,
+    max_buffered: usize,
+    ready: VecDeque<T>,
+    last: Option<DateTime<FixedOffset>>,
+    seq: u64,
+    late: u64,
+}
+
+impl<T> TimeMerge<T> {
+    /// Create a merge of the given number of inputs, waiting for events up to
+    /// `window` out of order within an input.
+    pub fn new(inputs: usize, window: Duration) -> Self {
+        TimeMerge {
+            inputs: (0..inputs)
+                .map(|_| Input {
+                    pending: BinaryHeap::new(),
+                    newest: None,
+                    finished: false,
+                })
+                .collect(),
+            window: chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::max_value()),
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            ready: VecDeque::new(),
+            last: None,
+            seq: 0,
+            late: 0,
+        }
+    }
+
+    /// Set the maximum number of events buffered for a single input. Once an
+    /// input has this many, the earliest buffered event is emitted without
+    /// waiting for the other inputs.
+    pub fn max_buffered(mut self, events: usize) -> Self {
+        self.max_buffered = events.max(1);
+        self
+    }
+
+    /// Add an event read from an input. Events without a timestamp and events
+    /// earlier than one already emitted are ready immediately.
+    pub fn push(&mut self, input: usize, timestamp: Option<DateTime<FixedOffset>>, item: T) {
+        let timestamp = match timestamp {
+            Some(ts) => ts,
+            None => {
+                self.ready.push_back(item);
+                return;
+            }
+        };
+
+        if self.last.map(|last| timestamp < last).unwrap_or(false) {
+            self.late += 1;
+            self.ready.push_back(item);
+            return;
+        }
+
+        let seq = self.seq;
+        self.seq += 1;
+
+        let input = &mut self.inputs[input];
+        input.newest = Some(input.newest.map(|n| n.max(timestamp)).unwrap_or(timestamp));
+        input.pending.push(Reverse(Pending { timestamp, seq, item }));
+    }
+
+    /// Mark an input as ended, so no more events are waited for from it.
+    pub fn finish(&mut self, input: usize) {
+        self.inputs[input].finished = true;
+    }
+
+    /// Next event that's ready to be emitted, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        if let Some(item) = self.ready.pop_front() {
+            return Some(item);
+        }
+
+        let (i, timestamp) = self
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, input)| input.pending.peek().map(|Reverse(p)| (i, p.timestamp, p.seq)))
+            .min_by(|a, b| a.1.cmp(&b.1).then(a.2.cmp(&b.2)))
+            .map(|(i, ts, _)| (i, ts))?;
+
+        let full = self.inputs.iter().any(|input| input.pending.len() >= self.max_buffered);
+        if !full && !self.inputs.iter().all(|input| input.is_past(timestamp, self.window)) {
+            return None;
+        }
+
+        self.last = Some(timestamp);
+        self.inputs[i].pending.pop().map(|Reverse(p)| p.item)
+    }
+
+    /// Input to read the next event from, the one that has been read the least
+    /// far, or `None` once every input has ended.
+    pub fn next_input(&self) -> Option<usize> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| !input.finished)
+            .min_by_key(|(_, input)| input.newest)
+            .map(|(i, _)| i)
+    }
+
+    /// Number of events that arrived after a later event had been emitted.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeMerge;
+    use chrono::{DateTime, FixedOffset};
+    use std::time::Duration;
+
+    fn ts(secs: u32) -> Option<DateTime<FixedOffset>> {
+        Some(DateTime::parse_from_rfc3339(&format!("2020-01-01T00:{:02}:{:02}Z", secs / 60, secs % 60)).unwrap())
+    }
+
+    /// Merge inputs of (seconds, label) pairs, reading them the way callers do.
+    fn merge(inputs: Vec<Vec<(Option<u32>, &'static str)>>, window: Duration, max: usize) -> (Vec<&'static str>, u64) {
+        let mut merge = TimeMerge::new(inputs.len(), window).max_buffered(max);
+        let mut iters: Vec<_> = inputs.into_iter().map(|i| i.into_iter()).collect();
+        let mut out = Vec::new();
+
+        while let Some(i) = merge.next_input() {
+            match iters[i].next() {
+                Some((secs, label)) => merge.push(i, secs.and_then(ts), label),
+                None => merge.finish(i),
+            }
+
+            while let Some(label) = merge.pop() {
+                out.push(label);
+            }
+        }
+
+        assert!(merge.pop().is_none());
+        (out, merge.late())
+    }
+
+    #[test]
+    fn test_merge_sorted_inputs() {
+        let (out, late) = merge(
+            vec![
+                vec![(Some(1), "a1"), (Some(4), "a4"), (Some(5), "a5")],
+                vec![(Some(2), "b2"), (Some(3), "b3"), (Some(6), "b6")],
+                vec![],
+            ],
+            Duration::from_secs(0),
+            100,
+        );
+
+        assert_eq!(vec!["a1", "b2", "b3", "a4", "a5", "b6"], out);
+        assert_eq!(0, late);
+    }
+
+    #[test]
+    fn test_merge_equal_timestamps_in_arrival_order() {
+        let (out, _) = merge(
+            vec![vec![(Some(1), "a1"), (Some(1), "a1'")], vec![(Some(1), "b1")]],
+            Duration::from_secs(0),
+            100,
+        );
+
+        assert_eq!(vec!["a1", "b1", "a1'"], out);
+    }
+
+    #[test]
+    fn test_merge_skew_within_window() {
+        // The second input is out of order by up to 3 seconds
+        let (out, late) = merge(
+            vec![
+                vec![(Some(1), "a1"), (Some(5), "a5"), (Some(9), "a9")],
+                vec![(Some(4), "b4"), (Some(2), "b2"), (Some(8), "b8"), (Some(6), "b6")],
+            ],
+            Duration::from_secs(3),
+            100,
+        );
+
+        assert_eq!(vec!["a1", "b2", "b4", "a5", "b6", "b8", "a9"], out);
+        assert_eq!(0, late);
+    }
+
+    #[test]
+    fn test_merge_skew_beyond_window_is_late() {
+        let (out, late) = merge(
+            vec![
+                vec![(Some(1), "a1"), (Some(3), "a3")],
+                vec![(Some(2), "b2"), (Some(10), "b10"), (Some(1), "b1"), (Some(11), "b11")],
+            ],
+            Duration::from_secs(0),
+            100,
+        );
+
+        // b10 is emitted once the first input ends, so b1 arrives after it
+        assert_eq!(vec!["a1", "b2", "a3", "b10", "b1", "b11"], out);
+        assert_eq!(1, late);
+    }
+
+    #[test]
+    fn test_merge_without_timestamps() {
+        let (out, late) = merge(
+            vec![
+                vec![(Some(2), "a2"), (None, "a-"), (Some(3), "a3")],
+                vec![(Some(1), "b1"), (None, "b-")],
+            ],
+            Duration::from_secs(0),
+            100,
+        );
+
+        // Events without a timestamp don't wait for the events before them
+        assert_eq!(vec!["b1", "b-", "a2", "a-", "a3"], out);
+        assert_eq!(0, late);
+    }
+
+    #[test]
+    fn test_merge_max_buffered() {
+        let mut merge = TimeMerge::new(2, Duration::from_secs(60)).max_buffered(2);
+        merge.push(0, ts(1), "a1");
+        assert_eq!(None, merge.pop());
+        merge.push(0, ts(2), "a2");
+
+        // The second input hasn't been read but the first is full
+        assert_eq!(Some("a1"), merge.pop());
+        assert_eq!(None, merge.pop());
+
+        merge.push(1, ts(0), "b0");
+        assert_eq!(Some("b0"), merge.pop());
+        assert_eq!(1, merge.late());
+    }
+
+    #[test]
+    fn test_next_input() {
+        let mut merge = TimeMerge::new(3, Duration::from_secs(0));
+        assert_eq!(Some(0), merge.next_input());
+
+        merge.push(0, ts(5), "a5");
+        assert_eq!(Some(1), merge.next_input());
+        merge.push(1, ts(3), "b3");
+        merge.push(2, ts(4), "c4");
+        assert_eq!(Some(1), merge.next_input());
+
+        merge.finish(1);
+        assert_eq!(Some(2), merge.next_input());
+        merge.finish(2);
+        merge.finish(0);
+        assert_eq!(None, merge.next_input());
+    }
+}
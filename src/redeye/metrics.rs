@@ -0,0 +1,682 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Counters, gauges, and histograms that can be exported to Prometheus
+//!
+//! Metrics are registered by name with a `Metrics` registry, which hands back
+//! a handle that can be cloned and shared between threads. Updating a metric
+//! through a handle is a relaxed atomic operation, cheap enough to do for every
+//! line. The registry is only locked to register metrics and to take snapshots.
+//!
+//! Handles can also be created without a registry, in which case they count as
+//! normal but are never rendered. Components use these as a default so they
+//! work the same whether or not they were given a registry.
+
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Count of something that only increases, e.g. lines read.
+#[derive(Debug, Clone, Default)]
+pub struct Counter {
+    value: Arc<AtomicU64>,
+}
+
+impl Counter {
+    /// Create a counter that isn't part of any registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+/// Value that can go up and down, e.g. the number of bytes buffered.
+#[derive(Debug, Clone, Default)]
+pub struct Gauge {
+    value: Arc<AtomicI64>,
+}
+
+impl Gauge {
+    /// Create a gauge that isn't part of any registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, value: i64) {
+        self.value.store(value, Ordering::Relaxed);
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn add(&self, n: i64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct HistogramInner {
+    bounds: Vec<f64>,
+    // One more bucket than bounds, for values larger than all of them
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    // Bits of an f64, updated with a compare and swap loop
+    sum: AtomicU64,
+}
+
+/// Distribution of values counted in buckets with fixed upper bounds, e.g.
+/// the size of lines in bytes.
+///
+/// Unlike `aggregate::Histogram`, quantiles can't be estimated accurately but
+/// values can be recorded from many threads at once without locking.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::metrics::Histogram;
+///
+/// let sizes = Histogram::new(&[100.0, 1000.0]);
+/// sizes.observe(50.0);
+/// sizes.observe(500.0);
+/// sizes.observe(5000.0);
+///
+/// assert_eq!(3, sizes.count());
+/// assert_eq!(5550.0, sizes.sum());
+/// assert_eq!(vec![1, 2, 3], sizes.cumulative());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    inner: Arc<HistogramInner>,
+}
+
+impl Histogram {
+    /// Create a histogram that isn't part of any registry with the given bucket
+    /// upper bounds. Bounds are sorted and any that aren't finite are removed.
+    pub fn new(bounds: &[f64]) -> Self {
+        let mut bounds: Vec<f64> = bounds.iter().copied().filter(|b| b.is_finite()).collect();
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        bounds.dedup();
+
+        let buckets = (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect();
+        Histogram {
+            inner: Arc::new(HistogramInner {
+                bounds,
+                buckets,
+                count: AtomicU64::new(0),
+                sum: AtomicU64::new(0f64.to_bits()),
+            }),
+        }
+    }
+
+    /// Record a value in the first bucket with an upper bound greater than or
+    /// equal to it.
+    pub fn observe(&self, value: f64) {
+        let inner = &self.inner;
+        let i = inner.bounds.partition_point(|b| *b < value);
+        inner.buckets[i].fetch_add(1, Ordering::Relaxed);
+        inner.count.fetch_add(1, Ordering::Relaxed);
+
+        let mut current = inner.sum.load(Ordering::Relaxed);
+        loop {
+            let next = (f64::from_bits(current) + value).to_bits();
+            match inner
+                .sum
+                .compare_exchange_weak(current, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(v) => current = v,
+            }
+        }
+    }
+
+    /// Upper bounds of each bucket, not including the implicit `+Inf` bucket.
+    pub fn bounds(&self) -> &[f64] {
+        &self.inner.bounds
+    }
+
+    pub fn count(&self) -> u64 {
+        self.inner.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.inner.sum.load(Ordering::Relaxed))
+    }
+
+    /// Number of values less than or equal to each bound, followed by the
+    /// number of values in all buckets.
+    pub fn cumulative(&self) -> Vec<u64> {
+        let mut total = 0;
+        self.inner
+            .buckets
+            .iter()
+            .map(|b| {
+                total += b.load(Ordering::Relaxed);
+                total
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl Metric {
+    fn kind(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    help: String,
+    metric: Metric,
+}
+
+/// Registry of named metrics that can be rendered in the Prometheus text format
+/// or included in a summary.
+///
+/// Cloning a registry is cheap and the clone refers to the same metrics.
+/// Registering a metric with a name that's already in use returns the existing
+/// metric, so components can register the metrics they update without
+/// coordinating with each other.
+///
+/// # Panics
+///
+/// Registering a metric with a name that isn't valid in Prometheus, or with the
+/// name of an existing metric of a different kind, panics.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::metrics::Metrics;
+///
+/// let metrics = Metrics::new();
+/// let lines = metrics.counter("redeye_lines_read_total", "Lines read");
+/// lines.inc();
+/// lines.add(2);
+///
+/// assert_eq!(3, lines.get());
+/// assert!(metrics.prometheus().contains("redeye_lines_read_total 3\n"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    entries: Arc<Mutex<BTreeMap<String, Entry>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a counter or return the existing counter with the same name.
+    pub fn counter(&self, name: &str, help: &str) -> Counter {
+        match self.register(name, help, || Metric::Counter(Counter::new())) {
+            Metric::Counter(c) => c,
+            m => panic!("metric {} is already registered as a {}", name, m.kind()),
+        }
+    }
+
+    /// Register a gauge or return the existing gauge with the same name.
+    pub fn gauge(&self, name: &str, help: &str) -> Gauge {
+        match self.register(name, help, || Metric::Gauge(Gauge::new())) {
+            Metric::Gauge(g) => g,
+            m => panic!("metric {} is already registered as a {}", name, m.kind()),
+        }
+    }
+
+    /// Register a histogram with the given bucket upper bounds or return the
+    /// existing histogram with the same name, whatever its bounds are.
+    pub fn histogram(&self, name: &str, help: &str, bounds: &[f64]) -> Histogram {
+        match self.register(name, help, || Metric::Histogram(Histogram::new(bounds))) {
+            Metric::Histogram(h) => h,
+            m => panic!("metric {} is already registered as a {}", name, m.kind()),
+        }
+    }
+
+    fn register<F>(&self, name: &str, help: &str, create: F) -> Metric
+    where
+        F: FnOnce() -> Metric,
+    {
+        assert!(is_valid_name(name), "invalid metric name '{}'", name);
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .entry(name.to_owned())
+            .or_insert_with(|| Entry {
+                help: help.to_owned(),
+                metric: create(),
+            })
+            .metric
+            .clone()
+    }
+
+    /// Current value of every metric, sorted by name.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let entries = self.entries.lock().unwrap();
+        let metrics = entries
+            .iter()
+            .map(|(name, entry)| MetricSnapshot {
+                name: name.clone(),
+                help: entry.help.clone(),
+                value: match entry.metric {
+                    Metric::Counter(ref c) => MetricValue::Counter(c.get()),
+                    Metric::Gauge(ref g) => MetricValue::Gauge(g.get()),
+                    Metric::Histogram(ref h) => MetricValue::Histogram {
+                        bounds: h.bounds().to_vec(),
+                        cumulative: h.cumulative(),
+                        sum: h.sum(),
+                    },
+                },
+            })
+            .collect();
+
+        MetricsSnapshot { metrics }
+    }
+
+    /// Render the current value of every metric in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        self.snapshot().prometheus()
+    }
+}
+
+/// Names must match `[a-zA-Z_:][a-zA-Z0-9_:]*`
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':' => {}
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Value of a metric at the time a snapshot was taken.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    Counter(u64),
+    Gauge(i64),
+    /// Upper bounds of each bucket, the number of values less than or equal to
+    /// each bound followed by the total number of values, and their sum.
+    Histogram {
+        bounds: Vec<f64>,
+        cumulative: Vec<u64>,
+        sum: f64,
+    },
+}
+
+/// Name, description, and value of a metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSnapshot {
+    pub name: String,
+    pub help: String,
+    pub value: MetricValue,
+}
+
+/// Values of all metrics in a registry, sorted by name.
+///
+/// Displaying a snapshot gives a line per metric, suitable for a summary printed
+/// when exiting. Serializing it gives an object with a field per metric.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub metrics: Vec<MetricSnapshot>,
+}
+
+impl MetricsSnapshot {
+    /// Render every metric in the Prometheus text format.
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        for m in self.metrics.iter() {
+            // Writing to a String can't fail
+            let _ = write_prometheus(&mut out, m);
+        }
+        out
+    }
+}
+
+fn write_prometheus(out: &mut String, m: &MetricSnapshot) -> fmt::Result {
+    let help = m.help.replace('\\', "\\\\").replace('\n', "\\n");
+    writeln!(out, "# HELP {} {}", m.name, help)?;
+
+    match m.value {
+        MetricValue::Counter(v) => {
+            writeln!(out, "# TYPE {} counter", m.name)?;
+            writeln!(out, "{} {}", m.name, v)
+        }
+        MetricValue::Gauge(v) => {
+            writeln!(out, "# TYPE {} gauge", m.name)?;
+            writeln!(out, "{} {}", m.name, v)
+        }
+        MetricValue::Histogram {
+            ref bounds,
+            ref cumulative,
+            sum,
+        } => {
+            writeln!(out, "# TYPE {} histogram", m.name)?;
+            for (bound, count) in bounds.iter().zip(cumulative.iter()) {
+                writeln!(out, "{}_bucket{{le=\"{}\"}} {}", m.name, bound, count)?;
+            }
+            let count = cumulative.last().copied().unwrap_or(0);
+            writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", m.name, count)?;
+            writeln!(out, "{}_sum {}", m.name, sum)?;
+            writeln!(out, "{}_count {}", m.name, count)
+        }
+    }
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, m) in self.metrics.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+
+            match m.value {
+                MetricValue::Counter(v) => write!(f, "{}: {}", m.name, v)?,
+                MetricValue::Gauge(v) => write!(f, "{}: {}", m.name, v)?,
+                MetricValue::Histogram {
+                    ref cumulative, sum, ..
+                } => {
+                    let count = cumulative.last().copied().unwrap_or(0);
+                    let mean = if count > 0 { sum / count as f64 } else { 0.0 };
+                    write!(f, "{}: count {} sum {} mean {:.3}", m.name, count, sum, mean)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Buckets of a histogram serialized as `{"le": 100.0, "count": 3}`
+struct Bucket(Option<f64>, u64);
+
+impl Serialize for Bucket {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut m = serializer.serialize_map(Some(2))?;
+        match self.0 {
+            Some(bound) => m.serialize_entry("le", &bound)?,
+            None => m.serialize_entry("le", "+Inf")?,
+        }
+        m.serialize_entry("count", &self.1)?;
+        m.end()
+    }
+}
+
+struct HistogramValue<'a> {
+    bounds: &'a [f64],
+    cumulative: &'a [u64],
+    sum: f64,
+}
+
+impl<'a> Serialize for HistogramValue<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let buckets: Vec<Bucket> = self
+            .cumulative
+            .iter()
+            .enumerate()
+            .map(|(i, count)| Bucket(self.bounds.get(i).copied(), *count))
+            .collect();
+
+        let mut m = serializer.serialize_map(Some(3))?;
+        m.serialize_entry("count", &self.cumulative.last().copied().unwrap_or(0))?;
+        m.serialize_entry("sum", &self.sum)?;
+        m.serialize_entry("buckets", &buckets)?;
+        m.end()
+    }
+}
+
+impl Serialize for MetricsSnapshot {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.metrics.len()))?;
+        for m in self.metrics.iter() {
+            match m.value {
+                MetricValue::Counter(v) => map.serialize_entry(&m.name, &v)?,
+                MetricValue::Gauge(v) => map.serialize_entry(&m.name, &v)?,
+                MetricValue::Histogram {
+                    ref bounds,
+                    ref cumulative,
+                    sum,
+                } => map.serialize_entry(
+                    &m.name,
+                    &HistogramValue {
+                        bounds,
+                        cumulative,
+                        sum,
+                    },
+                )?,
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counter, Gauge, Histogram, Metrics};
+    use std::thread;
+
+    #[test]
+    fn test_counter_shared_between_threads() {
+        let metrics = Metrics::new();
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    let lines = metrics.counter("lines_total", "Lines");
+                    for _ in 0..10_000 {
+                        lines.inc();
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(80_000, metrics.counter("lines_total", "Lines").get());
+    }
+
+    #[test]
+    fn test_gauge_shared_between_threads() {
+        let gauge = Gauge::new();
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let gauge = gauge.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        if i % 2 == 0 {
+                            gauge.inc();
+                        } else {
+                            gauge.dec();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(0, gauge.get());
+    }
+
+    #[test]
+    fn test_histogram_shared_between_threads() {
+        let hist = Histogram::new(&[10.0, 100.0]);
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let hist = hist.clone();
+                thread::spawn(move || {
+                    for i in 0..1_000 {
+                        hist.observe((i % 200) as f64);
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(8_000, hist.count());
+        // Each thread observes 0 through 199 five times
+        assert_eq!(8.0 * 5.0 * 19_900.0, hist.sum());
+        assert_eq!(vec![8 * 5 * 11, 8 * 5 * 101, 8_000], hist.cumulative());
+    }
+
+    #[test]
+    fn test_histogram_bounds() {
+        let hist = Histogram::new(&[5.0, 1.0, f64::INFINITY, 1.0, f64::NAN]);
+        hist.observe(1.0);
+        hist.observe(1.5);
+        hist.observe(10.0);
+
+        assert_eq!(&[1.0, 5.0], hist.bounds());
+        assert_eq!(vec![1, 2, 3], hist.cumulative());
+    }
+
+    #[test]
+    fn test_metrics_register_existing() {
+        let metrics = Metrics::new();
+        let first = metrics.counter("events_total", "Events");
+        let second = metrics.counter("events_total", "Ignored");
+        first.inc();
+        second.inc();
+
+        assert_eq!(2, first.get());
+        assert_eq!("Events", metrics.snapshot().metrics[0].help);
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered as a counter")]
+    fn test_metrics_register_different_kind() {
+        let metrics = Metrics::new();
+        metrics.counter("events_total", "Events");
+        metrics.gauge("events_total", "Events");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid metric name")]
+    fn test_metrics_register_invalid_name() {
+        Metrics::new().counter("1-events", "Events");
+    }
+
+    #[test]
+    fn test_detached_metrics_not_rendered() {
+        let metrics = Metrics::new();
+        let counter = Counter::new();
+        counter.inc();
+
+        assert_eq!(1, counter.get());
+        assert_eq!("", metrics.prometheus());
+    }
+
+    fn example() -> Metrics {
+        let metrics = Metrics::new();
+        metrics.counter("redeye_lines_read_total", "Lines read").add(3);
+        metrics.gauge("redeye_spill_bytes", "Bytes spilled\nto disk").set(-2);
+        let sizes = metrics.histogram("redeye_line_bytes", "Size of lines", &[64.0, 1024.0]);
+        sizes.observe(10.0);
+        sizes.observe(100.0);
+        sizes.observe(2048.5);
+        metrics
+    }
+
+    #[test]
+    fn test_metrics_prometheus() {
+        let expected = "\
+# HELP redeye_line_bytes Size of lines
+# TYPE redeye_line_bytes histogram
+redeye_line_bytes_bucket{le=\"64\"} 1
+redeye_line_bytes_bucket{le=\"1024\"} 2
+redeye_line_bytes_bucket{le=\"+Inf\"} 3
+redeye_line_bytes_sum 2158.5
+redeye_line_bytes_count 3
+# HELP redeye_lines_read_total Lines read
+# TYPE redeye_lines_read_total counter
+redeye_lines_read_total 3
+# HELP redeye_spill_bytes Bytes spilled\\nto disk
+# TYPE redeye_spill_bytes gauge
+redeye_spill_bytes -2
+";
+
+        assert_eq!(expected, example().prometheus());
+    }
+
+    #[test]
+    fn test_metrics_snapshot_display() {
+        let expected = "\
+redeye_line_bytes: count 3 sum 2158.5 mean 719.500
+redeye_lines_read_total: 3
+redeye_spill_bytes: -2";
+
+        assert_eq!(expected, example().snapshot().to_string());
+    }
+
+    #[test]
+    fn test_metrics_snapshot_serialize() {
+        let expected = concat!(
+            r#"{"redeye_line_bytes":{"count":3,"sum":2158.5,"buckets":["#,
+            r#"{"le":64.0,"count":1},{"le":1024.0,"count":2},{"le":"+Inf","count":3}]},"#,
+            r#""redeye_lines_read_total":3,"redeye_spill_bytes":-2}"#,
+        );
+
+        assert_eq!(expected, serde_json::to_string(&example().snapshot()).unwrap());
+    }
+}
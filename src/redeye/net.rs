@@ -0,0 +1,220 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Receiving log entries over the network
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Largest payload of a UDP datagram sent over IPv4.
+pub const MAX_DATAGRAM_BYTES: usize = 65_507;
+
+/// Default amount of time to wait for a datagram before returning.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Single datagram received by a `DatagramReceiver`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Datagram<'a> {
+    /// Datagram no larger than the maximum size, including any trailing newlines.
+    Record { data: &'a [u8], from: SocketAddr },
+    /// Datagram larger than the maximum size, which is discarded.
+    Oversized { from: SocketAddr },
+}
+
+/// Receiver of UDP datagrams that each contain a single log entry.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::net::{Datagram, DatagramReceiver};
+/// use std::net::UdpSocket;
+///
+/// let mut receiver = DatagramReceiver::bind("127.0.0.1:0").unwrap();
+/// let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// sender.send_to(b"hello\n", receiver.local_addr().unwrap()).unwrap();
+///
+/// match receiver.receive().unwrap() {
+///     Some(Datagram::Record { data, .. }) => assert_eq!(b"hello\n", data),
+///     other => panic!("unexpected datagram {:?}", other),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DatagramReceiver {
+    socket: UdpSocket,
+    max_size: usize,
+    buf: Vec<u8>,
+}
+
+impl DatagramReceiver {
+    /// Bind to the given address to receive datagrams.
+    pub fn bind<A>(addr: A) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(DEFAULT_TIMEOUT))?;
+
+        Ok(DatagramReceiver {
+            socket,
+            max_size: MAX_DATAGRAM_BYTES,
+            buf: vec![0; MAX_DATAGRAM_BYTES + 1],
+        })
+    }
+
+    /// Set the size in bytes of the largest datagram that is returned as a record.
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = bytes;
+        self.buf = vec![0; bytes + 1];
+        self
+    }
+
+    /// Set the longest amount of time `receive` waits for a datagram.
+    pub fn timeout(self, timeout: Duration) -> io::Result<Self> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        Ok(self)
+    }
+
+    /// Underlying socket, e.g. to set socket options.
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Address the receiver is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Wait for the next datagram, returning `None` if there wasn't one before the
+    /// timeout or if waiting was interrupted by a signal.
+    pub fn receive(&mut self) -> io::Result<Option<Datagram<'_>>> {
+        match self.socket.recv_from(&mut self.buf) {
+            // Datagrams larger than the buffer are truncated to its size, which is one
+            // byte larger than the maximum so that we can tell when that happens.
+            Ok((n, from)) if n > self.max_size => Ok(Some(Datagram::Oversized { from })),
+            Ok((n, from)) => Ok(Some(Datagram::Record {
+                data: &self.buf[..n],
+                from,
+            })),
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Best-effort count of datagrams dropped by the OS because the socket receive
+    /// buffer was full, if the OS provides it (only Linux, currently).
+    pub fn dropped(&self) -> Option<u64> {
+        dropped_datagrams(&self.socket)
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn dropped_datagrams(socket: &UdpSocket) -> Option<u64> {
+    use std::fs;
+    use std::os::unix::io::AsRawFd;
+
+    // The inode of the socket identifies it in the tables of UDP sockets, which
+    // include the number of datagrams dropped for each.
+    let link = fs::read_link(format!("/proc/self/fd/{}", socket.as_raw_fd())).ok()?;
+    let inode = link.to_str()?.strip_prefix("socket:[")?.strip_suffix(']')?.to_owned();
+
+    ["/proc/net/udp", "/proc/net/udp6"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .find_map(|table| parse_drops(&table, &inode))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn dropped_datagrams(_socket: &UdpSocket) -> Option<u64> {
+    None
+}
+
+/// Find the number of drops for the socket with the given inode in the contents of
+/// `/proc/net/udp` or `/proc/net/udp6`, where it is the last column.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_drops(table: &str, inode: &str) -> Option<u64> {
+    table.lines().skip(1).find_map(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.get(9) == Some(&inode) {
+            columns.last().and_then(|d| d.parse().ok())
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_drops, Datagram, DatagramReceiver};
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn test_datagram_receiver_oversized() {
+        let mut receiver = DatagramReceiver::bind("127.0.0.1:0").unwrap().max_size(4);
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+
+        sender.send_to(b"12345", addr).unwrap();
+        sender.send_to(b"1234", addr).unwrap();
+
+        let from = sender.local_addr().unwrap();
+        assert_eq!(Some(Datagram::Oversized { from }), receiver.receive().unwrap());
+        assert_eq!(
+            Some(Datagram::Record { data: b"1234", from }),
+            receiver.receive().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_datagram_receiver_timeout() {
+        let mut receiver = DatagramReceiver::bind("127.0.0.1:0")
+            .unwrap()
+            .timeout(Duration::from_millis(10))
+            .unwrap();
+
+        assert_eq!(None, receiver.receive().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_datagram_receiver_dropped() {
+        let receiver = DatagramReceiver::bind("127.0.0.1:0").unwrap();
+        assert_eq!(Some(0), receiver.dropped());
+    }
+
+    #[test]
+    fn test_parse_drops() {
+        let table = concat!(
+            "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n",
+            "  123: 00000000:0202 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 41234 2 0000000000000000 17\n",
+            "  456: 0100007F:9C40 00000000:0000 07 00000000:00000000 00:00000000 00000000  1000        0 41235 2 0000000000000000 0\n",
+        );
+
+        assert_eq!(Some(17), parse_drops(table, "41234"));
+        assert_eq!(Some(0), parse_drops(table, "41235"));
+        assert_eq!(None, parse_drops(table, "1"));
+    }
+}
@@ -0,0 +1,468 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! How events are written
+//!
+//! Events are usually written as JSON. A `PrettyRenderer` instead renders each
+//! event as a single aligned and optionally colorized line, for reading them in
+//! a terminal.
+
+use crate::types::{LogEvent, LogFieldValue, RedeyeError};
+use std::fmt;
+use std::str::FromStr;
+
+/// Fields shown in columns of a pretty line, in order.
+const COLUMNS: &[&str] = &[
+    "@timestamp",
+    "method",
+    "status_code",
+    "requested_uri",
+    "remote_host",
+    "content_length",
+];
+
+/// Fields that aren't shown in a pretty line, even when verbose. The message is
+/// the original log line, so everything in it is already shown.
+const HIDDEN: &[&str] = &["message"];
+
+/// Width of each column of a pretty line, except the URI which uses whatever is left.
+const TIMESTAMP_WIDTH: usize = 25;
+const METHOD_WIDTH: usize = 7;
+const STATUS_WIDTH: usize = 3;
+const IP_WIDTH: usize = 15;
+const BYTES_WIDTH: usize = 8;
+
+/// Width of the URI column when the width of the terminal isn't known, and the
+/// narrowest it will be when it is.
+const DEFAULT_URI_WIDTH: usize = 40;
+const MIN_URI_WIDTH: usize = 10;
+
+/// Value shown for fields that an event doesn't have.
+const MISSING: &str = "-";
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
+
+/// How events are framed when they're written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A JSON object per line (NDJSON).
+    Json,
+    /// A JSON object per line, each preceded by an action line, as expected by
+    /// the Elasticsearch `_bulk` API.
+    EsBulk,
+    /// A human readable line per event, rendered by a `PrettyRenderer`.
+    PrettyLog,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Json => f.write_str("json"),
+            OutputFormat::EsBulk => f.write_str("es-bulk"),
+            OutputFormat::PrettyLog => f.write_str("pretty-log"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "es-bulk" => Ok(OutputFormat::EsBulk),
+            "pretty-log" => Ok(OutputFormat::PrettyLog),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown output format '{}', expected one of json, es-bulk, pretty-log",
+                s
+            ))),
+        }
+    }
+}
+
+/// Whether output should be colorized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Colorize output written to a terminal unless `NO_COLOR` is set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// True if output should be colorized, given whether it's written to a terminal
+    /// and whether the `NO_COLOR` environment variable is set to a non-empty value.
+    pub fn enabled(self, terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && !no_color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorChoice::Auto => f.write_str("auto"),
+            ColorChoice::Always => f.write_str("always"),
+            ColorChoice::Never => f.write_str("never"),
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown color choice '{}', expected one of auto, always, never",
+                s
+            ))),
+        }
+    }
+}
+
+/// Render events as single lines with aligned columns for the timestamp, method,
+/// status code, URI, client IP, and size of each request.
+///
+/// The URI is truncated with an ellipsis to fit the width of the line, if set.
+/// When verbose, every other field is added to the end of the line as `key=value`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::output::PrettyRenderer;
+/// use redeye::types::{LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let mut fields = HashMap::new();
+/// fields.insert("method".to_owned(), LogFieldValue::Text("GET".to_owned()));
+/// fields.insert("status_code".to_owned(), LogFieldValue::Int(200));
+/// fields.insert("requested_uri".to_owned(), LogFieldValue::Text("/index.html".to_owned()));
+/// let event = LogEvent::from(fields);
+///
+/// let renderer = PrettyRenderer::new().width(Some(80));
+/// assert_eq!(
+///     "-                         GET     200 /index.html                     -        -",
+///     renderer.render(&event)
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PrettyRenderer {
+    color: bool,
+    verbose: bool,
+    width: Option<usize>,
+}
+
+impl PrettyRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether lines are colorized with ANSI escape codes.
+    pub fn color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set whether fields that don't have a column are added to each line.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Set the width that columns of each line should fit, usually the width of
+    /// the terminal. Fields added when verbose may extend past it.
+    pub fn width(mut self, width: Option<usize>) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Render an event as a single line, without a trailing newline.
+    pub fn render(&self, event: &LogEvent) -> String {
+        let fields = event.fields();
+        let text = |name: &str| {
+            fields
+                .get(name)
+                .map(display_value)
+                .unwrap_or_else(|| MISSING.to_owned())
+        };
+
+        let method = text("method");
+        let status = text("status_code");
+        let uri = truncate(&text("requested_uri"), self.uri_width());
+
+        let mut line = String::new();
+        line.push_str(&self.paint(DIM, &format!("{:<1$}", text("@timestamp"), TIMESTAMP_WIDTH)));
+        line.push(' ');
+        line.push_str(&self.paint(method_color(&method), &format!("{:<1$}", method, METHOD_WIDTH)));
+        line.push(' ');
+        line.push_str(&self.paint(status_color(&status), &format!("{:<1$}", status, STATUS_WIDTH)));
+        line.push(' ');
+        line.push_str(&format!("{:<1$}", uri, self.uri_width()));
+        line.push(' ');
+        line.push_str(&format!("{:>1$}", text("remote_host"), IP_WIDTH));
+        line.push(' ');
+        line.push_str(&format!("{:>1$}", text("content_length"), BYTES_WIDTH));
+
+        if self.verbose {
+            let mut rest: Vec<(&String, &LogFieldValue)> = fields
+                .iter()
+                .filter(|(k, _)| !COLUMNS.contains(&k.as_str()) && !HIDDEN.contains(&k.as_str()))
+                .collect();
+            rest.sort_by(|a, b| a.0.cmp(b.0));
+
+            if !rest.is_empty() {
+                let pairs: Vec<String> = rest
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, quote(&display_value(v))))
+                    .collect();
+                line.push(' ');
+                line.push_str(&self.paint(DIM, &pairs.join(" ")));
+            }
+        }
+
+        line
+    }
+
+    /// Add the number of duplicates dropped before an event to its rendered line.
+    pub fn repeat_count(&self, mut line: String, repeats: u64) -> String {
+        line.push(' ');
+        line.push_str(&self.paint(DIM, &format!("repeat_count={}", repeats)));
+        line
+    }
+
+    /// Width of the URI column, whatever is left of the line after the others.
+    fn uri_width(&self) -> usize {
+        let others = TIMESTAMP_WIDTH + METHOD_WIDTH + STATUS_WIDTH + IP_WIDTH + BYTES_WIDTH + 5;
+        match self.width {
+            Some(w) => w.saturating_sub(others).max(MIN_URI_WIDTH),
+            None => DEFAULT_URI_WIDTH,
+        }
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+/// Color of a request method based on what kind of request it is.
+fn method_color(method: &str) -> &'static str {
+    match method {
+        "GET" | "HEAD" | "OPTIONS" => BLUE,
+        "POST" => GREEN,
+        "PUT" | "PATCH" => YELLOW,
+        "DELETE" => RED,
+        _ => MAGENTA,
+    }
+}
+
+/// Color of a status code based on its class: errors are red, client errors are
+/// yellow, and everything else is green.
+fn status_color(status: &str) -> &'static str {
+    match status.as_bytes().first() {
+        Some(b'5') => RED,
+        Some(b'4') => YELLOW,
+        Some(b'1'..=b'3') => GREEN,
+        _ => DIM,
+    }
+}
+
+/// Value of a field as text, with nested fields and lists as JSON.
+fn display_value(value: &LogFieldValue) -> String {
+    match value {
+        LogFieldValue::Mapping(_) | LogFieldValue::List(_) => serde_json::to_string(value).unwrap_or_default(),
+        LogFieldValue::Timestamp(ts) => ts.to_rfc3339(),
+        LogFieldValue::Text(s) => s.clone(),
+        LogFieldValue::Int(n) => n.to_string(),
+        LogFieldValue::Float(f) => f.to_string(),
+        LogFieldValue::Bool(b) => b.to_string(),
+    }
+}
+
+/// Quote a value for `key=value` if it would otherwise be ambiguous.
+fn quote(value: &str) -> String {
+    if value.is_empty() || value.contains(char::is_whitespace) || value.contains('"') {
+        serde_json::to_string(value).unwrap_or_default()
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Shorten text to at most `width` characters, ending with an ellipsis if it's cut.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_owned();
+    }
+
+    let mut out: String = text.chars().take(width.saturating_sub(1)).collect();
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorChoice, OutputFormat, PrettyRenderer};
+    use crate::types::{LogEvent, LogFieldValue};
+    use chrono::DateTime;
+    use std::collections::HashMap;
+
+    fn event(uri: &str, method: &str, status: u64) -> LogEvent {
+        let mut fields = HashMap::new();
+        fields.insert(
+            "@timestamp".to_owned(),
+            LogFieldValue::Timestamp(DateTime::parse_from_rfc3339("2000-10-10T13:55:36-07:00").unwrap()),
+        );
+        fields.insert("method".to_owned(), LogFieldValue::Text(method.to_owned()));
+        fields.insert("status_code".to_owned(), LogFieldValue::Int(status));
+        fields.insert("requested_uri".to_owned(), LogFieldValue::Text(uri.to_owned()));
+        fields.insert("remote_host".to_owned(), LogFieldValue::Text("127.0.0.1".to_owned()));
+        fields.insert("content_length".to_owned(), LogFieldValue::Int(2326));
+        fields.insert(
+            "message".to_owned(),
+            LogFieldValue::Text("the original line".to_owned()),
+        );
+        fields.insert("protocol".to_owned(), LogFieldValue::Text("HTTP/1.1".to_owned()));
+        fields.insert("remote_user".to_owned(), LogFieldValue::Text("frank jones".to_owned()));
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!(OutputFormat::Json, "json".parse().unwrap());
+        assert_eq!(OutputFormat::EsBulk, "es-bulk".parse().unwrap());
+        assert_eq!(OutputFormat::PrettyLog, "pretty-log".parse().unwrap());
+        assert!("bulk".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_color_choice_enabled() {
+        assert_eq!(ColorChoice::Auto, "auto".parse().unwrap());
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+
+        assert!(ColorChoice::Auto.enabled(true, false));
+        assert!(!ColorChoice::Auto.enabled(true, true));
+        assert!(!ColorChoice::Auto.enabled(false, false));
+        assert!(ColorChoice::Always.enabled(false, true));
+        assert!(!ColorChoice::Never.enabled(true, false));
+    }
+
+    #[test]
+    fn test_pretty_renderer_plain() {
+        let renderer = PrettyRenderer::new();
+        assert_eq!(
+            "2000-10-10T13:55:36-07:00 GET     200 /index.html                                    127.0.0.1     2326",
+            renderer.render(&event("/index.html", "GET", 200))
+        );
+    }
+
+    #[test]
+    fn test_pretty_renderer_color() {
+        let renderer = PrettyRenderer::new().color(true);
+        assert_eq!(
+            concat!(
+                "\x1b[2m2000-10-10T13:55:36-07:00\x1b[0m ",
+                "\x1b[32mPOST   \x1b[0m ",
+                "\x1b[33m404\x1b[0m ",
+                "/api                                           127.0.0.1     2326",
+            ),
+            renderer.render(&event("/api", "POST", 404))
+        );
+        assert!(renderer
+            .render(&event("/", "DELETE", 503))
+            .contains("\x1b[31mDELETE \x1b[0m \x1b[31m503\x1b[0m"));
+        assert!(renderer
+            .render(&event("/", "GET", 301))
+            .contains("\x1b[34mGET    \x1b[0m \x1b[32m301\x1b[0m"));
+    }
+
+    #[test]
+    fn test_pretty_renderer_width() {
+        let renderer = PrettyRenderer::new().width(Some(80));
+        let line = renderer.render(&event("/a/very/long/path/that/does/not/fit", "GET", 200));
+        assert_eq!(
+            "2000-10-10T13:55:36-07:00 GET     200 /a/very/long/pat…       127.0.0.1     2326",
+            line
+        );
+        assert_eq!(80, line.chars().count());
+
+        // Columns are never narrower than the minimum, even if they don't fit.
+        let line = PrettyRenderer::new()
+            .width(Some(20))
+            .render(&event("/abcdefghijkl", "GET", 200));
+        assert!(line.contains(" /abcdefgh… "), "{}", line);
+    }
+
+    #[test]
+    fn test_pretty_renderer_verbose() {
+        let plain = PrettyRenderer::new().verbose(true).render(&event("/", "GET", 200));
+        assert!(
+            plain.ends_with("     2326 protocol=HTTP/1.1 remote_user=\"frank jones\""),
+            "{}",
+            plain
+        );
+
+        let color = PrettyRenderer::new()
+            .verbose(true)
+            .color(true)
+            .render(&event("/", "GET", 200));
+        assert!(
+            color.ends_with(" \x1b[2mprotocol=HTTP/1.1 remote_user=\"frank jones\"\x1b[0m"),
+            "{}",
+            color
+        );
+    }
+
+    #[test]
+    fn test_pretty_renderer_missing_fields() {
+        let renderer = PrettyRenderer::new().color(true);
+        assert_eq!(
+            concat!(
+                "\x1b[2m-                        \x1b[0m \x1b[35m-      \x1b[0m \x1b[2m-  \x1b[0m ",
+                "-                                                      -        -",
+            ),
+            renderer.render(&LogEvent::from(HashMap::new()))
+        );
+    }
+
+    #[test]
+    fn test_pretty_renderer_repeat_count() {
+        let renderer = PrettyRenderer::new();
+        assert_eq!("line repeat_count=3", renderer.repeat_count("line".to_owned(), 3));
+        let renderer = PrettyRenderer::new().color(true);
+        assert_eq!(
+            "line \x1b[2mrepeat_count=3\x1b[0m",
+            renderer.repeat_count("line".to_owned(), 3)
+        );
+    }
+}
@@ -18,13 +18,28 @@
 
 //! Parsers for various access log formats
 
-use crate::types::{LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
-use chrono::DateTime;
+use crate::enrich::percent_decode;
+use crate::format::{
+    normalize_header, unescape_quoted, FieldKind, FormatField, LogFormat, COMBINED_FORMAT, COMMON_FORMAT,
+    VARNISHNCSA_FORMAT, VHOST_COMBINED_FORMAT,
+};
+use crate::timestamp::{AssumedZone, TimestampContext};
+use crate::types::{DurationUnit, LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone, Utc};
 use regex::{Captures, Regex};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
 
-const COMMON_LOG_TIMESTAMP: &str = "%d/%b/%Y:%T %z";
+pub(crate) const COMMON_LOG_TIMESTAMP: &str = "%d/%b/%Y:%T %z";
+const ERROR_LOG_TIMESTAMP: &str = "%b %d %T%.f %Y";
+const PYTHON_DEV_SERVER_TIMESTAMP: &str = "%d/%b/%Y %T";
+const TOMCAT_TIMESTAMP: &str = "%d/%b/%Y:%T %#z";
+const ISO8601_TIMESTAMP: &str = "%Y-%m-%dT%T%.f%#z";
+const W3C_TIMESTAMP: &str = "%Y-%m-%d %T%.f";
 const OUTPUT_VERSION: &str = "1";
 
 /// Parse a single log line of a pre-determined format into an object
@@ -42,6 +57,67 @@ pub trait LogLineParser {
     /// The fields of the `LogEvent` object should match the names expected
     /// by [Logstash](https://github.com/logstash/logstash-logback-encoder#standard-fields).
     fn parse(&self, line: &str) -> RedeyeResult<LogEvent>;
+
+    /// Handle the given line if it's a directive instead of an event, e.g. one
+    /// that describes the lines after it, and return true if it was.
+    ///
+    /// Callers should skip lines that are directives instead of parsing them.
+    /// Most formats don't have directives so the default implementation always
+    /// returns false.
+    fn directive(&self, _line: &str) -> bool {
+        false
+    }
+
+    /// True if parsing a line depends on the lines read before it, e.g. because
+    /// directives describe the lines after them.
+    ///
+    /// Lines for these parsers must be handled one at a time in the order they
+    /// were read. Most formats parse each line on its own so the default
+    /// implementation returns false.
+    fn is_stateful(&self) -> bool {
+        false
+    }
+
+    /// Remove and return events that are ready without being the result of
+    /// parsing a line, e.g. those for requests that were given up on while
+    /// waiting for the line that completes them.
+    ///
+    /// Callers should take these events after handling each line. The default
+    /// implementation never has any.
+    fn take_events(&self) -> Vec<LogEvent> {
+        Vec::new()
+    }
+
+    /// Remove and return events for everything still buffered, including those
+    /// returned by `take_events`, once there are no more lines to handle.
+    ///
+    /// The default implementation never has any.
+    fn flush_events(&self) -> Vec<LogEvent> {
+        Vec::new()
+    }
+
+    /// Full name and type of each field emitted when parsing lines, in the same
+    /// form as `LogFormat::schema`, or `None` if the fields depend on the lines.
+    ///
+    /// Formats where each line names its own fields (e.g. LTSV or logfmt) can't
+    /// know them ahead of time, so the default implementation returns `None`.
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        None
+    }
+}
+
+/// Schema of the given Apache format with extra fields emitted after the fields
+/// of the format, before the `@version` and `message` fields.
+fn extended_schema(spec: &str, extra: Vec<(String, &'static str)>) -> Vec<(String, &'static str)> {
+    let mut schema = LogFormat::apache(spec).expect("built in formats must compile").schema();
+    let at = schema.len() - 2;
+    schema.splice(at..at, extra);
+    schema
+}
+
+/// Owned names and types of the given fields, for building a schema.
+fn schema_fields(fields: &[(&str, &'static str)]) -> Vec<(String, &'static str)> {
+    fields.iter().map(|(name, kind)| ((*name).to_owned(), *kind)).collect()
 }
 
 /// Implementation of a `LogLineParser` that parses access logs in the
@@ -136,6 +212,12 @@ impl CommonLogLineParser {
             ),
         }
     }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
 }
 
 impl Default for CommonLogLineParser {
@@ -167,6 +249,10 @@ impl LogLineParser for CommonLogLineParser {
 
         Ok(LogEvent::from(fields))
     }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(COMMON_FORMAT, Vec::new()))
+    }
 }
 
 /// Implementation of a `LogLineParser` that parses access logs in the
@@ -204,7 +290,7 @@ impl LogLineParser for CommonLogLineParser {
 ///   "content_length": 2326,
 ///   "request_headers": {
 ///     "referer": "http://www.example.com/start.html",
-///     "user-agent": "Mozilla/4.08 [en] (Win98; I ;Nav)"
+///     "user_agent": "Mozilla/4.08 [en] (Win98; I ;Nav)"
 ///   },
 ///   "@version": "1",
 ///   "message": "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326 \"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\""
@@ -245,33 +331,184 @@ impl LogLineParser for CommonLogLineParser {
 ///     headers.get("referer").unwrap(),
 /// );
 /// ```
+///
+/// Lines with quoted response headers after the user agent, such as those
+/// written with the format `%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i" "%{Content-Type}o"`,
+/// can be parsed by giving the names of the headers in the order they appear.
+/// They're nested in a `response_headers` mapping with names lowercased and `-`
+/// replaced by `_`, and `Content-Type` is also a top-level `mime_type` field.
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, CombinedLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CombinedLogLineParser::new().response_headers(&["Content-Type", "Cache-Control"]);
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 \"-\" \"curl/7.64.1\" \"text/html\" \"-\"").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("text/html".to_string())), event.lookup("response_headers.content_type"));
+/// assert_eq!(Some(&LogFieldValue::Text("text/html".to_string())), event.lookup("mime_type"));
+/// assert_eq!(None, event.lookup("response_headers.cache_control"));
+/// ```
+///
+/// Lines written by nginx with `$request_time` after the user agent, the stock
+/// `combined` format with ` $request_time` appended, can be parsed as well. The
+/// time is a `request_time` field in seconds, as a float.
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, CombinedLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CombinedLogLineParser::new().request_time(true);
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 0.042").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Float(0.042)), event.lookup("request_time"));
+/// ```
+///
+/// Lines written by Apache with the duration of the request after the user agent,
+/// using `%D` (microseconds), `%{ms}T` (milliseconds), or `%T` (seconds), can be
+/// parsed by giving the unit of the duration. It's a `duration_us` field in
+/// microseconds whatever the unit, so the same as `%D` in a custom format.
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, CombinedLogLineParser};
+/// use redeye::types::{DurationUnit, LogFieldValue};
+///
+/// let parser = CombinedLogLineParser::new().duration(DurationUnit::Milliseconds);
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 42").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(42_000)), event.lookup("duration_us"));
+/// ```
+///
+/// Lines with the quoted `X-Forwarded-For` request header right after the user
+/// agent can be parsed too. Its comma separated addresses are a list in the
+/// `request_headers` mapping as `x_forwarded_for`, and the first of them (the
+/// original client, if the proxies are trusted) is also the `client_ip` field.
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, CombinedLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CombinedLogLineParser::new().forwarded_for(true);
+/// let event = parser.parse("10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" \"203.0.113.7, 10.0.0.2\"").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("203.0.113.7".to_string())), event.lookup("client_ip"));
+/// assert_eq!(
+///     Some(&LogFieldValue::List(vec![
+///         LogFieldValue::Text("203.0.113.7".to_string()),
+///         LogFieldValue::Text("10.0.0.2".to_string()),
+///     ])),
+///     event.lookup("request_headers.x_forwarded_for"),
+/// );
+/// ```
+///
+/// Lines written by nginx with `$upstream_addr $upstream_response_time $upstream_status`
+/// after the user agent can be parsed too. They're nested in an `upstream` mapping
+/// as `addr`, `response_time`, and `status`. When nginx tries more than one upstream
+/// server for a request it writes a value for each of them, which become lists.
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, CombinedLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CombinedLogLineParser::new().upstream(true);
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 10.0.0.5:8080, 10.0.0.6:8080 0.032, 0.015 502, 200").unwrap();
+///
+/// assert_eq!(
+///     Some(&LogFieldValue::List(vec![LogFieldValue::Int(502), LogFieldValue::Int(200)])),
+///     event.lookup("upstream.status"),
+/// );
+/// ```
 #[derive(Debug, Clone)]
 pub struct CombinedLogLineParser {
     inner: ParserImpl,
+    forwarded_for: bool,
+    request_time: bool,
+    duration: Option<DurationUnit>,
+    upstream: bool,
+    response_headers: Vec<String>,
 }
 
 impl CombinedLogLineParser {
     pub fn new() -> Self {
         Self {
-            inner: ParserImpl::new(
-                Regex::new(concat!(
-                    r"^([^\s]+)\s+",    // host
-                    r"([^\s]+)\s+",     // rfc1413 ident
-                    r"([^\s]+)\s+",     // username
-                    r"\[(.+)\]\s+",     // timestamp
-                    "\"(",              // open " and HTTP request
-                    r"([^\s]+)\s",      // method
-                    r"([^\s]+)\s",      // path
-                    r"([^\s]+)",        // protocol
-                    ")\"\\s+",          // close " and HTTP request
-                    r"([^\s]+)\s+",     // status
-                    r"([^\s]+)\s+",     // bytes
-                    "\"([^\"]+)\"\\s+", // "referer" [sic]
-                    "\"([^\"]+)\"$",    // "user agent"
-                ))
-                .unwrap(),
-            ),
+            inner: ParserImpl::new(combined_regex(&[], 0)),
+            forwarded_for: false,
+            request_time: false,
+            duration: None,
+            upstream: false,
+            response_headers: Vec::new(),
+        }
+    }
+
+    /// Parse the quoted `X-Forwarded-For` header of each request right after the
+    /// user agent, before any other values.
+    pub fn forwarded_for(mut self, forwarded_for: bool) -> Self {
+        self.forwarded_for = forwarded_for;
+        self.inner.regex = self.regex();
+        self
+    }
+
+    /// Parse the time taken by each request after the user agent (and the
+    /// `X-Forwarded-For` header, if any), before any response headers.
+    pub fn request_time(mut self, request_time: bool) -> Self {
+        self.request_time = request_time;
+        self.inner.regex = self.regex();
+        self
+    }
+
+    /// Parse the duration of each request in the given unit after the user agent
+    /// (and request time, if any), before any response headers. It's emitted as
+    /// an integer number of microseconds regardless of the unit.
+    pub fn duration(mut self, unit: DurationUnit) -> Self {
+        self.duration = Some(unit);
+        self.inner.regex = self.regex();
+        self
+    }
+
+    /// Parse the address, response time, and status of the upstream servers that
+    /// nginx sent each request to after the user agent (and request time and
+    /// duration, if any), before any response headers.
+    pub fn upstream(mut self, upstream: bool) -> Self {
+        self.upstream = upstream;
+        self.inner.regex = self.regex();
+        self
+    }
+
+    /// Parse quoted response headers with the given names after the user agent.
+    pub fn response_headers<S>(mut self, names: &[S]) -> Self
+    where
+        S: AsRef<str>,
+    {
+        self.response_headers = names.iter().map(|n| normalize_header(n.as_ref())).collect();
+        self.inner.regex = self.regex();
+        self
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
+
+    fn regex(&self) -> Regex {
+        let mut trailing = Vec::new();
+        if self.forwarded_for {
+            trailing.push(TRAILING_QUOTED);
         }
+
+        if self.request_time {
+            trailing.push(TRAILING_VALUE);
+        }
+
+        if self.duration.is_some() {
+            trailing.push(TRAILING_VALUE);
+        }
+
+        if self.upstream {
+            trailing.extend(&[TRAILING_LIST, TRAILING_LIST, TRAILING_LIST]);
+        }
+
+        combined_regex(&trailing, self.response_headers.len())
     }
 }
 
@@ -285,315 +522,5958 @@ impl LogLineParser for CombinedLogLineParser {
     fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
         let line = line.trim();
 
-        let fields = self
-            .inner
-            .apply(line)?
-            .add_text_field("remote_host", 1)?
-            .add_text_field("ident", 2)?
-            .add_text_field("remote_user", 3)?
-            .add_timestamp_field("@timestamp", 4, COMMON_LOG_TIMESTAMP)?
-            .add_text_field("requested_url", 5)?
-            .add_text_field("method", 6)?
-            .add_text_field("requested_uri", 7)?
-            .add_text_field("protocol", 8)?
-            .add_int_field("status_code", 9)?
-            .add_int_field("content_length", 10)?
-            .add_mapping_field("request_headers")
-            .add_text_field("referer", 11)?
-            .add_text_field("user-agent", 12)?
-            .complete_mapping()
+        let builder = self.inner.apply(line)?.add_combined_fields()?;
+
+        let (builder, next) = if self.forwarded_for {
+            (builder.add_forwarded_for_fields(13)?, 14)
+        } else {
+            (builder, 13)
+        };
+
+        let (builder, next) = if self.request_time {
+            (builder.add_float_field("request_time", next)?, next + 1)
+        } else {
+            (builder, next)
+        };
+
+        let (builder, next) = match self.duration {
+            Some(unit) => (builder.add_duration_field("duration_us", next, unit)?, next + 1),
+            None => (builder, next),
+        };
+
+        let (builder, headers) = if self.upstream {
+            let builder = builder
+                .add_mapping_field("upstream")
+                .add_list_field("addr", next, |v| Some(LogFieldValue::Text(v.to_owned())))?
+                .add_list_field("response_time", next + 1, float_value)?
+                .add_list_field("status", next + 2, |v| v.parse().ok().map(LogFieldValue::Int))?
+                .complete_mapping();
+            (builder, next + 3)
+        } else {
+            (builder, next)
+        };
+
+        let mut builder = builder.add_mapping_field("response_headers");
+        for (i, name) in self.response_headers.iter().enumerate() {
+            builder = builder.add_text_field(name.as_str(), headers + i)?;
+        }
+        let mut builder = builder.complete_mapping();
+
+        if let Some(i) = self.response_headers.iter().position(|n| n == "content_type") {
+            builder = builder.add_text_field("mime_type", headers + i)?;
+        }
+
+        let fields = builder
             .add_fixed_value("@version", OUTPUT_VERSION)
             .add_fixed_value("message", line)
             .build();
 
         Ok(LogEvent::from(fields))
     }
-}
 
-/// Regex-based parser for constructing logging events from an access log.
-///
-/// The provided regular expression is applied and log line and a builder is
-/// returned that is used to parse captured values and build up a `HashMap`
-/// of fields and values.
-#[derive(Debug, Clone)]
-struct ParserImpl {
-    regex: Regex,
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        let mut extra = Vec::new();
+        if self.forwarded_for {
+            extra.extend(schema_fields(&[
+                ("client_ip", "text"),
+                ("request_headers.x_forwarded_for", "text"),
+            ]));
+        }
+
+        if self.request_time {
+            extra.push(("request_time".to_owned(), "float"));
+        }
+
+        if self.duration.is_some() {
+            extra.push(("duration_us".to_owned(), "int"));
+        }
+
+        if self.upstream {
+            extra.extend(schema_fields(&[
+                ("upstream.addr", "text"),
+                ("upstream.response_time", "float"),
+                ("upstream.status", "int"),
+            ]));
+        }
+
+        for name in self.response_headers.iter() {
+            extra.push((format!("response_headers.{}", name), "text"));
+        }
+
+        if self.response_headers.iter().any(|n| n == "content_type") {
+            extra.push(("mime_type".to_owned(), "text"));
+        }
+
+        Some(extended_schema(COMBINED_FORMAT, extra))
+    }
 }
 
-impl ParserImpl {
-    fn new(regex: Regex) -> Self {
-        Self { regex }
+/// Pattern for a single value after the user agent, e.g. the request time.
+const TRAILING_VALUE: &str = r"([^\s]+)";
+
+/// Pattern for a single quoted value after the user agent, e.g. a request header.
+const TRAILING_QUOTED: &str = "\"([^\"]*)\"";
+
+/// Pattern for one or more values after the user agent separated by `, ` or ` : `,
+/// e.g. the address of each upstream server tried by nginx.
+const TRAILING_LIST: &str = r"([^\s,]+(?:(?:, | : )[^\s,]+)*)";
+
+/// Pattern for the Combined log format, up to and including the user agent.
+const COMBINED_PATTERN: &str = concat!(
+    r"^([^\s]+)\s+",    // host
+    r"([^\s]+)\s+",     // rfc1413 ident
+    r"([^\s]+)\s+",     // username
+    r"\[(.+)\]\s+",     // timestamp
+    "\"(",              // open " and HTTP request
+    r"([^\s]+)\s",      // method
+    r"([^\s]+)\s",      // path
+    r"([^\s]+)",        // protocol
+    ")\"\\s+",          // close " and HTTP request
+    r"([^\s]+)\s+",     // status
+    r"([^\s]+)\s+",     // bytes
+    "\"([^\"]+)\"\\s+", // "referer" [sic]
+    "\"([^\"]+)\"",     // "user agent"
+);
+
+/// Regex for the Combined log format followed by the given patterns for values
+/// after the user agent and the given number of quoted response headers.
+fn combined_regex(trailing: &[&str], response_headers: usize) -> Regex {
+    let mut pattern = String::from(COMBINED_PATTERN);
+
+    for value in trailing {
+        pattern.push_str(r"\s+");
+        pattern.push_str(value);
     }
 
-    fn apply<'a>(&'a self, line: &'a str) -> RedeyeResult<FieldBuilder> {
-        self.regex
-            .captures(line)
-            .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
-            .map(|matches| FieldBuilder::root(line, matches))
+    for _ in 0..response_headers {
+        pattern.push_str("\\s+\"([^\"]*)\""); // "response header"
     }
-}
 
-/// Builder for constructing a `HashMap` of fields and values based
-/// on the results of parsing log values from the provided `Captures`
-/// object.
-#[derive(Debug)]
-struct FieldBuilder<'a> {
-    line: &'a str,
-    captures: Rc<Captures<'a>>,
-    field: Option<String>,
-    parent: Option<Box<FieldBuilder<'a>>>,
-    values: HashMap<String, LogFieldValue>,
+    pattern.push('$');
+    Regex::new(&pattern).unwrap()
 }
 
-impl<'a> FieldBuilder<'a> {
-    /// Create a new root field builder for parsing fields from the given
-    /// `regex::Captures` object.
-    fn root(line: &'a str, captures: Captures<'a>) -> Self {
-        let len = captures.len();
+/// Implementation of a `LogLineParser` that parses access logs in the Apache
+/// `vhost_combined` format: the Combined format prefixed with the name and port
+/// of the virtual host that handled the request.
+///
+/// The name and port are emitted as the `server_name` and `server_port` fields
+/// and the rest of the line is parsed the same as by `CombinedLogLineParser`.
+/// The port may be missing, in which case only `server_name` is emitted, and
+/// IPv6 addresses in brackets (e.g. `[::1]:80`) are kept as they're written.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, VhostCombinedLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = VhostCombinedLogLineParser::new();
+/// let event = parser.parse("www.example.com:443 127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 \"-\" \"curl/7.64.1\"").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("www.example.com".to_string())), event.lookup("server_name"));
+/// assert_eq!(Some(&LogFieldValue::Int(443)), event.lookup("server_port"));
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VhostCombinedLogLineParser {
+    inner: CombinedLogLineParser,
+}
 
-        FieldBuilder {
-            line,
-            captures: Rc::new(captures),
-            field: None,
-            parent: None,
-            values: HashMap::with_capacity(len),
+impl VhostCombinedLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: CombinedLogLineParser::new(),
         }
     }
 
-    /// Create a nested field builder object for parsing fields from the
-    /// given `regex::Captures` object and parent builder that control will
-    /// be returned to when `.complete_mapping()` is called.
-    fn leaf(line: &'a str, captures: Rc<Captures<'a>>, field: String, parent: Box<FieldBuilder<'a>>) -> Self {
-        FieldBuilder {
-            line,
-            captures,
-            field: Some(field),
-            parent: Some(parent),
-            values: HashMap::new(),
-        }
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner = self.inner.timestamp_context(context);
+        self
     }
+}
 
-    /// Parse the text value in position `index` and output the field
-    /// using the given name. Return an error if the value could not be
-    /// parsed.
-    fn add_text_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
-    where
-        S: Into<String>,
-    {
-        let res = parse_text_value(&self.captures, index, self.line)?;
-        if let Some(v) = res {
-            self.values.insert(field.into(), v);
-        }
+impl LogLineParser for VhostCombinedLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let invalid = || RedeyeError::ParseError(line.to_string());
 
-        Ok(self)
-    }
+        let (vhost, rest) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let (name, port) = split_vhost(vhost).ok_or_else(invalid)?;
+        let port = match port.and_then(empty_field) {
+            Some(p) => Some(p.parse::<u64>().map_err(|_| invalid())?),
+            None => None,
+        };
 
-    /// Parse the timestamp value in position `index` and output the field
-    /// using the given name. Return an error if the value could not be parsed.
-    fn add_timestamp_field<S>(mut self, field: S, index: usize, format: &str) -> RedeyeResult<Self>
-    where
-        S: Into<String>,
-    {
-        let res = parse_timestamp(&self.captures, index, self.line, format)?;
-        if let Some(v) = res {
-            self.values.insert(field.into(), v);
+        let mut event = self.inner.parse(rest).map_err(|e| match e {
+            RedeyeError::ParseError(_) => invalid(),
+            e => e,
+        })?;
+
+        if let Some(name) = empty_field(name) {
+            event.insert("server_name", LogFieldValue::Text(name.to_owned()));
         }
 
-        Ok(self)
+        if let Some(port) = port {
+            event.insert("server_port", LogFieldValue::Int(port));
+        }
+
+        event.insert("message", LogFieldValue::Text(line.to_owned()));
+        Ok(event)
     }
 
-    /// Parse the integer value in position `index` and output the field
-    /// using the given name. Return an error if the value could not be parsed.
-    fn add_int_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
-    where
-        S: Into<String>,
-    {
-        let res = parse_int_value(&self.captures, index, self.line)?;
-        if let Some(v) = res {
-            self.values.insert(field.into(), v);
-        }
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(VHOST_COMBINED_FORMAT, Vec::new()))
+    }
+}
 
-        Ok(self)
+/// Split a virtual host written as `name:port`, `[address]:port`, or just the
+/// name or address into the name and port, if there is one.
+fn split_vhost(vhost: &str) -> Option<(&str, Option<&str>)> {
+    if vhost.starts_with('[') {
+        let end = vhost.find(']')? + 1;
+        return match &vhost[end..] {
+            "" => Some((&vhost[..end], None)),
+            rest => rest.strip_prefix(':').map(|p| (&vhost[..end], Some(p))),
+        };
     }
 
-    /// Add a literal string value and output the field using the given name.
-    fn add_fixed_value<K, V>(mut self, field: K, value: V) -> Self
-    where
-        K: Into<String>,
-        V: Into<String>,
-    {
-        self.values.insert(field.into(), LogFieldValue::Text(value.into()));
-        self
+    match vhost.split_once(':') {
+        Some((name, port)) => Some((name, Some(port))),
+        None => Some((vhost, None)),
     }
+}
 
-    /// Return a new `FieldBuilder` that will be used to construct a nested
-    /// mapping value and will be output using the given name. Note that callers
-    /// must also make a corresponding call to `.complete_mapping()` after adding
-    /// all desired values to the nested mapping.
-    fn add_mapping_field<S>(self, field: S) -> Self
-    where
-        S: Into<String>,
-    {
-        let parent = Box::new(self);
-        FieldBuilder::leaf(parent.line, parent.captures.clone(), field.into(), parent)
+/// Implementation of a `LogLineParser` that parses access logs written by the
+/// Jetty `RequestLog` in extended NCSA mode.
+///
+/// Lines are in the Combined format, optionally followed by the cookies of the
+/// request (quoted, or `-` if there were none) and the latency of the request in
+/// milliseconds. The latency is emitted as the `duration_ms` field and the cookies
+/// are ignored. Lines without either are parsed exactly as by `CombinedLogLineParser`,
+/// so the same parser works for Jetty deployments with and without latency logging.
+///
+/// Requests sent through a proxy may have the authority (e.g. `CONNECT example.com:443`)
+/// or an absolute URL instead of a path. These are kept in `requested_uri` as written.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{JettyLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = JettyLogLineParser::new();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" - 42").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert_eq!(Some(&LogFieldValue::Int(42)), event.lookup("duration_ms"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct JettyLogLineParser {
+    inner: ParserImpl,
+}
+
+impl JettyLogLineParser {
+    pub fn new() -> Self {
+        let pattern = format!(
+            "{}{}{}$",
+            COMBINED_PATTERN,
+            "(?:\\s+(?:\"[^\"]*\"|-))?", // optional "cookies"
+            r"(?:\s+(\d+))?",            // optional latency
+        );
+
+        Self {
+            inner: ParserImpl::new(Regex::new(&pattern).unwrap()),
+        }
     }
 
-    /// Complete adding fields to a nested mapping value and return the original
-    /// `FieldBuilder` instance to continue working on the previous set of fields.
-    fn complete_mapping(self) -> Self {
-        // Unwraps are OK here because if we're calling this method when not building
-        // a nested mapping, that's a bug completely within our control and panicking
-        // is the most obvious way to handle it.
-        let mut parent = self.parent.unwrap();
-        if !self.values.is_empty() {
-            parent
-                .values
-                .insert(self.field.unwrap(), LogFieldValue::Mapping(self.values));
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
+}
+
+impl Default for JettyLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for JettyLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_combined_fields()?
+            .add_optional_int_field("duration_ms", 13)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(
+            COMBINED_FORMAT,
+            schema_fields(&[("duration_ms", "int")]),
+        ))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses access logs in the Apache
+/// `combinedio` format: the Combined format followed by the number of bytes
+/// received and sent, including headers, as logged by `mod_logio` (`%I %O`).
+///
+/// The byte counts are emitted as the `bytes_received` and `bytes_sent` fields in
+/// addition to `content_length`. Lines without them, written before `mod_logio`
+/// was enabled, are parsed exactly as by `CombinedLogLineParser` so files with
+/// both kinds of lines can be parsed with the same parser.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{CombinedIoLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CombinedIoLogLineParser::new();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 530 982").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(612)), event.lookup("content_length"));
+/// assert_eq!(Some(&LogFieldValue::Int(530)), event.lookup("bytes_received"));
+/// assert_eq!(Some(&LogFieldValue::Int(982)), event.lookup("bytes_sent"));
+///
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\"").unwrap();
+/// assert_eq!(None, event.lookup("bytes_sent"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CombinedIoLogLineParser {
+    inner: ParserImpl,
+}
+
+impl CombinedIoLogLineParser {
+    pub fn new() -> Self {
+        let pattern = format!(
+            "{}{}$",
+            COMBINED_PATTERN,
+            r"(?:\s+(\d+)\s+(\d+))?", // optional bytes received and sent
+        );
+
+        Self {
+            inner: ParserImpl::new(Regex::new(&pattern).unwrap()),
         }
+    }
 
-        *parent
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
     }
+}
 
-    /// Complete parsing and build fields and return a `HashMap` of the values.
-    fn build(self) -> HashMap<String, LogFieldValue> {
-        self.values
+impl Default for CombinedIoLogLineParser {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-/// Parse the regex capture identified by `index into a timestamp with
-/// a fixed offset.
+impl LogLineParser for CombinedIoLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_combined_fields()?
+            .add_optional_int_field("bytes_received", 13)?
+            .add_optional_int_field("bytes_sent", 14)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(
+            COMBINED_FORMAT,
+            schema_fields(&[("bytes_received", "int"), ("bytes_sent", "int")]),
+        ))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses access logs written by the
+/// Kubernetes ingress-nginx controller in its default format.
 ///
-/// Return an error if the capture was missing (the field didn't exist
-/// at all, which is not the same as being empty, aka `-`) or the field
-/// could not be parsed into a timestamp. Return `Ok(None)` if the field
-/// exists but contains an empty value (`-`).
-fn parse_timestamp(matches: &Captures, index: usize, line: &str, format: &str) -> RedeyeResult<Option<LogFieldValue>> {
-    let field_match = matches
-        .get(index)
-        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
-        .map(|m| m.as_str())
-        .map(empty_field)?;
+/// Lines are in the Combined format followed by
+/// `$request_length $request_time [$proxy_upstream_name] [$proxy_alternative_upstream_name]`
+/// and `$upstream_addr $upstream_response_length $upstream_response_time $upstream_status $req_id`.
+/// These are emitted as the `request_length`, `request_time` (a float, in seconds),
+/// `proxy_upstream_name`, `proxy_alternative_upstream_name`, and `request_id` fields,
+/// and the `addr`, `response_length`, `response_time`, and `status` fields of an
+/// `upstream` mapping. The upstream names are often empty (`[]`), in which case they
+/// are omitted. When a request is retried on several upstream servers each of the
+/// upstream fields is a list with a value for every server tried, the same as with
+/// `CombinedLogLineParser::upstream`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{IngressNginxLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = IngressNginxLogLineParser::new();
+/// let event = parser.parse(concat!(
+///     "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 78 0.003 ",
+///     "[default-web-80] [] 10.244.0.7:8080 612 0.003 200 5f9c1ae8b4d1c2a3e4f5a6b7c8d9e0f1",
+/// )).unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(78)), event.lookup("request_length"));
+/// assert_eq!(Some(&LogFieldValue::Text("default-web-80".to_string())), event.lookup("proxy_upstream_name"));
+/// assert_eq!(None, event.lookup("proxy_alternative_upstream_name"));
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("upstream.status"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct IngressNginxLogLineParser {
+    inner: ParserImpl,
+}
 
-    if let Some(v) = field_match {
-        Ok(Some(LogFieldValue::Timestamp(DateTime::parse_from_str(v, format)?)))
-    } else {
-        Ok(None)
+impl IngressNginxLogLineParser {
+    pub fn new() -> Self {
+        let trailing = [
+            r"(\d+)",         // request length
+            TRAILING_VALUE,   // request time
+            r"\[([^\]]+)?\]", // [proxy upstream name]
+            r"\[([^\]]+)?\]", // [proxy alternative upstream name]
+            TRAILING_LIST,    // upstream addresses
+            TRAILING_LIST,    // upstream response lengths
+            TRAILING_LIST,    // upstream response times
+            TRAILING_LIST,    // upstream statuses
+            TRAILING_VALUE,   // request ID
+        ];
+
+        Self {
+            inner: ParserImpl::new(combined_regex(&trailing, 0)),
+        }
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
     }
 }
 
-/// Parse the regex capture identified by `index` into a string value.
+impl Default for IngressNginxLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for IngressNginxLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let int = |v: &str| v.parse().ok().map(LogFieldValue::Int);
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_combined_fields()?
+            .add_int_field("request_length", 13)?
+            .add_float_field("request_time", 14)?
+            .add_optional_text_field("proxy_upstream_name", 15)?
+            .add_optional_text_field("proxy_alternative_upstream_name", 16)?
+            .add_mapping_field("upstream")
+            .add_list_field("addr", 17, |v| Some(LogFieldValue::Text(v.to_owned())))?
+            .add_list_field("response_length", 18, int)?
+            .add_list_field("response_time", 19, float_value)?
+            .add_list_field("status", 20, int)?
+            .complete_mapping()
+            .add_text_field("request_id", 21)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(
+            COMBINED_FORMAT,
+            schema_fields(&[
+                ("request_length", "int"),
+                ("request_time", "float"),
+                ("proxy_upstream_name", "text"),
+                ("proxy_alternative_upstream_name", "text"),
+                ("upstream.addr", "text"),
+                ("upstream.response_length", "int"),
+                ("upstream.response_time", "float"),
+                ("upstream.status", "int"),
+                ("request_id", "text"),
+            ]),
+        ))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses access logs written by
+/// Traefik in its default Common Log Format.
 ///
-/// Return an error if the capture was missing (the field didn't exist
-/// at all, which is not the same as being empty, aka `-`). Return
-/// `Ok(None)` if the field exists but contains an empty value (`-`).
-fn parse_text_value(matches: &Captures, index: usize, line: &str) -> RedeyeResult<Option<LogFieldValue>> {
-    matches
-        .get(index)
-        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
-        .map(|m| m.as_str())
-        .map(empty_field)
-        .map(|o| o.map(|s| LogFieldValue::Text(s.to_string())))
+/// Despite the name, lines are in the Combined format followed by the number of
+/// requests Traefik has received, the name of the router that handled the request,
+/// the URL of the server it was sent to, and the duration of the request. These
+/// are emitted as the `request_count`, `router_name`, `backend_url`, and `duration_ms`
+/// fields. The router and server are `-` for requests that didn't match a router,
+/// in which case the fields are omitted. The duration is written with a unit (e.g.
+/// `12ms`, `850µs`, or `1.2s`) and emitted as a, possibly fractional, number of
+/// milliseconds.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, TraefikLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = TraefikLogLineParser::new();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 412 \"websecure-router@docker\" \"http://10.0.0.3:8080\" 12ms").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(412)), event.lookup("request_count"));
+/// assert_eq!(Some(&LogFieldValue::Text("websecure-router@docker".to_string())), event.lookup("router_name"));
+/// assert_eq!(Some(&LogFieldValue::Float(12.0)), event.lookup("duration_ms"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TraefikLogLineParser {
+    inner: ParserImpl,
+}
+
+impl TraefikLogLineParser {
+    pub fn new() -> Self {
+        let trailing = [
+            r"(\d+)",       // request count
+            "\"([^\"]*)\"", // "router name"
+            "\"([^\"]*)\"", // "server URL"
+            TRAILING_VALUE, // duration
+        ];
+
+        Self {
+            inner: ParserImpl::new(combined_regex(&trailing, 0)),
+        }
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
+}
+
+impl Default for TraefikLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for TraefikLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_combined_fields()?
+            .add_int_field("request_count", 13)?
+            .add_text_field("router_name", 14)?
+            .add_text_field("backend_url", 15)?
+            .add_go_duration_field("duration_ms", 16)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(
+            COMBINED_FORMAT,
+            schema_fields(&[
+                ("request_count", "int"),
+                ("router_name", "text"),
+                ("backend_url", "text"),
+                ("duration_ms", "float"),
+            ]),
+        ))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses access logs written by the
+/// Tomcat `AccessLogValve` using its `common` or `combined` patterns, each
+/// optionally followed by the time taken to process the request (`%D`).
+///
+/// Fields are named the same as the fields of `CombinedLogLineParser`. The time
+/// taken is written in milliseconds by Tomcat (before version 10) and is emitted
+/// as the `duration_ms` field. The offset of timestamps may be written as a
+/// literal `Z` instead of `+0000`. Tomcat writes a status of `-` when the client
+/// aborted the connection before the response, in which case the `status_code`
+/// field is omitted.
+///
+/// Lines with the name of the thread that handled the request (`%I`) after the
+/// time taken can be parsed too, emitting it as the `thread_name` field.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, TomcatLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = TomcatLogLineParser::new();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 42").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert_eq!(Some(&LogFieldValue::Int(42)), event.lookup("duration_ms"));
+///
+/// let parser = TomcatLogLineParser::new().thread_name(true);
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 Z] \"GET / HTTP/1.1\" - - 42 http-nio-8080-exec-1").unwrap();
+///
+/// assert_eq!(None, event.lookup("status_code"));
+/// assert_eq!(Some(&LogFieldValue::Text("http-nio-8080-exec-1".to_string())), event.lookup("thread_name"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TomcatLogLineParser {
+    inner: ParserImpl,
+    thread_name: bool,
+}
+
+impl TomcatLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: ParserImpl::new(tomcat_regex(false)),
+            thread_name: false,
+        }
+    }
+
+    /// Parse the name of the thread that handled each request after the time
+    /// taken (or the status and bytes, if the time taken isn't logged).
+    pub fn thread_name(mut self, thread_name: bool) -> Self {
+        self.thread_name = thread_name;
+        self.inner.regex = tomcat_regex(thread_name);
+        self
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
+}
+
+impl Default for TomcatLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-/// Parse the regex capture identified by `index` into an integer value.
-///
-/// Return an error if the capture was missing (the field didn't exist
-/// at all, which is not the same as being empty, aka `-`) or the field
-/// could not be parsed into an integer. Return `Ok(None)` if the field
-/// exists but contains an empty value (`-`).
-fn parse_int_value(matches: &Captures, index: usize, line: &str) -> RedeyeResult<Option<LogFieldValue>> {
-    let field_match = matches
-        .get(index)
-        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
-        .map(|m| m.as_str())
-        .map(empty_field)?;
+impl LogLineParser for TomcatLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let mut builder = self
+            .inner
+            .apply(line)?
+            .add_text_field("remote_host", 1)?
+            .add_text_field("ident", 2)?
+            .add_text_field("remote_user", 3)?
+            .add_timestamp_field("@timestamp", 4, TOMCAT_TIMESTAMP)?
+            .add_text_field("requested_url", 5)?
+            .add_text_field("method", 6)?
+            .add_text_field("requested_uri", 7)?
+            .add_text_field("protocol", 8)?
+            .add_int_field("status_code", 9)?
+            .add_int_field("content_length", 10)?
+            .add_mapping_field("request_headers")
+            .add_optional_text_field("referer", 11)?
+            .add_optional_text_field("user_agent", 12)?
+            .complete_mapping()
+            .add_optional_int_field("duration_ms", 13)?;
+
+        if self.thread_name {
+            builder = builder.add_text_field("thread_name", 14)?;
+        }
+
+        let fields = builder
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        let mut extra = vec![("duration_ms".to_owned(), "int")];
+        if self.thread_name {
+            extra.push(("thread_name".to_owned(), "text"));
+        }
+
+        Some(extended_schema(COMBINED_FORMAT, extra))
+    }
+}
+
+/// Regex for the Tomcat `common` and `combined` patterns followed by the optional
+/// time taken in milliseconds and, if `thread_name` is set, the name of a thread.
+fn tomcat_regex(thread_name: bool) -> Regex {
+    let mut pattern = String::from(concat!(
+        r"^([^\s]+)\s+",                         // host
+        r"([^\s]+)\s+",                          // rfc1413 ident
+        r"([^\s]+)\s+",                          // username
+        r"\[(.+)\]\s+",                          // timestamp
+        "\"(",                                   // open " and HTTP request
+        r"([^\s]+)\s",                           // method
+        r"([^\s]+)\s",                           // path
+        r"([^\s]+)",                             // protocol
+        ")\"\\s+",                               // close " and HTTP request
+        r"([^\s]+)\s+",                          // status
+        r"([^\s]+)",                             // bytes
+        "(?:\\s+\"([^\"]+)\"\\s+\"([^\"]+)\")?", // optional "referer" [sic] and "user agent"
+        r"(?:\s+(\d+))?",                        // optional time taken
+    ));
+
+    if thread_name {
+        pattern.push_str(r"\s+([^\s]+)");
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern).unwrap()
+}
+
+/// Implementation of a `LogLineParser` that parses access logs written by
+/// lighttpd with its default `accesslog.format` or common variations of it.
+///
+/// The default format is `%h %V %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i"`,
+/// which is the Combined format with the server name in place of the ident. The
+/// server name may instead be prepended to an otherwise Combined (or Common) line
+/// with `%V %h %l %u ...`, and the time taken to serve the request in seconds may
+/// be appended with `%T`. Either way, the server name is emitted as the
+/// `server_name` field and the time taken as the `duration_s` field.
+///
+/// Requests rejected before lighttpd parsed a URL are logged with `-` as the entire
+/// request. These are parsed into events without the `requested_url`, `method`,
+/// `requested_uri`, and `protocol` fields rather than being invalid.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LighttpdLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = LighttpdLogLineParser::new();
+/// let event = parser.parse("127.0.0.1 www.example.com - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 3").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("www.example.com".to_string())), event.lookup("server_name"));
+/// assert_eq!(Some(&LogFieldValue::Int(3)), event.lookup("duration_s"));
+///
+/// let event = parser.parse("127.0.0.1 www.example.com - [10/Oct/2000:13:55:36 -0700] \"-\" 400 0 \"-\" \"-\"").unwrap();
+///
+/// assert_eq!(None, event.lookup("method"));
+/// assert_eq!(Some(&LogFieldValue::Int(400)), event.lookup("status_code"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LighttpdLogLineParser {
+    inner: ParserImpl,
+}
+
+impl LighttpdLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: ParserImpl::new(
+                Regex::new(concat!(
+                    r"^(?:([^\s]+)\s+)?",                    // optional server name
+                    r"([^\s]+)\s+",                          // host
+                    r"([^\s]+)\s+",                          // server name or rfc1413 ident
+                    r"([^\s]+)\s+",                          // username
+                    r"\[(.+)\]\s+",                          // timestamp
+                    "\"(-|",                                 // open " and HTTP request, or -
+                    r"([^\s]+)\s",                           // method
+                    r"([^\s]+)\s",                           // path
+                    r"([^\s]+)",                             // protocol
+                    ")\"\\s+",                               // close " and HTTP request
+                    r"([^\s]+)\s+",                          // status
+                    r"([^\s]+)",                             // bytes
+                    "(?:\\s+\"([^\"]+)\"\\s+\"([^\"]+)\")?", // optional "referer" [sic] and "user agent"
+                    r"(?:\s+(\d+))?$",                       // optional time taken
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
+}
+
+impl Default for LighttpdLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for LighttpdLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let builder = self.inner.apply(line)?;
+
+        // With the server name prepended, the second column is the ident as in
+        // the Common format. Otherwise it's the server name, as in the default format.
+        let builder = if builder.captures.get(1).is_some() {
+            builder
+                .add_text_field("server_name", 1)?
+                .add_text_field("remote_host", 2)?
+                .add_text_field("ident", 3)?
+        } else {
+            builder
+                .add_text_field("remote_host", 2)?
+                .add_text_field("server_name", 3)?
+        };
+
+        let fields = builder
+            .add_text_field("remote_user", 4)?
+            .add_timestamp_field("@timestamp", 5, COMMON_LOG_TIMESTAMP)?
+            .add_text_field("requested_url", 6)?
+            .add_optional_text_field("method", 7)?
+            .add_optional_text_field("requested_uri", 8)?
+            .add_optional_text_field("protocol", 9)?
+            .add_int_field("status_code", 10)?
+            .add_int_field("content_length", 11)?
+            .add_mapping_field("request_headers")
+            .add_optional_text_field("referer", 12)?
+            .add_optional_text_field("user_agent", 13)?
+            .complete_mapping()
+            .add_optional_int_field("duration_s", 14)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(
+            COMBINED_FORMAT,
+            schema_fields(&[("server_name", "text"), ("duration_s", "int")]),
+        ))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses Apache error logs in the
+/// default Apache 2.4 format into an object suitable for being serialized
+/// into Logstash compatible JSON.
+///
+/// # Logs
+///
+/// An example of a log line in this format is given below.
+///
+/// ```text
+/// [Thu Oct 10 13:55:36.123456 2000] [core:error] [pid 1234:tid 5678] [client 1.2.3.4:56789] AH00126: Invalid URI in request GET /../ HTTP/1.1
+/// ```
+///
+/// In this log line, the fields of a parsed `LogEvent` object would be
+/// (in JSON).
+///
+/// ```json
+/// {
+///   "@timestamp": "2000-10-10T13:55:36.123456+00:00",
+///   "module": "core",
+///   "level": "error",
+///   "pid": 1234,
+///   "tid": 5678,
+///   "client": "1.2.3.4:56789",
+///   "error_code": "AH00126",
+///   "@version": "1",
+///   "message": "Invalid URI in request GET /../ HTTP/1.1"
+/// }
+/// ```
+///
+/// Some things to note about this example:
+/// * The timestamp doesn't have an offset so it's assumed to be UTC unless a
+///   different zone is set with `timestamp_context`. The day of the week is
+///   ignored.
+/// * The `[client ...]` portion and the `AHxxxxx:` code are optional, as are the
+///   module and thread ID. Fields for portions that are missing are omitted.
+/// * Lines in the Apache 2.2 format, without fractional seconds or a module,
+///   are parsed the same way.
+/// * Unlike access logs, the field `message` only contains the error message,
+///   not the entire original log line.
+///
+/// See the [Apache docs](https://httpd.apache.org/docs/current/logs.html#errorlog)
+/// for the specifics of the log line format.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{ErrorLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = ErrorLogLineParser::new();
+/// let event = parser.parse("[Thu Oct 10 13:55:36.123456 2000] [core:error] [pid 1234:tid 5678] [client 1.2.3.4:56789] AH00126: Invalid URI in request").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("error".to_string())), event.lookup("level"));
+/// assert_eq!(Some(&LogFieldValue::Int(1234)), event.lookup("pid"));
+/// assert_eq!(Some(&LogFieldValue::Text("AH00126".to_string())), event.lookup("error_code"));
+/// assert_eq!(Some(&LogFieldValue::Text("Invalid URI in request".to_string())), event.lookup("message"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ErrorLogLineParser {
+    inner: ParserImpl,
+}
+
+impl ErrorLogLineParser {
+    pub fn new() -> Self {
+        let mut inner = ParserImpl::new(
+            Regex::new(concat!(
+                r"^\[[A-Za-z]+\s+([^\]]+)\]\s+",             // weekday and timestamp
+                r"\[(?:([^\]:]+)?:)?([^\]]+)\]\s+",          // module and level
+                r"(?:\[pid\s+(\d+)(?::tid\s+(\d+))?\]\s+)?", // process and thread
+                r"(?:\[client\s+([^\]]+)\]\s+)?",            // client
+                r"(?:(AH\d+):\s+)?",                         // error code
+                r"(.*)$",                                    // message
+            ))
+            .unwrap(),
+        );
+
+        inner.timestamps = TimestampContext::new().assume_zone(AssumedZone::Fixed(FixedOffset::east(0)));
+        Self { inner }
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    /// Timestamps are still assumed to be UTC if the context doesn't assume a zone.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context.or_assume_zone(AssumedZone::Fixed(FixedOffset::east(0)));
+        self
+    }
+}
+
+impl Default for ErrorLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for ErrorLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_timestamp_field("@timestamp", 1, ERROR_LOG_TIMESTAMP)?
+            .add_optional_text_field("module", 2)?
+            .add_text_field("level", 3)?
+            .add_optional_int_field("pid", 4)?
+            .add_optional_int_field("tid", 5)?
+            .add_optional_text_field("client", 6)?
+            .add_optional_text_field("error_code", 7)?
+            .add_text_field("message", 8)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(schema_fields(&[
+            ("@timestamp", "timestamp"),
+            ("module", "text"),
+            ("level", "text"),
+            ("pid", "int"),
+            ("tid", "int"),
+            ("client", "text"),
+            ("error_code", "text"),
+            ("@version", "text"),
+            ("message", "text"),
+        ]))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses logs written by the Python
+/// `http.server` module or the werkzeug development server.
+///
+/// These are similar to Apache Common logs except that the timestamp doesn't
+/// include an offset and the size of the response (usually `-`) may be missing,
+/// e.g. `127.0.0.1 - - [10/Oct/2020 13:55:36] "GET /api HTTP/1.1" 200 -`. The
+/// request is split into `method`, `requested_uri`, and `protocol` fields the
+/// same way as `CommonLogLineParser`. Timestamps are assumed to be UTC unless
+/// a different zone is set with `timestamp_context`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, PythonDevServerLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = PythonDevServerLogLineParser::new();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api HTTP/1.1\" 200 -").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("/api".to_string())), event.lookup("requested_uri"));
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert_eq!(None, event.lookup("content_length"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PythonDevServerLogLineParser {
+    inner: ParserImpl,
+}
+
+impl PythonDevServerLogLineParser {
+    pub fn new() -> Self {
+        let mut inner = ParserImpl::new(
+            Regex::new(concat!(
+                r"^([^\s]+)\s+",      // host
+                r"([^\s]+)\s+",       // rfc1413 ident
+                r"([^\s]+)\s+",       // username
+                r"\[([^\]]+)\]\s+",   // timestamp
+                "\"(",                // open " and HTTP request
+                r"([^\s]+)\s",        // method
+                r"([^\s]+)\s",        // path
+                r"([^\s]+)",          // protocol
+                ")\"\\s+",            // close " and HTTP request
+                r"(\d{3})",           // status
+                r"(?:\s+([^\s]+))?$", // bytes
+            ))
+            .unwrap(),
+        );
+
+        inner.timestamps = TimestampContext::new().assume_zone(AssumedZone::Fixed(FixedOffset::east(0)));
+        Self { inner }
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    /// Timestamps are still assumed to be UTC if the context doesn't assume a zone.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context.or_assume_zone(AssumedZone::Fixed(FixedOffset::east(0)));
+        self
+    }
+}
+
+impl Default for PythonDevServerLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for PythonDevServerLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_text_field("remote_host", 1)?
+            .add_text_field("ident", 2)?
+            .add_text_field("remote_user", 3)?
+            .add_timestamp_field("@timestamp", 4, PYTHON_DEV_SERVER_TIMESTAMP)?
+            .add_text_field("requested_url", 5)?
+            .add_text_field("method", 6)?
+            .add_text_field("requested_uri", 7)?
+            .add_text_field("protocol", 8)?
+            .add_int_field("status_code", 9)?
+            .add_optional_int_field("content_length", 10)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(extended_schema(COMMON_FORMAT, Vec::new()))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses the legacy Apache referer
+/// log written by `mod_log_referer` (or `%{Referer}i -> %U` with `mod_log_config`).
+///
+/// Each line is the referer and the requested path separated by `->`, e.g.
+/// `http://example.com/links.html -> /index.html`, which are emitted as the
+/// `referer` field in the `request_headers` mapping (the same as the other
+/// parsers in this module) and the `requested_uri` field. A referer of `-` is
+/// omitted. The lines don't include a time so `@timestamp` is the time each
+/// line was parsed.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, RefererLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = RefererLogLineParser::new();
+/// let event = parser.parse("http://example.com/links.html -> /index.html").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("http://example.com/links.html".to_string())), event.lookup("request_headers.referer"));
+/// assert_eq!(Some(&LogFieldValue::Text("/index.html".to_string())), event.lookup("requested_uri"));
+/// assert!(event.lookup("@timestamp").is_some());
+/// ```
+#[derive(Debug, Clone)]
+pub struct RefererLogLineParser {
+    inner: ParserImpl,
+}
+
+impl RefererLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: ParserImpl::new(
+                Regex::new(concat!(
+                    r"^([^\s]+?)\s*->\s*", // referer
+                    r"([^\s]+)$",          // path
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+}
+
+impl Default for RefererLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for RefererLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let mut fields = self
+            .inner
+            .apply(line)?
+            .add_mapping_field("request_headers")
+            .add_text_field("referer", 1)?
+            .complete_mapping()
+            .add_text_field("requested_uri", 2)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        fields.insert("@timestamp".to_owned(), ingestion_timestamp());
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(schema_fields(&[
+            ("request_headers.referer", "text"),
+            ("requested_uri", "text"),
+            ("@timestamp", "timestamp"),
+            ("@version", "text"),
+            ("message", "text"),
+        ]))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses the legacy Apache agent
+/// log written by `mod_log_agent` (or `%{User-agent}i` with `mod_log_config`).
+///
+/// Each line is the user agent of a request, which is emitted as the `user_agent`
+/// field in the `request_headers` mapping (the same as the other parsers in this
+/// module). A user agent of `-` is omitted and empty lines are invalid. The lines
+/// don't include a time so `@timestamp` is the time each line was parsed.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{AgentLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = AgentLogLineParser::new();
+/// let event = parser.parse("Mozilla/4.08 [en] (Win98; I ;Nav)").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("Mozilla/4.08 [en] (Win98; I ;Nav)".to_string())), event.lookup("request_headers.user_agent"));
+/// assert!(parser.parse("").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AgentLogLineParser {}
+
+impl AgentLogLineParser {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl LogLineParser for AgentLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Err(RedeyeError::ParseError(line.to_string()));
+        }
+
+        let mut fields = HashMap::new();
+        if let Some(agent) = empty_field(line) {
+            let mut headers = HashMap::new();
+            headers.insert("user_agent".to_owned(), LogFieldValue::Text(agent.to_owned()));
+            fields.insert("request_headers".to_owned(), LogFieldValue::Mapping(headers));
+        }
+
+        fields.insert("@timestamp".to_owned(), ingestion_timestamp());
+        fields.insert("@version".to_owned(), LogFieldValue::Text(OUTPUT_VERSION.to_owned()));
+        fields.insert("message".to_owned(), LogFieldValue::Text(line.to_owned()));
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(schema_fields(&[
+            ("request_headers.user_agent", "text"),
+            ("@timestamp", "timestamp"),
+            ("@version", "text"),
+            ("message", "text"),
+        ]))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses logs written by the Express
+/// `morgan` middleware in its `tiny` format.
+///
+/// The format is `:method :url :status :res[content-length] - :response-time ms`,
+/// e.g. `GET / 200 2326 - 2.134 ms`. These are emitted as the `method`,
+/// `requested_uri`, `status_code`, `content_length`, and `response_time_ms`
+/// fields. A content length or response time of `-` is omitted. The lines don't
+/// include a time so `@timestamp` is the time each line was parsed.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, MorganTinyLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = MorganTinyLogLineParser::new();
+/// let event = parser.parse("GET / 304 - - 1.025 ms").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(304)), event.lookup("status_code"));
+/// assert_eq!(None, event.lookup("content_length"));
+/// assert_eq!(Some(&LogFieldValue::Float(1.025)), event.lookup("response_time_ms"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MorganTinyLogLineParser {
+    inner: ParserImpl,
+}
+
+impl MorganTinyLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: ParserImpl::new(
+                Regex::new(concat!(
+                    r"^([^\s]+)\s+",   // method
+                    r"([^\s]+)\s+",    // url
+                    r"(\d{3}|-)\s+",   // status
+                    r"(\d+|-)\s+-\s+", // content length
+                    r"([^\s]+)\s+ms$", // response time
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+}
+
+impl Default for MorganTinyLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for MorganTinyLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let mut fields = self
+            .inner
+            .apply(line)?
+            .add_text_field("method", 1)?
+            .add_text_field("requested_uri", 2)?
+            .add_int_field("status_code", 3)?
+            .add_int_field("content_length", 4)?
+            .add_float_field("response_time_ms", 5)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        fields.insert("@timestamp".to_owned(), ingestion_timestamp());
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(schema_fields(&[
+            ("method", "text"),
+            ("requested_uri", "text"),
+            ("status_code", "int"),
+            ("content_length", "int"),
+            ("response_time_ms", "float"),
+            ("@timestamp", "timestamp"),
+            ("@version", "text"),
+            ("message", "text"),
+        ]))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses logs written by the Express
+/// `morgan` middleware in its `short` format.
+///
+/// The format is `:remote-addr :remote-user :method :url HTTP/:http-version :status
+/// :res[content-length] - :response-time ms`, e.g. `1.2.3.4 - GET / HTTP/1.1 200 2326 - 2.134 ms`.
+/// These are emitted as the `remote_host`, `remote_user`, `method`, `requested_uri`,
+/// `protocol`, `status_code`, `content_length`, and `response_time_ms` fields. A
+/// content length or response time of `-` is omitted. The lines don't include
+/// a time so `@timestamp` is the time each line was parsed.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, MorganShortLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = MorganShortLogLineParser::new();
+/// let event = parser.parse("1.2.3.4 - GET / HTTP/1.1 200 2326 - 2.134 ms").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("HTTP/1.1".to_string())), event.lookup("protocol"));
+/// assert_eq!(Some(&LogFieldValue::Int(2326)), event.lookup("content_length"));
+/// assert_eq!(Some(&LogFieldValue::Float(2.134)), event.lookup("response_time_ms"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MorganShortLogLineParser {
+    inner: ParserImpl,
+}
+
+impl MorganShortLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: ParserImpl::new(
+                Regex::new(concat!(
+                    r"^([^\s]+)\s+",   // remote address
+                    r"([^\s]+)\s+",    // remote user
+                    r"([^\s]+)\s+",    // method
+                    r"([^\s]+)\s+",    // url
+                    r"([^\s]+)\s+",    // protocol
+                    r"(\d{3}|-)\s+",   // status
+                    r"(\d+|-)\s+-\s+", // content length
+                    r"([^\s]+)\s+ms$", // response time
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+}
+
+impl Default for MorganShortLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for MorganShortLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let mut fields = self
+            .inner
+            .apply(line)?
+            .add_text_field("remote_host", 1)?
+            .add_text_field("remote_user", 2)?
+            .add_text_field("method", 3)?
+            .add_text_field("requested_uri", 4)?
+            .add_text_field("protocol", 5)?
+            .add_int_field("status_code", 6)?
+            .add_int_field("content_length", 7)?
+            .add_float_field("response_time_ms", 8)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        fields.insert("@timestamp".to_owned(), ingestion_timestamp());
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(schema_fields(&[
+            ("remote_host", "text"),
+            ("remote_user", "text"),
+            ("method", "text"),
+            ("requested_uri", "text"),
+            ("protocol", "text"),
+            ("status_code", "int"),
+            ("content_length", "int"),
+            ("response_time_ms", "float"),
+            ("@timestamp", "timestamp"),
+            ("@version", "text"),
+            ("message", "text"),
+        ]))
+    }
+}
+
+/// Current time as a timestamp field, for formats that don't include a time.
+fn ingestion_timestamp() -> LogFieldValue {
+    LogFieldValue::Timestamp(Utc::now().with_timezone(&FixedOffset::east(0)))
+}
+
+/// Implementation of a `LogLineParser` that parses Squid access logs in its
+/// default native format into an object suitable for being serialized into
+/// Logstash compatible JSON.
+///
+/// # Logs
+///
+/// An example of a log line in this format is given below.
+///
+/// ```text
+/// 1157689324.156   1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html
+/// ```
+///
+/// In this log line, the fields of a parsed `LogEvent` object would be
+/// (in JSON).
+///
+/// ```json
+/// {
+///   "@timestamp": "2006-09-08T04:22:04.156+00:00",
+///   "duration_ms": 1372,
+///   "remote_host": "10.0.0.1",
+///   "cache_status": "TCP_MISS",
+///   "status_code": 200,
+///   "content_length": 399,
+///   "method": "GET",
+///   "requested_url": "http://example.com/",
+///   "hierarchy_code": "DIRECT",
+///   "peer_host": "192.0.2.1",
+///   "content_type": "text/html",
+///   "@version": "1",
+///   "message": "1157689324.156   1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html"
+/// }
+/// ```
+///
+/// Some things to note about this example:
+/// * The timestamp is seconds since the epoch and so is always UTC.
+/// * The result code has been split into the cache status and HTTP status.
+/// * The user (the "-" in the original log line) has been omitted because the
+///   "-" represents a missing value. The same is true of the peer host and
+///   content type, which are often missing.
+/// * Unlike the other parsers, `requested_url` is the full URL requested
+///   rather than the request line.
+///
+/// See the [Squid docs](https://wiki.squid-cache.org/Features/LogFormat) for
+/// the specifics of the log line format.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, SquidLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = SquidLogLineParser::new();
+/// let event = parser.parse("1157689324.156   1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("TCP_MISS".to_string())), event.lookup("cache_status"));
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert_eq!(Some(&LogFieldValue::Int(1372)), event.lookup("duration_ms"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SquidLogLineParser {
+    inner: ParserImpl,
+}
+
+impl SquidLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            inner: ParserImpl::new(
+                Regex::new(concat!(
+                    r"^([^\s]+)\s+",          // timestamp
+                    r"([^\s]+)\s+",           // elapsed
+                    r"([^\s]+)\s+",           // host
+                    r"([^\s/]+)/([^\s]+)\s+", // cache status and HTTP status
+                    r"([^\s]+)\s+",           // bytes
+                    r"([^\s]+)\s+",           // method
+                    r"([^\s]+)\s+",           // URL
+                    r"([^\s]+)\s+",           // username
+                    r"([^\s/]+)/([^\s]+)\s+", // hierarchy code and peer host
+                    r"([^\s]+)$",             // content type
+                ))
+                .unwrap(),
+            ),
+        }
+    }
+}
+
+impl Default for SquidLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogLineParser for SquidLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+
+        let fields = self
+            .inner
+            .apply(line)?
+            .add_epoch_timestamp_field("@timestamp", 1)?
+            .add_int_field("duration_ms", 2)?
+            .add_text_field("remote_host", 3)?
+            .add_text_field("cache_status", 4)?
+            .add_int_field("status_code", 5)?
+            .add_int_field("content_length", 6)?
+            .add_text_field("method", 7)?
+            .add_text_field("requested_url", 8)?
+            .add_text_field("remote_user", 9)?
+            .add_text_field("hierarchy_code", 10)?
+            .add_text_field("peer_host", 11)?
+            .add_text_field("content_type", 12)?
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(schema_fields(&[
+            ("@timestamp", "timestamp"),
+            ("duration_ms", "int"),
+            ("remote_host", "text"),
+            ("cache_status", "text"),
+            ("status_code", "int"),
+            ("content_length", "int"),
+            ("method", "text"),
+            ("requested_url", "text"),
+            ("remote_user", "text"),
+            ("hierarchy_code", "text"),
+            ("peer_host", "text"),
+            ("content_type", "text"),
+            ("@version", "text"),
+            ("message", "text"),
+        ]))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses the JSON access logs written
+/// by Caddy 2 and remaps them to the same fields as the other parsers in this module.
+///
+/// # Logs
+///
+/// An example of a log line in this format is given below (wrapped for readability).
+///
+/// ```text
+/// {"level":"info","ts":1646861401.5241024,"logger":"http.log.access","msg":"handled request",
+///  "request":{"remote_addr":"1.2.3.4:55555","proto":"HTTP/1.1","method":"GET","host":"example.com",
+///  "uri":"/","headers":{"User-Agent":["curl/7.64.1"]}},"duration":0.000929,"size":12,"status":200,
+///  "resp_headers":{"Content-Type":["text/plain"]}}
+/// ```
+///
+/// In this log line, the fields of a parsed `LogEvent` object would be
+/// (represented as JSON for ease of reading):
+///
+/// ```json
+/// {
+///   "@timestamp": "2022-03-09T21:30:01.524102400+00:00",
+///   "remote_host": "1.2.3.4",
+///   "method": "GET",
+///   "requested_uri": "/",
+///   "protocol": "HTTP/1.1",
+///   "requested_url": "GET / HTTP/1.1",
+///   "status_code": 200,
+///   "content_length": 12,
+///   "duration_us": 929,
+///   "request_headers": {
+///     "user_agent": "curl/7.64.1"
+///   },
+///   "response_headers": {
+///     "content_type": "text/plain"
+///   },
+///   "caddy": {
+///     "level": "info",
+///     "logger": "http.log.access",
+///     "msg": "handled request",
+///     "request": {
+///       "host": "example.com"
+///     }
+///   },
+///   "@version": "1",
+///   "message": "{\"level\":\"info\",\"ts\":1646861401.5241024,..."
+/// }
+/// ```
+///
+/// Some things to note about this example:
+/// * The port is removed from the remote address. If the log includes the
+///   `remote_ip` of the request, that is used instead.
+/// * Headers are named the same way as by the other parsers. Headers with a
+///   single value are emitted as text and those with several values as a list.
+/// * Everything else in the log line is kept in the `caddy` mapping, with the
+///   same structure as the original JSON.
+/// * Any line that isn't a JSON object, or has a timestamp that isn't a number
+///   of seconds since the epoch, results in a `ParseError`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{CaddyJsonLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = CaddyJsonLineParser::new();
+/// let event = parser.parse(r#"{"ts":1646861401.5,"request":{"remote_addr":"[::1]:55555","method":"GET","uri":"/"},"status":200}"#).unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("::1".to_string())), event.lookup("remote_host"));
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert!(parser.parse("not json").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CaddyJsonLineParser {}
+
+impl CaddyJsonLineParser {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl LogLineParser for CaddyJsonLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let invalid = || RedeyeError::ParseError(line.to_string());
+
+        let mut entry: Map<String, Value> = serde_json::from_str(line).map_err(|_| invalid())?;
+        let mut fields = HashMap::new();
+
+        if let Some(ts) = entry.remove("ts") {
+            let ts = match ts {
+                Value::Number(n) => epoch_timestamp(&n.to_string()).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            };
+            fields.insert("@timestamp".to_owned(), LogFieldValue::Timestamp(ts));
+        }
+
+        let mut request = match entry.remove("request") {
+            Some(Value::Object(request)) => request,
+            Some(other) => {
+                entry.insert("request".to_owned(), other);
+                Map::new()
+            }
+            None => Map::new(),
+        };
+
+        let remote_addr = take_json_text(&mut request, "remote_addr");
+        let remote_host = take_json_text(&mut request, "remote_ip")
+            .or_else(|| remote_addr.and_then(|a| split_vhost(&a).map(|(host, _)| host.to_owned())));
+        if let Some(host) = remote_host {
+            let host = host.trim_start_matches('[').trim_end_matches(']').to_owned();
+            fields.insert("remote_host".to_owned(), LogFieldValue::Text(host));
+        }
+
+        let method = take_json_text(&mut request, "method");
+        let uri = take_json_text(&mut request, "uri");
+        let protocol = take_json_text(&mut request, "proto");
+        if let (Some(m), Some(u), Some(p)) = (&method, &uri, &protocol) {
+            let url = format!("{} {} {}", m, u, p);
+            fields.insert("requested_url".to_owned(), LogFieldValue::Text(url));
+        }
+
+        for (name, value) in &[("method", method), ("requested_uri", uri), ("protocol", protocol)] {
+            if let Some(v) = value {
+                fields.insert((*name).to_owned(), LogFieldValue::Text(v.clone()));
+            }
+        }
+
+        if let Some(Value::Object(headers)) = request.remove("headers") {
+            insert_caddy_headers(&mut fields, "request_headers", headers);
+        }
+
+        if let Some(Value::Object(headers)) = entry.remove("resp_headers") {
+            insert_caddy_headers(&mut fields, "response_headers", headers);
+        }
+
+        for (name, key) in &[("status_code", "status"), ("content_length", "size")] {
+            if let Some(v) = take_json_int(&mut entry, key) {
+                fields.insert((*name).to_owned(), LogFieldValue::Int(v));
+            }
+        }
+
+        if let Some(Value::Number(n)) = entry.get("duration") {
+            if let Some(secs) = n.as_f64() {
+                let micros = (DurationUnit::Seconds.to_seconds(secs) * 1_000_000.0).round() as u64;
+                fields.insert("duration_us".to_owned(), LogFieldValue::Int(micros));
+                entry.remove("duration");
+            }
+        }
+
+        if !request.is_empty() {
+            entry.insert("request".to_owned(), Value::Object(request));
+        }
+
+        if let Some(LogFieldValue::Mapping(caddy)) = json_field_value(Value::Object(entry)) {
+            if !caddy.is_empty() {
+                fields.insert("caddy".to_owned(), LogFieldValue::Mapping(caddy));
+            }
+        }
+
+        fields.insert("@version".to_owned(), LogFieldValue::Text(OUTPUT_VERSION.to_owned()));
+        fields.insert("message".to_owned(), LogFieldValue::Text(line.to_owned()));
+
+        Ok(LogEvent::from(fields))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses access logs in Labeled
+/// Tab-separated Values (LTSV) format.
+///
+/// Each line is a series of `label:value` fields separated by tabs. Well-known
+/// labels are emitted with the same names as the other parsers in this module:
+///
+/// * `time` as `@timestamp`, either in brackets like the Common Log Format
+///   (`[10/Oct/2000:13:55:36 -0700]`) or in ISO 8601 format.
+/// * `host` as `remote_host` and `user` as `remote_user`.
+/// * `req` as `requested_url`, also split into `method`, `requested_uri`, and
+///   `protocol`. The `method` and `uri` labels are emitted as `method` and
+///   `requested_uri` too.
+/// * `status` as `status_code` and `size` as `content_length`, both integers.
+/// * `referer` and `ua` as `referer` and `user_agent` in the `request_headers` mapping.
+/// * `reqtime` as `request_time` and `apptime` as `response_time` in the `upstream`
+///   mapping, both floats in seconds.
+///
+/// Other labels are emitted as they are as text fields. Fields with empty values
+/// or `-` are omitted. Lines with fields that don't have a valid label (made of
+/// letters, digits, `_`, `.`, and `-`), or values of well-known labels that can't
+/// be parsed, result in a `ParseError`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, LtsvLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = LtsvLogLineParser::new();
+/// let event = parser.parse("time:[10/Oct/2000:13:55:36 -0700]\thost:127.0.0.1\treq:GET / HTTP/1.1\tstatus:200\tsize:2326\tvhost:example.com").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("GET".to_string())), event.lookup("method"));
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert_eq!(Some(&LogFieldValue::Text("example.com".to_string())), event.lookup("vhost"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LtsvLogLineParser {
+    timestamps: TimestampContext,
+}
+
+impl LtsvLogLineParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.timestamps = context;
+        self
+    }
+}
+
+impl LogLineParser for LtsvLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim_matches(|c| c == '\r' || c == '\n');
+        let invalid = || RedeyeError::ParseError(line.to_string());
+
+        if line.trim().is_empty() {
+            return Err(invalid());
+        }
+
+        let mut fields = HashMap::new();
+        let mut request_headers = HashMap::new();
+        let mut upstream = HashMap::new();
+
+        for field in line.split('\t') {
+            let (label, value) = field
+                .split_once(':')
+                .filter(|(label, _)| is_ltsv_label(label))
+                .ok_or_else(invalid)?;
+            let value = match empty_field(value) {
+                Some(v) if !v.is_empty() => v,
+                _ => continue,
+            };
+
+            let int = || value.parse().map(LogFieldValue::Int).map_err(|_| invalid());
+            let float = || float_value(value).ok_or_else(invalid);
+            let text = || LogFieldValue::Text(value.to_owned());
+
+            let (mapping, name, parsed) = match label {
+                "time" => {
+                    let ts = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                        Some(v) => self.timestamps.parse(v, COMMON_LOG_TIMESTAMP)?,
+                        None => self.timestamps.parse(value, ISO8601_TIMESTAMP)?,
+                    };
+                    (&mut fields, "@timestamp", LogFieldValue::Timestamp(ts))
+                }
+                "req" => {
+                    let parts: Vec<&str> = value.split(' ').collect();
+                    if parts.len() != 3 || parts.iter().any(|p| p.is_empty()) {
+                        return Err(invalid());
+                    }
+
+                    for (name, part) in ["method", "requested_uri", "protocol"].iter().zip(parts) {
+                        fields.insert((*name).to_owned(), LogFieldValue::Text(part.to_owned()));
+                    }
+                    (&mut fields, "requested_url", text())
+                }
+                "host" => (&mut fields, "remote_host", text()),
+                "user" => (&mut fields, "remote_user", text()),
+                "method" => (&mut fields, "method", text()),
+                "uri" => (&mut fields, "requested_uri", text()),
+                "status" => (&mut fields, "status_code", int()?),
+                "size" => (&mut fields, "content_length", int()?),
+                "referer" => (&mut request_headers, "referer", text()),
+                "ua" => (&mut request_headers, "user_agent", text()),
+                "reqtime" => (&mut fields, "request_time", float()?),
+                "apptime" => (&mut upstream, "response_time", float()?),
+                _ => (&mut fields, label, text()),
+            };
+
+            mapping.insert(name.to_owned(), parsed);
+        }
+
+        for (name, mapping) in [("request_headers", request_headers), ("upstream", upstream)] {
+            if !mapping.is_empty() {
+                fields.insert(name.to_owned(), LogFieldValue::Mapping(mapping));
+            }
+        }
+
+        fields.insert("@version".to_owned(), LogFieldValue::Text(OUTPUT_VERSION.to_owned()));
+        fields.insert("message".to_owned(), LogFieldValue::Text(line.to_owned()));
+
+        Ok(LogEvent::from(fields))
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses access logs in logfmt format,
+/// a series of `key=value` pairs separated by spaces.
+///
+/// Values containing spaces are surrounded by double quotes, within which quotes
+/// and backslashes are escaped with a backslash. Well-known keys are emitted with
+/// the same names and types as the other parsers in this module:
+///
+/// * `ts` or `time` as `@timestamp`, in ISO 8601 format or seconds since the epoch.
+/// * `method` as `method` and `path` or `uri` as `requested_uri`.
+/// * `status` as `status_code` and `bytes` or `size` as `content_length`, both integers.
+/// * `duration` or `dur` as `duration_ms`, written with a unit (e.g. `13ms` or `1.5s`)
+///   or as a bare number of seconds (e.g. `12`), and emitted as a, possibly
+///   fractional, number of milliseconds.
+///
+/// Other keys are emitted as they are as text fields. Keys without a value (not
+/// followed by `=`) are collected into a `tags` list. Fields with empty values or
+/// `-` are omitted. Lines without any `key=value` pairs, with unterminated quoted
+/// values, or with values of well-known keys that can't be parsed result in a
+/// `ParseError`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogfmtLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = LogfmtLineParser::new();
+/// let event = parser.parse(r#"ts=2021-01-01T00:00:00Z method=GET path=/x status=200 bytes=512 dur=13ms ua="curl/7.64.1 (x86_64)" cached"#).unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+/// assert_eq!(Some(&LogFieldValue::Float(13.0)), event.lookup("duration_ms"));
+/// assert_eq!(Some(&LogFieldValue::Text("curl/7.64.1 (x86_64)".to_string())), event.lookup("ua"));
+/// assert_eq!(Some(&LogFieldValue::List(vec![LogFieldValue::Text("cached".to_string())])), event.lookup("tags"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LogfmtLineParser {
+    timestamps: TimestampContext,
+}
+
+impl LogfmtLineParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.timestamps = context;
+        self
+    }
+}
+
+impl LogLineParser for LogfmtLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let invalid = || RedeyeError::ParseError(line.to_string());
+
+        let pairs = split_logfmt(line).ok_or_else(invalid)?;
+        if !pairs.iter().any(|(_, value)| value.is_some()) {
+            return Err(invalid());
+        }
+
+        let mut fields = HashMap::new();
+        let mut tags = Vec::new();
+
+        for (key, value) in pairs {
+            let value = match value {
+                Some(v) => v,
+                None => {
+                    tags.push(LogFieldValue::Text(key.to_owned()));
+                    continue;
+                }
+            };
+
+            let value = match empty_field(&value) {
+                Some(v) if !v.is_empty() => v,
+                _ => continue,
+            };
+
+            let int = || value.parse().map(LogFieldValue::Int).map_err(|_| invalid());
+            let (name, parsed) = match key {
+                "ts" | "time" => {
+                    let ts = match self.timestamps.parse(value, ISO8601_TIMESTAMP) {
+                        Ok(ts) => ts,
+                        Err(e) => epoch_timestamp(value).ok_or(e)?,
+                    };
+                    ("@timestamp", LogFieldValue::Timestamp(ts))
+                }
+                "method" => ("method", LogFieldValue::Text(value.to_owned())),
+                "path" | "uri" => ("requested_uri", LogFieldValue::Text(value.to_owned())),
+                "status" => ("status_code", int()?),
+                "bytes" | "size" => ("content_length", int()?),
+                "duration" | "dur" => {
+                    let seconds = || {
+                        value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|s| s.is_finite() && *s >= 0.0)
+                            .map(|s| s * 1_000.0)
+                    };
+                    let millis = go_duration_millis(value).or_else(seconds).ok_or_else(invalid)?;
+                    ("duration_ms", LogFieldValue::Float(millis))
+                }
+                _ => (key, LogFieldValue::Text(value.to_owned())),
+            };
+
+            fields.insert(name.to_owned(), parsed);
+        }
+
+        if !tags.is_empty() {
+            fields.insert("tags".to_owned(), LogFieldValue::List(tags));
+        }
+
+        fields.insert("@version".to_owned(), LogFieldValue::Text(OUTPUT_VERSION.to_owned()));
+        fields.insert("message".to_owned(), LogFieldValue::Text(line.to_owned()));
+
+        Ok(LogEvent::from(fields))
+    }
+}
+
+/// Split a logfmt line into keys and their values, if any, unescaping quoted
+/// values. Return `None` if a key is empty or contains a quote, or if a quoted
+/// value is unterminated or followed by anything but a space.
+fn split_logfmt(line: &str) -> Option<Vec<(&str, Option<String>)>> {
+    let mut out = Vec::new();
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() {
+        let key_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        if key.is_empty() || key.contains('"') {
+            return None;
+        }
+
+        rest = &rest[key_end..];
+        let value = match rest.strip_prefix('=') {
+            None => None,
+            Some(after) if after.starts_with('"') => {
+                let mut value = String::new();
+                let mut chars = after.char_indices().skip(1);
+                let end = loop {
+                    match chars.next()? {
+                        (i, '"') => break i + 1,
+                        (_, '\\') => match chars.next()?.1 {
+                            'n' => value.push('\n'),
+                            't' => value.push('\t'),
+                            c => value.push(c),
+                        },
+                        (_, c) => value.push(c),
+                    }
+                };
+
+                rest = &after[end..];
+                if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                    return None;
+                }
+                Some(value)
+            }
+            Some(after) => {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                rest = &after[end..];
+                Some(after[..end].to_owned())
+            }
+        };
+
+        out.push((key, value));
+        rest = rest.trim_start();
+    }
+
+    Some(out)
+}
+
+/// Return true if the label is valid according to the LTSV spec, made of only
+/// letters, digits, `_`, `.`, and `-`.
+fn is_ltsv_label(label: &str) -> bool {
+    !label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// Remove the value of `key` from a JSON object if it's a string, leaving
+/// values of any other type in place.
+fn take_json_text(map: &mut Map<String, Value>, key: &str) -> Option<String> {
+    match map.remove(key) {
+        Some(Value::String(s)) => Some(s),
+        Some(other) => {
+            map.insert(key.to_owned(), other);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Remove the value of `key` from a JSON object if it's a non-negative integer,
+/// leaving values of any other type in place.
+fn take_json_int(map: &mut Map<String, Value>, key: &str) -> Option<u64> {
+    let val = map.get(key).and_then(Value::as_u64)?;
+    map.remove(key);
+    Some(val)
+}
+
+/// Insert a mapping of headers with normalized names, as logged by Caddy with
+/// a list of values for each header, unless there aren't any.
+fn insert_caddy_headers(fields: &mut HashMap<String, LogFieldValue>, mapping: &str, headers: Map<String, Value>) {
+    let headers: HashMap<String, LogFieldValue> = headers
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let value = match value {
+                Value::Array(mut values) if values.len() == 1 => values.remove(0),
+                v => v,
+            };
+            json_field_value(value).map(|v| (normalize_header(&name), v))
+        })
+        .collect();
+
+    if !headers.is_empty() {
+        fields.insert(mapping.to_owned(), LogFieldValue::Mapping(headers));
+    }
+}
+
+/// Convert a JSON value to a field value, or `None` if it's `null`.
+fn json_field_value(value: Value) -> Option<LogFieldValue> {
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) => LogFieldValue::Bool(b),
+        Value::Number(n) => match n.as_u64() {
+            Some(i) => LogFieldValue::Int(i),
+            None => LogFieldValue::Float(n.as_f64()?),
+        },
+        Value::String(s) => LogFieldValue::Text(s),
+        Value::Array(values) => LogFieldValue::List(values.into_iter().filter_map(json_field_value).collect()),
+        Value::Object(map) => LogFieldValue::Mapping(
+            map.into_iter()
+                .filter_map(|(k, v)| json_field_value(v).map(|v| (k, v)))
+                .collect(),
+        ),
+    })
+}
+
+/// Implementation of a `LogLineParser` that parses access logs in the W3C
+/// Extended Log Format, as written by IIS and several proxies.
+///
+/// The columns of each line are given by the most recent `#Fields` directive,
+/// so the parser is stateful: the directive is handled by `directive`, which
+/// changes how the lines after it are parsed. Other directives, such as
+/// `#Version` and `#Date`, are ignored. Lines before the first `#Fields`
+/// directive aren't valid.
+///
+/// Columns are emitted with the same names as the fields of the other parsers
+/// in this module where possible, e.g. `c-ip` as `remote_host`, `cs-uri-stem`
+/// as `requested_uri`, and `sc-status` as `status_code`. The `date` and `time`
+/// columns are combined into `@timestamp`, which is always UTC. Request headers
+/// such as `cs(User-Agent)` are nested in `request_headers` and response headers
+/// such as `sc(Content-Type)` in `response_headers`. Other columns are emitted
+/// with their name lowercased and punctuation replaced by `_`, e.g. `s-sitename`
+/// as `s_sitename`. `time-taken` is in milliseconds by default, as written by
+/// IIS, and emitted as `duration_us` in microseconds.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, W3cLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = W3cLogLineParser::new();
+/// assert!(parser.directive("#Fields: date time c-ip cs-method cs-uri-stem sc-status cs(User-Agent) time-taken"));
+///
+/// let event = parser.parse("2000-10-10 20:55:36 127.0.0.1 GET /index.html 200 Mozilla/4.08 15").unwrap();
+/// assert_eq!(Some(&LogFieldValue::Text("/index.html".to_string())), event.lookup("requested_uri"));
+/// assert_eq!(Some(&LogFieldValue::Text("Mozilla/4.08".to_string())), event.lookup("request_headers.user_agent"));
+/// assert_eq!(Some(&LogFieldValue::Int(15_000)), event.lookup("duration_us"));
+/// ```
+#[derive(Debug)]
+pub struct W3cLogLineParser {
+    columns: RwLock<Vec<W3cColumn>>,
+    time_taken: DurationUnit,
+}
+
+/// Field that a column of a W3C extended log is emitted as.
+#[derive(Debug, Clone, PartialEq)]
+enum W3cColumn {
+    Date,
+    Time,
+    Text(String),
+    Int(String),
+    Duration(String),
+    RequestHeader(String),
+    ResponseHeader(String),
+}
+
+impl W3cLogLineParser {
+    pub fn new() -> Self {
+        Self {
+            columns: RwLock::new(Vec::new()),
+            time_taken: DurationUnit::Milliseconds,
+        }
+    }
+
+    /// Set the unit of the `time-taken` column, which is milliseconds by default.
+    pub fn time_taken_unit(mut self, unit: DurationUnit) -> Self {
+        self.time_taken = unit;
+        self
+    }
+}
+
+impl Default for W3cLogLineParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for W3cLogLineParser {
+    fn clone(&self) -> Self {
+        Self {
+            columns: RwLock::new(self.columns.read().unwrap().clone()),
+            time_taken: self.time_taken,
+        }
+    }
+}
+
+impl LogLineParser for W3cLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let invalid = || RedeyeError::ParseError(line.to_string());
+
+        // Directives aren't events but are still handled for callers that don't
+        // check for them first.
+        if self.directive(line) {
+            return Err(invalid());
+        }
+
+        let columns = self.columns.read().unwrap();
+        let values = split_w3c(line);
+        if columns.is_empty() || values.len() != columns.len() {
+            return Err(invalid());
+        }
+
+        let mut fields = HashMap::with_capacity(columns.len() + 2);
+        let mut request_headers = HashMap::new();
+        let mut response_headers = HashMap::new();
+        let (mut date, mut time) = (None, None);
+
+        for (column, value) in columns.iter().zip(values) {
+            let value = match empty_field(value) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            match column {
+                W3cColumn::Date => date = Some(value),
+                W3cColumn::Time => time = Some(value),
+                W3cColumn::Text(name) => {
+                    fields.insert(name.clone(), LogFieldValue::Text(value.to_owned()));
+                }
+                W3cColumn::Int(name) => {
+                    let val = value.parse::<u64>().map_err(|_| invalid())?;
+                    fields.insert(name.clone(), LogFieldValue::Int(val));
+                }
+                W3cColumn::Duration(name) => {
+                    let val = match float_value(value) {
+                        Some(LogFieldValue::Float(v)) => v,
+                        _ => return Err(invalid()),
+                    };
+                    let micros = (self.time_taken.to_seconds(val) * 1_000_000.0).round() as u64;
+                    fields.insert(name.clone(), LogFieldValue::Int(micros));
+                }
+                W3cColumn::RequestHeader(name) => {
+                    request_headers.insert(name.clone(), LogFieldValue::Text(value.to_owned()));
+                }
+                W3cColumn::ResponseHeader(name) => {
+                    if name == "content_type" {
+                        fields.insert("mime_type".to_owned(), LogFieldValue::Text(value.to_owned()));
+                    }
+                    response_headers.insert(name.clone(), LogFieldValue::Text(value.to_owned()));
+                }
+            }
+        }
+
+        if let (Some(date), Some(time)) = (date, time) {
+            let local = NaiveDateTime::parse_from_str(&format!("{} {}", date, time), W3C_TIMESTAMP)?;
+            let ts = FixedOffset::east(0).from_utc_datetime(&local);
+            fields.insert("@timestamp".to_owned(), LogFieldValue::Timestamp(ts));
+        }
+
+        if !request_headers.is_empty() {
+            fields.insert("request_headers".to_owned(), LogFieldValue::Mapping(request_headers));
+        }
+
+        if !response_headers.is_empty() {
+            fields.insert("response_headers".to_owned(), LogFieldValue::Mapping(response_headers));
+        }
+
+        fields.insert("@version".to_owned(), LogFieldValue::Text(OUTPUT_VERSION.to_owned()));
+        fields.insert("message".to_owned(), LogFieldValue::Text(line.to_owned()));
+        Ok(LogEvent::from(fields))
+    }
+
+    fn directive(&self, line: &str) -> bool {
+        let line = line.trim();
+        if !line.starts_with('#') {
+            return false;
+        }
+
+        if let Some(fields) = line.strip_prefix("#Fields:") {
+            *self.columns.write().unwrap() = fields.split_whitespace().map(w3c_column).collect();
+        }
+
+        true
+    }
+
+    fn is_stateful(&self) -> bool {
+        true
+    }
+}
+
+/// Field that a W3C extended log column with the given name is emitted as.
+fn w3c_column(name: &str) -> W3cColumn {
+    let text = |n: &str| W3cColumn::Text(n.to_owned());
+    let int = |n: &str| W3cColumn::Int(n.to_owned());
+
+    match name.to_lowercase().as_str() {
+        "date" => W3cColumn::Date,
+        "time" => W3cColumn::Time,
+        "c-ip" => text("remote_host"),
+        "cs-username" => text("remote_user"),
+        "cs-method" => text("method"),
+        "cs-uri-stem" | "cs-uri" => text("requested_uri"),
+        "cs-uri-query" => text("query_string"),
+        "cs-version" => text("protocol"),
+        "cs-host" => W3cColumn::RequestHeader("host".to_owned()),
+        "s-ip" => text("local_addr"),
+        "s-port" => int("server_port"),
+        "sc-status" => int("status_code"),
+        "sc-bytes" => int("bytes_sent"),
+        "cs-bytes" => int("bytes_received"),
+        "time-taken" => W3cColumn::Duration("duration_us".to_owned()),
+        _ => {
+            let header = |prefix: &str| name.get(prefix.len()..name.len() - 1).filter(|_| name.ends_with(')'));
+            if name.starts_with("cs(") {
+                if let Some(h) = header("cs(") {
+                    return W3cColumn::RequestHeader(normalize_header(h));
+                }
+            } else if name.starts_with("sc(") {
+                if let Some(h) = header("sc(") {
+                    return W3cColumn::ResponseHeader(normalize_header(h));
+                }
+            }
+
+            let name: String = name
+                .to_lowercase()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            W3cColumn::Text(name.trim_matches('_').to_owned())
+        }
+    }
+}
+
+/// Split a W3C extended log line into the value of each column. Values are
+/// separated by whitespace and may be quoted if they contain whitespace.
+fn split_w3c(line: &str) -> Vec<&str> {
+    let mut values = Vec::new();
+    let mut rest = line.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            values.push(&quoted[..end]);
+            rest = quoted.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            values.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    values
+}
+
+/// Implementation of a `LogLineParser` that parses logs written by Apache
+/// `mod_log_forensic`, which writes two records for each request.
+///
+/// A record starting with `+` is written when a request starts and contains a
+/// unique ID, the request line, and each request header separated by `|`, e.g.
+/// `+yQtJf8CoAB4AAFNXBIEAAAAA|GET /index.html HTTP/1.1|Host:localhost%3a8080`.
+/// A record with the same ID starting with `-` is written when the request
+/// completes, e.g. `-yQtJf8CoAB4AAFNXBIEAAAAA`.
+///
+/// Because events span multiple lines, this parser keeps state between them
+/// using interior mutability (the same as `W3cLogLineParser`). Start records
+/// are directives: they're buffered by `directive` and no event is emitted for
+/// them. Completion records are parsed into an event for the matching start
+/// record with `completed` set to `true`. The request is emitted the same way
+/// as `CommonLogLineParser`, headers are emitted in the `request_headers` mapping,
+/// and the ID is emitted as `forensic_id`. Escape sequences (e.g. `%7c` for `|`)
+/// in the request and headers are decoded. The records don't include a time so
+/// `@timestamp` is the time each start record was read.
+///
+/// At most `max_pending` start records are buffered. Past that, the oldest is
+/// converted to an event with `completed` set to `false` that can be retrieved
+/// with `take_incomplete` (or `take_events`). All buffered records can be
+/// converted to incomplete events with `flush` (or `flush_events`), e.g. once
+/// the end of a log file is reached.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{ForensicLogParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = ForensicLogParser::new();
+/// assert!(parser.directive("+yQtJf8CoAB4AAFNXBIEAAAAA|GET /index.html HTTP/1.1|Host:localhost%3a8080"));
+///
+/// let event = parser.parse("-yQtJf8CoAB4AAFNXBIEAAAAA").unwrap();
+/// assert_eq!(Some(&LogFieldValue::Bool(true)), event.lookup("completed"));
+/// assert_eq!(Some(&LogFieldValue::Text("/index.html".to_string())), event.lookup("requested_uri"));
+/// assert_eq!(Some(&LogFieldValue::Text("localhost:8080".to_string())), event.lookup("request_headers.host"));
+/// ```
+#[derive(Debug)]
+pub struct ForensicLogParser {
+    state: Mutex<ForensicState>,
+    max_pending: usize,
+}
+
+/// Start records waiting for their completion record, in the order they were read.
+#[derive(Debug, Clone, Default)]
+struct ForensicState {
+    next: u64,
+    order: BTreeMap<u64, String>,
+    pending: HashMap<String, (u64, HashMap<String, LogFieldValue>)>,
+    incomplete: Vec<LogEvent>,
+}
+
+impl ForensicState {
+    /// Buffer the fields of a start record, evicting the oldest buffered records
+    /// as incomplete events if there are more than `max_pending` of them.
+    fn insert(&mut self, id: String, fields: HashMap<String, LogFieldValue>, max_pending: usize) {
+        if let Some((seq, _)) = self.pending.remove(&id) {
+            self.order.remove(&seq);
+        }
+
+        let seq = self.next;
+        self.next += 1;
+        self.order.insert(seq, id.clone());
+        self.pending.insert(id, (seq, fields));
+
+        while self.pending.len() > max_pending {
+            let oldest = match self.order.keys().next() {
+                Some(seq) => *seq,
+                None => break,
+            };
+
+            if let Some(id) = self.order.remove(&oldest) {
+                if let Some((_, fields)) = self.pending.remove(&id) {
+                    self.incomplete.push(forensic_event(fields, false));
+                }
+            }
+        }
+    }
+
+    /// Remove and return the fields of the start record with the given ID.
+    fn complete(&mut self, id: &str) -> Option<HashMap<String, LogFieldValue>> {
+        let (seq, fields) = self.pending.remove(id)?;
+        self.order.remove(&seq);
+        Some(fields)
+    }
+}
+
+impl ForensicLogParser {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ForensicState::default()),
+            max_pending: 10_000,
+        }
+    }
+
+    /// Set the maximum number of start records buffered while waiting for their
+    /// completion record, which is 10,000 by default.
+    pub fn max_pending(mut self, max: usize) -> Self {
+        self.max_pending = max;
+        self
+    }
+
+    /// Remove and return events for start records that were evicted because
+    /// there were more than `max_pending` buffered.
+    pub fn take_incomplete(&self) -> Vec<LogEvent> {
+        std::mem::take(&mut self.state.lock().unwrap().incomplete)
+    }
+
+    /// Remove and return events for all start records that haven't been completed,
+    /// including those returned by `take_incomplete`, oldest first.
+    pub fn flush(&self) -> Vec<LogEvent> {
+        let mut state = self.state.lock().unwrap();
+        let mut events = std::mem::take(&mut state.incomplete);
+
+        for (_, id) in std::mem::take(&mut state.order) {
+            if let Some((_, fields)) = state.pending.remove(&id) {
+                events.push(forensic_event(fields, false));
+            }
+        }
+
+        events
+    }
+}
+
+impl Default for ForensicLogParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for ForensicLogParser {
+    fn clone(&self) -> Self {
+        Self {
+            state: Mutex::new(self.state.lock().unwrap().clone()),
+            max_pending: self.max_pending,
+        }
+    }
+}
+
+impl LogLineParser for ForensicLogParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let invalid = || RedeyeError::ParseError(line.to_string());
+
+        // Start records aren't events but are still handled for callers that
+        // don't check for them first.
+        if self.directive(line) {
+            return Err(invalid());
+        }
+
+        let id = line
+            .strip_prefix('-')
+            .filter(|id| is_forensic_id(id))
+            .ok_or_else(invalid)?;
+
+        self.state
+            .lock()
+            .unwrap()
+            .complete(id)
+            .map(|fields| forensic_event(fields, true))
+            .ok_or_else(invalid)
+    }
+
+    fn directive(&self, line: &str) -> bool {
+        let line = line.trim();
+        match forensic_start(line) {
+            Some((id, fields)) => {
+                self.state.lock().unwrap().insert(id, fields, self.max_pending);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn is_stateful(&self) -> bool {
+        true
+    }
+
+    fn take_events(&self) -> Vec<LogEvent> {
+        self.take_incomplete()
+    }
+
+    fn flush_events(&self) -> Vec<LogEvent> {
+        self.flush()
+    }
+}
+
+/// Parse a `mod_log_forensic` start record into its ID and fields, or return
+/// `None` if it isn't a valid start record.
+fn forensic_start(line: &str) -> Option<(String, HashMap<String, LogFieldValue>)> {
+    let mut parts = line.strip_prefix('+')?.split('|');
+    let id = parts.next().filter(|id| is_forensic_id(id))?;
+    let request = percent_decode(parts.next()?, false);
+
+    let mut headers = HashMap::new();
+    for part in parts {
+        let (name, value) = part.split_once(':')?;
+        let name = percent_decode(name, false);
+        if name.is_empty() {
+            return None;
+        }
+
+        headers.insert(
+            normalize_header(&name),
+            LogFieldValue::Text(percent_decode(value, false)),
+        );
+    }
+
+    let mut fields = HashMap::new();
+    let request_parts: Vec<&str> = request.split_whitespace().collect();
+    if let [method, uri, protocol] = request_parts[..] {
+        fields.insert("method".to_owned(), LogFieldValue::Text(method.to_owned()));
+        fields.insert("requested_uri".to_owned(), LogFieldValue::Text(uri.to_owned()));
+        fields.insert("protocol".to_owned(), LogFieldValue::Text(protocol.to_owned()));
+    }
+
+    if !headers.is_empty() {
+        fields.insert("request_headers".to_owned(), LogFieldValue::Mapping(headers));
+    }
+
+    fields.insert("requested_url".to_owned(), LogFieldValue::Text(request));
+    fields.insert("forensic_id".to_owned(), LogFieldValue::Text(id.to_owned()));
+    fields.insert("@timestamp".to_owned(), ingestion_timestamp());
+    fields.insert("@version".to_owned(), LogFieldValue::Text(OUTPUT_VERSION.to_owned()));
+    fields.insert("message".to_owned(), LogFieldValue::Text(line.to_owned()));
+
+    Some((id.to_owned(), fields))
+}
+
+/// Return true if the given value is a valid `mod_log_forensic` ID.
+fn is_forensic_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains(|c: char| c.is_whitespace() || c == '|')
+}
+
+/// Create an event from the fields of a start record.
+fn forensic_event(mut fields: HashMap<String, LogFieldValue>, completed: bool) -> LogEvent {
+    fields.insert("completed".to_owned(), LogFieldValue::Bool(completed));
+    LogEvent::from(fields)
+}
+
+/// Implementation of a `LogLineParser` that parses access logs in a format
+/// given by an Apache `LogFormat` or nginx `log_format` string.
+///
+/// Fields are named the same as the fields of the other parsers in this module
+/// where possible. See `LogFormat` for the supported directives and variables.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::format::LogFormat;
+/// use redeye::parser::{CustomLogLineParser, LogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let format = LogFormat::apache("%h %l %u %t \"%r\" %>s %b %D").unwrap();
+/// let parser = CustomLogLineParser::from_format(format);
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 1534").unwrap();
+/// let fields = event.fields();
+///
+/// assert_eq!(&LogFieldValue::Int(200), fields.get("status_code").unwrap());
+/// assert_eq!(&LogFieldValue::Int(1534), fields.get("duration_us").unwrap());
+/// ```
+///
+/// Request headers (`%{Name}i`) and response headers (`%{Name}o`) are nested in
+/// separate `request_headers` and `response_headers` mappings, even when the same
+/// header is logged for both.
+///
+/// ```rust
+/// use redeye::format::LogFormat;
+/// use redeye::parser::{CustomLogLineParser, LogLineParser};
+/// use serde_json::json;
+///
+/// let format = LogFormat::apache("%h \"%{Cache-Control}i\" \"%{Content-Type}o\" \"%{Cache-Control}o\"").unwrap();
+/// let parser = CustomLogLineParser::from_format(format);
+/// let event = parser.parse("127.0.0.1 \"no-cache\" \"text/html\" \"max-age=60\"").unwrap();
+/// let json = serde_json::to_value(&event).unwrap();
+///
+/// assert_eq!(json!({"cache_control": "no-cache"}), json["request_headers"]);
+/// assert_eq!(
+///     json!({"content_type": "text/html", "cache_control": "max-age=60"}),
+///     json["response_headers"]
+/// );
+/// assert_eq!(json!("text/html"), json["mime_type"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CustomLogLineParser {
+    inner: ParserImpl,
+    fields: Vec<FormatField>,
+    mappings: Vec<(String, Vec<FormatField>)>,
+    schema: Vec<(String, &'static str)>,
+}
+
+impl CustomLogLineParser {
+    /// Create a parser for lines in the format of an Apache `LogFormat` string,
+    /// returning an error if it contains unknown directives.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use redeye::parser::{CustomLogLineParser, LogLineParser};
+    ///
+    /// let parser = CustomLogLineParser::new("%h %l %u %t \"%r\" %>s %b %D").unwrap();
+    /// assert!(parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 - 1534").is_ok());
+    ///
+    /// assert!(CustomLogLineParser::new("%h %Z").is_err());
+    /// ```
+    pub fn new(spec: &str) -> RedeyeResult<Self> {
+        LogFormat::apache(spec).map(Self::from_format)
+    }
+
+    /// Create a parser for lines of the given compiled format.
+    pub fn from_format(format: LogFormat) -> Self {
+        let mut fields = Vec::new();
+        let mut mappings: Vec<(String, Vec<FormatField>)> = Vec::new();
+
+        // Group fields by the mapping they are nested in (if any) since each
+        // mapping has to be built all at once.
+        for field in format.fields() {
+            match field.mapping() {
+                None => fields.push(field.clone()),
+                Some(m) => match mappings.iter_mut().find(|(name, _)| name == m) {
+                    Some((_, nested)) => nested.push(field.clone()),
+                    None => mappings.push((m.to_owned(), vec![field.clone()])),
+                },
+            }
+        }
+
+        Self {
+            inner: ParserImpl::new(format.regex().clone()),
+            fields,
+            mappings,
+            schema: format.schema(),
+        }
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner.timestamps = context;
+        self
+    }
+}
+
+impl LogLineParser for CustomLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        let line = line.trim();
+        let mut builder = self.inner.apply(line)?;
+
+        for field in self.fields.iter() {
+            builder = builder.add_format_field(field)?;
+        }
+
+        for (mapping, nested) in self.mappings.iter() {
+            builder = builder.add_mapping_field(mapping.as_str());
+            for field in nested.iter() {
+                builder = builder.add_format_field(field)?;
+            }
+            builder = builder.complete_mapping();
+        }
+
+        let fields = builder
+            .add_fixed_value("@version", OUTPUT_VERSION)
+            .add_fixed_value("message", line)
+            .build();
+
+        Ok(LogEvent::from(fields))
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        Some(self.schema.clone())
+    }
+}
+
+/// Implementation of a `LogLineParser` that parses Varnish access logs written
+/// by `varnishncsa` in a format given by its format string.
+///
+/// The default format is the Combined Log Format and is parsed the same as by
+/// `CombinedLogLineParser`. Extension fields (`%{Varnish:...}x` and `%{VCL_Log:...}x`)
+/// are emitted in a nested `varnish` mapping, e.g. `%{Varnish:handling}x` is emitted
+/// as `varnish.handling`. Lines that don't match the format, such as those written
+/// before extension fields were added to it, result in a `ParseError`.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, VarnishLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = VarnishLogLineParser::new(
+///     "%h %l %u %t \"%r\" %s %b \"%{Referer}i\" \"%{User-agent}i\" %{Varnish:handling}x"
+/// ).unwrap();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" hit").unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("hit".to_string())), event.lookup("varnish.handling"));
+/// assert!(parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"").is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct VarnishLogLineParser {
+    inner: CustomLogLineParser,
+}
+
+impl VarnishLogLineParser {
+    /// Create a parser for lines in the format of a `varnishncsa` format string,
+    /// returning an error if it contains unknown directives or extensions.
+    pub fn new(spec: &str) -> RedeyeResult<Self> {
+        LogFormat::varnish(spec).map(|format| Self {
+            inner: CustomLogLineParser::from_format(format),
+        })
+    }
+
+    /// Set how timestamps are parsed, e.g. the zone assumed for those without an offset.
+    pub fn timestamp_context(mut self, context: TimestampContext) -> Self {
+        self.inner = self.inner.timestamp_context(context);
+        self
+    }
+}
+
+impl Default for VarnishLogLineParser {
+    fn default() -> Self {
+        Self::new(VARNISHNCSA_FORMAT).expect("default varnishncsa format must compile")
+    }
+}
+
+impl LogLineParser for VarnishLogLineParser {
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        self.inner.parse(line)
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        self.inner.schema()
+    }
+}
+
+/// Log formats with a built-in parser, selected by name.
+///
+/// These are the formats that can be written by `redeye bench` and are the first
+/// formats in `ParserRegistry::new()`, which has every format that can be selected
+/// by name, including variations of these with extra fields.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{LogLineParser, NamedFormat};
+///
+/// let format: NamedFormat = "common".parse().unwrap();
+/// let parser = format.parser();
+/// let event = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326").unwrap();
+///
+/// assert_eq!(NamedFormat::Common, format);
+/// assert!(event.fields().contains_key("status_code"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedFormat {
+    /// NCSA Common log format.
+    Common,
+    /// NCSA Combined log format, the Common format with a referer and user agent.
+    Combined,
+    /// Apache `vhost_combined` format, the Combined format prefixed with the
+    /// name and port of the virtual host.
+    VhostCombined,
+}
+
+impl NamedFormat {
+    /// Every named format, in the order they're listed in help.
+    pub const ALL: &'static [NamedFormat] = &[NamedFormat::Common, NamedFormat::Combined, NamedFormat::VhostCombined];
+
+    /// Names of every format, in the same order as `ALL`.
+    pub const NAMES: &'static [&'static str] = &["common", "combined", "vhost_combined"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NamedFormat::Common => "common",
+            NamedFormat::Combined => "combined",
+            NamedFormat::VhostCombined => "vhost_combined",
+        }
+    }
+
+    /// Parser for lines in this format.
+    pub fn parser(&self) -> Box<dyn LogLineParser + Send + Sync> {
+        self.parser_with_context(TimestampContext::new())
+    }
+
+    /// Parser for lines in this format that parses timestamps using the given context.
+    pub fn parser_with_context(&self, context: TimestampContext) -> Box<dyn LogLineParser + Send + Sync> {
+        match self {
+            NamedFormat::Common => Box::new(CommonLogLineParser::new().timestamp_context(context)),
+            NamedFormat::Combined => Box::new(CombinedLogLineParser::new().timestamp_context(context)),
+            NamedFormat::VhostCombined => Box::new(VhostCombinedLogLineParser::new().timestamp_context(context)),
+        }
+    }
+
+    /// Equivalent Apache format string, which emits the same fields as the
+    /// parser for this format.
+    pub fn spec(&self) -> &'static str {
+        match self {
+            NamedFormat::Common => COMMON_FORMAT,
+            NamedFormat::Combined => COMBINED_FORMAT,
+            NamedFormat::VhostCombined => VHOST_COMBINED_FORMAT,
+        }
+    }
+}
+
+impl FromStr for NamedFormat {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NamedFormat::ALL.iter().find(|f| f.name() == s).copied().ok_or_else(|| {
+            RedeyeError::ConfigError(format!(
+                "unknown format '{}', expected one of {}",
+                s,
+                NamedFormat::NAMES.join(", ")
+            ))
+        })
+    }
+}
+
+impl fmt::Display for NamedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Function that creates a parser for a format, given how timestamps should be parsed.
+pub type ParserFactory = Arc<dyn Fn(TimestampContext) -> Box<dyn LogLineParser + Send + Sync> + Send + Sync>;
+
+/// Formats that parsers can be created for by name.
+///
+/// A new registry has every built in format. Programs embedding redeye can add
+/// their own formats with `register`, which doesn't allow replacing a format
+/// that's already registered, including a built in one. Use `replace` to do so
+/// deliberately.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::format::LogFormat;
+/// use redeye::parser::{CustomLogLineParser, ParserRegistry};
+///
+/// let mut registry = ParserRegistry::new();
+/// registry
+///     .register("status", |context| {
+///         let format = LogFormat::apache("%h %>s").unwrap();
+///         Box::new(CustomLogLineParser::from_format(format).timestamp_context(context))
+///     })
+///     .unwrap();
+///
+/// assert!(registry.names().ends_with(&["status"]));
+/// assert!(registry.create("status").unwrap().parse("127.0.0.1 404").is_ok());
+/// assert!(registry.create("yaml").is_err());
+/// ```
+#[derive(Clone)]
+pub struct ParserRegistry {
+    formats: Vec<(String, ParserFactory)>,
+}
+
+impl ParserRegistry {
+    /// Create a registry with every built in format.
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        for format in NamedFormat::ALL {
+            let format = *format;
+            registry.builtin(format.name(), move |context| format.parser_with_context(context));
+        }
+
+        registry.builtin("nginx_timed", |context| {
+            Box::new(
+                CombinedLogLineParser::new()
+                    .request_time(true)
+                    .timestamp_context(context),
+            )
+        });
+        registry.builtin("nginx_upstream", |context| {
+            Box::new(CombinedLogLineParser::new().upstream(true).timestamp_context(context))
+        });
+        registry.builtin("ingress_nginx", |context| {
+            Box::new(IngressNginxLogLineParser::new().timestamp_context(context))
+        });
+
+        for (name, unit) in &[
+            ("combined_duration_s", DurationUnit::Seconds),
+            ("combined_duration_ms", DurationUnit::Milliseconds),
+            ("combined_duration_us", DurationUnit::Microseconds),
+        ] {
+            let unit = *unit;
+            registry.builtin(name, move |context| {
+                Box::new(CombinedLogLineParser::new().duration(unit).timestamp_context(context))
+            });
+        }
+
+        registry.builtin("error", |context| {
+            Box::new(ErrorLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("referer", |_| Box::new(RefererLogLineParser::new()));
+        registry.builtin("agent", |_| Box::new(AgentLogLineParser::new()));
+        registry.builtin("w3c", |_| Box::new(W3cLogLineParser::new()));
+        registry.builtin("squid", |_| Box::new(SquidLogLineParser::new()));
+        registry.builtin("varnish", |context| {
+            Box::new(VarnishLogLineParser::default().timestamp_context(context))
+        });
+        registry.builtin("tomcat", |context| {
+            Box::new(TomcatLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("jetty", |context| {
+            Box::new(JettyLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("caddy", |_| Box::new(CaddyJsonLineParser::new()));
+        registry.builtin("traefik", |context| {
+            Box::new(TraefikLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("ltsv", |context| {
+            Box::new(LtsvLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("logfmt", |context| {
+            Box::new(LogfmtLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("combined_xff", |context| {
+            Box::new(
+                CombinedLogLineParser::new()
+                    .forwarded_for(true)
+                    .timestamp_context(context),
+            )
+        });
+        registry.builtin("combinedio", |context| {
+            Box::new(CombinedIoLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("lighttpd", |context| {
+            Box::new(LighttpdLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("morgan_tiny", |_| Box::new(MorganTinyLogLineParser::new()));
+        registry.builtin("morgan_short", |_| Box::new(MorganShortLogLineParser::new()));
+        registry.builtin("python_dev_server", |context| {
+            Box::new(PythonDevServerLogLineParser::new().timestamp_context(context))
+        });
+        registry.builtin("forensic", |_| Box::new(ForensicLogParser::new()));
+        registry
+    }
+
+    /// Add a built in format, which must have a name that isn't registered yet.
+    fn builtin<F>(&mut self, name: &str, factory: F)
+    where
+        F: Fn(TimestampContext) -> Box<dyn LogLineParser + Send + Sync> + Send + Sync + 'static,
+    {
+        debug_assert!(!self.contains(name), "format '{}' registered twice", name);
+        self.formats.push((name.to_owned(), Arc::new(factory)));
+    }
+
+    /// Create a registry without any formats.
+    pub fn empty() -> Self {
+        ParserRegistry { formats: Vec::new() }
+    }
+
+    /// Add a format, returning an error if there's already a format with the name.
+    pub fn register<F>(&mut self, name: &str, factory: F) -> RedeyeResult<()>
+    where
+        F: Fn(TimestampContext) -> Box<dyn LogLineParser + Send + Sync> + Send + Sync + 'static,
+    {
+        if self.contains(name) {
+            return Err(RedeyeError::ConfigError(format!(
+                "format '{}' is already registered",
+                name
+            )));
+        }
+
+        self.formats.push((name.to_owned(), Arc::new(factory)));
+        Ok(())
+    }
+
+    /// Add a format or replace the existing format with the name, returning the
+    /// factory that was replaced if any.
+    pub fn replace<F>(&mut self, name: &str, factory: F) -> Option<ParserFactory>
+    where
+        F: Fn(TimestampContext) -> Box<dyn LogLineParser + Send + Sync> + Send + Sync + 'static,
+    {
+        let factory: ParserFactory = Arc::new(factory);
+        match self.formats.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => Some(std::mem::replace(existing, factory)),
+            None => {
+                self.formats.push((name.to_owned(), factory));
+                None
+            }
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.formats.iter().any(|(n, _)| n == name)
+    }
+
+    /// Names of every format, in the order they were registered.
+    pub fn names(&self) -> Vec<&str> {
+        self.formats.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    /// Create a parser for the format with the given name.
+    pub fn create(&self, name: &str) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+        self.create_with_context(name, TimestampContext::new())
+    }
+
+    /// Create a parser for the format with the given name that parses timestamps
+    /// using the given context.
+    pub fn create_with_context(
+        &self,
+        name: &str,
+        context: TimestampContext,
+    ) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+        match self.formats.iter().find(|(n, _)| n == name) {
+            Some((_, factory)) => Ok(factory(context)),
+            None => Err(RedeyeError::ConfigError(format!(
+                "unknown format '{}', expected one of {}",
+                name,
+                self.names().join(", ")
+            ))),
+        }
+    }
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ParserRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParserRegistry")
+            .field("formats", &self.names())
+            .finish()
+    }
+}
+
+/// Parser for a format given as either the name of a format in the default
+/// `ParserRegistry` (e.g. `common` or `squid`) or an Apache format string.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::parser_for;
+///
+/// let parser = parser_for("%h %>s").unwrap();
+/// assert!(parser.parse("127.0.0.1 404").is_ok());
+/// assert!(parser_for("yaml").is_err());
+/// ```
+pub fn parser_for(format: &str) -> RedeyeResult<Box<dyn LogLineParser + Send + Sync>> {
+    if format.contains('%') {
+        let format = LogFormat::apache(format)?;
+        Ok(Box::new(CustomLogLineParser::from_format(format)))
+    } else {
+        ParserRegistry::new().create(format)
+    }
+}
+
+/// Parse a single line and serialize the event as JSON. The format is either the
+/// name of a built in format or an Apache format string, as for `parser_for`.
+///
+/// Errors are returned as text so that this is simple to expose outside of Rust,
+/// e.g. from WebAssembly with wasm-bindgen. The parser is built for each call,
+/// so programs parsing many lines should build one with `parser_for` and reuse
+/// it instead.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::parse_line_to_json;
+///
+/// let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+/// let json = parse_line_to_json("common", line).unwrap();
+/// assert!(json.contains("\"status_code\":200"));
+///
+/// let json = parse_line_to_json("%h %>s", "127.0.0.1 404").unwrap();
+/// assert!(json.contains("\"status_code\":404"));
+///
+/// assert!(parse_line_to_json("common", "not a log line").is_err());
+/// ```
+pub fn parse_line_to_json(format: &str, line: &str) -> Result<String, String> {
+    let parser = parser_for(format).map_err(|e| e.to_string())?;
+    let event = parser.parse(line).map_err(|e| e.to_string())?;
+    serde_json::to_string(&event).map_err(|e| e.to_string())
+}
+
+impl<P> LogLineParser for Box<P>
+where
+    P: LogLineParser + ?Sized,
+{
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        (**self).parse(line)
+    }
+
+    fn directive(&self, line: &str) -> bool {
+        (**self).directive(line)
+    }
+
+    fn is_stateful(&self) -> bool {
+        (**self).is_stateful()
+    }
+
+    fn take_events(&self) -> Vec<LogEvent> {
+        (**self).take_events()
+    }
+
+    fn flush_events(&self) -> Vec<LogEvent> {
+        (**self).flush_events()
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        (**self).schema()
+    }
+}
+
+/// Implementation of a `LogLineParser` that removes a syslog header from
+/// each line before parsing the rest of it with another parser.
+///
+/// Both the traditional BSD format ([RFC 3164](https://tools.ietf.org/html/rfc3164))
+/// and the newer format ([RFC 5424](https://tools.ietf.org/html/rfc5424)) are
+/// supported. Lines that don't start with a syslog priority (e.g. `<134>`) are
+/// passed to the other parser unchanged.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::{CommonLogLineParser, LogLineParser, SyslogLogLineParser};
+/// use redeye::types::LogFieldValue;
+///
+/// let parser = SyslogLogLineParser::new(CommonLogLineParser::new());
+/// let event = parser.parse(
+///     "<134>Oct 11 22:14:15 web1 apache: 127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326"
+/// ).unwrap();
+///
+/// assert_eq!(Some(&LogFieldValue::Text("127.0.0.1".to_owned())), event.lookup("remote_host"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SyslogLogLineParser<P> {
+    inner: P,
+}
+
+impl<P> SyslogLogLineParser<P>
+where
+    P: LogLineParser,
+{
+    pub fn new(inner: P) -> Self {
+        Self { inner }
+    }
+}
+
+impl<P> LogLineParser for SyslogLogLineParser<P>
+where
+    P: LogLineParser,
+{
+    fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+        self.inner.parse(strip_syslog_header(line.trim_start()))
+    }
+
+    fn directive(&self, line: &str) -> bool {
+        self.inner.directive(strip_syslog_header(line.trim_start()))
+    }
+
+    fn is_stateful(&self) -> bool {
+        self.inner.is_stateful()
+    }
+
+    fn take_events(&self) -> Vec<LogEvent> {
+        self.inner.take_events()
+    }
+
+    fn flush_events(&self) -> Vec<LogEvent> {
+        self.inner.flush_events()
+    }
+
+    fn schema(&self) -> Option<Vec<(String, &'static str)>> {
+        self.inner.schema()
+    }
+}
+
+/// Return the message of a syslog line without the header, or the entire line
+/// if it doesn't start with a syslog priority.
+fn strip_syslog_header(line: &str) -> &str {
+    let rest = match line.strip_prefix('<').and_then(|s| s.find('>').map(|i| (s, i))) {
+        Some((s, i)) if (1..=3).contains(&i) && s[..i].bytes().all(|b| b.is_ascii_digit()) => &s[i + 1..],
+        _ => return line,
+    };
+
+    match rest.strip_prefix("1 ") {
+        Some(rest) => strip_rfc5424_header(rest),
+        None => strip_rfc3164_header(rest),
+    }
+}
+
+/// Remove the timestamp, hostname, app name, process ID, message ID, and
+/// structured data that follow the version in an RFC 5424 header.
+fn strip_rfc5424_header(rest: &str) -> &str {
+    let mut rest = rest;
+    for _ in 0..5 {
+        rest = match rest.find(' ') {
+            Some(i) => &rest[i + 1..],
+            None => return "",
+        };
+    }
+
+    if let Some(r) = rest.strip_prefix('-') {
+        rest = r;
+    } else {
+        // One or more [id param="value"] elements, where values may contain escaped brackets
+        while rest.starts_with('[') {
+            let mut escaped = false;
+            let end = rest.char_indices().find(|(_, c)| {
+                let found = !escaped && *c == ']';
+                escaped = !escaped && *c == '\\';
+                found
+            });
+
+            rest = match end {
+                Some((i, _)) => &rest[i + 1..],
+                None => return "",
+            };
+        }
+    }
+
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+    rest.strip_prefix('\u{feff}').unwrap_or(rest)
+}
+
+/// Remove the timestamp, hostname, and tag that follow the priority in an RFC 3164
+/// header. Everything up to the first `: ` after the timestamp is considered part of
+/// the header since hostnames and tags can't contain it.
+fn strip_rfc3164_header(rest: &str) -> &str {
+    // Timestamps are always the same length, e.g. "Oct 11 22:14:15 "
+    match rest.get(16..).and_then(|s| s.find(": ").map(|i| (s, i))) {
+        Some((s, i)) => &s[i + 2..],
+        None => rest,
+    }
+}
+
+/// Regex-based parser for constructing logging events from an access log.
+///
+/// The provided regular expression is applied and log line and a builder is
+/// returned that is used to parse captured values and build up a `HashMap`
+/// of fields and values.
+#[derive(Debug, Clone)]
+struct ParserImpl {
+    regex: Regex,
+    timestamps: TimestampContext,
+}
+
+impl ParserImpl {
+    fn new(regex: Regex) -> Self {
+        Self {
+            regex,
+            timestamps: TimestampContext::new(),
+        }
+    }
+
+    fn apply<'a>(&'a self, line: &'a str) -> RedeyeResult<FieldBuilder<'a>> {
+        self.regex
+            .captures(line)
+            .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+            .map(|matches| FieldBuilder::root(line, matches, &self.timestamps))
+    }
+}
+
+/// Builder for constructing a `HashMap` of fields and values based
+/// on the results of parsing log values from the provided `Captures`
+/// object.
+#[derive(Debug)]
+struct FieldBuilder<'a> {
+    line: &'a str,
+    captures: Rc<Captures<'a>>,
+    timestamps: &'a TimestampContext,
+    field: Option<String>,
+    parent: Option<Box<FieldBuilder<'a>>>,
+    values: HashMap<String, LogFieldValue>,
+}
+
+impl<'a> FieldBuilder<'a> {
+    /// Create a new root field builder for parsing fields from the given
+    /// `regex::Captures` object, parsing timestamps using the given context.
+    fn root(line: &'a str, captures: Captures<'a>, timestamps: &'a TimestampContext) -> Self {
+        let len = captures.len();
+
+        FieldBuilder {
+            line,
+            captures: Rc::new(captures),
+            timestamps,
+            field: None,
+            parent: None,
+            values: HashMap::with_capacity(len),
+        }
+    }
+
+    /// Create a nested field builder object for parsing fields from the
+    /// given `regex::Captures` object and parent builder that control will
+    /// be returned to when `.complete_mapping()` is called.
+    fn leaf(line: &'a str, captures: Rc<Captures<'a>>, field: String, parent: Box<FieldBuilder<'a>>) -> Self {
+        FieldBuilder {
+            line,
+            captures,
+            timestamps: parent.timestamps,
+            field: Some(field),
+            parent: Some(parent),
+            values: HashMap::new(),
+        }
+    }
+
+    /// Parse the text value in position `index` and output the field
+    /// using the given name. Return an error if the value could not be
+    /// parsed.
+    fn add_text_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_text_value(&self.captures, index, self.line)?;
+        if let Some(v) = res {
+            self.values.insert(field.into(), v);
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the text value in position `index`, written between quotes, and
+    /// output the field with its escapes decoded using the given name.
+    fn add_quoted_text_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        if let Some(LogFieldValue::Text(v)) = parse_text_value(&self.captures, index, self.line)? {
+            let value = unescape_quoted(&v).into_owned();
+            self.values.insert(field.into(), LogFieldValue::Text(value));
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the text value in position `index`, which is part of an optional
+    /// group, and output the field using the given name if the group matched.
+    /// Return an error if the value could not be parsed.
+    fn add_optional_text_field<S>(self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        if self.captures.get(index).is_none() {
+            return Ok(self);
+        }
+
+        self.add_text_field(field, index)
+    }
+
+    /// Parse the integer value in position `index`, which is part of an optional
+    /// group, and output the field using the given name if the group matched.
+    /// Return an error if the value could not be parsed.
+    fn add_optional_int_field<S>(self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        if self.captures.get(index).is_none() {
+            return Ok(self);
+        }
+
+        self.add_int_field(field, index)
+    }
+
+    /// Parse the values in position `index`, separated by `, ` or ` : ` as nginx
+    /// writes a value for each upstream server, with the given function and output
+    /// the field using the given name. A single value is output as it is and several
+    /// values as a list. Empty values (`-`) are skipped and the field is omitted if
+    /// all of them are. Return an error if a value could not be parsed.
+    fn add_list_field<S, F>(mut self, field: S, index: usize, parse: F) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+        F: Fn(&str) -> Option<LogFieldValue>,
+    {
+        let value = self
+            .captures
+            .get(index)
+            .ok_or_else(|| RedeyeError::ParseError(self.line.to_string()))?
+            .as_str();
+
+        let parts: Vec<&str> = value.split(", ").flat_map(|v| v.split(" : ")).collect();
+        let mut values = Vec::with_capacity(parts.len());
+        for part in parts.iter().filter_map(|p| empty_field(p)) {
+            values.push(parse(part).ok_or_else(|| RedeyeError::ParseError(self.line.to_string()))?);
+        }
+
+        if parts.len() == 1 && values.len() == 1 {
+            self.values.insert(field.into(), values.remove(0));
+        } else if !values.is_empty() {
+            self.values.insert(field.into(), LogFieldValue::List(values));
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the timestamp value in position `index` and output the field
+    /// using the given name. Return an error if the value could not be parsed.
+    fn add_timestamp_field<S>(mut self, field: S, index: usize, format: &str) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_timestamp(&self.captures, index, self.line, format, self.timestamps)?;
+        if let Some(v) = res {
+            self.values.insert(field.into(), v);
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the timestamp value in position `index`, written as seconds since
+    /// the epoch with optional fractional seconds, and output the field using
+    /// the given name. Return an error if the value could not be parsed.
+    fn add_epoch_timestamp_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_epoch_timestamp(&self.captures, index, self.line)?;
+        if let Some(v) = res {
+            self.values.insert(field.into(), v);
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the fields of the Combined log format from the first twelve capture
+    /// groups, as matched by `COMBINED_PATTERN`.
+    fn add_combined_fields(self) -> RedeyeResult<Self> {
+        Ok(self
+            .add_text_field("remote_host", 1)?
+            .add_text_field("ident", 2)?
+            .add_text_field("remote_user", 3)?
+            .add_timestamp_field("@timestamp", 4, COMMON_LOG_TIMESTAMP)?
+            .add_text_field("requested_url", 5)?
+            .add_text_field("method", 6)?
+            .add_text_field("requested_uri", 7)?
+            .add_text_field("protocol", 8)?
+            .add_int_field("status_code", 9)?
+            .add_int_field("content_length", 10)?
+            .add_mapping_field("request_headers")
+            .add_text_field("referer", 11)?
+            .add_text_field("user_agent", 12)?
+            .complete_mapping())
+    }
+
+    /// Parse the comma separated addresses in position `index`, as written for
+    /// the `X-Forwarded-For` header, and output them as a list in the
+    /// `request_headers` mapping and the first of them as `client_ip`. Both
+    /// are omitted if there aren't any addresses.
+    fn add_forwarded_for_fields(mut self, index: usize) -> RedeyeResult<Self> {
+        let addrs: Vec<LogFieldValue> = match parse_text_value(&self.captures, index, self.line)? {
+            Some(LogFieldValue::Text(v)) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|a| !a.is_empty() && *a != "-")
+                .map(|a| LogFieldValue::Text(a.to_owned()))
+                .collect(),
+            _ => return Ok(self),
+        };
+
+        if let Some(first) = addrs.first() {
+            self.values.insert("client_ip".to_owned(), first.clone());
+            let headers = self
+                .values
+                .entry("request_headers".to_owned())
+                .or_insert_with(|| LogFieldValue::Mapping(HashMap::new()));
+
+            if let LogFieldValue::Mapping(headers) = headers {
+                headers.insert("x_forwarded_for".to_owned(), LogFieldValue::List(addrs));
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the integer value in position `index` and output the field
+    /// using the given name. Return an error if the value could not be parsed.
+    fn add_int_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_int_value(&self.captures, index, self.line)?;
+        if let Some(v) = res {
+            self.values.insert(field.into(), v);
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the float value in position `index` and output the field
+    /// using the given name. Return an error if the value could not be parsed.
+    fn add_float_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_float_value(&self.captures, index, self.line)?;
+        if let Some(v) = res {
+            self.values.insert(field.into(), v);
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the duration value in position `index`, written by Go with a unit
+    /// (e.g. `12ms` or `1.5s`), and output the field as a number of milliseconds
+    /// using the given name. Return an error if the value could not be parsed.
+    fn add_go_duration_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_text_value(&self.captures, index, self.line)?;
+        if let Some(LogFieldValue::Text(v)) = res {
+            let millis = go_duration_millis(&v).ok_or_else(|| RedeyeError::ParseError(self.line.to_string()))?;
+            self.values.insert(field.into(), LogFieldValue::Float(millis));
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the duration value in position `index` in the given unit and
+    /// output the field as an integer number of microseconds using the given
+    /// name. Return an error if the value could not be parsed.
+    fn add_duration_field<S>(mut self, field: S, index: usize, unit: DurationUnit) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let res = parse_float_value(&self.captures, index, self.line)?;
+        if let Some(LogFieldValue::Float(v)) = res {
+            let micros = (unit.to_seconds(v) * 1_000_000.0).round() as u64;
+            self.values.insert(field.into(), LogFieldValue::Int(micros));
+        }
+
+        Ok(self)
+    }
+
+    /// Parse the Apache connection status character in position `index` and
+    /// output a readable name for it using the given name. Unknown characters
+    /// are output unchanged. Return an error if the value could not be parsed.
+    fn add_connection_status_field<S>(mut self, field: S, index: usize) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        let status = self
+            .captures
+            .get(index)
+            .ok_or_else(|| RedeyeError::ParseError(self.line.to_string()))?
+            .as_str();
+
+        let value = match status {
+            "X" => "aborted",
+            "+" => "keepalive",
+            "-" => "closed",
+            "" => return Ok(self),
+            other => other,
+        };
+
+        self.values.insert(field.into(), LogFieldValue::Text(value.to_owned()));
+        Ok(self)
+    }
+
+    /// Parse the value for a field of a compiled format based on its kind. Fields
+    /// from optional groups that didn't match (e.g. `%q` without a query string)
+    /// are omitted.
+    fn add_format_field(self, field: &FormatField) -> RedeyeResult<Self> {
+        let (name, index) = (field.name(), field.index());
+        if self.captures.get(index).is_none() {
+            return Ok(self);
+        }
+
+        match field.kind() {
+            FieldKind::Text => self.add_text_field(name, index),
+            FieldKind::QuotedText => self.add_quoted_text_field(name, index),
+            FieldKind::Int => self.add_int_field(name, index),
+            FieldKind::Float => self.add_float_field(name, index),
+            FieldKind::Timestamp(format) => self.add_timestamp_field(name, index, format),
+            FieldKind::Duration(unit) => self.add_duration_field(name, index, *unit),
+            FieldKind::ConnectionStatus => self.add_connection_status_field(name, index),
+        }
+    }
+
+    /// Add a literal string value and output the field using the given name.
+    fn add_fixed_value<K, V>(mut self, field: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.values.insert(field.into(), LogFieldValue::Text(value.into()));
+        self
+    }
+
+    /// Return a new `FieldBuilder` that will be used to construct a nested
+    /// mapping value and will be output using the given name. Note that callers
+    /// must also make a corresponding call to `.complete_mapping()` after adding
+    /// all desired values to the nested mapping.
+    fn add_mapping_field<S>(self, field: S) -> Self
+    where
+        S: Into<String>,
+    {
+        let parent = Box::new(self);
+        FieldBuilder::leaf(parent.line, parent.captures.clone(), field.into(), parent)
+    }
+
+    /// Complete adding fields to a nested mapping value and return the original
+    /// `FieldBuilder` instance to continue working on the previous set of fields.
+    fn complete_mapping(self) -> Self {
+        // Unwraps are OK here because if we're calling this method when not building
+        // a nested mapping, that's a bug completely within our control and panicking
+        // is the most obvious way to handle it.
+        let mut parent = self.parent.unwrap();
+        if !self.values.is_empty() {
+            parent
+                .values
+                .insert(self.field.unwrap(), LogFieldValue::Mapping(self.values));
+        }
+
+        *parent
+    }
+
+    /// Complete parsing and build fields and return a `HashMap` of the values.
+    fn build(self) -> HashMap<String, LogFieldValue> {
+        self.values
+    }
+}
+
+/// Parse the regex capture identified by `index into a timestamp with
+/// a fixed offset, using the zone assumed by the context if it doesn't
+/// have an offset.
+///
+/// Return an error if the capture was missing (the field didn't exist
+/// at all, which is not the same as being empty, aka `-`) or the field
+/// could not be parsed into a timestamp. Return `Ok(None)` if the field
+/// exists but contains an empty value (`-`).
+fn parse_timestamp(
+    matches: &Captures,
+    index: usize,
+    line: &str,
+    format: &str,
+    context: &TimestampContext,
+) -> RedeyeResult<Option<LogFieldValue>> {
+    let field_match = matches
+        .get(index)
+        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+        .map(|m| m.as_str())
+        .map(empty_field)?;
+
+    if let Some(v) = field_match {
+        Ok(Some(LogFieldValue::Timestamp(context.parse(v, format)?)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse the regex capture identified by `index` into a UTC timestamp from
+/// seconds since the epoch, e.g. `1157689324.156`.
+///
+/// Return an error if the capture was missing (the field didn't exist
+/// at all, which is not the same as being empty, aka `-`) or the field
+/// could not be parsed into a timestamp. Return `Ok(None)` if the field
+/// exists but contains an empty value (`-`).
+fn parse_epoch_timestamp(matches: &Captures, index: usize, line: &str) -> RedeyeResult<Option<LogFieldValue>> {
+    let field_match = matches
+        .get(index)
+        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+        .map(|m| m.as_str())
+        .map(empty_field)?;
+
+    match field_match {
+        Some(v) => epoch_timestamp(v)
+            .map(|ts| Some(LogFieldValue::Timestamp(ts)))
+            .ok_or_else(|| RedeyeError::ParseError(line.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Parse seconds since the epoch, with up to nanosecond precision, into a UTC timestamp.
+fn epoch_timestamp(val: &str) -> Option<DateTime<FixedOffset>> {
+    let (secs, frac) = match val.split_once('.') {
+        Some((secs, frac)) => (secs, frac),
+        None => (val, ""),
+    };
+
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if secs.is_empty() || !all_digits(secs) || frac.len() > 9 || !all_digits(frac) {
+        return None;
+    }
+
+    let nanos = if frac.is_empty() {
+        0
+    } else {
+        frac.parse::<u32>().ok()? * 10u32.pow(9 - frac.len() as u32)
+    };
+
+    FixedOffset::east(0).timestamp_opt(secs.parse().ok()?, nanos).single()
+}
+
+/// Parse the regex capture identified by `index` into a string value.
+///
+/// Return an error if the capture was missing (the field didn't exist
+/// at all, which is not the same as being empty, aka `-`). Return
+/// `Ok(None)` if the field exists but contains an empty value (`-`).
+fn parse_text_value(matches: &Captures, index: usize, line: &str) -> RedeyeResult<Option<LogFieldValue>> {
+    matches
+        .get(index)
+        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+        .map(|m| m.as_str())
+        .map(empty_field)
+        .map(|o| o.map(|s| LogFieldValue::Text(s.to_string())))
+}
+
+/// Parse the regex capture identified by `index` into an integer value.
+///
+/// Return an error if the capture was missing (the field didn't exist
+/// at all, which is not the same as being empty, aka `-`) or the field
+/// could not be parsed into an integer. Return `Ok(None)` if the field
+/// exists but contains an empty value (`-`).
+fn parse_int_value(matches: &Captures, index: usize, line: &str) -> RedeyeResult<Option<LogFieldValue>> {
+    let field_match = matches
+        .get(index)
+        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+        .map(|m| m.as_str())
+        .map(empty_field)?;
+
+    if let Some(v) = field_match {
+        let val = v
+            .parse::<u64>()
+            .map_err(|_| RedeyeError::ParseError(line.to_string()))?;
+        Ok(Some(LogFieldValue::Int(val)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse the regex capture identified by `index` into a float value.
+///
+/// Return an error if the capture was missing (the field didn't exist
+/// at all, which is not the same as being empty, aka `-`) or the field
+/// could not be parsed into a finite float. Return `Ok(None)` if the field
+/// exists but contains an empty value (`-`).
+fn parse_float_value(matches: &Captures, index: usize, line: &str) -> RedeyeResult<Option<LogFieldValue>> {
+    let field_match = matches
+        .get(index)
+        .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+        .map(|m| m.as_str())
+        .map(empty_field)?;
+
+    if let Some(v) = field_match {
+        float_value(v)
+            .map(Some)
+            .ok_or_else(|| RedeyeError::ParseError(line.to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse a finite, non-negative float value.
+fn float_value(val: &str) -> Option<LogFieldValue> {
+    val.parse::<f64>()
+        .ok()
+        .filter(|f| f.is_finite() && *f >= 0.0)
+        .map(LogFieldValue::Float)
+}
+
+/// Parse a duration as written by Go, a number and unit optionally followed by
+/// more of them (e.g. `850µs`, `12ms`, or `1m2.5s`), into milliseconds.
+fn go_duration_millis(val: &str) -> Option<f64> {
+    if val.is_empty() {
+        return None;
+    }
+
+    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+    let mut rest = val;
+    let mut total = 0.0;
+
+    while !rest.is_empty() {
+        let number_end = rest.find(|c| !is_number(c)).filter(|i| *i > 0)?;
+        let number = rest[..number_end].parse::<f64>().ok()?;
+        rest = &rest[number_end..];
+
+        let unit_end = rest.find(is_number).unwrap_or(rest.len());
+        let to_millis = match &rest[..unit_end] {
+            "ns" => 0.000_001,
+            "us" | "µs" | "μs" => 0.001,
+            "ms" => 1.0,
+            "s" => 1_000.0,
+            "m" => 60_000.0,
+            "h" => 3_600_000.0,
+            _ => return None,
+        };
+
+        total += number * to_millis;
+        rest = &rest[unit_end..];
+    }
+
+    Some(total)
+}
+
+/// Convert the "-" character that represents empty fields
+fn empty_field(val: &str) -> Option<&str> {
+    if val == "-" {
+        None
+    } else {
+        Some(val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{
+        epoch_timestamp, go_duration_millis, parse_float_value, parse_int_value, parse_line_to_json, parse_text_value,
+        parse_timestamp, split_logfmt, split_w3c, strip_syslog_header, AgentLogLineParser, CaddyJsonLineParser,
+        CombinedIoLogLineParser, CombinedLogLineParser, CommonLogLineParser, CustomLogLineParser, ErrorLogLineParser,
+        ForensicLogParser, IngressNginxLogLineParser, JettyLogLineParser, LighttpdLogLineParser, LogLineParser,
+        LogfmtLineParser, LtsvLogLineParser, MorganShortLogLineParser, MorganTinyLogLineParser, NamedFormat,
+        ParserImpl, ParserRegistry, PythonDevServerLogLineParser, RefererLogLineParser, SquidLogLineParser,
+        SyslogLogLineParser, TomcatLogLineParser, TraefikLogLineParser, VarnishLogLineParser,
+        VhostCombinedLogLineParser, W3cLogLineParser, COMMON_LOG_TIMESTAMP,
+    };
+    use crate::format::{LogFormat, COMBINED_FORMAT};
+    use crate::timestamp::{AssumedZone, TimestampContext};
+    use crate::types::{DurationUnit, LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
+    use chrono::{Datelike, FixedOffset, TimeZone, Timelike, Utc};
+    use regex::{Captures, Regex};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_common_log_line_parser() {
+        let line = "127.0.0.1 - frank [11/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
+        let offset = FixedOffset::west(7 * 3600);
+        let ts = Utc::now()
+            .with_timezone(&offset)
+            .with_year(2000)
+            .unwrap()
+            .with_month(10)
+            .unwrap()
+            .with_day(11)
+            .unwrap()
+            .with_hour(13)
+            .unwrap()
+            .with_minute(55)
+            .unwrap()
+            .with_second(36)
+            .unwrap()
+            .with_nanosecond(0)
+            .unwrap();
+
+        let parser = CommonLogLineParser::new();
+        let event = parser.parse(line).unwrap();
+        let fields = event.fields();
+
+        assert_eq!(
+            &LogFieldValue::Text("127.0.0.1".to_owned()),
+            fields.get("remote_host").unwrap()
+        );
+        assert!(!fields.contains_key("ident"));
+        assert_eq!(
+            &LogFieldValue::Text("frank".to_owned()),
+            fields.get("remote_user").unwrap()
+        );
+        assert_eq!(&LogFieldValue::Timestamp(ts), fields.get("@timestamp").unwrap());
+        assert_eq!(
+            &LogFieldValue::Text("GET /index.html HTTP/1.0".to_owned()),
+            fields.get("requested_url").unwrap()
+        );
+        assert_eq!(&LogFieldValue::Text("GET".to_owned()), fields.get("method").unwrap());
+        assert_eq!(
+            &LogFieldValue::Text("/index.html".to_owned()),
+            fields.get("requested_uri").unwrap()
+        );
+        assert_eq!(
+            &LogFieldValue::Text("HTTP/1.0".to_owned()),
+            fields.get("protocol").unwrap()
+        );
+        assert_eq!(&LogFieldValue::Int(200), fields.get("status_code").unwrap());
+        assert_eq!(&LogFieldValue::Int(2326), fields.get("content_length").unwrap());
+        assert_eq!(&LogFieldValue::Text("1".to_owned()), fields.get("@version").unwrap());
+        assert_eq!(&LogFieldValue::Text(line.to_owned()), fields.get("message").unwrap());
+    }
+
+    #[test]
+    fn test_combined_log_line_parser() {}
+
+    #[test]
+    fn test_custom_log_line_parser_matches_combined() {
+        let line = concat!(
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326 ",
+            "\"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\""
+        );
+
+        let custom = CustomLogLineParser::from_format(LogFormat::apache(COMBINED_FORMAT).unwrap());
+        let combined = CombinedLogLineParser::new();
+
+        assert_eq!(combined.parse(line).unwrap(), custom.parse(line).unwrap());
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_response_headers() {
+        let parser = CombinedLogLineParser::new().response_headers(&["Content-Type", "Cache-Control"]);
+        let line = concat!(
+            "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326 ",
+            "\"http://www.example.com/start.html\" \"Mozilla/4.08 [en] (Win98; I ;Nav)\" \"text/html\" \"no-cache\""
+        );
+
+        let event = parser.parse(line).unwrap();
+        let text = |s: &str| LogFieldValue::Text(s.to_owned());
+        assert_eq!(Some(&text("text/html")), event.lookup("response_headers.content_type"));
+        assert_eq!(Some(&text("no-cache")), event.lookup("response_headers.cache_control"));
+        assert_eq!(Some(&text("text/html")), event.lookup("mime_type"));
+        assert_eq!(Some(&text("frank")), event.lookup("remote_user"));
+
+        let format = LogFormat::apache(&format!(
+            "{} \"%{{Content-Type}}o\" \"%{{Cache-Control}}o\"",
+            COMBINED_FORMAT
+        ))
+        .unwrap();
+        let custom = CustomLogLineParser::from_format(format);
+        assert_eq!(event, custom.parse(line).unwrap());
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_request_time() {
+        let parser = CombinedLogLineParser::new().request_time(true);
+        let prefix = "10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"Mozilla/5.0\"";
+
+        let event = parser.parse(&format!("{} 0.042", prefix)).unwrap();
+        assert_eq!(Some(&LogFieldValue::Float(0.042)), event.lookup("request_time"));
+        assert_eq!(Some(&LogFieldValue::Int(612)), event.lookup("content_length"));
+
+        let event = parser.parse(&format!("{} -", prefix)).unwrap();
+        assert!(!event.fields().contains_key("request_time"));
+
+        assert!(matches!(parser.parse(prefix), Err(RedeyeError::ParseError(_))));
+        assert!(matches!(
+            parser.parse(&format!("{} fast", prefix)),
+            Err(RedeyeError::ParseError(_))
+        ));
+        assert!(matches!(
+            CombinedLogLineParser::new().parse(&format!("{} 0.042", prefix)),
+            Err(RedeyeError::ParseError(_))
+        ));
+
+        let parser = parser.response_headers(&["Content-Type"]);
+        let event = parser.parse(&format!("{} 0.042 \"text/html\"", prefix)).unwrap();
+        assert_eq!(Some(&LogFieldValue::Float(0.042)), event.lookup("request_time"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("text/html".to_owned())),
+            event.lookup("mime_type")
+        );
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_duration() {
+        let prefix = "10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"Mozilla/5.0\"";
+
+        for (unit, value) in &[
+            (DurationUnit::Microseconds, "42000"),
+            (DurationUnit::Milliseconds, "42"),
+            (DurationUnit::Seconds, "0.042"),
+        ] {
+            let parser = CombinedLogLineParser::new().duration(*unit);
+            let event = parser.parse(&format!("{} {}", prefix, value)).unwrap();
+            assert_eq!(
+                Some(&LogFieldValue::Int(42_000)),
+                event.lookup("duration_us"),
+                "{:?}",
+                unit
+            );
+        }
+
+        let parser = CombinedLogLineParser::new().duration(DurationUnit::Seconds);
+        let event = parser.parse(&format!("{} -", prefix)).unwrap();
+        assert!(!event.fields().contains_key("duration_us"));
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+
+        // A missing duration means the format is wrong so the line isn't valid
+        assert!(matches!(parser.parse(prefix), Err(RedeyeError::ParseError(_))));
+        assert!(matches!(
+            parser.parse(&format!("{} slow", prefix)),
+            Err(RedeyeError::ParseError(_))
+        ));
+
+        let parser = parser.request_time(true).response_headers(&["Content-Type"]);
+        let event = parser.parse(&format!("{} 0.042 1 \"text/html\"", prefix)).unwrap();
+        assert_eq!(Some(&LogFieldValue::Float(0.042)), event.lookup("request_time"));
+        assert_eq!(Some(&LogFieldValue::Int(1_000_000)), event.lookup("duration_us"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("text/html".to_owned())),
+            event.lookup("mime_type")
+        );
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_upstream() {
+        let parser = CombinedLogLineParser::new().upstream(true);
+        let prefix = "10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"Mozilla/5.0\"";
+
+        let event = parser.parse(&format!("{} 10.0.0.5:8080 0.032 200", prefix)).unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("10.0.0.5:8080".to_owned())),
+            event.lookup("upstream.addr")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Float(0.032)),
+            event.lookup("upstream.response_time")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("upstream.status"));
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+
+        // Requests retried across upstreams have a value for each of them
+        let event = parser
+            .parse(&format!(
+                "{} 10.0.0.5:8080, 10.0.0.6:8080 0.032, 0.015 502, 200",
+                prefix
+            ))
+            .unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Text("10.0.0.5:8080".to_owned()),
+                LogFieldValue::Text("10.0.0.6:8080".to_owned()),
+            ])),
+            event.lookup("upstream.addr")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Float(0.032),
+                LogFieldValue::Float(0.015)
+            ])),
+            event.lookup("upstream.response_time")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Int(502),
+                LogFieldValue::Int(200)
+            ])),
+            event.lookup("upstream.status")
+        );
+
+        // Internal redirects to other upstream groups are separated by " : "
+        let event = parser
+            .parse(&format!(
+                "{} 10.0.0.5:8080 : unix:/run/app.sock 0.032 : 0.001 404 : 200",
+                prefix
+            ))
+            .unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Text("10.0.0.5:8080".to_owned()),
+                LogFieldValue::Text("unix:/run/app.sock".to_owned()),
+            ])),
+            event.lookup("upstream.addr")
+        );
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_forwarded_for() {
+        let parser = CombinedLogLineParser::new().forwarded_for(true);
+        let prefix = "10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"Mozilla/5.0\"";
+        let text = |v: &str| LogFieldValue::Text(v.to_string());
+
+        let event = parser
+            .parse(&format!("{} \"203.0.113.7,10.0.0.2 ,  10.0.0.3\"", prefix))
+            .unwrap();
+        assert_eq!(Some(&text("203.0.113.7")), event.lookup("client_ip"));
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                text("203.0.113.7"),
+                text("10.0.0.2"),
+                text("10.0.0.3")
+            ])),
+            event.lookup("request_headers.x_forwarded_for")
+        );
+        assert_eq!(Some(&text("Mozilla/5.0")), event.lookup("request_headers.user_agent"));
+
+        let event = parser.parse(&format!("{} \"2001:db8::1\"", prefix)).unwrap();
+        assert_eq!(Some(&text("2001:db8::1")), event.lookup("client_ip"));
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![text("2001:db8::1")])),
+            event.lookup("request_headers.x_forwarded_for")
+        );
+
+        for rest in &["\"-\"", "\"\""] {
+            let event = parser.parse(&format!("{} {}", prefix, rest)).unwrap();
+            assert_eq!(None, event.lookup("client_ip"));
+            assert_eq!(None, event.lookup("request_headers.x_forwarded_for"));
+        }
+
+        match parser.parse(prefix) {
+            Err(RedeyeError::ParseError(_)) => (),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_forwarded_for_request_time() {
+        let parser = CombinedLogLineParser::new().forwarded_for(true).request_time(true);
+        let event = parser
+            .parse("10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"-\" \"203.0.113.7\" 0.042")
+            .unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("203.0.113.7".to_string())),
+            event.lookup("client_ip")
+        );
+        assert_eq!(Some(&LogFieldValue::Float(0.042)), event.lookup("request_time"));
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_upstream_missing() {
+        let parser = CombinedLogLineParser::new().upstream(true);
+        let prefix = "10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"Mozilla/5.0\"";
+
+        // Requests served without an upstream, e.g. static files
+        let event = parser.parse(&format!("{} - - -", prefix)).unwrap();
+        assert!(!event.fields().contains_key("upstream"));
+
+        let event = parser.parse(&format!("{} 10.0.0.5:8080 - 504", prefix)).unwrap();
+        assert!(event.lookup("upstream.response_time").is_none());
+        assert_eq!(Some(&LogFieldValue::Int(504)), event.lookup("upstream.status"));
+
+        let event = parser
+            .parse(&format!("{} 10.0.0.5:8080, 10.0.0.6:8080 -, 0.015 -, 200", prefix))
+            .unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![LogFieldValue::Int(200)])),
+            event.lookup("upstream.status")
+        );
+
+        for rest in &[
+            "",
+            " 10.0.0.5:8080 0.032",
+            " 10.0.0.5:8080 fast 200",
+            " 10.0.0.5:8080 0.032 ok",
+        ] {
+            assert!(
+                matches!(
+                    parser.parse(&format!("{}{}", prefix, rest)),
+                    Err(RedeyeError::ParseError(_))
+                ),
+                "{}",
+                rest
+            );
+        }
+    }
+
+    #[test]
+    fn test_vhost_combined_log_line_parser() {
+        let parser = VhostCombinedLogLineParser::new();
+        let line = "www.example.com:8080 127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326 \"-\" \"curl/7.64.1\"";
+        let event = parser.parse(line).unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("www.example.com".to_owned())),
+            event.lookup("server_name")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(8080)), event.lookup("server_port"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("127.0.0.1".to_owned())),
+            event.lookup("remote_host")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("frank".to_owned())),
+            event.lookup("remote_user")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("curl/7.64.1".to_owned())),
+            event.lookup("request_headers.user_agent")
+        );
+        assert_eq!(Some(&LogFieldValue::Text(line.to_owned())), event.lookup("message"));
+    }
+
+    #[test]
+    fn test_vhost_combined_log_line_parser_missing_port() {
+        let parser = VhostCombinedLogLineParser::new();
+        let rest = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 \"-\" \"-\"";
+
+        let event = parser.parse(&format!("www.example.com {}", rest)).unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("www.example.com".to_owned())),
+            event.lookup("server_name")
+        );
+        assert!(!event.fields().contains_key("server_port"));
+
+        let event = parser.parse(&format!("www.example.com:- {}", rest)).unwrap();
+        assert!(!event.fields().contains_key("server_port"));
+
+        let event = parser.parse(&format!("- {}", rest)).unwrap();
+        assert!(!event.fields().contains_key("server_name"));
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+    }
+
+    #[test]
+    fn test_vhost_combined_log_line_parser_ipv6() {
+        let parser = VhostCombinedLogLineParser::new();
+        let rest = "::1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 \"-\" \"-\"";
+
+        let event = parser.parse(&format!("[::1]:80 {}", rest)).unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("[::1]".to_owned())),
+            event.lookup("server_name")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(80)), event.lookup("server_port"));
+
+        let event = parser.parse(&format!("[2001:db8::1] {}", rest)).unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("[2001:db8::1]".to_owned())),
+            event.lookup("server_name")
+        );
+        assert!(!event.fields().contains_key("server_port"));
+    }
+
+    fn error_log_parser() -> ErrorLogLineParser {
+        ErrorLogLineParser::new().timestamp_context(TimestampContext::new().assume_zone("UTC".parse().unwrap()))
+    }
+
+    #[test]
+    fn test_error_log_line_parser() {
+        let parser = error_log_parser();
+        let event = parser
+            .parse("[Thu Oct 10 13:55:36.123456 2000] [core:error] [pid 1234:tid 5678] [client 1.2.3.4:56789] AH00126: Invalid URI in request GET /../ HTTP/1.1")
+            .unwrap();
+        let fields = event.fields();
+
+        match fields.get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2000-10-10T13:55:36.123456+00:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+        assert_eq!(Some(&LogFieldValue::Text("core".to_owned())), fields.get("module"));
+        assert_eq!(Some(&LogFieldValue::Text("error".to_owned())), fields.get("level"));
+        assert_eq!(Some(&LogFieldValue::Int(1234)), fields.get("pid"));
+        assert_eq!(Some(&LogFieldValue::Int(5678)), fields.get("tid"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("1.2.3.4:56789".to_owned())),
+            fields.get("client")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("AH00126".to_owned())),
+            fields.get("error_code")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text(
+                "Invalid URI in request GET /../ HTTP/1.1".to_owned()
+            )),
+            fields.get("message")
+        );
+        assert_eq!(Some(&LogFieldValue::Text("1".to_owned())), fields.get("@version"));
+    }
+
+    #[test]
+    fn test_error_log_line_parser_optional_portions() {
+        let parser = error_log_parser();
+
+        let event = parser
+            .parse("[Thu Oct 10 13:55:36.123456 2000] [mpm_event:notice] [pid 1234:tid 5678] AH00489: Apache/2.4.41 configured")
+            .unwrap();
+        assert!(!event.fields().contains_key("client"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("AH00489".to_owned())),
+            event.lookup("error_code")
+        );
+
+        let event = parser
+            .parse("[Thu Oct 10 13:55:36.123456 2000] [php7:error] [pid 1234] [client 1.2.3.4:56789] PHP Fatal error: oops")
+            .unwrap();
+        assert!(!event.fields().contains_key("tid"));
+        assert!(!event.fields().contains_key("error_code"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("PHP Fatal error: oops".to_owned())),
+            event.lookup("message")
+        );
+
+        let event = parser
+            .parse("[Thu Oct 10 13:55:36.123456 2000] [:error] [pid 1234] something went wrong")
+            .unwrap();
+        assert!(!event.fields().contains_key("module"));
+        assert_eq!(Some(&LogFieldValue::Text("error".to_owned())), event.lookup("level"));
+
+        // Apache 2.2, without fractional seconds, a module, or a process
+        let event = parser
+            .parse("[Wed Oct 11 14:32:52 2000] [error] [client 127.0.0.1] client denied by server configuration: /export/home/live/ap/htdocs/test")
+            .unwrap();
+        match event.fields().get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2000-10-11T14:32:52+00:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+        assert!(!event.fields().contains_key("pid"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("127.0.0.1".to_owned())),
+            event.lookup("client")
+        );
+    }
+
+    #[test]
+    fn test_error_log_line_parser_invalid() {
+        let parser = error_log_parser();
+
+        for line in &[
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326",
+            "[Thu Oct 10 13:55:36.123456 2000] AH00126: no level",
+            "[10/Oct/2000:13:55:36 -0700] [core:error] not an error log timestamp",
+            "",
+        ] {
+            assert!(parser.parse(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_error_log_line_parser_default_zone() {
+        let line = "[Wed Oct 11 14:32:52.123456 2000] [core:error] [pid 1] AH00037: Symbolic link not allowed";
+
+        let event = ErrorLogLineParser::new().parse(line).unwrap();
+        match event.fields().get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2000-10-11T14:32:52.123456+00:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+
+        // A context without a zone still assumes UTC, one with a zone uses it
+        let event = ErrorLogLineParser::new()
+            .timestamp_context(TimestampContext::new())
+            .parse(line)
+            .unwrap();
+        assert!(event.fields().contains_key("@timestamp"));
+
+        let zone = AssumedZone::Fixed(FixedOffset::west(7 * 3600));
+        let event = ErrorLogLineParser::new()
+            .timestamp_context(TimestampContext::new().assume_zone(zone))
+            .parse(line)
+            .unwrap();
+        match event.fields().get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2000-10-11T14:32:52.123456-07:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_squid_log_line_parser() {
+        let parser = SquidLogLineParser::new();
+        let line =
+            "1157689324.156   1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html";
+        let event = parser.parse(line).unwrap();
+        let fields = event.fields();
+
+        match fields.get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2006-09-08T04:22:04.156+00:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+        assert_eq!(Some(&LogFieldValue::Int(1372)), fields.get("duration_ms"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("10.0.0.1".to_owned())),
+            fields.get("remote_host")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("TCP_MISS".to_owned())),
+            fields.get("cache_status")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(200)), fields.get("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(399)), fields.get("content_length"));
+        assert_eq!(Some(&LogFieldValue::Text("GET".to_owned())), fields.get("method"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("http://example.com/".to_owned())),
+            fields.get("requested_url")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("DIRECT".to_owned())),
+            fields.get("hierarchy_code")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("192.0.2.1".to_owned())),
+            fields.get("peer_host")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("text/html".to_owned())),
+            fields.get("content_type")
+        );
+        assert_eq!(Some(&LogFieldValue::Text(line.to_owned())), fields.get("message"));
+        assert!(!fields.contains_key("remote_user"));
+    }
+
+    #[test]
+    fn test_squid_log_line_parser_missing_values() {
+        let parser = SquidLogLineParser::new();
+        let event = parser
+            .parse("1157689412.108      0 10.0.0.2 TCP_DENIED/403 3914 CONNECT example.com:443 frank HIER_NONE/- -")
+            .unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("frank".to_owned())),
+            event.lookup("remote_user")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("HIER_NONE".to_owned())),
+            event.lookup("hierarchy_code")
+        );
+        assert!(event.lookup("peer_host").is_none());
+        assert!(event.lookup("content_type").is_none());
+        assert_eq!(Some(&LogFieldValue::Int(0)), event.lookup("duration_ms"));
+    }
+
+    #[test]
+    fn test_python_dev_server_log_line_parser() {
+        let parser = PythonDevServerLogLineParser::new();
+
+        for line in &[
+            "127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api?x=1 HTTP/1.1\" 200 -",
+            "127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api?x=1 HTTP/1.1\" 200",
+        ] {
+            let event = parser.parse(line).unwrap();
+
+            assert_eq!(
+                Some(&LogFieldValue::Text("127.0.0.1".to_string())),
+                event.lookup("remote_host")
+            );
+            assert_eq!(None, event.lookup("ident"));
+            assert_eq!(None, event.lookup("remote_user"));
+            assert_eq!(
+                Some(&LogFieldValue::Timestamp(
+                    FixedOffset::east(0).ymd(2020, 10, 10).and_hms(13, 55, 36)
+                )),
+                event.lookup("@timestamp")
+            );
+            assert_eq!(
+                Some(&LogFieldValue::Text("GET /api?x=1 HTTP/1.1".to_string())),
+                event.lookup("requested_url")
+            );
+            assert_eq!(Some(&LogFieldValue::Text("GET".to_string())), event.lookup("method"));
+            assert_eq!(
+                Some(&LogFieldValue::Text("/api?x=1".to_string())),
+                event.lookup("requested_uri")
+            );
+            assert_eq!(
+                Some(&LogFieldValue::Text("HTTP/1.1".to_string())),
+                event.lookup("protocol")
+            );
+            assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+            assert_eq!(None, event.lookup("content_length"), "{}", line);
+        }
+
+        let event = parser
+            .parse("::1 - - [10/Oct/2020 13:55:36] \"POST /upload HTTP/1.0\" 201 532")
+            .unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(532)), event.lookup("content_length"));
+    }
+
+    #[test]
+    fn test_python_dev_server_log_line_parser_assumed_zone() {
+        let zone = AssumedZone::Fixed(FixedOffset::west(7 * 3600));
+        let parser = PythonDevServerLogLineParser::new().timestamp_context(TimestampContext::new().assume_zone(zone));
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET / HTTP/1.1\" 304 -")
+            .unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Timestamp(
+                FixedOffset::west(7 * 3600).ymd(2020, 10, 10).and_hms(13, 55, 36)
+            )),
+            event.lookup("@timestamp")
+        );
+    }
+
+    #[test]
+    fn test_python_dev_server_log_line_parser_invalid() {
+        let parser = PythonDevServerLogLineParser::new();
+
+        for line in &[
+            "",
+            "127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api HTTP/1.1\" OK -",
+            "127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api HTTP/1.1\" 200 - extra",
+            "127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api HTTP/1.1\" 200 many",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_referer_log_line_parser() {
+        let parser = RefererLogLineParser::new();
+        let before = Utc::now();
+
+        for line in &[
+            "http://example.com/links.html -> /index.html",
+            "http://example.com/links.html->/index.html",
+            "http://example.com/links.html \t->   /index.html",
+        ] {
+            let event = parser.parse(line).unwrap();
+
+            assert_eq!(
+                Some(&LogFieldValue::Text("http://example.com/links.html".to_string())),
+                event.lookup("request_headers.referer"),
+                "{}",
+                line
+            );
+            assert_eq!(
+                Some(&LogFieldValue::Text("/index.html".to_string())),
+                event.lookup("requested_uri")
+            );
+            assert_eq!(Some(&LogFieldValue::Text(line.to_string())), event.lookup("message"));
+            assert_eq!(Some(&LogFieldValue::Text("1".to_string())), event.lookup("@version"));
+            match event.lookup("@timestamp") {
+                Some(LogFieldValue::Timestamp(ts)) => assert!(*ts >= before),
+                v => panic!("Unexpected timestamp: {:?}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_referer_log_line_parser_no_referer() {
+        let event = RefererLogLineParser::new().parse("- -> /index.html").unwrap();
+
+        assert_eq!(None, event.lookup("request_headers"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("/index.html".to_string())),
+            event.lookup("requested_uri")
+        );
+    }
+
+    #[test]
+    fn test_referer_log_line_parser_invalid() {
+        let parser = RefererLogLineParser::new();
+
+        for line in &[
+            "",
+            "/index.html",
+            "http://example.com/ -> ",
+            "-> /index.html",
+            "a b -> /index.html",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_agent_log_line_parser() {
+        let parser = AgentLogLineParser::new();
+        let event = parser.parse("Mozilla/5.0 (X11; Linux x86_64) curl -> ok\n").unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text(
+                "Mozilla/5.0 (X11; Linux x86_64) curl -> ok".to_string()
+            )),
+            event.lookup("request_headers.user_agent")
+        );
+        assert!(matches!(event.lookup("@timestamp"), Some(LogFieldValue::Timestamp(_))));
+
+        let event = parser.parse("-").unwrap();
+        assert_eq!(None, event.lookup("request_headers"));
+
+        match parser.parse("  ") {
+            Err(RedeyeError::ParseError(_)) => (),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_morgan_tiny_log_line_parser() {
+        let parser = MorganTinyLogLineParser::new();
+        let before = Utc::now();
+        let event = parser.parse("GET /search?q=x 200 2326 - 2.134 ms").unwrap();
+
+        assert_eq!(Some(&LogFieldValue::Text("GET".to_string())), event.lookup("method"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("/search?q=x".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(2326)), event.lookup("content_length"));
+        assert_eq!(Some(&LogFieldValue::Float(2.134)), event.lookup("response_time_ms"));
+        assert_eq!(None, event.lookup("protocol"));
+        match event.lookup("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert!(*ts >= before),
+            v => panic!("Unexpected timestamp: {:?}", v),
+        }
+
+        let event = parser.parse("GET / 304 - - 0.512 ms").unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(304)), event.lookup("status_code"));
+        assert_eq!(None, event.lookup("content_length"));
+
+        let event = parser.parse("GET / - - - - ms").unwrap();
+        assert_eq!(None, event.lookup("status_code"));
+        assert_eq!(None, event.lookup("response_time_ms"));
+    }
+
+    #[test]
+    fn test_morgan_short_log_line_parser() {
+        let parser = MorganShortLogLineParser::new();
+        let event = parser
+            .parse("::ffff:127.0.0.1 - POST /login HTTP/1.1 302 58 - 12.004 ms")
+            .unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("::ffff:127.0.0.1".to_string())),
+            event.lookup("remote_host")
+        );
+        assert_eq!(None, event.lookup("remote_user"));
+        assert_eq!(Some(&LogFieldValue::Text("POST".to_string())), event.lookup("method"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("/login".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("HTTP/1.1".to_string())),
+            event.lookup("protocol")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(302)), event.lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(58)), event.lookup("content_length"));
+        assert_eq!(Some(&LogFieldValue::Float(12.004)), event.lookup("response_time_ms"));
+        assert!(matches!(event.lookup("@timestamp"), Some(LogFieldValue::Timestamp(_))));
+    }
+
+    #[test]
+    fn test_morgan_log_line_parser_invalid() {
+        let tiny = MorganTinyLogLineParser::new();
+        let short = MorganShortLogLineParser::new();
+
+        for line in &[
+            "",
+            "GET / 200 2326 2.134 ms",
+            "GET / 200 2326 - 2.134",
+            "GET / 200 abc - 2.134 ms",
+            "GET / 200 2326 - fast ms",
+        ] {
+            match tiny.parse(line) {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+
+        for line in &["", "GET / 200 2326 - 2.134 ms", "1.2.3.4 - GET / 200 2326 - 2.134 ms"] {
+            match short.parse(line) {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_squid_log_line_parser_invalid() {
+        let parser = SquidLogLineParser::new();
+
+        for line in &[
+            "1157689324.156 1372 10.0.0.1 TCP_MISS 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html",
+            "1157689324.156 1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1",
+            "yesterday 1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html",
+            "1157689324.156 slow 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326",
+        ] {
+            assert!(
+                matches!(parser.parse(line), Err(RedeyeError::ParseError(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_ltsv_log_line_parser() {
+        let line = concat!(
+            "time:[10/Oct/2000:13:55:36 -0700]\thost:127.0.0.1\tuser:frank\treq:GET /index.html HTTP/1.0\t",
+            "status:200\tsize:2326\treferer:http://example.com/\tua:curl/7.64.1\treqtime:0.042\t",
+            "apptime:0.040\tvhost:example.com",
+        );
+        let event = LtsvLogLineParser::new().parse(line).unwrap();
+        let common = CommonLogLineParser::new()
+            .parse("127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326")
+            .unwrap();
+        let text = |v: &str| Some(LogFieldValue::Text(v.to_string()));
+
+        for name in &[
+            "@timestamp",
+            "remote_host",
+            "remote_user",
+            "requested_url",
+            "method",
+            "requested_uri",
+            "protocol",
+            "status_code",
+            "content_length",
+        ] {
+            assert_eq!(common.lookup(name), event.lookup(name), "{}", name);
+        }
+
+        assert_eq!(
+            text("http://example.com/"),
+            event.lookup("request_headers.referer").cloned()
+        );
+        assert_eq!(text("curl/7.64.1"), event.lookup("request_headers.user_agent").cloned());
+        assert_eq!(Some(&LogFieldValue::Float(0.042)), event.lookup("request_time"));
+        assert_eq!(
+            Some(&LogFieldValue::Float(0.04)),
+            event.lookup("upstream.response_time")
+        );
+        assert_eq!(text("example.com"), event.lookup("vhost").cloned());
+        assert_eq!(text(line), event.lookup("message").cloned());
+    }
+
+    #[test]
+    fn test_ltsv_log_line_parser_empty_values() {
+        let line = "time:2000-10-10T13:55:36.5-07:00\thost:127.0.0.1\tuser:-\tmethod:GET\turi:/\tstatus:200\tsize:-\tua:\tforwardedfor:";
+        let event = LtsvLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(
+            Some("2000-10-10T13:55:36.500-07:00".to_string()),
+            match event.lookup("@timestamp") {
+                Some(LogFieldValue::Timestamp(ts)) => Some(ts.to_rfc3339()),
+                _ => None,
+            }
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("/".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(None, event.lookup("remote_user"));
+        assert_eq!(None, event.lookup("content_length"));
+        assert_eq!(None, event.lookup("request_headers"));
+        assert_eq!(None, event.lookup("forwardedfor"));
+        assert_eq!(None, event.lookup("requested_url"));
+    }
+
+    #[test]
+    fn test_ltsv_log_line_parser_invalid() {
+        let parser = LtsvLogLineParser::new();
+
+        for line in &[
+            "",
+            "host:127.0.0.1\tstatus:OK",
+            "host:127.0.0.1\tsize:-1",
+            "host:127.0.0.1\treqtime:fast",
+            "host:127.0.0.1\treq:GET /",
+            "host:127.0.0.1\t200",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(*line, msg),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+
+        assert!(parser.parse("time:yesterday\thost:127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn test_logfmt_line_parser() {
+        let line = r#"ts=2021-01-01T00:00:00Z method=GET path=/x status=200 bytes=512 dur=13ms ua="Mozilla/5.0 (X11; Linux x86_64)" err="said \"no\" \\ twice" cached"#;
+        let event = LogfmtLineParser::new().parse(line).unwrap();
+        let text = |v: &str| Some(LogFieldValue::Text(v.to_string()));
+
+        assert_eq!(
+            Some("2021-01-01T00:00:00+00:00".to_string()),
+            match event.lookup("@timestamp") {
+                Some(LogFieldValue::Timestamp(ts)) => Some(ts.to_rfc3339()),
+                _ => None,
+            }
+        );
+        assert_eq!(text("GET"), event.lookup("method").cloned());
+        assert_eq!(text("/x"), event.lookup("requested_uri").cloned());
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(512)), event.lookup("content_length"));
+        assert_eq!(Some(&LogFieldValue::Float(13.0)), event.lookup("duration_ms"));
+        assert_eq!(text("Mozilla/5.0 (X11; Linux x86_64)"), event.lookup("ua").cloned());
+        assert_eq!(text("said \"no\" \\ twice"), event.lookup("err").cloned());
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![LogFieldValue::Text("cached".to_string())])),
+            event.lookup("tags")
+        );
+        assert_eq!(text(line), event.lookup("message").cloned());
+    }
+
+    #[test]
+    fn test_logfmt_line_parser_alternate_keys() {
+        let line = "time=1157689324.156 uri=/y size=- duration=1.5s user= query=a=b retry tls";
+        let event = LogfmtLineParser::new().parse(line).unwrap();
+
+        assert_eq!(
+            Some("2006-09-08T04:22:04.156+00:00".to_string()),
+            match event.lookup("@timestamp") {
+                Some(LogFieldValue::Timestamp(ts)) => Some(ts.to_rfc3339()),
+                _ => None,
+            }
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("/y".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(None, event.lookup("content_length"));
+        assert_eq!(None, event.lookup("user"));
+        assert_eq!(Some(&LogFieldValue::Float(1500.0)), event.lookup("duration_ms"));
+        assert_eq!(Some(&LogFieldValue::Text("a=b".to_string())), event.lookup("query"));
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Text("retry".to_string()),
+                LogFieldValue::Text("tls".to_string()),
+            ])),
+            event.lookup("tags")
+        );
+    }
+
+    #[test]
+    fn test_logfmt_line_parser_duration_seconds() {
+        let parser = LogfmtLineParser::new();
+
+        let event = parser.parse("path=/x duration=12").unwrap();
+        assert_eq!(Some(&LogFieldValue::Float(12_000.0)), event.lookup("duration_ms"));
+
+        let event = parser.parse("path=/x dur=0.25").unwrap();
+        assert_eq!(Some(&LogFieldValue::Float(250.0)), event.lookup("duration_ms"));
+    }
+
+    #[test]
+    fn test_logfmt_line_parser_invalid() {
+        let parser = LogfmtLineParser::new();
+
+        for line in &[
+            "",
+            "just some words",
+            "status=OK",
+            "bytes=-5",
+            "dur=fast",
+            "dur=-13",
+            r#"msg="unterminated"#,
+            r#"msg="quoted"trailing status=200"#,
+            "=value status=200",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(*line, msg),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+
+        assert!(parser.parse("ts=yesterday status=200").is_err());
+    }
+
+    #[test]
+    fn test_split_logfmt() {
+        assert_eq!(
+            Some(vec![
+                ("a", Some("1".to_owned())),
+                ("b", Some("two words".to_owned())),
+                ("c", None),
+                ("d", Some("".to_owned())),
+                ("e", Some("".to_owned())),
+            ]),
+            split_logfmt(r#"  a=1   b="two words" c d= e="""#)
+        );
+        assert_eq!(
+            Some(vec![("m", Some("tab\tnewline\n\"q\"".to_owned()))]),
+            split_logfmt(r#"m="tab\tnewline\n\"q\"""#)
+        );
+        assert_eq!(None, split_logfmt(r#"m="\"#));
+    }
+
+    #[test]
+    fn test_caddy_json_line_parser() {
+        let line = concat!(
+            r#"{"level":"info","ts":1646861401.5241024,"logger":"http.log.access","msg":"handled request","#,
+            r#""request":{"remote_addr":"1.2.3.4:55555","proto":"HTTP/1.1","method":"GET","host":"example.com","#,
+            r#""uri":"/index.html","headers":{"User-Agent":["curl/7.64.1"],"Referer":["https://example.com/"],"#,
+            r#""Accept":["text/html","*/*"]}},"user_id":"","duration":0.000929675,"size":12,"status":200,"#,
+            r#""resp_headers":{"Content-Type":["text/html"]}}"#,
+        );
+        let event = CaddyJsonLineParser::new().parse(line).unwrap();
+        let text = |v: &str| Some(LogFieldValue::Text(v.to_string()));
+
+        assert_eq!(
+            Some("2022-03-09T21:30:01.524102400+00:00".to_string()),
+            match event.lookup("@timestamp") {
+                Some(LogFieldValue::Timestamp(ts)) => Some(ts.to_rfc3339()),
+                _ => None,
+            }
+        );
+        assert_eq!(text("1.2.3.4"), event.lookup("remote_host").cloned());
+        assert_eq!(text("GET"), event.lookup("method").cloned());
+        assert_eq!(text("/index.html"), event.lookup("requested_uri").cloned());
+        assert_eq!(text("HTTP/1.1"), event.lookup("protocol").cloned());
+        assert_eq!(text("GET /index.html HTTP/1.1"), event.lookup("requested_url").cloned());
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(12)), event.lookup("content_length"));
+        assert_eq!(Some(&LogFieldValue::Int(930)), event.lookup("duration_us"));
+        assert_eq!(text("curl/7.64.1"), event.lookup("request_headers.user_agent").cloned());
+        assert_eq!(
+            text("https://example.com/"),
+            event.lookup("request_headers.referer").cloned()
+        );
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Text("text/html".to_string()),
+                LogFieldValue::Text("*/*".to_string()),
+            ])),
+            event.lookup("request_headers.accept")
+        );
+        assert_eq!(
+            text("text/html"),
+            event.lookup("response_headers.content_type").cloned()
+        );
+        assert_eq!(text(line), event.lookup("message").cloned());
+        assert_eq!(text("1"), event.lookup("@version").cloned());
+    }
+
+    #[test]
+    fn test_caddy_json_line_parser_unknown_keys() {
+        let line = r#"{"ts":1646861401,"logger":"http.log.access","request":{"remote_ip":"::1","remote_port":"55555","remote_addr":"[::1]:55555","host":"example.com","tls":{"resumed":false}},"status":"200","extra":null}"#;
+        let event = CaddyJsonLineParser::new().parse(line).unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("::1".to_string())),
+            event.lookup("remote_host")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("http.log.access".to_string())),
+            event.lookup("caddy.logger")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("example.com".to_string())),
+            event.lookup("caddy.request.host")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("55555".to_string())),
+            event.lookup("caddy.request.remote_port")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Bool(false)),
+            event.lookup("caddy.request.tls.resumed")
+        );
+        assert_eq!(None, event.lookup("caddy.request.remote_addr"));
+        assert_eq!(None, event.lookup("caddy.extra"));
+
+        // Values of an unexpected type are kept as they are rather than remapped
+        assert_eq!(None, event.lookup("status_code"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("200".to_string())),
+            event.lookup("caddy.status")
+        );
+    }
+
+    #[test]
+    fn test_caddy_json_line_parser_remote_addr() {
+        let parser = CaddyJsonLineParser::new();
+
+        for (addr, host) in &[
+            ("1.2.3.4:55555", "1.2.3.4"),
+            ("[::1]:55555", "::1"),
+            ("1.2.3.4", "1.2.3.4"),
+        ] {
+            let line = format!(r#"{{"request":{{"remote_addr":"{}"}}}}"#, addr);
+            let event = parser.parse(&line).unwrap();
+            assert_eq!(
+                Some(&LogFieldValue::Text(host.to_string())),
+                event.lookup("remote_host")
+            );
+            assert_eq!(None, event.lookup("caddy"));
+        }
+    }
+
+    #[test]
+    fn test_caddy_json_line_parser_invalid() {
+        let parser = CaddyJsonLineParser::new();
+
+        for line in &[
+            "not json",
+            r#"{"ts":1646861401.5,"status":200"#,
+            r#"[{"status":200}]"#,
+            r#"{"ts":"yesterday","status":200}"#,
+            r#"{"ts":-1,"status":200}"#,
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(*line, msg),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_epoch_timestamp() {
+        let ts = |v| epoch_timestamp(v).map(|t| t.to_rfc3339());
+
+        assert_eq!(Some("2006-09-08T04:22:04+00:00".to_owned()), ts("1157689324"));
+        assert_eq!(Some("2006-09-08T04:22:04.156+00:00".to_owned()), ts("1157689324.156"));
+        assert_eq!(
+            Some("2006-09-08T04:22:04.000000001+00:00".to_owned()),
+            ts("1157689324.000000001")
+        );
+        assert_eq!(Some("2006-09-08T04:22:04+00:00".to_owned()), ts("1157689324."));
+
+        for bad in &[
+            "",
+            ".5",
+            "-1",
+            "1e9",
+            "1157689324.0000000001",
+            "1157689324.1.2",
+            "99999999999999999999",
+        ] {
+            assert_eq!(None, ts(bad), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_w3c_log_line_parser() {
+        let parser = W3cLogLineParser::new();
+        assert!(parser.directive("#Software: Microsoft Internet Information Services 10.0"));
+        assert!(parser.directive("#Fields: date time s-ip cs-method cs-uri-stem cs-uri-query s-port cs-username c-ip cs(User-Agent) cs(Referer) sc-status sc-substatus sc-win32-status time-taken"));
+
+        let event = parser
+            .parse("2021-07-04 12:00:00 10.0.0.1 GET /default.htm q=1 443 - 192.0.2.1 Mozilla/5.0+(Windows+NT+10.0) - 200 0 0 15")
+            .unwrap();
+        let fields = event.fields();
+
+        match fields.get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2021-07-04T12:00:00+00:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+        assert_eq!(
+            Some(&LogFieldValue::Text("10.0.0.1".to_owned())),
+            fields.get("local_addr")
+        );
+        assert_eq!(Some(&LogFieldValue::Text("GET".to_owned())), fields.get("method"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("/default.htm".to_owned())),
+            fields.get("requested_uri")
+        );
+        assert_eq!(Some(&LogFieldValue::Text("q=1".to_owned())), fields.get("query_string"));
+        assert_eq!(Some(&LogFieldValue::Int(443)), fields.get("server_port"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("192.0.2.1".to_owned())),
+            fields.get("remote_host")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(200)), fields.get("status_code"));
+        assert_eq!(Some(&LogFieldValue::Text("0".to_owned())), fields.get("sc_substatus"));
+        assert_eq!(Some(&LogFieldValue::Int(15_000)), fields.get("duration_us"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("Mozilla/5.0+(Windows+NT+10.0)".to_owned())),
+            event.lookup("request_headers.user_agent")
+        );
+        assert!(event.lookup("request_headers.referer").is_none());
+        assert!(!fields.contains_key("remote_user"));
+        assert_eq!(Some(&LogFieldValue::Text("1".to_owned())), fields.get("@version"));
+    }
+
+    #[test]
+    fn test_w3c_log_line_parser_fields_change() {
+        let parser = W3cLogLineParser::new();
+        assert!(parser.is_stateful());
+        assert!(!CommonLogLineParser::new().is_stateful());
+
+        // Lines aren't valid until the columns are known
+        assert!(matches!(parser.parse("200 /"), Err(RedeyeError::ParseError(_))));
+
+        parser.directive("#Fields: sc-status cs-uri-stem");
+        assert_eq!(
+            Some(&LogFieldValue::Int(200)),
+            parser.parse("200 /").unwrap().lookup("status_code")
+        );
+
+        // Parsing a directive instead of handling it still changes the columns
+        assert!(matches!(
+            parser.parse("#Fields: cs-uri-stem sc-status sc(Content-Type)"),
+            Err(RedeyeError::ParseError(_))
+        ));
+        let event = parser.parse("/ 404 \"text/html; charset=utf-8\"").unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(404)), event.lookup("status_code"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("text/html; charset=utf-8".to_owned())),
+            event.lookup("response_headers.content_type")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("text/html; charset=utf-8".to_owned())),
+            event.lookup("mime_type")
+        );
+
+        // Other directives are handled but don't change anything
+        assert!(parser.directive("#Date: 2021-07-04 12:00:00"));
+        assert!(parser.parse("/ 304 -").is_ok());
+        assert!(!parser.directive("/ 304 -"));
+
+        // A clone keeps the columns but changes to one don't affect the other
+        let clone = parser.clone();
+        clone.directive("#Fields: sc-status");
+        assert!(parser.parse("/ 304 -").is_ok());
+        assert!(clone.parse("304").is_ok());
+    }
+
+    #[test]
+    fn test_forensic_log_parser() {
+        let parser = ForensicLogParser::new();
+        let start = concat!(
+            "+yQtJf8CoAB4AAFNXBIEAAAAA|GET /manual/de/images/down.gif HTTP/1.1|Host:localhost%3a8080",
+            "|User-Agent:Mozilla/5.0 (X11; U; Linux i686; en-US; rv%3a1.6) Gecko/20040216 Firefox/0.8",
+            "|X-Custom:a%7cb"
+        );
+
+        assert!(parser.directive(start));
+        assert!(!parser.directive("-yQtJf8CoAB4AAFNXBIEAAAAA"));
+
+        let event = parser.parse("-yQtJf8CoAB4AAFNXBIEAAAAA").unwrap();
+        assert_eq!(Some(&LogFieldValue::Bool(true)), event.lookup("completed"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("yQtJf8CoAB4AAFNXBIEAAAAA".to_string())),
+            event.lookup("forensic_id")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text(
+                "GET /manual/de/images/down.gif HTTP/1.1".to_string()
+            )),
+            event.lookup("requested_url")
+        );
+        assert_eq!(Some(&LogFieldValue::Text("GET".to_string())), event.lookup("method"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("/manual/de/images/down.gif".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("HTTP/1.1".to_string())),
+            event.lookup("protocol")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("localhost:8080".to_string())),
+            event.lookup("request_headers.host")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text(
+                "Mozilla/5.0 (X11; U; Linux i686; en-US; rv:1.6) Gecko/20040216 Firefox/0.8".to_string()
+            )),
+            event.lookup("request_headers.user_agent")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("a|b".to_string())),
+            event.lookup("request_headers.x_custom")
+        );
+        assert_eq!(Some(&LogFieldValue::Text(start.to_string())), event.lookup("message"));
+        assert!(matches!(event.lookup("@timestamp"), Some(LogFieldValue::Timestamp(_))));
+
+        // Each start record is only completed once.
+        assert!(matches!(
+            parser.parse("-yQtJf8CoAB4AAFNXBIEAAAAA"),
+            Err(RedeyeError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_forensic_log_parser_interleaved() {
+        let parser = ForensicLogParser::new();
+
+        assert!(parser.directive("+a|GET /a HTTP/1.1"));
+        assert!(parser.directive("+b|GET /b HTTP/1.1"));
+
+        let event = parser.parse("-b").unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("/b".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(None, event.lookup("request_headers"));
+
+        let event = parser.parse("-a").unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("/a".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert!(parser.flush().is_empty());
+    }
+
+    #[test]
+    fn test_forensic_log_parser_max_pending() {
+        let parser = ForensicLogParser::new().max_pending(2);
+
+        assert!(parser.directive("+a|GET /a HTTP/1.1"));
+        assert!(parser.directive("+b|GET /b HTTP/1.1"));
+        assert!(parser.take_incomplete().is_empty());
+        assert!(parser.directive("+c|GET /c HTTP/1.1"));
+
+        let incomplete = parser.take_incomplete();
+        assert_eq!(1, incomplete.len());
+        assert_eq!(
+            Some(&LogFieldValue::Text("a".to_string())),
+            incomplete[0].lookup("forensic_id")
+        );
+        assert_eq!(Some(&LogFieldValue::Bool(false)), incomplete[0].lookup("completed"));
+        assert!(parser.parse("-a").is_err());
+
+        assert!(parser.parse("-c").is_ok());
+        assert!(parser.directive("+d|GET /d HTTP/1.1"));
+        assert!(parser.directive("+e|GET /e HTTP/1.1"));
+
+        let ids: Vec<_> = parser
+            .flush()
+            .iter()
+            .map(|e| e.lookup("forensic_id").cloned())
+            .collect();
+        assert_eq!(
+            vec![
+                Some(LogFieldValue::Text("b".to_string())),
+                Some(LogFieldValue::Text("d".to_string())),
+                Some(LogFieldValue::Text("e".to_string())),
+            ],
+            ids
+        );
+        assert!(parser.flush().is_empty());
+    }
+
+    #[test]
+    fn test_forensic_log_parser_invalid() {
+        let parser = ForensicLogParser::new();
+
+        for line in &["", "+", "+abc", "+|GET / HTTP/1.1", "+abc|GET / HTTP/1.1|NoColon"] {
+            assert!(!parser.directive(line), "{}", line);
+        }
+
+        assert!(parser.directive("+abc|GET / HTTP/1.1"));
+        for line in &["", "-", "abc", "-abc def", "-missing", "+abc|GET / HTTP/1.1"] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result for {:?}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_w3c_log_line_parser_invalid() {
+        let parser = W3cLogLineParser::new().time_taken_unit(DurationUnit::Seconds);
+        parser.directive("#Fields: date time sc-status time-taken");
+
+        assert!(parser.parse("2021-07-04 12:00:00 200 0.5").is_ok());
+        for line in &[
+            "2021-07-04 12:00:00 200",
+            "2021-07-04 12:00:00 200 0.5 extra",
+            "2021-07-04 12:00:00 OK 0.5",
+            "2021-07-04 12:00:00 200 slow",
+            "",
+        ] {
+            assert!(
+                matches!(parser.parse(line), Err(RedeyeError::ParseError(_))),
+                "{}",
+                line
+            );
+        }
+
+        assert!(matches!(
+            parser.parse("2021-07-04 noon 200 0.5"),
+            Err(RedeyeError::TimestampParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_split_w3c() {
+        assert_eq!(vec!["a", "b c", "", "d"], split_w3c(" a \"b c\" \"\"  d "));
+        assert_eq!(vec!["a", "b"], split_w3c("a \"b"));
+        assert!(split_w3c("").is_empty());
+    }
+
+    #[test]
+    fn test_vhost_combined_log_line_parser_invalid() {
+        let parser = VhostCombinedLogLineParser::new();
+        let rest = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326 \"-\" \"-\"";
+
+        for line in &[
+            format!("www.example.com:http {}", rest),
+            format!("[::1 {}", rest),
+            format!("[::1]80 {}", rest),
+            rest.to_owned(),
+            "www.example.com:80".to_owned(),
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(line.as_str(), msg),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_combined_io_log_line_parser() {
+        let parser = CombinedIoLogLineParser::new();
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"HEAD / HTTP/1.1\" 200 - \"-\" \"curl/7.64.1\" 530 0")
+            .unwrap();
+
+        assert_eq!(None, event.lookup("content_length"));
+        assert_eq!(Some(&LogFieldValue::Int(530)), event.lookup("bytes_received"));
+        assert_eq!(Some(&LogFieldValue::Int(0)), event.lookup("bytes_sent"));
+    }
+
+    #[test]
+    fn test_combined_io_log_line_parser_without_logio() {
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"";
+        let io = CombinedIoLogLineParser::new().parse(line).unwrap();
+        let combined = CombinedLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(combined.fields(), io.fields());
+    }
+
+    #[test]
+    fn test_combined_io_log_line_parser_invalid() {
+        let parser = CombinedIoLogLineParser::new();
+        let prefix = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"";
+
+        for rest in &["530", "530 -", "- 982", "530 982 17"] {
+            let line = format!("{} {}", prefix, rest);
+            match parser.parse(&line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(line, msg),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_jetty_log_line_parser_without_latency() {
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"";
+        let jetty = JettyLogLineParser::new().parse(line).unwrap();
+        let combined = CombinedLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(combined.fields(), jetty.fields());
+    }
+
+    #[test]
+    fn test_jetty_log_line_parser_latency() {
+        let parser = JettyLogLineParser::new();
+
+        for line in &[
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 17",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" - 17",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" \"JSESSIONID=abc; theme=dark\" 17",
+        ] {
+            let event = parser.parse(line).unwrap();
+            assert_eq!(Some(&LogFieldValue::Int(17)), event.lookup("duration_ms"), "{}", line);
+            assert_eq!(
+                Some(&LogFieldValue::Text("curl/7.64.1".to_string())),
+                event.lookup("request_headers.user_agent")
+            );
+        }
+    }
+
+    #[test]
+    fn test_jetty_log_line_parser_authority() {
+        let parser = JettyLogLineParser::new();
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"CONNECT example.com:443 HTTP/1.1\" 200 0 \"-\" \"curl/7.64.1\" 250")
+            .unwrap();
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("CONNECT".to_string())),
+            event.lookup("method")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("example.com:443".to_string())),
+            event.lookup("requested_uri")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(250)), event.lookup("duration_ms"));
+    }
+
+    #[test]
+    fn test_jetty_log_line_parser_invalid() {
+        let parser = JettyLogLineParser::new();
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 0.017";
+
+        match parser.parse(line) {
+            Err(RedeyeError::ParseError(msg)) => assert_eq!(line, msg),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_ingress_nginx_log_line_parser() {
+        let line = concat!(
+            "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"POST /api HTTP/2.0\" 200 17 \"-\" \"curl/7.64.1\" 412 0.052 ",
+            "[prod-api-8080] [prod-api-canary-8080] 10.244.0.7:8080 17 0.052 200 5f9c1ae8b4d1c2a3e4f5a6b7c8d9e0f1",
+        );
+        let event = IngressNginxLogLineParser::new().parse(line).unwrap();
+        let text = |v: &str| Some(LogFieldValue::Text(v.to_string()));
+
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(text("curl/7.64.1"), event.lookup("request_headers.user_agent").cloned());
+        assert_eq!(Some(&LogFieldValue::Int(412)), event.lookup("request_length"));
+        assert_eq!(Some(&LogFieldValue::Float(0.052)), event.lookup("request_time"));
+        assert_eq!(text("prod-api-8080"), event.lookup("proxy_upstream_name").cloned());
+        assert_eq!(
+            text("prod-api-canary-8080"),
+            event.lookup("proxy_alternative_upstream_name").cloned()
+        );
+        assert_eq!(text("10.244.0.7:8080"), event.lookup("upstream.addr").cloned());
+        assert_eq!(Some(&LogFieldValue::Int(17)), event.lookup("upstream.response_length"));
+        assert_eq!(
+            Some(&LogFieldValue::Float(0.052)),
+            event.lookup("upstream.response_time")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("upstream.status"));
+        assert_eq!(
+            text("5f9c1ae8b4d1c2a3e4f5a6b7c8d9e0f1"),
+            event.lookup("request_id").cloned()
+        );
+    }
+
+    #[test]
+    fn test_ingress_nginx_log_line_parser_retries() {
+        let line = concat!(
+            "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 78 1.004 ",
+            "[default-web-80] [] 10.244.0.7:8080, 10.244.0.8:8080 0, 612 1.000, 0.004 504, 200 abc123",
+        );
+        let event = IngressNginxLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(None, event.lookup("proxy_alternative_upstream_name"));
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Text("10.244.0.7:8080".to_string()),
+                LogFieldValue::Text("10.244.0.8:8080".to_string()),
+            ])),
+            event.lookup("upstream.addr")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Int(0),
+                LogFieldValue::Int(612)
+            ])),
+            event.lookup("upstream.response_length")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::List(vec![
+                LogFieldValue::Int(504),
+                LogFieldValue::Int(200)
+            ])),
+            event.lookup("upstream.status")
+        );
+    }
+
+    #[test]
+    fn test_ingress_nginx_log_line_parser_no_upstream() {
+        let line = concat!(
+            "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /missing HTTP/1.1\" 404 146 \"-\" \"curl/7.64.1\" 78 0.000 ",
+            "[] [] - - - - abc123",
+        );
+        let event = IngressNginxLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(None, event.lookup("proxy_upstream_name"));
+        assert_eq!(None, event.lookup("upstream"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("abc123".to_string())),
+            event.lookup("request_id")
+        );
+    }
+
+    #[test]
+    fn test_ingress_nginx_log_line_parser_invalid() {
+        let parser = IngressNginxLogLineParser::new();
+        let prefix = "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\"";
+
+        for rest in &[
+            "",
+            "78 0.003",
+            "78 0.003 default-web-80 [] 10.244.0.7:8080 612 0.003 200 abc123",
+            "78 0.003 [default-web-80] [] 10.244.0.7:8080 612 0.003 OK abc123",
+            "78 0.003 [default-web-80] [] 10.244.0.7:8080 612 0.003 200",
+        ] {
+            let line = format!("{} {}", prefix, rest);
+            match parser.parse(&line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(line.trim(), msg),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_traefik_log_line_parser() {
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 412 \"websecure-router@docker\" \"http://10.0.0.3:8080\" 12ms";
+        let event = TraefikLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("curl/7.64.1".to_string())),
+            event.lookup("request_headers.user_agent")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(412)), event.lookup("request_count"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("websecure-router@docker".to_string())),
+            event.lookup("router_name")
+        );
+        assert_eq!(
+            Some(&LogFieldValue::Text("http://10.0.0.3:8080".to_string())),
+            event.lookup("backend_url")
+        );
+        assert_eq!(Some(&LogFieldValue::Float(12.0)), event.lookup("duration_ms"));
+    }
+
+    #[test]
+    fn test_traefik_log_line_parser_no_router() {
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /missing HTTP/1.1\" 404 19 \"-\" \"curl/7.64.1\" 7 \"-\" \"-\" 0ms";
+        let event = TraefikLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(None, event.lookup("router_name"));
+        assert_eq!(None, event.lookup("backend_url"));
+        assert_eq!(Some(&LogFieldValue::Float(0.0)), event.lookup("duration_ms"));
+    }
+
+    #[test]
+    fn test_traefik_log_line_parser_invalid() {
+        let parser = TraefikLogLineParser::new();
+
+        for line in &[
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 412 \"router\" \"http://10.0.0.3:8080\" 12",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 412 \"router\" \"http://10.0.0.3:8080\" 12days",
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(*line, msg),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_go_duration_millis() {
+        assert_eq!(Some(12.0), go_duration_millis("12ms"));
+        assert_eq!(Some(0.85), go_duration_millis("850µs"));
+        assert_eq!(Some(0.85), go_duration_millis("850us"));
+        assert_eq!(Some(1_500.0), go_duration_millis("1.5s"));
+        assert_eq!(Some(62_500.0), go_duration_millis("1m2.5s"));
+        assert_eq!(Some(0.0), go_duration_millis("0s"));
+
+        for bad in &["", "12", "ms", "1.2.3s", "12days", "-5ms"] {
+            assert_eq!(None, go_duration_millis(bad), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_lighttpd_log_line_parser_default() {
+        let parser = LighttpdLogLineParser::new();
+        let event = parser
+            .parse("127.0.0.1 www.example.com frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.1\" 200 2326 \"http://example.com/\" \"curl/7.64.1\"")
+            .unwrap();
+        let text = |v: &str| Some(LogFieldValue::Text(v.to_string()));
+
+        assert_eq!(text("127.0.0.1"), event.lookup("remote_host").cloned());
+        assert_eq!(text("www.example.com"), event.lookup("server_name").cloned());
+        assert_eq!(None, event.lookup("ident"));
+        assert_eq!(text("frank"), event.lookup("remote_user").cloned());
+        assert_eq!(text("/index.html"), event.lookup("requested_uri").cloned());
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(
+            text("http://example.com/"),
+            event.lookup("request_headers.referer").cloned()
+        );
+        assert_eq!(None, event.lookup("duration_s"));
+    }
+
+    #[test]
+    fn test_lighttpd_log_line_parser_prefixed() {
+        let parser = LighttpdLogLineParser::new();
+        let rest = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
+        let event = parser.parse(&format!("www.example.com {} 2", rest)).unwrap();
+        let common = CommonLogLineParser::new().parse(rest).unwrap();
+
+        for name in &[
+            "remote_host",
+            "remote_user",
+            "@timestamp",
+            "requested_url",
+            "status_code",
+        ] {
+            assert_eq!(common.lookup(name), event.lookup(name), "{}", name);
+        }
+
+        assert_eq!(
+            Some(&LogFieldValue::Text("www.example.com".to_string())),
+            event.lookup("server_name")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(2)), event.lookup("duration_s"));
+    }
+
+    #[test]
+    fn test_lighttpd_log_line_parser_no_request() {
+        let parser = LighttpdLogLineParser::new();
+        let line = "127.0.0.1 www.example.com - [10/Oct/2000:13:55:36 -0700] \"-\" 400 0 \"-\" \"-\" 0";
+        let event = parser.parse(line).unwrap();
+
+        for name in &[
+            "requested_url",
+            "method",
+            "requested_uri",
+            "protocol",
+            "request_headers",
+        ] {
+            assert_eq!(None, event.lookup(name), "{}", name);
+        }
+
+        assert_eq!(Some(&LogFieldValue::Int(400)), event.lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Int(0)), event.lookup("duration_s"));
+        assert_eq!(Some(&LogFieldValue::Text(line.to_string())), event.lookup("message"));
+    }
+
+    #[test]
+    fn test_lighttpd_log_line_parser_invalid() {
+        let parser = LighttpdLogLineParser::new();
+
+        for line in &[
+            "127.0.0.1 - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326",
+            "127.0.0.1 www.example.com - [10/Oct/2000:13:55:36 -0700] \"GET /\" 200 2326",
+            "127.0.0.1 www.example.com - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 slow",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(msg)) => assert_eq!(*line, msg),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tomcat_log_line_parser_common() {
+        let line = "127.0.0.1 - frank [11/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
+        let tomcat = TomcatLogLineParser::new().parse(line).unwrap();
+        let common = CommonLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(common.fields(), tomcat.fields());
+    }
+
+    #[test]
+    fn test_tomcat_log_line_parser_combined_duration() {
+        let parser = TomcatLogLineParser::new();
+        let event = parser
+            .parse("127.0.0.1 - - [11/Oct/2000:13:55:36 Z] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 1534")
+            .unwrap();
+
+        let ts = FixedOffset::east(0).ymd(2000, 10, 11).and_hms(13, 55, 36);
+        assert_eq!(Some(&LogFieldValue::Timestamp(ts)), event.lookup("@timestamp"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("curl/7.64.1".to_string())),
+            event.lookup("request_headers.user_agent")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(1534)), event.lookup("duration_ms"));
+        assert_eq!(None, event.lookup("duration_us"));
+    }
+
+    #[test]
+    fn test_tomcat_log_line_parser_aborted() {
+        let parser = TomcatLogLineParser::new();
+        let event = parser
+            .parse("127.0.0.1 - - [11/Oct/2000:13:55:36 +0000] \"POST /upload HTTP/1.1\" - - 30012")
+            .unwrap();
+
+        assert_eq!(None, event.lookup("status_code"));
+        assert_eq!(None, event.lookup("content_length"));
+        assert_eq!(Some(&LogFieldValue::Int(30012)), event.lookup("duration_ms"));
+    }
+
+    #[test]
+    fn test_tomcat_log_line_parser_thread_name() {
+        let parser = TomcatLogLineParser::new().thread_name(true);
+
+        let event = parser
+            .parse("127.0.0.1 - - [11/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 12 http-nio-8080-exec-3")
+            .unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(12)), event.lookup("duration_ms"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("http-nio-8080-exec-3".to_string())),
+            event.lookup("thread_name")
+        );
+
+        let event = parser
+            .parse("127.0.0.1 - - [11/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 http-nio-8080-exec-3")
+            .unwrap();
+        assert_eq!(None, event.lookup("duration_ms"));
+        assert_eq!(
+            Some(&LogFieldValue::Text("http-nio-8080-exec-3".to_string())),
+            event.lookup("thread_name")
+        );
+    }
+
+    #[test]
+    fn test_tomcat_log_line_parser_invalid() {
+        let parser = TomcatLogLineParser::new();
+
+        for line in &[
+            "127.0.0.1 - - [11/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 http-nio-8080-exec-3",
+            "127.0.0.1 - - [11/Oct/2000:13:55:36 +0000] GET / HTTP/1.1 200 2326",
+            "127.0.0.1 - - [11/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" OK 2326",
+        ] {
+            match parser.parse(line) {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result for {}: {:?}", line, v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_combined_log_line_parser_response_headers_empty() {
+        let parser = CombinedLogLineParser::new().response_headers(&["Content-Type"]);
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 304 - \"-\" \"curl/7.64.1\" \"-\"";
+
+        let event = parser.parse(line).unwrap();
+        assert!(!event.fields().contains_key("response_headers"));
+        assert!(!event.fields().contains_key("mime_type"));
+
+        // Lines without the response headers don't match
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 304 - \"-\" \"curl/7.64.1\"";
+        assert!(matches!(parser.parse(line), Err(RedeyeError::ParseError(_))));
+        assert!(CombinedLogLineParser::new().parse(line).is_ok());
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_new() {
+        let parser = CustomLogLineParser::new("%h %l %u %t \"%r\" %>s %b %D").unwrap();
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /index.html?a=b HTTP/1.0\" 200 - 1534")
+            .unwrap();
+        let fields = event.fields();
+
+        assert_eq!(&LogFieldValue::Int(1534), fields.get("duration_us").unwrap());
+        assert_eq!(&LogFieldValue::Text("GET".to_owned()), fields.get("method").unwrap());
+        assert!(!fields.contains_key("ident"));
+        assert!(!fields.contains_key("content_length"));
+        assert!(fields.contains_key("@version"));
+        assert!(fields.contains_key("message"));
+
+        let parser = CustomLogLineParser::new("%v:%p %m %U%q %H %s %T").unwrap();
+        let event = parser
+            .parse("www.example.com:443 GET /search?q=x HTTP/1.1 404 2")
+            .unwrap();
+        let fields = event.fields();
+
+        assert_eq!(
+            &LogFieldValue::Text("www.example.com".to_owned()),
+            fields.get("server_name").unwrap()
+        );
+        assert_eq!(&LogFieldValue::Int(443), fields.get("server_port").unwrap());
+        assert_eq!(&LogFieldValue::Int(2_000_000), fields.get("duration_us").unwrap());
+        assert_eq!(&LogFieldValue::Int(404), fields.get("status_code").unwrap());
+
+        match CustomLogLineParser::new("%h %Z") {
+            Err(RedeyeError::ConfigError(_)) => (),
+            v => panic!("Unexpected result: {:?}", v.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_nginx() {
+        let format = LogFormat::nginx("$remote_addr [$time_iso8601] \"$request\" $status $request_time").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+
+        let event = parser
+            .parse("10.0.0.1 [2000-10-10T13:55:36+00:00] \"GET / HTTP/1.1\" 200 0.042")
+            .unwrap();
+        let fields = event.fields();
+        assert_eq!(&LogFieldValue::Float(0.042), fields.get("request_time").unwrap());
+        assert!(fields.contains_key("@timestamp"));
+
+        let event = parser
+            .parse("10.0.0.1 [2000-10-10T13:55:36+00:00] \"GET / HTTP/1.1\" 200 -")
+            .unwrap();
+        assert!(!event.fields().contains_key("request_time"));
+
+        match parser.parse("10.0.0.1 [2000-10-10T13:55:36+00:00] \"GET / HTTP/1.1\" 200 fast") {
+            Err(RedeyeError::ParseError(_)) => (),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_duration() {
+        let format = LogFormat::apache("%h %{ms}T").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+        let event = parser.parse("127.0.0.1 42").unwrap();
+
+        assert_eq!(&LogFieldValue::Int(42_000), event.fields().get("duration_us").unwrap());
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_path_and_query_string() {
+        let format = LogFormat::apache("%h %U%q").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+
+        let event = parser.parse("127.0.0.1 /search?q=redeye").unwrap();
+        let fields = event.fields();
+        assert_eq!(
+            &LogFieldValue::Text("/search".to_owned()),
+            fields.get("uri_path").unwrap()
+        );
+        assert_eq!(
+            &LogFieldValue::Text("?q=redeye".to_owned()),
+            fields.get("query_string").unwrap()
+        );
+
+        let event = parser.parse("127.0.0.1 /search").unwrap();
+        let fields = event.fields();
+        assert_eq!(
+            &LogFieldValue::Text("/search".to_owned()),
+            fields.get("uri_path").unwrap()
+        );
+        assert!(fields.get("query_string").is_none());
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_connection_status() {
+        let format = LogFormat::apache("%h %X %k").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
 
-    if let Some(v) = field_match {
-        let val = v
-            .parse::<u64>()
-            .map_err(|_| RedeyeError::ParseError(line.to_string()))?;
-        Ok(Some(LogFieldValue::Int(val)))
-    } else {
-        Ok(None)
+        let expected = [("X", "aborted"), ("+", "keepalive"), ("-", "closed"), ("?", "?")];
+        for (status, name) in expected.iter() {
+            let event = parser.parse(&format!("127.0.0.1 {} 3", status)).unwrap();
+            let fields = event.fields();
+
+            assert_eq!(
+                &LogFieldValue::Text((*name).to_owned()),
+                fields.get("connection_status").unwrap()
+            );
+            assert_eq!(&LogFieldValue::Int(3), fields.get("keepalive_count").unwrap());
+        }
     }
-}
 
-/// Convert the "-" character that represents empty fields
-fn empty_field(val: &str) -> Option<&str> {
-    if val == "-" {
-        None
-    } else {
-        Some(val)
+    #[test]
+    fn test_custom_log_line_parser_assumed_zone() {
+        let format = LogFormat::apache("%h [%{%Y-%m-%d %H:%M:%S}t] %>s").unwrap();
+        let line = "127.0.0.1 [2021-03-14 02:30:00] 200";
+
+        let parser = CustomLogLineParser::from_format(format.clone());
+        assert!(matches!(parser.parse(line), Err(RedeyeError::TimestampParseError(_))));
+
+        let context = TimestampContext::new().assume_zone("America/New_York".parse().unwrap());
+        let parser = CustomLogLineParser::from_format(format).timestamp_context(context);
+        match parser.parse(line).unwrap().fields().get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2021-03-14T03:30:00-04:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn test_named_format_parser_with_context() {
+        // Timestamps in the common format always have an offset, which is kept.
+        let context = TimestampContext::new().assume_zone("UTC".parse().unwrap());
+        let parser = NamedFormat::Common.parser_with_context(context);
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326")
+            .unwrap();
 
-    use super::{
-        parse_int_value, parse_text_value, parse_timestamp, CommonLogLineParser, LogLineParser, ParserImpl,
-        COMMON_LOG_TIMESTAMP,
-    };
-    use crate::types::{LogFieldValue, RedeyeError};
-    use chrono::{Datelike, FixedOffset, Timelike, Utc};
-    use regex::{Captures, Regex};
+        match event.fields().get("@timestamp") {
+            Some(LogFieldValue::Timestamp(ts)) => assert_eq!("2000-10-10T13:55:36-07:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
 
     #[test]
-    fn test_common_log_line_parser() {
-        let line = "127.0.0.1 - frank [11/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
-        let offset = FixedOffset::west(7 * 3600);
-        let ts = Utc::now()
-            .with_timezone(&offset)
-            .with_year(2000)
-            .unwrap()
-            .with_month(10)
-            .unwrap()
-            .with_day(11)
-            .unwrap()
-            .with_hour(13)
-            .unwrap()
-            .with_minute(55)
-            .unwrap()
-            .with_second(36)
-            .unwrap()
-            .with_nanosecond(0)
+    fn test_custom_log_line_parser_request_headers() {
+        let format = LogFormat::apache("%h \"%{User-Agent}i\" %{X-Request-Id}i %{Accept}i \"%{Referer}i\"").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+        let event = parser
+            .parse("127.0.0.1 \"Mozilla/4.08 [en] (Win98; I ;Nav)\" 7f3a9c - \"http://example.com/caf\\xc3\\xa9\"")
             .unwrap();
 
-        let parser = CommonLogLineParser::new();
-        let event = parser.parse(line).unwrap();
-        let fields = event.fields();
+        let mut expected = HashMap::new();
+        expected.insert(
+            "user_agent".to_owned(),
+            LogFieldValue::Text("Mozilla/4.08 [en] (Win98; I ;Nav)".to_owned()),
+        );
+        expected.insert("x_request_id".to_owned(), LogFieldValue::Text("7f3a9c".to_owned()));
+        expected.insert(
+            "referer".to_owned(),
+            LogFieldValue::Text("http://example.com/caf\u{e9}".to_owned()),
+        );
 
         assert_eq!(
-            &LogFieldValue::Text("127.0.0.1".to_owned()),
-            fields.get("remote_host").unwrap()
+            Some(&LogFieldValue::Mapping(expected)),
+            event.fields().get("request_headers")
         );
-        assert!(!fields.contains_key("ident"));
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_response_headers() {
+        let format = LogFormat::apache("%h \"%{Link}i\" \"%{Link}o\" %{X-Cache}o").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+        let event = parser
+            .parse("127.0.0.1 \"<https://example.com/>\" \"<style.css>; title=\\\"main \\\\ css\\\"\" -")
+            .unwrap();
+
         assert_eq!(
-            &LogFieldValue::Text("frank".to_owned()),
-            fields.get("remote_user").unwrap()
+            Some(&LogFieldValue::Text("<https://example.com/>".to_string())),
+            event.lookup("request_headers.link")
         );
-        assert_eq!(&LogFieldValue::Timestamp(ts), fields.get("@timestamp").unwrap());
         assert_eq!(
-            &LogFieldValue::Text("GET /index.html HTTP/1.0".to_owned()),
-            fields.get("requested_url").unwrap()
+            Some(&LogFieldValue::Text("<style.css>; title=\"main \\ css\"".to_string())),
+            event.lookup("response_headers.link")
         );
-        assert_eq!(&LogFieldValue::Text("GET".to_owned()), fields.get("method").unwrap());
+        assert_eq!(None, event.lookup("response_headers.x_cache"));
+        assert_eq!(None, event.lookup("request_headers.x_cache"));
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_cookies() {
+        let format = LogFormat::apache("%h %{sessionID}C %{ab_test}C %{theme}C").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+        let event = parser.parse("127.0.0.1 a1b2%3D%3D=x== B -").unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert("sessionID".to_owned(), LogFieldValue::Text("a1b2%3D%3D=x==".to_owned()));
+        expected.insert("ab_test".to_owned(), LogFieldValue::Text("B".to_owned()));
+
+        assert_eq!(Some(&LogFieldValue::Mapping(expected)), event.fields().get("cookies"));
+
+        let event = parser.parse("127.0.0.1 - - -").unwrap();
+        assert!(!event.fields().contains_key("cookies"));
+    }
+
+    #[test]
+    fn test_custom_log_line_parser_empty_mapping() {
+        let format = LogFormat::apache("%h \"%{Referer}i\" \"%{User-agent}i\"").unwrap();
+        let parser = CustomLogLineParser::from_format(format);
+        let event = parser.parse("127.0.0.1 \"-\" \"-\"").unwrap();
+
+        assert!(!event.fields().contains_key("request_headers"));
+    }
+
+    #[test]
+    fn test_varnish_log_line_parser_default() {
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"";
+        let varnish = VarnishLogLineParser::default().parse(line).unwrap();
+        let combined = CombinedLogLineParser::new().parse(line).unwrap();
+
+        assert_eq!(combined.fields(), varnish.fields());
+    }
+
+    #[test]
+    fn test_varnish_log_line_parser_extensions() {
+        let parser = VarnishLogLineParser::new(
+            "%h %l %u %t \"%r\" %s %b \"%{Referer}i\" \"%{User-agent}i\" %{Varnish:handling}x %{Varnish:hitmiss}x %{Varnish:time_firstbyte}x %{Varnish:vxid}x",
+        )
+        .unwrap();
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" pass miss 0.000293 32770")
+            .unwrap();
+
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
         assert_eq!(
-            &LogFieldValue::Text("/index.html".to_owned()),
-            fields.get("requested_uri").unwrap()
+            Some(&LogFieldValue::Text("pass".to_string())),
+            event.lookup("varnish.handling")
         );
         assert_eq!(
-            &LogFieldValue::Text("HTTP/1.0".to_owned()),
-            fields.get("protocol").unwrap()
+            Some(&LogFieldValue::Text("miss".to_string())),
+            event.lookup("varnish.hitmiss")
         );
-        assert_eq!(&LogFieldValue::Int(200), fields.get("status_code").unwrap());
-        assert_eq!(&LogFieldValue::Int(2326), fields.get("content_length").unwrap());
-        assert_eq!(&LogFieldValue::Text("1".to_owned()), fields.get("@version").unwrap());
-        assert_eq!(&LogFieldValue::Text(line.to_owned()), fields.get("message").unwrap());
+        assert_eq!(
+            Some(&LogFieldValue::Float(0.000293)),
+            event.lookup("varnish.time_firstbyte")
+        );
+        assert_eq!(Some(&LogFieldValue::Int(32770)), event.lookup("varnish.vxid"));
     }
 
     #[test]
-    fn test_combined_log_line_parser() {}
+    fn test_varnish_log_line_parser_fewer_columns() {
+        let parser = VarnishLogLineParser::new("%h %l %u %t \"%r\" %s %b %{Varnish:handling}x").unwrap();
+        let res = parser.parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326");
+
+        match res {
+            Err(RedeyeError::ParseError(_)) => (),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_varnish_log_line_parser_unknown_extension() {
+        let res = VarnishLogLineParser::new("%h %{Varnish:bogus}x");
+        assert!(res.unwrap_err().is_config_error());
+    }
 
     #[test]
     fn test_parser_impl_no_match() {
@@ -783,7 +6663,7 @@ mod tests {
         }
     }
 
-    fn single_val_capture(line: &str) -> Captures {
+    fn single_val_capture(line: &str) -> Captures<'_> {
         let r = Regex::new(r"^(.+)$").unwrap();
         r.captures(line).unwrap()
     }
@@ -792,7 +6672,13 @@ mod tests {
     fn test_parse_timestamp_missing() {
         let line = "127.0.0.1";
         let c = single_val_capture(line);
-        let res = parse_timestamp(&c, 2 /* shouldn't exist */, line, COMMON_LOG_TIMESTAMP);
+        let res = parse_timestamp(
+            &c,
+            2, /* shouldn't exist */
+            line,
+            COMMON_LOG_TIMESTAMP,
+            &TimestampContext::new(),
+        );
 
         match res {
             Err(RedeyeError::ParseError(_)) => (),
@@ -804,7 +6690,7 @@ mod tests {
     fn test_parse_timestamp_empty_field() {
         let line = "-";
         let c = single_val_capture(line);
-        let res = parse_timestamp(&c, 1, line, COMMON_LOG_TIMESTAMP);
+        let res = parse_timestamp(&c, 1, line, COMMON_LOG_TIMESTAMP, &TimestampContext::new());
 
         match res {
             Ok(None) => (),
@@ -816,7 +6702,7 @@ mod tests {
     fn test_parse_timestamp_bad_format() {
         let line = "asdf";
         let c = single_val_capture(line);
-        let res = parse_timestamp(&c, 1, line, COMMON_LOG_TIMESTAMP);
+        let res = parse_timestamp(&c, 1, line, COMMON_LOG_TIMESTAMP, &TimestampContext::new());
 
         match res {
             Err(RedeyeError::TimestampParseError(_)) => (),
@@ -828,7 +6714,7 @@ mod tests {
     fn test_parse_timestamp_success() {
         let line = "11/Oct/2000:13:55:36 -0700";
         let c = single_val_capture(line);
-        let res = parse_timestamp(&c, 1, line, COMMON_LOG_TIMESTAMP);
+        let res = parse_timestamp(&c, 1, line, COMMON_LOG_TIMESTAMP, &TimestampContext::new());
 
         match res {
             Ok(Some(LogFieldValue::Timestamp(ts))) => {
@@ -844,6 +6730,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_timestamp_assumed_zone() {
+        let line = "2000-10-11 13:55:36";
+        let c = single_val_capture(line);
+        let context = TimestampContext::new().assume_zone("-07:00".parse().unwrap());
+        let res = parse_timestamp(&c, 1, line, "%Y-%m-%d %H:%M:%S", &context);
+
+        match res {
+            Ok(Some(LogFieldValue::Timestamp(ts))) => assert_eq!("2000-10-11T13:55:36-07:00", ts.to_rfc3339()),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+
+        let res = parse_timestamp(&c, 1, line, "%Y-%m-%d %H:%M:%S", &TimestampContext::new());
+        assert!(matches!(res, Err(RedeyeError::TimestampParseError(_))));
+    }
+
     #[test]
     fn test_parse_text_value_missing() {
         let line = "127.0.0.1";
@@ -931,4 +6833,347 @@ mod tests {
             v => panic!("Unexpected result: {:?}", v),
         }
     }
+
+    #[test]
+    fn test_parse_float_value_empty_field() {
+        let line = "-";
+        let c = single_val_capture(line);
+        let res = parse_float_value(&c, 1, line);
+
+        match res {
+            Ok(None) => (),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_parse_float_value_bad_format() {
+        for line in &["asdf", "NaN", "inf", "-1.5"] {
+            let c = single_val_capture(line);
+            let res = parse_float_value(&c, 1, line);
+
+            match res {
+                Err(RedeyeError::ParseError(_)) => (),
+                v => panic!("Unexpected result: {:?}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_float_value_success() {
+        let line = "0.042";
+        let c = single_val_capture(line);
+        let res = parse_float_value(&c, 1, line);
+
+        match res {
+            Ok(Some(LogFieldValue::Float(v))) => {
+                assert_eq!(0.042, v);
+            }
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_strip_syslog_header_rfc3164() {
+        assert_eq!(
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326",
+            strip_syslog_header(
+                "<190>Oct  1 02:04:05 web1 httpd[1234]: 127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326"
+            )
+        );
+        assert_eq!("message", strip_syslog_header("<13>Oct 11 22:14:15 host tag: message"));
+    }
+
+    #[test]
+    fn test_strip_syslog_header_rfc5424() {
+        assert_eq!(
+            "message",
+            strip_syslog_header("<165>1 2003-10-11T22:14:15.003Z web1 apache - ID47 - message")
+        );
+        assert_eq!(
+            "message",
+            strip_syslog_header(
+                "<165>1 2003-10-11T22:14:15.003Z web1 apache 1234 ID47 [a@1 x=\"y\\]z\"][b@2 c=\"d\"] \u{feff}message"
+            )
+        );
+        assert_eq!(
+            "",
+            strip_syslog_header("<165>1 2003-10-11T22:14:15.003Z web1 apache - -")
+        );
+    }
+
+    #[test]
+    fn test_strip_syslog_header_not_syslog() {
+        for line in &[
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700]",
+            "<abc>def",
+            "<1234>x",
+            "<>x",
+        ] {
+            assert_eq!(*line, strip_syslog_header(line));
+        }
+    }
+
+    #[test]
+    fn test_syslog_log_line_parser() {
+        let parser = SyslogLogLineParser::new(CommonLogLineParser::new());
+        let plain = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+        let wrapped = format!("<134>Oct 11 22:14:15 web1 apache: {}", plain);
+
+        let event = parser.parse(&wrapped).unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+        assert_eq!(Some(&LogFieldValue::Text(plain.to_owned())), event.lookup("message"));
+        assert!(parser.parse(plain).is_ok());
+        assert!(parser.parse("<134>Oct 11 22:14:15 web1 apache: nope").is_err());
+    }
+
+    #[test]
+    fn test_named_format_names() {
+        let names: Vec<&str> = NamedFormat::ALL.iter().map(|f| f.name()).collect();
+        assert_eq!(NamedFormat::NAMES, names.as_slice());
+
+        for format in NamedFormat::ALL {
+            assert_eq!(*format, format.name().parse::<NamedFormat>().unwrap());
+            assert_eq!(format.name(), format.to_string());
+        }
+
+        let err = "apache".parse::<NamedFormat>().unwrap_err();
+        assert_eq!(
+            "unknown format 'apache', expected one of common, combined, vhost_combined",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_named_format_parser() {
+        let common = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+        let combined = format!("{} \"http://www.example.com/start.html\" \"Mozilla/4.08\"", common);
+        let vhost_combined = format!("www.example.com:443 {}", combined);
+
+        // Each parser emits the same fields as its equivalent Apache format.
+        for format in NamedFormat::ALL {
+            let line = match format {
+                NamedFormat::Common => common,
+                NamedFormat::Combined => &combined,
+                NamedFormat::VhostCombined => &vhost_combined,
+            };
+
+            let spec = CustomLogLineParser::from_format(LogFormat::apache(format.spec()).unwrap());
+            let mut expected: Vec<String> = spec.parse(line).unwrap().fields().keys().cloned().collect();
+            let mut actual: Vec<String> = format.parser().parse(line).unwrap().fields().keys().cloned().collect();
+
+            expected.sort();
+            actual.sort();
+            assert_eq!(expected, actual, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_parse_line_to_json_errors() {
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+
+        let err = parse_line_to_json("yaml", line).unwrap_err();
+        assert!(err.contains("unknown format 'yaml'"), "{}", err);
+        assert!(parse_line_to_json("combined", line).is_err());
+        assert!(parse_line_to_json("%h %{Referer", line).is_err());
+    }
+    #[test]
+    fn test_parser_registry_builtins() {
+        let registry = ParserRegistry::new();
+        assert_eq!(
+            vec!["common", "combined", "vhost_combined"],
+            registry.names()[..3].to_vec()
+        );
+        assert!(registry.contains("nginx_timed"));
+        assert!(registry.contains("combined_duration_ms"));
+        assert!(registry.contains("agent"));
+
+        let parser = registry.create("common").unwrap();
+        let event = parser
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326")
+            .unwrap();
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.fields().get("status_code"));
+    }
+
+    #[test]
+    fn test_parser_registry_register_existing() {
+        let mut registry = ParserRegistry::new();
+        let res = registry.register("common", |context| NamedFormat::Combined.parser_with_context(context));
+
+        assert!(matches!(res, Err(RedeyeError::ConfigError(_))));
+        assert!(registry
+            .create("common")
+            .unwrap()
+            .parse("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_parser_registry_replace() {
+        let mut registry = ParserRegistry::new();
+        let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+
+        let replaced = registry.replace("common", |context| NamedFormat::Combined.parser_with_context(context));
+        assert!(replaced.is_some());
+        assert!(registry.create("common").unwrap().parse(line).is_err());
+        assert_eq!(ParserRegistry::new().names(), registry.names());
+
+        let added = registry.replace("status", |_| {
+            Box::new(CustomLogLineParser::from_format(LogFormat::apache("%h %>s").unwrap()))
+        });
+        assert!(added.is_none());
+        assert_eq!(Some(&"status"), registry.names().last());
+    }
+
+    #[test]
+    fn test_parser_registry_custom_trait_object() {
+        struct Upper;
+
+        impl LogLineParser for Upper {
+            fn parse(&self, line: &str) -> RedeyeResult<LogEvent> {
+                let mut fields = HashMap::new();
+                fields.insert("message".to_owned(), LogFieldValue::Text(line.to_uppercase()));
+                Ok(LogEvent::from(fields))
+            }
+        }
+
+        let mut registry = ParserRegistry::empty();
+        registry.register("upper", |_| Box::new(Upper)).unwrap();
+
+        let parsers: Vec<Box<dyn LogLineParser + Send + Sync>> = vec![registry.create("upper").unwrap()];
+        let event = parsers[0].parse("hello").unwrap();
+        assert_eq!(
+            Some(&LogFieldValue::Text("HELLO".to_owned())),
+            event.fields().get("message")
+        );
+    }
+
+    #[test]
+    fn test_parser_registry_unknown() {
+        let registry = ParserRegistry::new();
+        let err = match registry.create("yaml") {
+            Err(e) => e,
+            Ok(_) => panic!("expected an error for an unknown format"),
+        };
+
+        assert_eq!(
+            format!("unknown format 'yaml', expected one of {}", registry.names().join(", ")),
+            err.to_string()
+        );
+    }
+
+    /// Lines in each built in format, ending with one that's parsed into an event
+    /// after the others are handled as directives.
+    fn registry_sample(name: &str) -> &'static [&'static str] {
+        match name {
+            "common" => &["127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326"],
+            "combined" | "varnish" | "jetty" | "combinedio" => {
+                &["127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\""]
+            }
+            "vhost_combined" => &[
+                "www.example.com:443 127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"",
+            ],
+            "nginx_timed" | "combined_duration_s" | "combined_duration_ms" | "combined_duration_us" => {
+                &["127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 12"]
+            }
+            "nginx_upstream" => &[
+                "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 10.0.0.5:8080 0.032 200",
+            ],
+            "ingress_nginx" => &[
+                "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 612 \"-\" \"curl/7.64.1\" 78 0.003 [default-web-80] [] 10.244.0.7:8080 612 0.003 200 5f9c1ae8",
+            ],
+            "error" => &["[Wed Oct 11 14:32:52.123456 2000] [core:error] [pid 1] AH00037: Symbolic link not allowed"],
+            "referer" => &["http://example.com/links.html -> /index.html"],
+            "agent" => &["Mozilla/4.08 [en] (Win98; I ;Nav)"],
+            "w3c" => &[
+                "#Version: 1.0",
+                "#Fields: date time c-ip cs-method cs-uri-stem sc-status",
+                "2000-10-10 20:55:36 127.0.0.1 GET /index.html 200",
+            ],
+            "squid" => &["1157689324.156   1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html"],
+            "tomcat" => &["127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 42"],
+            "caddy" => &[r#"{"ts":1646861401.5,"request":{"remote_addr":"[::1]:55555","method":"GET","uri":"/"},"status":200}"#],
+            "traefik" => &[
+                "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 412 \"web@docker\" \"http://10.0.0.3:8080\" 12ms",
+            ],
+            "ltsv" => &["time:[10/Oct/2000:13:55:36 -0700]\thost:127.0.0.1\treq:GET / HTTP/1.1\tstatus:200"],
+            "logfmt" => &["ts=2021-01-01T00:00:00Z method=GET path=/x status=200"],
+            "combined_xff" => &[
+                "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" \"203.0.113.7\"",
+            ],
+            "lighttpd" => &[
+                "127.0.0.1 www.example.com - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"",
+            ],
+            "morgan_tiny" => &["GET / 200 2326 - 2.134 ms"],
+            "morgan_short" => &["1.2.3.4 - GET / HTTP/1.1 200 2326 - 2.134 ms"],
+            "python_dev_server" => &["127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api HTTP/1.1\" 200 -"],
+            "forensic" => &["+yQtJf8CoAB4AAFNXBIEAAAAA|GET / HTTP/1.1|Host:localhost", "-yQtJf8CoAB4AAFNXBIEAAAAA"],
+            _ => &[],
+        }
+    }
+
+    #[test]
+    fn test_parser_registry_every_format() {
+        let registry = ParserRegistry::new();
+        for name in registry.names() {
+            let lines = registry_sample(name);
+            assert!(!lines.is_empty(), "no sample line for {}", name);
+
+            let parser = registry.create(name).unwrap();
+            let (last, rest) = lines.split_last().unwrap();
+            for line in rest {
+                assert!(parser.directive(line), "{}: {}", name, line);
+            }
+
+            let event = parser.parse(last).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(event.fields().contains_key("message"), "{}", name);
+
+            // Every field in the schema of the format is one that can be emitted,
+            // so those in the sample line are a subset of it.
+            if let Some(schema) = parser.schema() {
+                let json = serde_json::to_value(&event).unwrap();
+                for (key, value) in json.as_object().unwrap() {
+                    let nested = value
+                        .as_object()
+                        .map(|m| m.keys().map(|k| format!("{}.{}", key, k)).collect());
+                    for path in nested.unwrap_or_else(|| vec![key.clone()]) {
+                        assert!(
+                            schema.iter().any(|(n, _)| *n == path),
+                            "{}: {} not in schema",
+                            name,
+                            path
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_registry_schema() {
+        let registry = ParserRegistry::new();
+        for name in registry.names() {
+            // Every line of these names its own fields
+            if ["w3c", "caddy", "ltsv", "logfmt", "forensic"].contains(&name) {
+                assert!(registry.create(name).unwrap().schema().is_none(), "{}", name);
+                continue;
+            }
+
+            let schema = registry.create(name).unwrap().schema().unwrap();
+            let fields: Vec<&str> = schema.iter().map(|(n, _)| n.as_str()).collect();
+            assert!(fields.ends_with(&["@version", "message"]), "{}: {:?}", name, fields);
+        }
+
+        let schema = registry.create("nginx_upstream").unwrap().schema().unwrap();
+        let at = schema.len() - 5;
+        assert_eq!(
+            vec![
+                ("request_headers.user_agent".to_owned(), "text"),
+                ("upstream.addr".to_owned(), "text"),
+                ("upstream.response_time".to_owned(), "float"),
+                ("upstream.status".to_owned(), "int"),
+            ],
+            schema[at - 1..at + 3].to_vec()
+        );
+    }
 }
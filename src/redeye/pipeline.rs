@@ -0,0 +1,1218 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Reading, parsing, enriching, and writing access log lines end to end
+//!
+//! This is what the `redeye` binary is built on, and what programs that need to
+//! convert lines to JSON the same way can use: lines are read and split on a
+//! delimiter, parsed and enriched by a pool of worker threads, and written in the
+//! order they were read. Stages are connected by bounded queues so a slow writer
+//! makes the workers and reader wait rather than buffering input without limit.
+//!
+//! `run` and `run_sync` handle a single input from start to finish. Programs that
+//! read several inputs into the same output (or read files as they're written,
+//! the journal, or datagrams) use the `process` functions for each of them and
+//! then `finish` once all input has been handled.
+
+use crate::aggregate::{RequestSummary, TopValues};
+use crate::dedup::{Dedup, Deduped};
+use crate::enrich::Enricher;
+use crate::failures::Failures;
+use crate::filter::Filter;
+use crate::io::{self, Delimiter, Output, Utf8Policy};
+#[cfg(feature = "journal")]
+use crate::journal::JournalFollower;
+use crate::merge::TimeMerge;
+use crate::metrics::Metrics;
+use crate::net::{Datagram, DatagramReceiver};
+use crate::output::PrettyRenderer;
+use crate::parser::LogLineParser;
+use crate::sample::Sampler;
+use crate::select::Selection;
+use crate::stats::Stats;
+use crate::types::{LogEvent, LogFieldValue, RedeyeError, RedeyeResult};
+use crate::warnings::Warn;
+use crate::watch::FileWatcher;
+use crate::workers::{Processed, Record, WorkerPool};
+use chrono::{DateTime, FixedOffset};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Number of handled lines from each input that may wait to be merged by time.
+const MERGE_READ_AHEAD: usize = 1024;
+
+/// Longest time between saving the offsets of watched files, or the journal
+/// cursor, while there's more to read.
+const WATCH_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Destination for events that are summarized instead of written, e.g. by
+/// `redeye stats` and `redeye top`.
+pub trait Collect: Send {
+    fn add(&mut self, event: &LogEvent);
+}
+
+impl Collect for RequestSummary {
+    fn add(&mut self, event: &LogEvent) {
+        RequestSummary::add(self, event)
+    }
+}
+
+impl Collect for TopValues {
+    fn add(&mut self, event: &LogEvent) {
+        TopValues::add(self, event)
+    }
+}
+
+/// Parser for the lines of a single input and the name of its format.
+#[derive(Clone)]
+struct SourceParser {
+    format: String,
+    parser: Arc<dyn LogLineParser + Send + Sync>,
+}
+
+/// How lines are read, parsed, enriched, and written by `run`.
+///
+/// Everything that is shared between inputs, such as stats, the events being
+/// deduplicated, and recorded failures, is shared between clones of a config
+/// too. Clones that only differ by `source` are used to process several inputs
+/// into the same output.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::CommonLogLineParser;
+/// use redeye::pipeline::{self, PipelineConfig};
+/// use std::io::Cursor;
+/// use std::sync::Arc;
+///
+/// let config = PipelineConfig::new(Arc::new(CommonLogLineParser::new())).workers(2);
+/// let input = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326\nnot a log line\n";
+/// let mut out = Vec::new();
+///
+/// let summary = pipeline::run(&config, Cursor::new(input), &mut out).unwrap();
+/// assert_eq!(2, summary.lines);
+/// assert_eq!(1, summary.events);
+/// assert_eq!(1, summary.errors);
+/// assert!(String::from_utf8(out).unwrap().contains("\"requested_uri\":\"/\""));
+/// ```
+#[derive(Clone)]
+pub struct PipelineConfig {
+    parser: Arc<dyn LogLineParser + Send + Sync>,
+    source_parsers: HashMap<String, SourceParser>,
+    enrichers: Vec<Arc<dyn Enricher + Send + Sync>>,
+    filters: Vec<Filter>,
+    select: Option<Selection>,
+    sampler: Option<Arc<Sampler>>,
+    dedup: Option<Arc<Dedup<String>>>,
+    pretty: Option<PrettyRenderer>,
+    stamp_source: bool,
+    max_errors: Option<u64>,
+    failures: Option<Arc<Mutex<Failures>>>,
+    collect: Option<Arc<Mutex<dyn Collect>>>,
+    workers: usize,
+    input_delimiter: Delimiter,
+    max_record_bytes: Option<usize>,
+    utf8: Utf8Policy,
+    output_delimiter: Delimiter,
+    line_buffered: bool,
+    reopen: Option<Arc<AtomicBool>>,
+    stop: Option<Arc<AtomicBool>>,
+    warnings: Option<Arc<dyn Warn>>,
+    source: String,
+    stats: Arc<Stats>,
+}
+
+impl PipelineConfig {
+    pub fn new(parser: Arc<dyn LogLineParser + Send + Sync>) -> Self {
+        PipelineConfig {
+            parser,
+            source_parsers: HashMap::new(),
+            enrichers: Vec::new(),
+            filters: Vec::new(),
+            select: None,
+            sampler: None,
+            dedup: None,
+            pretty: None,
+            stamp_source: false,
+            max_errors: None,
+            failures: None,
+            collect: None,
+            workers: 1,
+            input_delimiter: Delimiter::Newline,
+            max_record_bytes: None,
+            utf8: Utf8Policy::default(),
+            output_delimiter: Delimiter::Newline,
+            line_buffered: false,
+            reopen: None,
+            stop: None,
+            warnings: None,
+            source: "-".to_owned(),
+            stats: Arc::new(Stats::new()),
+        }
+    }
+
+    /// Parse lines from the given source with a different parser, adding the
+    /// name of its format to each event as the `source_format` field.
+    pub fn source_parser<S, F>(mut self, source: S, format: F, parser: Arc<dyn LogLineParser + Send + Sync>) -> Self
+    where
+        S: Into<String>,
+        F: Into<String>,
+    {
+        let format = format.into();
+        self.source_parsers
+            .insert(source.into(), SourceParser { format, parser });
+        self
+    }
+
+    /// Add an enricher, applied to events after those added before it.
+    pub fn enricher(mut self, enricher: Arc<dyn Enricher + Send + Sync>) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
+    /// Add a filter that events must match to be written, after enrichment.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Write only the selected fields of each event.
+    pub fn select(mut self, selection: Selection) -> Self {
+        self.select = Some(selection);
+        self
+    }
+
+    /// Write only the events kept by the sampler.
+    pub fn sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = Some(Arc::new(sampler));
+        self
+    }
+
+    /// Drop events that are duplicates of the event written before them, adding
+    /// the number dropped to the next event written as `repeat_count`.
+    pub fn dedup(mut self, dedup: Dedup<String>) -> Self {
+        self.dedup = Some(Arc::new(dedup));
+        self
+    }
+
+    /// Write each event as a line of text for people instead of JSON.
+    pub fn pretty(mut self, renderer: PrettyRenderer) -> Self {
+        self.pretty = Some(renderer);
+        self
+    }
+
+    /// Add the name of the input each event was read from as `source_file`.
+    pub fn stamp_source(mut self, stamp: bool) -> Self {
+        self.stamp_source = stamp;
+        self
+    }
+
+    /// Stop with `PipelineError::TooManyErrors` once more than this many lines
+    /// in total couldn't be handled. There's no limit by default.
+    pub fn max_errors(mut self, max: Option<u64>) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Record each line that can't be handled, including its text.
+    pub fn failures(mut self, failures: Arc<Mutex<Failures>>) -> Self {
+        self.failures = Some(failures);
+        self
+    }
+
+    /// Add events to the collector instead of writing them.
+    pub fn collect(mut self, collect: Arc<Mutex<dyn Collect>>) -> Self {
+        self.collect = Some(collect);
+        self
+    }
+
+    /// Set the number of threads lines are parsed on, one by default. Lines are
+    /// always parsed on a single thread if the parser of any input is stateful
+    /// (see `LogLineParser::is_stateful`).
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Set the delimiter that input is split into lines on, a newline by default.
+    pub fn input_delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.input_delimiter = delimiter;
+        self
+    }
+
+    /// Set the maximum length of a line, not counting the delimiter. Longer lines
+    /// are errors.
+    pub fn max_record_bytes(mut self, bytes: Option<usize>) -> Self {
+        self.max_record_bytes = bytes;
+        self
+    }
+
+    /// Set what to do with lines that aren't valid UTF-8. By default, invalid
+    /// bytes are replaced.
+    pub fn utf8(mut self, policy: Utf8Policy) -> Self {
+        self.utf8 = policy;
+        self
+    }
+
+    /// Set the delimiter written after each event, a newline by default.
+    pub fn output_delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.output_delimiter = delimiter;
+        self
+    }
+
+    /// Flush output after every event instead of when the writer's buffer fills.
+    pub fn line_buffered(mut self, line_buffered: bool) -> Self {
+        self.line_buffered = line_buffered;
+        self
+    }
+
+    /// Reopen output between events once the flag is set, e.g. by a SIGHUP
+    /// handler, and then clear the flag.
+    pub fn reopen_when(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.reopen = Some(flag);
+        self
+    }
+
+    /// Stop reading new lines once the flag is set, e.g. by a signal handler.
+    /// Lines that have already been read are still written.
+    pub fn stop_when(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.stop = Some(flag);
+        self
+    }
+
+    /// Report each line that can't be handled to the sink, usually a
+    /// `WarningSink`. Lines are only counted by default.
+    pub fn warnings(mut self, sink: Arc<dyn Warn>) -> Self {
+        self.warnings = Some(sink);
+        self
+    }
+
+    /// Set the name of the input used in warnings, `-` by default.
+    pub fn source<S: Into<String>>(mut self, source: S) -> Self {
+        self.source = source.into();
+        self
+    }
+
+    /// Count lines, events, and errors in the stats as well as the summary
+    /// returned by `run`, e.g. to share them between pipelines.
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Count lines, events, and errors in the registry as well as the summary
+    /// returned by `run`.
+    pub fn metrics(self, metrics: &Metrics) -> Self {
+        self.stats(Arc::new(Stats::register(metrics)))
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.as_ref().map(|s| s.load(Ordering::Acquire)).unwrap_or(false)
+    }
+
+    /// True if any parser depends on lines being handled in the order they were read.
+    fn is_stateful(&self) -> bool {
+        self.parser.is_stateful() || self.source_parsers.values().any(|p| p.parser.is_stateful())
+    }
+
+    /// Parser for lines from the given source and the name of its format, if it
+    /// has its own.
+    fn parser_of(&self, source: &str) -> (&Arc<dyn LogLineParser + Send + Sync>, Option<&str>) {
+        match self.source_parsers.get(source) {
+            Some(p) => (&p.parser, Some(p.format.as_str())),
+            None => (&self.parser, None),
+        }
+    }
+}
+
+/// Counts of what happened to lines processed by `run`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineSummary {
+    /// Number of lines read.
+    pub lines: u64,
+    /// Number of events written.
+    pub events: u64,
+    /// Number of lines that couldn't be parsed or were too long.
+    pub errors: u64,
+    /// Number of lines skipped because they weren't valid UTF-8.
+    pub skipped: u64,
+    /// Number of bytes read, including delimiters.
+    pub bytes_in: u64,
+    /// Number of bytes written, including delimiters.
+    pub bytes_out: u64,
+}
+
+/// Reason that processing stopped before the end of the input.
+#[derive(Debug)]
+pub enum PipelineError {
+    /// Error reading input, such as a corrupt compressed file.
+    Input(RedeyeError),
+    /// Error writing output, after which nothing more can be written.
+    Output(RedeyeError),
+    /// More lines couldn't be handled than the maximum number of errors.
+    TooManyErrors,
+}
+
+impl PipelineError {
+    /// Return true if reading input or writing output failed.
+    pub fn is_io_error(&self) -> bool {
+        match self {
+            PipelineError::Input(e) | PipelineError::Output(e) => e.is_io_error(),
+            PipelineError::TooManyErrors => false,
+        }
+    }
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PipelineError::Input(e) => write!(f, "Unable to read input: {}", e),
+            PipelineError::Output(e) => write!(f, "Unable to write output: {}", e),
+            PipelineError::TooManyErrors => write!(f, "Too many invalid lines"),
+        }
+    }
+}
+
+impl error::Error for PipelineError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PipelineError::Input(e) | PipelineError::Output(e) => Some(e),
+            PipelineError::TooManyErrors => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PipelineError {
+    fn from(e: std::io::Error) -> Self {
+        PipelineError::Input(e.into())
+    }
+}
+
+fn output_error(e: std::io::Error) -> PipelineError {
+    PipelineError::Output(e.into())
+}
+
+/// Result of handling a single line, before it is written.
+#[derive(Debug)]
+pub enum Handled {
+    /// Event to write, already serialized, along with its timestamp and the key
+    /// used to tell whether it's a duplicate.
+    Emit {
+        json: String,
+        timestamp: Option<DateTime<FixedOffset>>,
+        key: Option<u64>,
+    },
+    /// Event to add to the collector instead of being written.
+    Collect(LogEvent),
+    /// Line that was a directive describing the lines after it, not an event.
+    Directive,
+    /// Event that didn't match every filter.
+    Filtered,
+    /// Event that wasn't part of the sample.
+    SampledOut,
+    /// Line that was skipped because it wasn't valid UTF-8.
+    InvalidUtf8,
+    /// Line that couldn't be handled, along with its text if failures are being
+    /// recorded.
+    Failed { err: RedeyeError, text: Option<String> },
+}
+
+impl Handled {
+    /// Timestamp of the event, if it has one and will be written or collected.
+    pub fn timestamp(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Handled::Emit { timestamp, .. } => *timestamp,
+            Handled::Collect(event) => event_timestamp(event),
+            _ => None,
+        }
+    }
+}
+
+/// Value of the `@timestamp` field of an event, if it has one.
+fn event_timestamp(event: &LogEvent) -> Option<DateTime<FixedOffset>> {
+    match event.fields().get("@timestamp") {
+        Some(LogFieldValue::Timestamp(ts)) => Some(*ts),
+        _ => None,
+    }
+}
+
+/// Read lines until the end of the reader (or until the stop flag is set), and
+/// write each of them that can be parsed to the writer as JSON followed by the
+/// output delimiter. Lines that can't be parsed are counted, skipped, and
+/// reported to the warning sink if there is one.
+///
+/// Errors reading input or writing output stop processing and are returned once
+/// lines that have already been read are written. The pipeline is finished (see
+/// `finish`) before returning successfully.
+pub fn run<R, W>(config: &PipelineConfig, reader: R, writer: &mut W) -> Result<PipelineSummary, PipelineError>
+where
+    R: BufRead + Send,
+    W: Output,
+{
+    let mut summary = process(config, reader, writer)?;
+    finish_into(config, writer, &mut summary)?;
+    Ok(summary)
+}
+
+/// Read, parse, and write lines the same as `run` but entirely on the calling
+/// thread, ignoring the number of workers. This suits readers that can't be sent
+/// between threads and tests that need to run deterministically.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::parser::CommonLogLineParser;
+/// use redeye::pipeline::{self, PipelineConfig};
+/// use std::io::Cursor;
+/// use std::sync::Arc;
+///
+/// let config = PipelineConfig::new(Arc::new(CommonLogLineParser::new()));
+/// let mut out = Vec::new();
+///
+/// let summary = pipeline::run_sync(&config, Cursor::new("not a log line\n"), &mut out).unwrap();
+/// assert_eq!(1, summary.lines);
+/// assert_eq!(1, summary.errors);
+/// assert!(out.is_empty());
+/// ```
+pub fn run_sync<R, W>(config: &PipelineConfig, reader: R, writer: &mut W) -> Result<PipelineSummary, PipelineError>
+where
+    R: BufRead,
+    W: Output,
+{
+    let mut summary = process_sync(config, reader, writer)?;
+    finish_into(config, writer, &mut summary)?;
+    Ok(summary)
+}
+
+/// Handle each line of the reader the same as `run` without finishing the
+/// pipeline afterwards, so that more input can be written to the same output.
+/// Lines are handled on the calling thread if there's only a single worker or
+/// the parser is stateful. Events the parser still has buffered at the end of
+/// the reader are written too (see `LogLineParser::flush_events`).
+pub fn process<R, W>(config: &PipelineConfig, reader: R, writer: &mut W) -> Result<PipelineSummary, PipelineError>
+where
+    R: BufRead + Send,
+    W: Output,
+{
+    if config.workers <= 1 || config.is_stateful() {
+        return process_sync(config, reader, writer);
+    }
+
+    let mut pool = WorkerPool::new(config.workers)
+        .delimiter(config.input_delimiter)
+        .max_record_bytes(config.max_record_bytes);
+    if let Some(ref stop) = config.stop {
+        pool = pool.stop_when(stop.clone());
+    }
+
+    let mut summary = PipelineSummary::default();
+    pool.run(
+        reader,
+        |record| handle(config, record),
+        |processed| emit(config, processed, writer, &mut summary),
+    )?;
+
+    Ok(summary)
+}
+
+/// Handle each line of the reader the same as `run_sync` without finishing the
+/// pipeline afterwards.
+pub fn process_sync<R, W>(
+    config: &PipelineConfig,
+    mut reader: R,
+    writer: &mut W,
+) -> Result<PipelineSummary, PipelineError>
+where
+    R: BufRead,
+    W: Output,
+{
+    let mut summary = PipelineSummary::default();
+    let mut buf = Vec::new();
+    let mut line = 0;
+
+    while !config.is_stopped() {
+        buf.clear();
+        let delimiter = config.input_delimiter.byte();
+        let read = match io::read_record(&mut reader, delimiter, config.max_record_bytes, &mut buf)? {
+            Some(read) => read,
+            None => break,
+        };
+
+        line += 1;
+        let value = handle(
+            config,
+            Record {
+                data: &buf,
+                too_long: read.too_long,
+            },
+        );
+        let processed = Processed {
+            line,
+            bytes: read.bytes,
+            value,
+        };
+        emit(config, processed, writer, &mut summary)?;
+    }
+
+    emit_parser_events(config, &config.source, line, true, writer, &mut summary)?;
+    Ok(summary)
+}
+
+/// Handle each line of every input at once, each on its own thread, and write
+/// the resulting events in order of their timestamps. Each input is named by
+/// its source. Errors reading an input are passed to `input_error` and the rest
+/// of the inputs continue to be read. Other errors stop processing.
+pub fn process_merged<W, E>(
+    config: &PipelineConfig,
+    inputs: Vec<(String, Box<dyn BufRead + Send>)>,
+    window: Duration,
+    writer: &mut W,
+    mut input_error: E,
+) -> Result<PipelineSummary, PipelineError>
+where
+    W: Output,
+    E: FnMut(PipelineError, &str),
+{
+    let sources: Vec<String> = inputs.iter().map(|(source, _)| source.clone()).collect();
+    let mut merge = TimeMerge::new(inputs.len(), window);
+    let mut summary = PipelineSummary::default();
+
+    let res = thread::scope(|scope| {
+        let mut receivers = Vec::with_capacity(inputs.len());
+        for (source, mut reader) in inputs {
+            let (tx, rx) = mpsc::sync_channel(MERGE_READ_AHEAD);
+            receivers.push(rx);
+
+            // Each thread stops at the end of its input, when the stop flag is
+            // set, or once the receiver is dropped because processing failed.
+            scope.spawn(move || {
+                let mut buf = Vec::new();
+                let mut line = 0;
+
+                while !config.is_stopped() {
+                    buf.clear();
+                    let delimiter = config.input_delimiter.byte();
+                    let read = match io::read_record(&mut reader, delimiter, config.max_record_bytes, &mut buf) {
+                        Ok(Some(read)) => read,
+                        Ok(None) => return,
+                        Err(e) => {
+                            let _ = tx.send(Err(e));
+                            return;
+                        }
+                    };
+
+                    line += 1;
+                    let record = config.input_delimiter.trim(&buf);
+                    let value = handle_record(config, &source, record, read.too_long, &[]);
+                    let processed = Processed {
+                        line,
+                        bytes: read.bytes,
+                        value,
+                    };
+                    if tx.send(Ok(processed)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        while let Some(i) = merge.next_input() {
+            match receivers[i].recv() {
+                Ok(Ok(processed)) => {
+                    let timestamp = processed.value.timestamp();
+                    merge.push(i, timestamp, (i, processed));
+                }
+                Ok(Err(e)) => {
+                    input_error(e.into(), &sources[i]);
+                    merge.finish(i);
+                }
+                Err(_) => merge.finish(i),
+            }
+
+            while let Some((i, processed)) = merge.pop() {
+                emit_from(config, &sources[i], processed, writer, &mut summary)?;
+            }
+        }
+
+        flush_parsers(config, &sources, writer, &mut summary)
+    });
+
+    config.stats.events_late(merge.late());
+    res.map(|_| summary)
+}
+
+/// Handle each line of the files found by the watcher, the same as lines of a
+/// single file, until the stop flag is set. Each event is from the file it was
+/// read from. Output is flushed and the offsets of files are saved when there's
+/// nothing new to read, and at least every `WATCH_SAVE_INTERVAL` otherwise, so that saved
+/// offsets never include events that haven't been written.
+pub fn process_watched<W>(
+    config: &PipelineConfig,
+    watcher: &mut FileWatcher,
+    writer: &mut W,
+) -> Result<PipelineSummary, PipelineError>
+where
+    W: Output,
+{
+    let mut summary = PipelineSummary::default();
+    let mut buf = Vec::new();
+    let mut saved = Instant::now();
+    let mut pending = false;
+
+    loop {
+        // Check the flag before reading so that everything that was available
+        // when we noticed we were being stopped is read.
+        let stopping = config.is_stopped();
+
+        buf.clear();
+        match watcher.read_line(config.input_delimiter.byte(), &mut buf)? {
+            Some(read) => {
+                let source = read.path.to_string_lossy().into_owned();
+                let (line, bytes) = (read.line, read.bytes);
+                let record = config.input_delimiter.trim(&buf);
+                let value = handle_record(config, &source, record, read.too_long, &[]);
+                emit_from(config, &source, Processed { line, bytes, value }, writer, &mut summary)?;
+                pending = true;
+            }
+            None if stopping => break,
+            None => {
+                flush_duplicates(config, writer, &mut summary)?;
+                if pending {
+                    flush_and_save(writer, || watcher.save_state())?;
+                    saved = Instant::now();
+                    pending = false;
+                }
+
+                watcher.wait();
+                continue;
+            }
+        }
+
+        if saved.elapsed() >= WATCH_SAVE_INTERVAL {
+            flush_and_save(writer, || watcher.save_state())?;
+            saved = Instant::now();
+            pending = false;
+        }
+    }
+
+    flush_parsers(config, &[], writer, &mut summary)?;
+    flush_and_save(writer, || watcher.save_state())?;
+    Ok(summary)
+}
+
+/// Handle the message of each journal entry, the same as lines of a file, until
+/// the stop flag is set. The cursor of the last entry is saved the same way as
+/// the offsets of watched files.
+#[cfg(feature = "journal")]
+pub fn process_journal<W>(
+    config: &PipelineConfig,
+    journal: &mut JournalFollower,
+    writer: &mut W,
+) -> Result<PipelineSummary, PipelineError>
+where
+    W: Output,
+{
+    let mut summary = PipelineSummary::default();
+    let mut line = 0;
+    let mut saved = Instant::now();
+    let mut pending = false;
+
+    while !config.is_stopped() {
+        let entry = match journal.receive()? {
+            Some(entry) => entry,
+            None => {
+                flush_duplicates(config, writer, &mut summary)?;
+                if pending {
+                    flush_and_save(writer, || journal.save_state())?;
+                    saved = Instant::now();
+                    pending = false;
+                }
+
+                continue;
+            }
+        };
+
+        // Entries without a message (e.g. those only carrying metadata about a
+        // unit) have nothing to parse, but are still included in the saved cursor.
+        pending = true;
+        if let Some(message) = entry.message() {
+            line += 1;
+            let record = Delimiter::Newline.trim(message);
+            let value = handle_record(config, &config.source, record, false, &entry.metadata());
+            let bytes = message.len();
+            emit(config, Processed { line, bytes, value }, writer, &mut summary)?;
+        }
+
+        if saved.elapsed() >= WATCH_SAVE_INTERVAL {
+            flush_and_save(writer, || journal.save_state())?;
+            saved = Instant::now();
+            pending = false;
+        }
+    }
+
+    flush_parsers(config, &[], writer, &mut summary)?;
+    flush_and_save(writer, || journal.save_state())?;
+    Ok(summary)
+}
+
+/// Handle each datagram received, the same as lines of a file, until the stop
+/// flag is set. The address each datagram was sent from is added to its event
+/// as `source_address` and datagrams larger than the maximum size of the
+/// receiver are skipped.
+pub fn process_datagrams<W>(
+    config: &PipelineConfig,
+    receiver: &mut DatagramReceiver,
+    writer: &mut W,
+) -> Result<PipelineSummary, PipelineError>
+where
+    W: Output,
+{
+    let mut summary = PipelineSummary::default();
+    let mut line = 0;
+
+    while !config.is_stopped() {
+        let (data, from) = match receiver.receive()? {
+            Some(Datagram::Record { data, from }) => (data, from),
+            Some(Datagram::Oversized { .. }) => {
+                config.stats.datagram_oversized();
+                continue;
+            }
+            None => {
+                // Nothing has been received for a while, make sure that events
+                // that have been received aren't stuck waiting in a buffer.
+                flush_duplicates(config, writer, &mut summary)?;
+                writer.flush().map_err(output_error)?;
+                continue;
+            }
+        };
+
+        line += 1;
+        let record = Delimiter::Newline.trim(data);
+        let address = [("source_address", LogFieldValue::Text(from.ip().to_string()))];
+        let value = handle_record(config, &config.source, record, false, &address);
+        let bytes = data.len();
+        emit(config, Processed { line, bytes, value }, writer, &mut summary)?;
+    }
+
+    if let Some(dropped) = receiver.dropped() {
+        config.stats.datagrams_dropped(dropped);
+    }
+
+    flush_parsers(config, &[], writer, &mut summary)?;
+    Ok(summary)
+}
+
+/// Write the last of the current run of duplicate events, if any, finish the
+/// warning sink, and finish the writer (see `Output::finish`), once all input has
+/// been processed. Returns counts of any events written.
+pub fn finish<W>(config: &PipelineConfig, writer: &mut W) -> Result<PipelineSummary, PipelineError>
+where
+    W: Output,
+{
+    let mut summary = PipelineSummary::default();
+    finish_into(config, writer, &mut summary)?;
+    Ok(summary)
+}
+
+fn finish_into<W>(config: &PipelineConfig, writer: &mut W, summary: &mut PipelineSummary) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    let res = match config.dedup.as_ref().and_then(|d| d.finish()) {
+        Some(deduped) => write_event(config, deduped, writer, summary),
+        None => Ok(()),
+    };
+
+    if let Some(ref warnings) = config.warnings {
+        warnings.finish();
+    }
+
+    res?;
+    writer.finish().map_err(output_error)
+}
+
+/// Parse, enrich, and filter a single line read from the source of the config,
+/// including its delimiter, returning what to do with it. This is done by the
+/// worker threads of `process` and is safe to call from any thread, although
+/// lines for stateful parsers must be handled in the order they were read.
+pub fn handle(config: &PipelineConfig, record: Record<'_>) -> Handled {
+    let line = config.input_delimiter.trim(record.data);
+    handle_record(config, &config.source, line, record.too_long, &[])
+}
+
+/// Write the event from a line handled by `handle` if there is one, or report
+/// why there isn't, and update the summary and stats. Lines must be emitted in
+/// the order they were read.
+pub fn emit<W>(
+    config: &PipelineConfig,
+    processed: Processed<Handled>,
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    emit_from(config, &config.source, processed, writer, summary)
+}
+
+/// Handle a single record read from an input, the same as a line unless it's
+/// longer than the maximum record size (or was already cut short because it
+/// was), in which case it's an error and only the start of it is kept. Fields
+/// describing where the line came from, such as the address it was received
+/// from, are added before enrichment.
+fn handle_record(
+    config: &PipelineConfig,
+    source: &str,
+    record: &[u8],
+    too_long: bool,
+    metadata: &[(&str, LogFieldValue)],
+) -> Handled {
+    let (res, text) = match config.max_record_bytes {
+        Some(max) if too_long || record.len() > max => {
+            (Err(RedeyeError::RecordTooLong(max)), &record[..record.len().min(max)])
+        }
+        _ => (handle_line(config, source, record, metadata), record),
+    };
+
+    res.unwrap_or_else(|err| Handled::Failed {
+        err,
+        text: config
+            .failures
+            .as_ref()
+            .map(|_| String::from_utf8_lossy(text).into_owned()),
+    })
+}
+
+fn handle_line(
+    config: &PipelineConfig,
+    source: &str,
+    line: &[u8],
+    metadata: &[(&str, LogFieldValue)],
+) -> RedeyeResult<Handled> {
+    let line = match config.utf8.decode(line)? {
+        Some(line) => line,
+        None => return Ok(Handled::InvalidUtf8),
+    };
+
+    let (parser, _) = config.parser_of(source);
+    if parser.directive(&line) {
+        return Ok(Handled::Directive);
+    }
+
+    let event = parser.parse(&line)?;
+    handle_event(config, source, event, metadata)
+}
+
+/// Sample, add fields to, enrich, filter, and serialize an event from the source,
+/// returning what to do with it.
+fn handle_event(
+    config: &PipelineConfig,
+    source: &str,
+    mut event: LogEvent,
+    metadata: &[(&str, LogFieldValue)],
+) -> RedeyeResult<Handled> {
+    let (_, format) = config.parser_of(source);
+
+    // Samplers that depend on the order of events are applied once lines are
+    // back in order (see `emit`), so that the same events are kept regardless
+    // of which worker parsed them.
+    if let Some(ref sampler) = config.sampler {
+        if !sampler.is_ordered() && !sampler.keep(&event) {
+            return Ok(Handled::SampledOut);
+        }
+    }
+
+    if config.stamp_source {
+        event.insert("source_file", LogFieldValue::Text(source.to_owned()));
+    }
+
+    if let Some(format) = format {
+        event.insert("source_format", LogFieldValue::Text(format.to_owned()));
+    }
+
+    for (field, value) in metadata {
+        event.insert(*field, value.clone());
+    }
+
+    for enricher in config.enrichers.iter() {
+        enricher.enrich(&mut event);
+    }
+
+    if !config.filters.iter().all(|f| f.matches(&event)) {
+        return Ok(Handled::Filtered);
+    }
+
+    if config.collect.is_some() {
+        return Ok(Handled::Collect(event));
+    }
+
+    let json = match (&config.pretty, &config.select) {
+        (Some(pretty), _) => pretty.render(&event),
+        (None, Some(s)) => serde_json::to_string(&s.apply(&event))?,
+        (None, None) => serde_json::to_string(&event)?,
+    };
+
+    Ok(Handled::Emit {
+        json,
+        timestamp: event_timestamp(&event),
+        key: config.dedup.as_ref().map(|d| d.key(&event)),
+    })
+}
+
+fn emit_from<W>(
+    config: &PipelineConfig,
+    source: &str,
+    processed: Processed<Handled>,
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    // Only reopen output between lines so that an event is never split between files
+    if let Some(ref reopen) = config.reopen {
+        if reopen.swap(false, Ordering::AcqRel) {
+            writer.reopen().map_err(output_error)?;
+        }
+    }
+
+    summary.lines += 1;
+    summary.bytes_in += processed.bytes as u64;
+    config.stats.line_read(processed.bytes);
+
+    emit_handled(config, source, processed.line, processed.value, writer, summary)?;
+    emit_parser_events(config, source, processed.line, false, writer, summary)
+}
+
+/// Write the event from a line (or one from the parser of its source) if there
+/// is one, or report why there isn't.
+fn emit_handled<W>(
+    config: &PipelineConfig,
+    source: &str,
+    line: u64,
+    handled: Handled,
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    match sample(config, handled) {
+        Handled::Directive => (),
+        Handled::Filtered => config.stats.event_filtered(),
+        Handled::SampledOut => config.stats.event_sampled_out(),
+        Handled::InvalidUtf8 => {
+            summary.skipped += 1;
+            config.stats.line_invalid_utf8();
+        }
+        Handled::Collect(event) => {
+            if let Some(ref collect) = config.collect {
+                collect.lock().unwrap().add(&event);
+            }
+        }
+        Handled::Emit { json, key, .. } => {
+            flush_duplicates(config, writer, summary)?;
+            let deduped = match (&config.dedup, key) {
+                (Some(dedup), Some(key)) => dedup.push(key, json),
+                _ => Some(Deduped { item: json, repeats: 0 }),
+            };
+
+            if let Some(deduped) = deduped {
+                write_event(config, deduped, writer, summary)?;
+            }
+        }
+        Handled::Failed { err, text } => {
+            summary.errors += 1;
+            config.stats.error(&err);
+            if let (Some(failures), Some(text)) = (&config.failures, text) {
+                failures.lock().unwrap().record(&err, source, line, &text);
+            }
+
+            if let Some(ref warnings) = config.warnings {
+                warnings.warn(&err, source, line);
+            }
+
+            if config
+                .max_errors
+                .map(|max| config.stats.errors() > max)
+                .unwrap_or(false)
+            {
+                return Err(PipelineError::TooManyErrors);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the events of the parser for the source that aren't the result of
+/// parsing a line (see `LogLineParser::take_events`), or every event it has
+/// buffered once there's no more input if `flush` is true.
+fn emit_parser_events<W>(
+    config: &PipelineConfig,
+    source: &str,
+    line: u64,
+    flush: bool,
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    let (parser, _) = config.parser_of(source);
+    let events = if flush {
+        parser.flush_events()
+    } else {
+        parser.take_events()
+    };
+
+    for event in events {
+        let handled =
+            handle_event(config, source, event, &[]).unwrap_or_else(|err| Handled::Failed { err, text: None });
+        emit_handled(config, source, line, handled, writer, summary)?;
+    }
+
+    Ok(())
+}
+
+/// Write every event buffered by the parsers of the config once there's no more
+/// input, attributing those from the default parser to the first of the sources
+/// given (or the source of the config if there are none).
+fn flush_parsers<W>(
+    config: &PipelineConfig,
+    sources: &[String],
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    let default = sources.first().unwrap_or(&config.source);
+    emit_parser_events(config, default, 0, true, writer, summary)?;
+    for source in config.source_parsers.keys() {
+        emit_parser_events(config, source, 0, true, writer, summary)?;
+    }
+
+    Ok(())
+}
+
+/// Apply an ordered sampler to a line that was parsed, which must be done in the
+/// order lines were read. Events are sampled before they're filtered, the same as
+/// samplers that look at the contents of events.
+fn sample(config: &PipelineConfig, handled: Handled) -> Handled {
+    let sampler = match config.sampler {
+        Some(ref sampler) if sampler.is_ordered() => sampler,
+        _ => return handled,
+    };
+
+    match handled {
+        Handled::Emit { .. } | Handled::Collect(_) | Handled::Filtered if !sampler.keep_next() => Handled::SampledOut,
+        handled => handled,
+    }
+}
+
+/// Write the last of a run of duplicate events that has lasted longer than the
+/// dedup interval, so that long runs are reported while they're still going.
+fn flush_duplicates<W>(
+    config: &PipelineConfig,
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    match config.dedup.as_ref().and_then(|d| d.flush()) {
+        Some(deduped) => write_event(config, deduped, writer, summary),
+        None => Ok(()),
+    }
+}
+
+/// Write an event followed by the output delimiter, adding the number of
+/// duplicates dropped before it as the `repeat_count` field if there were any.
+fn write_event<W>(
+    config: &PipelineConfig,
+    deduped: Deduped<String>,
+    writer: &mut W,
+    summary: &mut PipelineSummary,
+) -> Result<(), PipelineError>
+where
+    W: Output,
+{
+    let json = match config.pretty {
+        _ if deduped.repeats == 0 => deduped.item,
+        Some(ref pretty) => pretty.repeat_count(deduped.item, deduped.repeats),
+        None => with_repeat_count(deduped.item, deduped.repeats),
+    };
+
+    writer
+        .write_all(json.as_bytes())
+        .and_then(|_| writer.write_all(&[config.output_delimiter.byte()]))
+        .and_then(|_| if config.line_buffered { writer.flush() } else { Ok(()) })
+        .map_err(output_error)?;
+
+    let bytes = json.len() + 1;
+    summary.events += 1;
+    summary.bytes_out += bytes as u64;
+    config.stats.event_emitted(bytes);
+    config.stats.events_deduped(deduped.repeats);
+    Ok(())
+}
+
+/// Add a `repeat_count` field to the end of an event serialized as a JSON object.
+fn with_repeat_count(mut json: String, repeats: u64) -> String {
+    if json.ends_with('}') {
+        json.pop();
+        if !json.ends_with('{') {
+            json.push(',');
+        }
+        json.push_str(&format!("\"repeat_count\":{}}}", repeats));
+    }
+
+    json
+}
+
+/// Flush output and then save how far input has been read, so that the saved
+/// position never includes events that haven't been written.
+fn flush_and_save<W, F>(writer: &mut W, save: F) -> Result<(), PipelineError>
+where
+    W: Output,
+    F: FnOnce() -> std::io::Result<()>,
+{
+    writer.flush().map_err(output_error)?;
+    save().map_err(|e| output_error(std::io::Error::new(e.kind(), format!("Unable to save state: {}", e))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_repeat_count;
+
+    #[test]
+    fn test_with_repeat_count() {
+        assert_eq!(
+            "{\"a\":1,\"repeat_count\":3}",
+            with_repeat_count("{\"a\":1}".to_owned(), 3)
+        );
+        assert_eq!("{\"repeat_count\":3}", with_repeat_count("{}".to_owned(), 3));
+    }
+}
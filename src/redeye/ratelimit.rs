@@ -0,0 +1,161 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Limiting how often something happens, such as printing warnings
+
+use std::time::{Duration, Instant};
+
+/// Result of checking a `RateLimiter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimit {
+    /// The action is allowed. Includes the number of actions that were suppressed
+    /// since the last one that was allowed.
+    Allow { suppressed: u64 },
+    /// The action should be suppressed.
+    Suppress,
+}
+
+/// Fixed window rate limiter that allows a certain number of actions per window
+/// and keeps track of how many actions were suppressed.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::ratelimit::{RateLimit, RateLimiter};
+/// use std::time::{Duration, Instant};
+///
+/// let mut limiter = RateLimiter::new(2, Duration::from_secs(1));
+/// let start = Instant::now();
+///
+/// assert_eq!(RateLimit::Allow { suppressed: 0 }, limiter.check_at(start));
+/// assert_eq!(RateLimit::Allow { suppressed: 0 }, limiter.check_at(start));
+/// assert_eq!(RateLimit::Suppress, limiter.check_at(start));
+///
+/// let later = start + Duration::from_secs(1);
+/// assert_eq!(RateLimit::Allow { suppressed: 1 }, limiter.check_at(later));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: u64,
+    window: Duration,
+    window_start: Option<Instant>,
+    count: u64,
+    suppressed: u64,
+}
+
+impl RateLimiter {
+    /// Create a new limiter that allows `limit` actions every `window`.
+    pub fn new(limit: u64, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            window_start: None,
+            count: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Check if an action should be allowed now.
+    pub fn check(&mut self) -> RateLimit {
+        self.check_at(Instant::now())
+    }
+
+    /// Check if an action should be allowed at the given time.
+    pub fn check_at(&mut self, now: Instant) -> RateLimit {
+        let expired = match self.window_start {
+            Some(start) => now.saturating_duration_since(start) >= self.window,
+            None => true,
+        };
+
+        if expired {
+            self.window_start = Some(now);
+            self.count = 0;
+        }
+
+        if self.count < self.limit {
+            self.count += 1;
+            RateLimit::Allow {
+                suppressed: self.take_suppressed(),
+            }
+        } else {
+            self.suppressed += 1;
+            RateLimit::Suppress
+        }
+    }
+
+    /// Return the number of actions suppressed since the last one that was allowed
+    /// and reset it to zero.
+    pub fn take_suppressed(&mut self) -> u64 {
+        std::mem::replace(&mut self.suppressed, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimiter};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rate_limiter_within_limit() {
+        let mut limiter = RateLimiter::new(3, Duration::from_secs(1));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(RateLimit::Allow { suppressed: 0 }, limiter.check_at(now));
+        }
+    }
+
+    #[test]
+    fn test_rate_limiter_suppressed_until_next_window() {
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(1));
+        let start = Instant::now();
+
+        assert_eq!(RateLimit::Allow { suppressed: 0 }, limiter.check_at(start));
+        assert_eq!(RateLimit::Suppress, limiter.check_at(start));
+        assert_eq!(
+            RateLimit::Suppress,
+            limiter.check_at(start + Duration::from_millis(999))
+        );
+
+        let next = start + Duration::from_secs(1);
+        assert_eq!(RateLimit::Allow { suppressed: 2 }, limiter.check_at(next));
+        assert_eq!(RateLimit::Suppress, limiter.check_at(next));
+    }
+
+    #[test]
+    fn test_rate_limiter_take_suppressed() {
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+
+        limiter.check_at(now);
+        limiter.check_at(now);
+        limiter.check_at(now);
+
+        assert_eq!(2, limiter.take_suppressed());
+        assert_eq!(0, limiter.take_suppressed());
+    }
+
+    #[test]
+    fn test_rate_limiter_zero_limit() {
+        let mut limiter = RateLimiter::new(0, Duration::from_secs(1));
+        let now = Instant::now();
+
+        assert_eq!(RateLimit::Suppress, limiter.check_at(now));
+        assert_eq!(1, limiter.take_suppressed());
+    }
+}
@@ -0,0 +1,391 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Sending events to a Redis list or stream
+//!
+//! Commands for each batch of events are built by a `RedisTarget`. A `Connector`
+//! that sends them to a Redis server is available when the `redis` feature is
+//! enabled, for use with a `Forwarder`.
+
+use crate::types::RedeyeError;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+
+/// Field used for events that aren't JSON objects when adding them to a stream.
+const DEFAULT_STREAM_FIELD: &str = "event";
+
+/// Kind of Redis key events are added to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedisMode {
+    /// Append events to a list with `RPUSH`, as read by the `redis` input of Logstash.
+    List,
+    /// Add events to a stream with `XADD`.
+    Stream,
+}
+
+impl fmt::Display for RedisMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedisMode::List => f.write_str("list"),
+            RedisMode::Stream => f.write_str("stream"),
+        }
+    }
+}
+
+impl FromStr for RedisMode {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "list" => Ok(RedisMode::List),
+            "stream" => Ok(RedisMode::Stream),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown Redis mode '{}', expected one of list, stream",
+                s
+            ))),
+        }
+    }
+}
+
+/// Single command, the name followed by its arguments.
+pub type RedisCommand = Vec<Vec<u8>>;
+
+/// Key that events are added to and how they're added.
+///
+/// In stream mode, each field of an event becomes a field of its stream entry,
+/// with nested fields joined by dots (e.g. `request_headers.host`). Alternatively,
+/// the entire event can be added as JSON in a single field.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::redis::{RedisMode, RedisTarget};
+///
+/// let target = RedisTarget::new("logstash").mode(RedisMode::Stream);
+/// let commands = target.commands(&[br#"{"status_code":200,"method":"GET"}"#.to_vec()]);
+///
+/// let args: Vec<&[u8]> = commands[0].iter().map(|a| a.as_slice()).collect();
+/// assert_eq!(
+///     vec![&b"XADD"[..], b"logstash", b"*", b"method", b"GET", b"status_code", b"200"],
+///     args
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisTarget {
+    key: String,
+    mode: RedisMode,
+    stream_field: Option<String>,
+}
+
+impl RedisTarget {
+    pub fn new<S>(key: S) -> Self
+    where
+        S: Into<String>,
+    {
+        RedisTarget {
+            key: key.into(),
+            mode: RedisMode::List,
+            stream_field: None,
+        }
+    }
+
+    /// Set the kind of key events are added to.
+    pub fn mode(mut self, mode: RedisMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// In stream mode, add each event as JSON in a single field with this name
+    /// instead of adding each of its fields.
+    pub fn stream_field<S>(mut self, field: Option<S>) -> Self
+    where
+        S: Into<String>,
+    {
+        self.stream_field = field.map(|f| f.into());
+        self
+    }
+
+    /// Commands to add a batch of serialized events: a single `RPUSH` for a list
+    /// or an `XADD` for each event for a stream.
+    pub fn commands(&self, records: &[Vec<u8>]) -> Vec<RedisCommand> {
+        match self.mode {
+            RedisMode::List => {
+                let mut command = vec![b"RPUSH".to_vec(), self.key.clone().into_bytes()];
+                command.extend(records.iter().cloned());
+                vec![command]
+            }
+            RedisMode::Stream => records.iter().map(|r| self.xadd(r)).collect(),
+        }
+    }
+
+    fn xadd(&self, record: &[u8]) -> RedisCommand {
+        let mut command = vec![b"XADD".to_vec(), self.key.clone().into_bytes(), b"*".to_vec()];
+
+        let event = match self.stream_field {
+            Some(_) => None,
+            None => match serde_json::from_slice(record) {
+                Ok(Value::Object(map)) => Some(map),
+                _ => None,
+            },
+        };
+
+        match event {
+            Some(map) => {
+                let mut fields = Vec::new();
+                flatten("", &map, &mut fields);
+                for (name, value) in fields {
+                    command.push(name.into_bytes());
+                    command.push(value.into_bytes());
+                }
+            }
+            None => {
+                let field = self.stream_field.as_deref().unwrap_or(DEFAULT_STREAM_FIELD);
+                command.push(field.as_bytes().to_vec());
+                command.push(record.to_vec());
+            }
+        }
+
+        command
+    }
+}
+
+/// Add the fields of an object to a list of names and values, joining the names
+/// of nested objects with dots and skipping null values.
+fn flatten(prefix: &str, map: &Map<String, Value>, out: &mut Vec<(String, String)>) {
+    for (name, value) in map {
+        let name = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+
+        match value {
+            Value::Null => {}
+            Value::Object(nested) => flatten(&name, nested, out),
+            Value::String(s) => out.push((name, s.clone())),
+            v => out.push((name, v.to_string())),
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::connector::RedisConnector;
+
+#[cfg(feature = "redis")]
+mod connector {
+    use super::RedisTarget;
+    use crate::forward::Connector;
+    use crate::types::{RedeyeError, RedeyeResult};
+    use std::io;
+    use std::time::Duration;
+
+    /// Default amount of time to wait for a connection or for replies to commands.
+    const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Connector that adds records to a Redis list or stream with pipelined commands.
+    pub struct RedisConnector {
+        client: ::redis::Client,
+        target: RedisTarget,
+        timeout: Duration,
+    }
+
+    impl RedisConnector {
+        /// Create a connector for the server at the URL, e.g. `redis://localhost:6379/0`.
+        pub fn new(url: &str, target: RedisTarget) -> RedeyeResult<Self> {
+            let client = ::redis::Client::open(url)
+                .map_err(|e| RedeyeError::ConfigError(format!("invalid Redis URL '{}': {}", url, e)))?;
+
+            Ok(RedisConnector {
+                client,
+                target,
+                timeout: DEFAULT_TIMEOUT,
+            })
+        }
+
+        /// Set the longest amount of time to wait for a connection or for replies
+        /// before the connection is considered lost.
+        pub fn timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+    }
+
+    impl Connector for RedisConnector {
+        type Stream = ::redis::Connection;
+
+        fn connect(&self) -> io::Result<::redis::Connection> {
+            let conn = self.client.get_connection_with_timeout(self.timeout).map_err(to_io)?;
+            conn.set_write_timeout(Some(self.timeout)).map_err(to_io)?;
+            conn.set_read_timeout(Some(self.timeout)).map_err(to_io)?;
+            Ok(conn)
+        }
+
+        fn send(&self, conn: &mut ::redis::Connection, records: &[Vec<u8>], _delimiter: u8) -> io::Result<()> {
+            let mut pipe = ::redis::pipe();
+            for command in self.target.commands(records) {
+                let mut args = command.into_iter();
+                if let Some(name) = args.next() {
+                    let cmd = pipe.cmd(&String::from_utf8_lossy(&name));
+                    for arg in args {
+                        cmd.arg(arg);
+                    }
+                    cmd.ignore();
+                }
+            }
+
+            pipe.query::<()>(conn).map_err(|e| {
+                if e.is_io_error() || e.is_connection_dropped() || e.is_timeout() {
+                    to_io(e)
+                } else {
+                    // The server replied with an error, e.g. because the key has the
+                    // wrong type, so sending the same commands again won't help.
+                    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+                }
+            })
+        }
+    }
+
+    fn to_io(e: ::redis::RedisError) -> io::Error {
+        io::Error::other(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RedisMode, RedisTarget};
+    #[cfg(feature = "redis")]
+    use {
+        super::RedisConnector,
+        crate::forward::Forwarder,
+        std::io::{BufRead, BufReader, Read, Write},
+        std::net::TcpListener,
+        std::thread,
+    };
+
+    fn args(command: &[Vec<u8>]) -> Vec<String> {
+        command.iter().map(|a| String::from_utf8(a.clone()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_redis_mode_from_str() {
+        assert_eq!(RedisMode::List, "list".parse().unwrap());
+        assert_eq!(RedisMode::Stream, "stream".parse().unwrap());
+        assert!("hash".parse::<RedisMode>().is_err());
+    }
+
+    #[test]
+    fn test_redis_target_list() {
+        let target = RedisTarget::new("logstash");
+        let commands = target.commands(&[b"{\"a\":1}".to_vec(), b"{\"a\":2}".to_vec()]);
+
+        assert_eq!(1, commands.len());
+        assert_eq!(vec!["RPUSH", "logstash", "{\"a\":1}", "{\"a\":2}"], args(&commands[0]));
+    }
+
+    #[test]
+    fn test_redis_target_stream_flattened() {
+        let target = RedisTarget::new("logs").mode(RedisMode::Stream);
+        let record =
+            br#"{"remote_host":"10.1.1.1","user":null,"request_headers":{"host":"example.com"},"status_code":404}"#;
+        let commands = target.commands(&[record.to_vec(), b"not json".to_vec()]);
+
+        assert_eq!(2, commands.len());
+        assert_eq!(
+            vec![
+                "XADD",
+                "logs",
+                "*",
+                "remote_host",
+                "10.1.1.1",
+                "request_headers.host",
+                "example.com",
+                "status_code",
+                "404"
+            ],
+            args(&commands[0])
+        );
+        assert_eq!(vec!["XADD", "logs", "*", "event", "not json"], args(&commands[1]));
+    }
+
+    #[test]
+    fn test_redis_target_stream_field() {
+        let target = RedisTarget::new("logs")
+            .mode(RedisMode::Stream)
+            .stream_field(Some("message"));
+        let commands = target.commands(&[b"{\"a\":1}".to_vec()]);
+
+        assert_eq!(vec!["XADD", "logs", "*", "message", "{\"a\":1}"], args(&commands[0]));
+    }
+
+    /// Read commands sent by a client, replying OK to each, until it disconnects.
+    #[cfg(feature = "redis")]
+    fn mock_server(listener: TcpListener) -> Vec<Vec<String>> {
+        let (conn, _) = listener.accept().unwrap();
+        let mut writer = conn.try_clone().unwrap();
+        let mut reader = BufReader::new(conn);
+        let mut commands = Vec::new();
+
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            let count: usize = line.trim_end()[1..].parse().unwrap();
+            let mut command = Vec::new();
+            for _ in 0..count {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+                let len: usize = line.trim_end()[1..].parse().unwrap();
+                let mut arg = vec![0; len + 2];
+                reader.read_exact(&mut arg).unwrap();
+                arg.truncate(len);
+                command.push(String::from_utf8(arg).unwrap());
+            }
+
+            writer.write_all(b"+OK\r\n").unwrap();
+            commands.push(command);
+            line.clear();
+        }
+
+        commands
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redis_connector_list() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let server = thread::spawn(move || mock_server(listener));
+
+        let connector = RedisConnector::new(&url, RedisTarget::new("logstash")).unwrap();
+        let mut sender = Forwarder::new(connector).start().unwrap();
+        sender.write_all(b"{\"a\":1}\n{\"a\":2}\n").unwrap();
+        sender.finish().unwrap();
+
+        let pushed: Vec<String> = server
+            .join()
+            .unwrap()
+            .into_iter()
+            .filter(|c| c[0] == "RPUSH")
+            .flat_map(|c| {
+                assert_eq!("logstash", c[1]);
+                c.into_iter().skip(2)
+            })
+            .collect();
+
+        assert_eq!(vec!["{\"a\":1}", "{\"a\":2}"], pushed);
+    }
+}
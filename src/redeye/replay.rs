@@ -0,0 +1,262 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Replaying existing access logs with the pacing they were written with
+
+use crate::parser::COMMON_LOG_TIMESTAMP;
+use crate::timestamp::TimestampContext;
+use chrono::{DateTime, FixedOffset};
+use std::ops::Range;
+use std::time::Duration;
+
+/// Timestamp of an access log line and where it is in the line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineTimestamp {
+    pub timestamp: DateTime<FixedOffset>,
+    pub range: Range<usize>,
+}
+
+impl LineTimestamp {
+    /// Find the timestamp of a Common or Combined format line: the first value
+    /// in square brackets that can be parsed as a timestamp. The range is of
+    /// the value, not including the brackets.
+    pub fn find(line: &str, context: &TimestampContext) -> Option<Self> {
+        let mut from = 0;
+
+        while let Some(open) = line[from..].find('[').map(|i| from + i) {
+            let close = open + 1 + line[open + 1..].find(']')?;
+            if let Ok(timestamp) = context.parse(&line[open + 1..close], COMMON_LOG_TIMESTAMP) {
+                return Some(LineTimestamp {
+                    timestamp,
+                    range: open + 1..close,
+                });
+            }
+
+            from = open + 1;
+        }
+
+        None
+    }
+
+    /// Copy of the line with the timestamp replaced by a new one, written in the
+    /// same format and offset as the original.
+    pub fn rewrite(&self, line: &str, timestamp: DateTime<FixedOffset>) -> String {
+        let replacement = timestamp
+            .with_timezone(self.timestamp.offset())
+            .format(COMMON_LOG_TIMESTAMP)
+            .to_string();
+
+        let mut out = String::with_capacity(line.len());
+        out.push_str(&line[..self.range.start]);
+        out.push_str(&replacement);
+        out.push_str(&line[self.range.end..]);
+        out
+    }
+}
+
+/// When each line of a replayed log is due to be written, relative to the start
+/// of the replay.
+///
+/// Lines are due after the same gap from the first line as they had originally,
+/// divided by the speed. Lines with a timestamp earlier than one before them are
+/// due straight away rather than going back in time. When the log is replayed
+/// again, the next pass starts one average gap after the last line so there is
+/// no pause or burst between passes.
+///
+/// # Example
+///
+/// ```rust
+/// use chrono::DateTime;
+/// use redeye::replay::ReplaySchedule;
+/// use std::time::Duration;
+///
+/// let mut schedule = ReplaySchedule::new(2.0);
+/// let first = DateTime::parse_from_rfc3339("2020-10-10T13:55:36-07:00").unwrap();
+/// let second = DateTime::parse_from_rfc3339("2020-10-10T13:55:40-07:00").unwrap();
+///
+/// assert_eq!(Duration::from_secs(0), schedule.due(first));
+/// assert_eq!(Duration::from_secs(2), schedule.due(second));
+///
+/// schedule.restart();
+/// assert_eq!(Duration::from_secs(4), schedule.due(first));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplaySchedule {
+    speed: f64,
+    pass_start: Duration,
+    first: Option<DateTime<FixedOffset>>,
+    latest: Duration,
+    timestamped: u64,
+}
+
+impl ReplaySchedule {
+    /// Create a schedule that replays `speed` times faster than the original, or
+    /// as fast as possible if the speed is zero.
+    pub fn new(speed: f64) -> Self {
+        ReplaySchedule {
+            speed: speed.max(0.0),
+            pass_start: Duration::from_secs(0),
+            first: None,
+            latest: Duration::from_secs(0),
+            timestamped: 0,
+        }
+    }
+
+    /// Time after the start of the replay that a line with the given timestamp
+    /// is due to be written.
+    pub fn due(&mut self, timestamp: DateTime<FixedOffset>) -> Duration {
+        let first = *self.first.get_or_insert(timestamp);
+        self.timestamped += 1;
+
+        if self.speed == 0.0 {
+            return self.pass_start;
+        }
+
+        let original = (timestamp - first).to_std().unwrap_or_default();
+        let due = (self.pass_start + original.div_f64(self.speed)).max(self.latest);
+        self.latest = due;
+        due
+    }
+
+    /// Start another pass over the same log.
+    pub fn restart(&mut self) {
+        let gap = if self.timestamped > 1 {
+            (self.latest - self.pass_start).div_f64((self.timestamped - 1) as f64)
+        } else {
+            Duration::from_secs(0)
+        };
+
+        self.pass_start = self.latest + gap;
+        self.latest = self.pass_start;
+        self.first = None;
+        self.timestamped = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LineTimestamp, ReplaySchedule};
+    use crate::timestamp::TimestampContext;
+    use chrono::DateTime;
+    use std::time::Duration;
+
+    const LINE: &str = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
+
+    fn ts(value: &str) -> chrono::DateTime<chrono::FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    #[test]
+    fn test_line_timestamp_find() {
+        let found = LineTimestamp::find(LINE, &TimestampContext::new()).unwrap();
+        assert_eq!(ts("2000-10-10T13:55:36-07:00"), found.timestamp);
+        assert_eq!("10/Oct/2000:13:55:36 -0700", &LINE[found.range]);
+    }
+
+    #[test]
+    fn test_line_timestamp_find_skips_other_brackets() {
+        let line = "[::1] - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326";
+        let found = LineTimestamp::find(line, &TimestampContext::new()).unwrap();
+        assert_eq!(ts("2000-10-10T13:55:36-07:00"), found.timestamp);
+    }
+
+    #[test]
+    fn test_line_timestamp_find_none() {
+        let context = TimestampContext::new();
+        assert_eq!(None, LineTimestamp::find("", &context));
+        assert_eq!(None, LineTimestamp::find("no timestamp here", &context));
+        assert_eq!(
+            None,
+            LineTimestamp::find("127.0.0.1 - - [10/Foo/2000:13:55:36 -0700]", &context)
+        );
+        assert_eq!(
+            None,
+            LineTimestamp::find("127.0.0.1 - - [10/Oct/2000:13:55:36 -0700", &context)
+        );
+    }
+
+    #[test]
+    fn test_line_timestamp_rewrite() {
+        let found = LineTimestamp::find(LINE, &TimestampContext::new()).unwrap();
+        let rewritten = found.rewrite(LINE, ts("2020-07-04T20:00:00+00:00"));
+
+        assert_eq!(
+            "127.0.0.1 - frank [04/Jul/2020:13:00:00 -0700] \"GET /index.html HTTP/1.0\" 200 2326",
+            rewritten
+        );
+    }
+
+    #[test]
+    fn test_replay_schedule_original_pacing() {
+        let mut schedule = ReplaySchedule::new(1.0);
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T13:55:36Z")));
+        assert_eq!(Duration::from_secs(1), schedule.due(ts("2020-10-10T13:55:37Z")));
+        assert_eq!(Duration::from_secs(5), schedule.due(ts("2020-10-10T13:55:41Z")));
+    }
+
+    #[test]
+    fn test_replay_schedule_speed() {
+        let mut schedule = ReplaySchedule::new(4.0);
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T13:55:36Z")));
+        assert_eq!(Duration::from_millis(250), schedule.due(ts("2020-10-10T13:55:37Z")));
+        assert_eq!(Duration::from_secs(1), schedule.due(ts("2020-10-10T13:55:40Z")));
+    }
+
+    #[test]
+    fn test_replay_schedule_as_fast_as_possible() {
+        let mut schedule = ReplaySchedule::new(0.0);
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T13:55:36Z")));
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T14:55:36Z")));
+        schedule.restart();
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T13:55:36Z")));
+    }
+
+    #[test]
+    fn test_replay_schedule_out_of_order() {
+        let mut schedule = ReplaySchedule::new(1.0);
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T13:55:36Z")));
+        assert_eq!(Duration::from_secs(3), schedule.due(ts("2020-10-10T13:55:39Z")));
+        assert_eq!(Duration::from_secs(3), schedule.due(ts("2020-10-10T13:55:37Z")));
+        assert_eq!(Duration::from_secs(3), schedule.due(ts("2020-10-10T13:55:30Z")));
+        assert_eq!(Duration::from_secs(4), schedule.due(ts("2020-10-10T13:55:40Z")));
+    }
+
+    #[test]
+    fn test_replay_schedule_restart() {
+        let mut schedule = ReplaySchedule::new(1.0);
+        schedule.due(ts("2020-10-10T13:55:36Z"));
+        schedule.due(ts("2020-10-10T13:55:38Z"));
+        schedule.due(ts("2020-10-10T13:55:40Z"));
+
+        schedule.restart();
+        assert_eq!(Duration::from_secs(6), schedule.due(ts("2020-10-10T13:55:36Z")));
+        assert_eq!(Duration::from_secs(8), schedule.due(ts("2020-10-10T13:55:38Z")));
+        assert_eq!(Duration::from_secs(10), schedule.due(ts("2020-10-10T13:55:40Z")));
+
+        schedule.restart();
+        assert_eq!(Duration::from_secs(12), schedule.due(ts("2020-10-10T13:55:36Z")));
+    }
+
+    #[test]
+    fn test_replay_schedule_restart_single_line() {
+        let mut schedule = ReplaySchedule::new(1.0);
+        schedule.due(ts("2020-10-10T13:55:36Z"));
+        schedule.restart();
+        assert_eq!(Duration::from_secs(0), schedule.due(ts("2020-10-10T13:55:36Z")));
+    }
+}
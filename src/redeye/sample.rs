@@ -0,0 +1,274 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Keeping only a sample of events
+
+use crate::types::{LogEvent, RedeyeError, RedeyeResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Increment used for each step of the splitmix64 generator.
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// FNV-1a 64 bit offset basis and prime.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Finalizer of the splitmix64 generator, also used to mix hashes.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Map a random 64 bit value to a float in `[0, 1)`.
+fn unit(v: u64) -> f64 {
+    (v >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn validate_rate(rate: f64) -> RedeyeResult<f64> {
+    if rate.is_finite() && (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(RedeyeError::ConfigError(format!(
+            "invalid sample rate {}, must be between 0 and 1",
+            rate
+        )))
+    }
+}
+
+#[derive(Debug)]
+enum Method {
+    Every { n: u64, count: AtomicU64 },
+    Rate { rate: f64, state: AtomicU64 },
+    Key { path: String, rate: f64, seed: u64 },
+}
+
+/// Decides which events to keep, either deterministically or at random.
+///
+/// Samplers may be shared between threads. Sampling every `n` events or at a
+/// random rate depends only on the order events are sampled in, so the same
+/// events are kept for the same seed as long as they're sampled in the order
+/// they were read (see `is_ordered`).
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::sample::Sampler;
+/// use redeye::types::LogEvent;
+/// use std::collections::HashMap;
+///
+/// let sampler = Sampler::every(3).unwrap();
+/// let event = LogEvent::from(HashMap::new());
+/// let kept: Vec<bool> = (0..6).map(|_| sampler.keep(&event)).collect();
+///
+/// assert_eq!(vec![true, false, false, true, false, false], kept);
+/// ```
+#[derive(Debug)]
+pub struct Sampler {
+    method: Method,
+}
+
+impl Sampler {
+    /// Keep the first of every `n` events. Return a `ConfigError` if `n` is zero.
+    pub fn every(n: u64) -> RedeyeResult<Self> {
+        if n == 0 {
+            return Err(RedeyeError::ConfigError(
+                "invalid sample interval 0, must be at least 1".to_owned(),
+            ));
+        }
+
+        Ok(Sampler {
+            method: Method::Every {
+                n,
+                count: AtomicU64::new(0),
+            },
+        })
+    }
+
+    /// Keep each event with probability `rate` using a random number generator
+    /// initialized with `seed`. Return a `ConfigError` if `rate` isn't between
+    /// zero and one.
+    pub fn rate(rate: f64, seed: u64) -> RedeyeResult<Self> {
+        Ok(Sampler {
+            method: Method::Rate {
+                rate: validate_rate(rate)?,
+                state: AtomicU64::new(seed),
+            },
+        })
+    }
+
+    /// Keep approximately `rate` of events based on a hash of the field at the
+    /// given dotted path, so that all events with the same value for the field
+    /// are either kept or dropped. Events without the field are treated as if
+    /// they all had the same value. Return a `ConfigError` if `rate` isn't between
+    /// zero and one.
+    pub fn key<S>(path: S, rate: f64, seed: u64) -> RedeyeResult<Self>
+    where
+        S: Into<String>,
+    {
+        Ok(Sampler {
+            method: Method::Key {
+                path: path.into(),
+                rate: validate_rate(rate)?,
+                seed,
+            },
+        })
+    }
+
+    /// Return true if which events are kept depends on the order they're sampled
+    /// in rather than on their contents. Ordered samplers need to see events in the
+    /// order they were read, e.g. after they've been parsed by several threads.
+    pub fn is_ordered(&self) -> bool {
+        !matches!(self.method, Method::Key { .. })
+    }
+
+    /// Return true if the next event should be kept, without looking at it.
+    /// Samplers that aren't ordered keep every event this way.
+    pub fn keep_next(&self) -> bool {
+        match &self.method {
+            Method::Every { n, count } => count.fetch_add(1, Ordering::Relaxed) % n == 0,
+            Method::Rate { rate, state } => {
+                let s = state.fetch_add(GAMMA, Ordering::Relaxed).wrapping_add(GAMMA);
+                unit(mix(s)) < *rate
+            }
+            Method::Key { .. } => true,
+        }
+    }
+
+    /// Return true if the event should be kept.
+    pub fn keep(&self, event: &LogEvent) -> bool {
+        match &self.method {
+            Method::Every { .. } | Method::Rate { .. } => self.keep_next(),
+            Method::Key { path, rate, seed } => {
+                let value = event
+                    .lookup(path)
+                    .and_then(|v| serde_json::to_string(v).ok())
+                    .unwrap_or_default();
+
+                let hash = seed
+                    .to_le_bytes()
+                    .iter()
+                    .chain(value.as_bytes())
+                    .fold(FNV_OFFSET, |h, b| (h ^ u64::from(*b)).wrapping_mul(FNV_PRIME));
+
+                unit(mix(hash)) < *rate
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sampler;
+    use crate::types::{LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+
+    fn event_from(host: &str) -> LogEvent {
+        let mut event = LogEvent::from(HashMap::new());
+        event.insert("remote_host", LogFieldValue::Text(host.to_owned()));
+        event
+    }
+
+    #[test]
+    fn test_sampler_every() {
+        let sampler = Sampler::every(2).unwrap();
+        let event = event_from("127.0.0.1");
+        let kept: Vec<bool> = (0..5).map(|_| sampler.keep(&event)).collect();
+
+        assert_eq!(vec![true, false, true, false, true], kept);
+        assert!(Sampler::every(0).unwrap_err().is_config_error());
+    }
+
+    #[test]
+    fn test_sampler_every_one_keeps_all() {
+        let sampler = Sampler::every(1).unwrap();
+        let event = event_from("127.0.0.1");
+
+        assert!((0..10).all(|_| sampler.keep(&event)));
+    }
+
+    #[test]
+    fn test_sampler_rate_bounds() {
+        let event = event_from("127.0.0.1");
+        let none = Sampler::rate(0.0, 1).unwrap();
+        let all = Sampler::rate(1.0, 1).unwrap();
+
+        assert!((0..1000).all(|_| !none.keep(&event)));
+        assert!((0..1000).all(|_| all.keep(&event)));
+        assert!(Sampler::rate(1.5, 1).unwrap_err().is_config_error());
+        assert!(Sampler::rate(-0.1, 1).unwrap_err().is_config_error());
+        assert!(Sampler::rate(f64::NAN, 1).unwrap_err().is_config_error());
+    }
+
+    #[test]
+    fn test_sampler_rate_approximate() {
+        let event = event_from("127.0.0.1");
+        let sampler = Sampler::rate(0.25, 42).unwrap();
+        let kept = (0..10_000).filter(|_| sampler.keep(&event)).count();
+
+        assert!(kept > 2_300 && kept < 2_700, "kept {}", kept);
+    }
+
+    #[test]
+    fn test_sampler_rate_seed_reproducible() {
+        let event = event_from("127.0.0.1");
+        let run = |seed| {
+            let sampler = Sampler::rate(0.5, seed).unwrap();
+            (0..100).map(|_| sampler.keep(&event)).collect::<Vec<bool>>()
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn test_sampler_key_consistent() {
+        let sampler = Sampler::key("remote_host", 0.5, 0).unwrap();
+        let hosts: Vec<String> = (0..200).map(|i| format!("10.0.0.{}", i)).collect();
+        let first: Vec<bool> = hosts.iter().map(|h| sampler.keep(&event_from(h))).collect();
+        let second: Vec<bool> = hosts.iter().map(|h| sampler.keep(&event_from(h))).collect();
+
+        assert_eq!(first, second);
+
+        let kept = first.iter().filter(|k| **k).count();
+        assert!(kept > 70 && kept < 130, "kept {}", kept);
+    }
+
+    #[test]
+    fn test_sampler_key_missing_field() {
+        let sampler = Sampler::key("remote_user", 0.5, 0).unwrap();
+        let a = sampler.keep(&event_from("10.0.0.1"));
+        let b = sampler.keep(&event_from("10.0.0.2"));
+
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_sampler_keep_next() {
+        let every = Sampler::every(3).unwrap();
+        let key = Sampler::key("remote_host", 0.0, 0).unwrap();
+        let kept: Vec<bool> = (0..4).map(|_| every.keep_next()).collect();
+
+        assert!(every.is_ordered());
+        assert!(Sampler::rate(0.5, 1).unwrap().is_ordered());
+        assert!(!key.is_ordered());
+        assert_eq!(vec![true, false, false, true], kept);
+        assert!(key.keep_next());
+        assert!(!key.keep(&event_from("10.0.0.1")));
+    }
+}
@@ -0,0 +1,470 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Scenarios that change generated traffic over time, for load testing
+//!
+//! A `Scenario` combines any number of modifiers: periodic bursts that multiply
+//! the rate lines are written at, hot clients that make a fixed fraction of all
+//! requests, scanners that request hostile paths, and status spikes where a
+//! fraction of requests fail with a particular status for a while. Bursts and
+//! status spikes are active during periodic windows measured from when lines
+//! start being written.
+
+use crate::types::{parse_duration, RedeyeError};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Periodic window of time during which a modifier is active. The first window
+/// starts `every` after the start, lasts for `duration`, and repeats `every`
+/// after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    every: Duration,
+    duration: Duration,
+}
+
+impl Window {
+    pub fn new(every: Duration, duration: Duration) -> Self {
+        Window { every, duration }
+    }
+
+    /// True if the window is active the given time after the start.
+    pub fn active(&self, elapsed: Duration) -> bool {
+        if self.every.as_nanos() == 0 || elapsed < self.every {
+            return false;
+        }
+
+        elapsed.as_nanos() % self.every.as_nanos() < self.duration.as_nanos()
+    }
+
+    /// Window from the `every` and `duration` settings of a modifier.
+    fn from_settings(kind: &str, settings: &[(&str, &str)]) -> Result<Self, RedeyeError> {
+        let every = parse_duration(required(kind, settings, "every")?)?;
+        let duration = parse_duration(required(kind, settings, "duration")?)?;
+
+        if every.as_nanos() == 0 || duration.as_nanos() == 0 {
+            return Err(RedeyeError::ConfigError(format!(
+                "{} 'every' and 'duration' must be greater than zero",
+                kind
+            )));
+        }
+
+        if duration > every {
+            return Err(RedeyeError::ConfigError(format!(
+                "{} 'duration' must not be longer than 'every'",
+                kind
+            )));
+        }
+
+        Ok(Window::new(every, duration))
+    }
+}
+
+/// Periodic spike in the rate of requests, e.g. `every=30s,duration=5s,multiplier=20`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Burst {
+    pub window: Window,
+    pub multiplier: f64,
+}
+
+impl FromStr for Burst {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let settings = settings("burst", s, &["every", "duration", "multiplier"])?;
+        let window = Window::from_settings("burst", &settings)?;
+        let multiplier: f64 = number("burst", "multiplier", required("burst", &settings, "multiplier")?)?;
+
+        if !multiplier.is_finite() || multiplier <= 0.0 {
+            return Err(RedeyeError::ConfigError(
+                "burst 'multiplier' must be greater than zero".to_owned(),
+            ));
+        }
+
+        Ok(Burst { window, multiplier })
+    }
+}
+
+/// Single client that makes a fraction of all requests, e.g. `10.0.0.5:0.4`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HotClient {
+    pub address: IpAddr,
+    pub fraction: f64,
+}
+
+impl FromStr for HotClient {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // IPv6 addresses contain colons so the fraction is after the last one
+        let i = s.rfind(':').ok_or_else(|| {
+            RedeyeError::ConfigError(format!("invalid hot client '{}', expected ADDRESS:FRACTION", s))
+        })?;
+
+        let address = s[..i]
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .map_err(|_| RedeyeError::ConfigError(format!("invalid hot client address '{}'", &s[..i])))?;
+
+        Ok(HotClient {
+            address,
+            fraction: fraction("hot client", "fraction", &s[i + 1..])?,
+        })
+    }
+}
+
+/// Periodic failure of a fraction of requests with a particular status, e.g.
+/// `503:every=60s,duration=10s,fraction=0.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusSpike {
+    pub status: u16,
+    pub window: Window,
+    pub fraction: f64,
+}
+
+impl FromStr for StatusSpike {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let i = s.find(':').ok_or_else(|| {
+            RedeyeError::ConfigError(format!(
+                "invalid status spike '{}', expected STATUS:every=DURATION,duration=DURATION,fraction=FRACTION",
+                s
+            ))
+        })?;
+
+        let status = s[..i]
+            .parse()
+            .ok()
+            .filter(|s| (100..=599).contains(s))
+            .ok_or_else(|| RedeyeError::ConfigError(format!("invalid status spike status '{}'", &s[..i])))?;
+
+        let settings = settings("status spike", &s[i + 1..], &["every", "duration", "fraction"])?;
+        Ok(StatusSpike {
+            status,
+            window: Window::from_settings("status spike", &settings)?,
+            fraction: fraction(
+                "status spike",
+                "fraction",
+                required("status spike", &settings, "fraction")?,
+            )?,
+        })
+    }
+}
+
+/// Split `name=value` settings separated by commas, making sure each is known.
+fn settings<'a>(kind: &str, s: &'a str, known: &[&str]) -> Result<Vec<(&'a str, &'a str)>, RedeyeError> {
+    s.split(',')
+        .map(|part| {
+            let i = part.find('=').ok_or_else(|| {
+                RedeyeError::ConfigError(format!("invalid {} setting '{}', expected name=value", kind, part))
+            })?;
+
+            let name = part[..i].trim();
+            if !known.contains(&name) {
+                return Err(RedeyeError::ConfigError(format!(
+                    "unknown {} setting '{}', expected one of {}",
+                    kind,
+                    name,
+                    known.join(", ")
+                )));
+            }
+
+            Ok((name, part[i + 1..].trim()))
+        })
+        .collect()
+}
+
+fn required<'a>(kind: &str, settings: &[(&str, &'a str)], name: &str) -> Result<&'a str, RedeyeError> {
+    settings
+        .iter()
+        .rev()
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| *v)
+        .ok_or_else(|| RedeyeError::ConfigError(format!("{} setting '{}' is required", kind, name)))
+}
+
+fn number<T: FromStr>(kind: &str, name: &str, value: &str) -> Result<T, RedeyeError> {
+    value.parse().map_err(|_| {
+        RedeyeError::ConfigError(format!(
+            "invalid value '{}' for {} setting '{}', expected a number",
+            value, kind, name
+        ))
+    })
+}
+
+fn fraction(kind: &str, name: &str, value: &str) -> Result<f64, RedeyeError> {
+    let fraction: f64 = number(kind, name, value)?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(RedeyeError::ConfigError(format!(
+            "{} '{}' must be between 0 and 1",
+            kind, name
+        )));
+    }
+
+    Ok(fraction)
+}
+
+/// Modifiers that change generated traffic over time.
+///
+/// Modifiers compose: the rate during overlapping bursts is multiplied by each
+/// of their multipliers, hot clients each make their own fraction of requests,
+/// and when status spikes overlap each one applies to its fraction of the
+/// requests the ones before it didn't.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::scenario::Scenario;
+/// use std::time::Duration;
+///
+/// let scenario = Scenario::new()
+///     .burst("every=30s,duration=5s,multiplier=20".parse().unwrap())
+///     .status_spike("503:every=60s,duration=10s,fraction=0.5".parse().unwrap());
+///
+/// assert_eq!(10.0, scenario.rate(10.0, Duration::from_secs(10)));
+/// assert_eq!(200.0, scenario.rate(10.0, Duration::from_secs(32)));
+/// assert!(scenario.active_status_spikes(Duration::from_secs(32)).next().is_none());
+/// assert_eq!(1, scenario.active_status_spikes(Duration::from_secs(62)).count());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    bursts: Vec<Burst>,
+    hot_clients: Vec<HotClient>,
+    scanner_rate: f64,
+    status_spikes: Vec<StatusSpike>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn burst(mut self, burst: Burst) -> Self {
+        self.bursts.push(burst);
+        self
+    }
+
+    pub fn hot_client(mut self, client: HotClient) -> Self {
+        self.hot_clients.push(client);
+        self
+    }
+
+    /// Set the fraction of requests (between 0 and 1) made by scanners. Values
+    /// outside that range are clamped to it.
+    pub fn scanner_rate(mut self, rate: f64) -> Self {
+        self.scanner_rate = if rate.is_nan() { 0.0 } else { rate.clamp(0.0, 1.0) };
+        self
+    }
+
+    pub fn status_spike(mut self, spike: StatusSpike) -> Self {
+        self.status_spikes.push(spike);
+        self
+    }
+
+    /// True if there are no modifiers, so traffic is the same as without one.
+    pub fn is_empty(&self) -> bool {
+        self.bursts.is_empty()
+            && self.hot_clients.is_empty()
+            && self.scanner_rate == 0.0
+            && self.status_spikes.is_empty()
+    }
+
+    /// Lines per second the given time after the start, when the base rate is
+    /// `base`.
+    pub fn rate(&self, base: f64, elapsed: Duration) -> f64 {
+        self.bursts
+            .iter()
+            .filter(|b| b.window.active(elapsed))
+            .fold(base, |rate, b| rate * b.multiplier)
+    }
+
+    /// Time after the start that the line after one due at `due` is due, when
+    /// the base rate is `base`.
+    pub fn next_due(&self, base: f64, due: Duration) -> Duration {
+        due + Duration::from_secs_f64(1.0 / self.rate(base, due))
+    }
+
+    pub fn hot_clients(&self) -> &[HotClient] {
+        &self.hot_clients
+    }
+
+    /// Fraction of requests made by scanners.
+    pub fn scanner_fraction(&self) -> f64 {
+        self.scanner_rate
+    }
+
+    /// Status spikes active the given time after the start.
+    pub fn active_status_spikes(&self, elapsed: Duration) -> impl Iterator<Item = &StatusSpike> {
+        self.status_spikes.iter().filter(move |s| s.window.active(elapsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Burst, HotClient, Scenario, StatusSpike, Window};
+    use std::time::Duration;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn test_window_active() {
+        let window = Window::new(secs(30), secs(5));
+        assert!(!window.active(secs(0)));
+        assert!(!window.active(secs(4)));
+        assert!(!window.active(secs(29)));
+        assert!(window.active(secs(30)));
+        assert!(window.active(Duration::from_millis(34_999)));
+        assert!(!window.active(secs(35)));
+        assert!(window.active(secs(62)));
+        assert!(!window.active(secs(89)));
+    }
+
+    #[test]
+    fn test_burst_from_str() {
+        let burst: Burst = "every=30s,duration=5s,multiplier=20".parse().unwrap();
+        assert_eq!(Window::new(secs(30), secs(5)), burst.window);
+        assert_eq!(20.0, burst.multiplier);
+
+        let burst: Burst = "multiplier=0.5, every=1m, duration=500ms".parse().unwrap();
+        assert_eq!(Window::new(secs(60), Duration::from_millis(500)), burst.window);
+        assert_eq!(0.5, burst.multiplier);
+    }
+
+    #[test]
+    fn test_burst_from_str_invalid() {
+        assert!("".parse::<Burst>().is_err());
+        assert!("every=30s,duration=5s".parse::<Burst>().is_err());
+        assert!("every=30s,multiplier=2".parse::<Burst>().is_err());
+        assert!("every=30s,duration=5s,multiplier=0".parse::<Burst>().is_err());
+        assert!("every=30s,duration=5s,multiplier=lots".parse::<Burst>().is_err());
+        assert!("every=5s,duration=30s,multiplier=2".parse::<Burst>().is_err());
+        assert!("every=0s,duration=0s,multiplier=2".parse::<Burst>().is_err());
+        assert!("every=30s,duration=5s,multiplier=2,jitter=1s".parse::<Burst>().is_err());
+    }
+
+    #[test]
+    fn test_hot_client_from_str() {
+        let client: HotClient = "10.0.0.5:0.4".parse().unwrap();
+        assert_eq!("10.0.0.5", client.address.to_string());
+        assert_eq!(0.4, client.fraction);
+
+        let client: HotClient = "2001:db8::1:0.25".parse().unwrap();
+        assert_eq!("2001:db8::1", client.address.to_string());
+        assert_eq!(0.25, client.fraction);
+
+        let client: HotClient = "[2001:db8::1]:0.25".parse().unwrap();
+        assert_eq!("2001:db8::1", client.address.to_string());
+    }
+
+    #[test]
+    fn test_hot_client_from_str_invalid() {
+        assert!("10.0.0.5".parse::<HotClient>().is_err());
+        assert!("10.0.0.5:1.5".parse::<HotClient>().is_err());
+        assert!("10.0.0.5:most".parse::<HotClient>().is_err());
+        assert!("example.com:0.4".parse::<HotClient>().is_err());
+    }
+
+    #[test]
+    fn test_status_spike_from_str() {
+        let spike: StatusSpike = "503:every=60s,duration=10s,fraction=0.5".parse().unwrap();
+        assert_eq!(503, spike.status);
+        assert_eq!(Window::new(secs(60), secs(10)), spike.window);
+        assert_eq!(0.5, spike.fraction);
+    }
+
+    #[test]
+    fn test_status_spike_from_str_invalid() {
+        assert!("every=60s,duration=10s,fraction=0.5".parse::<StatusSpike>().is_err());
+        assert!("600:every=60s,duration=10s,fraction=0.5"
+            .parse::<StatusSpike>()
+            .is_err());
+        assert!("503:every=60s,duration=10s".parse::<StatusSpike>().is_err());
+        assert!("503:every=60s,duration=10s,fraction=2".parse::<StatusSpike>().is_err());
+    }
+
+    #[test]
+    fn test_scenario_empty() {
+        let scenario = Scenario::new();
+        assert!(scenario.is_empty());
+        assert_eq!(10.0, scenario.rate(10.0, secs(30)));
+        assert_eq!(Duration::from_millis(1100), scenario.next_due(10.0, secs(1)));
+        assert_eq!(0, scenario.active_status_spikes(secs(60)).count());
+    }
+
+    #[test]
+    fn test_scenario_bursts_compose() {
+        let scenario = Scenario::new()
+            .burst("every=30s,duration=10s,multiplier=2".parse().unwrap())
+            .burst("every=20s,duration=5s,multiplier=3".parse().unwrap());
+        assert!(!scenario.is_empty());
+
+        assert_eq!(10.0, scenario.rate(10.0, secs(10)));
+        assert_eq!(30.0, scenario.rate(10.0, secs(22)));
+        assert_eq!(20.0, scenario.rate(10.0, secs(35)));
+        assert_eq!(60.0, scenario.rate(10.0, secs(60)));
+        assert_eq!(
+            secs(60) + Duration::from_secs_f64(1.0 / 60.0),
+            scenario.next_due(10.0, secs(60))
+        );
+    }
+
+    #[test]
+    fn test_scenario_burst_line_count() {
+        // Bursts at 20 times the rate from 30s to 35s and 60s to 65s
+        let scenario = Scenario::new().burst("every=30s,duration=5s,multiplier=20".parse().unwrap());
+        let mut due = secs(0);
+        let mut lines = 0;
+        while due < secs(90) {
+            due = scenario.next_due(10.0, due);
+            lines += 1;
+        }
+
+        let expected = 80 * 10 + 10 * 200;
+        assert!((lines as i64 - expected).abs() <= 2, "{} lines", lines);
+    }
+
+    #[test]
+    fn test_scenario_status_spikes() {
+        let scenario = Scenario::new()
+            .status_spike("503:every=60s,duration=10s,fraction=0.5".parse().unwrap())
+            .status_spike("500:every=30s,duration=5s,fraction=0.1".parse().unwrap());
+
+        let active = |s: u64| {
+            scenario
+                .active_status_spikes(secs(s))
+                .map(|s| s.status)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Vec::<u16>::new(), active(10));
+        assert_eq!(vec![500], active(32));
+        assert_eq!(vec![503, 500], active(61));
+        assert_eq!(vec![503], active(68));
+    }
+
+    #[test]
+    fn test_scenario_scanner_rate_clamped() {
+        assert_eq!(1.0, Scenario::new().scanner_rate(1.5).scanner_fraction());
+        assert_eq!(0.0, Scenario::new().scanner_rate(f64::NAN).scanner_fraction());
+        assert!(Scenario::new().scanner_rate(0.0).is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Selection of a subset of event fields for output
+
+use crate::types::{LogEvent, RedeyeError, RedeyeResult};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// Single field to be included in output: the dotted path of the field in an
+/// event and the top-level name it is output as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    path: String,
+    name: String,
+}
+
+impl Column {
+    /// Dotted path of the field in the event, e.g. `request_headers.user_agent`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Name of the field in output, e.g. `user_agent`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Ordered list of fields to output in place of entire events.
+///
+/// Parsed from a comma separated list of dotted field paths. Each field is output
+/// at the top level under the last component of its path unless given a different
+/// name using `path=name`. Fields are output in the order they are listed and any
+/// that are missing from an event are omitted.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::select::Selection;
+/// use redeye::types::{LogEvent, LogFieldValue};
+/// use std::collections::HashMap;
+///
+/// let selection = Selection::parse("status_code,request_headers.user_agent=ua").unwrap();
+///
+/// let mut headers = HashMap::new();
+/// headers.insert("user_agent".to_owned(), LogFieldValue::Text("curl/7.64.1".to_owned()));
+///
+/// let mut event = LogEvent::from(HashMap::new());
+/// event.insert("request_headers", LogFieldValue::Mapping(headers));
+/// event.insert("status_code", LogFieldValue::Int(200));
+/// event.insert("method", LogFieldValue::Text("GET".to_owned()));
+///
+/// let json = serde_json::to_string(&selection.apply(&event)).unwrap();
+/// assert_eq!(r#"{"status_code":200,"ua":"curl/7.64.1"}"#, json);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    columns: Vec<Column>,
+}
+
+impl Selection {
+    /// Parse a comma separated list of fields, returning a `ConfigError` if any
+    /// are empty or more than one would be output with the same name.
+    pub fn parse(spec: &str) -> RedeyeResult<Self> {
+        let mut columns = Vec::new();
+        let mut seen = HashSet::new();
+
+        for part in spec.split(',').map(str::trim) {
+            let (path, name) = match part.find('=') {
+                Some(i) => (part[..i].trim(), part[i + 1..].trim()),
+                None => (part, part.rsplit('.').next().unwrap_or(part)),
+            };
+
+            if path.is_empty() || name.is_empty() || path.split('.').any(str::is_empty) {
+                return Err(RedeyeError::ConfigError(format!(
+                    "invalid field selection '{}': empty field name",
+                    spec
+                )));
+            }
+
+            if !seen.insert(name.to_owned()) {
+                return Err(RedeyeError::ConfigError(format!(
+                    "invalid field selection '{}': field '{}' selected more than once",
+                    spec, name
+                )));
+            }
+
+            columns.push(Column {
+                path: path.to_owned(),
+                name: name.to_owned(),
+            });
+        }
+
+        Ok(Selection { columns })
+    }
+
+    /// Fields to output, in order.
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Wrap an event so that only the selected fields are serialized.
+    pub fn apply<'a>(&'a self, event: &'a LogEvent) -> SelectedEvent<'a> {
+        SelectedEvent { selection: self, event }
+    }
+}
+
+impl FromStr for Selection {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, c) in self.columns.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+
+            if c.path.rsplit('.').next() == Some(c.name.as_str()) {
+                f.write_str(&c.path)?;
+            } else {
+                write!(f, "{}={}", c.path, c.name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Event that serializes only the fields of a `Selection`, in order.
+#[derive(Debug)]
+pub struct SelectedEvent<'a> {
+    selection: &'a Selection,
+    event: &'a LogEvent,
+}
+
+impl<'a> Serialize for SelectedEvent<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        for c in self.selection.columns.iter() {
+            if let Some(v) = self.event.lookup(&c.path) {
+                map.serialize_entry(&c.name, v)?;
+            }
+        }
+
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Selection;
+    use crate::types::{LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+
+    fn event() -> LogEvent {
+        let mut headers = HashMap::new();
+        headers.insert("referer".to_owned(), LogFieldValue::Text("-".to_owned()));
+
+        let mut event = LogEvent::from(HashMap::new());
+        event.insert("remote_host", LogFieldValue::Text("127.0.0.1".to_owned()));
+        event.insert("status_code", LogFieldValue::Int(404));
+        event.insert("requested_uri", LogFieldValue::Text("/missing".to_owned()));
+        event.insert("request_headers", LogFieldValue::Mapping(headers));
+        event
+    }
+
+    #[test]
+    fn test_selection_preserves_order() {
+        let selection = Selection::parse("status_code,remote_host,requested_uri").unwrap();
+        let json = serde_json::to_string(&selection.apply(&event())).unwrap();
+
+        assert_eq!(
+            r#"{"status_code":404,"remote_host":"127.0.0.1","requested_uri":"/missing"}"#,
+            json
+        );
+    }
+
+    #[test]
+    fn test_selection_nested_and_aliased() {
+        let selection = Selection::parse("request_headers.referer, requested_uri = uri").unwrap();
+        let json = serde_json::to_string(&selection.apply(&event())).unwrap();
+
+        assert_eq!(r#"{"referer":"-","uri":"/missing"}"#, json);
+        assert_eq!("request_headers.referer,requested_uri=uri", selection.to_string());
+    }
+
+    #[test]
+    fn test_selection_missing_fields_omitted() {
+        let selection = Selection::parse("remote_user,status_code,request_headers.user_agent").unwrap();
+        let json = serde_json::to_string(&selection.apply(&event())).unwrap();
+
+        assert_eq!(r#"{"status_code":404}"#, json);
+    }
+
+    #[test]
+    fn test_selection_whole_mapping() {
+        let selection = Selection::parse("request_headers").unwrap();
+        let json = serde_json::to_string(&selection.apply(&event())).unwrap();
+
+        assert_eq!(r#"{"request_headers":{"referer":"-"}}"#, json);
+    }
+
+    #[test]
+    fn test_selection_invalid() {
+        for spec in &[
+            "",
+            "status_code,",
+            "a..b",
+            "=alias",
+            "status_code=",
+            "a.status,b.status",
+        ] {
+            let err = Selection::parse(spec).unwrap_err();
+            assert!(err.is_config_error(), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,560 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Keeping records on disk while a remote output can't accept them
+
+use crate::stats::Stats;
+use flate2::Crc;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const DEFAULT_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
+
+const SEGMENT_SUFFIX: &str = ".spill";
+
+const SEGMENT_DIGITS: usize = 20;
+
+/// Length and CRC-32 of the record that follows, as little endian `u32`s.
+const HEADER_BYTES: usize = 8;
+
+/// A file of records in the spill directory, named for its sequence number.
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    path: PathBuf,
+    bytes: u64,
+}
+
+/// Result of reading the next record of a segment.
+enum ReadRecord {
+    Record(Vec<u8>),
+    End,
+    Corrupted,
+}
+
+/// First-in, first-out queue of records kept in segment files in a directory.
+///
+/// Records are appended to the newest segment until it reaches the segment size,
+/// after which a new one is started, and are read back in the order they were
+/// appended. Segments are deleted once every record in them has been read. The
+/// total size of all segments is capped and records that don't fit are refused.
+///
+/// Each record is framed with its length and a CRC-32 of its contents and is
+/// written with a single write, so a record only partially written before a crash
+/// is detected when it's read back. Since records after it can't be found reliably,
+/// the rest of that segment is skipped and counted as a single corrupted record.
+///
+/// Segments left in the directory by a previous process are read before any
+/// records appended by this one, so records spilled before a restart are kept.
+/// Records already read from a segment that wasn't finished are read again.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::spill::SpillQueue;
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let mut spill = SpillQueue::open(dir.path()).unwrap().segment_bytes(64);
+///
+/// assert!(spill.push(b"first").unwrap());
+/// assert!(spill.push(b"second").unwrap());
+/// assert_eq!(Some(b"first".to_vec()), spill.pop().unwrap());
+/// assert_eq!(Some(b"second".to_vec()), spill.pop().unwrap());
+/// assert_eq!(None, spill.pop().unwrap());
+/// assert!(spill.is_empty());
+/// ```
+pub struct SpillQueue {
+    dir: PathBuf,
+    segment_bytes: u64,
+    max_bytes: u64,
+    segments: VecDeque<Segment>,
+    writer: Option<File>,
+    reader: Option<BufReader<File>>,
+    next_seq: u64,
+    bytes: u64,
+    stats: Option<Arc<Stats>>,
+}
+
+impl SpillQueue {
+    /// Use the directory for segments, creating it if it doesn't exist and
+    /// queueing any segments already in it to be read first.
+    pub fn open<P>(dir: P) -> io::Result<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let seq = match segment_seq(&entry.file_name().to_string_lossy()) {
+                Some(seq) => seq,
+                None => continue,
+            };
+
+            let path = entry.path();
+            let bytes = entry.metadata()?.len();
+            if bytes == 0 {
+                fs::remove_file(&path)?;
+            } else {
+                segments.push(Segment { seq, path, bytes });
+            }
+        }
+
+        segments.sort_by_key(|s| s.seq);
+        let next_seq = segments.last().map(|s| s.seq + 1).unwrap_or(0);
+        let bytes = segments.iter().map(|s| s.bytes).sum();
+
+        Ok(SpillQueue {
+            dir,
+            segment_bytes: DEFAULT_SEGMENT_BYTES,
+            max_bytes: DEFAULT_MAX_BYTES,
+            segments: segments.into(),
+            writer: None,
+            reader: None,
+            next_seq,
+            bytes,
+            stats: None,
+        })
+    }
+
+    /// Set the size after which a new segment is started, 64MiB by default. A
+    /// segment always holds at least one record, however large.
+    pub fn segment_bytes(mut self, bytes: u64) -> Self {
+        self.segment_bytes = bytes;
+        self
+    }
+
+    /// Set the largest total size of all segments, 1GiB by default.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Set the stats used to count records spilled, read back, and corrupted, and
+    /// the current size of the spill.
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = Some(stats);
+        self.update_usage();
+        self
+    }
+
+    /// Append a record, returning `false` without writing it if it doesn't fit in
+    /// the maximum size.
+    pub fn push(&mut self, record: &[u8]) -> io::Result<bool> {
+        if record.len() > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "record too large to spill"));
+        }
+
+        let frame_bytes = (HEADER_BYTES + record.len()) as u64;
+        if self.bytes + frame_bytes > self.max_bytes {
+            return Ok(false);
+        }
+
+        let full = match (self.segments.back(), &self.writer) {
+            (Some(segment), Some(_)) => segment.bytes > 0 && segment.bytes + frame_bytes > self.segment_bytes,
+            _ => true,
+        };
+
+        if full {
+            self.start_segment()?;
+        }
+
+        let mut crc = Crc::new();
+        crc.update(record);
+
+        let mut frame = Vec::with_capacity(frame_bytes as usize);
+        frame.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc.sum().to_le_bytes());
+        frame.extend_from_slice(record);
+
+        // Both were set by start_segment if they weren't already
+        let segment = self.segments.back_mut().unwrap();
+        self.writer.as_mut().unwrap().write_all(&frame)?;
+        segment.bytes += frame_bytes;
+        self.bytes += frame_bytes;
+
+        if let Some(ref stats) = self.stats {
+            stats.spill_written();
+        }
+        self.update_usage();
+        Ok(true)
+    }
+
+    /// Remove and return the oldest record, or `None` if there are no more.
+    /// Corrupted records are counted and skipped.
+    pub fn pop(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let path = match self.segments.front() {
+                Some(segment) => segment.path.clone(),
+                None => return Ok(None),
+            };
+
+            if self.reader.is_none() {
+                self.reader = Some(BufReader::new(File::open(&path)?));
+            }
+
+            // Set just above if it wasn't already
+            match read_record(self.reader.as_mut().unwrap(), self.segment_bytes.max(self.max_bytes))? {
+                ReadRecord::Record(record) => {
+                    if let Some(ref stats) = self.stats {
+                        stats.spill_drained();
+                    }
+                    return Ok(Some(record));
+                }
+                ReadRecord::Corrupted => {
+                    if let Some(ref stats) = self.stats {
+                        stats.spill_corrupted();
+                    }
+                    self.finish_segment()?;
+                }
+                ReadRecord::End => self.finish_segment()?,
+            }
+        }
+    }
+
+    /// True if there are no records left to read.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Number of bytes of segments on disk, including records that have been
+    /// read from a segment that hasn't been deleted yet.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Number of segments on disk.
+    pub fn segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn start_segment(&mut self) -> io::Result<()> {
+        let seq = self.next_seq;
+        let path = self.dir.join(segment_name(seq));
+        let file = OpenOptions::new().create_new(true).append(true).open(&path)?;
+
+        self.next_seq += 1;
+        self.writer = Some(file);
+        self.segments.push_back(Segment { seq, path, bytes: 0 });
+        self.update_usage();
+        Ok(())
+    }
+
+    /// Delete the oldest segment once everything in it has been read.
+    fn finish_segment(&mut self) -> io::Result<()> {
+        self.reader = None;
+        if let Some(segment) = self.segments.pop_front() {
+            // The segment being appended to is finished when reading catches up
+            // with writing, so the next record starts a new one.
+            if self.segments.is_empty() {
+                self.writer = None;
+            }
+
+            self.bytes -= segment.bytes;
+            fs::remove_file(&segment.path)?;
+        }
+
+        self.update_usage();
+        Ok(())
+    }
+
+    fn update_usage(&self) {
+        if let Some(ref stats) = self.stats {
+            stats.spill_usage(self.bytes, self.segments.len() as u64);
+        }
+    }
+}
+
+impl fmt::Debug for SpillQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpillQueue")
+            .field("dir", &self.dir)
+            .field("segment_bytes", &self.segment_bytes)
+            .field("max_bytes", &self.max_bytes)
+            .field("segments", &self.segments)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+fn segment_name(seq: u64) -> String {
+    format!("{:0width$}{}", seq, SEGMENT_SUFFIX, width = SEGMENT_DIGITS)
+}
+
+fn segment_seq(name: &str) -> Option<u64> {
+    let digits = name.strip_suffix(SEGMENT_SUFFIX)?;
+    if digits.len() != SEGMENT_DIGITS || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+/// Read the next record, which is corrupted if it's truncated, longer than any
+/// record that could have been written, or doesn't match its checksum.
+fn read_record<R: Read>(reader: &mut R, max_len: u64) -> io::Result<ReadRecord> {
+    let mut header = [0; HEADER_BYTES];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(ReadRecord::End),
+        HEADER_BYTES => {}
+        _ => return Ok(ReadRecord::Corrupted),
+    }
+
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let expected = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if len > max_len {
+        return Ok(ReadRecord::Corrupted);
+    }
+
+    let mut record = vec![0; len as usize];
+    if read_full(reader, &mut record)? != record.len() {
+        return Ok(ReadRecord::Corrupted);
+    }
+
+    let mut crc = Crc::new();
+    crc.update(&record);
+    if crc.sum() != expected {
+        return Ok(ReadRecord::Corrupted);
+    }
+
+    Ok(ReadRecord::Record(record))
+}
+
+/// Read until the buffer is full or the end of the reader, returning the number
+/// of bytes read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{segment_name, segment_seq, SpillQueue};
+    use crate::stats::Stats;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    /// Path of the segment files in a directory, oldest first.
+    fn segment_paths(dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| segment_seq(&p.file_name().unwrap().to_string_lossy()).is_some())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn drain(spill: &mut SpillQueue) -> Vec<String> {
+        std::iter::from_fn(|| spill.pop().unwrap())
+            .map(|r| String::from_utf8(r).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_segment_names() {
+        assert_eq!("00000000000000000042.spill", segment_name(42));
+        assert_eq!(Some(42), segment_seq(&segment_name(42)));
+        assert_eq!(None, segment_seq("42.spill"));
+        assert_eq!(None, segment_seq("0000000000000000004x.spill"));
+        assert_eq!(None, segment_seq("00000000000000000042.log"));
+    }
+
+    #[test]
+    fn test_spill_queue_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = Arc::new(Stats::new());
+        // Each record is 8 bytes of header and 4 bytes of data, so three fit in a segment
+        let mut spill = SpillQueue::open(dir.path())
+            .unwrap()
+            .segment_bytes(36)
+            .stats(stats.clone());
+
+        for i in 0..10 {
+            assert!(spill.push(format!("r{:03}", i).as_bytes()).unwrap());
+        }
+
+        assert_eq!(4, spill.segments());
+        assert_eq!(4, segment_paths(dir.path()).len());
+        assert_eq!(120, spill.bytes());
+        assert_eq!(120, stats.snapshot().spill_bytes);
+        assert_eq!(4, stats.snapshot().spill_segments);
+
+        let expected: Vec<String> = (0..10).map(|i| format!("r{:03}", i)).collect();
+        assert_eq!(expected, drain(&mut spill));
+        assert!(spill.is_empty());
+        assert_eq!(0, spill.bytes());
+        assert!(segment_paths(dir.path()).is_empty());
+
+        let snapshot = stats.snapshot();
+        assert_eq!(10, snapshot.spill_written);
+        assert_eq!(10, snapshot.spill_drained);
+        assert_eq!(0, snapshot.spill_bytes);
+        assert_eq!(0, snapshot.spill_segments);
+    }
+
+    #[test]
+    fn test_spill_queue_max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spill = SpillQueue::open(dir.path()).unwrap().segment_bytes(24).max_bytes(36);
+
+        assert!(spill.push(b"r000").unwrap());
+        assert!(spill.push(b"r001").unwrap());
+        assert!(spill.push(b"r002").unwrap());
+        assert!(!spill.push(b"r003").unwrap());
+
+        // Room is only made once a segment has been read completely
+        assert_eq!(Some(b"r000".to_vec()), spill.pop().unwrap());
+        assert_eq!(Some(b"r001".to_vec()), spill.pop().unwrap());
+        assert!(!spill.push(b"r003").unwrap());
+        assert_eq!(Some(b"r002".to_vec()), spill.pop().unwrap());
+        assert!(spill.push(b"r003").unwrap());
+
+        assert_eq!(vec!["r003"], drain(&mut spill));
+    }
+
+    #[test]
+    fn test_spill_queue_interleaved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spill = SpillQueue::open(dir.path()).unwrap().segment_bytes(1024);
+
+        spill.push(b"a").unwrap();
+        spill.push(b"b").unwrap();
+        assert_eq!(Some(b"a".to_vec()), spill.pop().unwrap());
+        spill.push(b"c").unwrap();
+        assert_eq!(vec!["b", "c"], drain(&mut spill));
+
+        // Reading caught up with writing, so a new segment is started
+        spill.push(b"d").unwrap();
+        assert_eq!(1, spill.segments());
+        assert_eq!(vec!["d"], drain(&mut spill));
+    }
+
+    #[test]
+    fn test_spill_queue_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut spill = SpillQueue::open(dir.path()).unwrap().segment_bytes(24);
+            for r in &["a", "b", "c"] {
+                spill.push(r.as_bytes()).unwrap();
+            }
+            assert_eq!(Some(b"a".to_vec()), spill.pop().unwrap());
+        }
+
+        // Records left from before are read first, including any from a segment
+        // that was partially read.
+        let mut spill = SpillQueue::open(dir.path()).unwrap().segment_bytes(24);
+        spill.push(b"d").unwrap();
+        assert_eq!(3, spill.segments());
+        assert_eq!(vec!["a", "b", "c", "d"], drain(&mut spill));
+    }
+
+    #[test]
+    fn test_spill_queue_truncated_record() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut spill = SpillQueue::open(dir.path()).unwrap();
+            for r in &["first", "second", "third"] {
+                spill.push(r.as_bytes()).unwrap();
+            }
+        }
+
+        // Simulate a crash part way through writing the last record
+        let path = segment_paths(dir.path()).pop().unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+
+        let stats = Arc::new(Stats::new());
+        let mut spill = SpillQueue::open(dir.path()).unwrap().stats(stats.clone());
+        spill.push(b"fourth").unwrap();
+        assert_eq!(vec!["first", "second", "fourth"], drain(&mut spill));
+        assert_eq!(1, stats.snapshot().spill_corrupted);
+        assert!(segment_paths(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn test_spill_queue_corrupted_record() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut spill = SpillQueue::open(dir.path()).unwrap().segment_bytes(40);
+            for r in &["first", "second", "third", "fourth"] {
+                spill.push(r.as_bytes()).unwrap();
+            }
+        }
+
+        // Flip a byte of the second record, after which nothing in its segment
+        // can be trusted
+        let paths = segment_paths(dir.path());
+        assert_eq!(2, paths.len());
+        let mut data = fs::read(&paths[0]).unwrap();
+        data[21] ^= 0xff;
+        fs::write(&paths[0], &data).unwrap();
+
+        // Garbage appended after a valid record
+        OpenOptions::new()
+            .append(true)
+            .open(&paths[1])
+            .unwrap()
+            .write_all(b"\x01\x02\x03")
+            .unwrap();
+
+        let stats = Arc::new(Stats::new());
+        let mut spill = SpillQueue::open(dir.path()).unwrap().stats(stats.clone());
+        assert_eq!(vec!["first", "fourth"], drain(&mut spill));
+        assert_eq!(2, stats.snapshot().spill_corrupted);
+    }
+
+    #[test]
+    fn test_spill_queue_ignores_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), b"not a segment").unwrap();
+        fs::write(dir.path().join(segment_name(7)), b"").unwrap();
+
+        let mut spill = SpillQueue::open(dir.path()).unwrap();
+        assert!(spill.is_empty());
+        assert!(!dir.path().join(segment_name(7)).exists());
+
+        spill.push(b"a").unwrap();
+        assert!(dir.path().join(segment_name(0)).exists());
+        assert!(dir.path().join("notes.txt").exists());
+    }
+}
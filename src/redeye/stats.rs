@@ -0,0 +1,699 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Counters for lines and events processed
+
+use crate::metrics::{Counter, Gauge, Histogram, Metrics};
+use crate::types::RedeyeError;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the buckets that line sizes in bytes are counted in.
+const LINE_BYTES_BUCKETS: &[f64] = &[64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0, 8192.0];
+
+/// Counters of lines read, events emitted, and errors encountered.
+///
+/// Each counter is a metric in a `Metrics` registry, so the same values that
+/// make up the summary printed on exit can be rendered in the Prometheus text
+/// format. All counters may be updated concurrently through a shared reference
+/// so a single instance can be shared between threads (e.g. in an `Arc`).
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::metrics::Metrics;
+/// use redeye::stats::Stats;
+/// use redeye::types::RedeyeError;
+///
+/// let metrics = Metrics::new();
+/// let stats = Stats::register(&metrics);
+/// stats.line_read(64);
+/// stats.event_emitted(256);
+/// stats.line_read(12);
+/// stats.error(&RedeyeError::ParseError("bad line".to_owned()));
+///
+/// let snapshot = stats.snapshot();
+/// assert_eq!(2, snapshot.lines_read);
+/// assert_eq!(1, snapshot.events_emitted);
+/// assert_eq!(1, snapshot.parse_errors);
+/// assert_eq!(1, snapshot.errors());
+/// assert!(metrics.prometheus().contains("redeye_lines_read_total 2\n"));
+/// ```
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    metrics: Metrics,
+    lines_read: Counter,
+    line_bytes: Histogram,
+    lines_invalid_utf8: Counter,
+    events_emitted: Counter,
+    events_filtered: Counter,
+    events_sampled_out: Counter,
+    events_late: Gauge,
+    events_deduped: Counter,
+    bytes_in: Counter,
+    bytes_out: Counter,
+    io_errors: Counter,
+    serialization_errors: Counter,
+    timestamp_errors: Counter,
+    parse_errors: Counter,
+    config_errors: Counter,
+    record_too_long_errors: Counter,
+    datagrams_oversized: Counter,
+    datagrams_dropped: Gauge,
+    output_dropped: Counter,
+    output_reconnects: Counter,
+    output_dead_lettered: Counter,
+    warnings_printed: Counter,
+    warnings_suppressed: Counter,
+    spill_written: Counter,
+    spill_drained: Counter,
+    spill_corrupted: Counter,
+    spill_bytes: Gauge,
+    spill_segments: Gauge,
+}
+
+impl Stats {
+    /// Create counters in a registry of their own.
+    pub fn new() -> Self {
+        Self::register(&Metrics::new())
+    }
+
+    /// Create counters in the given registry, or use the existing counters with
+    /// the same names if they've already been registered.
+    pub fn register(metrics: &Metrics) -> Self {
+        Stats {
+            started: Instant::now(),
+            metrics: metrics.clone(),
+            lines_read: metrics.counter("redeye_lines_read_total", "Lines read"),
+            line_bytes: metrics.histogram("redeye_line_bytes", "Size of lines read in bytes", LINE_BYTES_BUCKETS),
+            lines_invalid_utf8: metrics.counter("redeye_lines_skipped_total", "Lines skipped as invalid UTF-8"),
+            events_emitted: metrics.counter("redeye_events_emitted_total", "Events written"),
+            events_filtered: metrics.counter("redeye_events_filtered_total", "Events not matching filters"),
+            events_sampled_out: metrics.counter("redeye_events_sampled_out_total", "Events not in the sample"),
+            events_late: metrics.gauge("redeye_events_late", "Events written out of order when merging"),
+            events_deduped: metrics.counter("redeye_events_deduped_total", "Duplicate events dropped"),
+            bytes_in: metrics.counter("redeye_read_bytes_total", "Bytes read"),
+            bytes_out: metrics.counter("redeye_written_bytes_total", "Bytes written"),
+            io_errors: metrics.counter("redeye_io_errors_total", "Lines that couldn't be read"),
+            serialization_errors: metrics.counter(
+                "redeye_serialization_errors_total",
+                "Events that couldn't be serialized",
+            ),
+            timestamp_errors: metrics.counter("redeye_timestamp_errors_total", "Lines with invalid timestamps"),
+            parse_errors: metrics.counter("redeye_parse_errors_total", "Lines that couldn't be parsed"),
+            config_errors: metrics.counter("redeye_config_errors_total", "Lines failing because of configuration"),
+            record_too_long_errors: metrics.counter("redeye_record_too_long_errors_total", "Lines that were too long"),
+            datagrams_oversized: metrics.counter("redeye_datagrams_oversized_total", "Datagrams that were too large"),
+            datagrams_dropped: metrics.gauge("redeye_datagrams_dropped", "Datagrams dropped by the OS"),
+            output_dropped: metrics.counter("redeye_output_dropped_total", "Events that couldn't be sent"),
+            output_reconnects: metrics.counter("redeye_output_reconnects_total", "Reconnections to the output"),
+            output_dead_lettered: metrics.counter(
+                "redeye_output_dead_lettered_total",
+                "Events written to the dead letter file",
+            ),
+            warnings_printed: metrics.counter("redeye_warnings_printed_total", "Warnings printed"),
+            warnings_suppressed: metrics.counter("redeye_warnings_suppressed_total", "Warnings suppressed"),
+            spill_written: metrics.counter("redeye_spill_written_total", "Events written to the spill directory"),
+            spill_drained: metrics.counter(
+                "redeye_spill_drained_total",
+                "Events read back from the spill directory",
+            ),
+            spill_corrupted: metrics.counter(
+                "redeye_spill_corrupted_total",
+                "Corrupted events skipped in the spill directory",
+            ),
+            spill_bytes: metrics.gauge("redeye_spill_bytes", "Bytes in the spill directory"),
+            spill_segments: metrics.gauge("redeye_spill_segments", "Segment files in the spill directory"),
+        }
+    }
+
+    /// Registry that the counters are part of.
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Record a line of the given size in bytes being read.
+    pub fn line_read(&self, bytes: usize) {
+        self.lines_read.inc();
+        self.bytes_in.add(bytes as u64);
+        self.line_bytes.observe(bytes as f64);
+    }
+
+    /// Record a line being skipped because it wasn't valid UTF-8.
+    pub fn line_invalid_utf8(&self) {
+        self.lines_invalid_utf8.inc();
+    }
+
+    /// Record an event of the given size in bytes being emitted.
+    pub fn event_emitted(&self, bytes: usize) {
+        self.events_emitted.inc();
+        self.bytes_out.add(bytes as u64);
+    }
+
+    /// Record an event being dropped because it didn't match a filter.
+    pub fn event_filtered(&self) {
+        self.events_filtered.inc();
+    }
+
+    /// Record an event being dropped because it wasn't included in a sample.
+    pub fn event_sampled_out(&self) {
+        self.events_sampled_out.inc();
+    }
+
+    /// Set the number of events emitted out of order when merging inputs by time.
+    pub fn events_late(&self, late: u64) {
+        self.events_late.set(late as i64);
+    }
+
+    /// Record events being dropped because they were duplicates of the event before them.
+    pub fn events_deduped(&self, dropped: u64) {
+        self.events_deduped.add(dropped);
+    }
+
+    /// Record a datagram being skipped because it was too large.
+    pub fn datagram_oversized(&self) {
+        self.datagrams_oversized.inc();
+    }
+
+    /// Set the number of datagrams dropped by the OS before they could be read.
+    pub fn datagrams_dropped(&self, dropped: u64) {
+        self.datagrams_dropped.set(dropped as i64);
+    }
+
+    /// Record events being dropped because they couldn't be sent to the output.
+    pub fn output_dropped(&self, events: u64) {
+        self.output_dropped.add(events);
+    }
+
+    /// Record the connection to the output being established again after it was lost.
+    pub fn output_reconnected(&self) {
+        self.output_reconnects.inc();
+    }
+
+    /// Record events being written to the dead letter file because they couldn't be sent.
+    pub fn output_dead_lettered(&self, events: u64) {
+        self.output_dead_lettered.add(events);
+    }
+
+    /// Record an event being written to the spill directory because the output
+    /// couldn't keep up.
+    pub fn spill_written(&self) {
+        self.spill_written.inc();
+    }
+
+    /// Record an event being read back from the spill directory.
+    pub fn spill_drained(&self) {
+        self.spill_drained.inc();
+    }
+
+    /// Record a corrupted or partially written event in the spill directory being skipped.
+    pub fn spill_corrupted(&self) {
+        self.spill_corrupted.inc();
+    }
+
+    /// Set the number of bytes and segment files in the spill directory.
+    pub fn spill_usage(&self, bytes: u64, segments: u64) {
+        self.spill_bytes.set(bytes as i64);
+        self.spill_segments.set(segments as i64);
+    }
+
+    /// Record a warning being printed.
+    pub fn warning_printed(&self) {
+        self.warnings_printed.inc();
+    }
+
+    /// Record a warning being suppressed because too many of its kind were printed recently.
+    pub fn warning_suppressed(&self) {
+        self.warnings_suppressed.inc();
+    }
+
+    /// Record an error handling a line, based on the kind of error.
+    pub fn error(&self, err: &RedeyeError) {
+        let counter = if err.is_io_error() {
+            &self.io_errors
+        } else if err.is_serialization_error() {
+            &self.serialization_errors
+        } else if err.is_timestamp_parse_error() {
+            &self.timestamp_errors
+        } else if err.is_parse_error() {
+            &self.parse_errors
+        } else if err.is_record_too_long_error() {
+            &self.record_too_long_errors
+        } else {
+            &self.config_errors
+        };
+
+        counter.inc();
+    }
+
+    /// Total number of errors of any kind recorded.
+    pub fn errors(&self) -> u64 {
+        self.snapshot().errors()
+    }
+
+    /// Current value of each counter and the time since this object was created.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            elapsed: self.started.elapsed(),
+            lines_read: self.lines_read.get(),
+            lines_invalid_utf8: self.lines_invalid_utf8.get(),
+            events_emitted: self.events_emitted.get(),
+            events_filtered: self.events_filtered.get(),
+            events_sampled_out: self.events_sampled_out.get(),
+            events_late: self.events_late.get() as u64,
+            events_deduped: self.events_deduped.get(),
+            bytes_in: self.bytes_in.get(),
+            bytes_out: self.bytes_out.get(),
+            io_errors: self.io_errors.get(),
+            serialization_errors: self.serialization_errors.get(),
+            timestamp_errors: self.timestamp_errors.get(),
+            parse_errors: self.parse_errors.get(),
+            config_errors: self.config_errors.get(),
+            record_too_long_errors: self.record_too_long_errors.get(),
+            datagrams_oversized: self.datagrams_oversized.get(),
+            datagrams_dropped: self.datagrams_dropped.get() as u64,
+            output_dropped: self.output_dropped.get(),
+            output_reconnects: self.output_reconnects.get(),
+            output_dead_lettered: self.output_dead_lettered.get(),
+            warnings_printed: self.warnings_printed.get(),
+            warnings_suppressed: self.warnings_suppressed.get(),
+            spill_written: self.spill_written.get(),
+            spill_drained: self.spill_drained.get(),
+            spill_corrupted: self.spill_corrupted.get(),
+            spill_bytes: self.spill_bytes.get() as u64,
+            spill_segments: self.spill_segments.get() as u64,
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Values of `Stats` counters at a point in time.
+///
+/// Displayed as a human readable block of text or serialized as a JSON object
+/// that includes the total number of errors and the rate of lines per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatsSnapshot {
+    pub elapsed: Duration,
+    pub lines_read: u64,
+    pub lines_invalid_utf8: u64,
+    pub events_emitted: u64,
+    pub events_filtered: u64,
+    pub events_sampled_out: u64,
+    pub events_late: u64,
+    pub events_deduped: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub io_errors: u64,
+    pub serialization_errors: u64,
+    pub timestamp_errors: u64,
+    pub parse_errors: u64,
+    pub config_errors: u64,
+    pub record_too_long_errors: u64,
+    pub datagrams_oversized: u64,
+    pub datagrams_dropped: u64,
+    pub output_dropped: u64,
+    pub output_reconnects: u64,
+    pub output_dead_lettered: u64,
+    pub warnings_printed: u64,
+    pub warnings_suppressed: u64,
+    pub spill_written: u64,
+    pub spill_drained: u64,
+    pub spill_corrupted: u64,
+    pub spill_bytes: u64,
+    pub spill_segments: u64,
+}
+
+impl StatsSnapshot {
+    /// Total number of errors of any kind.
+    pub fn errors(&self) -> u64 {
+        self.io_errors
+            + self.serialization_errors
+            + self.timestamp_errors
+            + self.parse_errors
+            + self.config_errors
+            + self.record_too_long_errors
+    }
+
+    /// Average number of lines read per second, zero if no time has elapsed.
+    pub fn lines_per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.lines_read as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "lines read:       {}", self.lines_read)?;
+        if self.lines_invalid_utf8 > 0 {
+            writeln!(f, "invalid utf-8:    {}", self.lines_invalid_utf8)?;
+        }
+        writeln!(f, "events emitted:   {}", self.events_emitted)?;
+        writeln!(f, "events filtered:  {}", self.events_filtered)?;
+        writeln!(f, "sampled out:      {}", self.events_sampled_out)?;
+        if self.events_late > 0 {
+            writeln!(f, "late events:      {}", self.events_late)?;
+        }
+        if self.events_deduped > 0 {
+            writeln!(f, "duplicates:       {}", self.events_deduped)?;
+        }
+        writeln!(f, "errors:           {}", self.errors())?;
+        writeln!(f, "  invalid line:   {}", self.parse_errors)?;
+        writeln!(f, "  timestamp:      {}", self.timestamp_errors)?;
+        writeln!(f, "  serialization:  {}", self.serialization_errors)?;
+        writeln!(f, "  i/o:            {}", self.io_errors)?;
+        writeln!(f, "  config:         {}", self.config_errors)?;
+        if self.record_too_long_errors > 0 {
+            writeln!(f, "  too long:       {}", self.record_too_long_errors)?;
+        }
+        if self.datagrams_oversized > 0 || self.datagrams_dropped > 0 {
+            writeln!(f, "datagrams:")?;
+            writeln!(f, "  too large:      {}", self.datagrams_oversized)?;
+            writeln!(f, "  dropped:        {}", self.datagrams_dropped)?;
+        }
+        if self.output_dropped > 0 || self.output_reconnects > 0 || self.output_dead_lettered > 0 {
+            writeln!(f, "output:")?;
+            writeln!(f, "  dropped:        {}", self.output_dropped)?;
+            writeln!(f, "  reconnects:     {}", self.output_reconnects)?;
+            writeln!(f, "  dead lettered:  {}", self.output_dead_lettered)?;
+        }
+        if self.spill_written > 0 || self.spill_corrupted > 0 || self.spill_bytes > 0 {
+            writeln!(f, "spill:")?;
+            writeln!(f, "  written:        {}", self.spill_written)?;
+            writeln!(f, "  drained:        {}", self.spill_drained)?;
+            writeln!(f, "  corrupted:      {}", self.spill_corrupted)?;
+            writeln!(f, "  bytes:          {}", self.spill_bytes)?;
+            writeln!(f, "  segments:       {}", self.spill_segments)?;
+        }
+        if self.warnings_suppressed > 0 {
+            writeln!(f, "warnings:")?;
+            writeln!(f, "  printed:        {}", self.warnings_printed)?;
+            writeln!(f, "  suppressed:     {}", self.warnings_suppressed)?;
+        }
+        writeln!(f, "bytes in:         {}", self.bytes_in)?;
+        writeln!(f, "bytes out:        {}", self.bytes_out)?;
+        writeln!(f, "elapsed:          {:.3}s", self.elapsed.as_secs_f64())?;
+        write!(f, "lines per second: {:.1}", self.lines_per_second())
+    }
+}
+
+impl Serialize for StatsSnapshot {
+    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("StatsSnapshot", 30)?;
+        s.serialize_field("lines_read", &self.lines_read)?;
+        s.serialize_field("lines_invalid_utf8", &self.lines_invalid_utf8)?;
+        s.serialize_field("events_emitted", &self.events_emitted)?;
+        s.serialize_field("events_filtered", &self.events_filtered)?;
+        s.serialize_field("events_sampled_out", &self.events_sampled_out)?;
+        s.serialize_field("events_late", &self.events_late)?;
+        s.serialize_field("events_deduped", &self.events_deduped)?;
+        s.serialize_field("errors", &self.errors())?;
+        s.serialize_field("parse_errors", &self.parse_errors)?;
+        s.serialize_field("timestamp_errors", &self.timestamp_errors)?;
+        s.serialize_field("serialization_errors", &self.serialization_errors)?;
+        s.serialize_field("io_errors", &self.io_errors)?;
+        s.serialize_field("config_errors", &self.config_errors)?;
+        s.serialize_field("record_too_long_errors", &self.record_too_long_errors)?;
+        s.serialize_field("datagrams_oversized", &self.datagrams_oversized)?;
+        s.serialize_field("datagrams_dropped", &self.datagrams_dropped)?;
+        s.serialize_field("output_dropped", &self.output_dropped)?;
+        s.serialize_field("output_reconnects", &self.output_reconnects)?;
+        s.serialize_field("output_dead_lettered", &self.output_dead_lettered)?;
+        s.serialize_field("warnings_printed", &self.warnings_printed)?;
+        s.serialize_field("warnings_suppressed", &self.warnings_suppressed)?;
+        s.serialize_field("spill_written", &self.spill_written)?;
+        s.serialize_field("spill_drained", &self.spill_drained)?;
+        s.serialize_field("spill_corrupted", &self.spill_corrupted)?;
+        s.serialize_field("spill_bytes", &self.spill_bytes)?;
+        s.serialize_field("spill_segments", &self.spill_segments)?;
+        s.serialize_field("bytes_in", &self.bytes_in)?;
+        s.serialize_field("bytes_out", &self.bytes_out)?;
+        s.serialize_field("elapsed_seconds", &self.elapsed.as_secs_f64())?;
+        s.serialize_field("lines_per_second", &self.lines_per_second())?;
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stats, StatsSnapshot};
+    use crate::metrics::Metrics;
+    use crate::types::RedeyeError;
+    use std::io;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stats_errors_by_kind() {
+        let stats = Stats::new();
+        stats.error(&RedeyeError::ParseError("bad".to_owned()));
+        stats.error(&RedeyeError::ParseError("worse".to_owned()));
+        stats.error(&RedeyeError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "utf-8",
+        )));
+        stats.error(&RedeyeError::ConfigError("nope".to_owned()));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(2, snapshot.parse_errors);
+        assert_eq!(1, snapshot.io_errors);
+        assert_eq!(1, snapshot.config_errors);
+        assert_eq!(0, snapshot.timestamp_errors);
+        assert_eq!(4, snapshot.errors());
+        assert_eq!(4, stats.errors());
+    }
+
+    #[test]
+    fn test_stats_shared_between_threads() {
+        let stats = Arc::new(Stats::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let stats = stats.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        stats.line_read(10);
+                    }
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let snapshot = stats.snapshot();
+        assert_eq!(4000, snapshot.lines_read);
+        assert_eq!(40000, snapshot.bytes_in);
+    }
+
+    #[test]
+    fn test_stats_register() {
+        let metrics = Metrics::new();
+        let stats = Stats::register(&metrics);
+        stats.line_read(100);
+        stats.event_emitted(200);
+        stats.error(&RedeyeError::RecordTooLong(50));
+        stats.spill_usage(1024, 2);
+
+        // Registering again shares the same counters
+        let again = Stats::register(&metrics);
+        again.line_read(10);
+        assert_eq!(2, stats.snapshot().lines_read);
+        assert_eq!(110, stats.snapshot().bytes_in);
+
+        let rendered = metrics.prometheus();
+        assert!(rendered.contains("redeye_lines_read_total 2\n"));
+        assert!(rendered.contains("redeye_events_emitted_total 1\n"));
+        assert!(rendered.contains("redeye_written_bytes_total 200\n"));
+        assert!(rendered.contains("redeye_record_too_long_errors_total 1\n"));
+        assert!(rendered.contains("redeye_spill_bytes 1024\n"));
+        assert!(rendered.contains("redeye_line_bytes_count 2\n"));
+        assert!(stats.metrics().prometheus().contains("redeye_spill_segments 2\n"));
+    }
+
+    #[test]
+    fn test_stats_snapshot_lines_per_second() {
+        let snapshot = StatsSnapshot {
+            elapsed: Duration::from_secs(2),
+            lines_read: 500,
+            ..StatsSnapshot::default()
+        };
+
+        assert_eq!(250.0, snapshot.lines_per_second());
+        assert_eq!(0.0, StatsSnapshot::default().lines_per_second());
+    }
+
+    #[test]
+    fn test_stats_snapshot_serialize() {
+        let snapshot = StatsSnapshot {
+            elapsed: Duration::from_millis(500),
+            lines_read: 10,
+            events_emitted: 7,
+            events_filtered: 1,
+            parse_errors: 2,
+            ..StatsSnapshot::default()
+        };
+
+        let json: serde_json::Value = serde_json::to_value(snapshot).unwrap();
+        assert_eq!(10, json["lines_read"]);
+        assert_eq!(7, json["events_emitted"]);
+        assert_eq!(1, json["events_filtered"]);
+        assert_eq!(2, json["errors"]);
+        assert_eq!(0, json["datagrams_dropped"]);
+        assert_eq!(0.5, json["elapsed_seconds"]);
+        assert_eq!(20.0, json["lines_per_second"]);
+    }
+
+    #[test]
+    fn test_stats_datagrams() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("datagrams:"));
+
+        stats.datagram_oversized();
+        stats.datagrams_dropped(12);
+        stats.datagrams_dropped(15);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(1, snapshot.datagrams_oversized);
+        assert_eq!(15, snapshot.datagrams_dropped);
+        assert!(snapshot.to_string().contains("  dropped:        15\n"));
+    }
+
+    #[test]
+    fn test_stats_late() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("late events:"));
+
+        stats.events_late(3);
+        let snapshot = stats.snapshot();
+        assert_eq!(3, snapshot.events_late);
+        assert!(snapshot.to_string().contains("late events:      3\n"));
+    }
+
+    #[test]
+    fn test_stats_invalid_utf8() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("invalid utf-8:"));
+
+        stats.line_invalid_utf8();
+        stats.line_invalid_utf8();
+        let snapshot = stats.snapshot();
+        assert_eq!(2, snapshot.lines_invalid_utf8);
+        assert!(snapshot.to_string().contains("invalid utf-8:    2\n"));
+    }
+
+    #[test]
+    fn test_stats_warnings() {
+        let stats = Stats::new();
+        stats.warning_printed();
+        assert!(!stats.snapshot().to_string().contains("warnings:"));
+
+        stats.warning_suppressed();
+        stats.warning_suppressed();
+        let snapshot = stats.snapshot();
+        assert_eq!(1, snapshot.warnings_printed);
+        assert_eq!(2, snapshot.warnings_suppressed);
+        assert!(snapshot
+            .to_string()
+            .contains("warnings:\n  printed:        1\n  suppressed:     2\n"));
+    }
+
+    #[test]
+    fn test_stats_spill() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("spill:"));
+
+        stats.spill_written();
+        stats.spill_written();
+        stats.spill_drained();
+        stats.spill_corrupted();
+        stats.spill_usage(1024, 2);
+        let snapshot = stats.snapshot();
+        assert_eq!(2, snapshot.spill_written);
+        assert_eq!(1, snapshot.spill_drained);
+        assert_eq!(1, snapshot.spill_corrupted);
+        assert_eq!(1024, snapshot.spill_bytes);
+        assert_eq!(2, snapshot.spill_segments);
+        assert!(snapshot
+            .to_string()
+            .contains("spill:\n  written:        2\n  drained:        1\n"));
+    }
+
+    #[test]
+    fn test_stats_deduped() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("duplicates:"));
+
+        stats.events_deduped(0);
+        stats.events_deduped(2);
+        let snapshot = stats.snapshot();
+        assert_eq!(2, snapshot.events_deduped);
+        assert!(snapshot.to_string().contains("duplicates:       2\n"));
+    }
+
+    #[test]
+    fn test_stats_record_too_long() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("too long:"));
+
+        stats.error(&RedeyeError::RecordTooLong(1024));
+        let snapshot = stats.snapshot();
+        assert_eq!(1, snapshot.record_too_long_errors);
+        assert_eq!(1, snapshot.errors());
+        assert!(snapshot.to_string().contains("  too long:       1\n"));
+
+        let json = serde_json::to_value(snapshot).unwrap();
+        assert_eq!(1, json["record_too_long_errors"]);
+    }
+
+    #[test]
+    fn test_stats_output() {
+        let stats = Stats::new();
+        assert!(!stats.snapshot().to_string().contains("output:"));
+
+        stats.output_dropped(3);
+        stats.output_dropped(2);
+        stats.output_reconnected();
+        stats.output_dead_lettered(4);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(5, snapshot.output_dropped);
+        assert_eq!(1, snapshot.output_reconnects);
+        assert_eq!(4, snapshot.output_dead_lettered);
+        assert!(snapshot.to_string().contains(
+            "  dead lettered:  4
+"
+        ));
+        assert!(snapshot.to_string().contains("  reconnects:     1\n"));
+    }
+}
@@ -0,0 +1,275 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Reading events from an asynchronous reader as a `Stream`
+//!
+//! This is only available with the `stream` feature, for programs that already
+//! use tokio and want to parse lines as they arrive without blocking a thread.
+
+use crate::io::LineDecoder;
+use crate::parser::LogLineParser;
+use crate::types::{LogEvent, RedeyeResult};
+use futures_core::Stream;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Size of the buffer that input is read into before being split into lines.
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Stream of events parsed from each line of an asynchronous reader.
+///
+/// Lines are split by a `LineDecoder`, so lines may arrive in any number of
+/// reads and a final line without a delimiter is still parsed. Lines that can't
+/// be parsed (or are too long, or aren't valid UTF-8, depending on the decoder)
+/// are `Err` items and the lines after them are still read. An error reading
+/// from the reader is the last item.
+///
+/// Each item is independent of the others, so the stream can be used with any
+/// of the usual combinators, e.g. to filter events or handle them concurrently.
+///
+/// # Example
+///
+/// ```rust
+/// use futures::executor::block_on;
+/// use futures::StreamExt;
+/// use redeye::parser::CommonLogLineParser;
+/// use redeye::stream::LogEventStream;
+/// use std::sync::Arc;
+///
+/// let input: &[u8] = b"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326\nnot a log line\n";
+/// let stream = LogEventStream::new(input, Arc::new(CommonLogLineParser::new()));
+///
+/// let results: Vec<_> = block_on(stream.collect());
+/// assert_eq!(2, results.len());
+/// assert!(results[0].is_ok());
+/// assert!(results[1].is_err());
+/// ```
+pub struct LogEventStream<R> {
+    reader: R,
+    parser: Arc<dyn LogLineParser + Send + Sync>,
+    decoder: LineDecoder,
+    buf: Vec<u8>,
+    chunk: Box<[u8]>,
+    eof: bool,
+    failed: bool,
+}
+
+impl<R> LogEventStream<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Create a stream that parses each line of the reader. Readers that aren't
+    /// `Unpin` can be pinned with `Box::pin` first.
+    pub fn new(reader: R, parser: Arc<dyn LogLineParser + Send + Sync>) -> Self {
+        LogEventStream {
+            reader,
+            parser,
+            decoder: LineDecoder::new(),
+            buf: Vec::new(),
+            chunk: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            eof: false,
+            failed: false,
+        }
+    }
+
+    /// Set the decoder used to split input into lines, which determines the
+    /// delimiter, the maximum length of a line, and what's done with lines that
+    /// aren't valid UTF-8. Lines are split on newlines with no maximum by default.
+    pub fn decoder(mut self, decoder: LineDecoder) -> Self {
+        self.decoder = decoder;
+        self
+    }
+
+    /// Number of lines skipped because they weren't valid UTF-8.
+    pub fn skipped(&self) -> u64 {
+        self.decoder.skipped()
+    }
+}
+
+impl<R> Stream for LogEventStream<R>
+where
+    R: AsyncRead + Unpin,
+{
+    type Item = RedeyeResult<LogEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.failed {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            if let Some(line) = this.decoder.decode(&mut this.buf) {
+                return Poll::Ready(Some(line.and_then(|l| this.parser.parse(&l))));
+            }
+
+            if this.eof {
+                let line = this.decoder.decode_eof(&mut this.buf);
+                return Poll::Ready(line.map(|res| res.and_then(|l| this.parser.parse(&l))));
+            }
+
+            let mut read = ReadBuf::new(&mut this.chunk);
+            match Pin::new(&mut this.reader).poll_read(cx, &mut read) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(())) if read.filled().is_empty() => this.eof = true,
+                Poll::Ready(Ok(())) => this.buf.extend_from_slice(read.filled()),
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(e)) => {
+                    this.failed = true;
+                    this.buf.clear();
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogEventStream;
+    use crate::io::{Delimiter, LineDecoder};
+    use crate::parser::CommonLogLineParser;
+    use crate::types::{LogFieldValue, RedeyeError};
+    use futures::executor::block_on;
+    use futures::StreamExt;
+    use std::cmp;
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, ReadBuf};
+
+    const FIRST: &str = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /a HTTP/1.0\" 200 2326";
+    const SECOND: &str = "127.0.0.1 - - [10/Oct/2000:13:55:37 -0700] \"GET /b HTTP/1.0\" 404 12";
+
+    /// Reader that returns a few bytes at a time, is only ready every other time
+    /// it's polled, and optionally fails once its data runs out.
+    struct Chunked {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        ready: bool,
+        fail: bool,
+    }
+
+    impl Chunked {
+        fn new(data: &str, chunk: usize) -> Self {
+            Chunked {
+                data: data.as_bytes().to_vec(),
+                pos: 0,
+                chunk,
+                ready: false,
+                fail: false,
+            }
+        }
+
+        fn fail(mut self) -> Self {
+            self.fail = true;
+            self
+        }
+    }
+
+    impl AsyncRead for Chunked {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            if self.pos == self.data.len() && self.fail {
+                return Poll::Ready(Err(io::Error::other("connection reset")));
+            }
+
+            let end = cmp::min(self.pos + self.chunk, self.data.len());
+            let end = cmp::min(end, self.pos + buf.remaining());
+            buf.put_slice(&self.data[self.pos..end]);
+            self.pos = end;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn stream(reader: Chunked) -> LogEventStream<Chunked> {
+        LogEventStream::new(reader, Arc::new(CommonLogLineParser::new()))
+    }
+
+    fn uri(event: &crate::types::LogEvent) -> String {
+        match event.fields().get("requested_uri") {
+            Some(LogFieldValue::Text(uri)) => uri.clone(),
+            v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_log_event_stream_lines_split_across_reads() {
+        let input = format!("{}\r\nnot a log line\n{}", FIRST, SECOND);
+        let results: Vec<_> = block_on(stream(Chunked::new(&input, 7)).collect());
+
+        assert_eq!(3, results.len());
+        assert_eq!("/a", uri(results[0].as_ref().unwrap()));
+        match results[1] {
+            Err(RedeyeError::ParseError(_)) => {}
+            ref v => panic!("Unexpected result: {:?}", v),
+        }
+        // The final line has no newline and still produces an event
+        assert_eq!("/b", uri(results[2].as_ref().unwrap()));
+    }
+
+    #[test]
+    fn test_log_event_stream_decoder() {
+        let input = format!("{}\0{}\0", FIRST, SECOND);
+        let decoder = LineDecoder::new().delimiter(Delimiter::Nul).max_record_bytes(Some(68));
+        let results: Vec<_> = block_on(stream(Chunked::new(&input, 16)).decoder(decoder).collect());
+
+        assert_eq!(2, results.len());
+        assert!(results[0].as_ref().unwrap_err().is_record_too_long_error());
+        assert_eq!("/b", uri(results[1].as_ref().unwrap()));
+    }
+
+    #[test]
+    fn test_log_event_stream_io_error_ends_stream() {
+        let input = format!("{}\n{}", FIRST, SECOND);
+        let results: Vec<_> = block_on(stream(Chunked::new(&input, 5).fail()).collect());
+
+        // The partial line before the error is discarded
+        assert_eq!(2, results.len());
+        assert_eq!("/a", uri(results[0].as_ref().unwrap()));
+        match results[1] {
+            Err(RedeyeError::IoError(ref e)) => assert_eq!("connection reset", e.to_string()),
+            ref v => panic!("Unexpected result: {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_log_event_stream_combinators() {
+        let input = format!("{}\nnot a log line\n{}\n{}\n", FIRST, SECOND, FIRST);
+        let mut uris: Vec<String> = block_on(
+            stream(Chunked::new(&input, 3))
+                .filter_map(|res| async move { res.ok() })
+                .map(|event| async move { uri(&event) })
+                .buffer_unordered(4)
+                .collect(),
+        );
+
+        uris.sort();
+        assert_eq!(vec!["/a", "/a", "/b"], uris);
+    }
+}
@@ -0,0 +1,314 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Parsing timestamps that may not include an offset
+//!
+//! Timestamps without an offset are local times, which can only be turned into
+//! an instant by assuming the zone they were written in. A `TimestampContext`
+//! holds that zone (if any) and is used by parsers for every timestamp.
+
+use crate::types::RedeyeError;
+use chrono::format::ParseError;
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use std::fmt;
+use std::str::FromStr;
+
+/// Zone that local times without an offset are assumed to be in.
+///
+/// Either a fixed offset from UTC (e.g. `+05:30` or `-0700`) or an IANA time
+/// zone name (e.g. `America/New_York`). For IANA zones, a local time that occurs
+/// twice when clocks go back is the earlier of the two, and a local time that is
+/// skipped when clocks go forward is moved forward by the length of the gap (e.g.
+/// 02:30 on a day that skips from 02:00 to 03:00 becomes 03:30).
+///
+/// # Example
+///
+/// ```rust
+/// use chrono::NaiveDate;
+/// use redeye::timestamp::AssumedZone;
+///
+/// let zone: AssumedZone = "America/New_York".parse().unwrap();
+/// let ts = zone.resolve(NaiveDate::from_ymd(2021, 7, 4).and_hms(12, 0, 0));
+/// assert_eq!("2021-07-04T12:00:00-04:00", ts.to_rfc3339());
+///
+/// let zone: AssumedZone = "+05:30".parse().unwrap();
+/// let ts = zone.resolve(NaiveDate::from_ymd(2021, 7, 4).and_hms(12, 0, 0));
+/// assert_eq!("2021-07-04T12:00:00+05:30", ts.to_rfc3339());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssumedZone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl AssumedZone {
+    /// Interpret a local time in this zone.
+    pub fn resolve(&self, local: NaiveDateTime) -> DateTime<FixedOffset> {
+        match self {
+            AssumedZone::Fixed(offset) => resolve_in(offset, local),
+            AssumedZone::Named(tz) => resolve_in(tz, local),
+        }
+    }
+}
+
+/// Interpret a local time in a zone, picking the earlier time when it's ambiguous
+/// and moving it past the gap when it doesn't exist.
+fn resolve_in<Z: TimeZone>(zone: &Z, local: NaiveDateTime) -> DateTime<FixedOffset> {
+    let dt = match zone.from_local_datetime(&local) {
+        LocalResult::Single(dt) => dt,
+        LocalResult::Ambiguous(a, b) => {
+            if a <= b {
+                a
+            } else {
+                b
+            }
+        }
+        LocalResult::None => {
+            // Use the offset from before the transition, which turns a time in the
+            // gap into the same distance past the end of it. Transitions are always
+            // much more than a day apart.
+            let before = zone.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+            zone.from_utc_datetime(&(local - before))
+        }
+    };
+
+    let offset = dt.offset().fix();
+    dt.with_timezone(&offset)
+}
+
+impl FromStr for AssumedZone {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "Z" {
+            return Ok(AssumedZone::Fixed(FixedOffset::east(0)));
+        }
+
+        if s.starts_with('+') || s.starts_with('-') {
+            return parse_offset(s).map(AssumedZone::Fixed).ok_or_else(|| {
+                RedeyeError::ConfigError(format!("invalid offset '{}', expected a form like +05:30 or -0700", s))
+            });
+        }
+
+        s.parse::<Tz>().map(AssumedZone::Named).map_err(|_| {
+            RedeyeError::ConfigError(format!(
+                "unknown time zone '{}', expected an IANA name like America/New_York or an offset like -07:00",
+                s
+            ))
+        })
+    }
+}
+
+impl fmt::Display for AssumedZone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssumedZone::Fixed(offset) => offset.fmt(f),
+            AssumedZone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+/// Parse an offset from UTC of the form `+HH`, `+HHMM`, or `+HH:MM`.
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let sign = if s.starts_with('-') { -1 } else { 1 };
+    let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) || (s[1..].contains(':') && s.len() != 6) {
+        return None;
+    }
+
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse::<i32>().ok()?, digits[2..].parse::<i32>().ok()?),
+        _ => return None,
+    };
+
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Settings used when parsing every timestamp in a log line.
+///
+/// Timestamps that include an offset are parsed as they are. Timestamps without
+/// one are local times in the assumed zone, if there is one, and invalid if not.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::timestamp::TimestampContext;
+///
+/// let context = TimestampContext::new();
+/// assert!(context.parse("2021-07-04 12:00:00", "%Y-%m-%d %H:%M:%S").is_err());
+///
+/// let context = TimestampContext::new().assume_zone("UTC".parse().unwrap());
+/// let ts = context.parse("2021-07-04 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+/// assert_eq!("2021-07-04T12:00:00+00:00", ts.to_rfc3339());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimestampContext {
+    zone: Option<AssumedZone>,
+}
+
+impl TimestampContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the zone that timestamps without an offset are assumed to be in.
+    pub fn assume_zone(mut self, zone: AssumedZone) -> Self {
+        self.zone = Some(zone);
+        self
+    }
+
+    /// Set the zone that timestamps without an offset are assumed to be in, unless
+    /// one was already set. Used by parsers for formats that are usually UTC.
+    pub fn or_assume_zone(mut self, zone: AssumedZone) -> Self {
+        self.zone.get_or_insert(zone);
+        self
+    }
+
+    /// Parse a timestamp in the given `strftime` format.
+    pub fn parse(&self, value: &str, format: &str) -> Result<DateTime<FixedOffset>, ParseError> {
+        match DateTime::parse_from_str(value, format) {
+            Ok(ts) => Ok(ts),
+            // The only way a timestamp that isn't valid with an offset can be valid
+            // as a local time is when it doesn't have an offset.
+            Err(e) => match self.zone {
+                Some(ref zone) => NaiveDateTime::parse_from_str(value, format)
+                    .map(|local| zone.resolve(local))
+                    .map_err(|_| e),
+                None => Err(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AssumedZone, TimestampContext};
+    use chrono::{FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+
+    fn local(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_assumed_zone_from_str() {
+        let fixed = |secs| AssumedZone::Fixed(FixedOffset::east(secs));
+        assert_eq!(fixed(0), "Z".parse().unwrap());
+        assert_eq!(fixed(5 * 3600 + 1800), "+05:30".parse().unwrap());
+        assert_eq!(fixed(-7 * 3600), "-0700".parse().unwrap());
+        assert_eq!(fixed(-3 * 3600), "-03".parse().unwrap());
+        assert_eq!(AssumedZone::Named(chrono_tz::UTC), "UTC".parse().unwrap());
+        assert_eq!(
+            AssumedZone::Named(chrono_tz::Europe::Berlin),
+            "Europe/Berlin".parse().unwrap()
+        );
+
+        for bad in &["+5:30", "+24:00", "-07:60", "+0700x", "+", "Mars/Olympus_Mons", ""] {
+            assert!(bad.parse::<AssumedZone>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_assumed_zone_display() {
+        assert_eq!("+05:30", "+0530".parse::<AssumedZone>().unwrap().to_string());
+        assert_eq!(
+            "America/New_York",
+            "America/New_York".parse::<AssumedZone>().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_assumed_zone_resolve_dst() {
+        let zone: AssumedZone = "America/New_York".parse().unwrap();
+        assert_eq!(
+            "2021-01-15T12:00:00-05:00",
+            zone.resolve(local("2021-01-15 12:00:00")).to_rfc3339()
+        );
+        assert_eq!(
+            "2021-07-15T12:00:00-04:00",
+            zone.resolve(local("2021-07-15 12:00:00")).to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn test_assumed_zone_resolve_ambiguous() {
+        // Clocks go back from 02:00 to 01:00, so 01:30 happens twice and the
+        // first (still daylight saving) one is used.
+        let zone: AssumedZone = "America/New_York".parse().unwrap();
+        assert_eq!(
+            "2021-11-07T01:30:00-04:00",
+            zone.resolve(local("2021-11-07 01:30:00")).to_rfc3339()
+        );
+
+        let zone: AssumedZone = "Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            "2021-10-31T02:30:00+02:00",
+            zone.resolve(local("2021-10-31 02:30:00")).to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn test_assumed_zone_resolve_nonexistent() {
+        // Clocks go forward from 02:00 to 03:00, so 02:30 never happens and is
+        // moved forward an hour.
+        let zone: AssumedZone = "America/New_York".parse().unwrap();
+        assert_eq!(
+            "2021-03-14T03:30:00-04:00",
+            zone.resolve(local("2021-03-14 02:30:00")).to_rfc3339()
+        );
+
+        let zone: AssumedZone = "Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            "2021-03-28T03:30:00+02:00",
+            zone.resolve(local("2021-03-28 02:30:00")).to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn test_timestamp_context_parse() {
+        let format = "%Y-%m-%d %H:%M:%S";
+        let context = TimestampContext::new().assume_zone("-07:00".parse().unwrap());
+        let expected = FixedOffset::west(7 * 3600)
+            .from_local_datetime(&NaiveDate::from_ymd(2000, 10, 10).and_hms(13, 55, 36))
+            .unwrap();
+
+        assert_eq!(expected, context.parse("2000-10-10 13:55:36", format).unwrap());
+        assert!(context.parse("yesterday", format).is_err());
+
+        // Timestamps with an offset ignore the assumed zone.
+        let ts = context.parse("10/Oct/2000:13:55:36 +0200", "%d/%b/%Y:%T %z").unwrap();
+        assert_eq!("2000-10-10T13:55:36+02:00", ts.to_rfc3339());
+        assert!(context.parse("10/Oct/2000:13:55:36", "%d/%b/%Y:%T %z").is_err());
+    }
+
+    #[test]
+    fn test_timestamp_context_or_assume_zone() {
+        let utc = AssumedZone::Fixed(FixedOffset::east(0));
+        let context = TimestampContext::new().or_assume_zone(utc);
+        assert_eq!(TimestampContext::new().assume_zone(utc), context);
+
+        let berlin: AssumedZone = "Europe/Berlin".parse().unwrap();
+        let context = TimestampContext::new().assume_zone(berlin).or_assume_zone(utc);
+        assert_eq!(TimestampContext::new().assume_zone(berlin), context);
+    }
+}
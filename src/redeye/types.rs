@@ -21,10 +21,14 @@
 use chrono::{format, DateTime, FixedOffset};
 use serde::{Serialize, Serializer};
 use serde_json::error::Error as SerdeError;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::error;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::str::FromStr;
+use std::time::Duration;
 
 pub type RedeyeResult<T> = Result<T, RedeyeError>;
 
@@ -35,6 +39,8 @@ pub enum RedeyeError {
     SerializationError(SerdeError),
     TimestampParseError(format::ParseError),
     ParseError(String),
+    ConfigError(String),
+    RecordTooLong(usize),
 }
 
 impl fmt::Display for RedeyeError {
@@ -44,6 +50,8 @@ impl fmt::Display for RedeyeError {
             RedeyeError::SerializationError(ref e) => e.fmt(f),
             RedeyeError::TimestampParseError(ref e) => e.fmt(f),
             RedeyeError::ParseError(ref s) => s.fmt(f),
+            RedeyeError::ConfigError(ref s) => s.fmt(f),
+            RedeyeError::RecordTooLong(max) => write!(f, "record longer than {} bytes", max),
         }
     }
 }
@@ -61,31 +69,27 @@ impl error::Error for RedeyeError {
 
 impl RedeyeError {
     pub fn is_io_error(&self) -> bool {
-        match self {
-            RedeyeError::IoError(_) => true,
-            _ => false,
-        }
+        matches!(self, RedeyeError::IoError(_))
     }
 
     pub fn is_serialization_error(&self) -> bool {
-        match self {
-            RedeyeError::SerializationError(_) => true,
-            _ => false,
-        }
+        matches!(self, RedeyeError::SerializationError(_))
     }
 
     pub fn is_timestamp_parse_error(&self) -> bool {
-        match self {
-            RedeyeError::TimestampParseError(_) => true,
-            _ => false,
-        }
+        matches!(self, RedeyeError::TimestampParseError(_))
     }
 
     pub fn is_parse_error(&self) -> bool {
-        match self {
-            RedeyeError::ParseError(_) => true,
-            _ => false,
-        }
+        matches!(self, RedeyeError::ParseError(_))
+    }
+
+    pub fn is_config_error(&self) -> bool {
+        matches!(self, RedeyeError::ConfigError(_))
+    }
+
+    pub fn is_record_too_long_error(&self) -> bool {
+        matches!(self, RedeyeError::RecordTooLong(_))
     }
 }
 
@@ -111,13 +115,17 @@ impl From<format::ParseError> for RedeyeError {
 ///
 /// Values may be nested arbitrarily deep by using the `Mapping` variant.
 /// This is typically used for groups of values like request or response
-/// headers.
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// headers. The `List` variant is used for fields written with several
+/// values, like the addresses of each upstream server nginx tried.
+#[derive(Debug, Clone, PartialEq)]
 pub enum LogFieldValue {
     Mapping(HashMap<String, LogFieldValue>),
     Timestamp(DateTime<FixedOffset>),
     Text(String),
     Int(u64),
+    Float(f64),
+    Bool(bool),
+    List(Vec<LogFieldValue>),
 }
 
 impl Serialize for LogFieldValue {
@@ -130,6 +138,9 @@ impl Serialize for LogFieldValue {
             LogFieldValue::Timestamp(ref val) => serializer.serialize_str(&val.to_rfc3339()),
             LogFieldValue::Text(ref val) => serializer.serialize_str(val),
             LogFieldValue::Int(val) => serializer.serialize_u64(val),
+            LogFieldValue::Float(val) => serializer.serialize_f64(val),
+            LogFieldValue::Bool(val) => serializer.serialize_bool(val),
+            LogFieldValue::List(ref vals) => vals.serialize(serializer),
         }
     }
 }
@@ -139,7 +150,7 @@ impl Serialize for LogFieldValue {
 /// Most of the values will correspond to a field parsed from the incoming
 /// access log line. The names of the fields are picked to be compatible
 /// with the format expected by Logstash consumers.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LogEvent {
     values: HashMap<String, LogFieldValue>,
 }
@@ -148,6 +159,123 @@ impl LogEvent {
     pub fn fields(&self) -> &HashMap<String, LogFieldValue> {
         &self.values
     }
+
+    pub fn fields_mut(&mut self) -> &mut HashMap<String, LogFieldValue> {
+        &mut self.values
+    }
+
+    /// Add or replace a top-level field, returning the previous value if any.
+    pub fn insert<S>(&mut self, field: S, value: LogFieldValue) -> Option<LogFieldValue>
+    where
+        S: Into<String>,
+    {
+        self.values.insert(field.into(), value)
+    }
+
+    /// Get the value of a field by a dotted path, e.g. `request_headers.user_agent`.
+    ///
+    /// Each component of the path except the last must refer to a `Mapping`
+    /// value. Return `None` if any component of the path doesn't exist.
+    pub fn lookup(&self, path: &str) -> Option<&LogFieldValue> {
+        let mut parts = path.split('.');
+        let mut current = self.values.get(parts.next()?)?;
+
+        for part in parts {
+            current = match current {
+                LogFieldValue::Mapping(map) => map.get(part)?,
+                _ => return None,
+            };
+        }
+
+        Some(current)
+    }
+
+    /// Hash of the values of the given fields (by dotted path), or of every field
+    /// except those in `DEDUP_IGNORED_FIELDS` if no fields are given, used to find
+    /// duplicate events. The key doesn't depend on the order fields were added in
+    /// and fields that the event doesn't have are included as missing.
+    pub fn dedup_key(&self, fields: Option<&[String]>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match fields {
+            Some(paths) => {
+                for path in paths {
+                    match self.lookup(path) {
+                        Some(value) => {
+                            hasher.write_u8(1);
+                            hash_value(value, &mut hasher);
+                        }
+                        None => hasher.write_u8(0),
+                    }
+                }
+            }
+            None => {
+                let mut names: Vec<&String> = self
+                    .values
+                    .keys()
+                    .filter(|k| !DEDUP_IGNORED_FIELDS.contains(&k.as_str()))
+                    .collect();
+                names.sort();
+
+                for name in names {
+                    name.hash(&mut hasher);
+                    hash_value(&self.values[name], &mut hasher);
+                }
+            }
+        }
+
+        hasher.finish()
+    }
+}
+
+/// Fields left out of the dedup key of an event by default, since they differ
+/// between otherwise identical events.
+pub const DEDUP_IGNORED_FIELDS: &[&str] = &["@timestamp", "message"];
+
+/// Hash a value, including the keys of mappings in sorted order so that the hash
+/// is the same regardless of the order of the map.
+fn hash_value<H>(value: &LogFieldValue, hasher: &mut H)
+where
+    H: Hasher,
+{
+    match value {
+        LogFieldValue::Mapping(map) => {
+            hasher.write_u8(0);
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            hasher.write_usize(keys.len());
+            for key in keys {
+                key.hash(hasher);
+                hash_value(&map[key], hasher);
+            }
+        }
+        LogFieldValue::Timestamp(ts) => {
+            hasher.write_u8(1);
+            ts.hash(hasher);
+        }
+        LogFieldValue::Text(s) => {
+            hasher.write_u8(2);
+            s.hash(hasher);
+        }
+        LogFieldValue::Int(i) => {
+            hasher.write_u8(3);
+            i.hash(hasher);
+        }
+        LogFieldValue::Float(f) => {
+            hasher.write_u8(4);
+            f.to_bits().hash(hasher);
+        }
+        LogFieldValue::Bool(b) => {
+            hasher.write_u8(5);
+            b.hash(hasher);
+        }
+        LogFieldValue::List(vals) => {
+            hasher.write_u8(6);
+            hasher.write_usize(vals.len());
+            for val in vals {
+                hash_value(val, hasher);
+            }
+        }
+    }
 }
 
 impl Serialize for LogEvent {
@@ -164,3 +292,209 @@ impl From<HashMap<String, LogFieldValue>> for LogEvent {
         Self { values }
     }
 }
+
+/// Unit of a request duration value written by a web server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurationUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+}
+
+impl DurationUnit {
+    /// Convert a duration in this unit to seconds.
+    pub fn to_seconds(self, val: f64) -> f64 {
+        match self {
+            DurationUnit::Seconds => val,
+            DurationUnit::Milliseconds => val / 1_000.0,
+            DurationUnit::Microseconds => val / 1_000_000.0,
+        }
+    }
+}
+
+impl FromStr for DurationUnit {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" | "seconds" => Ok(DurationUnit::Seconds),
+            "ms" | "milliseconds" => Ok(DurationUnit::Milliseconds),
+            "us" | "microseconds" => Ok(DurationUnit::Microseconds),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown duration unit '{}', expected one of s, ms, us",
+                s
+            ))),
+        }
+    }
+}
+
+/// Parse a human friendly duration such as `30s`, `500ms`, `5m`, `1h`, or `1d`.
+/// A number without a unit is interpreted as seconds.
+pub fn parse_duration(s: &str) -> RedeyeResult<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+
+    let val: u64 = num
+        .parse()
+        .map_err(|_| RedeyeError::ConfigError(format!("invalid duration '{}'", s)))?;
+
+    let secs = match unit {
+        "ms" => return Ok(Duration::from_millis(val)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => {
+            return Err(RedeyeError::ConfigError(format!(
+                "invalid duration '{}', expected a unit of ms, s, m, h, or d",
+                s
+            )))
+        }
+    };
+
+    val.checked_mul(secs)
+        .map(Duration::from_secs)
+        .ok_or_else(|| RedeyeError::ConfigError(format!("duration '{}' is too large", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_duration, DurationUnit, LogEvent, LogFieldValue};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn event_with_headers() -> LogEvent {
+        let mut headers = HashMap::new();
+        headers.insert("user_agent".to_owned(), LogFieldValue::Text("curl/7.64.1".to_owned()));
+
+        let mut fields = HashMap::new();
+        fields.insert("status_code".to_owned(), LogFieldValue::Int(200));
+        fields.insert("request_headers".to_owned(), LogFieldValue::Mapping(headers));
+        LogEvent::from(fields)
+    }
+
+    #[test]
+    fn test_log_event_lookup_top_level() {
+        let event = event_with_headers();
+        assert_eq!(Some(&LogFieldValue::Int(200)), event.lookup("status_code"));
+    }
+
+    #[test]
+    fn test_log_event_lookup_nested() {
+        let event = event_with_headers();
+        assert_eq!(
+            Some(&LogFieldValue::Text("curl/7.64.1".to_owned())),
+            event.lookup("request_headers.user_agent")
+        );
+    }
+
+    #[test]
+    fn test_log_event_lookup_missing() {
+        let event = event_with_headers();
+        assert_eq!(None, event.lookup("request_headers.referer"));
+        assert_eq!(None, event.lookup("response_headers.user_agent"));
+        assert_eq!(None, event.lookup(""));
+    }
+
+    #[test]
+    fn test_log_event_lookup_through_non_mapping() {
+        let event = event_with_headers();
+        assert_eq!(None, event.lookup("status_code.value"));
+    }
+
+    #[test]
+    fn test_log_event_dedup_key() {
+        let mut event = event_with_headers();
+        let key = event.dedup_key(None);
+
+        // Fields that differ between identical events are ignored by default
+        event.insert("message", LogFieldValue::Text("GET /".to_owned()));
+        assert_eq!(key, event.dedup_key(None));
+
+        event.insert("status_code", LogFieldValue::Int(404));
+        assert_ne!(key, event.dedup_key(None));
+    }
+
+    #[test]
+    fn test_log_event_dedup_key_order() {
+        let mut first = HashMap::new();
+        let mut second = HashMap::new();
+        for i in 0..20 {
+            first.insert(format!("field_{}", i), LogFieldValue::Int(i));
+            second.insert(format!("field_{}", 19 - i), LogFieldValue::Int(19 - i));
+        }
+
+        assert_eq!(
+            LogEvent::from(first).dedup_key(None),
+            LogEvent::from(second).dedup_key(None)
+        );
+    }
+
+    #[test]
+    fn test_log_event_dedup_key_fields() {
+        let fields = vec!["status_code".to_owned(), "request_headers.user_agent".to_owned()];
+        let mut event = event_with_headers();
+        let key = event.dedup_key(Some(&fields));
+
+        event.insert("requested_uri", LogFieldValue::Text("/health".to_owned()));
+        assert_eq!(key, event.dedup_key(Some(&fields)));
+
+        // Missing fields and fields with different types are different
+        event.insert("status_code", LogFieldValue::Text("200".to_owned()));
+        assert_ne!(key, event.dedup_key(Some(&fields)));
+        event.fields_mut().remove("status_code");
+        assert_ne!(key, event.dedup_key(Some(&fields)));
+    }
+
+    #[test]
+    fn test_log_event_list_values() {
+        let list = |vals: &[u64]| LogFieldValue::List(vals.iter().map(|v| LogFieldValue::Int(*v)).collect());
+        let mut event = event_with_headers();
+        event.insert("statuses", list(&[502, 200]));
+        let key = event.dedup_key(None);
+
+        assert_eq!(
+            "[502,200]",
+            serde_json::to_string(event.lookup("statuses").unwrap()).unwrap()
+        );
+
+        // Order of the values in a list matters
+        event.insert("statuses", list(&[200, 502]));
+        assert_ne!(key, event.dedup_key(None));
+    }
+
+    #[test]
+    fn test_duration_unit_from_str() {
+        assert_eq!(DurationUnit::Seconds, "s".parse().unwrap());
+        assert_eq!(DurationUnit::Milliseconds, "milliseconds".parse().unwrap());
+        assert_eq!(DurationUnit::Microseconds, "us".parse().unwrap());
+        assert!("minutes".parse::<DurationUnit>().unwrap_err().is_config_error());
+    }
+
+    #[test]
+    fn test_duration_unit_to_seconds() {
+        assert_eq!(1.5, DurationUnit::Seconds.to_seconds(1.5));
+        assert_eq!(0.042, DurationUnit::Milliseconds.to_seconds(42.0));
+        assert_eq!(0.000_042, DurationUnit::Microseconds.to_seconds(42.0));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Duration::from_secs(30), parse_duration("30").unwrap());
+        assert_eq!(Duration::from_secs(30), parse_duration("30s").unwrap());
+        assert_eq!(Duration::from_millis(250), parse_duration("250ms").unwrap());
+        assert_eq!(Duration::from_secs(300), parse_duration("5m").unwrap());
+        assert_eq!(Duration::from_secs(7200), parse_duration("2h").unwrap());
+        assert_eq!(Duration::from_secs(86400), parse_duration("1d").unwrap());
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").unwrap_err().is_config_error());
+        assert!(parse_duration("s").unwrap_err().is_config_error());
+        assert!(parse_duration("5w").unwrap_err().is_config_error());
+        assert!(parse_duration("-5s").unwrap_err().is_config_error());
+        assert!(parse_duration("99999999999999999999d").unwrap_err().is_config_error());
+    }
+}
@@ -0,0 +1,463 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Printing warnings about lines that couldn't be handled
+
+use crate::clock::{Clock, SystemClock};
+use crate::ratelimit::{RateLimit, RateLimiter};
+use crate::stats::Stats;
+use crate::types::RedeyeError;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_PER_SECOND: u64 = 10;
+
+/// How warnings are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarningFormat {
+    /// A line of human readable text per warning.
+    #[default]
+    Text,
+    /// A JSON object per warning, one per line.
+    Json,
+}
+
+impl FromStr for WarningFormat {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(WarningFormat::Text),
+            "json" => Ok(WarningFormat::Json),
+            _ => Err(RedeyeError::ConfigError(format!(
+                "unknown warnings format '{}', expected one of text, json",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for WarningFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningFormat::Text => write!(f, "text"),
+            WarningFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Destination for warnings about lines that couldn't be handled, implemented
+/// by `WarningSink` and by anything that needs to capture warnings instead, e.g.
+/// in tests.
+pub trait Warn: Send + Sync {
+    /// Report an error at a line of the source.
+    fn warn(&self, err: &RedeyeError, source: &str, line: u64);
+
+    /// Called once all input has been read.
+    fn finish(&self) {}
+}
+
+/// Writer and per-category limiters, locked together so that a warning and
+/// the suppression summary before it are written without being interleaved
+/// with warnings from other threads.
+struct State {
+    out: BufWriter<Box<dyn Write + Send>>,
+    limiters: HashMap<&'static str, RateLimiter>,
+}
+
+/// Destination for warnings about lines that couldn't be handled.
+///
+/// Warnings are rate limited per kind of error, with a count of the warnings
+/// that were suppressed written once warnings are allowed again (or when the
+/// sink is finished). Each warning is buffered and written with a single write
+/// while holding a lock so that warnings from multiple threads never interleave.
+/// Errors writing warnings are ignored.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::types::RedeyeError;
+/// use redeye::warnings::{WarningFormat, WarningSink};
+///
+/// let sink = WarningSink::new(Vec::new()).per_second(1).format(WarningFormat::Json);
+/// sink.warn(&RedeyeError::ParseError("bad line".to_owned()), "access.log", 3);
+/// sink.warn(&RedeyeError::ParseError("bad line".to_owned()), "access.log", 4);
+/// sink.finish();
+/// ```
+pub struct WarningSink {
+    quiet: bool,
+    per_second: u64,
+    format: WarningFormat,
+    stats: Option<Arc<Stats>>,
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+}
+
+impl WarningSink {
+    /// Create a sink that writes warnings to the given writer.
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        WarningSink {
+            quiet: false,
+            per_second: DEFAULT_PER_SECOND,
+            format: WarningFormat::default(),
+            stats: None,
+            clock: Arc::new(SystemClock),
+            state: Mutex::new(State {
+                out: BufWriter::new(Box::new(writer)),
+                limiters: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Create a sink that writes warnings to stderr.
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+
+    /// Don't write any warnings if `quiet` is true.
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Set the maximum number of warnings of each kind written per second, ten
+    /// by default.
+    pub fn per_second(mut self, per_second: u64) -> Self {
+        self.per_second = per_second;
+        self
+    }
+
+    /// Set how warnings are written, as text by default.
+    pub fn format(mut self, format: WarningFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Count warnings that are written or suppressed in the given stats.
+    pub fn stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Set the clock used to decide when warnings are allowed again, the system
+    /// clock by default.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Write a warning for the error at a line of the source unless warnings are
+    /// disabled or too many errors of the same kind have been written recently.
+    pub fn warn(&self, err: &RedeyeError, source: &str, line: u64) {
+        if self.quiet {
+            return;
+        }
+
+        let (category, display) = match err {
+            RedeyeError::IoError(e) => ("I/O error", e.to_string()),
+            RedeyeError::SerializationError(e) => ("Serialization error", e.to_string()),
+            RedeyeError::TimestampParseError(e) => ("Invalid timestamp", e.to_string()),
+            RedeyeError::ParseError(e) => ("Invalid log line", e.clone()),
+            RedeyeError::ConfigError(e) => ("Invalid configuration", e.clone()),
+            e @ RedeyeError::RecordTooLong(_) => ("Record too long", e.to_string()),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let per_second = self.per_second;
+        let check = state
+            .limiters
+            .entry(category)
+            .or_insert_with(|| RateLimiter::new(per_second, Duration::from_secs(1)))
+            .check_at(self.clock.now());
+
+        match check {
+            RateLimit::Allow { suppressed } => {
+                let mut buf = self.render_suppressed(category, suppressed);
+                buf.extend(self.render_warning(category, &display, source, line));
+                let _ = state.out.write_all(&buf).and_then(|_| state.out.flush());
+
+                if let Some(ref stats) = self.stats {
+                    stats.warning_printed();
+                }
+            }
+            RateLimit::Suppress => {
+                if let Some(ref stats) = self.stats {
+                    stats.warning_suppressed();
+                }
+            }
+        }
+    }
+
+    /// Write the number of warnings suppressed since the last of each kind was written.
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        let mut categories: Vec<_> = state
+            .limiters
+            .iter_mut()
+            .map(|(category, limiter)| (*category, limiter.take_suppressed()))
+            .collect();
+        categories.sort_unstable();
+
+        let buf: Vec<u8> = categories
+            .into_iter()
+            .flat_map(|(category, suppressed)| self.render_suppressed(category, suppressed))
+            .collect();
+        let _ = state.out.write_all(&buf).and_then(|_| state.out.flush());
+    }
+
+    fn render_warning(&self, category: &str, display: &str, source: &str, line: u64) -> Vec<u8> {
+        match self.format {
+            WarningFormat::Text => format!("redeye: warning: {}:{}: {}: {}\n", source, line, category, display),
+            WarningFormat::Json => {
+                let warning = json!({
+                    "level": "warning",
+                    "source": source,
+                    "line": line,
+                    "category": category,
+                    "message": display,
+                });
+                format!("{}\n", warning)
+            }
+        }
+        .into_bytes()
+    }
+
+    fn render_suppressed(&self, category: &str, suppressed: u64) -> Vec<u8> {
+        if suppressed == 0 {
+            return Vec::new();
+        }
+
+        match self.format {
+            WarningFormat::Text => format!(
+                "redeye: warning: Suppressed {} similar warnings ({})\n",
+                suppressed, category
+            ),
+            WarningFormat::Json => {
+                let summary = json!({
+                    "level": "warning",
+                    "category": category,
+                    "suppressed": suppressed,
+                });
+                format!("{}\n", summary)
+            }
+        }
+        .into_bytes()
+    }
+}
+
+impl Warn for WarningSink {
+    fn warn(&self, err: &RedeyeError, source: &str, line: u64) {
+        WarningSink::warn(self, err, source, line)
+    }
+
+    fn finish(&self) {
+        WarningSink::finish(self)
+    }
+}
+
+impl fmt::Debug for WarningSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarningSink")
+            .field("quiet", &self.quiet)
+            .field("per_second", &self.per_second)
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WarningFormat, WarningSink};
+    use crate::clock::ManualClock;
+    use crate::stats::Stats;
+    use crate::types::RedeyeError;
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Writer that appends to a buffer shared with the test.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Shared {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|s| s.to_owned())
+                .collect()
+        }
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse_error() -> RedeyeError {
+        RedeyeError::ParseError("bad line".to_owned())
+    }
+
+    #[test]
+    fn test_warning_format_from_str() {
+        assert_eq!(WarningFormat::Text, "text".parse().unwrap());
+        assert_eq!(WarningFormat::Json, "json".parse().unwrap());
+        assert!("yaml".parse::<WarningFormat>().is_err());
+    }
+
+    #[test]
+    fn test_warning_sink_text() {
+        let out = Shared::default();
+        let sink = WarningSink::new(out.clone());
+        sink.warn(&parse_error(), "access.log", 3);
+        sink.warn(&RedeyeError::RecordTooLong(10), "-", 4);
+
+        assert_eq!(
+            vec![
+                "redeye: warning: access.log:3: Invalid log line: bad line",
+                "redeye: warning: -:4: Record too long: record longer than 10 bytes",
+            ],
+            out.lines()
+        );
+    }
+
+    #[test]
+    fn test_warning_sink_quiet() {
+        let out = Shared::default();
+        let sink = WarningSink::new(out.clone()).quiet(true);
+        sink.warn(&parse_error(), "access.log", 3);
+        sink.finish();
+
+        assert!(out.lines().is_empty());
+    }
+
+    #[test]
+    fn test_warning_sink_rate_limited() {
+        let out = Shared::default();
+        let stats = Arc::new(Stats::new());
+        let sink = WarningSink::new(out.clone()).per_second(2).stats(stats.clone());
+        for line in 1..=5 {
+            sink.warn(&parse_error(), "access.log", line);
+        }
+        // Limits are per kind of error
+        sink.warn(&RedeyeError::RecordTooLong(10), "access.log", 6);
+        sink.finish();
+
+        let lines = out.lines();
+        assert_eq!(4, lines.len());
+        assert!(lines[0].contains("access.log:1:"));
+        assert!(lines[1].contains("access.log:2:"));
+        assert!(lines[2].contains("access.log:6:"));
+        assert_eq!(
+            "redeye: warning: Suppressed 3 similar warnings (Invalid log line)",
+            lines[3]
+        );
+
+        let snapshot = stats.snapshot();
+        assert_eq!(3, snapshot.warnings_printed);
+        assert_eq!(3, snapshot.warnings_suppressed);
+    }
+
+    #[test]
+    fn test_warning_sink_rate_limit_resets() {
+        let out = Shared::default();
+        let clock = ManualClock::new();
+        let sink = WarningSink::new(out.clone())
+            .per_second(1)
+            .clock(Arc::new(clock.clone()));
+        sink.warn(&parse_error(), "access.log", 1);
+        sink.warn(&parse_error(), "access.log", 2);
+        sink.warn(&parse_error(), "access.log", 3);
+        clock.advance(Duration::from_secs(1));
+        sink.warn(&parse_error(), "access.log", 4);
+        sink.warn(&parse_error(), "access.log", 5);
+        sink.finish();
+
+        assert_eq!(
+            vec![
+                "redeye: warning: access.log:1: Invalid log line: bad line",
+                "redeye: warning: Suppressed 2 similar warnings (Invalid log line)",
+                "redeye: warning: access.log:4: Invalid log line: bad line",
+                "redeye: warning: Suppressed 1 similar warnings (Invalid log line)",
+            ],
+            out.lines()
+        );
+    }
+
+    #[test]
+    fn test_warning_sink_json() {
+        let out = Shared::default();
+        let sink = WarningSink::new(out.clone()).per_second(1).format(WarningFormat::Json);
+        sink.warn(&parse_error(), "access.log", 3);
+        sink.warn(&parse_error(), "access.log", 4);
+        sink.finish();
+
+        let values: Vec<serde_json::Value> = out.lines().iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(2, values.len());
+        assert_eq!("warning", values[0]["level"]);
+        assert_eq!("access.log", values[0]["source"]);
+        assert_eq!(3, values[0]["line"]);
+        assert_eq!("Invalid log line", values[0]["category"]);
+        assert_eq!("bad line", values[0]["message"]);
+        assert_eq!("Invalid log line", values[1]["category"]);
+        assert_eq!(1, values[1]["suppressed"]);
+    }
+
+    #[test]
+    fn test_warning_sink_threads_dont_interleave() {
+        let out = Shared::default();
+        let sink = Arc::new(WarningSink::new(out.clone()).per_second(10_000));
+
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let sink = sink.clone();
+                thread::spawn(move || {
+                    for line in 0..100 {
+                        let err = RedeyeError::ParseError(format!("thread {} {}", t, "x".repeat(200)));
+                        sink.warn(&err, "access.log", line);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let lines = out.lines();
+        assert_eq!(800, lines.len());
+        for line in lines {
+            assert!(line.starts_with("redeye: warning: access.log:"), "{}", line);
+            assert!(line.ends_with(&"x".repeat(200)), "{}", line);
+        }
+    }
+}
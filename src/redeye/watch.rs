@@ -0,0 +1,1009 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Following every file that matches a glob pattern, including files created later
+//!
+//! Files are found by scanning for paths matching the pattern when a watcher
+//! starts and again periodically. Each file is read with a `FileFollower` and
+//! lines are taken from the files in turn. Files are identified by device and
+//! inode so a file that's renamed to another matching path is still read from
+//! where it was, and a file that no longer matches (because it was deleted or
+//! rotated away) is read until there's nothing left and then closed.
+
+use crate::follow::{FileFollower, FileIdentity, FollowFrom};
+use crate::io::RecordRead;
+use crate::types::{RedeyeError, RedeyeResult};
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default time between looking for new files that match the pattern.
+const DEFAULT_RESCAN: Duration = Duration::from_secs(5);
+
+/// Default initial time to wait when no file has a complete line.
+const DEFAULT_MIN_POLL: Duration = Duration::from_millis(10);
+
+/// Default maximum time to wait when no file has a complete line.
+const DEFAULT_MAX_POLL: Duration = Duration::from_secs(1);
+
+/// Size of each read from a file.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Shell style glob pattern for paths of files.
+///
+/// `*` matches any number of characters, `?` matches a single character, and
+/// `[...]` matches one of a set of characters, e.g. `[abc]`, `[a-z]`, or `[!a]`.
+/// Wildcards don't match `/` and don't match a `.` at the start of a file name
+/// unless the pattern has one there too. `**` isn't supported.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::watch::Pattern;
+///
+/// let pattern = Pattern::new("/var/log/nginx/*.access.log").unwrap();
+/// assert!(pattern.matches("/var/log/nginx/www.access.log"));
+/// assert!(!pattern.matches("/var/log/nginx/www.error.log"));
+/// assert!(!pattern.matches("/var/log/nginx/old/www.access.log"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    raw: String,
+    absolute: bool,
+    components: Vec<String>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> RedeyeResult<Self> {
+        let invalid = |msg: &str| RedeyeError::ConfigError(format!("Invalid pattern '{}': {}", pattern, msg));
+        let components: Vec<String> = pattern
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .map(str::to_owned)
+            .collect();
+
+        if components.is_empty() {
+            return Err(invalid("no file name"));
+        }
+
+        for component in components.iter() {
+            if component.contains("**") {
+                return Err(invalid("'**' isn't supported"));
+            }
+
+            let chars: Vec<char> = component.chars().collect();
+            let unclosed = chars
+                .iter()
+                .enumerate()
+                .any(|(i, c)| *c == '[' && match_class(&chars[i..], ' ').is_none());
+            if unclosed {
+                return Err(invalid("'[' without a matching ']'"));
+            }
+        }
+
+        Ok(Pattern {
+            raw: pattern.to_owned(),
+            absolute: pattern.starts_with('/'),
+            components,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// Return true if the path matches the pattern.
+    pub fn matches<P>(&self, path: P) -> bool
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        if path.is_absolute() != self.absolute {
+            return false;
+        }
+
+        let names: Vec<&str> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name.to_str().unwrap_or("")),
+                _ => None,
+            })
+            .collect();
+
+        names.len() == self.components.len()
+            && self
+                .components
+                .iter()
+                .zip(names)
+                .all(|(pattern, name)| match_name(pattern, name))
+    }
+
+    /// Paths of the files that currently match the pattern, sorted. Directories
+    /// that don't exist or can't be read don't have any matching files.
+    pub fn scan(&self) -> Vec<PathBuf> {
+        let root = if self.absolute {
+            PathBuf::from("/")
+        } else {
+            PathBuf::new()
+        };
+        let mut candidates = vec![root];
+
+        for component in self.components.iter() {
+            let mut next = Vec::new();
+            for dir in candidates {
+                if !component.contains(&['*', '?', '['][..]) {
+                    next.push(dir.join(component));
+                    continue;
+                }
+
+                let entries = match fs::read_dir(if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    &dir
+                }) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+
+                for entry in entries.flatten() {
+                    if let Some(name) = entry.file_name().to_str() {
+                        if match_name(component, name) {
+                            next.push(dir.join(name));
+                        }
+                    }
+                }
+            }
+
+            candidates = next;
+        }
+
+        candidates.retain(|p| p.is_file());
+        candidates.sort();
+        candidates
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = RedeyeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pattern::new(s)
+    }
+}
+
+/// Return true if a single file name matches a component of a pattern.
+fn match_name(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_chars(&pattern, &name)
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| match_chars(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && match_chars(&pattern[1..], &name[1..]),
+        Some('[') => match (
+            name.first(),
+            match_class(pattern, name.first().copied().unwrap_or('\0')),
+        ) {
+            (Some(_), Some((true, len))) => match_chars(&pattern[len..], &name[1..]),
+            _ => false,
+        },
+        Some(c) => name.first() == Some(c) && match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Match a character against the `[...]` class at the start of a pattern,
+/// returning whether it matched and the length of the class, or `None` if the
+/// class isn't closed. A `]` right after the opening bracket is part of the set.
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let start = i;
+    let mut matched = false;
+    while let Some(&ch) = pattern.get(i) {
+        if ch == ']' && i > start {
+            return Some((matched != negate, i + 1));
+        }
+
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                matched |= ch <= c && c <= end;
+                i += 3;
+            }
+            _ => {
+                matched |= ch == c;
+                i += 1;
+            }
+        }
+    }
+
+    None
+}
+
+/// Complete line read by a `FileWatcher`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchedLine<'a> {
+    /// Path of the file the line was read from.
+    pub path: &'a Path,
+    /// Number of the line in the file since it was first read, starting from one.
+    pub line: u64,
+    /// Length of the line, including the delimiter.
+    pub bytes: usize,
+    /// True if the line was longer than the maximum record size, in which case
+    /// only the first maximum number of bytes were kept.
+    pub too_long: bool,
+}
+
+/// File being read by a watcher.
+#[derive(Debug)]
+struct Watched {
+    follower: FileFollower,
+    /// Path the file was last found at, which changes if it's renamed.
+    path: PathBuf,
+    /// Data read after the last complete line.
+    partial: Vec<u8>,
+    /// Start of a line that is longer than the maximum, and the number of bytes
+    /// of it that have been discarded while looking for its end.
+    overflow: Option<Vec<u8>>,
+    skipped: usize,
+    lines: u64,
+    /// Set when no path matching the pattern refers to the file. It's read until
+    /// there's nothing left and then closed.
+    removed: bool,
+    drained: bool,
+}
+
+impl Watched {
+    fn new(follower: FileFollower) -> Self {
+        Watched {
+            path: follower.path().to_path_buf(),
+            follower,
+            partial: Vec::new(),
+            overflow: None,
+            skipped: 0,
+            lines: 0,
+            removed: false,
+            drained: false,
+        }
+    }
+
+    /// Offset just past the last complete line, where reading should start after
+    /// a restart.
+    fn consumed(&self) -> u64 {
+        self.follower.offset() - (self.partial.len() + self.skipped) as u64
+    }
+
+    /// Append the next complete line of the file to `buf` if there is one. The
+    /// rest of a removed file is returned as a line even without a delimiter since
+    /// nothing will be added to it. If `max` is set, only the first `max` bytes of
+    /// longer lines are kept while looking for the end of them.
+    fn read_line(
+        &mut self,
+        delimiter: u8,
+        max: Option<usize>,
+        buf: &mut Vec<u8>,
+        chunk: &mut [u8],
+    ) -> io::Result<Option<RecordRead>> {
+        let mut searched = 0;
+
+        loop {
+            if let Some(i) = self.partial[searched..].iter().position(|b| *b == delimiter) {
+                let end = searched + i + 1;
+                return Ok(Some(self.take(end, end - 1, max, buf)));
+            }
+
+            if let Some(max) = max {
+                if self.partial.len() > max {
+                    // Keep the start of the line for reporting and discard the rest
+                    // so that memory use doesn't depend on the length of the line.
+                    if self.overflow.is_none() {
+                        self.overflow = Some(self.partial[..max].to_vec());
+                    }
+
+                    self.skipped += self.partial.len();
+                    self.partial.clear();
+                }
+            }
+
+            searched = self.partial.len();
+            let before = self.follower.offset();
+            let n = self.follower.read_available(chunk)?;
+
+            if n == 0 {
+                if !self.removed {
+                    return Ok(None);
+                }
+
+                self.drained = true;
+                if self.partial.is_empty() && self.overflow.is_none() {
+                    return Ok(None);
+                }
+
+                let len = self.partial.len();
+                return Ok(Some(self.take(len, len, max, buf)));
+            }
+
+            // The follower starts again from the beginning of a truncated file,
+            // which makes anything read before that stale.
+            if self.follower.offset() != before + n as u64 {
+                self.partial.clear();
+                self.overflow = None;
+                self.skipped = 0;
+                searched = 0;
+            }
+
+            self.partial.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Append the line made of anything skipped so far and the first `end` bytes
+    /// of the partial data, `len` of which are the line without its delimiter.
+    fn take(&mut self, end: usize, len: usize, max: Option<usize>, buf: &mut Vec<u8>) -> RecordRead {
+        let bytes = self.skipped + end;
+        let too_long = max.map(|m| self.skipped + len > m).unwrap_or(false);
+
+        match (self.overflow.take(), max) {
+            (Some(prefix), _) => buf.extend_from_slice(&prefix),
+            (None, Some(max)) if too_long => buf.extend_from_slice(&self.partial[..max]),
+            _ => buf.extend_from_slice(&self.partial[..end]),
+        }
+
+        self.partial.drain(..end);
+        self.skipped = 0;
+        self.lines += 1;
+        RecordRead { bytes, too_long }
+    }
+}
+
+/// Offset of a file saved in a state file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SavedOffset {
+    path: PathBuf,
+    identity: Option<FileIdentity>,
+    offset: u64,
+}
+
+/// Offsets of the files read by a watcher, or the cursor of the last journal
+/// entry read, saved so that reading continues from the same place after a restart.
+#[derive(Debug)]
+pub(crate) struct StateFile {
+    path: PathBuf,
+    saved: Vec<SavedOffset>,
+    cursor: Option<String>,
+}
+
+impl StateFile {
+    /// Load offsets and the cursor from a state file, if it exists.
+    pub(crate) fn load(path: PathBuf) -> RedeyeResult<Self> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(StateFile {
+                    path,
+                    saved: Vec::new(),
+                    cursor: None,
+                })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let invalid = || RedeyeError::ConfigError(format!("Invalid state file {}", path.display()));
+        let json: Value = serde_json::from_str(&contents).map_err(|_| invalid())?;
+        let files = match json.get("files") {
+            Some(files) => files.as_array().ok_or_else(invalid)?.as_slice(),
+            None if json.is_object() => &[],
+            None => return Err(invalid()),
+        };
+
+        let cursor = match json.get("cursor") {
+            Some(cursor) => Some(cursor.as_str().ok_or_else(invalid)?.to_owned()),
+            None => None,
+        };
+
+        let mut saved = Vec::with_capacity(files.len());
+        for file in files {
+            let identity = match (
+                file.get("dev").and_then(Value::as_u64),
+                file.get("ino").and_then(Value::as_u64),
+            ) {
+                (Some(dev), Some(ino)) => Some(FileIdentity { dev, ino }),
+                _ => None,
+            };
+
+            saved.push(SavedOffset {
+                path: file
+                    .get("path")
+                    .and_then(Value::as_str)
+                    .map(PathBuf::from)
+                    .ok_or_else(invalid)?,
+                identity,
+                offset: file.get("offset").and_then(Value::as_u64).ok_or_else(invalid)?,
+            });
+        }
+
+        Ok(StateFile { path, saved, cursor })
+    }
+
+    /// Saved cursor of the last journal entry read, if there is one.
+    #[cfg(feature = "journal")]
+    pub(crate) fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    /// Saved offset of a file, matched by its identity if it's known or its path
+    /// otherwise.
+    fn offset(&self, path: &Path, identity: Option<FileIdentity>) -> Option<u64> {
+        self.saved
+            .iter()
+            .find(|s| match (identity, s.identity) {
+                (Some(a), Some(b)) => a == b,
+                _ => s.path == path,
+            })
+            .map(|s| s.offset)
+    }
+
+    /// Replace the saved offsets and write them to the file if they've changed.
+    fn save(&mut self, saved: Vec<SavedOffset>) -> io::Result<()> {
+        if saved == self.saved {
+            return Ok(());
+        }
+
+        self.saved = saved;
+        self.write()
+    }
+
+    /// Replace the saved cursor and write it to the file if it's changed.
+    #[cfg(feature = "journal")]
+    pub(crate) fn save_cursor(&mut self, cursor: &str) -> io::Result<()> {
+        if self.cursor.as_deref() == Some(cursor) {
+            return Ok(());
+        }
+
+        self.cursor = Some(cursor.to_owned());
+        self.write()
+    }
+
+    /// Write offsets and the cursor to the file. The file is replaced by renaming
+    /// a new one so it's never partially written.
+    fn write(&self) -> io::Result<()> {
+        let files: Vec<Value> = self
+            .saved
+            .iter()
+            .map(|s| {
+                let mut file = json!({"path": s.path.to_string_lossy(), "offset": s.offset});
+                if let Some(id) = s.identity {
+                    file["dev"] = json!(id.dev);
+                    file["ino"] = json!(id.ino);
+                }
+                file
+            })
+            .collect();
+
+        let mut state = json!({ "files": files });
+        if let Some(ref cursor) = self.cursor {
+            state["cursor"] = json!(cursor);
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, state.to_string())?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// Reader for every file that matches a pattern, including files created after
+/// it starts.
+///
+/// Files found when the watcher starts are read from the beginning or end,
+/// depending on `FollowFrom`, and files found later are read from the beginning.
+/// When a state file is used, files that have an offset saved in it are read
+/// from there instead.
+#[derive(Debug)]
+pub struct FileWatcher {
+    pattern: Pattern,
+    from: FollowFrom,
+    stop: Arc<AtomicBool>,
+    files: Vec<Watched>,
+    next: usize,
+    chunk: Vec<u8>,
+    max_record: Option<usize>,
+    rescan: Duration,
+    last_scan: Option<Instant>,
+    state: Option<StateFile>,
+    min_poll: Duration,
+    max_poll: Duration,
+    poll: Duration,
+}
+
+impl FileWatcher {
+    /// Create a watcher for files matching the pattern. Files aren't looked for
+    /// until the first line is read. The `stop` flag is passed to the follower
+    /// of each file.
+    pub fn new(pattern: Pattern, from: FollowFrom, stop: Arc<AtomicBool>) -> Self {
+        FileWatcher {
+            pattern,
+            from,
+            stop,
+            files: Vec::new(),
+            next: 0,
+            chunk: vec![0; READ_SIZE],
+            max_record: None,
+            rescan: DEFAULT_RESCAN,
+            last_scan: None,
+            state: None,
+            min_poll: DEFAULT_MIN_POLL,
+            max_poll: DEFAULT_MAX_POLL,
+            poll: DEFAULT_MIN_POLL,
+        }
+    }
+
+    /// Set the maximum length of a line, not counting the delimiter. Only the first
+    /// `bytes` of longer lines are kept and they're returned as too long.
+    pub fn max_record_bytes(mut self, bytes: Option<usize>) -> Self {
+        self.max_record = bytes;
+        self
+    }
+
+    /// Set how often to look for new files matching the pattern.
+    pub fn rescan_interval(mut self, interval: Duration) -> Self {
+        self.rescan = interval;
+        self
+    }
+
+    /// Set the minimum and maximum time to wait when no file has a complete line.
+    pub fn poll_interval(mut self, min: Duration, max: Duration) -> Self {
+        self.min_poll = min;
+        self.max_poll = max;
+        self.poll = min;
+        self
+    }
+
+    /// Read offsets saved in the given file and save the offsets of files being
+    /// read to it when `save_state` is called. The file doesn't need to exist.
+    pub fn state_file<P>(mut self, path: P) -> RedeyeResult<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        self.state = Some(StateFile::load(path.into())?);
+        Ok(self)
+    }
+
+    /// Paths of the files being read.
+    pub fn files(&self) -> Vec<&Path> {
+        self.files.iter().map(|f| f.path.as_path()).collect()
+    }
+
+    /// Append the next complete line from any of the files to `buf`, taking lines
+    /// from each file in turn. Looks for new files first if it has been longer
+    /// than the rescan interval. Returns `None` without waiting if no file has
+    /// a complete line.
+    pub fn read_line(&mut self, delimiter: u8, buf: &mut Vec<u8>) -> io::Result<Option<WatchedLine<'_>>> {
+        if self.last_scan.map(|t| t.elapsed() >= self.rescan).unwrap_or(true) {
+            self.scan();
+        }
+
+        let count = self.files.len();
+        for n in 0..count {
+            let i = (self.next + n) % count;
+            let file = &mut self.files[i];
+
+            let res = file
+                .read_line(delimiter, self.max_record, buf, &mut self.chunk)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file.path.display(), e)))?;
+
+            if let Some(read) = res {
+                self.next = (i + 1) % count;
+                self.poll = self.min_poll;
+
+                let file = &self.files[i];
+                return Ok(Some(WatchedLine {
+                    path: &file.path,
+                    line: file.lines,
+                    bytes: read.bytes,
+                    too_long: read.too_long,
+                }));
+            }
+        }
+
+        if self.files.iter().any(|f| f.drained) {
+            self.files.retain(|f| !f.drained);
+            self.next = 0;
+        }
+
+        Ok(None)
+    }
+
+    /// Wait after `read_line` didn't find a complete line, longer each time until
+    /// a line is found, up to the maximum poll interval.
+    pub fn wait(&mut self) {
+        thread::sleep(self.poll);
+        self.poll = (self.poll * 2).min(self.max_poll);
+    }
+
+    /// Save the offset just past the last line read from each file to the state
+    /// file, if there is one and the offsets have changed since they were last
+    /// saved. Only call this once lines that have been read have been handled.
+    pub fn save_state(&mut self) -> io::Result<()> {
+        let saved = self
+            .files
+            .iter()
+            .map(|f| SavedOffset {
+                path: f.path.clone(),
+                identity: f.follower.identity(),
+                offset: f.consumed(),
+            })
+            .collect();
+
+        match self.state {
+            Some(ref mut state) => state.save(saved),
+            None => Ok(()),
+        }
+    }
+
+    /// Look for files matching the pattern, opening new ones and marking files
+    /// that aren't matched by any path as removed.
+    fn scan(&mut self) {
+        let first = self.last_scan.is_none();
+        self.last_scan = Some(Instant::now());
+        let mut seen = vec![false; self.files.len()];
+
+        for path in self.pattern.scan() {
+            let identity = match fs::metadata(&path) {
+                Ok(meta) => FileIdentity::of(&meta),
+                Err(_) => continue,
+            };
+
+            let existing = self.files.iter().position(|f| match (identity, f.follower.identity()) {
+                (Some(a), Some(b)) => a == b,
+                _ => f.path == path,
+            });
+
+            if let Some(i) = existing {
+                seen[i] = true;
+                self.files[i].path = path;
+                self.files[i].removed = false;
+                continue;
+            }
+
+            // The file may have been deleted since the scan, in which case there's
+            // nothing to read from it.
+            if let Ok(follower) = self.open(&path, first) {
+                self.files.push(Watched::new(follower));
+                seen.push(true);
+            }
+        }
+
+        for (file, seen) in self.files.iter_mut().zip(seen) {
+            file.removed = !seen;
+        }
+    }
+
+    /// Open a file, starting where it was saved in the state file if it was.
+    fn open(&self, path: &Path, first: bool) -> io::Result<FileFollower> {
+        let from = if first { self.from } else { FollowFrom::Start };
+        let mut follower = FileFollower::open(path, from, self.stop.clone())?.reopen_rotated(false);
+
+        if let Some(offset) = self.state.as_ref().and_then(|s| s.offset(path, follower.identity())) {
+            follower.seek_to(offset)?;
+        }
+
+        Ok(follower)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileWatcher, Pattern};
+    use crate::follow::FollowFrom;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn append(path: &Path, contents: &str) {
+        let mut f = OpenOptions::new().create(true).append(true).open(path).unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+    }
+
+    fn watch(dir: &TempDir, pattern: &str, from: FollowFrom) -> FileWatcher {
+        let pattern = Pattern::new(&format!("{}/{}", dir.path().display(), pattern)).unwrap();
+        FileWatcher::new(pattern, from, Arc::new(AtomicBool::new(false)))
+            .rescan_interval(Duration::from_millis(0))
+            .poll_interval(Duration::from_millis(1), Duration::from_millis(10))
+    }
+
+    /// Read lines until there are `n` of them, as (file name, line) pairs.
+    fn read(watcher: &mut FileWatcher, n: usize) -> Vec<(String, String)> {
+        let start = Instant::now();
+        let mut lines = Vec::new();
+        let mut buf = Vec::new();
+
+        while lines.len() < n {
+            assert!(start.elapsed() < TIMEOUT, "timed out with {:?}", lines);
+            buf.clear();
+            match watcher.read_line(b'\n', &mut buf).unwrap() {
+                Some(line) => {
+                    let name = line.path.file_name().unwrap().to_string_lossy().into_owned();
+                    let text = String::from_utf8_lossy(&buf).trim_end().to_owned();
+                    lines.push((name, text));
+                }
+                None => watcher.wait(),
+            }
+        }
+
+        lines
+    }
+
+    /// Read until there are no more complete lines.
+    fn read_rest(watcher: &mut FileWatcher) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut buf = Vec::new();
+        while watcher.read_line(b'\n', &mut buf).unwrap().is_some() {
+            lines.push(String::from_utf8_lossy(&buf).trim_end().to_owned());
+            buf.clear();
+        }
+        lines
+    }
+
+    fn pair(name: &str, line: &str) -> (String, String) {
+        (name.to_owned(), line.to_owned())
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        let pattern = Pattern::new("/var/log/*/[a-c]?.log").unwrap();
+        assert!(pattern.matches("/var/log/nginx/a1.log"));
+        assert!(pattern.matches("/var/log/httpd/cc.log"));
+        assert!(!pattern.matches("/var/log/nginx/d1.log"));
+        assert!(!pattern.matches("/var/log/nginx/a12.log"));
+        assert!(!pattern.matches("/var/log/a1.log"));
+        assert!(!pattern.matches("var/log/nginx/a1.log"));
+
+        let pattern = Pattern::new("logs/[!.]*").unwrap();
+        assert!(pattern.matches("logs/access.log"));
+        assert!(!pattern.matches("logs/.hidden"));
+
+        // Wildcards don't match hidden files unless the pattern starts with a dot
+        assert!(!Pattern::new("*.log").unwrap().matches(".access.log"));
+        assert!(Pattern::new(".*.log").unwrap().matches(".access.log"));
+        assert!(Pattern::new("[]a]").unwrap().matches("]"));
+        assert!(Pattern::new("./*.log").unwrap().matches("access.log"));
+    }
+
+    #[test]
+    fn test_pattern_invalid() {
+        assert!(Pattern::new("").unwrap_err().is_config_error());
+        assert!(Pattern::new("/").is_err());
+        assert!(Pattern::new("/var/log/**/*.log").is_err());
+        assert!(Pattern::new("/var/log/[abc.log").is_err());
+    }
+
+    #[test]
+    fn test_pattern_scan() {
+        let dir = TempDir::new().unwrap();
+        for name in &["b.access.log", "a.access.log", "error.log", ".c.access.log"] {
+            append(&dir.path().join(name), "");
+        }
+        fs::create_dir(dir.path().join("d.access.log")).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        append(&dir.path().join("sub").join("e.access.log"), "");
+
+        let pattern = Pattern::new(&format!("{}/*.access.log", dir.path().display())).unwrap();
+        assert_eq!(
+            vec![dir.path().join("a.access.log"), dir.path().join("b.access.log")],
+            pattern.scan()
+        );
+
+        let pattern = Pattern::new(&format!("{}/*/*.log", dir.path().display())).unwrap();
+        assert_eq!(vec![dir.path().join("sub").join("e.access.log")], pattern.scan());
+
+        let pattern = Pattern::new(&format!("{}/missing/*.log", dir.path().display())).unwrap();
+        assert_eq!(Vec::<PathBuf>::new(), pattern.scan());
+    }
+
+    #[test]
+    fn test_file_watcher_created_and_appended() {
+        let dir = TempDir::new().unwrap();
+        let a = dir.path().join("a.log");
+        append(&a, "a1\n");
+        append(&dir.path().join("other.txt"), "x\n");
+
+        let mut watcher = watch(&dir, "*.log", FollowFrom::Start);
+        assert_eq!(vec![pair("a.log", "a1")], read(&mut watcher, 1));
+
+        append(&dir.path().join("b.log"), "b1\nb2\n");
+        append(&a, "a2\n");
+
+        // Lines are taken from each file in turn
+        let mut lines = read(&mut watcher, 3);
+        lines.sort();
+        assert_eq!(
+            vec![pair("a.log", "a2"), pair("b.log", "b1"), pair("b.log", "b2")],
+            lines
+        );
+        assert_eq!(2, watcher.files().len());
+        assert!(read_rest(&mut watcher).is_empty());
+    }
+
+    #[test]
+    fn test_file_watcher_from_end() {
+        let dir = TempDir::new().unwrap();
+        append(&dir.path().join("a.log"), "old\n");
+
+        let mut watcher = watch(&dir, "*.log", FollowFrom::End);
+        assert!(read_rest(&mut watcher).is_empty());
+
+        // Files created after starting are read from the beginning
+        append(&dir.path().join("b.log"), "new\n");
+        append(&dir.path().join("a.log"), "appended\n");
+
+        let mut lines = read(&mut watcher, 2);
+        lines.sort();
+        assert_eq!(vec![pair("a.log", "appended"), pair("b.log", "new")], lines);
+    }
+
+    #[test]
+    fn test_file_watcher_deleted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.log");
+        append(&path, "one\ntwo");
+
+        let mut watcher = watch(&dir, "*.log", FollowFrom::Start);
+        assert_eq!(vec![pair("a.log", "one")], read(&mut watcher, 1));
+
+        // The rest of a deleted file is read, even without a newline at the end,
+        // and then it's closed.
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec![pair("a.log", "two")], read(&mut watcher, 1));
+        assert!(read_rest(&mut watcher).is_empty());
+        assert!(watcher.files().is_empty());
+    }
+
+    #[test]
+    fn test_file_watcher_rotated() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.log");
+        append(&path, "one\n");
+
+        let mut watcher = watch(&dir, "*.log", FollowFrom::Start);
+        assert_eq!(vec![pair("a.log", "one")], read(&mut watcher, 1));
+
+        // Rotated to a name that doesn't match, with a new file in its place
+        append(&path, "two\n");
+        fs::rename(&path, dir.path().join("a.log.1")).unwrap();
+        append(&path, "three\n");
+
+        let lines = read(&mut watcher, 2);
+        assert!(lines.contains(&pair("a.log", "two")), "{:?}", lines);
+        assert!(lines.contains(&pair("a.log", "three")), "{:?}", lines);
+        assert!(read_rest(&mut watcher).is_empty());
+        assert_eq!(vec![path.as_path()], watcher.files());
+    }
+
+    #[test]
+    fn test_file_watcher_renamed() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.log");
+        append(&path, "one\n");
+
+        let mut watcher = watch(&dir, "*.log", FollowFrom::Start);
+        assert_eq!(vec![pair("a.log", "one")], read(&mut watcher, 1));
+
+        // Renamed to another matching path, which isn't read again from the start
+        let renamed = dir.path().join("b.log");
+        fs::rename(&path, &renamed).unwrap();
+        append(&renamed, "two\n");
+
+        assert_eq!(vec![pair("b.log", "two")], read(&mut watcher, 1));
+        assert!(read_rest(&mut watcher).is_empty());
+        assert_eq!(vec![renamed.as_path()], watcher.files());
+    }
+
+    #[test]
+    fn test_file_watcher_max_record_bytes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.log");
+        let long = "x".repeat(200_000);
+        append(&path, &format!("12345\n123456\n{}\nok\n{}", long, long));
+
+        let mut watcher = watch(&dir, "*.log", FollowFrom::Start).max_record_bytes(Some(5));
+        let mut buf = Vec::new();
+        let mut lines = Vec::new();
+        while let Some(line) = watcher.read_line(b'\n', &mut buf).unwrap() {
+            lines.push((String::from_utf8_lossy(&buf).into_owned(), line.bytes, line.too_long));
+            buf.clear();
+        }
+
+        assert_eq!(
+            vec![
+                ("12345\n".to_owned(), 6, false),
+                ("12345".to_owned(), 7, true),
+                ("xxxxx".to_owned(), 200_001, true),
+                ("ok\n".to_owned(), 3, false),
+            ],
+            lines
+        );
+
+        // The rest of an overly long line is discarded until its end is found, and
+        // reported once it is.
+        append(&path, "\n");
+        let line = loop {
+            match watcher.read_line(b'\n', &mut buf).unwrap() {
+                Some(line) => break (line.bytes, line.too_long),
+                None => watcher.wait(),
+            }
+        };
+        assert_eq!((200_001, true), line);
+        assert_eq!(b"xxxxx", buf.as_slice());
+    }
+
+    #[test]
+    fn test_file_watcher_state_file() {
+        let dir = TempDir::new().unwrap();
+        let logs = TempDir::new().unwrap();
+        let state = dir.path().join("state.json");
+        append(&logs.path().join("a.log"), "one\ntwo\nthr");
+
+        let mut watcher = watch(&logs, "*.log", FollowFrom::Start).state_file(&state).unwrap();
+        assert_eq!(2, read(&mut watcher, 2).len());
+        watcher.save_state().unwrap();
+        drop(watcher);
+
+        // Reading continues after the last complete line
+        append(&logs.path().join("a.log"), "ee\n");
+        append(&logs.path().join("b.log"), "b1\n");
+        let mut watcher = watch(&logs, "*.log", FollowFrom::End).state_file(&state).unwrap();
+        let lines = read(&mut watcher, 1);
+        assert_eq!(vec![pair("a.log", "three")], lines);
+
+        // Files without a saved offset use the starting position
+        assert!(read_rest(&mut watcher).is_empty());
+    }
+
+    #[test]
+    fn test_file_watcher_state_file_invalid() {
+        let dir = TempDir::new().unwrap();
+        let state = dir.path().join("state.json");
+        fs::write(&state, "not json").unwrap();
+
+        let err = watch(&dir, "*.log", FollowFrom::Start).state_file(&state).unwrap_err();
+        assert!(err.is_config_error());
+        assert!(err.to_string().contains("state.json"), "{}", err);
+    }
+}
@@ -0,0 +1,521 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Processing lines on multiple threads while preserving their order
+
+use crate::io::{read_record, Delimiter};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Default number of lines sent to a worker thread at once.
+const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Default number of batches that may be read but not yet handled per worker.
+const DEFAULT_BATCHES_PER_WORKER: usize = 4;
+
+/// Result of processing a single line along with its position in the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Processed<T> {
+    /// Line number in the input, starting at 1.
+    pub line: u64,
+    /// Number of bytes read for the line, including the delimiter.
+    pub bytes: usize,
+    /// Value returned by the processing function.
+    pub value: T,
+}
+
+/// Line passed to the processing function of a `WorkerPool`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// Contents of the line including the trailing delimiter, if any. Only the
+    /// first maximum number of bytes if the line was too long.
+    pub data: &'a [u8],
+    /// True if the line was longer than the maximum record size.
+    pub too_long: bool,
+}
+
+/// End of a line within a batch along with the number of bytes read for it.
+struct Span {
+    end: usize,
+    bytes: usize,
+    too_long: bool,
+}
+
+/// Lines read together, stored contiguously to avoid an allocation per line.
+struct Batch {
+    seq: u64,
+    first_line: u64,
+    data: Vec<u8>,
+    spans: Vec<Span>,
+}
+
+/// Pool of threads that process lines read from a single reader.
+///
+/// Lines are read on one thread and sent in batches to worker threads. Results
+/// are handed back to the calling thread in the same order the lines were read.
+/// The number of batches that have been read but not yet handled is bounded so
+/// if handling results is slow, the workers and reader wait instead of buffering
+/// an unbounded amount of input.
+///
+/// # Example
+///
+/// ```rust
+/// use redeye::workers::WorkerPool;
+/// use std::io::{self, Cursor};
+///
+/// let mut lengths = Vec::new();
+/// WorkerPool::new(4)
+///     .run(Cursor::new("a\nbb\nccc\n"), |r| r.data.len(), |p| -> io::Result<()> {
+///         lengths.push((p.line, p.value));
+///         Ok(())
+///     })
+///     .unwrap();
+///
+/// assert_eq!(vec![(1, 2), (2, 3), (3, 4)], lengths);
+/// ```
+#[derive(Debug, Clone)]
+pub struct WorkerPool {
+    workers: usize,
+    batch_size: usize,
+    max_batches: usize,
+    delimiter: u8,
+    max_record: Option<usize>,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl WorkerPool {
+    /// Create a pool that uses the given number of worker threads (at least one).
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        WorkerPool {
+            workers,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_batches: workers * DEFAULT_BATCHES_PER_WORKER,
+            delimiter: Delimiter::Newline.byte(),
+            max_record: None,
+            stop: None,
+        }
+    }
+
+    /// Set the number of lines sent to a worker thread at once.
+    pub fn batch_size(mut self, lines: usize) -> Self {
+        self.batch_size = lines.max(1);
+        self
+    }
+
+    /// Set the number of batches that may be read but not yet handled.
+    pub fn max_batches(mut self, batches: usize) -> Self {
+        self.max_batches = batches.max(1);
+        self
+    }
+
+    /// Set the delimiter that lines are split on, a newline by default.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter.byte();
+        self
+    }
+
+    /// Set the maximum length of a line, not counting the delimiter. Only the first
+    /// `bytes` of longer lines are kept and passed to be processed as too long.
+    pub fn max_record_bytes(mut self, bytes: Option<usize>) -> Self {
+        self.max_record = bytes;
+        self
+    }
+
+    /// Stop reading new lines once the flag is set. Lines that have already been
+    /// read are still processed and handled.
+    pub fn stop_when(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.stop = Some(flag);
+        self
+    }
+
+    /// Read lines from the reader, call `process` on each of them (including
+    /// the trailing delimiter, if any) on a worker thread, and call `handle` with
+    /// each result on this thread in the order the lines were read.
+    ///
+    /// If `handle` returns an error, reading stops and the error is returned. If
+    /// reading fails, results for lines read before the failure are handled and
+    /// then the error is returned.
+    pub fn run<R, F, T, H, E>(&self, reader: R, process: F, mut handle: H) -> Result<(), E>
+    where
+        R: BufRead + Send,
+        F: Fn(Record<'_>) -> T + Sync,
+        T: Send,
+        H: FnMut(Processed<T>) -> Result<(), E>,
+        E: From<io::Error>,
+    {
+        thread::scope(|scope| {
+            let (job_tx, job_rx) = mpsc::sync_channel::<Batch>(self.workers);
+            let (done_tx, done_rx) = mpsc::sync_channel::<(u64, Vec<Processed<T>>)>(self.workers);
+            let (permit_tx, permit_rx) = mpsc::sync_channel::<()>(self.max_batches);
+            let batch_size = self.batch_size;
+            let delimiter = self.delimiter;
+            let max_record = self.max_record;
+
+            let stop = self.stop.clone();
+            let reader =
+                scope.spawn(move || read_batches(reader, batch_size, delimiter, max_record, stop, job_tx, permit_tx));
+
+            // The receiver for jobs is only referenced by workers so that once they
+            // have all stopped, the reader is unable to send any more jobs and stops.
+            let job_rx = Arc::new(Mutex::new(job_rx));
+            for _ in 0..self.workers {
+                let job_rx = job_rx.clone();
+                let done_tx = done_tx.clone();
+                let process = &process;
+
+                scope.spawn(move || loop {
+                    let batch = match job_rx.lock().unwrap().recv() {
+                        Ok(b) => b,
+                        Err(_) => return,
+                    };
+
+                    let mut start = 0;
+                    let mut out = Vec::with_capacity(batch.spans.len());
+                    for (i, span) in batch.spans.iter().enumerate() {
+                        let record = Record {
+                            data: &batch.data[start..span.end],
+                            too_long: span.too_long,
+                        };
+                        out.push(Processed {
+                            line: batch.first_line + i as u64,
+                            bytes: span.bytes,
+                            value: process(record),
+                        });
+                        start = span.end;
+                    }
+
+                    if done_tx.send((batch.seq, out)).is_err() {
+                        return;
+                    }
+                });
+            }
+
+            drop(job_rx);
+            drop(done_tx);
+
+            reorder(done_rx, permit_rx, &mut handle)?;
+            match reader.join() {
+                Ok(res) => res.map_err(E::from),
+                Err(e) => std::panic::resume_unwind(e),
+            }
+        })
+    }
+}
+
+/// Read batches of lines and send them to workers, waiting for a permit before
+/// reading each batch, until the end of the input or the stop flag is set.
+fn read_batches<R>(
+    mut reader: R,
+    batch_size: usize,
+    delimiter: u8,
+    max_record: Option<usize>,
+    stop: Option<Arc<AtomicBool>>,
+    jobs: SyncSender<Batch>,
+    permits: SyncSender<()>,
+) -> io::Result<()>
+where
+    R: BufRead,
+{
+    let stopped = || stop.as_ref().map(|s| s.load(Ordering::Acquire)).unwrap_or(false);
+    let mut seq = 0;
+    let mut line = 1;
+
+    loop {
+        if permits.send(()).is_err() {
+            return Ok(());
+        }
+
+        let mut batch = Batch {
+            seq,
+            first_line: line,
+            data: Vec::new(),
+            spans: Vec::with_capacity(batch_size),
+        };
+
+        // Lines read before an error are still sent to be processed. Any part
+        // of a line read when the error happened is ignored.
+        let mut err = None;
+        while batch.spans.len() < batch_size && !stopped() {
+            match read_record(&mut reader, delimiter, max_record, &mut batch.data) {
+                Ok(None) => break,
+                Ok(Some(read)) => batch.spans.push(Span {
+                    end: batch.data.len(),
+                    bytes: read.bytes,
+                    too_long: read.too_long,
+                }),
+                Err(e) => {
+                    err = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let lines = batch.spans.len();
+        if lines > 0 && jobs.send(batch).is_err() {
+            return Ok(());
+        }
+
+        if let Some(e) = err {
+            return Err(e);
+        }
+
+        if lines == 0 {
+            return Ok(());
+        }
+
+        seq += 1;
+        line += lines as u64;
+    }
+}
+
+/// Hand results to `handle` in order, releasing a permit for each batch handled.
+fn reorder<T, H, E>(done: Receiver<(u64, Vec<Processed<T>>)>, permits: Receiver<()>, handle: &mut H) -> Result<(), E>
+where
+    H: FnMut(Processed<T>) -> Result<(), E>,
+{
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    for (seq, results) in done {
+        pending.insert(seq, results);
+
+        while let Some(results) = pending.remove(&next) {
+            for r in results {
+                handle(r)?;
+            }
+
+            // A permit is always sent before the batch it is for
+            let _ = permits.recv();
+            next += 1;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Processed, Record, WorkerPool};
+    use crate::io::Delimiter;
+    use std::io::{self, Cursor, Read};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn input(lines: usize) -> String {
+        (0..lines).map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[test]
+    fn test_worker_pool_preserves_order() {
+        let mut out = Vec::new();
+        WorkerPool::new(8)
+            .batch_size(3)
+            .run(
+                Cursor::new(input(1000)),
+                |Record { data: line, .. }| {
+                    // Make later lines faster so batches finish out of order
+                    let n: u64 = std::str::from_utf8(&line[5..line.len() - 1]).unwrap().parse().unwrap();
+                    if n.is_multiple_of(7) {
+                        thread::sleep(Duration::from_micros(100));
+                    }
+                    String::from_utf8(line.to_vec()).unwrap()
+                },
+                |p| -> io::Result<()> {
+                    out.push(p);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert_eq!(1000, out.len());
+        for (i, p) in out.iter().enumerate() {
+            assert_eq!(i as u64 + 1, p.line);
+            assert_eq!(format!("line {}\n", i), p.value);
+            assert_eq!(p.value.len(), p.bytes);
+        }
+    }
+
+    #[test]
+    fn test_worker_pool_last_line_without_newline() {
+        let mut out = Vec::new();
+        WorkerPool::new(2)
+            .run(
+                Cursor::new("a\nb"),
+                |r| r.data.to_vec(),
+                |p| -> io::Result<()> {
+                    out.push(p);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                Processed {
+                    line: 1,
+                    bytes: 2,
+                    value: b"a\n".to_vec()
+                },
+                Processed {
+                    line: 2,
+                    bytes: 1,
+                    value: b"b".to_vec()
+                },
+            ],
+            out
+        );
+    }
+
+    #[test]
+    fn test_worker_pool_nul_delimiter() {
+        let mut out = Vec::new();
+        WorkerPool::new(2)
+            .batch_size(1)
+            .delimiter(Delimiter::Nul)
+            .run(
+                Cursor::new("a\nb\0c\0"),
+                |r| r.data.to_vec(),
+                |p| -> io::Result<()> {
+                    out.push(p.value);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert_eq!(vec![b"a\nb\0".to_vec(), b"c\0".to_vec()], out);
+    }
+
+    #[test]
+    fn test_worker_pool_max_record_bytes() {
+        let mut out = Vec::new();
+        WorkerPool::new(2)
+            .batch_size(2)
+            .max_record_bytes(Some(3))
+            .run(
+                Cursor::new("abc\nabcd\nab\nabcdefgh"),
+                |r| (r.data.to_vec(), r.too_long),
+                |p| -> io::Result<()> {
+                    out.push((p.line, p.bytes, p.value));
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                (1, 4, (b"abc\n".to_vec(), false)),
+                (2, 5, (b"abc".to_vec(), true)),
+                (3, 3, (b"ab\n".to_vec(), false)),
+                (4, 8, (b"abc".to_vec(), true)),
+            ],
+            out
+        );
+    }
+
+    #[test]
+    fn test_worker_pool_handle_error_stops() {
+        let processed = AtomicUsize::new(0);
+        let mut handled = 0;
+
+        let res = WorkerPool::new(4).batch_size(1).max_batches(2).run(
+            Cursor::new(input(10_000)),
+            |_| processed.fetch_add(1, Ordering::Relaxed),
+            |p| {
+                handled += 1;
+                if p.line == 5 {
+                    Err(io::Error::other("stop"))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+
+        assert_eq!("stop", res.unwrap_err().to_string());
+        assert_eq!(5, handled);
+        // Only a bounded number of lines past the failure are ever processed
+        assert!(processed.load(Ordering::Relaxed) < 100);
+    }
+
+    #[test]
+    fn test_worker_pool_stop_when() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut handled = Vec::new();
+
+        WorkerPool::new(2)
+            .batch_size(4)
+            .max_batches(1)
+            .stop_when(stop.clone())
+            .run(
+                Cursor::new(input(1000)),
+                |r| r.data.len(),
+                |p| -> io::Result<()> {
+                    if p.line == 3 {
+                        stop.store(true, Ordering::Release);
+                    }
+                    handled.push(p.line);
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        // Only the batch in progress when the flag was set (and at most one more,
+        // since a permit is released as soon as a batch is handled) is handled.
+        assert!(handled.len() >= 4 && handled.len() <= 8, "{:?}", handled);
+        assert_eq!((1..=handled.len() as u64).collect::<Vec<u64>>(), handled);
+    }
+
+    struct FailingReader {
+        inner: Cursor<String>,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.inner.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn test_worker_pool_read_error_after_results() {
+        let reader = io::BufReader::new(FailingReader {
+            inner: Cursor::new(input(10)),
+        });
+
+        let mut handled = 0;
+        let res = WorkerPool::new(2).batch_size(4).run(
+            reader,
+            |r| r.data.len(),
+            |_| -> io::Result<()> {
+                handled += 1;
+                Ok(())
+            },
+        );
+
+        assert_eq!(io::ErrorKind::InvalidData, res.unwrap_err().kind());
+        assert_eq!(10, handled);
+    }
+}
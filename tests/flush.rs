@@ -0,0 +1,77 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Delivery of events from slow input without waiting for the output buffer to fill
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+const LINE: &str = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326\n";
+
+/// Write a single line to redeye and keep its input open, returning the first
+/// event written if there is one within the timeout.
+fn first_event(args: &[&str], timeout: Duration) -> Option<String> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args(["--common-format", "--no-summary"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(LINE.as_bytes()).unwrap();
+    stdin.flush().unwrap();
+
+    let stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let reader = thread::spawn(move || {
+        let mut line = String::new();
+        BufReader::new(stdout).read_line(&mut line).unwrap();
+        let _ = tx.send(line);
+    });
+
+    let event = rx.recv_timeout(timeout).ok();
+
+    // Closing input lets redeye exit, flushing anything still buffered.
+    drop(stdin);
+    child.wait().unwrap();
+    reader.join().unwrap();
+    event
+}
+
+#[test]
+fn test_buffered_until_exit() {
+    assert_eq!(None, first_event(&[], Duration::from_millis(500)));
+}
+
+#[test]
+fn test_line_buffered() {
+    let event = first_event(&["--line-buffered"], Duration::from_secs(5)).unwrap();
+    assert!(event.contains("\"requested_uri\":\"/index.html\""), "{}", event);
+}
+
+#[test]
+fn test_flush_interval() {
+    let event = first_event(&["--flush-interval", "100ms"], Duration::from_secs(5)).unwrap();
+    assert!(event.contains("\"requested_uri\":\"/index.html\""), "{}", event);
+}
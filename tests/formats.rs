@@ -0,0 +1,260 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Selecting each format in the parser registry by name from the command line
+
+use serde_json::Value;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run redeye with the given arguments and input on stdin.
+fn redeye(args: &[&str], input: &str) -> Output {
+    run(&[&["--no-summary", "--quiet"][..], args].concat(), input)
+}
+
+/// Run redeye with exactly the given arguments, e.g. for a subcommand, and input on stdin.
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Parse a single line in the format with the given name, returning the event.
+fn parse(format: &str, line: &str) -> Value {
+    parse_with(format, &[], line)
+}
+
+/// Parse a single line in the format with the given name and extra arguments.
+fn parse_with(format: &str, args: &[&str], line: &str) -> Value {
+    let output = redeye(&[&["--format", format][..], args].concat(), &format!("{}\n", line));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(1, lines.len(), "{}: {:?}", format, lines);
+    serde_json::from_str(lines[0]).unwrap()
+}
+
+#[test]
+fn test_list_formats() {
+    let output = redeye(&["--list-formats"], "");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let names: Vec<&str> = stdout.lines().collect();
+    for name in &[
+        "common",
+        "combined",
+        "vhost_combined",
+        "nginx_timed",
+        "error",
+        "agent",
+        "w3c",
+        "squid",
+        "logfmt",
+        "forensic",
+    ] {
+        assert!(names.contains(name), "{} not in {:?}", name, names);
+    }
+}
+
+#[test]
+fn test_format_unknown() {
+    let output = redeye(&["--format", "yaml"], "");
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown format 'yaml'"), "{}", stderr);
+}
+
+#[test]
+fn test_format_print_schema() {
+    let output = redeye(&["--format", "combined_duration_us", "--print-schema"], "");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let fields: Vec<&str> = stdout.lines().rev().take(3).collect();
+    assert_eq!(vec!["message\ttext", "@version\ttext", "duration_us\tint"], fields);
+}
+
+#[test]
+fn test_format_print_schema_per_line() {
+    let output = redeye(&["--format", "ltsv", "--print-schema"], "");
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("the fields of the ltsv format depend on each line"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_format_common() {
+    let event = parse(
+        "common",
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.0\" 200 2326",
+    );
+    assert_eq!(200, event["status_code"]);
+}
+
+#[test]
+fn test_format_nginx_timed() {
+    let event = parse(
+        "nginx_timed",
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 0.042",
+    );
+    assert_eq!(0.042, event["request_time"]);
+}
+
+#[test]
+fn test_format_error() {
+    let line = "[Wed Oct 11 14:32:52.123456 2000] [core:error] [pid 1234:tid 5678] [client 127.0.0.1:4321] AH00037: Symbolic link not allowed";
+
+    let event = parse("error", line);
+    assert_eq!("error", event["level"]);
+    assert_eq!(1234, event["pid"]);
+    assert_eq!("2000-10-11T14:32:52.123456+00:00", event["@timestamp"]);
+
+    // Negative offsets may be given as a separate argument, not only after `=`
+    for args in &[&["--assume-timezone", "-07:00"][..], &["--assume-timezone=-07:00"][..]] {
+        let event = parse_with("error", args, line);
+        assert_eq!("2000-10-11T14:32:52.123456-07:00", event["@timestamp"], "{:?}", args);
+    }
+}
+
+#[test]
+fn test_format_combined_duration() {
+    let line = "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\"";
+
+    for (format, duration) in &[
+        ("combined_duration_us", "1500"),
+        ("combined_duration_ms", "1.5"),
+        ("combined_duration_s", "0.0015"),
+    ] {
+        let event = parse(format, &format!("{} {}", line, duration));
+        assert_eq!(1500, event["duration_us"], "{}", format);
+    }
+}
+
+#[test]
+fn test_format_nginx_upstream() {
+    let event = parse(
+        "nginx_upstream",
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 2326 \"-\" \"curl/7.64.1\" 10.0.0.1:80, 10.0.0.2:80 0.002, 0.010 502, 200",
+    );
+    assert_eq!(
+        serde_json::json!(["10.0.0.1:80", "10.0.0.2:80"]),
+        event["upstream"]["addr"]
+    );
+    assert_eq!(serde_json::json!([502, 200]), event["upstream"]["status"]);
+}
+
+#[test]
+fn test_format_ingress_nginx() {
+    let event = parse(
+        "ingress_nginx",
+        concat!(
+            "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"POST /api HTTP/2.0\" 200 17 \"-\" \"curl/7.64.1\" 412 0.052 ",
+            "[prod-api-8080] [] 10.244.0.7:8080 17 0.052 200 5f9c1ae8b4d1c2a3e4f5a6b7c8d9e0f1",
+        ),
+    );
+    assert_eq!("prod-api-8080", event["proxy_upstream_name"]);
+    assert_eq!("5f9c1ae8b4d1c2a3e4f5a6b7c8d9e0f1", event["request_id"]);
+}
+
+#[test]
+fn test_format_squid() {
+    let event = parse(
+        "squid",
+        "1157689324.156   1372 10.0.0.1 TCP_MISS/200 399 GET http://example.com/ - DIRECT/192.0.2.1 text/html",
+    );
+    assert_eq!("TCP_MISS", event["cache_status"]);
+    assert_eq!(1372, event["duration_ms"]);
+}
+
+#[test]
+fn test_format_stats_duration_ms() {
+    let input = concat!(
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.1\" 200 2326 42\n",
+        "127.0.0.1 - - [10/Oct/2000:13:55:37 +0000] \"GET /a HTTP/1.1\" 200 12 8\n",
+    );
+    let output = run(&["stats", "--json", "--format", "tomcat"], input);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(2, report["duration_ms"]["count"]);
+    assert_eq!(42.0, report["duration_ms"]["max"]);
+    assert_eq!(8.0, report["duration_ms"]["min"]);
+}
+
+#[test]
+fn test_format_agent_detect_bots() {
+    let event = parse_with("agent", &["--detect-bots"], "Mozilla/5.0 (X11; Linux x86_64)");
+    assert_eq!(
+        "Mozilla/5.0 (X11; Linux x86_64)",
+        event["request_headers"]["user_agent"]
+    );
+    assert_eq!(false, event["bot"]);
+
+    let event = parse_with(
+        "agent",
+        &["--detect-bots"],
+        "Googlebot/2.1 (+http://www.google.com/bot.html)",
+    );
+    assert_eq!(true, event["bot"]);
+}
+
+#[test]
+fn test_format_apache_varnish_extensions() {
+    let spec = "%h %t \"%r\" %s %{Varnish:handling}x %{VSL:Timestamp[2]}x";
+    let line = "127.0.0.1 [10/Oct/2000:13:55:36 -0700] \"GET / HTTP/1.1\" 200 hit 0.000125\n";
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("format.txt");
+    std::fs::write(&path, format!("{}\n", spec)).unwrap();
+
+    for args in &[
+        vec!["--format-apache", spec],
+        vec!["--format-file", path.to_str().unwrap()],
+    ] {
+        let output = redeye(args, line);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let event: Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!("hit", event["varnish"]["handling"], "{:?}", args);
+        assert_eq!("0.000125", event["varnish"]["vsl_timestamp_2"], "{:?}", args);
+    }
+}
+
+#[test]
+fn test_format_python_dev_server() {
+    let event = parse(
+        "python_dev_server",
+        "127.0.0.1 - - [10/Oct/2020 13:55:36] \"GET /api HTTP/1.1\" 200 -",
+    );
+    assert_eq!("2020-10-10T13:55:36+00:00", event["@timestamp"]);
+}
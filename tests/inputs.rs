@@ -0,0 +1,134 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Reading inputs in different formats with --input
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+const COMBINED_LINE: &str = concat!(
+    "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /combined HTTP/1.0\" 200 2326 ",
+    "\"http://www.example.com/start.html\" \"curl/7.64.1\"\n"
+);
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+/// Run redeye with the given arguments, returning each event written and stderr.
+fn run(args: &[&str]) -> (Vec<serde_json::Value>, String, bool) {
+    let output = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .arg("--no-summary")
+        .args(args)
+        .output()
+        .unwrap();
+
+    let events = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+
+    (
+        events,
+        String::from_utf8(output.stderr).unwrap(),
+        output.status.success(),
+    )
+}
+
+fn combined_log(dir: &Path) -> String {
+    let path = dir.join("combined.log");
+    fs::write(&path, COMBINED_LINE).unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+fn assert_mixed(events: &[serde_json::Value], combined: &str) {
+    assert_eq!(6, events.len());
+
+    let event = &events[0];
+    assert_eq!(combined, event["source_file"]);
+    assert_eq!("combined", event["source_format"]);
+    assert_eq!("/combined", event["requested_uri"]);
+    assert_eq!("curl/7.64.1", event["request_headers"]["user_agent"]);
+
+    for event in &events[1..] {
+        assert_eq!(fixture("frontend-a.log").to_str().unwrap(), event["source_file"]);
+        assert_eq!("common", event["source_format"]);
+        assert!(event["requested_uri"].as_str().unwrap().starts_with("/frontend-a/"));
+        assert!(event.get("request_headers").is_none());
+    }
+}
+
+#[test]
+fn test_inputs_in_different_formats() {
+    let dir = TempDir::new().unwrap();
+    let combined = combined_log(dir.path());
+    let common = format!("{}/frontend-[a].log", fixture("").display());
+
+    let (events, stderr, success) = run(&[
+        "--input",
+        &format!("path={},format=combined", combined),
+        "--input",
+        &format!("path={},format=common", common),
+    ]);
+
+    assert!(success, "stderr: {}", stderr);
+    assert_mixed(&events, &combined);
+}
+
+#[test]
+fn test_inputs_from_config_file() {
+    let dir = TempDir::new().unwrap();
+    let combined = combined_log(dir.path());
+    let config = dir.path().join("redeye.toml");
+    fs::write(
+        &config,
+        format!(
+            "input = [\n  {{ path = \"{}\", format = \"combined\" }},\n  \"path={},format=common\",\n]\n",
+            combined,
+            fixture("frontend-a.log").display()
+        ),
+    )
+    .unwrap();
+
+    let (events, stderr, success) = run(&["--config", config.to_str().unwrap()]);
+
+    assert!(success, "stderr: {}", stderr);
+    assert_mixed(&events, &combined);
+}
+
+#[test]
+fn test_inputs_unknown_format() {
+    let dir = TempDir::new().unwrap();
+    let combined = combined_log(dir.path());
+
+    let (events, stderr, success) = run(&["--input", &format!("path={},format=haproxy-http", combined)]);
+
+    assert!(!success);
+    assert!(events.is_empty());
+    assert!(
+        stderr.contains("unknown format 'haproxy-http' for input"),
+        "stderr: {}",
+        stderr
+    );
+}
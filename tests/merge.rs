@@ -0,0 +1,98 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Merging multiple files into a single stream ordered by timestamp
+
+use std::path::PathBuf;
+use std::process::Command;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+/// Run redeye merging the frontend fixtures, returning the requested URI of each
+/// event and the summary.
+fn merge(window: &str) -> (Vec<String>, serde_json::Value) {
+    let output = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args([
+            "--format",
+            "common",
+            "--summary-json",
+            "--merge-by-time",
+            "--merge-window",
+            window,
+        ])
+        .arg(fixture("frontend-a.log"))
+        .arg(fixture("frontend-b.log"))
+        .arg(fixture("frontend-c.log"))
+        .output()
+        .unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "stderr: {}", stderr);
+
+    let uris = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|l| {
+            let event: serde_json::Value = serde_json::from_str(l).unwrap();
+            event["requested_uri"].as_str().unwrap().to_owned()
+        })
+        .collect();
+
+    (uris, serde_json::from_str(stderr.lines().last().unwrap()).unwrap())
+}
+
+#[test]
+fn test_merge_skewed_files() {
+    // Each file is out of order by up to a second, except for the last entry of
+    // frontend-c, which is far earlier than the entries before it.
+    let (uris, summary) = merge("2s");
+    assert_eq!(
+        vec![
+            "/frontend-a/00",
+            "/frontend-b/01",
+            "/frontend-a/02",
+            "/frontend-a/03",
+            "/frontend-b/04",
+            "/frontend-b/05",
+            "/frontend-b/06",
+            "/frontend-a/07",
+            "/frontend-c/08",
+            "/frontend-a/09",
+            "/frontend-b/10",
+            "/frontend-c/01",
+            "/frontend-c/20",
+        ],
+        uris
+    );
+    assert_eq!(13, summary["events_emitted"]);
+    assert_eq!(1, summary["events_late"]);
+}
+
+#[test]
+fn test_merge_without_window() {
+    // Entries out of order within a file are late without a window to wait for them
+    let (uris, summary) = merge("0s");
+    assert_eq!(13, uris.len());
+    assert_eq!(vec!["/frontend-a/00", "/frontend-b/01"], uris[..2].to_vec());
+    assert_eq!(3, summary["events_late"]);
+}
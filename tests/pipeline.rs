@@ -0,0 +1,1128 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Running the library pipeline end to end over in-memory input and output
+
+use redeye::aggregate::{Metric, RequestSummary, TopValues};
+use redeye::clock::ManualClock;
+use redeye::dedup::Dedup;
+use redeye::enrich::HttpVersionEnricher;
+use redeye::failures::{FailureReason, Failures};
+use redeye::io::{Delimiter, Output, Utf8Policy};
+use redeye::metrics::{MetricValue, Metrics};
+use redeye::net::DatagramReceiver;
+use redeye::parser::{CommonLogLineParser, ForensicLogParser, SyslogLogLineParser, W3cLogLineParser};
+use redeye::pipeline::{self, PipelineConfig, PipelineError, PipelineSummary};
+use redeye::sample::Sampler;
+use redeye::stats::Stats;
+use redeye::types::RedeyeError;
+use redeye::warnings::{Warn, WarningFormat, WarningSink};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Write};
+use std::net::UdpSocket;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const FIRST: &str = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /apache_pb.gif HTTP/1.0\" 200 2326";
+const SECOND: &str = "::1 - - [10/Oct/2000:13:55:37 -0700] \"POST /login HTTP/1.1\" 302 -";
+
+fn first_event() -> Value {
+    json!({
+        "@timestamp": "2000-10-10T13:55:36-07:00",
+        "@version": "1",
+        "content_length": 2326,
+        "message": FIRST,
+        "method": "GET",
+        "protocol": "HTTP/1.0",
+        "remote_host": "127.0.0.1",
+        "remote_user": "frank",
+        "requested_uri": "/apache_pb.gif",
+        "requested_url": "GET /apache_pb.gif HTTP/1.0",
+        "status_code": 200,
+    })
+}
+
+fn second_event() -> Value {
+    json!({
+        "@timestamp": "2000-10-10T13:55:37-07:00",
+        "@version": "1",
+        "message": SECOND,
+        "method": "POST",
+        "protocol": "HTTP/1.1",
+        "remote_host": "::1",
+        "requested_uri": "/login",
+        "requested_url": "POST /login HTTP/1.1",
+        "status_code": 302,
+    })
+}
+
+fn config() -> PipelineConfig {
+    PipelineConfig::new(Arc::new(CommonLogLineParser::new()))
+}
+
+/// Events written, parsed so they can be compared regardless of field order.
+fn events(out: &[u8], delimiter: u8) -> Vec<Value> {
+    out.split(|b| *b == delimiter)
+        .filter(|e| !e.is_empty())
+        .map(|e| serde_json::from_slice(e).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_pipeline_run() {
+    let input = format!("{}\nnot a log line\n{}\r\n", FIRST, SECOND);
+
+    for workers in &[1, 4] {
+        let mut out = Vec::new();
+        let summary = pipeline::run(&config().workers(*workers), Cursor::new(input.clone()), &mut out).unwrap();
+
+        assert_eq!(vec![first_event(), second_event()], events(&out, b'\n'));
+        assert_eq!(
+            PipelineSummary {
+                lines: 3,
+                events: 2,
+                errors: 1,
+                skipped: 0,
+                bytes_in: input.len() as u64,
+                bytes_out: out.len() as u64,
+            },
+            summary
+        );
+    }
+}
+
+#[test]
+fn test_pipeline_run_in_order() {
+    let input: String = (0..5000)
+        .map(|i| {
+            format!(
+                "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /{} HTTP/1.0\" 200 {}\n",
+                i, i
+            )
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config().workers(8), Cursor::new(input), &mut out).unwrap();
+    assert_eq!(5000, summary.events);
+
+    for (i, event) in events(&out, b'\n').iter().enumerate() {
+        assert_eq!(json!(format!("/{}", i)), event["requested_uri"]);
+    }
+}
+
+#[test]
+fn test_pipeline_run_enrichers() {
+    let config = config().enricher(Arc::new(HttpVersionEnricher::new()));
+    let mut out = Vec::new();
+    pipeline::run(&config, Cursor::new(format!("{}\n{}\n", FIRST, SECOND)), &mut out).unwrap();
+
+    let events = events(&out, b'\n');
+    assert_eq!(json!(1.0), events[0]["http_version"]);
+    assert_eq!(json!(1.1), events[1]["http_version"]);
+}
+
+#[test]
+fn test_pipeline_run_delimiters_and_limit() {
+    let config = config()
+        .input_delimiter(Delimiter::Nul)
+        .output_delimiter(Delimiter::Nul)
+        .max_record_bytes(Some(FIRST.len()));
+    let input = format!("{}\0{}x\0{}", FIRST, FIRST, SECOND);
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config, Cursor::new(input.clone()), &mut out).unwrap();
+
+    assert_eq!(vec![first_event(), second_event()], events(&out, 0));
+    assert_eq!(3, summary.lines);
+    assert_eq!(1, summary.errors);
+    assert_eq!(input.len() as u64, summary.bytes_in);
+}
+
+#[test]
+fn test_pipeline_run_stopped() {
+    let config = config().stop_when(Arc::new(AtomicBool::new(true)));
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config, Cursor::new(format!("{}\n", FIRST)), &mut out).unwrap();
+
+    assert_eq!(PipelineSummary::default(), summary);
+    assert!(out.is_empty());
+}
+
+/// Writer that fails once more than a few bytes have been written.
+struct FailingWriter(usize);
+
+impl Output for FailingWriter {}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.0 + buf.len() > 500 {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"));
+        }
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_pipeline_run_write_error() {
+    let input = format!("{}\n", FIRST).repeat(100);
+    let res = pipeline::run(&config().workers(2), Cursor::new(input), &mut FailingWriter(0));
+    assert!(res.unwrap_err().is_io_error());
+}
+
+#[test]
+fn test_pipeline_run_invalid_utf8() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/invalid-utf8.log");
+    let uris = |out: &[u8]| -> Vec<String> {
+        events(out, b'\n')
+            .iter()
+            .map(|e| e["requested_uri"].as_str().unwrap().to_owned())
+            .collect()
+    };
+
+    // Every valid line produces an event regardless of the invalid lines around it.
+    let cases = vec![
+        (
+            Utf8Policy::Lossy,
+            vec![
+                "/index.html",
+                "/caf\u{fffd}",
+                "/about.html",
+                "/caf\u{e9}",
+                "/contact.html",
+            ],
+            2,
+            0,
+        ),
+        (
+            Utf8Policy::Skip,
+            vec!["/index.html", "/about.html", "/caf\u{e9}", "/contact.html"],
+            0,
+            3,
+        ),
+        (
+            Utf8Policy::Error,
+            vec!["/index.html", "/about.html", "/caf\u{e9}", "/contact.html"],
+            3,
+            0,
+        ),
+    ];
+
+    for (policy, expected, errors, skipped) in cases {
+        for workers in &[1, 4] {
+            let reader = BufReader::new(File::open(path).unwrap());
+            let mut out = Vec::new();
+            let summary = pipeline::run(&config().workers(*workers).utf8(policy), reader, &mut out).unwrap();
+
+            assert_eq!(expected, uris(&out), "{:?}", policy);
+            assert_eq!(7, summary.lines);
+            assert_eq!(errors, summary.errors, "{:?}", policy);
+            assert_eq!(skipped, summary.skipped, "{:?}", policy);
+        }
+    }
+}
+
+/// Writer that appends to a buffer shared with the test.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_pipeline_run_warnings() {
+    let mut input = String::new();
+    for i in 0..20 {
+        input.push_str(FIRST);
+        input.push('\n');
+        input.push_str(&format!("not a log line {}\n", i));
+    }
+
+    let stats = Arc::new(Stats::new());
+    let warnings = Shared::default();
+    let sink = WarningSink::new(warnings.clone())
+        .per_second(5)
+        .format(WarningFormat::Json)
+        .stats(stats.clone());
+    let config = config().workers(4).warnings(Arc::new(sink)).source("access.log");
+
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config, Cursor::new(input), &mut out).unwrap();
+    assert_eq!(20, summary.events);
+    assert_eq!(20, summary.errors);
+
+    let output = String::from_utf8(warnings.0.lock().unwrap().clone()).unwrap();
+    let values: Vec<serde_json::Value> = output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(6, values.len());
+    for (i, value) in values[..5].iter().enumerate() {
+        assert_eq!("access.log", value["source"]);
+        assert_eq!((i as u64 + 1) * 2, value["line"]);
+        assert_eq!("Invalid log line", value["category"]);
+    }
+    assert_eq!(15, values[5]["suppressed"]);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(5, snapshot.warnings_printed);
+    assert_eq!(15, snapshot.warnings_suppressed);
+}
+
+#[test]
+fn test_pipeline_run_metrics() {
+    let input = format!("{}\nnot a log line\n{}\n", FIRST, SECOND);
+    let metrics = Metrics::new();
+    let config = config().workers(2).metrics(&metrics);
+
+    for _ in 0..2 {
+        let mut out = Vec::new();
+        pipeline::run(&config, Cursor::new(input.clone()), &mut out).unwrap();
+    }
+
+    let rendered = metrics.prometheus();
+    assert!(rendered.contains("redeye_lines_read_total 6\n"));
+    assert!(rendered.contains("redeye_events_emitted_total 4\n"));
+    assert!(rendered.contains("redeye_parse_errors_total 2\n"));
+    assert!(rendered.contains("redeye_lines_skipped_total 0\n"));
+    assert!(rendered.contains("redeye_line_bytes_bucket{le=\"64\"} 2\n"));
+    assert!(rendered.contains("redeye_line_bytes_count 6\n"));
+
+    let sizes = metrics
+        .snapshot()
+        .metrics
+        .into_iter()
+        .find(|m| m.name == "redeye_line_bytes");
+    match sizes.map(|m| m.value) {
+        Some(MetricValue::Histogram { sum, .. }) => assert_eq!((input.len() * 2) as f64, sum),
+        v => panic!("Unexpected result: {:?}", v),
+    }
+}
+
+/// Good lines, a bad line, CRLF line endings, empty lines, and no newline at the end.
+const MIXED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mixed.log");
+
+/// Warnings reported to the pipeline, kept instead of being written.
+#[derive(Default)]
+struct Collected {
+    warnings: Mutex<Vec<(String, u64, String)>>,
+    finished: Mutex<bool>,
+}
+
+impl Warn for Collected {
+    fn warn(&self, err: &RedeyeError, source: &str, line: u64) {
+        self.warnings
+            .lock()
+            .unwrap()
+            .push((source.to_owned(), line, err.to_string()));
+    }
+
+    fn finish(&self) {
+        *self.finished.lock().unwrap() = true;
+    }
+}
+
+#[test]
+fn test_pipeline_run_sync_fixture() {
+    let input = std::fs::read(MIXED).unwrap();
+    let collected = Arc::new(Collected::default());
+    let config = config().source("mixed.log").warnings(collected.clone());
+
+    let mut out = Vec::new();
+    let summary = pipeline::run_sync(&config, BufReader::new(File::open(MIXED).unwrap()), &mut out).unwrap();
+
+    assert_eq!(vec![first_event(), second_event(), first_event()], events(&out, b'\n'));
+    assert_eq!(b'\n', out[out.len() - 1]);
+    assert_eq!(
+        PipelineSummary {
+            lines: 6,
+            events: 3,
+            errors: 3,
+            skipped: 0,
+            bytes_in: input.len() as u64,
+            bytes_out: out.len() as u64,
+        },
+        summary
+    );
+
+    let lines: Vec<(String, u64)> = collected
+        .warnings
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(source, line, _)| (source.clone(), *line))
+        .collect();
+    assert_eq!(
+        vec![
+            ("mixed.log".to_owned(), 2),
+            ("mixed.log".to_owned(), 4),
+            ("mixed.log".to_owned(), 5),
+        ],
+        lines
+    );
+    assert!(*collected.finished.lock().unwrap());
+}
+
+#[test]
+fn test_pipeline_run_sync_same_as_run() {
+    let input = std::fs::read(MIXED).unwrap();
+    for workers in &[1, 4] {
+        let config = config()
+            .workers(*workers)
+            .enricher(Arc::new(HttpVersionEnricher::new()));
+        let (mut sync_out, mut out) = (Vec::new(), Vec::new());
+
+        let sync_summary = pipeline::run_sync(&config, Cursor::new(&input), &mut sync_out).unwrap();
+        let summary = pipeline::run(&config, Cursor::new(&input), &mut out).unwrap();
+
+        assert_eq!(summary, sync_summary);
+        assert_eq!(events(&out, b'\n'), events(&sync_out, b'\n'));
+    }
+}
+
+#[test]
+fn test_pipeline_run_sync_not_send() {
+    // Readers that can't be sent to another thread can still be read synchronously
+    let input: Rc<[u8]> = Rc::from(format!("{}\n{}\n", FIRST, SECOND).into_bytes());
+    let mut out = Vec::new();
+    let summary = pipeline::run_sync(&config(), Cursor::new(input), &mut out).unwrap();
+
+    assert_eq!(2, summary.events);
+    assert_eq!(vec![first_event(), second_event()], events(&out, b'\n'));
+}
+
+#[test]
+fn test_pipeline_run_sync_stopped() {
+    let config = config().stop_when(Arc::new(AtomicBool::new(true)));
+    let mut out = Vec::new();
+    let summary = pipeline::run_sync(&config, Cursor::new(format!("{}\n", FIRST)), &mut out).unwrap();
+
+    assert_eq!(PipelineSummary::default(), summary);
+    assert!(out.is_empty());
+}
+
+#[test]
+fn test_pipeline_run_sync_warnings_rate_limited() {
+    let out = Shared::default();
+    let clock = ManualClock::new();
+    let warnings = WarningSink::new(out.clone())
+        .per_second(1)
+        .clock(Arc::new(clock.clone()));
+    let config = config().source("mixed.log").warnings(Arc::new(warnings));
+
+    let reader = || BufReader::new(File::open(MIXED).unwrap());
+    pipeline::run_sync(&config, reader(), &mut io::sink()).unwrap();
+    clock.advance(Duration::from_secs(1));
+    pipeline::run_sync(&config, reader(), &mut io::sink()).unwrap();
+
+    let output = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(
+        vec![
+            "redeye: warning: mixed.log:2: Invalid log line: not a log line",
+            "redeye: warning: Suppressed 2 similar warnings (Invalid log line)",
+            "redeye: warning: mixed.log:2: Invalid log line: not a log line",
+            "redeye: warning: Suppressed 2 similar warnings (Invalid log line)",
+        ],
+        output.lines().collect::<Vec<_>>()
+    );
+}
+
+/// Access log line for a request to the URI at a second past the first line.
+fn request(host: &str, second: u32, method: &str, uri: &str, status: u16, bytes: u64) -> String {
+    format!(
+        "{} - - [10/Oct/2000:13:55:{:02} -0700] \"{} {} HTTP/1.1\" {} {}\n",
+        host, second, method, uri, status, bytes
+    )
+}
+
+#[test]
+fn test_pipeline_process_source() {
+    let input = format!("{}\nnot a log line\n{}\n", FIRST, SECOND);
+    let stats = Arc::new(Stats::new());
+    let config = config()
+        .enricher(Arc::new(HttpVersionEnricher::new()))
+        .stamp_source(true)
+        .source("access.log")
+        .stats(stats.clone());
+
+    let mut out = Vec::new();
+    pipeline::process(&config, Cursor::new(input), &mut out).unwrap();
+    let snapshot = stats.snapshot();
+    assert_eq!(3, snapshot.lines_read);
+    assert_eq!(2, snapshot.events_emitted);
+    assert_eq!(1, snapshot.parse_errors);
+
+    let events = events(&out, b'\n');
+    assert_eq!(json!(1.0), events[0]["http_version"]);
+    assert_eq!(json!("access.log"), events[0]["source_file"]);
+    assert_eq!(json!(302), events[1]["status_code"]);
+}
+
+#[test]
+fn test_pipeline_process_filters() {
+    let input = [
+        request("127.0.0.1", 36, "GET", "/", 200, 2326),
+        request("127.0.0.1", 37, "GET", "/api/a", 404, 12),
+        request("127.0.0.1", 38, "GET", "/api/b", 200, 12),
+    ]
+    .concat();
+
+    let stats = Arc::new(Stats::new());
+    let config = config()
+        .filter("requested_uri startswith \"/api/\"".parse().unwrap())
+        .filter("status_code >= 400".parse().unwrap())
+        .stats(stats.clone());
+
+    let mut out = Vec::new();
+    pipeline::process(&config, Cursor::new(input), &mut out).unwrap();
+
+    let snapshot = stats.snapshot();
+    assert_eq!(3, snapshot.lines_read);
+    assert_eq!(1, snapshot.events_emitted);
+    assert_eq!(2, snapshot.events_filtered);
+    assert_eq!(json!("/api/a"), events(&out, b'\n')[0]["requested_uri"]);
+}
+
+#[test]
+fn test_pipeline_process_select() {
+    let config = config().select("status_code,remote_host=ip,requested_uri".parse().unwrap());
+    let mut out = Vec::new();
+    pipeline::process(
+        &config,
+        Cursor::new(request("127.0.0.1", 36, "GET", "/", 200, 2326)),
+        &mut out,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "{\"status_code\":200,\"ip\":\"127.0.0.1\",\"requested_uri\":\"/\"}\n",
+        String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn test_pipeline_process_sample() {
+    let input = [
+        request("127.0.0.1", 36, "GET", "/1", 200, 2326),
+        "not a log line\n".to_owned(),
+        request("127.0.0.1", 37, "GET", "/2", 200, 12),
+        request("127.0.0.1", 38, "GET", "/3", 200, 12),
+    ]
+    .concat();
+
+    let stats = Arc::new(Stats::new());
+    let config = config()
+        .select("requested_uri".parse().unwrap())
+        .sampler(Sampler::every(2).unwrap())
+        .stats(stats.clone());
+
+    let mut out = Vec::new();
+    pipeline::process(&config, Cursor::new(input), &mut out).unwrap();
+
+    let snapshot = stats.snapshot();
+    assert_eq!(4, snapshot.lines_read);
+    assert_eq!(2, snapshot.events_emitted);
+    assert_eq!(1, snapshot.events_sampled_out);
+    assert_eq!(1, snapshot.parse_errors);
+    assert_eq!(
+        "{\"requested_uri\":\"/1\"}\n{\"requested_uri\":\"/3\"}\n",
+        String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn test_pipeline_run_sample_workers() {
+    let input: String = (0..2000)
+        .map(|i| match i % 7 {
+            0 => "not a log line\n".to_owned(),
+            _ => request("127.0.0.1", 36, "GET", &format!("/{}", i), 200, 2326),
+        })
+        .collect();
+
+    let samplers = || vec![Sampler::every(3).unwrap(), Sampler::rate(0.3, 7).unwrap()];
+    for (i, sampler) in samplers().into_iter().enumerate() {
+        let config = config().select("requested_uri".parse().unwrap()).sampler(sampler);
+        let mut expected = Vec::new();
+        pipeline::run_sync(&config, Cursor::new(input.clone()), &mut expected).unwrap();
+
+        // Ordered samplers keep the same events no matter which thread parsed them
+        for workers in &[2, 4, 8] {
+            let sampler = samplers().remove(i);
+            let config = config.clone().sampler(sampler).workers(*workers);
+            let mut out = Vec::new();
+            pipeline::run(&config, Cursor::new(input.clone()), &mut out).unwrap();
+
+            assert_eq!(String::from_utf8_lossy(&expected), String::from_utf8_lossy(&out));
+        }
+    }
+}
+
+#[test]
+fn test_pipeline_run_dedup() {
+    let mut input = String::new();
+    for (second, uri) in ["/a", "/a", "/a", "/b", "/a", "/b"].iter().enumerate() {
+        input.push_str(&request("127.0.0.1", 36 + second as u32, "GET", uri, 200, 2326));
+    }
+    input.push_str("not a log line\n");
+    for (second, uri) in ["/b", "/c", "/c", "/c"].iter().enumerate() {
+        input.push_str(&request("127.0.0.1", 42 + second as u32, "GET", uri, 200, 2326));
+    }
+
+    let stats = Arc::new(Stats::new());
+    let config = config()
+        .select("requested_uri".parse().unwrap())
+        .dedup(Dedup::new(None))
+        .stats(stats.clone());
+
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config, Cursor::new(input), &mut out).unwrap();
+
+    // Events that alternate aren't duplicates, invalid lines don't end a run,
+    // and the last duplicate of a run at the end of input is still written.
+    let snapshot = stats.snapshot();
+    assert_eq!(11, snapshot.lines_read);
+    assert_eq!(6, snapshot.events_emitted);
+    assert_eq!(6, summary.events);
+    assert_eq!(4, snapshot.events_deduped);
+    assert_eq!(1, snapshot.parse_errors);
+    assert_eq!(
+        concat!(
+            "{\"requested_uri\":\"/a\"}\n",
+            "{\"requested_uri\":\"/b\",\"repeat_count\":2}\n",
+            "{\"requested_uri\":\"/a\"}\n",
+            "{\"requested_uri\":\"/b\"}\n",
+            "{\"requested_uri\":\"/c\",\"repeat_count\":1}\n",
+            "{\"requested_uri\":\"/c\",\"repeat_count\":1}\n",
+        ),
+        String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn test_pipeline_run_dedup_fields() {
+    let input = [
+        request("127.0.0.1", 36, "GET", "/a", 200, 2326),
+        request("127.0.0.2", 37, "GET", "/a", 200, 12),
+        request("127.0.0.1", 38, "GET", "/a", 500, 2326),
+    ]
+    .concat();
+
+    let config = config()
+        .select("remote_host".parse().unwrap())
+        .dedup(Dedup::new(Some(vec![
+            "requested_uri".to_owned(),
+            "status_code".to_owned(),
+        ])));
+
+    let mut out = Vec::new();
+    pipeline::run(&config, Cursor::new(input), &mut out).unwrap();
+
+    assert_eq!(
+        concat!(
+            "{\"remote_host\":\"127.0.0.1\"}\n",
+            "{\"remote_host\":\"127.0.0.1\",\"repeat_count\":1}\n",
+        ),
+        String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn test_pipeline_process_workers() {
+    let input: String = (0..1000)
+        .map(|i| {
+            if i % 100 == 0 {
+                "not a log line\n".to_owned()
+            } else {
+                request("127.0.0.1", 36, "GET", &format!("/{}", i), 200, 2326)
+            }
+        })
+        .collect();
+
+    let stats = Arc::new(Stats::new());
+    let config = config()
+        .select("requested_uri".parse().unwrap())
+        .workers(4)
+        .stats(stats.clone());
+
+    let mut out = Vec::new();
+    pipeline::process(&config, Cursor::new(input), &mut out).unwrap();
+
+    let snapshot = stats.snapshot();
+    assert_eq!(1000, snapshot.lines_read);
+    assert_eq!(990, snapshot.events_emitted);
+    assert_eq!(10, snapshot.parse_errors);
+
+    let expected: String = (0..1000)
+        .filter(|i| i % 100 != 0)
+        .map(|i| format!("{{\"requested_uri\":\"/{}\"}}\n", i))
+        .collect();
+    assert_eq!(expected, String::from_utf8(out).unwrap());
+}
+
+#[test]
+fn test_pipeline_process_delimiters() {
+    let lines = [
+        request("127.0.0.1", 36, "GET", "/a", 200, 2326),
+        request("127.0.0.1", 37, "GET", "/b", 200, 2326),
+    ];
+
+    let modes = [
+        (Delimiter::Nul, Delimiter::Newline, 1),
+        (Delimiter::Newline, Delimiter::Nul, 1),
+        (Delimiter::Nul, Delimiter::Nul, 3),
+    ];
+
+    for (input_delimiter, output_delimiter, workers) in modes.iter() {
+        let input: String = lines
+            .iter()
+            .map(|l| format!("{}{}", l.trim_end(), input_delimiter.byte() as char))
+            .collect();
+
+        let stats = Arc::new(Stats::new());
+        let config = config()
+            .select("requested_uri".parse().unwrap())
+            .workers(*workers)
+            .input_delimiter(*input_delimiter)
+            .output_delimiter(*output_delimiter)
+            .stats(stats.clone());
+
+        let mut out = Vec::new();
+        pipeline::process(&config, Cursor::new(input), &mut out).unwrap();
+
+        let end = output_delimiter.byte() as char;
+        let expected = format!("{{\"requested_uri\":\"/a\"}}{}{{\"requested_uri\":\"/b\"}}{}", end, end);
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+        assert_eq!(0, stats.snapshot().parse_errors);
+    }
+}
+
+#[test]
+fn test_pipeline_process_failures() {
+    let input = [
+        request("127.0.0.1", 36, "GET", "/", 200, 2326),
+        "not a log line\n".to_owned(),
+        request("127.0.0.1", 37, "GET", "/a", 404, 12),
+        "127.0.0.1 - - [99/Oct/2000:13:55:37 -0700] \"GET /b HTTP/1.0\" 404 12\n".to_owned(),
+        "also not a log line\n".to_owned(),
+    ]
+    .concat();
+
+    let failures = Arc::new(Mutex::new(Failures::new()));
+    let config = config().failures(failures.clone()).source("access.log");
+    pipeline::process(&config, Cursor::new(input), &mut io::sink()).unwrap();
+
+    let failures = failures.lock().unwrap();
+    assert_eq!(3, failures.total());
+    assert_eq!(
+        vec![(FailureReason::InvalidLine, 2), (FailureReason::InvalidTimestamp, 1)],
+        failures.by_reason()
+    );
+
+    let top = failures.top(1);
+    assert_eq!(1, top.len());
+    assert_eq!(2, top[0].count);
+    assert_eq!("not a log line", top[0].examples[0].text);
+    assert_eq!(2, top[0].examples[0].line);
+    assert_eq!("also not a log line", top[0].examples[1].text);
+    assert_eq!(5, top[0].examples[1].line);
+}
+
+#[test]
+fn test_pipeline_process_w3c_directives() {
+    let mut input =
+        String::from("#Version: 1.0\n#Date: 2000-10-10 20:55:36\n#Fields: date time cs-uri-stem sc-status\n");
+    for i in 0..200 {
+        input.push_str(&format!("2000-10-10 20:55:36 /first/{} 200\n", i));
+    }
+    input.push_str("#Fields: date time c-ip cs-method cs-uri-stem\n");
+    for i in 0..200 {
+        input.push_str(&format!("2000-10-10 20:55:37 127.0.0.1 GET /second/{}\n", i));
+    }
+
+    let failures = Arc::new(Mutex::new(Failures::new()));
+    let config = PipelineConfig::new(Arc::new(W3cLogLineParser::new()))
+        .select("requested_uri,status_code,method".parse().unwrap())
+        .failures(failures.clone())
+        .workers(4);
+
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config, Cursor::new(input), &mut out).unwrap();
+    assert_eq!(404, summary.lines);
+    assert_eq!(400, summary.events);
+    assert_eq!(0, summary.errors);
+    assert_eq!(0, failures.lock().unwrap().total());
+
+    let events = events(&out, b'\n');
+    for (i, event) in events[..200].iter().enumerate() {
+        assert_eq!(
+            &json!({"requested_uri": format!("/first/{}", i), "status_code": 200}),
+            event
+        );
+    }
+    for (i, event) in events[200..].iter().enumerate() {
+        assert_eq!(
+            &json!({"requested_uri": format!("/second/{}", i), "method": "GET"}),
+            event
+        );
+    }
+}
+
+#[test]
+fn test_pipeline_process_forensic_records() {
+    let input = [
+        "+aaaa|GET /a HTTP/1.1|Host:example.com",
+        "+bbbb|GET /b HTTP/1.1|Host:example.com",
+        "+cccc|GET /c HTTP/1.1|Host:example.com",
+        "-cccc",
+        "-dddd",
+        "+eeee|GET /e HTTP/1.1|Host:example.com",
+        "",
+    ]
+    .join("\n");
+
+    let failures = Arc::new(Mutex::new(Failures::new()));
+    let config = PipelineConfig::new(Arc::new(ForensicLogParser::new().max_pending(2)))
+        .select("forensic_id,completed".parse().unwrap())
+        .failures(failures.clone())
+        .workers(4);
+
+    let mut out = Vec::new();
+    let summary = pipeline::run(&config, Cursor::new(input), &mut out).unwrap();
+    assert_eq!(6, summary.lines);
+    assert_eq!(4, summary.events);
+    assert_eq!(1, summary.errors);
+    assert_eq!(1, failures.lock().unwrap().total());
+
+    // The oldest start record is given up on when a third is buffered, the rest
+    // are written once there's no more input.
+    assert_eq!(
+        vec![
+            json!({"forensic_id": "aaaa", "completed": false}),
+            json!({"forensic_id": "cccc", "completed": true}),
+            json!({"forensic_id": "bbbb", "completed": false}),
+            json!({"forensic_id": "eeee", "completed": false}),
+        ],
+        events(&out, b'\n')
+    );
+}
+
+#[test]
+fn test_pipeline_process_max_record_bytes() {
+    let entry = request("127.0.0.1", 36, "GET", "/", 200, 2326);
+    let entry = entry.trim_end();
+    let input = format!(
+        "{}\n{}x\n{}\n{}\n{}",
+        entry,
+        entry,
+        "x".repeat(100_000),
+        entry,
+        "y".repeat(100_000)
+    );
+
+    for workers in &[1, 3] {
+        let stats = Arc::new(Stats::new());
+        let failures = Arc::new(Mutex::new(Failures::new()));
+        let config = config()
+            .select("requested_uri".parse().unwrap())
+            .failures(failures.clone())
+            .workers(*workers)
+            .max_record_bytes(Some(entry.len()))
+            .stats(stats.clone());
+
+        let mut out = Vec::new();
+        pipeline::process(&config, Cursor::new(input.clone()), &mut out).unwrap();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(5, snapshot.lines_read);
+        assert_eq!(input.len() as u64, snapshot.bytes_in);
+        assert_eq!(2, snapshot.events_emitted);
+        assert_eq!(3, snapshot.record_too_long_errors);
+        assert_eq!(0, snapshot.parse_errors);
+        assert_eq!(
+            "{\"requested_uri\":\"/\"}\n{\"requested_uri\":\"/\"}\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let failures = failures.lock().unwrap();
+        assert_eq!(vec![(FailureReason::RecordTooLong, 3)], failures.by_reason());
+
+        let top = failures.top(1);
+        assert_eq!(format!("record longer than {} bytes", entry.len()), top[0].message);
+        assert_eq!(entry, top[0].examples[0].text);
+        assert_eq!(2, top[0].examples[0].line);
+        assert_eq!("x".repeat(entry.len()), top[0].examples[1].text);
+        assert_eq!("y".repeat(entry.len()), top[0].examples[2].text);
+    }
+}
+
+#[test]
+fn test_pipeline_process_collect_summary() {
+    let input = [
+        request("127.0.0.1", 36, "GET", "/", 200, 2326),
+        "not a log line\n".to_owned(),
+        request("127.0.0.2", 37, "POST", "/a", 404, 12),
+        request("127.0.0.1", 38, "GET", "/b", 200, 12),
+    ]
+    .concat();
+
+    let stats = Arc::new(Stats::new());
+    let summary = Arc::new(Mutex::new(RequestSummary::new()));
+    let config = config()
+        .filter("requested_uri != \"/b\"".parse().unwrap())
+        .collect(summary.clone())
+        .workers(2)
+        .stats(stats.clone());
+
+    let mut out = Vec::new();
+    pipeline::process(&config, Cursor::new(input), &mut out).unwrap();
+    assert!(out.is_empty());
+
+    let summary = summary.lock().unwrap();
+    assert_eq!(2, summary.requests());
+    assert_eq!(
+        vec![("2xx".to_owned(), 1), ("4xx".to_owned(), 1)],
+        summary.status_classes()
+    );
+    assert_eq!(2, summary.clients().count());
+    assert_eq!(1, stats.snapshot().events_filtered);
+    assert_eq!(1, stats.snapshot().parse_errors);
+}
+
+#[test]
+fn test_pipeline_process_collect_top() {
+    let input = [
+        request("127.0.0.1", 36, "GET", "/", 200, 2326),
+        request("127.0.0.2", 37, "GET", "/a", 404, 12),
+        request("127.0.0.1", 38, "GET", "/b", 200, 12),
+    ]
+    .concat();
+
+    let values = Arc::new(Mutex::new(TopValues::new("remote_host", Metric::Bytes)));
+    let config = config()
+        .filter("status_code == 200".parse().unwrap())
+        .collect(values.clone());
+    pipeline::process(&config, Cursor::new(input), &mut io::sink()).unwrap();
+
+    let top = values.lock().unwrap().top(10);
+    assert_eq!(1, top.len());
+    assert_eq!("127.0.0.1", top[0].value);
+    assert_eq!(2338, top[0].total);
+}
+
+#[test]
+fn test_pipeline_process_datagrams() {
+    let mut receiver = DatagramReceiver::bind("127.0.0.1:0")
+        .unwrap()
+        .max_size(256)
+        .timeout(Duration::from_millis(10))
+        .unwrap();
+    let addr = receiver.local_addr().unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    let entry = request("127.0.0.1", 36, "GET", "/", 200, 2326);
+    sender
+        .send_to(format!("<134>Oct 10 13:55:36 web1 httpd: {}", entry).as_bytes(), addr)
+        .unwrap();
+    sender.send_to(&[b'x'; 300], addr).unwrap();
+    sender.send_to(&[b'y'; 200], addr).unwrap();
+    sender.send_to(entry.trim_end().as_bytes(), addr).unwrap();
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stats = Arc::new(Stats::new());
+    let config = PipelineConfig::new(Arc::new(SyslogLogLineParser::new(CommonLogLineParser::new())))
+        .max_record_bytes(Some(150))
+        .stop_when(stop.clone())
+        .source("udp://127.0.0.1")
+        .stats(stats.clone());
+
+    let stopper = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        stop.store(true, Ordering::Release);
+    });
+
+    let mut out = Vec::new();
+    pipeline::process_datagrams(&config, &mut receiver, &mut out).unwrap();
+    stopper.join().unwrap();
+
+    let snapshot = stats.snapshot();
+    assert_eq!(2, snapshot.events_emitted);
+    assert_eq!(1, snapshot.datagrams_oversized);
+    assert_eq!(1, snapshot.record_too_long_errors);
+
+    for event in events(&out, b'\n') {
+        assert_eq!(json!("127.0.0.1"), event["source_address"]);
+        assert_eq!(json!("/"), event["requested_uri"]);
+    }
+}
+
+#[cfg(all(unix, feature = "journal"))]
+#[test]
+fn test_pipeline_process_journal() {
+    use redeye::journal::JournalFollower;
+    use std::os::unix::net::UnixStream;
+
+    let dir = tempfile::tempdir().unwrap();
+    let state = dir.path().join("state.json");
+
+    // Keep the other end open so the journal doesn't end before we stop
+    let (mut export, reader) = UnixStream::pair().unwrap();
+    write!(
+        export,
+        "__CURSOR=s=1;i=1\n_SYSTEMD_UNIT=init.scope\n\n\
+         __CURSOR=s=1;i=2\n__REALTIME_TIMESTAMP=1600000000000000\n_HOSTNAME=web1\n\
+         _SYSTEMD_UNIT=httpd.service\nMESSAGE={}\n\n",
+        FIRST
+    )
+    .unwrap();
+
+    let mut journal = JournalFollower::from_reader(BufReader::new(reader), Some(state.clone()))
+        .unwrap()
+        .timeout(Duration::from_millis(10));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let stats = Arc::new(Stats::new());
+    let config = config().stop_when(stop.clone()).source("journal").stats(stats.clone());
+
+    let stopper = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        stop.store(true, Ordering::Release);
+    });
+
+    let mut out = Vec::new();
+    pipeline::process_journal(&config, &mut journal, &mut out).unwrap();
+    stopper.join().unwrap();
+
+    assert_eq!(1, stats.snapshot().events_emitted);
+    let event: Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(json!("/apache_pb.gif"), event["requested_uri"]);
+    assert_eq!(json!("web1"), event["journal_hostname"]);
+    assert_eq!(json!("httpd.service"), event["journal_unit"]);
+    assert_eq!(json!("2020-09-13T12:26:40+00:00"), event["received_at"]);
+
+    let saved = std::fs::read_to_string(&state).unwrap();
+    assert!(saved.contains("\"cursor\":\"s=1;i=2\""), "{}", saved);
+}
+
+#[test]
+fn test_pipeline_process_max_errors() {
+    let input = format!("not a log line\n{}\nalso not a log line\n{}\n", FIRST, SECOND);
+    let stats = Arc::new(Stats::new());
+    let config = config().max_errors(Some(1)).stats(stats.clone());
+
+    let mut out = Vec::new();
+    match pipeline::process(&config, Cursor::new(input), &mut out) {
+        Err(PipelineError::TooManyErrors) => (),
+        v => panic!("Unexpected result: {:?}", v),
+    }
+
+    let snapshot = stats.snapshot();
+    assert_eq!(3, snapshot.lines_read);
+    assert_eq!(2, snapshot.errors());
+    assert_eq!(vec![first_event()], events(&out, b'\n'));
+}
+
+#[test]
+fn test_pipeline_process_strict() {
+    let stats = Arc::new(Stats::new());
+    let config = config().max_errors(Some(0)).stats(stats.clone());
+
+    match pipeline::process(&config, Cursor::new("bad\nworse\n"), &mut Vec::new()) {
+        Err(PipelineError::TooManyErrors) => (),
+        v => panic!("Unexpected result: {:?}", v),
+    }
+
+    let snapshot = stats.snapshot();
+    assert_eq!(1, snapshot.lines_read);
+    assert_eq!(1, snapshot.errors());
+}
+
+#[test]
+fn test_pipeline_process_output_error() {
+    let input = format!("{}\n", FIRST).repeat(100);
+    match pipeline::process(&config(), Cursor::new(input), &mut FailingWriter(0)) {
+        Err(PipelineError::Output(_)) => (),
+        v => panic!("Unexpected result: {:?}", v),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pipeline_process_reopen() {
+    use redeye::io::{RotatingFileWriter, RotationPolicy};
+    use std::fs;
+    use std::io::BufWriter;
+    use std::os::unix::fs::MetadataExt;
+    use tempfile::TempDir;
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("out.json");
+    let moved = dir.path().join("out.json.1");
+
+    let reopen = Arc::new(AtomicBool::new(false));
+    let config = config()
+        .select("requested_uri".parse().unwrap())
+        .reopen_when(reopen.clone());
+
+    let mut writer = BufWriter::new(RotatingFileWriter::open(&path, RotationPolicy::new()).unwrap());
+    pipeline::process(&config, Cursor::new(format!("{}\n", FIRST)), &mut writer).unwrap();
+    let original = fs::metadata(&path).unwrap().ino();
+    fs::rename(&path, &moved).unwrap();
+    reopen.store(true, Ordering::Release);
+
+    pipeline::run(&config, Cursor::new(format!("{}\n", SECOND)), &mut writer).unwrap();
+
+    assert_eq!(original, fs::metadata(&moved).unwrap().ino());
+    assert_ne!(original, fs::metadata(&path).unwrap().ino());
+    assert_eq!(
+        "{\"requested_uri\":\"/apache_pb.gif\"}\n",
+        fs::read_to_string(&moved).unwrap()
+    );
+    assert_eq!("{\"requested_uri\":\"/login\"}\n", fs::read_to_string(&path).unwrap());
+    assert!(!reopen.load(Ordering::Acquire));
+}
+
+#[test]
+fn test_output_finish_buffered_file() {
+    use redeye::io::{RotatingFileWriter, RotationPolicy};
+    use std::io::BufWriter;
+    use tempfile::TempDir;
+
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("out.json");
+    let file = RotatingFileWriter::open(&path, RotationPolicy::new()).unwrap();
+    let mut writer = BufWriter::with_capacity(1024, Box::new(file) as Box<dyn Output + Send>);
+
+    writer.write_all(b"{\"a\":1}\n").unwrap();
+    assert_eq!("", std::fs::read_to_string(&path).unwrap());
+
+    writer.finish().unwrap();
+    assert_eq!("{\"a\":1}\n", std::fs::read_to_string(&path).unwrap());
+}
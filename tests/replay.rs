@@ -0,0 +1,136 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Replaying access logs with the emitter at their original pace
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Lines one second apart, each numbered in its path.
+fn access_log(lines: usize) -> String {
+    (0..lines)
+        .map(|i| {
+            format!(
+                "127.0.0.1 - - [10/Oct/2000:13:{:02}:{:02} -0700] \"GET /{} HTTP/1.0\" 200 2326\n",
+                55 + (36 + i) / 60,
+                (36 + i) % 60,
+                i
+            )
+        })
+        .collect()
+}
+
+/// Replay a log with the given options, returning the first `count` lines written
+/// and when each of them arrived.
+fn replay(log: &str, args: &[&str], count: usize) -> Vec<(String, Instant)> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("access.log");
+    fs::write(&path, log).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_emitter"))
+        .arg("replay")
+        .arg(&path)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let reader = BufReader::new(child.stdout.take().unwrap());
+    let lines = reader
+        .lines()
+        .take(count)
+        .map(|l| (l.unwrap(), Instant::now()))
+        .collect();
+
+    let _ = child.kill();
+    child.wait().unwrap();
+    lines
+}
+
+#[test]
+fn test_replay_pacing() {
+    // One second gaps at 20 times the speed should arrive 50ms apart
+    let lines = replay(&access_log(11), &["--speed", "20"], 11);
+    assert_eq!(11, lines.len());
+
+    let expected = Duration::from_millis(50);
+    let gaps: Vec<Duration> = lines.windows(2).map(|w| w[1].1 - w[0].1).collect();
+    for gap in gaps.iter() {
+        let error = gap.abs_diff(expected);
+        assert!(error < Duration::from_millis(15), "gaps: {:?}", gaps);
+    }
+
+    // Lines are written at times relative to the start so errors don't add up
+    let total = lines[10].1 - lines[0].1;
+    let expected = Duration::from_millis(500);
+    let error = total.abs_diff(expected);
+    assert!(
+        error < Duration::from_millis(10),
+        "total: {:?}, gaps: {:?}",
+        total,
+        gaps
+    );
+}
+
+#[test]
+fn test_replay_unparsed_lines_immediately() {
+    let log = format!("not an access log line\n{}", access_log(2));
+    let lines = replay(&log, &["--speed", "10"], 3);
+
+    assert_eq!("not an access log line", lines[0].0);
+    assert!(lines[1].1 - lines[0].1 < Duration::from_millis(50));
+    assert!(lines[2].1 - lines[1].1 >= Duration::from_millis(90));
+}
+
+#[test]
+fn test_replay_loop_rewrite_time() {
+    let log = format!("{}not an access log line", access_log(3));
+    let lines = replay(&log, &["--speed", "0", "--loop", "--rewrite-time"], 12);
+    assert_eq!(12, lines.len());
+
+    let mut previous = None;
+    for (i, (line, _)) in lines.iter().enumerate() {
+        if i % 4 == 3 {
+            assert_eq!("not an access log line", line);
+            continue;
+        }
+
+        assert!(
+            line.ends_with(&format!("\"GET /{} HTTP/1.0\" 200 2326", i % 4)),
+            "{}",
+            line
+        );
+        assert!(!line.contains("/2000:"), "{}", line);
+
+        let start = line.find('[').unwrap() + 1;
+        let end = line.find(']').unwrap();
+        let ts = chrono::DateTime::parse_from_str(&line[start..end], "%d/%b/%Y:%H:%M:%S %z").unwrap();
+        assert_eq!(-7 * 3600, ts.offset().local_minus_utc());
+        assert!(
+            (chrono::Utc::now() - ts.with_timezone(&chrono::Utc))
+                .num_seconds()
+                .abs()
+                < 60
+        );
+        assert!(previous.map(|p| p <= ts).unwrap_or(true), "{}", line);
+        previous = Some(ts);
+    }
+}
@@ -0,0 +1,73 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Sampling the same events with --workers as on a single thread
+
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Run redeye over the file with the given arguments, returning stdout.
+fn run(path: &str, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args([
+            "--no-summary",
+            "--quiet",
+            "--format",
+            "common",
+            "--select",
+            "requested_uri",
+        ])
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_sample_workers_deterministic() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("access.log");
+    let input: String = (0..5000)
+        .map(|i| match i % 11 {
+            0 => "not a log line\n".to_owned(),
+            _ => format!(
+                "127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] \"GET /{} HTTP/1.0\" 200 {}\n",
+                i, i
+            ),
+        })
+        .collect();
+    fs::write(&path, input).unwrap();
+    let path = path.to_str().unwrap();
+
+    for sample in &[
+        &["--sample-every", "3"][..],
+        &["--sample-rate", "0.25", "--sample-seed", "42"][..],
+    ] {
+        let expected = run(path, sample);
+        assert!(!expected.is_empty());
+
+        for _ in 0..3 {
+            let args: Vec<&str> = sample.iter().copied().chain(vec!["--workers", "4"]).collect();
+            assert_eq!(expected, run(path, &args), "{:?}", sample);
+        }
+    }
+}
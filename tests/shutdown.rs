@@ -0,0 +1,85 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Stopping a running redeye process with a signal
+
+#![cfg(unix)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+const LINE: &str = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326\n";
+
+fn interrupt_mid_stream(workers: &str) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args(["--common-format", "--summary-json", "--workers", workers])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Keep writing input until redeye exits and closes its end of the pipe
+    let mut stdin = child.stdin.take().unwrap();
+    let feeder = thread::spawn(move || while stdin.write_all(LINE.repeat(100).as_bytes()).is_ok() {});
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut first = String::new();
+    stdout.read_line(&mut first).unwrap();
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    let mut rest = String::new();
+    stdout.read_to_string(&mut rest).unwrap();
+    let mut stderr = String::new();
+    child.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+
+    let status = child.wait().unwrap();
+    feeder.join().unwrap();
+
+    assert_eq!(Some(130), status.code(), "stderr: {}", stderr);
+
+    let output = first + &rest;
+    assert!(output.ends_with('\n'));
+
+    let events = output.lines().count() as u64;
+    for line in output.lines() {
+        let event: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!("/index.html", event["requested_uri"]);
+    }
+
+    // Everything read was written before the summary was printed
+    let summary: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(events, summary["events_emitted"]);
+    assert_eq!(summary["lines_read"], summary["events_emitted"]);
+}
+
+#[test]
+fn test_sigterm_single_worker() {
+    interrupt_mid_stream("1");
+}
+
+#[test]
+fn test_sigterm_multiple_workers() {
+    interrupt_mid_stream("4");
+}
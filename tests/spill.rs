@@ -0,0 +1,231 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Keeping events on disk while a remote output is down and sending them in order once it's back
+#![cfg(unix)]
+
+use redeye::forward::{BackPressureSender, Backoff, Forwarder, LogBuffer, UnixConnector};
+use redeye::spill::SpillQueue;
+use redeye::stats::Stats;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const LINE: &str = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326";
+
+fn record(i: usize) -> String {
+    format!("record {:05} {}", i, "x".repeat(64))
+}
+
+fn records(range: std::ops::Range<usize>) -> Vec<String> {
+    range.map(record).collect()
+}
+
+/// Start a forwarder to a Unix socket that may not exist yet, with a small
+/// in-memory buffer so that records are spilled quickly.
+fn start(socket: &Path, spill: &Path, max_bytes: u64, stats: &Arc<Stats>) -> BackPressureSender {
+    let spill = SpillQueue::open(spill)
+        .unwrap()
+        .segment_bytes(4096)
+        .max_bytes(max_bytes);
+
+    Forwarder::new(UnixConnector::new(socket))
+        .buffer(LogBuffer::new().max_records(20))
+        .backoff(
+            Backoff::new()
+                .initial(Duration::from_millis(10))
+                .max(Duration::from_millis(20)),
+        )
+        .spill(Some(spill))
+        .drain_timeout(Duration::from_millis(200))
+        .wait_for_connection(true)
+        .stats(stats.clone())
+        .start()
+        .unwrap()
+}
+
+/// Accept a single connection and collect every line sent on it.
+fn receive(socket: &Path) -> JoinHandle<Vec<String>> {
+    let listener = UnixListener::bind(socket).unwrap();
+    thread::spawn(move || {
+        let (conn, _) = listener.accept().unwrap();
+        BufReader::new(conn).lines().map(|l| l.unwrap()).collect()
+    })
+}
+
+fn wait_for<F: Fn() -> bool>(condition: F) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn segments(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_spill_drained_in_order_after_outage() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("out.sock");
+    let spill = dir.path().join("spill");
+    let stats = Arc::new(Stats::new());
+
+    let mut sender = start(&socket, &spill, u64::MAX, &stats);
+    for r in records(0..2000) {
+        writeln!(sender, "{}", r).unwrap();
+    }
+
+    // The output is down so everything ends up on disk, across many segments.
+    wait_for(|| stats.snapshot().spill_written == 2000);
+    assert!(segments(&spill).len() > 10);
+
+    let received = receive(&socket);
+    for r in records(2000..2500) {
+        writeln!(sender, "{}", r).unwrap();
+    }
+    sender.finish().unwrap();
+
+    assert_eq!(records(0..2500), received.join().unwrap());
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.spill_written, snapshot.spill_drained);
+    assert_eq!(0, snapshot.spill_bytes);
+    assert_eq!(0, snapshot.spill_segments);
+    assert_eq!(0, snapshot.output_dropped);
+    assert!(segments(&spill).is_empty());
+}
+
+#[test]
+fn test_spill_full_drops_newest() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("out.sock");
+    let spill = dir.path().join("spill");
+    let stats = Arc::new(Stats::new());
+
+    // Room for about a hundred records
+    let mut sender = start(&socket, &spill, 100 * 90, &stats);
+    for r in records(0..1000) {
+        writeln!(sender, "{}", r).unwrap();
+    }
+    wait_for(|| {
+        let snapshot = stats.snapshot();
+        snapshot.spill_written + snapshot.output_dropped == 1000
+    });
+
+    let received = receive(&socket);
+    sender.finish().unwrap();
+    let received = received.join().unwrap();
+
+    // Records that fit are sent in order with none missing between them.
+    let snapshot = stats.snapshot();
+    assert!(snapshot.output_dropped > 0);
+    assert_eq!(1000, received.len() as u64 + snapshot.output_dropped);
+    assert_eq!(records(0..received.len()), received);
+}
+
+#[test]
+fn test_spill_kept_across_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("out.sock");
+    let spill = dir.path().join("spill");
+
+    let stats = Arc::new(Stats::new());
+    let mut sender = start(&socket, &spill, u64::MAX, &stats);
+    for r in records(0..300) {
+        writeln!(sender, "{}", r).unwrap();
+    }
+    sender.finish().unwrap();
+
+    // Nothing could be sent before giving up, but nothing was lost either.
+    let snapshot = stats.snapshot();
+    assert_eq!(300, snapshot.spill_written);
+    assert_eq!(0, snapshot.output_dropped);
+    assert!(!segments(&spill).is_empty());
+
+    // Simulate a crash part way through writing the last record.
+    let last = segments(&spill).pop().unwrap();
+    let len = fs::metadata(&last).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&last)
+        .unwrap()
+        .set_len(len - 10)
+        .unwrap();
+
+    let received = receive(&socket);
+    let stats = Arc::new(Stats::new());
+    let mut sender = start(&socket, &spill, u64::MAX, &stats);
+    for r in records(300..400) {
+        writeln!(sender, "{}", r).unwrap();
+    }
+    sender.finish().unwrap();
+
+    let expected: Vec<String> = records(0..299).into_iter().chain(records(300..400)).collect();
+    assert_eq!(expected, received.join().unwrap());
+    assert_eq!(1, stats.snapshot().spill_corrupted);
+    assert!(segments(&spill).is_empty());
+}
+
+#[test]
+fn test_spill_dir_option() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("out.sock");
+    let spill = dir.path().join("spill");
+    let input: String = (0..50).map(|_| format!("{}\n", LINE)).collect();
+
+    let run = |input: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_redeye"))
+            .arg("--common-format")
+            .arg("--no-summary")
+            .arg("--output-unix")
+            .arg(&socket)
+            .arg("--reconnect-buffer-events")
+            .arg("10")
+            .arg("--spill-dir")
+            .arg(&spill)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+        assert!(child.wait().unwrap().success());
+    };
+
+    // The first run can't connect so its events are left in the spill directory,
+    // and the second run sends them before its own.
+    run(&input);
+    assert!(!segments(&spill).is_empty());
+
+    let received = receive(&socket);
+    run(&input[..LINE.len() + 1]);
+
+    let events = received.join().unwrap();
+    assert_eq!(51, events.len());
+    assert!(events.iter().all(|e| e.contains("\"requested_uri\":\"/index.html\"")));
+    assert!(segments(&spill).is_empty());
+}
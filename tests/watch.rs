@@ -0,0 +1,117 @@
+// Redeye - Parse Apache-style access logs into Logstash JSON
+//
+// Copyright 2018 Nick Pillitteri
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+//
+
+//! Following every file that matches a pattern, and continuing where it left off
+
+#![cfg(unix)]
+
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+const LINE: &str = "127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] \"GET /index.html HTTP/1.0\" 200 2326\n";
+
+fn append(path: &Path, line: &str) {
+    let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+    file.write_all(line.as_bytes()).unwrap();
+}
+
+fn watch(dir: &Path) -> (Child, BufReader<ChildStdout>) {
+    let pattern = dir.join("*.log");
+    let state = dir.join("redeye.state");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args(["--format", "common", "--line-buffered", "--no-summary"])
+        .args(["--follow-from", "start", "--watch-interval", "100ms"])
+        .arg("--watch")
+        .arg(&pattern)
+        .arg("--state-file")
+        .arg(&state)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    (child, stdout)
+}
+
+fn next_event(stdout: &mut BufReader<ChildStdout>) -> serde_json::Value {
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+fn stop(mut child: Child) -> String {
+    let killed = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+
+    let mut stderr = String::new();
+    child.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+    let status = child.wait().unwrap();
+    assert_eq!(Some(130), status.code(), "stderr: {}", stderr);
+    stderr
+}
+
+#[test]
+fn test_watch_new_files_and_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.log");
+    let second = dir.path().join("second.log");
+    append(&first, LINE);
+
+    let (child, mut stdout) = watch(dir.path());
+    let event = next_event(&mut stdout);
+    assert_eq!(first.to_str().unwrap(), event["source_file"]);
+
+    // Files created after starting are found by the next scan
+    append(&second, LINE);
+    let event = next_event(&mut stdout);
+    assert_eq!(second.to_str().unwrap(), event["source_file"]);
+    assert_eq!("", stop(child));
+
+    // Lines written while stopped are read, lines read before aren't read again
+    append(&first, &LINE.replace("/index.html", "/while-stopped.html"));
+    let (child, mut stdout) = watch(dir.path());
+    let event = next_event(&mut stdout);
+    assert_eq!(first.to_str().unwrap(), event["source_file"]);
+    assert_eq!("/while-stopped.html", event["requested_uri"]);
+
+    append(&second, &LINE.replace("/index.html", "/after-restart.html"));
+    let event = next_event(&mut stdout);
+    assert_eq!(second.to_str().unwrap(), event["source_file"]);
+    assert_eq!("/after-restart.html", event["requested_uri"]);
+    stop(child);
+
+    assert!(fs::metadata(dir.path().join("redeye.state")).unwrap().len() > 0);
+}
+
+#[test]
+fn test_watch_conflicts_with_files() {
+    let output = Command::new(env!("CARGO_BIN_EXE_redeye"))
+        .args(["--format", "common", "--watch", "*.log", "access.log"])
+        .output()
+        .unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("cannot be used with '--watch <watch>'"), "{}", stderr);
+}
@@ -0,0 +1,18 @@
+#!/bin/sh
+#
+# Build the wasm_smoke example for WebAssembly and run it with node. The example
+# panics, which traps and makes node exit with an error, if the parsers don't
+# work in WebAssembly.
+#
+# Requires the wasm32-unknown-unknown target (rustup target add wasm32-unknown-unknown)
+# and node.
+
+set -e
+
+cargo build --example wasm_smoke --target wasm32-unknown-unknown
+node -e '
+const fs = require("fs");
+const module = new WebAssembly.Module(fs.readFileSync(process.argv[1]));
+new WebAssembly.Instance(module, {}).exports.main(0, 0);
+console.log("wasm smoke test passed");
+' target/wasm32-unknown-unknown/debug/examples/wasm_smoke.wasm